@@ -0,0 +1,112 @@
+//
+// anonymize.rs
+// Dicom-Tools-rs
+//
+// Criterion benchmark of a cohort anonymization run: header-only splice versus loading every file in full.
+//
+// Run with `cargo bench --bench anonymize`; set ANON_BENCH_FILES=10000 for a full 10k-file cohort.
+//
+// Thales Matheus Mendonça Santos - November 2025
+
+use std::path::{Path, PathBuf};
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use dicom::core::value::DataSetSequence;
+use dicom::core::{DataElement, PrimitiveValue, Tag, VR};
+use dicom::object::{FileDicomObject, FileMetaTableBuilder, InMemDicomObject};
+use dicom_tools::anonymize;
+use rayon::prelude::*;
+
+fn write_instance(dir: &Path, index: usize) -> PathBuf {
+    let sop_instance = format!("1.2.826.0.1.3680043.2.1125.9.{}", index);
+    let meta = FileMetaTableBuilder::new()
+        .transfer_syntax("1.2.840.10008.1.2.1")
+        .media_storage_sop_class_uid("1.2.840.10008.5.1.4.1.1.2")
+        .media_storage_sop_instance_uid(sop_instance.as_str())
+        .build()
+        .expect("meta");
+    let mut obj = FileDicomObject::new_empty_with_meta(meta);
+    for (tag, vr, value) in [
+        (Tag(0x0008, 0x0020), VR::DA, "20240101"),
+        (Tag(0x0008, 0x0030), VR::TM, "101500"),
+        (Tag(0x0008, 0x0090), VR::PN, "Referring^Doctor"),
+        (Tag(0x0010, 0x0010), VR::PN, "Cohort^Patient"),
+        (Tag(0x0010, 0x0020), VR::LO, "PAT0001"),
+        (Tag(0x0010, 0x0030), VR::DA, "19700101"),
+    ] {
+        obj.put(DataElement::new(tag, vr, PrimitiveValue::from(value)));
+    }
+    let mut item = InMemDicomObject::new_empty();
+    item.put(DataElement::new(
+        Tag(0x0032, 0x1032),
+        VR::PN,
+        PrimitiveValue::from("Requesting^Doctor"),
+    ));
+    obj.put(DataElement::new(
+        Tag(0x0040, 0x0275),
+        VR::SQ,
+        DataSetSequence::from(vec![item]),
+    ));
+    for (tag, value) in [
+        (Tag(0x0028, 0x0010), 512_u16),
+        (Tag(0x0028, 0x0011), 512),
+        (Tag(0x0028, 0x0100), 16),
+    ] {
+        obj.put(DataElement::new(tag, VR::US, PrimitiveValue::from(value)));
+    }
+    // A 512x512 16-bit CT slice worth of pixel bytes.
+    obj.put(DataElement::new(
+        Tag(0x7FE0, 0x0010),
+        VR::OW,
+        PrimitiveValue::from(vec![0_u8; 512 * 512 * 2]),
+    ));
+
+    let path = dir.join(format!("{:05}.dcm", index));
+    obj.write_to_file(&path).expect("write instance");
+    path
+}
+
+fn full_load(input: &Path, output: &Path) {
+    let mut obj = dicom::object::open_file(input).expect("open");
+    anonymize::anonymize_obj(&mut obj).expect("anonymize");
+    obj.write_to_file(output).expect("write");
+}
+
+fn bench_cohort(c: &mut Criterion) {
+    let files: usize = std::env::var("ANON_BENCH_FILES")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(200);
+    let input = tempfile::tempdir().expect("tempdir");
+    let output = tempfile::tempdir().expect("tempdir");
+    let cohort: Vec<(PathBuf, PathBuf)> = (0..files)
+        .into_par_iter()
+        .map(|i| {
+            let path = write_instance(input.path(), i);
+            let out = output.path().join(path.file_name().unwrap());
+            (path, out)
+        })
+        .collect();
+
+    let mut group = c.benchmark_group("anonymize_cohort");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(files as u64));
+    group.bench_function("header_splice", |b| {
+        b.iter(|| {
+            cohort.par_iter().for_each(|(input, output)| {
+                anonymize::anonymize_file(input, output).expect("anonymize")
+            })
+        })
+    });
+    group.bench_function("full_load", |b| {
+        b.iter(|| {
+            cohort
+                .par_iter()
+                .for_each(|(input, output)| full_load(input, output))
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_cohort);
+criterion_main!(benches);
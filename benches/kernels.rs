@@ -0,0 +1,59 @@
+//
+// kernels.rs
+// Dicom-Tools-rs
+//
+// Criterion benchmarks comparing the scalar reference kernels with the dispatching (SIMD when enabled) kernels.
+//
+// Run with `cargo bench --bench kernels` and again with `--features simd` to compare.
+//
+// Thales Matheus Mendonça Santos - November 2025
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use dicom_tools::kernels;
+
+fn synthetic_frame(len: usize) -> Vec<f32> {
+    // A CT-like ramp with some wiggle so min/max are not trivially at the ends.
+    (0..len)
+        .map(|i| ((i % 4096) as f32) - 1024.0 + ((i * 7) % 13) as f32)
+        .collect()
+}
+
+fn bench_kernels(c: &mut Criterion) {
+    let mut group = c.benchmark_group("kernels");
+    // 512x512 slice and a 2048x2048 mammography-sized frame.
+    for &len in &[512 * 512, 2048 * 2048] {
+        let values = synthetic_frame(len);
+        group.throughput(Throughput::Elements(len as u64));
+
+        group.bench_with_input(
+            BenchmarkId::new("min_max_sum/scalar", len),
+            &values,
+            |b, v| b.iter(|| kernels::scalar::min_max_sum(black_box(v))),
+        );
+        group.bench_with_input(
+            BenchmarkId::new("min_max_sum/dispatch", len),
+            &values,
+            |b, v| b.iter(|| kernels::min_max_sum(black_box(v))),
+        );
+
+        group.bench_with_input(BenchmarkId::new("variance/scalar", len), &values, |b, v| {
+            b.iter(|| kernels::scalar::sum_squared_diff(black_box(v), 1000.0))
+        });
+        group.bench_with_input(
+            BenchmarkId::new("variance/dispatch", len),
+            &values,
+            |b, v| b.iter(|| kernels::sum_squared_diff(black_box(v), 1000.0)),
+        );
+
+        group.bench_with_input(BenchmarkId::new("window/scalar", len), &values, |b, v| {
+            b.iter(|| kernels::scalar::window_to_u8(black_box(v), 40.0, 400.0))
+        });
+        group.bench_with_input(BenchmarkId::new("window/dispatch", len), &values, |b, v| {
+            b.iter(|| kernels::window_to_u8(black_box(v), 40.0, 400.0))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_kernels);
+criterion_main!(benches);
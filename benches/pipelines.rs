@@ -0,0 +1,47 @@
+//
+// pipelines.rs
+// Dicom-Tools-rs
+//
+// Criterion benchmark of the anonymization and transcode pipelines over a synthetic CT series,
+// with throughput in bytes so regressions in the pixel and IO paths show up as MB/s.
+//
+// Run with `cargo bench --features bench --bench pipelines`.
+//
+// Thales Matheus Mendonça Santos - November 2025
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use dicom_tools::synth::{self, Pattern, SynthSpec};
+use dicom_tools::throughput::{self, Pipeline};
+
+fn bench_pipelines(c: &mut Criterion) {
+    let input = tempfile::tempdir().expect("tempdir");
+    let scratch = tempfile::tempdir().expect("tempdir");
+    let spec = SynthSpec {
+        modality: "CT".to_string(),
+        rows: 512,
+        columns: 512,
+        bits_stored: 12,
+        instances: 20,
+        pattern: Pattern::Noise,
+        ..SynthSpec::default()
+    };
+    synth::write_series(&spec, input.path()).expect("synthetic series");
+    let files = throughput::collect_files(input.path());
+    let bytes: u64 = files
+        .iter()
+        .map(|f| std::fs::metadata(f).expect("stat").len())
+        .sum();
+
+    let mut group = c.benchmark_group("pipelines");
+    group.sample_size(10);
+    group.throughput(Throughput::Bytes(bytes));
+    for pipeline in [Pipeline::Anonymize, Pipeline::Transcode] {
+        group.bench_function(pipeline.to_string(), |b| {
+            b.iter(|| throughput::run(pipeline, &files, scratch.path(), 1).expect("run"))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_pipelines);
+criterion_main!(benches);
@@ -0,0 +1,1372 @@
+//
+// annotations.rs
+// Dicom-Tools-rs
+//
+// Imports annotations drawn in other viewers (OsiriX/Horos ROI exports as plist XML or JSON,
+// and plain CSV point lists) and writes them back as DICOM: a Comprehensive SR measurement
+// report (TID 1500 layout) or a binary Segmentation, referenced to the annotated instances.
+//
+// Thales Matheus Mendonça Santos - November 2025
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use chrono::Local;
+use dicom::core::value::DataSetSequence;
+use dicom::core::{DataElement, PrimitiveValue, Tag, VR};
+use dicom::dictionary_std::uids;
+use dicom::object::{DefaultDicomObject, InMemDicomObject, OpenFileOptions};
+use quick_xml::events::Event;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use walkdir::WalkDir;
+
+use crate::atomic_file;
+use crate::codes::{CODE_MEANING, CODE_VALUE, CODING_SCHEME_DESIGNATOR};
+use crate::derivation::new_instance_uid;
+use crate::dicom_access::ElementAccess;
+use crate::measure::{
+    frame_multi_f64, FrameGeometry, PixelPoint, IMAGE_ORIENTATION, IMAGE_POSITION,
+    PLANE_ORIENTATION_SEQUENCE, PLANE_POSITION_SEQUENCE,
+};
+use crate::roi_mask::{self, SEGMENTATION_STORAGE};
+
+const IMAGE_TYPE: Tag = Tag(0x0008, 0x0008);
+const SOP_CLASS_UID: Tag = Tag(0x0008, 0x0016);
+const SOP_INSTANCE_UID: Tag = Tag(0x0008, 0x0018);
+const STUDY_DATE: Tag = Tag(0x0008, 0x0020);
+const CONTENT_DATE: Tag = Tag(0x0008, 0x0023);
+const STUDY_TIME: Tag = Tag(0x0008, 0x0030);
+const CONTENT_TIME: Tag = Tag(0x0008, 0x0033);
+const ACCESSION_NUMBER: Tag = Tag(0x0008, 0x0050);
+const MODALITY: Tag = Tag(0x0008, 0x0060);
+const REFERRING_PHYSICIAN_NAME: Tag = Tag(0x0008, 0x0090);
+const SERIES_DESCRIPTION: Tag = Tag(0x0008, 0x103E);
+const REFERENCED_SERIES_SEQUENCE: Tag = Tag(0x0008, 0x1115);
+const REFERENCED_INSTANCE_SEQUENCE: Tag = Tag(0x0008, 0x114A);
+const REFERENCED_SOP_CLASS_UID: Tag = Tag(0x0008, 0x1150);
+const REFERENCED_SOP_INSTANCE_UID: Tag = Tag(0x0008, 0x1155);
+const REFERENCED_FRAME_NUMBER: Tag = Tag(0x0008, 0x1160);
+const REFERENCED_SOP_SEQUENCE: Tag = Tag(0x0008, 0x1199);
+const SOURCE_IMAGE_SEQUENCE: Tag = Tag(0x0008, 0x2112);
+const DERIVATION_IMAGE_SEQUENCE: Tag = Tag(0x0008, 0x9124);
+const DERIVATION_CODE_SEQUENCE: Tag = Tag(0x0008, 0x9215);
+const MAPPING_RESOURCE: Tag = Tag(0x0008, 0x0105);
+const PATIENT_NAME: Tag = Tag(0x0010, 0x0010);
+const PATIENT_ID: Tag = Tag(0x0010, 0x0020);
+const PATIENT_BIRTH_DATE: Tag = Tag(0x0010, 0x0030);
+const PATIENT_SEX: Tag = Tag(0x0010, 0x0040);
+const SLICE_THICKNESS: Tag = Tag(0x0018, 0x0050);
+const STUDY_INSTANCE_UID: Tag = Tag(0x0020, 0x000D);
+const SERIES_INSTANCE_UID: Tag = Tag(0x0020, 0x000E);
+const STUDY_ID: Tag = Tag(0x0020, 0x0010);
+const SERIES_NUMBER: Tag = Tag(0x0020, 0x0011);
+const INSTANCE_NUMBER: Tag = Tag(0x0020, 0x0013);
+const FRAME_OF_REFERENCE_UID: Tag = Tag(0x0020, 0x0052);
+const SAMPLES_PER_PIXEL: Tag = Tag(0x0028, 0x0002);
+const PHOTOMETRIC_INTERPRETATION: Tag = Tag(0x0028, 0x0004);
+const NUMBER_OF_FRAMES: Tag = Tag(0x0028, 0x0008);
+const ROWS: Tag = Tag(0x0028, 0x0010);
+const COLUMNS: Tag = Tag(0x0028, 0x0011);
+const PIXEL_SPACING: Tag = Tag(0x0028, 0x0030);
+const BITS_ALLOCATED: Tag = Tag(0x0028, 0x0100);
+const BITS_STORED: Tag = Tag(0x0028, 0x0101);
+const HIGH_BIT: Tag = Tag(0x0028, 0x0102);
+const PIXEL_REPRESENTATION: Tag = Tag(0x0028, 0x0103);
+const LOSSY_IMAGE_COMPRESSION: Tag = Tag(0x0028, 0x2110);
+const PIXEL_MEASURES_SEQUENCE: Tag = Tag(0x0028, 0x9110);
+const PURPOSE_OF_REFERENCE_CODE_SEQUENCE: Tag = Tag(0x0040, 0xA170);
+const RELATIONSHIP_TYPE: Tag = Tag(0x0040, 0xA010);
+const VALUE_TYPE: Tag = Tag(0x0040, 0xA040);
+const CONCEPT_NAME_CODE_SEQUENCE: Tag = Tag(0x0040, 0xA043);
+const CONTINUITY_OF_CONTENT: Tag = Tag(0x0040, 0xA050);
+const UID_VALUE: Tag = Tag(0x0040, 0xA124);
+const TEXT_VALUE: Tag = Tag(0x0040, 0xA160);
+const MEASURED_VALUE_SEQUENCE: Tag = Tag(0x0040, 0xA300);
+const MEASUREMENT_UNITS_CODE_SEQUENCE: Tag = Tag(0x0040, 0x08EA);
+const NUMERIC_VALUE: Tag = Tag(0x0040, 0xA30A);
+const CURRENT_REQUESTED_PROCEDURE_EVIDENCE_SEQUENCE: Tag = Tag(0x0040, 0xA375);
+const COMPLETION_FLAG: Tag = Tag(0x0040, 0xA491);
+const VERIFICATION_FLAG: Tag = Tag(0x0040, 0xA493);
+const CONTENT_TEMPLATE_SEQUENCE: Tag = Tag(0x0040, 0xA504);
+const CONTENT_SEQUENCE: Tag = Tag(0x0040, 0xA730);
+const TEMPLATE_IDENTIFIER: Tag = Tag(0x0040, 0xDB00);
+const SEGMENTATION_TYPE: Tag = Tag(0x0062, 0x0001);
+const SEGMENT_SEQUENCE: Tag = Tag(0x0062, 0x0002);
+const SEGMENTED_PROPERTY_CATEGORY_CODE_SEQUENCE: Tag = Tag(0x0062, 0x0003);
+const SEGMENT_NUMBER: Tag = Tag(0x0062, 0x0004);
+const SEGMENT_LABEL: Tag = Tag(0x0062, 0x0005);
+const SEGMENT_ALGORITHM_TYPE: Tag = Tag(0x0062, 0x0008);
+const SEGMENT_IDENTIFICATION_SEQUENCE: Tag = Tag(0x0062, 0x000A);
+const REFERENCED_SEGMENT_NUMBER: Tag = Tag(0x0062, 0x000B);
+const SEGMENTED_PROPERTY_TYPE_CODE_SEQUENCE: Tag = Tag(0x0062, 0x000F);
+const GRAPHIC_DATA: Tag = Tag(0x0070, 0x0022);
+const GRAPHIC_TYPE: Tag = Tag(0x0070, 0x0023);
+const CONTENT_LABEL: Tag = Tag(0x0070, 0x0080);
+const CONTENT_DESCRIPTION: Tag = Tag(0x0070, 0x0081);
+const CONTENT_CREATOR_NAME: Tag = Tag(0x0070, 0x0084);
+const SHARED_FUNCTIONAL_GROUPS: Tag = Tag(0x5200, 0x9229);
+const PER_FRAME_FUNCTIONAL_GROUPS: Tag = Tag(0x5200, 0x9230);
+const PIXEL_DATA: Tag = Tag(0x7FE0, 0x0010);
+
+pub(crate) const COMPREHENSIVE_SR_STORAGE: &str = "1.2.840.10008.5.1.4.1.1.88.33";
+/// UID seed, Series Number (clear of the acquisition series) and Series Description of the
+/// imported objects.
+const IMPORTED_SERIES: (&str, &str, &str) = ("annotations", "9901", "Imported annotations");
+
+/// OsiriX/Horos ROI tool types (`ROI.h`) that draw open lines or single points; every
+/// other type (rectangle, oval, closed polygon, pencil, brush...) encloses an area.
+const OSIRIX_LINE_TYPES: [i64; 4] = [5, 10, 12, 14];
+const OSIRIX_POINT_TYPE: i64 = 19;
+
+/// Geometry of an imported annotation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Shape {
+    Point,
+    #[serde(alias = "line")]
+    Polyline,
+    Polygon,
+}
+
+impl Shape {
+    fn parse(text: &str) -> Result<Self> {
+        match text.trim().to_ascii_lowercase().as_str() {
+            "point" => Ok(Shape::Point),
+            "polyline" | "line" => Ok(Shape::Polyline),
+            "polygon" => Ok(Shape::Polygon),
+            other => bail!(
+                "Unknown shape {:?} (expected point, polyline or polygon)",
+                other
+            ),
+        }
+    }
+}
+
+/// How an annotation names its image.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ImageRef {
+    SopInstance(String),
+    /// 0-based position among the series' frames, ordered by Instance Number as viewers
+    /// list them (each frame of a multi-frame instance counts once).
+    Index(usize),
+}
+
+/// One annotation, in pixel indices of its frame (`x` column, `y` row, the centre of the
+/// first pixel at 0,0 as for `measure`).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Annotation {
+    pub label: String,
+    pub image: ImageRef,
+    /// 0-based frame within the instance.
+    pub frame: u32,
+    pub shape: Shape,
+    pub points: Vec<PixelPoint>,
+}
+
+/// What the annotations are written as.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportTarget {
+    /// Comprehensive SR with one measurement group per annotation.
+    Sr,
+    /// Binary Segmentation with one segment per label.
+    Seg,
+}
+
+/// Read an OsiriX/Horos ROI export (`.xml`/`.plist` or `.json`) or a `.csv` point list.
+pub fn read_annotations(path: &Path) -> Result<Vec<Annotation>> {
+    let extension = path
+        .extension()
+        .map(|e| e.to_string_lossy().to_ascii_lowercase());
+    let text = || fs::read_to_string(path).with_context(|| format!("Failed to read {:?}", path));
+    let annotations = match extension.as_deref() {
+        Some("xml") | Some("plist") => {
+            let export = parse_plist(&text()?)
+                .with_context(|| format!("Invalid OsiriX XML in {:?}", path))?;
+            from_osirix(&export)?
+        }
+        Some("json") => {
+            let export: Value = serde_json::from_str(&text()?)
+                .with_context(|| format!("Invalid OsiriX JSON in {:?}", path))?;
+            from_osirix(&export)?
+        }
+        Some("csv") => from_csv(path)?,
+        _ => bail!(
+            "Annotations {:?} must be a .xml, .plist, .json or .csv file",
+            path
+        ),
+    };
+    if annotations.is_empty() {
+        bail!("{:?} holds no annotation with points", path);
+    }
+    Ok(annotations)
+}
+
+/// Convert a property list into JSON values: dict to object, array to array, string, date
+/// and data to string, integer and real to number.
+fn parse_plist(text: &str) -> Result<Value> {
+    enum Open {
+        Dict(Map<String, Value>, Option<String>),
+        Array(Vec<Value>),
+    }
+
+    fn add(stack: &mut [Open], root: &mut Option<Value>, value: Value) -> Result<()> {
+        match stack.last_mut() {
+            Some(Open::Dict(map, key)) => {
+                let key = key.take().context("Dictionary value without a <key>")?;
+                map.insert(key, value);
+            }
+            Some(Open::Array(items)) => items.push(value),
+            None => *root = Some(value),
+        }
+        Ok(())
+    }
+
+    let mut reader = quick_xml::Reader::from_str(text);
+    reader.config_mut().trim_text(true);
+    let mut stack: Vec<Open> = Vec::new();
+    let mut root = None;
+    let mut element: Option<String> = None;
+    let mut content = String::new();
+    loop {
+        match reader.read_event()? {
+            Event::Start(start) => {
+                match start.local_name().as_ref() {
+                    b"dict" => stack.push(Open::Dict(Map::new(), None)),
+                    b"array" => stack.push(Open::Array(Vec::new())),
+                    b"plist" => {}
+                    name => element = Some(String::from_utf8_lossy(name).into_owned()),
+                }
+                content.clear();
+            }
+            Event::Text(text) => content.push_str(&text.unescape()?),
+            Event::CData(data) => content.push_str(&String::from_utf8_lossy(&data)),
+            Event::Empty(empty) => {
+                let value = match empty.local_name().as_ref() {
+                    b"true" => Value::Bool(true),
+                    b"false" => Value::Bool(false),
+                    b"dict" => Value::Object(Map::new()),
+                    b"array" => Value::Array(Vec::new()),
+                    _ => Value::String(String::new()),
+                };
+                add(&mut stack, &mut root, value)?;
+            }
+            Event::End(end) => match end.local_name().as_ref() {
+                b"dict" | b"array" => {
+                    let value = match stack.pop() {
+                        Some(Open::Dict(map, _)) => Value::Object(map),
+                        Some(Open::Array(items)) => Value::Array(items),
+                        None => bail!("Unbalanced </dict> or </array>"),
+                    };
+                    add(&mut stack, &mut root, value)?;
+                }
+                b"plist" => {}
+                _ => {
+                    let name = element.take().unwrap_or_default();
+                    let text = std::mem::take(&mut content);
+                    if name == "key" {
+                        match stack.last_mut() {
+                            Some(Open::Dict(_, key)) => *key = Some(text),
+                            _ => bail!("<key> outside a dictionary"),
+                        }
+                        continue;
+                    }
+                    let value = match name.as_str() {
+                        "integer" => text.trim().parse::<i64>().map(Value::from)?,
+                        "real" => text.trim().parse::<f64>().map(Value::from)?,
+                        _ => Value::String(text),
+                    };
+                    add(&mut stack, &mut root, value)?;
+                }
+            },
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    root.context("Property list is empty")
+}
+
+/// Annotations of an OsiriX/Horos export: `{"Images": [{"ImageIndex", "ROIs": [{"Name",
+/// "Type", "Point_px": ["(x, y)", ...]}]}]}`. A `SOPInstanceUID` on the image (or the ROI),
+/// written by some Horos versions, takes precedence over the index.
+fn from_osirix(export: &Value) -> Result<Vec<Annotation>> {
+    let images = export
+        .get("Images")
+        .and_then(Value::as_array)
+        .context("Export has no Images array")?;
+    let mut annotations = Vec::new();
+    for image in images {
+        let image_uid = image.get("SOPInstanceUID").and_then(Value::as_str);
+        let index = image.get("ImageIndex").and_then(Value::as_u64);
+        let frame = image.get("FrameIndex").and_then(Value::as_u64).unwrap_or(0) as u32;
+        for roi in image
+            .get("ROIs")
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+        {
+            let points = roi
+                .get("Point_px")
+                .and_then(Value::as_array)
+                .map(|points| points.iter().map(osirix_point).collect::<Result<Vec<_>>>())
+                .transpose()?
+                .unwrap_or_default();
+            if points.is_empty() {
+                continue;
+            }
+            let reference = match roi
+                .get("SOPInstanceUID")
+                .and_then(Value::as_str)
+                .or(image_uid)
+            {
+                Some(uid) => ImageRef::SopInstance(uid.trim().to_string()),
+                None => ImageRef::Index(
+                    index.context("Image entry has neither ImageIndex nor SOPInstanceUID")?
+                        as usize,
+                ),
+            };
+            let kind = roi.get("Type").and_then(Value::as_i64);
+            let shape = match (kind, points.len()) {
+                (Some(OSIRIX_POINT_TYPE), _) | (_, 1) => Shape::Point,
+                (Some(kind), _) if OSIRIX_LINE_TYPES.contains(&kind) => Shape::Polyline,
+                (_, 2) => Shape::Polyline,
+                _ => Shape::Polygon,
+            };
+            let label = roi
+                .get("Name")
+                .and_then(Value::as_str)
+                .filter(|name| !name.trim().is_empty())
+                .unwrap_or("ROI")
+                .trim()
+                .to_string();
+            annotations.push(Annotation {
+                label,
+                image: reference,
+                frame,
+                shape,
+                points,
+            });
+        }
+    }
+    Ok(annotations)
+}
+
+/// `"(x, y)"` (plist export) or `[x, y]` (JSON export).
+fn osirix_point(value: &Value) -> Result<PixelPoint> {
+    match value {
+        Value::String(text) => text
+            .trim()
+            .trim_start_matches('(')
+            .trim_end_matches(')')
+            .parse(),
+        Value::Array(pair) => match (
+            pair.first().and_then(Value::as_f64),
+            pair.get(1).and_then(Value::as_f64),
+        ) {
+            (Some(x), Some(y)) => Ok(PixelPoint { x, y }),
+            _ => bail!("Point {} must hold two numbers", value),
+        },
+        other => bail!("Unexpected point {}", other),
+    }
+}
+
+/// CSV with a header row: `sop_instance_uid` or `image_index`, `x`, `y`, and optionally
+/// `label`, `frame` (1-based) and `shape`. Without a shape every row is a point; rows sharing
+/// label, image, frame and a line or polygon shape form one annotation, in file order.
+fn from_csv(path: &Path) -> Result<Vec<Annotation>> {
+    let mut reader =
+        csv::Reader::from_path(path).with_context(|| format!("Failed to read {:?}", path))?;
+    let headers: Vec<String> = reader
+        .headers()?
+        .iter()
+        .map(|h| h.trim().to_ascii_lowercase())
+        .collect();
+    let column = |name: &str| headers.iter().position(|h| h == name);
+    let (Some(x_column), Some(y_column)) = (column("x"), column("y")) else {
+        bail!("{:?} needs x and y columns", path);
+    };
+    let uid_column = column("sop_instance_uid");
+    let index_column = column("image_index");
+    if uid_column.is_none() && index_column.is_none() {
+        bail!("{:?} needs a sop_instance_uid or image_index column", path);
+    }
+    let (label_column, frame_column, shape_column) =
+        (column("label"), column("frame"), column("shape"));
+
+    let mut annotations: Vec<Annotation> = Vec::new();
+    for (line, record) in reader.records().enumerate() {
+        let record = record?;
+        let row = line + 2;
+        let field = |column: Option<usize>| {
+            column
+                .and_then(|c| record.get(c))
+                .map(str::trim)
+                .filter(|v| !v.is_empty())
+        };
+        let number = |column: usize, name: &str| -> Result<f64> {
+            field(Some(column))
+                .with_context(|| format!("Row {}: missing {}", row, name))?
+                .parse()
+                .with_context(|| format!("Row {}: invalid {}", row, name))
+        };
+        let point = PixelPoint {
+            x: number(x_column, "x")?,
+            y: number(y_column, "y")?,
+        };
+        let image = match (field(uid_column), field(index_column)) {
+            (Some(uid), _) => ImageRef::SopInstance(uid.to_string()),
+            (None, Some(index)) => ImageRef::Index(
+                index
+                    .parse()
+                    .with_context(|| format!("Row {}: invalid image_index", row))?,
+            ),
+            (None, None) => bail!("Row {}: no image reference", row),
+        };
+        let frame = match field(frame_column) {
+            Some(frame) => {
+                frame
+                    .parse::<u32>()
+                    .ok()
+                    .filter(|&f| f >= 1)
+                    .with_context(|| format!("Row {}: frame numbers start at 1", row))?
+                    - 1
+            }
+            None => 0,
+        };
+        let shape = field(shape_column)
+            .map(Shape::parse)
+            .transpose()
+            .with_context(|| format!("Row {}", row))?
+            .unwrap_or(Shape::Point);
+        let label = field(label_column).unwrap_or("ROI").to_string();
+
+        let open = annotations.iter_mut().find(|a| {
+            shape != Shape::Point
+                && a.shape == shape
+                && a.label == label
+                && a.image == image
+                && a.frame == frame
+        });
+        match open {
+            Some(annotation) => annotation.points.push(point),
+            None => annotations.push(Annotation {
+                label,
+                image,
+                frame,
+                shape,
+                points: vec![point],
+            }),
+        }
+    }
+    Ok(annotations)
+}
+
+/// Header of one instance of the annotated series.
+#[derive(Debug, Clone)]
+pub(crate) struct SourceInstance {
+    pub(crate) obj: InMemDicomObject,
+    pub(crate) sop_class_uid: String,
+    pub(crate) sop_instance_uid: String,
+    instance_number: Option<u32>,
+    frames: u32,
+}
+
+impl SourceInstance {
+    pub(crate) fn text(&self, tag: Tag) -> String {
+        self.obj
+            .element_str(tag)
+            .map(|s| s.trim_end_matches(['\0', ' ']).to_string())
+            .unwrap_or_default()
+    }
+}
+
+/// Instances the annotations may refer to, in viewer order.
+#[derive(Debug, Clone)]
+pub(crate) struct SourceSeries {
+    pub(crate) instances: Vec<SourceInstance>,
+}
+
+impl SourceSeries {
+    pub(crate) fn scan(dir: &Path) -> Result<Self> {
+        if !dir.is_dir() {
+            bail!("{:?} is not a directory", dir);
+        }
+        let mut instances = Vec::new();
+        for entry in WalkDir::new(dir).into_iter().filter_map(|e| e.ok()) {
+            if !entry.file_type().is_file() {
+                continue;
+            }
+            let Ok(file) = OpenFileOptions::new()
+                .read_until(PIXEL_DATA)
+                .open_file(entry.path())
+            else {
+                continue;
+            };
+            let obj: InMemDicomObject = file.into_inner();
+            let text = |tag| {
+                obj.element_str(tag)
+                    .map(|s| s.trim_end_matches(['\0', ' ']).to_string())
+                    .unwrap_or_default()
+            };
+            let (sop_class_uid, sop_instance_uid) = (text(SOP_CLASS_UID), text(SOP_INSTANCE_UID));
+            if sop_instance_uid.is_empty() {
+                continue;
+            }
+            instances.push(SourceInstance {
+                instance_number: obj.element_u32(INSTANCE_NUMBER),
+                frames: obj.element_u32(NUMBER_OF_FRAMES).unwrap_or(1).max(1),
+                obj,
+                sop_class_uid,
+                sop_instance_uid,
+            });
+        }
+        if instances.is_empty() {
+            bail!("No DICOM instance found under {:?}", dir);
+        }
+        instances.sort_by(|a, b| {
+            (a.instance_number, &a.sop_instance_uid).cmp(&(b.instance_number, &b.sop_instance_uid))
+        });
+        Ok(Self { instances })
+    }
+
+    /// The instance and 0-based frame an annotation was drawn on.
+    fn resolve(&self, annotation: &Annotation) -> Result<(&SourceInstance, u32)> {
+        self.resolve_image(&annotation.image, annotation.frame, &annotation.label)
+    }
+
+    /// The instance and 0-based frame `image` names; `frame` applies to SOP Instance UID
+    /// references and `label` only appears in errors.
+    pub(crate) fn resolve_image(
+        &self,
+        image: &ImageRef,
+        frame: u32,
+        label: &str,
+    ) -> Result<(&SourceInstance, u32)> {
+        let (instance, frame) = match image {
+            ImageRef::SopInstance(uid) => (
+                self.instances
+                    .iter()
+                    .find(|i| i.sop_instance_uid == *uid)
+                    .with_context(|| format!("{} ({}) is not in the series", uid, label))?,
+                frame,
+            ),
+            ImageRef::Index(index) => {
+                let mut remaining = *index as u32;
+                let mut found = None;
+                for instance in &self.instances {
+                    if remaining < instance.frames {
+                        found = Some((instance, remaining));
+                        break;
+                    }
+                    remaining -= instance.frames;
+                }
+                found.with_context(|| {
+                    format!(
+                        "Image index {} ({}) is past the end of the series",
+                        index, label
+                    )
+                })?
+            }
+        };
+        if frame >= instance.frames {
+            bail!(
+                "{} has {} frame(s); {} refers to frame {}",
+                instance.sop_instance_uid,
+                instance.frames,
+                label,
+                frame + 1
+            );
+        }
+        Ok((instance, frame))
+    }
+}
+
+/// Summary of an import.
+#[derive(Debug, Clone)]
+pub struct ImportSummary {
+    pub annotations: usize,
+    pub referenced_instances: usize,
+    pub sop_instance_uid: String,
+    pub output: PathBuf,
+}
+
+/// Read `annotations`, resolve them against the instances under `series` and write them
+/// to `output` as `target`.
+pub fn import(
+    annotations: &Path,
+    series: &Path,
+    target: ImportTarget,
+    output: &Path,
+) -> Result<ImportSummary> {
+    let annotations = read_annotations(annotations)?;
+    let series = SourceSeries::scan(series)?;
+    let resolved = annotations
+        .iter()
+        .map(|annotation| {
+            series
+                .resolve(annotation)
+                .map(|(instance, frame)| (annotation, instance, frame))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let studies: Vec<String> = resolved
+        .iter()
+        .map(|(_, instance, _)| instance.text(STUDY_INSTANCE_UID))
+        .fold(Vec::new(), |mut uids, uid| {
+            if !uids.contains(&uid) {
+                uids.push(uid);
+            }
+            uids
+        });
+    if studies.len() > 1 {
+        bail!(
+            "Annotations span {} studies; import one study at a time",
+            studies.len()
+        );
+    }
+
+    let obj = match target {
+        ImportTarget::Sr => measurement_report(&resolved)?,
+        ImportTarget::Seg => segmentation(&resolved)?,
+    };
+    let sop_instance_uid = obj.element_str(SOP_INSTANCE_UID).unwrap_or_default();
+    atomic_file::write_dicom(output, &obj)
+        .with_context(|| format!("Failed to write {:?}", output))?;
+    let mut referenced: Vec<&str> = resolved
+        .iter()
+        .map(|(_, instance, _)| instance.sop_instance_uid.as_str())
+        .collect();
+    referenced.sort_unstable();
+    referenced.dedup();
+    Ok(ImportSummary {
+        annotations: resolved.len(),
+        referenced_instances: referenced.len(),
+        sop_instance_uid,
+        output: output.to_path_buf(),
+    })
+}
+
+/// CLI entry point.
+pub fn print_import(
+    annotations: &Path,
+    series: &Path,
+    target: ImportTarget,
+    output: &Path,
+) -> Result<()> {
+    let summary = import(annotations, series, target, output)?;
+    println!(
+        "{} annotation(s) on {} instance(s) written as {:?} to {:?} ({})",
+        summary.annotations,
+        summary.referenced_instances,
+        target,
+        summary.output,
+        summary.sop_instance_uid
+    );
+    Ok(())
+}
+
+type Resolved<'a> = (&'a Annotation, &'a SourceInstance, u32);
+
+pub(crate) fn put_str(obj: &mut InMemDicomObject, tag: Tag, vr: VR, value: &str) {
+    obj.put(DataElement::new(tag, vr, PrimitiveValue::from(value)));
+}
+
+fn put_us(obj: &mut InMemDicomObject, tag: Tag, value: u16) {
+    obj.put(DataElement::new(tag, VR::US, PrimitiveValue::from(value)));
+}
+
+pub(crate) fn put_sequence(obj: &mut InMemDicomObject, tag: Tag, items: Vec<InMemDicomObject>) {
+    obj.put(DataElement::new(tag, VR::SQ, DataSetSequence::from(items)));
+}
+
+pub(crate) fn code(value: &str, scheme: &str, meaning: &str) -> InMemDicomObject {
+    let mut item = InMemDicomObject::new_empty();
+    put_str(&mut item, CODE_VALUE, VR::SH, value);
+    put_str(&mut item, CODING_SCHEME_DESIGNATOR, VR::SH, scheme);
+    put_str(&mut item, CODE_MEANING, VR::LO, meaning);
+    item
+}
+
+/// Patient and study attributes of the annotated images, a new series and instance. `kind`
+/// seeds the new UIDs; the series gets `series_number` and `series_description`.
+pub(crate) fn derived_object(
+    source: &SourceInstance,
+    sop_class: &str,
+    modality: &str,
+    (kind, series_number, series_description): (&str, &str, &str),
+) -> InMemDicomObject {
+    let mut obj = InMemDicomObject::new_empty();
+    for (tag, vr) in [
+        (PATIENT_NAME, VR::PN),
+        (PATIENT_ID, VR::LO),
+        (PATIENT_BIRTH_DATE, VR::DA),
+        (PATIENT_SEX, VR::CS),
+        (STUDY_INSTANCE_UID, VR::UI),
+        (STUDY_DATE, VR::DA),
+        (STUDY_TIME, VR::TM),
+        (STUDY_ID, VR::SH),
+        (ACCESSION_NUMBER, VR::SH),
+        (REFERRING_PHYSICIAN_NAME, VR::PN),
+    ] {
+        put_str(&mut obj, tag, vr, &source.text(tag));
+    }
+    let now = Local::now();
+    put_str(&mut obj, SOP_CLASS_UID, VR::UI, sop_class);
+    put_str(
+        &mut obj,
+        SOP_INSTANCE_UID,
+        VR::UI,
+        &new_instance_uid(&format!("{}|{}", kind, source.sop_instance_uid)),
+    );
+    put_str(
+        &mut obj,
+        SERIES_INSTANCE_UID,
+        VR::UI,
+        &new_instance_uid(&format!("{}-series|{}", kind, source.sop_instance_uid)),
+    );
+    put_str(&mut obj, MODALITY, VR::CS, modality);
+    put_str(&mut obj, SERIES_NUMBER, VR::IS, series_number);
+    put_str(&mut obj, SERIES_DESCRIPTION, VR::LO, series_description);
+    put_str(&mut obj, INSTANCE_NUMBER, VR::IS, "1");
+    put_str(
+        &mut obj,
+        CONTENT_DATE,
+        VR::DA,
+        &now.format("%Y%m%d").to_string(),
+    );
+    put_str(
+        &mut obj,
+        CONTENT_TIME,
+        VR::TM,
+        &now.format("%H%M%S").to_string(),
+    );
+    obj
+}
+
+/// Referenced Series Sequence items listing every annotated instance, series by series.
+pub(crate) fn referenced_series<'a>(
+    instances: impl IntoIterator<Item = &'a SourceInstance>,
+) -> Vec<InMemDicomObject> {
+    let mut series: BTreeMap<String, Vec<&SourceInstance>> = BTreeMap::new();
+    for instance in instances {
+        let members = series
+            .entry(instance.text(SERIES_INSTANCE_UID))
+            .or_default();
+        if !members
+            .iter()
+            .any(|m| m.sop_instance_uid == instance.sop_instance_uid)
+        {
+            members.push(instance);
+        }
+    }
+    series
+        .into_iter()
+        .map(|(uid, instances)| {
+            let mut item = InMemDicomObject::new_empty();
+            put_str(&mut item, SERIES_INSTANCE_UID, VR::UI, &uid);
+            let references = instances
+                .into_iter()
+                .map(|instance| sop_reference(instance, None))
+                .collect();
+            put_sequence(&mut item, REFERENCED_INSTANCE_SEQUENCE, references);
+            item
+        })
+        .collect()
+}
+
+pub(crate) fn sop_reference(instance: &SourceInstance, frame: Option<u32>) -> InMemDicomObject {
+    let mut item = InMemDicomObject::new_empty();
+    put_str(
+        &mut item,
+        REFERENCED_SOP_CLASS_UID,
+        VR::UI,
+        &instance.sop_class_uid,
+    );
+    put_str(
+        &mut item,
+        REFERENCED_SOP_INSTANCE_UID,
+        VR::UI,
+        &instance.sop_instance_uid,
+    );
+    if let Some(frame) = frame.filter(|_| instance.frames > 1) {
+        put_str(
+            &mut item,
+            REFERENCED_FRAME_NUMBER,
+            VR::IS,
+            &(frame + 1).to_string(),
+        );
+    }
+    item
+}
+
+pub(crate) fn content_item(
+    relationship: &str,
+    value_type: &str,
+    concept: InMemDicomObject,
+) -> InMemDicomObject {
+    let mut item = InMemDicomObject::new_empty();
+    if !relationship.is_empty() {
+        put_str(&mut item, RELATIONSHIP_TYPE, VR::CS, relationship);
+    }
+    put_str(&mut item, VALUE_TYPE, VR::CS, value_type);
+    put_sequence(&mut item, CONCEPT_NAME_CODE_SEQUENCE, vec![concept]);
+    item
+}
+
+pub(crate) fn container(
+    relationship: &str,
+    concept: InMemDicomObject,
+    children: Vec<InMemDicomObject>,
+) -> InMemDicomObject {
+    let mut item = content_item(relationship, "CONTAINER", concept);
+    put_str(&mut item, CONTINUITY_OF_CONTENT, VR::CS, "SEPARATE");
+    put_sequence(&mut item, CONTENT_SEQUENCE, children);
+    item
+}
+
+/// SCOORD of the annotation over its image. SR coordinates put the top-left corner of the
+/// first pixel at 0,0, half a pixel before the index of its centre; polygons repeat their
+/// first point to close.
+pub(crate) fn spatial_coordinates(
+    shape: Shape,
+    points: &[PixelPoint],
+    instance: &SourceInstance,
+    frame: u32,
+) -> InMemDicomObject {
+    let mut item = content_item("CONTAINS", "SCOORD", code("111030", "DCM", "Image Region"));
+    let (graphic_type, mut points) = match (shape, points.len()) {
+        (Shape::Point, _) | (_, 1) => ("POINT", points[..1].to_vec()),
+        (Shape::Polyline, _) | (Shape::Polygon, 2) => ("POLYLINE", points.to_vec()),
+        (Shape::Polygon, _) => ("POLYGON", points.to_vec()),
+    };
+    if graphic_type == "POLYGON" && points.first() != points.last() {
+        points.push(points[0]);
+    }
+    let data: Vec<f32> = points
+        .iter()
+        .flat_map(|p| [(p.x + 0.5) as f32, (p.y + 0.5) as f32])
+        .collect();
+    put_str(&mut item, GRAPHIC_TYPE, VR::CS, graphic_type);
+    item.put(DataElement::new(
+        GRAPHIC_DATA,
+        VR::FL,
+        PrimitiveValue::F32(data.into()),
+    ));
+    let mut image = InMemDicomObject::new_empty();
+    put_str(&mut image, RELATIONSHIP_TYPE, VR::CS, "SELECTED FROM");
+    put_str(&mut image, VALUE_TYPE, VR::CS, "IMAGE");
+    put_sequence(
+        &mut image,
+        REFERENCED_SOP_SEQUENCE,
+        vec![sop_reference(instance, Some(frame))],
+    );
+    put_sequence(&mut item, CONTENT_SEQUENCE, vec![image]);
+    item
+}
+
+/// Length of a polyline or area of a polygon, when the image has a pixel spacing.
+fn measurement(
+    annotation: &Annotation,
+    instance: &SourceInstance,
+    frame: u32,
+) -> Option<InMemDicomObject> {
+    let geometry = FrameGeometry::for_frame(&instance.obj, frame).ok()?;
+    let points = &annotation.points;
+    let (concept, unit, value) = match annotation.shape {
+        Shape::Polyline if points.len() >= 2 => (
+            code("410668003", "SCT", "Length"),
+            code("mm", "UCUM", "millimeter"),
+            points
+                .windows(2)
+                .map(|pair| geometry.distance_mm(pair[0], pair[1]))
+                .sum::<f64>(),
+        ),
+        Shape::Polygon if points.len() >= 3 => (
+            code("42798000", "SCT", "Area"),
+            code("mm2", "UCUM", "square millimeter"),
+            geometry.polygon_area_mm2(points),
+        ),
+        _ => return None,
+    };
+    let mut item = content_item("CONTAINS", "NUM", concept);
+    let mut measured = InMemDicomObject::new_empty();
+    put_str(
+        &mut measured,
+        NUMERIC_VALUE,
+        VR::DS,
+        &format!("{:.4}", value),
+    );
+    put_sequence(&mut measured, MEASUREMENT_UNITS_CODE_SEQUENCE, vec![unit]);
+    put_sequence(&mut item, MEASURED_VALUE_SEQUENCE, vec![measured]);
+    Some(item)
+}
+
+/// Comprehensive SR laid out as TID 1500 (Measurement Report): one measurement group per
+/// annotation with its tracking identifiers, image region and length or area.
+fn measurement_report(resolved: &[Resolved]) -> Result<DefaultDicomObject> {
+    let (_, first, _) = resolved.first().context("No annotation to write")?;
+    let mut obj = derived_object(first, COMPREHENSIVE_SR_STORAGE, "SR", IMPORTED_SERIES);
+    put_str(&mut obj, VALUE_TYPE, VR::CS, "CONTAINER");
+    put_sequence(
+        &mut obj,
+        CONCEPT_NAME_CODE_SEQUENCE,
+        vec![code("126000", "DCM", "Imaging Measurement Report")],
+    );
+    put_str(&mut obj, CONTINUITY_OF_CONTENT, VR::CS, "SEPARATE");
+    let mut template = InMemDicomObject::new_empty();
+    put_str(&mut template, MAPPING_RESOURCE, VR::CS, "DCMR");
+    put_str(&mut template, TEMPLATE_IDENTIFIER, VR::CS, "1500");
+    put_sequence(&mut obj, CONTENT_TEMPLATE_SEQUENCE, vec![template]);
+    // Imported from another application and not reviewed here.
+    put_str(&mut obj, COMPLETION_FLAG, VR::CS, "PARTIAL");
+    put_str(&mut obj, VERIFICATION_FLAG, VR::CS, "UNVERIFIED");
+    let mut evidence = InMemDicomObject::new_empty();
+    put_str(
+        &mut evidence,
+        STUDY_INSTANCE_UID,
+        VR::UI,
+        &first.text(STUDY_INSTANCE_UID),
+    );
+    put_sequence(
+        &mut evidence,
+        REFERENCED_SERIES_SEQUENCE,
+        referenced_series(resolved.iter().map(|(_, instance, _)| *instance))
+            .into_iter()
+            .map(|mut series| {
+                // Evidence lists instances under Referenced SOP Sequence.
+                let instances = series.sequence_items(REFERENCED_INSTANCE_SEQUENCE).to_vec();
+                series.remove_element(REFERENCED_INSTANCE_SEQUENCE);
+                put_sequence(&mut series, REFERENCED_SOP_SEQUENCE, instances);
+                series
+            })
+            .collect(),
+    );
+    put_sequence(
+        &mut obj,
+        CURRENT_REQUESTED_PROCEDURE_EVIDENCE_SEQUENCE,
+        vec![evidence],
+    );
+
+    let groups = resolved
+        .iter()
+        .map(|(annotation, instance, frame)| {
+            let mut tracking = content_item(
+                "CONTAINS",
+                "TEXT",
+                code("112039", "DCM", "Tracking Identifier"),
+            );
+            put_str(&mut tracking, TEXT_VALUE, VR::UT, &annotation.label);
+            let mut tracking_uid = content_item(
+                "CONTAINS",
+                "UIDREF",
+                code("112040", "DCM", "Tracking Unique Identifier"),
+            );
+            put_str(
+                &mut tracking_uid,
+                UID_VALUE,
+                VR::UI,
+                &new_instance_uid(&format!(
+                    "tracking|{}|{}",
+                    annotation.label, instance.sop_instance_uid
+                )),
+            );
+            let mut children = vec![
+                tracking,
+                tracking_uid,
+                spatial_coordinates(annotation.shape, &annotation.points, instance, *frame),
+            ];
+            children.extend(measurement(annotation, instance, *frame));
+            container(
+                "CONTAINS",
+                code("125007", "DCM", "Measurement Group"),
+                children,
+            )
+        })
+        .collect();
+    put_sequence(
+        &mut obj,
+        CONTENT_SEQUENCE,
+        vec![container(
+            "CONTAINS",
+            code("126010", "DCM", "Imaging Measurements"),
+            groups,
+        )],
+    );
+    with_meta(obj, COMPREHENSIVE_SR_STORAGE)
+}
+
+/// Binary Segmentation with one segment per label. Polygons are filled, polylines drawn
+/// one pixel wide and points mark their pixel; one frame per segment and annotated frame.
+fn segmentation(resolved: &[Resolved]) -> Result<DefaultDicomObject> {
+    let (_, first, _) = resolved.first().context("No annotation to write")?;
+    let size = |instance: &SourceInstance| {
+        (
+            instance.obj.element_u32(ROWS).unwrap_or(0),
+            instance.obj.element_u32(COLUMNS).unwrap_or(0),
+        )
+    };
+    let (rows, columns) = size(first);
+    if rows == 0 || columns == 0 {
+        bail!("{} has no Rows/Columns", first.sop_instance_uid);
+    }
+    if let Some((_, other, _)) = resolved.iter().find(|(_, i, _)| size(i) != (rows, columns)) {
+        bail!(
+            "{} is not {}x{} like the other annotated images; a Segmentation needs one frame size",
+            other.sop_instance_uid,
+            columns,
+            rows
+        );
+    }
+
+    let mut labels: Vec<&str> = Vec::new();
+    for (annotation, _, _) in resolved {
+        if !labels.contains(&annotation.label.as_str()) {
+            labels.push(&annotation.label);
+        }
+    }
+    // One mask per segment, instance and frame, in the order they are first drawn.
+    let mut masks: Vec<SegmentFrame> = Vec::new();
+    for (annotation, instance, frame) in resolved {
+        let segment = labels
+            .iter()
+            .position(|l| *l == annotation.label)
+            .unwrap_or(0);
+        let index = match masks.iter().position(|m| {
+            m.segment == segment
+                && m.instance.sop_instance_uid == instance.sop_instance_uid
+                && m.frame == *frame
+        }) {
+            Some(index) => index,
+            None => {
+                masks.push(SegmentFrame {
+                    segment,
+                    instance,
+                    frame: *frame,
+                    mask: vec![false; (rows * columns) as usize],
+                });
+                masks.len() - 1
+            }
+        };
+        draw(annotation, rows, columns, &mut masks[index].mask);
+    }
+    masks.sort_by_key(|m| m.segment);
+
+    let mut obj = derived_object(first, SEGMENTATION_STORAGE, "SEG", IMPORTED_SERIES);
+    obj.put(DataElement::new(
+        IMAGE_TYPE,
+        VR::CS,
+        PrimitiveValue::Strs(["DERIVED".to_string(), "PRIMARY".to_string()][..].into()),
+    ));
+    put_str(&mut obj, CONTENT_LABEL, VR::CS, "ANNOTATIONS");
+    put_str(
+        &mut obj,
+        CONTENT_DESCRIPTION,
+        VR::LO,
+        "Imported viewer annotations",
+    );
+    put_str(&mut obj, CONTENT_CREATOR_NAME, VR::PN, "");
+    put_str(
+        &mut obj,
+        FRAME_OF_REFERENCE_UID,
+        VR::UI,
+        &first.text(FRAME_OF_REFERENCE_UID),
+    );
+    put_sequence(
+        &mut obj,
+        REFERENCED_SERIES_SEQUENCE,
+        referenced_series(resolved.iter().map(|(_, instance, _)| *instance)),
+    );
+    put_us(&mut obj, SAMPLES_PER_PIXEL, 1);
+    put_str(&mut obj, PHOTOMETRIC_INTERPRETATION, VR::CS, "MONOCHROME2");
+    put_us(&mut obj, ROWS, rows as u16);
+    put_us(&mut obj, COLUMNS, columns as u16);
+    put_us(&mut obj, BITS_ALLOCATED, 1);
+    put_us(&mut obj, BITS_STORED, 1);
+    put_us(&mut obj, HIGH_BIT, 0);
+    put_us(&mut obj, PIXEL_REPRESENTATION, 0);
+    put_str(&mut obj, LOSSY_IMAGE_COMPRESSION, VR::CS, "00");
+    put_str(&mut obj, SEGMENTATION_TYPE, VR::CS, "BINARY");
+    put_str(&mut obj, NUMBER_OF_FRAMES, VR::IS, &masks.len().to_string());
+
+    let segments = labels
+        .iter()
+        .enumerate()
+        .map(|(index, label)| {
+            let mut item = InMemDicomObject::new_empty();
+            put_us(&mut item, SEGMENT_NUMBER, index as u16 + 1);
+            put_str(&mut item, SEGMENT_LABEL, VR::LO, label);
+            put_str(&mut item, SEGMENT_ALGORITHM_TYPE, VR::CS, "MANUAL");
+            put_sequence(
+                &mut item,
+                SEGMENTED_PROPERTY_CATEGORY_CODE_SEQUENCE,
+                vec![code("91723000", "SCT", "Anatomical Structure")],
+            );
+            put_sequence(
+                &mut item,
+                SEGMENTED_PROPERTY_TYPE_CODE_SEQUENCE,
+                vec![code("85756007", "SCT", "Tissue")],
+            );
+            item
+        })
+        .collect();
+    put_sequence(&mut obj, SEGMENT_SEQUENCE, segments);
+
+    let mut shared = InMemDicomObject::new_empty();
+    if let Some(orientation) =
+        frame_multi_f64(&first.obj, 0, PLANE_ORIENTATION_SEQUENCE, IMAGE_ORIENTATION)
+    {
+        let mut item = InMemDicomObject::new_empty();
+        put_str(
+            &mut item,
+            IMAGE_ORIENTATION,
+            VR::DS,
+            &ds_values(&orientation),
+        );
+        put_sequence(&mut shared, PLANE_ORIENTATION_SEQUENCE, vec![item]);
+    }
+    if let Ok(geometry) = FrameGeometry::for_frame(&first.obj, 0) {
+        let mut item = InMemDicomObject::new_empty();
+        put_str(
+            &mut item,
+            PIXEL_SPACING,
+            VR::DS,
+            &ds_values(&[geometry.row_spacing, geometry.column_spacing]),
+        );
+        if let Some(thickness) = first.obj.element_f64(SLICE_THICKNESS) {
+            put_str(&mut item, SLICE_THICKNESS, VR::DS, &ds_values(&[thickness]));
+        }
+        put_sequence(&mut shared, PIXEL_MEASURES_SEQUENCE, vec![item]);
+    }
+    put_sequence(&mut obj, SHARED_FUNCTIONAL_GROUPS, vec![shared]);
+
+    let per_frame = masks
+        .iter()
+        .map(
+            |SegmentFrame {
+                 segment,
+                 instance,
+                 frame,
+                 ..
+             }| {
+                let mut group = InMemDicomObject::new_empty();
+                let mut source = sop_reference(instance, Some(*frame));
+                put_sequence(
+                    &mut source,
+                    PURPOSE_OF_REFERENCE_CODE_SEQUENCE,
+                    vec![code(
+                        "121322",
+                        "DCM",
+                        "Source image for image processing operation",
+                    )],
+                );
+                let mut derivation = InMemDicomObject::new_empty();
+                put_sequence(&mut derivation, SOURCE_IMAGE_SEQUENCE, vec![source]);
+                put_sequence(
+                    &mut derivation,
+                    DERIVATION_CODE_SEQUENCE,
+                    vec![code("113076", "DCM", "Segmentation")],
+                );
+                put_sequence(&mut group, DERIVATION_IMAGE_SEQUENCE, vec![derivation]);
+                let mut identification = InMemDicomObject::new_empty();
+                put_us(
+                    &mut identification,
+                    REFERENCED_SEGMENT_NUMBER,
+                    *segment as u16 + 1,
+                );
+                put_sequence(
+                    &mut group,
+                    SEGMENT_IDENTIFICATION_SEQUENCE,
+                    vec![identification],
+                );
+                if let Some(position) = frame_multi_f64(
+                    &instance.obj,
+                    *frame,
+                    PLANE_POSITION_SEQUENCE,
+                    IMAGE_POSITION,
+                ) {
+                    let mut item = InMemDicomObject::new_empty();
+                    put_str(&mut item, IMAGE_POSITION, VR::DS, &ds_values(&position));
+                    put_sequence(&mut group, PLANE_POSITION_SEQUENCE, vec![item]);
+                }
+                group
+            },
+        )
+        .collect();
+    put_sequence(&mut obj, PER_FRAME_FUNCTIONAL_GROUPS, per_frame);
+
+    // Binary frames are packed back to back, least significant bit first.
+    let total = masks.len() * (rows * columns) as usize;
+    let mut bytes = vec![0u8; total.div_ceil(8)];
+    for (bit, inside) in masks.iter().flat_map(|m| &m.mask).enumerate() {
+        if *inside {
+            bytes[bit / 8] |= 1 << (bit % 8);
+        }
+    }
+    if bytes.len() % 2 == 1 {
+        bytes.push(0);
+    }
+    obj.put(DataElement::new(
+        PIXEL_DATA,
+        VR::OB,
+        PrimitiveValue::U8(bytes.into()),
+    ));
+    with_meta(obj, SEGMENTATION_STORAGE)
+}
+
+/// Frame of a Segmentation: the pixels of one segment on one frame of an annotated image.
+struct SegmentFrame<'a> {
+    /// 0-based index into the Segment Sequence.
+    segment: usize,
+    instance: &'a SourceInstance,
+    frame: u32,
+    mask: Vec<bool>,
+}
+
+/// Rasterize `annotation` into a row-major mask.
+fn draw(annotation: &Annotation, rows: u32, columns: u32, mask: &mut [bool]) {
+    let mut set = |x: f64, y: f64| {
+        let (column, row) = (x.round(), y.round());
+        if column >= 0.0 && row >= 0.0 && (column as u32) < columns && (row as u32) < rows {
+            mask[(row as u32 * columns + column as u32) as usize] = true;
+        }
+    };
+    let points = &annotation.points;
+    match annotation.shape {
+        Shape::Polygon if points.len() >= 3 => {
+            let polygon: Vec<(f64, f64)> = points.iter().map(|p| (p.x, p.y)).collect();
+            let mut filled = vec![false; mask.len()];
+            roi_mask::fill_polygon(&polygon, rows, columns, &mut filled);
+            for (pixel, inside) in mask.iter_mut().zip(filled) {
+                *pixel |= inside;
+            }
+        }
+        Shape::Point => set(points[0].x, points[0].y),
+        _ => {
+            // Open lines (and degenerate polygons) are traced at sub-pixel steps.
+            for pair in points.windows(2) {
+                let (a, b) = (pair[0], pair[1]);
+                let steps = ((b.x - a.x).abs().max((b.y - a.y).abs()) * 2.0)
+                    .ceil()
+                    .max(1.0);
+                for step in 0..=steps as u32 {
+                    let t = step as f64 / steps;
+                    set(a.x + (b.x - a.x) * t, a.y + (b.y - a.y) * t);
+                }
+            }
+            if points.len() == 1 {
+                set(points[0].x, points[0].y);
+            }
+        }
+    }
+}
+
+fn ds_values(values: &[f64]) -> String {
+    values
+        .iter()
+        .map(|v| format!("{}", v))
+        .collect::<Vec<_>>()
+        .join("\\")
+}
+
+pub(crate) fn with_meta(obj: InMemDicomObject, sop_class: &str) -> Result<DefaultDicomObject> {
+    let sop_instance = obj.element_str(SOP_INSTANCE_UID).unwrap_or_default();
+    obj.with_meta(
+        dicom::object::FileMetaTableBuilder::new()
+            .transfer_syntax(uids::EXPLICIT_VR_LITTLE_ENDIAN)
+            .media_storage_sop_class_uid(sop_class)
+            .media_storage_sop_instance_uid(&sop_instance),
+    )
+    .context("Failed to build file meta information")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OSIRIX_XML: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+    <key>Images</key>
+    <array>
+        <dict>
+            <key>ImageIndex</key>
+            <integer>2</integer>
+            <key>NumberOfROIs</key>
+            <integer>2</integer>
+            <key>ROIs</key>
+            <array>
+                <dict>
+                    <key>Name</key>
+                    <string>Lesion &amp; margin</string>
+                    <key>Type</key>
+                    <integer>11</integer>
+                    <key>Point_px</key>
+                    <array>
+                        <string>(10.5, 12)</string>
+                        <string>(20, 12)</string>
+                        <string>(20, 22.25)</string>
+                    </array>
+                </dict>
+                <dict>
+                    <key>Name</key>
+                    <string>Ruler</string>
+                    <key>Type</key>
+                    <integer>5</integer>
+                    <key>Area</key>
+                    <real>0.0</real>
+                    <key>Point_px</key>
+                    <array>
+                        <string>(1, 1)</string>
+                        <string>(4, 5)</string>
+                    </array>
+                </dict>
+            </array>
+        </dict>
+    </array>
+</dict>
+</plist>"#;
+
+    #[test]
+    fn osirix_plist_rois_become_annotations() {
+        let export = parse_plist(OSIRIX_XML).unwrap();
+        assert_eq!(export["Images"][0]["NumberOfROIs"], 2);
+        let annotations = from_osirix(&export).unwrap();
+        assert_eq!(annotations.len(), 2);
+        assert_eq!(annotations[0].label, "Lesion & margin");
+        assert_eq!(annotations[0].image, ImageRef::Index(2));
+        assert_eq!(annotations[0].shape, Shape::Polygon);
+        assert_eq!(annotations[0].points[0], PixelPoint { x: 10.5, y: 12.0 });
+        assert_eq!(annotations[1].shape, Shape::Polyline);
+    }
+
+    #[test]
+    fn json_exports_accept_numeric_point_pairs() {
+        let export = serde_json::json!({
+            "Images": [{
+                "SOPInstanceUID": "1.2.3",
+                "ROIs": [{ "Name": "Seed", "Type": 19, "Point_px": [[3, 4]] }]
+            }]
+        });
+        let annotations = from_osirix(&export).unwrap();
+        assert_eq!(annotations[0].image, ImageRef::SopInstance("1.2.3".into()));
+        assert_eq!(annotations[0].shape, Shape::Point);
+        assert_eq!(annotations[0].points, [PixelPoint { x: 3.0, y: 4.0 }]);
+    }
+
+    #[test]
+    fn lines_are_traced_and_points_marked() {
+        let mut mask = vec![false; 16];
+        let line = Annotation {
+            label: "L".into(),
+            image: ImageRef::Index(0),
+            frame: 0,
+            shape: Shape::Polyline,
+            points: vec![PixelPoint { x: 0.0, y: 0.0 }, PixelPoint { x: 3.0, y: 3.0 }],
+        };
+        draw(&line, 4, 4, &mut mask);
+        let set: Vec<usize> = (0..16).filter(|&i| mask[i]).collect();
+        assert_eq!(set, [0, 5, 10, 15]);
+    }
+}
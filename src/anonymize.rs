@@ -6,102 +6,698 @@
 //
 // Thales Matheus Mendonça Santos - November 2025
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use dicom::core::header::Header;
-use dicom::core::value::PrimitiveValue;
+use dicom::core::value::{DataSetSequence, PrimitiveValue, Value};
 use dicom::core::{DataElement, Tag, VR};
-use dicom::object::{open_file, InMemDicomObject};
+use dicom::object::mem::InMemElement;
+use dicom::object::{DefaultDicomObject, InMemDicomObject, OpenFileOptions};
+use dicom::transfer_syntax::entries::{
+    DEFLATED_EXPLICIT_VR_LITTLE_ENDIAN, IMPLICIT_VR_LITTLE_ENDIAN, JPIP_REFERENCED_DEFLATE,
+};
+use rayon::prelude::*;
 use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
 
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+use crate::atomic_file;
+use crate::derivation::{self, Derivation, DerivationPolicy, SourceImage};
+use crate::descriptors;
+use crate::dicom_access::{hashed_uid, ElementAccess, EXPLICIT_VR_BIG_ENDIAN};
+
+const SOP_INSTANCE_UID: Tag = Tag(0x0008, 0x0018);
+const SERIES_INSTANCE_UID: Tag = Tag(0x0020, 0x000E);
+const PATIENT_NAME: Tag = Tag(0x0010, 0x0010);
+const PATIENT_ID: Tag = Tag(0x0010, 0x0020);
+const PIXEL_DATA: Tag = Tag(0x7FE0, 0x0010);
+const INSTITUTION_NAME: Tag = Tag(0x0008, 0x0080);
+const INSTITUTION_ADDRESS: Tag = Tag(0x0008, 0x0081);
+const STATION_NAME: Tag = Tag(0x0008, 0x1010);
+const INSTITUTIONAL_DEPARTMENT_NAME: Tag = Tag(0x0008, 0x1040);
+const OPERATORS_NAME: Tag = Tag(0x0008, 0x1070);
+const DEVICE_SERIAL_NUMBER: Tag = Tag(0x0018, 0x1000);
+const IDENTIFYING_COMMENTS: Tag = Tag(0x0008, 0x4000);
+const MODIFIED_ATTRIBUTES_SEQUENCE: Tag = Tag(0x0400, 0x0550);
+const ORIGINAL_ATTRIBUTES_SEQUENCE: Tag = Tag(0x0400, 0x0561);
+const ATTRIBUTE_MODIFICATION_DATETIME: Tag = Tag(0x0400, 0x0562);
+const MODIFYING_SYSTEM_TAG: Tag = Tag(0x0400, 0x0563);
+const SOURCE_OF_PREVIOUS_VALUES: Tag = Tag(0x0400, 0x0564);
+const REASON_FOR_MODIFICATION: Tag = Tag(0x0400, 0x0565);
+
 /// Generate a reproducible anonymized identifier by hashing the original value and trimming it.
 fn generate_hash(original: &str) -> String {
     let mut hasher = Sha256::new();
     hasher.update(original.as_bytes());
     let result = hasher.finalize();
-    hex::encode(&result)[..16].to_uppercase()
+    hex::encode(result)[..16].to_uppercase()
+}
+
+fn anonymized_patient_id(original: &str) -> String {
+    format!("ANON_{}", generate_hash(original))
+}
+
+/// UIDs naming instances of this study (rather than classes or syntaxes), replaced when
+/// [`AnonymizeOptions::remap_uids`] is set.
+const INSTANCE_UIDS: [Tag; 8] = [
+    Tag(0x0008, 0x0014), // Instance Creator UID
+    SOP_INSTANCE_UID,
+    Tag(0x0008, 0x1155), // Referenced SOP Instance UID
+    Tag(0x0020, 0x000D), // Study Instance UID
+    SERIES_INSTANCE_UID,
+    Tag(0x0020, 0x0052), // Frame of Reference UID
+    Tag(0x0020, 0x0200), // Synchronization Frame of Reference UID
+    Tag(0x0020, 0x9161), // Concatenation UID
+];
+
+/// Which attributes survive anonymization.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Profile {
+    /// Hash the Patient ID, replace person names, dates and times.
+    #[default]
+    Basic,
+    /// Like [`Profile::Basic`] but keeps dates and times, for longitudinal research.
+    RetainDates,
+}
+
+/// Institution and equipment attributes to keep, decided separately from patient identity:
+/// multi-site trials often need to know which site and scanner produced an image.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct DeviceRetention {
+    /// Institution Name, Institution Address and Institutional Department Name.
+    pub institution: bool,
+    /// Station Name.
+    pub station: bool,
+    /// Device Serial Number.
+    pub device_serial: bool,
+    /// Operators' Name.
+    pub operators: bool,
+}
+
+impl DeviceRetention {
+    /// Keep the whole device identity.
+    pub const ALL: Self = Self {
+        institution: true,
+        station: true,
+        device_serial: true,
+        operators: true,
+    };
+
+    /// Whether `tag` is a device identity attribute, and if so whether it is kept.
+    fn retains(self, tag: Tag) -> Option<bool> {
+        match tag {
+            INSTITUTION_NAME | INSTITUTION_ADDRESS | INSTITUTIONAL_DEPARTMENT_NAME => {
+                Some(self.institution)
+            }
+            STATION_NAME => Some(self.station),
+            DEVICE_SERIAL_NUMBER => Some(self.device_serial),
+            OPERATORS_NAME => Some(self.operators),
+            _ => None,
+        }
+    }
+}
+
+/// Whole groups and modules dropped from the output, beyond value replacement.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct GroupRemoval {
+    /// Retired curve data, repeating groups 50xx (except their audio elements).
+    pub curves: bool,
+    /// Overlay planes, repeating groups 60xx.
+    pub overlays: bool,
+    /// Retired audio elements, (50xx,2000) to (50xx,200E).
+    pub audio: bool,
+    /// Identifying Comments (0008,4000).
+    pub identifying_comments: bool,
+    /// Group 0400: Original Attributes Sequence, MAC and digital signature attributes.
+    pub original_attributes: bool,
+}
+
+impl GroupRemoval {
+    fn removes(self, tag: Tag) -> bool {
+        let (group, element) = (tag.group(), tag.element());
+        let repeating = |base: u16| group >= base && group <= base + 0x1E && group % 2 == 0;
+        let audio = repeating(0x5000) && (0x2000..=0x200E).contains(&element);
+        (self.curves && repeating(0x5000) && !audio)
+            || (self.audio && audio)
+            || (self.overlays && repeating(0x6000))
+            || (self.identifying_comments && tag == IDENTIFYING_COMMENTS)
+            || (self.original_attributes && group == 0x0400)
+    }
+}
+
+/// Reason for the Attribute Modification (0400,0565) recorded with original values.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ModificationReason {
+    /// Values were changed to conform to local policy or standard.
+    Coerce,
+    /// Values were changed because they were wrong.
+    Correct,
+}
+
+impl ModificationReason {
+    fn code(self) -> &'static str {
+        match self {
+            ModificationReason::Coerce => "COERCE",
+            ModificationReason::Correct => "CORRECT",
+        }
+    }
+}
+
+/// Modifying System (0400,0563) recorded in Original Attributes Sequence items.
+pub const MODIFYING_SYSTEM: &str = "DICOM-TOOLS-RS";
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct AnonymizeOptions {
+    pub profile: Profile,
+    /// Replace instance UIDs with hashed `2.25` UIDs; the same original always maps to the
+    /// same replacement, so references between the instances of a study stay intact.
+    pub remap_uids: bool,
+    /// Device identity attributes left untouched; everything else of it is replaced.
+    pub retain_device: DeviceRetention,
+    /// Keep free-text descriptors but redact the names and dates inside them
+    /// (PS3.15 Clean Descriptors option) instead of leaving them as they are.
+    pub clean_descriptors: bool,
+    pub remove_groups: GroupRemoval,
+    /// Keep the previous value of every changed or removed top-level attribute in a new
+    /// Original Attributes Sequence (0400,0561) item, for traceable modification; the
+    /// output then still holds the original identifiers.
+    pub record_original: Option<ModificationReason>,
+}
+
+/// Fixed replacement for elements scrubbed by tag (device identity) or purely by VR.
+fn replacement_for(tag: Tag, vr: VR, options: AnonymizeOptions) -> Option<&'static str> {
+    if let Some(retained) = options.retain_device.retains(tag) {
+        return (!retained).then_some("ANONYMIZED");
+    }
+    match vr {
+        VR::PN if tag == PATIENT_NAME => Some("ANONYMOUS^PATIENT"),
+        VR::PN => Some("ANONYMIZED"),
+        VR::DA | VR::TM | VR::DT if options.profile == Profile::RetainDates => None,
+        VR::DA => Some("19010101"),
+        VR::TM => Some("000000"),
+        VR::DT => Some("19010101000000"),
+        _ => None,
+    }
+}
+
+/// Whether the anonymizer treats this element as identifying.
+pub fn is_identifying(tag: Tag, vr: VR) -> bool {
+    tag == PATIENT_ID || replacement_for(tag, vr, AnonymizeOptions::default()).is_some()
+}
+
+/// Replacement for an instance UID: a `2.25` UID built from the hash of the original.
+pub fn remap_uid(original: &str) -> String {
+    hashed_uid(original.trim_end_matches(['\0', ' ']))
 }
 
 pub fn anonymize_obj(obj: &mut InMemDicomObject) -> Result<()> {
-    // 1. Get original ID to derive a hash.
-    //    We avoid randomization so repeated runs on the same input remain stable.
-    let patient_id_tag = Tag(0x0010, 0x0020);
-    let original_id = obj
-        .element(patient_id_tag)
-        .ok()
-        .and_then(|e| e.to_str().ok())
-        .unwrap_or("UNKNOWN".into());
+    anonymize_obj_with(obj, AnonymizeOptions::default())
+}
 
-    let anon_id = format!("ANON_{}", generate_hash(&original_id));
+pub fn anonymize_obj_with(obj: &mut InMemDicomObject, options: AnonymizeOptions) -> Result<()> {
+    // The Patient ID hash is derived from the original value, so repeated runs on the
+    // same input remain stable; a missing ID still gets a (constant) anonymized value.
+    if obj.element(PATIENT_ID).is_err() {
+        obj.put(DataElement::new(
+            PATIENT_ID,
+            VR::LO,
+            PrimitiveValue::from(anonymized_patient_id("UNKNOWN")),
+        ));
+    }
+    // Person names are collected before they are replaced so descriptors mentioning them
+    // can be cleaned too.
+    let names: Vec<String> = if options.clean_descriptors {
+        obj.iter()
+            .filter(|elem| elem.vr() == VR::PN)
+            .filter_map(|elem| elem.to_str().ok().map(|v| v.into_owned()))
+            .flat_map(|value| descriptors::name_components(&value))
+            .collect()
+    } else {
+        Vec::new()
+    };
+    // Pixel Data never changes here, so it is left out of the snapshot.
+    let before: Option<Vec<InMemElement>> = options.record_original.map(|_| {
+        obj.iter()
+            .filter(|elem| elem.tag() != PIXEL_DATA)
+            .cloned()
+            .collect()
+    });
+    anonymize_dataset(obj, options, &names);
+    if let (Some(before), Some(reason)) = (before, options.record_original) {
+        record_original_attributes(obj, before, reason);
+    }
+    Ok(())
+}
+
+/// Append an Original Attributes Sequence item holding the previous value of every
+/// attribute in `before` that is now different or gone.
+fn record_original_attributes(
+    obj: &mut InMemDicomObject,
+    before: Vec<InMemElement>,
+    reason: ModificationReason,
+) {
+    let modified: Vec<InMemElement> = before
+        .into_iter()
+        .filter(|original| obj.element(original.tag()).ok() != Some(original))
+        .filter(|original| original.tag() != ORIGINAL_ATTRIBUTES_SEQUENCE)
+        .collect();
+    if modified.is_empty() {
+        return;
+    }
+    let mut item = InMemDicomObject::new_empty();
+    item.put(DataElement::new(
+        MODIFIED_ATTRIBUTES_SEQUENCE,
+        VR::SQ,
+        DataSetSequence::from(vec![InMemDicomObject::from_element_iter(modified)]),
+    ));
+    item.put(DataElement::new(
+        ATTRIBUTE_MODIFICATION_DATETIME,
+        VR::DT,
+        PrimitiveValue::from(chrono::Utc::now().format("%Y%m%d%H%M%S").to_string()),
+    ));
+    item.put(DataElement::new(
+        MODIFYING_SYSTEM_TAG,
+        VR::LO,
+        PrimitiveValue::from(MODIFYING_SYSTEM),
+    ));
+    item.put(DataElement::new(
+        SOURCE_OF_PREVIOUS_VALUES,
+        VR::LO,
+        PrimitiveValue::from(""),
+    ));
+    item.put(DataElement::new(
+        REASON_FOR_MODIFICATION,
+        VR::CS,
+        PrimitiveValue::from(reason.code()),
+    ));
 
-    // 2. Collect tags that need replacement based on VR
-    //    Walking once lets us avoid borrowing issues while editing later.
+    // Earlier modifications stay; this one is appended after them.
+    let mut items = obj
+        .element(ORIGINAL_ATTRIBUTES_SEQUENCE)
+        .ok()
+        .and_then(|elem| elem.items())
+        .map(|items| items.to_vec())
+        .unwrap_or_default();
+    items.push(item);
+    obj.put(DataElement::new(
+        ORIGINAL_ATTRIBUTES_SEQUENCE,
+        VR::SQ,
+        DataSetSequence::from(items),
+    ));
+}
+
+/// Scrub one dataset level in place and recurse into sequence items in parallel.
+fn anonymize_dataset(obj: &mut InMemDicomObject, options: AnonymizeOptions, names: &[String]) {
+    // Only tags are collected while iterating; values are rewritten in place afterwards
+    // so untouched elements are never cloned.
     let mut replacements = Vec::new();
+    let mut removals = Vec::new();
+    let mut cleaned = Vec::new();
+    let mut uids = Vec::new();
+    let mut sequences = Vec::new();
+    let mut patient_id = None;
 
     for elem in obj.iter() {
         let tag = elem.tag();
-        let vr = elem.vr();
-
-        // Skip PatientID (handled explicitly)
-        if tag == patient_id_tag {
-            continue;
-        }
-
-        match vr {
-            VR::PN => {
-                if tag == Tag(0x0010, 0x0010) {
-                    replacements.push((tag, vr, "ANONYMOUS^PATIENT".to_string()));
-                } else {
-                    replacements.push((tag, vr, "ANONYMIZED".to_string()));
-                }
+        match elem.value() {
+            _ if options.remove_groups.removes(tag) => removals.push(tag),
+            // When recording, earlier records are an audit trail and stay as they are.
+            Value::Sequence(_)
+                if options.record_original.is_some() && tag == ORIGINAL_ATTRIBUTES_SEQUENCE => {}
+            Value::Sequence(_) => sequences.push(tag),
+            _ if tag == PATIENT_ID => {
+                patient_id = Some(elem.to_str().map(|v| v.into_owned()).unwrap_or_default())
             }
-            VR::DA => {
-                replacements.push((tag, vr, "19010101".to_string()));
+            _ if options.clean_descriptors && descriptors::DESCRIPTOR_TAGS.contains(&tag) => {
+                let original = elem.to_str().map(|v| v.into_owned()).unwrap_or_default();
+                cleaned.push((tag, descriptors::clean(&original, names)));
             }
-            VR::TM => {
-                replacements.push((tag, vr, "000000".to_string()));
+            _ if options.remap_uids && INSTANCE_UIDS.contains(&tag) => {
+                let original = elem.to_str().map(|v| v.into_owned()).unwrap_or_default();
+                uids.push((tag, remap_uid(&original)));
             }
-            VR::DT => {
-                replacements.push((tag, vr, "19010101000000".to_string()));
+            _ => {
+                if let Some(value) = replacement_for(tag, elem.vr(), options) {
+                    replacements.push((tag, value));
+                }
             }
-            _ => {}
         }
     }
 
-    // 3. Apply generic replacements.
-    for (tag, vr, val) in replacements {
-        obj.put(DataElement::new(tag, vr, PrimitiveValue::from(val)));
+    for tag in removals {
+        obj.remove_element(tag);
     }
+    for (tag, value) in replacements {
+        obj.update_value(tag, |v| *v = Value::Primitive(PrimitiveValue::from(value)));
+    }
+    for (tag, text) in cleaned {
+        obj.update_value(tag, |v| {
+            *v = Value::Primitive(PrimitiveValue::from(text.as_str()))
+        });
+    }
+    for (tag, uid) in uids {
+        obj.update_value(tag, |v| {
+            *v = Value::Primitive(PrimitiveValue::from(uid.as_str()))
+        });
+    }
+    if let Some(original) = patient_id {
+        let original = original.trim_end_matches(['\0', ' ']);
+        let original = if original.is_empty() {
+            "UNKNOWN"
+        } else {
+            original
+        };
+        let anon_id = anonymized_patient_id(original);
+        obj.update_value(PATIENT_ID, |v| {
+            *v = Value::Primitive(PrimitiveValue::from(anon_id.as_str()))
+        });
+    }
+    for tag in sequences {
+        obj.update_value(tag, |v| {
+            if let Some(items) = v.items_mut() {
+                items
+                    .par_iter_mut()
+                    .for_each(|item| anonymize_dataset(item, options, names));
+            }
+        });
+    }
+}
 
-    // 4. Apply specific PatientID override with the derived hash.
-    obj.put(DataElement::new(
-        patient_id_tag,
-        VR::LO,
-        PrimitiveValue::from(anon_id),
-    ));
-
-    Ok(())
+/// `<stem>_anon.dcm` next to the input, used when no output path is given.
+pub fn default_output_path(input: &Path) -> PathBuf {
+    let mut p = input.to_path_buf();
+    let stem = p.file_stem().unwrap().to_str().unwrap();
+    p.set_file_name(format!("{}_anon.dcm", stem));
+    p
 }
 
 pub fn process_file(input: &Path, output: Option<PathBuf>) -> Result<()> {
-    let mut obj = open_file(input)?;
-
-    anonymize_obj(&mut obj)?;
+    process_file_with(
+        input,
+        output,
+        AnonymizeOptions::default(),
+        DerivationPolicy::Preserve,
+    )
+}
 
-    // 5. Save file
-    let output_path = output.unwrap_or_else(|| {
-        let mut p = input.to_path_buf();
-        let stem = p.file_stem().unwrap().to_str().unwrap();
-        p.set_file_name(format!("{}_anon.dcm", stem));
-        p
-    });
+/// Like [`process_file`], with explicit `options` and provenance recorded on the copy as
+/// `derivation` asks for.
+pub fn process_file_with(
+    input: &Path,
+    output: Option<PathBuf>,
+    options: AnonymizeOptions,
+    derivation: DerivationPolicy,
+) -> Result<()> {
+    let output_path = output.unwrap_or_else(|| default_output_path(input));
 
-    obj.write_to_file(&output_path)?;
+    anonymize_file_with(input, &output_path, options, derivation)?;
     println!("Anonymized file saved to: {:?}", output_path);
 
     Ok(())
 }
 
+/// Anonymize `input` into `output`, copying pixel data bytes verbatim when possible.
+///
+/// Only header attributes change, so the dataset is parsed up to Pixel Data, scrubbed,
+/// re-encoded, and the remaining bytes of the source are appended unchanged. Encodings
+/// where that splice is not byte-safe (deflated, big endian) load the whole file instead.
+pub fn anonymize_file(input: &Path, output: &Path) -> Result<()> {
+    anonymize_file_with(
+        input,
+        output,
+        AnonymizeOptions::default(),
+        DerivationPolicy::Preserve,
+    )
+}
+
+/// Like [`anonymize_file`], with explicit `options` and provenance recorded on the copy as
+/// `derivation` asks for.
+pub fn anonymize_file_with(
+    input: &Path,
+    output: &Path,
+    options: AnonymizeOptions,
+    derivation: DerivationPolicy,
+) -> Result<()> {
+    let header = AnonymizedHeader::read(input, options, derivation)?;
+    atomic_file::write_with(output, |writer| header.write(input, writer))
+}
+
+/// Anonymize every file of a study with remapped UIDs and write the results to `writer` as
+/// a ZIP archive laid out as `<series UID>/<SOP Instance UID>.dcm` (both remapped); UIDs are
+/// remapped whatever `options` says. Entries go through the same splice as
+/// [`anonymize_file_with`], so only one header is held in memory at a time.
+pub fn anonymize_study_zip<W: Write + Seek>(
+    files: &[PathBuf],
+    options: AnonymizeOptions,
+    writer: W,
+) -> Result<W> {
+    let options = AnonymizeOptions {
+        remap_uids: true,
+        ..options
+    };
+    let mut archive = ZipWriter::new(writer);
+    let entry_options =
+        SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+    for path in files {
+        let header = AnonymizedHeader::read(path, options, DerivationPolicy::Preserve)
+            .with_context(|| format!("Failed to anonymize {:?}", path))?;
+        let uid = |tag| {
+            header
+                .obj
+                .element_str(tag)
+                .map(|s| s.trim_end_matches(['\0', ' ']).to_string())
+                .unwrap_or_else(|| "unknown".to_string())
+        };
+        let name = format!("{}/{}.dcm", uid(SERIES_INSTANCE_UID), uid(SOP_INSTANCE_UID));
+        archive.start_file(name, entry_options)?;
+        header.write(path, &mut archive)?;
+    }
+    Ok(archive.finish()?)
+}
+
+/// Anonymized header of a file, plus where the source bytes to append after it start.
+struct AnonymizedHeader {
+    obj: DefaultDicomObject,
+    /// Offset of Pixel Data in the source, or `None` when `obj` holds the whole data set.
+    tail_offset: Option<u64>,
+}
+
+impl AnonymizedHeader {
+    /// Only header attributes change, so the dataset is parsed up to Pixel Data and scrubbed;
+    /// encodings where the splice is not byte-safe (deflated, big endian) load the whole file.
+    fn read(input: &Path, options: AnonymizeOptions, derivation: DerivationPolicy) -> Result<Self> {
+        let obj = OpenFileOptions::new()
+            .read_until(PIXEL_DATA)
+            .open_file(input)
+            .context("Failed to open DICOM file")?;
+        let ts = obj
+            .meta()
+            .transfer_syntax()
+            .trim_end_matches('\0')
+            .to_string();
+        let tail_offset = match splice_encoding(&ts) {
+            Some(explicit_vr) => pixel_data_offset(input, explicit_vr)
+                .with_context(|| format!("Failed to scan {:?}", input))?,
+            None => None,
+        };
+        let mut obj = match tail_offset {
+            Some(_) => obj,
+            // No splice possible (or no pixel data): fall back to a full read and write.
+            None => dicom::object::open_file(input)?,
+        };
+
+        let source = SourceImage::of(&obj);
+        anonymize_obj_with(&mut obj, options)?;
+        derivation::apply(&mut obj, &source, Derivation::Anonymization, derivation);
+        // A remapped SOP Instance UID must not leave the original in the file meta group.
+        if let Some(instance) = obj.element_str(SOP_INSTANCE_UID) {
+            let instance = instance.trim_end_matches(['\0', ' ']).to_string();
+            if obj.meta().media_storage_sop_instance_uid() != instance {
+                obj.meta_mut().media_storage_sop_instance_uid = instance;
+                obj.meta_mut().update_information_group_length();
+            }
+        }
+        Ok(Self { obj, tail_offset })
+    }
+
+    /// Write the header, then the source bytes from Pixel Data on, unchanged.
+    fn write(&self, input: &Path, mut writer: impl Write) -> Result<()> {
+        self.obj.write_all(&mut writer)?;
+        if let Some(offset) = self.tail_offset {
+            let mut source = File::open(input)?;
+            source.seek(SeekFrom::Start(offset))?;
+            io::copy(&mut source, &mut writer)?;
+        }
+        Ok(())
+    }
+}
+
+/// Little endian, non-deflated transfer syntaxes can be spliced; returns whether VR is explicit.
+fn splice_encoding(ts: &str) -> Option<bool> {
+    if ts == IMPLICIT_VR_LITTLE_ENDIAN.uid() {
+        Some(false)
+    } else if ts == EXPLICIT_VR_BIG_ENDIAN
+        || ts == DEFLATED_EXPLICIT_VR_LITTLE_ENDIAN.uid()
+        || ts == JPIP_REFERENCED_DEFLATE.uid()
+    {
+        None
+    } else {
+        Some(true)
+    }
+}
+
+/// Byte offset of the top-level Pixel Data element header, found by skipping over
+/// element values without decoding them.
+fn pixel_data_offset(path: &Path, explicit_vr: bool) -> io::Result<Option<u64>> {
+    let mut walker = ElementWalker::new(File::open(path)?);
+    // Preamble and magic code, then File Meta Information Group Length (always explicit VR).
+    walker.skip(132)?;
+    let (tag, _, len) = walker.read_header(true)?;
+    if tag != Tag(0x0002, 0x0000) || len != 4 {
+        return Ok(None);
+    }
+    let meta_length = walker.read_u32()?;
+    walker.skip(meta_length as u64)?;
+
+    loop {
+        let start = walker.pos;
+        let (tag, vr, len) = match walker.read_header(explicit_vr) {
+            Ok(header) => header,
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        if tag == PIXEL_DATA {
+            return Ok(Some(start));
+        }
+        if tag > PIXEL_DATA {
+            return Ok(None);
+        }
+        walker.skip_value(vr, len, explicit_vr)?;
+    }
+}
+
+const UNDEFINED_LENGTH: u32 = 0xFFFF_FFFF;
+const ITEM: Tag = Tag(0xFFFE, 0xE000);
+const ITEM_DELIMITER: Tag = Tag(0xFFFE, 0xE00D);
+const SEQUENCE_DELIMITER: Tag = Tag(0xFFFE, 0xE0DD);
+
+/// Minimal little endian element scanner that tracks its byte position.
+pub(crate) struct ElementWalker<R> {
+    reader: BufReader<R>,
+    pub(crate) pos: u64,
+}
+
+impl<R: Read + Seek> ElementWalker<R> {
+    pub(crate) fn new(source: R) -> Self {
+        Self {
+            reader: BufReader::new(source),
+            pos: 0,
+        }
+    }
+
+    fn read_u16(&mut self) -> io::Result<u16> {
+        let mut buf = [0; 2];
+        self.reader.read_exact(&mut buf)?;
+        self.pos += 2;
+        Ok(u16::from_le_bytes(buf))
+    }
+
+    fn read_u32(&mut self) -> io::Result<u32> {
+        let mut buf = [0; 4];
+        self.reader.read_exact(&mut buf)?;
+        self.pos += 4;
+        Ok(u32::from_le_bytes(buf))
+    }
+
+    pub(crate) fn skip(&mut self, len: u64) -> io::Result<()> {
+        // Relative seeks keep the read buffer when the skipped value is small.
+        self.reader.seek_relative(len as i64)?;
+        self.pos += len;
+        Ok(())
+    }
+
+    /// Read tag, VR (explicit encodings only; item tags never carry one) and length.
+    pub(crate) fn read_header(
+        &mut self,
+        explicit_vr: bool,
+    ) -> io::Result<(Tag, Option<[u8; 2]>, u32)> {
+        let tag = Tag(self.read_u16()?, self.read_u16()?);
+        if tag.group() == 0xFFFE || !explicit_vr {
+            return Ok((tag, None, self.read_u32()?));
+        }
+        let vr = self.read_u16()?.to_le_bytes();
+        // PS3.5 7.1.2: these VRs use a reserved field and a 32-bit length.
+        let long_form = matches!(
+            &vr,
+            b"OB"
+                | b"OD"
+                | b"OF"
+                | b"OL"
+                | b"OV"
+                | b"OW"
+                | b"SQ"
+                | b"SV"
+                | b"UC"
+                | b"UN"
+                | b"UR"
+                | b"UT"
+                | b"UV"
+        );
+        let len = if long_form {
+            self.skip(2)?;
+            self.read_u32()?
+        } else {
+            self.read_u16()? as u32
+        };
+        Ok((tag, Some(vr), len))
+    }
+
+    pub(crate) fn skip_value(
+        &mut self,
+        vr: Option<[u8; 2]>,
+        len: u32,
+        explicit_vr: bool,
+    ) -> io::Result<()> {
+        if len != UNDEFINED_LENGTH {
+            return self.skip(len as u64);
+        }
+        // Undefined length: a sequence or encapsulated pixel data. UN content is always
+        // implicit VR little endian (PS3.5 6.2.2).
+        let nested_explicit = explicit_vr && vr != Some(*b"UN");
+        self.skip_items(nested_explicit)
+    }
+
+    fn skip_items(&mut self, explicit_vr: bool) -> io::Result<()> {
+        loop {
+            let (tag, _, len) = self.read_header(explicit_vr)?;
+            match tag {
+                SEQUENCE_DELIMITER => return Ok(()),
+                ITEM if len == UNDEFINED_LENGTH => self.skip_item_elements(explicit_vr)?,
+                ITEM => self.skip(len as u64)?,
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("unexpected {} inside sequence", tag),
+                    ))
+                }
+            }
+        }
+    }
+
+    fn skip_item_elements(&mut self, explicit_vr: bool) -> io::Result<()> {
+        loop {
+            let (tag, vr, len) = self.read_header(explicit_vr)?;
+            if tag == ITEM_DELIMITER {
+                return Ok(());
+            }
+            self.skip_value(vr, len, explicit_vr)?;
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -153,4 +749,225 @@ mod tests {
         let doctor = obj.element(Tag(0x0008, 0x0090)).unwrap().to_str().unwrap();
         assert_eq!(doctor, "ANONYMIZED");
     }
+
+    #[test]
+    fn device_identity_is_retained_separately_from_patient_identity() {
+        let device = || {
+            let mut obj = InMemDicomObject::new_empty();
+            for (tag, vr, value) in [
+                (PATIENT_NAME, VR::PN, "Doe^John"),
+                (INSTITUTION_NAME, VR::LO, "General Hospital"),
+                (STATION_NAME, VR::SH, "CT01"),
+                (DEVICE_SERIAL_NUMBER, VR::LO, "SN-42"),
+                (OPERATORS_NAME, VR::PN, "Tech^Ann"),
+            ] {
+                obj.put(DataElement::new(tag, vr, PrimitiveValue::from(value)));
+            }
+            obj
+        };
+        let text = |obj: &InMemDicomObject, tag| obj.element_str(tag).unwrap_or_default();
+
+        let mut scrubbed = device();
+        anonymize_obj(&mut scrubbed).unwrap();
+        for tag in [
+            INSTITUTION_NAME,
+            STATION_NAME,
+            DEVICE_SERIAL_NUMBER,
+            OPERATORS_NAME,
+        ] {
+            assert_eq!(text(&scrubbed, tag), "ANONYMIZED");
+        }
+
+        let mut kept = device();
+        let options = AnonymizeOptions {
+            retain_device: DeviceRetention {
+                station: true,
+                device_serial: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        anonymize_obj_with(&mut kept, options).unwrap();
+        assert_eq!(text(&kept, PATIENT_NAME), "ANONYMOUS^PATIENT");
+        assert_eq!(text(&kept, INSTITUTION_NAME), "ANONYMIZED");
+        assert_eq!(text(&kept, STATION_NAME), "CT01");
+        assert_eq!(text(&kept, DEVICE_SERIAL_NUMBER), "SN-42");
+        assert_eq!(text(&kept, OPERATORS_NAME), "ANONYMIZED");
+
+        let mut all = device();
+        let options = AnonymizeOptions {
+            retain_device: DeviceRetention::ALL,
+            ..Default::default()
+        };
+        anonymize_obj_with(&mut all, options).unwrap();
+        assert_eq!(text(&all, OPERATORS_NAME), "Tech^Ann");
+        assert_eq!(text(&all, INSTITUTION_NAME), "General Hospital");
+    }
+
+    #[test]
+    fn group_removal_drops_only_the_selected_groups() {
+        let overlay_data = Tag(0x6002, 0x3000);
+        let curve_data = Tag(0x5000, 0x3000);
+        let audio_type = Tag(0x5000, 0x2000);
+        let original_attributes = Tag(0x0400, 0x0561);
+        let mut obj = InMemDicomObject::new_empty();
+        for (tag, vr, value) in [
+            (overlay_data, VR::OW, "0"),
+            (curve_data, VR::OB, "0"),
+            (audio_type, VR::US, "1"),
+            (IDENTIFYING_COMMENTS, VR::LT, "call Mrs Doe"),
+            (STATION_NAME, VR::SH, "CT01"),
+        ] {
+            obj.put(DataElement::new(tag, vr, PrimitiveValue::from(value)));
+        }
+        obj.put(DataElement::new(
+            original_attributes,
+            VR::SQ,
+            dicom::core::value::DataSetSequence::from(vec![InMemDicomObject::new_empty()]),
+        ));
+
+        let options = AnonymizeOptions {
+            remove_groups: GroupRemoval {
+                overlays: true,
+                audio: true,
+                identifying_comments: true,
+                original_attributes: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        anonymize_obj_with(&mut obj, options).unwrap();
+        for tag in [
+            overlay_data,
+            audio_type,
+            IDENTIFYING_COMMENTS,
+            original_attributes,
+        ] {
+            assert!(obj.element(tag).is_err(), "{} should be removed", tag);
+        }
+        assert!(obj.element(curve_data).is_ok());
+        assert!(obj.element(STATION_NAME).is_ok());
+    }
+
+    #[test]
+    fn record_original_keeps_previous_values_in_a_new_item() {
+        let mut obj = InMemDicomObject::new_empty();
+        obj.put(DataElement::new(
+            PATIENT_NAME,
+            VR::PN,
+            PrimitiveValue::from("Doe^John"),
+        ));
+        obj.put(DataElement::new(
+            PATIENT_ID,
+            VR::LO,
+            PrimitiveValue::from("12345"),
+        ));
+        obj.put(DataElement::new(
+            Tag(0x0008, 0x0060),
+            VR::CS,
+            PrimitiveValue::from("CT"),
+        ));
+        let options = AnonymizeOptions {
+            record_original: Some(ModificationReason::Coerce),
+            ..Default::default()
+        };
+        anonymize_obj_with(&mut obj, options).unwrap();
+        // A second run appends instead of replacing the first record.
+        anonymize_obj_with(&mut obj, options).unwrap();
+
+        let items = obj.sequence_items(ORIGINAL_ATTRIBUTES_SEQUENCE);
+        assert_eq!(items.len(), 2);
+        let first = &items[0];
+        assert_eq!(
+            first.element_str(REASON_FOR_MODIFICATION).unwrap(),
+            "COERCE"
+        );
+        assert_eq!(
+            first.element_str(MODIFYING_SYSTEM_TAG).unwrap(),
+            MODIFYING_SYSTEM
+        );
+        let modified = &first.sequence_items(MODIFIED_ATTRIBUTES_SEQUENCE)[0];
+        assert_eq!(modified.element_str(PATIENT_NAME).unwrap(), "Doe^John");
+        assert_eq!(modified.element_str(PATIENT_ID).unwrap(), "12345");
+        // Unchanged attributes are not recorded.
+        assert!(modified.element(Tag(0x0008, 0x0060)).is_err());
+    }
+
+    #[test]
+    fn clean_descriptors_redacts_names_known_from_the_dataset() {
+        let study_description = Tag(0x0008, 0x1030);
+        let build = || {
+            let mut obj = InMemDicomObject::new_empty();
+            obj.put(DataElement::new(
+                PATIENT_NAME,
+                VR::PN,
+                PrimitiveValue::from("DOE^JOHN"),
+            ));
+            obj.put(DataElement::new(
+                study_description,
+                VR::LO,
+                PrimitiveValue::from("CT CHEST DOE 01/31/2024"),
+            ));
+            obj
+        };
+
+        let mut untouched = build();
+        anonymize_obj(&mut untouched).unwrap();
+        assert_eq!(
+            untouched.element_str(study_description).unwrap(),
+            "CT CHEST DOE 01/31/2024"
+        );
+
+        let mut cleaned = build();
+        let options = AnonymizeOptions {
+            clean_descriptors: true,
+            ..Default::default()
+        };
+        anonymize_obj_with(&mut cleaned, options).unwrap();
+        assert_eq!(
+            cleaned.element_str(study_description).unwrap(),
+            "CT CHEST [NAME] [DATE]"
+        );
+    }
+
+    #[test]
+    fn pixel_data_offset_skips_undefined_length_sequences() {
+        use dicom::core::value::DataSetSequence;
+        use dicom::object::{FileDicomObject, FileMetaTableBuilder};
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("seq.dcm");
+        let meta = FileMetaTableBuilder::new()
+            .transfer_syntax("1.2.840.10008.1.2.1")
+            .media_storage_sop_class_uid("1.2.840.10008.5.1.4.1.1.7")
+            .media_storage_sop_instance_uid("1.2.3.4")
+            .build()
+            .unwrap();
+        let mut obj = FileDicomObject::new_empty_with_meta(meta);
+        let mut item = InMemDicomObject::new_empty();
+        item.put(DataElement::new(
+            Tag(0x0008, 0x1155),
+            VR::UI,
+            PrimitiveValue::from("1.2.3"),
+        ));
+        obj.put(DataElement::new(
+            Tag(0x0008, 0x1140),
+            VR::SQ,
+            DataSetSequence::from(vec![item]),
+        ));
+        obj.put(DataElement::new(
+            PIXEL_DATA,
+            VR::OB,
+            PrimitiveValue::from(vec![1_u8, 2, 3, 4]),
+        ));
+        obj.write_to_file(&path).unwrap();
+
+        let offset = pixel_data_offset(&path, true).unwrap().expect("offset");
+        let bytes = std::fs::read(&path).unwrap();
+        assert_eq!(
+            &bytes[offset as usize..offset as usize + 4],
+            &[0xE0, 0x7F, 0x10, 0x00]
+        );
+        assert_eq!(&bytes[bytes.len() - 4..], &[1, 2, 3, 4]);
+    }
 }
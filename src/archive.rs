@@ -0,0 +1,272 @@
+//
+// archive.rs
+// Dicom-Tools-rs
+//
+// Reads DICOM instances straight out of `.zip`, `.tar` and `.tar.gz` study exports, one entry
+// at a time, so commands can run on an archive without extracting it first.
+//
+// Thales Matheus Mendonça Santos - November 2025
+
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::{Context, Result};
+use flate2::read::GzDecoder;
+use tempfile::{NamedTempFile, TempDir};
+
+use crate::atomic_file;
+
+/// Offset of the `DICM` magic code, after the 128-byte preamble.
+const MAGIC_OFFSET: usize = 128;
+
+/// Archive formats recognised from the file name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveKind {
+    Zip,
+    Tar,
+    TarGz,
+}
+
+impl ArchiveKind {
+    pub fn of(path: &Path) -> Option<Self> {
+        let name = path.file_name()?.to_string_lossy().to_ascii_lowercase();
+        if name.ends_with(".zip") {
+            Some(ArchiveKind::Zip)
+        } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Some(ArchiveKind::TarGz)
+        } else if name.ends_with(".tar") {
+            Some(ArchiveKind::Tar)
+        } else {
+            None
+        }
+    }
+}
+
+/// Entries seen while walking an archive.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ArchiveSummary {
+    /// DICOM entries handed to the visitor.
+    pub dicom: usize,
+    /// Regular file entries without the `DICM` magic, left unread. DICOMDIR counts as neither.
+    pub skipped: usize,
+}
+
+/// Call `visit` for each DICOM entry of `archive` with the entry's path inside the archive
+/// and a scratch copy of its bytes. Entries are recognised by the `DICM` magic rather than
+/// their extension, since PACS exports often have none. Only one entry is on disk at a time;
+/// an error from `visit` stops the walk.
+pub fn for_each_dicom_entry(
+    archive: &Path,
+    mut visit: impl FnMut(&Path, &Path) -> Result<()>,
+) -> Result<ArchiveSummary> {
+    let kind = ArchiveKind::of(archive)
+        .with_context(|| format!("{:?} is not a .zip, .tar or .tar.gz archive", archive))?;
+    let file = File::open(archive).with_context(|| format!("Failed to open {:?}", archive))?;
+    let mut scratch = ScratchFile::new()?;
+    let mut summary = ArchiveSummary::default();
+    let mut entry = |name: &Path, reader: &mut dyn Read| -> Result<()> {
+        if name.file_name().is_some_and(|n| n == "DICOMDIR") {
+            return Ok(());
+        }
+        if scratch.fill_if_dicom(reader)? {
+            summary.dicom += 1;
+            visit(name, scratch.0.path())
+        } else {
+            summary.skipped += 1;
+            Ok(())
+        }
+    };
+    match kind {
+        ArchiveKind::Zip => {
+            let mut zip = zip::ZipArchive::new(BufReader::new(file))
+                .with_context(|| format!("Failed to read {:?}", archive))?;
+            for index in 0..zip.len() {
+                let mut file = zip.by_index(index)?;
+                // Names escaping the archive root are refused by `enclosed_name`.
+                let Some(name) = file.enclosed_name().filter(|_| file.is_file()) else {
+                    continue;
+                };
+                entry(&name, &mut file)?;
+            }
+        }
+        ArchiveKind::Tar => walk_tar(tar::Archive::new(BufReader::new(file)), &mut entry)?,
+        ArchiveKind::TarGz => walk_tar(
+            tar::Archive::new(GzDecoder::new(BufReader::new(file))),
+            &mut entry,
+        )?,
+    }
+    Ok(summary)
+}
+
+/// DICOM entries of an archive unpacked under a temporary directory, removed when dropped.
+/// For consumers that need every instance at hand at once, such as a single association
+/// negotiated over all SOP classes.
+#[derive(Debug)]
+pub struct ExtractedArchive {
+    root: TempDir,
+    pub summary: ArchiveSummary,
+}
+
+impl ExtractedArchive {
+    /// Unpack the DICOM entries of `archive`, keeping their paths inside the archive.
+    pub fn extract(archive: &Path) -> Result<Self> {
+        let root = atomic_file::private_temp_dir("dicom-tools-extract-")?;
+        let mut extracted = ExtractedArchive {
+            root,
+            summary: ArchiveSummary::default(),
+        };
+        extracted.summary = for_each_dicom_entry(archive, |name, scratch| {
+            let target = extracted.root.path().join(name);
+            fs::create_dir_all(target.parent().unwrap_or(extracted.root.path()))?;
+            fs::copy(scratch, &target).with_context(|| format!("Failed to extract {:?}", name))?;
+            Ok(())
+        })?;
+        Ok(extracted)
+    }
+
+    pub fn path(&self) -> &Path {
+        self.root.path()
+    }
+
+    /// Where an extracted file came from, as `archive/entry`, for messages to the user.
+    pub fn origin(&self, archive: &Path, extracted: &Path) -> PathBuf {
+        extracted
+            .strip_prefix(self.root.path())
+            .map(|entry| archive.join(entry))
+            .unwrap_or_else(|_| extracted.to_path_buf())
+    }
+}
+
+fn walk_tar<R: Read>(
+    mut archive: tar::Archive<R>,
+    entry: &mut dyn FnMut(&Path, &mut dyn Read) -> Result<()>,
+) -> Result<()> {
+    for file in archive.entries().context("Failed to read tar archive")? {
+        let mut file = file.context("Failed to read tar entry")?;
+        if !file.header().entry_type().is_file() {
+            continue;
+        }
+        let Some(name) = enclosed(&file.path()?) else {
+            continue;
+        };
+        entry(&name, &mut file)?;
+    }
+    Ok(())
+}
+
+/// `name` made of plain components only, or `None` when it would leave the archive root.
+fn enclosed(name: &Path) -> Option<PathBuf> {
+    let mut clean = PathBuf::new();
+    for component in name.components() {
+        match component {
+            Component::Normal(part) => clean.push(part),
+            Component::CurDir => {}
+            _ => return None,
+        }
+    }
+    (!clean.as_os_str().is_empty()).then_some(clean)
+}
+
+/// Private (0600) temporary file holding the current entry, removed when dropped.
+struct ScratchFile(NamedTempFile);
+
+impl ScratchFile {
+    fn new() -> Result<Self> {
+        let file = tempfile::Builder::new()
+            .prefix("dicom-tools-archive-")
+            .suffix(".dcm")
+            .tempfile()
+            .context("Failed to create scratch file")?;
+        Ok(ScratchFile(file))
+    }
+
+    /// Copy `reader` into the scratch file when it starts like a DICOM Part 10 file;
+    /// other entries are left unread.
+    fn fill_if_dicom(&mut self, reader: &mut dyn Read) -> Result<bool> {
+        let mut head = Vec::with_capacity(MAGIC_OFFSET + 4);
+        reader
+            .take((MAGIC_OFFSET + 4) as u64)
+            .read_to_end(&mut head)?;
+        if head.get(MAGIC_OFFSET..) != Some(b"DICM".as_slice()) {
+            return Ok(false);
+        }
+        let file = self.0.as_file_mut();
+        file.set_len(0)?;
+        file.seek(SeekFrom::Start(0))?;
+        file.write_all(&head)?;
+        io::copy(reader, file)?;
+        file.flush()?;
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::synth::{self, SynthSpec};
+    use flate2::write::GzEncoder;
+    use zip::write::SimpleFileOptions;
+
+    #[test]
+    fn dicom_entries_are_read_from_zip_and_tar_gz() {
+        let dir = tempfile::tempdir().unwrap();
+        let spec = SynthSpec {
+            instances: 2,
+            ..SynthSpec::default()
+        };
+        let files = synth::write_series(&spec, &dir.path().join("series")).unwrap();
+
+        let zip_path = dir.path().join("study.zip");
+        let mut zip = zip::ZipWriter::new(File::create(&zip_path).unwrap());
+        for (index, file) in files.iter().enumerate() {
+            zip.start_file(
+                format!("STUDY/SER1/IM{}", index),
+                SimpleFileOptions::default(),
+            )
+            .unwrap();
+            zip.write_all(&fs::read(file).unwrap()).unwrap();
+        }
+        zip.start_file("README.txt", SimpleFileOptions::default())
+            .unwrap();
+        zip.write_all(b"exported by PACS").unwrap();
+        zip.finish().unwrap();
+
+        let tgz_path = dir.path().join("study.tar.gz");
+        let mut tar = tar::Builder::new(GzEncoder::new(
+            File::create(&tgz_path).unwrap(),
+            flate2::Compression::default(),
+        ));
+        for file in &files {
+            tar.append_path_with_name(file, Path::new("export").join(file.file_name().unwrap()))
+                .unwrap();
+        }
+        tar.into_inner().unwrap().finish().unwrap();
+
+        for (archive, first) in [
+            (&zip_path, "STUDY/SER1/IM0"),
+            (&tgz_path, "export/IMG0001.dcm"),
+        ] {
+            let mut names = Vec::new();
+            let summary = for_each_dicom_entry(archive, |name, scratch| {
+                dicom::object::open_file(scratch)?;
+                names.push(name.to_path_buf());
+                Ok(())
+            })
+            .unwrap();
+            assert_eq!(summary.dicom, 2, "{:?}", archive);
+            assert_eq!(names[0], Path::new(first));
+        }
+        assert!(for_each_dicom_entry(&files[0], |_, _| Ok(())).is_err());
+    }
+
+    #[test]
+    fn entry_names_cannot_leave_the_archive_root() {
+        assert_eq!(
+            enclosed(Path::new("./a/b.dcm")),
+            Some(PathBuf::from("a/b.dcm"))
+        );
+        assert_eq!(enclosed(Path::new("../escape.dcm")), None);
+        assert_eq!(enclosed(Path::new("/etc/passwd")), None);
+    }
+}
@@ -0,0 +1,149 @@
+//
+// atomic_file.rs
+// Dicom-Tools-rs
+//
+// Crash-safe file outputs: data goes to a temporary file in the destination directory, is
+// fsynced, then renamed over the destination, so readers see the old file or the new one. Also
+// hands out owner-only scratch space under the system temp dir for intermediate copies.
+//
+// Thales Matheus Mendonça Santos - November 2025
+
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use anyhow::{Context, Result};
+use dicom::object::{FileDicomObject, InMemDicomObject};
+
+/// Distinguishes temporary files of concurrent writes from the same process.
+static NEXT_TEMP: AtomicU64 = AtomicU64::new(0);
+
+/// Write `path` through `write`, atomically. On any error the temporary file is removed
+/// and whatever was at `path` before is left untouched.
+pub fn write_with<F>(path: &Path, write: F) -> Result<()>
+where
+    F: FnOnce(&mut BufWriter<File>) -> Result<()>,
+{
+    let temp = temp_path(path)?;
+    let result = write_and_rename(&temp, path, write);
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    result.with_context(|| format!("Failed to write {:?}", path))
+}
+
+/// Atomic counterpart of [`std::fs::write`].
+pub fn write(path: &Path, bytes: impl AsRef<[u8]>) -> Result<()> {
+    write_with(path, |writer| Ok(writer.write_all(bytes.as_ref())?))
+}
+
+/// Atomic counterpart of [`FileDicomObject::write_to_file`].
+pub fn write_dicom(path: &Path, obj: &FileDicomObject<InMemDicomObject>) -> Result<()> {
+    write_with(path, |writer| Ok(obj.write_all(writer)?))
+}
+
+/// Atomic counterpart of [`image::DynamicImage::save`], the format taken from the extension.
+pub fn write_image(path: &Path, image: &image::DynamicImage) -> Result<()> {
+    let format = image::ImageFormat::from_path(path)
+        .with_context(|| format!("Unsupported image format for {:?}", path))?;
+    write_with(path, |writer| Ok(image.write_to(writer, format)?))
+}
+
+/// Owner-only (0700 on Unix) directory under the system temp dir, removed when dropped. The
+/// name is random and creation fails rather than reuse an existing path, so another user of a
+/// shared /tmp can neither predict it nor plant a symlink there. Files need no counterpart:
+/// `tempfile::Builder::tempfile` already creates them new and 0600.
+pub fn private_temp_dir(prefix: &str) -> Result<tempfile::TempDir> {
+    let mut builder = tempfile::Builder::new();
+    builder.prefix(prefix);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        builder.permissions(fs::Permissions::from_mode(0o700));
+    }
+    builder
+        .tempdir()
+        .context("Failed to create a private temporary directory")
+}
+
+/// Hidden sibling of `path`; the same directory keeps the final rename on one filesystem.
+fn temp_path(path: &Path) -> Result<PathBuf> {
+    let name = path
+        .file_name()
+        .with_context(|| format!("{:?} is not a file path", path))?;
+    Ok(path.with_file_name(format!(
+        ".{}.{}-{}.tmp",
+        name.to_string_lossy(),
+        std::process::id(),
+        NEXT_TEMP.fetch_add(1, Ordering::Relaxed)
+    )))
+}
+
+fn write_and_rename<F>(temp: &Path, path: &Path, write: F) -> Result<()>
+where
+    F: FnOnce(&mut BufWriter<File>) -> Result<()>,
+{
+    let mut writer = BufWriter::new(File::create(temp)?);
+    write(&mut writer)?;
+    let file = writer.into_inner().map_err(|err| err.into_error())?;
+    // The data must be durable before the rename makes it visible under the final name.
+    file.sync_all()?;
+    drop(file);
+    fs::rename(temp, path)?;
+    sync_parent(path);
+    Ok(())
+}
+
+/// Persist the rename itself. Best effort: not every platform can open a directory.
+fn sync_parent(path: &Path) {
+    #[cfg(unix)]
+    if let Some(dir) = path.parent() {
+        let dir = if dir.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            dir
+        };
+        if let Ok(dir) = File::open(dir) {
+            let _ = dir.sync_all();
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn failed_writes_leave_the_previous_file_in_place() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("out.dcm");
+        write(&path, b"first").unwrap();
+
+        let err = write_with(&path, |writer| {
+            writer.write_all(b"half")?;
+            anyhow::bail!("crashed mid-write")
+        });
+        assert!(err.is_err());
+        assert_eq!(fs::read(&path).unwrap(), b"first");
+
+        write(&path, b"second").unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"second");
+        // No temporary file is left behind either way.
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn private_temp_dirs_are_owner_only() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = private_temp_dir("dicom-tools-test-").unwrap();
+        let mode = fs::metadata(dir.path()).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o700);
+        let path = dir.path().to_path_buf();
+        drop(dir);
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,554 @@
+//
+// audit.rs
+// Dicom-Tools-rs
+//
+// ATNA-style audit trail: DICOM PS3.15 A.5 audit messages (the RFC 3881 schema) for PHI
+// exports, imports and de-identifications, appended to a file or sent to a syslog collector.
+//
+// Thales Matheus Mendonça Santos - November 2025
+
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::net::{IpAddr, UdpSocket};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local, SecondsFormat};
+use dicom::core::Tag;
+use dicom::object::DefaultDicomObject;
+
+use crate::dicom_access::ElementAccess;
+use crate::scp::IndexedInstance;
+
+const PATIENT_ID: Tag = Tag(0x0010, 0x0020);
+const STUDY_INSTANCE_UID: Tag = Tag(0x0020, 0x000D);
+const SOP_CLASS_UID: Tag = Tag(0x0008, 0x0016);
+/// Name this tool reports as its audit source and syslog APP-NAME.
+const APP_NAME: &str = "dicom-tools";
+/// Syslog facility 10 (security/authorization), as PS3.15 A.6 recommends.
+const SYSLOG_FACILITY: u8 = 10;
+
+/// Where audit messages go: `udp://host:port` (or `syslog://host:port`) for an RFC 5424
+/// collector over UDP, anything else is a file that gets one message per line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AuditTarget {
+    File(PathBuf),
+    Syslog(String),
+}
+
+impl FromStr for AuditTarget {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let syslog = s
+            .strip_prefix("udp://")
+            .or_else(|| s.strip_prefix("syslog://"));
+        Ok(match syslog {
+            Some(addr) if addr.contains(':') => Self::Syslog(addr.to_string()),
+            Some(addr) => Self::Syslog(format!("{}:514", addr)),
+            None => Self::File(PathBuf::from(s)),
+        })
+    }
+}
+
+/// The DICOM audit events this tool emits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuditEventType {
+    /// Instances sent to another node (C-STORE push, C-MOVE/C-GET sub-operations).
+    Export,
+    /// Instances received from another node.
+    Import,
+    /// A de-identified copy was made of the instances.
+    DeIdentification,
+}
+
+impl AuditEventType {
+    /// Event ID code, its meaning and the EventActionCode.
+    fn codes(self) -> (&'static str, &'static str, &'static str) {
+        match self {
+            Self::Export => ("110106", "Export", "R"),
+            Self::Import => ("110107", "Import", "C"),
+            Self::DeIdentification => ("110103", "DICOM Instances Accessed", "C"),
+        }
+    }
+}
+
+/// EventOutcomeIndicator values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuditOutcome {
+    Success = 0,
+    /// The action did not complete for some instances.
+    MinorFailure = 4,
+    /// The action failed as a whole.
+    SeriousFailure = 8,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParticipantRole {
+    Source,
+    Destination,
+    Application,
+}
+
+impl ParticipantRole {
+    fn codes(self) -> (&'static str, &'static str) {
+        match self {
+            Self::Source => ("110153", "Source Role ID"),
+            Self::Destination => ("110152", "Destination Role ID"),
+            Self::Application => ("110150", "Application"),
+        }
+    }
+}
+
+/// A user or node taking part in the audited event.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuditParticipant {
+    pub user_id: String,
+    /// AE titles the participant used, as `AETITLES=...`.
+    pub alternative_user_id: Option<String>,
+    pub is_requestor: bool,
+    /// Host name or IP address.
+    pub network_access_point: Option<String>,
+    pub role: ParticipantRole,
+}
+
+impl AuditParticipant {
+    /// The local user running this tool, optionally acting as `ae_title`.
+    pub fn local(ae_title: Option<&str>, role: ParticipantRole, is_requestor: bool) -> Self {
+        let user = std::env::var("USER")
+            .or_else(|_| std::env::var("USERNAME"))
+            .unwrap_or_else(|_| APP_NAME.to_string());
+        Self {
+            user_id: user,
+            alternative_user_id: ae_title.map(|ae| format!("AETITLES={}", ae.trim())),
+            is_requestor,
+            network_access_point: Some(local_host()),
+            role,
+        }
+    }
+
+    /// A remote DICOM node known by AE title, at `addr` (`host` or `host:port`).
+    pub fn remote(ae_title: &str, addr: &str, role: ParticipantRole, is_requestor: bool) -> Self {
+        let host = addr
+            .rsplit_once(':')
+            .filter(|(host, port)| !host.is_empty() && port.parse::<u16>().is_ok())
+            .map_or(addr, |(host, _)| host);
+        Self {
+            user_id: ae_title.trim().to_string(),
+            alternative_user_id: Some(format!("AETITLES={}", ae_title.trim())),
+            is_requestor,
+            network_access_point: Some(host.trim_matches(['[', ']']).to_string())
+                .filter(|host| !host.is_empty()),
+            role,
+        }
+    }
+}
+
+/// Instances an event covers, grouped per study with the patient and SOP class counts.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AuditedStudies {
+    studies: BTreeMap<String, AuditedStudy>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct AuditedStudy {
+    patient_id: String,
+    sop_classes: BTreeMap<String, usize>,
+}
+
+impl AuditedStudies {
+    pub fn add(&mut self, patient_id: &str, study_instance_uid: &str, sop_class_uid: &str) {
+        let study = self
+            .studies
+            .entry(study_instance_uid.to_string())
+            .or_default();
+        if study.patient_id.is_empty() {
+            study.patient_id = patient_id.to_string();
+        }
+        *study
+            .sop_classes
+            .entry(sop_class_uid.to_string())
+            .or_default() += 1;
+    }
+
+    pub fn add_instance(&mut self, instance: &IndexedInstance) {
+        self.add(
+            &instance.patient_id,
+            &instance.study_instance_uid,
+            &instance.sop_class_uid,
+        );
+    }
+
+    pub fn add_object(&mut self, obj: &DefaultDicomObject) {
+        let text = |tag| {
+            obj.element_str(tag)
+                .map(|s| s.trim_end_matches(['\0', ' ']).to_string())
+                .unwrap_or_default()
+        };
+        self.add(
+            &text(PATIENT_ID),
+            &text(STUDY_INSTANCE_UID),
+            &text(SOP_CLASS_UID),
+        );
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.studies.is_empty()
+    }
+
+    pub fn instances(&self) -> usize {
+        self.studies
+            .values()
+            .flat_map(|study| study.sop_classes.values())
+            .sum()
+    }
+}
+
+/// One audit message.
+#[derive(Debug, Clone)]
+pub struct AuditEvent {
+    pub event_type: AuditEventType,
+    pub outcome: AuditOutcome,
+    pub time: DateTime<Local>,
+    pub participants: Vec<AuditParticipant>,
+    pub studies: AuditedStudies,
+}
+
+impl AuditEvent {
+    pub fn new(event_type: AuditEventType, outcome: AuditOutcome) -> Self {
+        Self {
+            event_type,
+            outcome,
+            time: Local::now(),
+            participants: Vec::new(),
+            studies: AuditedStudies::default(),
+        }
+    }
+
+    /// Instances this tool sent to `destination_ae` at `destination_addr`, on behalf of the
+    /// local user or, for C-MOVE, of the node that asked.
+    pub fn export(
+        local_ae: &str,
+        destination_ae: &str,
+        destination_addr: &str,
+        studies: AuditedStudies,
+        outcome: AuditOutcome,
+    ) -> Self {
+        let mut event = Self::new(AuditEventType::Export, outcome);
+        event.participants = vec![
+            AuditParticipant::local(Some(local_ae), ParticipantRole::Source, true),
+            AuditParticipant::remote(
+                destination_ae,
+                destination_addr,
+                ParticipantRole::Destination,
+                false,
+            ),
+        ];
+        event.studies = studies;
+        event
+    }
+
+    /// Instances `source_ae` at `source_addr` sent to this tool acting as `local_ae`.
+    pub fn import(
+        source_ae: &str,
+        source_addr: &str,
+        local_ae: &str,
+        studies: AuditedStudies,
+        outcome: AuditOutcome,
+    ) -> Self {
+        let mut event = Self::new(AuditEventType::Import, outcome);
+        event.participants = vec![
+            AuditParticipant::remote(source_ae, source_addr, ParticipantRole::Source, true),
+            AuditParticipant::local(Some(local_ae), ParticipantRole::Destination, false),
+        ];
+        event.studies = studies;
+        event
+    }
+
+    /// A de-identified copy of the instances made by the local user.
+    pub fn de_identification(studies: AuditedStudies, outcome: AuditOutcome) -> Self {
+        let mut event = Self::new(AuditEventType::DeIdentification, outcome);
+        event.participants = vec![AuditParticipant::local(
+            None,
+            ParticipantRole::Application,
+            true,
+        )];
+        event.studies = studies;
+        event
+    }
+
+    /// The message as a single line of XML following the PS3.15 A.5.1 schema.
+    pub fn to_xml(&self, audit_source_id: &str) -> String {
+        let (event_code, event_name, action) = self.event_type.codes();
+        let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?><AuditMessage>");
+        let _ = write!(
+            xml,
+            "<EventIdentification EventActionCode=\"{}\" EventDateTime=\"{}\" EventOutcomeIndicator=\"{}\">{}</EventIdentification>",
+            action,
+            self.time.to_rfc3339_opts(SecondsFormat::Millis, false),
+            self.outcome as u8,
+            coded("EventID", event_code, "DCM", event_name)
+        );
+        for participant in &self.participants {
+            let _ = write!(
+                xml,
+                "<ActiveParticipant UserID=\"{}\"",
+                escape(&participant.user_id)
+            );
+            if let Some(alternative) = &participant.alternative_user_id {
+                let _ = write!(xml, " AlternativeUserID=\"{}\"", escape(alternative));
+            }
+            let _ = write!(xml, " UserIsRequestor=\"{}\"", participant.is_requestor);
+            if let Some(host) = &participant.network_access_point {
+                // 1: machine name, 2: IP address.
+                let kind = if host.parse::<IpAddr>().is_ok() { 2 } else { 1 };
+                let _ = write!(
+                    xml,
+                    " NetworkAccessPointID=\"{}\" NetworkAccessPointTypeCode=\"{}\"",
+                    escape(host),
+                    kind
+                );
+            }
+            let (role_code, role_name) = participant.role.codes();
+            let _ = write!(
+                xml,
+                ">{}</ActiveParticipant>",
+                coded("RoleIDCode", role_code, "DCM", role_name)
+            );
+        }
+        // Source type 4: application server process.
+        let _ = write!(
+            xml,
+            "<AuditSourceIdentification AuditSourceID=\"{}\"><AuditSourceTypeCode csd-code=\"4\"/></AuditSourceIdentification>",
+            escape(audit_source_id)
+        );
+        let de_identified = self.event_type == AuditEventType::DeIdentification;
+        for (study_uid, study) in &self.studies.studies {
+            // Type 2 (system object), role 3 (report); life cycle 7 is de-identification.
+            let _ = write!(
+                xml,
+                "<ParticipantObjectIdentification ParticipantObjectID=\"{}\" ParticipantObjectTypeCode=\"2\" ParticipantObjectTypeCodeRole=\"3\"{}>{}<ParticipantObjectDescription>",
+                escape(study_uid),
+                if de_identified {
+                    " ParticipantObjectDataLifeCycle=\"7\""
+                } else {
+                    ""
+                },
+                coded(
+                    "ParticipantObjectIDTypeCode",
+                    "110180",
+                    "DCM",
+                    "Study Instance UID"
+                )
+            );
+            for (sop_class, count) in &study.sop_classes {
+                let _ = write!(
+                    xml,
+                    "<SOPClass UID=\"{}\" NumberOfInstances=\"{}\"/>",
+                    escape(sop_class),
+                    count
+                );
+            }
+            xml.push_str("</ParticipantObjectDescription></ParticipantObjectIdentification>");
+        }
+        let patients: Vec<&str> = self
+            .studies
+            .studies
+            .values()
+            .map(|study| study.patient_id.as_str())
+            .filter(|id| !id.is_empty())
+            .collect();
+        for (index, patient) in patients.iter().enumerate() {
+            if patients[..index].contains(patient) {
+                continue;
+            }
+            // Type 1 (person), role 1 (patient).
+            let _ = write!(
+                xml,
+                "<ParticipantObjectIdentification ParticipantObjectID=\"{}\" ParticipantObjectTypeCode=\"1\" ParticipantObjectTypeCodeRole=\"1\">{}</ParticipantObjectIdentification>",
+                escape(patient),
+                coded("ParticipantObjectIDTypeCode", "2", "RFC-3881", "Patient Number")
+            );
+        }
+        xml.push_str("</AuditMessage>");
+        xml
+    }
+}
+
+fn coded(element: &str, code: &str, system: &str, meaning: &str) -> String {
+    format!(
+        "<{} csd-code=\"{}\" codeSystemName=\"{}\" originalText=\"{}\"/>",
+        element, code, system, meaning
+    )
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c if c.is_control() => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Name of this machine, for participants and the audit source.
+fn local_host() -> String {
+    std::env::var("HOSTNAME")
+        .ok()
+        .or_else(|| fs::read_to_string("/etc/hostname").ok())
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| "localhost".to_string())
+}
+
+#[derive(Debug)]
+enum Sink {
+    File(Mutex<File>),
+    Syslog { socket: UdpSocket, addr: String },
+}
+
+/// An open audit destination, shared by every thread that records events.
+#[derive(Debug)]
+pub struct AuditLog {
+    sink: Sink,
+    host: String,
+}
+
+impl AuditLog {
+    pub fn open(target: &AuditTarget) -> Result<Arc<Self>> {
+        let sink = match target {
+            AuditTarget::File(path) => {
+                if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+                    fs::create_dir_all(parent)
+                        .with_context(|| format!("Failed to create {:?}", parent))?;
+                }
+                let file = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .with_context(|| format!("Failed to open audit log {:?}", path))?;
+                Sink::File(Mutex::new(file))
+            }
+            AuditTarget::Syslog(addr) => Sink::Syslog {
+                socket: UdpSocket::bind("0.0.0.0:0")
+                    .context("Failed to open a UDP socket for syslog")?,
+                addr: addr.clone(),
+            },
+        };
+        Ok(Arc::new(Self {
+            sink,
+            host: local_host(),
+        }))
+    }
+
+    /// Emit `event`. Failures are reported on stderr but do not stop the audited action.
+    pub fn record(&self, event: &AuditEvent) {
+        if let Err(err) = self.write(event) {
+            eprintln!("Failed to record audit event: {:#}", err);
+        }
+    }
+
+    fn write(&self, event: &AuditEvent) -> Result<()> {
+        let xml = event.to_xml(&format!("{}@{}", APP_NAME, self.host));
+        match &self.sink {
+            Sink::File(file) => {
+                let mut file = file.lock().expect("audit log lock");
+                writeln!(file, "{}", xml).context("Failed to append to audit log")
+            }
+            Sink::Syslog { socket, addr } => {
+                let message = syslog_frame(&self.host, event, &xml);
+                socket
+                    .send_to(message.as_bytes(), addr.as_str())
+                    .with_context(|| format!("Failed to send audit message to {}", addr))?;
+                Ok(())
+            }
+        }
+    }
+}
+
+/// RFC 5424 message carrying the audit XML, with the MSGID PS3.15 A.6 asks for.
+fn syslog_frame(host: &str, event: &AuditEvent, xml: &str) -> String {
+    // Notice for successes, warning for failures.
+    let severity = if event.outcome == AuditOutcome::Success {
+        5
+    } else {
+        4
+    };
+    format!(
+        "<{}>1 {} {} {} {} DICOM+RFC3881 - \u{FEFF}{}",
+        SYSLOG_FACILITY * 8 + severity,
+        event.time.to_rfc3339_opts(SecondsFormat::Millis, false),
+        host,
+        APP_NAME,
+        std::process::id(),
+        xml
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn export_messages_name_participants_studies_and_patients() {
+        let mut studies = AuditedStudies::default();
+        studies.add("PID<1>", "1.2.3", "1.2.840.10008.5.1.4.1.1.2");
+        studies.add("PID<1>", "1.2.3", "1.2.840.10008.5.1.4.1.1.2");
+        studies.add("PID<1>", "1.2.4", "1.2.840.10008.5.1.4.1.1.4");
+        assert_eq!(studies.instances(), 3);
+        let event = AuditEvent::export(
+            "DICOM-TOOLS",
+            "PACS",
+            "10.0.0.5:104",
+            studies,
+            AuditOutcome::MinorFailure,
+        );
+        let xml = event.to_xml("dicom-tools@test");
+        assert!(xml.contains("EventActionCode=\"R\""));
+        assert!(xml.contains("EventOutcomeIndicator=\"4\""));
+        assert!(xml.contains("csd-code=\"110106\""));
+        assert!(xml.contains(
+            "UserID=\"PACS\" AlternativeUserID=\"AETITLES=PACS\" UserIsRequestor=\"false\" NetworkAccessPointID=\"10.0.0.5\" NetworkAccessPointTypeCode=\"2\""
+        ));
+        assert!(
+            xml.contains("<SOPClass UID=\"1.2.840.10008.5.1.4.1.1.2\" NumberOfInstances=\"2\"/>")
+        );
+        // One patient object for both studies, escaped.
+        assert_eq!(
+            xml.matches("ParticipantObjectID=\"PID&lt;1&gt;\"").count(),
+            1
+        );
+        assert!(!xml.contains('\n'));
+
+        let frame = syslog_frame("host", &event, &xml);
+        assert!(frame.starts_with("<84>1 "));
+        assert!(frame.contains(" host dicom-tools "));
+    }
+
+    #[test]
+    fn targets_are_files_unless_given_a_udp_address() {
+        assert_eq!(
+            "udp://10.0.0.9:6514".parse::<AuditTarget>().unwrap(),
+            AuditTarget::Syslog("10.0.0.9:6514".into())
+        );
+        assert_eq!(
+            "syslog://audit.local".parse::<AuditTarget>().unwrap(),
+            AuditTarget::Syslog("audit.local:514".into())
+        );
+        assert_eq!(
+            "logs/audit.xml".parse::<AuditTarget>().unwrap(),
+            AuditTarget::File(PathBuf::from("logs/audit.xml"))
+        );
+    }
+}
@@ -0,0 +1,234 @@
+//
+// availability.rs
+// Dicom-Tools-rs
+//
+// Instance availability check against a remote PACS: queries every study of a local directory
+// with C-FIND and reports instances missing remotely or locally, for verifying migrations.
+//
+// Thales Matheus Mendonça Santos - November 2025
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use dicom::core::{DataElement, PrimitiveValue, Tag, VR};
+use dicom::object::InMemDicomObject;
+use serde::Serialize;
+
+use crate::atomic_file;
+use crate::dicom_access::ElementAccess;
+use crate::dimse::AssociationSettings;
+use crate::dimse_trace::DimseTracer;
+use crate::scp::InstanceIndex;
+use crate::scu;
+
+const QUERY_RETRIEVE_LEVEL: Tag = Tag(0x0008, 0x0052);
+const SOP_INSTANCE_UID: Tag = Tag(0x0008, 0x0018);
+const STUDY_INSTANCE_UID: Tag = Tag(0x0020, 0x000D);
+const SERIES_INSTANCE_UID: Tag = Tag(0x0020, 0x000E);
+
+/// The PACS to compare against.
+#[derive(Debug, Clone)]
+pub struct RemoteAe {
+    /// `host:port`
+    pub addr: String,
+    pub association: AssociationSettings,
+}
+
+/// An instance present on one side only.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct MissingInstance {
+    pub series_instance_uid: String,
+    pub sop_instance_uid: String,
+    /// Local file, for instances missing remotely.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<PathBuf>,
+}
+
+/// Comparison of one local study with the PACS.
+#[derive(Debug, Clone, Serialize)]
+pub struct StudyAvailability {
+    pub study_instance_uid: String,
+    pub local_instances: usize,
+    pub remote_instances: usize,
+    pub missing_remotely: Vec<MissingInstance>,
+    pub missing_locally: Vec<MissingInstance>,
+}
+
+impl StudyAvailability {
+    pub fn is_complete(&self) -> bool {
+        self.missing_remotely.is_empty() && self.missing_locally.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct AvailabilityReport {
+    pub studies: Vec<StudyAvailability>,
+}
+
+impl AvailabilityReport {
+    pub fn missing_remotely(&self) -> usize {
+        self.studies.iter().map(|s| s.missing_remotely.len()).sum()
+    }
+
+    pub fn missing_locally(&self) -> usize {
+        self.studies.iter().map(|s| s.missing_locally.len()).sum()
+    }
+}
+
+/// Compare every study under `dir` with what `remote` holds for it. Remote studies absent
+/// from `dir` are not listed, since the PACS may hold far more than was migrated.
+pub fn verify(
+    dir: &Path,
+    remote: &RemoteAe,
+    tracer: Option<Arc<DimseTracer>>,
+) -> Result<AvailabilityReport> {
+    let index = InstanceIndex::scan(dir)?;
+    // Study -> series -> SOP Instance UID -> local file.
+    let mut local: BTreeMap<&str, BTreeMap<&str, BTreeMap<&str, &Path>>> = BTreeMap::new();
+    for instance in index.instances() {
+        local
+            .entry(instance.study_instance_uid.as_str())
+            .or_default()
+            .entry(instance.series_instance_uid.as_str())
+            .or_default()
+            .insert(instance.sop_instance_uid.as_str(), instance.path.as_path());
+    }
+
+    let mut report = AvailabilityReport::default();
+    for (study, local_series) in local {
+        // Hierarchical queries name the series at the IMAGE level, so list the remote
+        // series first and query the union with the local ones.
+        let remote_series = query_uids(remote, &tracer, "SERIES", study, None)?;
+        let series: BTreeSet<&str> = local_series
+            .keys()
+            .copied()
+            .chain(remote_series.iter().map(String::as_str))
+            .collect();
+        let mut availability = StudyAvailability {
+            study_instance_uid: study.to_string(),
+            local_instances: local_series.values().map(BTreeMap::len).sum(),
+            remote_instances: 0,
+            missing_remotely: Vec::new(),
+            missing_locally: Vec::new(),
+        };
+        for series_uid in series {
+            let remote_instances = if remote_series.contains(series_uid) {
+                query_uids(remote, &tracer, "IMAGE", study, Some(series_uid))?
+            } else {
+                BTreeSet::new()
+            };
+            availability.remote_instances += remote_instances.len();
+            let local_instances = local_series.get(series_uid);
+            for (sop, path) in local_instances.into_iter().flatten() {
+                if !remote_instances.contains(*sop) {
+                    availability.missing_remotely.push(MissingInstance {
+                        series_instance_uid: series_uid.to_string(),
+                        sop_instance_uid: sop.to_string(),
+                        path: Some(path.to_path_buf()),
+                    });
+                }
+            }
+            for sop in &remote_instances {
+                if !local_instances.is_some_and(|l| l.contains_key(sop.as_str())) {
+                    availability.missing_locally.push(MissingInstance {
+                        series_instance_uid: series_uid.to_string(),
+                        sop_instance_uid: sop.clone(),
+                        path: None,
+                    });
+                }
+            }
+        }
+        report.studies.push(availability);
+    }
+    Ok(report)
+}
+
+/// Unique keys of the level below: series of a study, or instances of a series.
+fn query_uids(
+    remote: &RemoteAe,
+    tracer: &Option<Arc<DimseTracer>>,
+    level: &str,
+    study: &str,
+    series: Option<&str>,
+) -> Result<BTreeSet<String>> {
+    let text = |value: &str| PrimitiveValue::from(value);
+    let mut identifier = InMemDicomObject::new_empty();
+    identifier.put(DataElement::new(QUERY_RETRIEVE_LEVEL, VR::CS, text(level)));
+    identifier.put(DataElement::new(STUDY_INSTANCE_UID, VR::UI, text(study)));
+    let returned = match series {
+        Some(series) => {
+            identifier.put(DataElement::new(SERIES_INSTANCE_UID, VR::UI, text(series)));
+            SOP_INSTANCE_UID
+        }
+        None => SERIES_INSTANCE_UID,
+    };
+    identifier.put(DataElement::new(returned, VR::UI, PrimitiveValue::Empty));
+    let matches = scu::find(
+        &remote.addr,
+        &remote.association,
+        scu::STUDY_ROOT_FIND,
+        &identifier,
+        tracer.clone(),
+    )
+    .with_context(|| format!("{}-level query of study {} failed", level, study))?;
+    Ok(matches
+        .iter()
+        .filter_map(|found| found.element_str(returned))
+        .map(|value| value.trim_end_matches(['\0', ' ']).to_string())
+        .filter(|value| !value.is_empty())
+        .collect())
+}
+
+/// CLI helper: print the comparison, optionally writing it as JSON too.
+pub fn print_report(
+    dir: &Path,
+    remote: &RemoteAe,
+    json: Option<&Path>,
+    tracer: Option<Arc<DimseTracer>>,
+) -> Result<AvailabilityReport> {
+    let report = verify(dir, remote, tracer)?;
+    for study in &report.studies {
+        println!(
+            "Study {}: {} local, {} remote{}",
+            study.study_instance_uid,
+            study.local_instances,
+            study.remote_instances,
+            if study.is_complete() {
+                " - complete"
+            } else {
+                ""
+            }
+        );
+        for missing in &study.missing_remotely {
+            let path = missing.path.as_deref().unwrap_or(Path::new(""));
+            println!(
+                "  missing remotely: {} ({:?})",
+                missing.sop_instance_uid, path
+            );
+        }
+        for missing in &study.missing_locally {
+            println!(
+                "  missing locally:  {} (series {})",
+                missing.sop_instance_uid, missing.series_instance_uid
+            );
+        }
+    }
+    let complete = report.studies.iter().filter(|s| s.is_complete()).count();
+    println!(
+        "{} of {} studies complete on {}; {} instance(s) missing remotely, {} missing locally",
+        complete,
+        report.studies.len(),
+        remote.association.called_ae_title,
+        report.missing_remotely(),
+        report.missing_locally()
+    );
+    if let Some(path) = json {
+        let text = serde_json::to_string_pretty(&report).context("Failed to serialize report")?;
+        atomic_file::write(path, text)
+            .with_context(|| format!("Failed to write JSON to {:?}", path))?;
+        println!("JSON saved to {:?}", path);
+    }
+    Ok(report)
+}
@@ -8,12 +8,32 @@
 
 use anyhow::Result;
 use rayon::prelude::*;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
 use walkdir::WalkDir;
 
+use crate::archive::{self, ArchiveKind};
+use crate::progress::{NoProgress, ProgressEvent, ProgressSink};
+use crate::storage::StorageBackend;
 use crate::{anonymize, cli::BatchOperation, validate};
 
 pub fn process_directory(dir: &Path, operation: BatchOperation) -> Result<()> {
+    process_directory_with_progress(dir, operation, None, &NoProgress)
+}
+
+/// Same as [`process_directory`], reporting one event per finished file. When `output` is
+/// given, anonymized files are also stored there under their path relative to `dir`.
+///
+/// `dir` may also be a `.zip`, `.tar` or `.tar.gz` archive; see [`process_archive`].
+pub fn process_directory_with_progress(
+    dir: &Path,
+    operation: BatchOperation,
+    output: Option<&dyn StorageBackend>,
+    progress: &dyn ProgressSink,
+) -> Result<()> {
+    if dir.is_file() && ArchiveKind::of(dir).is_some() {
+        return process_archive(dir, operation, output, progress);
+    }
     // Scan recursively for `.dcm` files and fan out work across threads with Rayon.
     println!(
         "Processando diretório: {:?} | Operação: {:?}",
@@ -23,16 +43,27 @@ pub fn process_directory(dir: &Path, operation: BatchOperation) -> Result<()> {
     let files: Vec<_> = WalkDir::new(dir)
         .into_iter()
         .filter_map(|e| e.ok())
-        .filter(|e| e.path().extension().map_or(false, |ext| ext == "dcm"))
+        .filter(|e| e.path().extension().is_some_and(|ext| ext == "dcm"))
         .collect();
 
     println!("Encontrados {} arquivos.", files.len());
 
+    let total = files.len() as u64;
+    let done = AtomicU64::new(0);
+    progress.report(ProgressEvent::new("scan", 0, total));
+
     files.par_iter().for_each(|entry| {
         let path = entry.path();
         // Each file is processed independently; failures are logged but do not stop the batch.
         let res = match operation {
-            BatchOperation::Anonymize => anonymize::process_file(path, None),
+            BatchOperation::Anonymize => {
+                let out_path = anonymize::default_output_path(path);
+                anonymize::process_file(path, Some(out_path.clone())).and_then(|()| {
+                    let Some(store) = output else { return Ok(()) };
+                    let key = out_path.strip_prefix(dir).unwrap_or(&out_path);
+                    store.upload(&key.to_string_lossy(), &out_path)
+                })
+            }
             BatchOperation::Validate => validate::check_file(path),
         };
 
@@ -41,7 +72,76 @@ pub fn process_directory(dir: &Path, operation: BatchOperation) -> Result<()> {
         } else {
             println!("Sucesso: {:?}", path.file_name().unwrap());
         }
+
+        let current = done.fetch_add(1, Ordering::Relaxed) + 1;
+        progress.report(
+            ProgressEvent::new("process", current, total).with_item(path.display().to_string()),
+        );
     });
 
     Ok(())
 }
+
+/// Run `operation` over the DICOM entries of an archive as they are read, without
+/// extracting it. Anonymized entries are written under `<archive name>_anon/` next to the
+/// archive (and to `output`) at their path inside it. The total is unknown until the end,
+/// so progress counts the entries handled so far.
+pub fn process_archive(
+    archive: &Path,
+    operation: BatchOperation,
+    output: Option<&dyn StorageBackend>,
+    progress: &dyn ProgressSink,
+) -> Result<()> {
+    println!(
+        "Processando arquivo compactado: {:?} | Operação: {:?}",
+        archive, operation
+    );
+    let out_dir = anonymized_archive_dir(archive);
+    let mut done = 0;
+    let summary = archive::for_each_dicom_entry(archive, |name, scratch| {
+        let res = match operation {
+            BatchOperation::Anonymize => {
+                let out_path = out_dir.join(name);
+                std::fs::create_dir_all(out_path.parent().unwrap_or(&out_dir))?;
+                anonymize::process_file(scratch, Some(out_path.clone())).and_then(|()| {
+                    let Some(store) = output else { return Ok(()) };
+                    store.upload(&name.to_string_lossy(), &out_path)
+                })
+            }
+            BatchOperation::Validate => validate::check_file(scratch),
+        };
+
+        // As in the directory walk, one bad entry does not stop the rest.
+        if let Err(e) = res {
+            eprintln!("Erro em {:?}: {}", name, e);
+        } else {
+            println!("Sucesso: {:?}", name);
+        }
+
+        done += 1;
+        progress.report(
+            ProgressEvent::new("process", done, done).with_item(name.display().to_string()),
+        );
+        Ok(())
+    })?;
+
+    println!(
+        "Encontrados {} arquivos DICOM ({} outros ignorados).",
+        summary.dicom, summary.skipped
+    );
+    Ok(())
+}
+
+/// `study.zip` -> `study_anon/` in the same directory.
+pub fn anonymized_archive_dir(archive: &Path) -> PathBuf {
+    let name = archive
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let lower = name.to_ascii_lowercase();
+    let stem_len = [".tar.gz", ".tgz", ".tar", ".zip"]
+        .iter()
+        .find(|ext| lower.ends_with(*ext))
+        .map_or(name.len(), |ext| name.len() - ext.len());
+    archive.with_file_name(format!("{}_anon", &name[..stem_len]))
+}
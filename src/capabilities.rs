@@ -0,0 +1,111 @@
+//
+// capabilities.rs
+// Dicom-Tools-rs
+//
+// Describes what a DICOM object contains and which operations this tool can perform on it,
+// shared by `info` and the web upload response.
+//
+// Thales Matheus Mendonça Santos - November 2025
+
+use dicom::core::dictionary::UidDictionary;
+use dicom::core::Tag;
+use dicom::encoding::TransferSyntaxIndex;
+use dicom::object::DefaultDicomObject;
+use dicom::transfer_syntax::TransferSyntaxRegistry;
+use dicom_dictionary_std::StandardSopClassDictionary;
+
+use crate::dicom_access::ElementAccess;
+use crate::lut::PaletteLut;
+use crate::models::Capabilities;
+use crate::transcode;
+
+const SOP_CLASS_UID: Tag = Tag(0x0008, 0x0016);
+const MODALITY: Tag = Tag(0x0008, 0x0060);
+const SAMPLES_PER_PIXEL: Tag = Tag(0x0028, 0x0002);
+const PHOTOMETRIC_INTERPRETATION: Tag = Tag(0x0028, 0x0004);
+const NUMBER_OF_FRAMES: Tag = Tag(0x0028, 0x0008);
+const ROWS: Tag = Tag(0x0028, 0x0010);
+const COLUMNS: Tag = Tag(0x0028, 0x0011);
+const BITS_ALLOCATED: Tag = Tag(0x0028, 0x0100);
+const PIXEL_DATA: Tag = Tag(0x7FE0, 0x0010);
+
+/// Inspect `obj` without decoding its pixel data.
+pub fn detect(obj: &DefaultDicomObject) -> Capabilities {
+    let text = |tag: Tag| {
+        obj.element_str(tag)
+            .map(|s| s.trim_end_matches(['\0', ' ']).to_string())
+            .filter(|s| !s.is_empty())
+    };
+    let mut warnings = Vec::new();
+
+    let sop_class_uid = text(SOP_CLASS_UID);
+    let sop_class_name = sop_class_uid
+        .as_deref()
+        .and_then(|uid| StandardSopClassDictionary.by_uid(uid))
+        .map(|entry| entry.name.to_string());
+    if sop_class_uid.is_some() && sop_class_name.is_none() {
+        warnings.push("SOP Class UID is not a standard storage class".to_string());
+    }
+
+    let ts_uid = obj
+        .meta()
+        .transfer_syntax()
+        .trim_end_matches('\0')
+        .to_string();
+    let ts = TransferSyntaxRegistry.get(&ts_uid);
+    let transfer_syntax_name = ts.map(|ts| ts.name().to_string());
+    let dataset_readable = ts.is_some_and(|ts| ts.can_decode_dataset());
+    if !dataset_readable {
+        warnings.push(format!("Transfer syntax {} is not supported", ts_uid));
+    }
+
+    let has_pixel_data = obj.has_element(PIXEL_DATA);
+    let pixels_decodable = has_pixel_data && ts.is_some_and(|ts| ts.can_decode_all());
+    let frames = obj.element_u32(NUMBER_OF_FRAMES).unwrap_or(1).max(1);
+    let estimated_decoded_bytes = has_pixel_data
+        .then(|| {
+            let rows = obj.element_u32(ROWS)?;
+            let columns = obj.element_u32(COLUMNS)?;
+            let samples = obj.element_u32(SAMPLES_PER_PIXEL).unwrap_or(1);
+            let bytes = obj.element_u32(BITS_ALLOCATED)?.div_ceil(8);
+            Some(rows as u64 * columns as u64 * samples as u64 * bytes as u64 * frames as u64)
+        })
+        .flatten();
+
+    let mut can_render = pixels_decodable;
+    if !has_pixel_data {
+        warnings.push("No Pixel Data: nothing to preview".to_string());
+    } else if dataset_readable && !pixels_decodable {
+        warnings.push(format!(
+            "Unsupported transfer syntax for preview: {}",
+            transfer_syntax_name.as_deref().unwrap_or(&ts_uid)
+        ));
+    }
+    let photometric = text(PHOTOMETRIC_INTERPRETATION).unwrap_or_default();
+    if can_render && photometric == "PALETTE COLOR" && PaletteLut::from_object(obj).is_none() {
+        can_render = false;
+        warnings.push("PALETTE COLOR image without readable palette LUTs".to_string());
+    }
+
+    let lossy = has_pixel_data && transcode::lossy_history(obj).lossy;
+    if lossy {
+        warnings.push("Lossy compressed: further lossy transcoding needs --force".to_string());
+    }
+
+    Capabilities {
+        sop_class_uid,
+        sop_class_name,
+        modality: text(MODALITY),
+        transfer_syntax: ts_uid,
+        transfer_syntax_name,
+        has_pixel_data,
+        frames,
+        estimated_decoded_bytes,
+        can_render,
+        can_compute_stats: pixels_decodable,
+        // Re-encoding needs the dataset, and the pixels too when there are any.
+        can_transcode: dataset_readable && (!has_pixel_data || pixels_decodable),
+        lossy,
+        warnings,
+    }
+}
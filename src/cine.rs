@@ -0,0 +1,168 @@
+//
+// cine.rs
+// Dicom-Tools-rs
+//
+// Cine timing of multi-frame objects: derives the playback duration from Frame Time or
+// Frame Time Vector and checks that the frame timing attributes agree with each other.
+//
+// Thales Matheus Mendonça Santos - November 2025
+
+use dicom::core::Tag;
+
+use crate::dicom_access::ElementAccess;
+
+const RECOMMENDED_DISPLAY_FRAME_RATE: Tag = Tag(0x0008, 0x2144);
+const CINE_RATE: Tag = Tag(0x0018, 0x0040);
+const FRAME_TIME: Tag = Tag(0x0018, 0x1063);
+const FRAME_TIME_VECTOR: Tag = Tag(0x0018, 0x1065);
+const NUMBER_OF_FRAMES: Tag = Tag(0x0028, 0x0008);
+const FRAME_INCREMENT_POINTER: Tag = Tag(0x0028, 0x0009);
+
+/// Display rates above this many frames per second are treated as data-entry errors;
+/// even high-frame-rate ultrasound stays well below it.
+pub const MAX_PLAUSIBLE_FPS: f64 = 1000.0;
+
+/// Playback duration in milliseconds of a multi-frame object, from Frame Time Vector when
+/// present (its first entry is the offset of frame one, normally 0) or Frame Time × frames.
+pub fn duration_ms<T: ElementAccess>(obj: &T) -> Option<f64> {
+    let frames = obj.element_u32(NUMBER_OF_FRAMES).filter(|&f| f > 1)?;
+    let vector = numbers(obj, FRAME_TIME_VECTOR);
+    if !vector.is_empty() {
+        return Some(vector.iter().sum());
+    }
+    obj.element_f64(FRAME_TIME)
+        .filter(|&t| t > 0.0)
+        .map(|t| t * frames as f64)
+}
+
+/// Problems with the cine timing attributes of a multi-frame object: Frame Increment
+/// Pointer targets that are absent, a Frame Time that is not positive, a Frame Time Vector
+/// whose length is not Number of Frames, and display rates outside (0, 1000] fps.
+pub fn check<T: ElementAccess>(obj: &T) -> Vec<String> {
+    let mut problems = Vec::new();
+    let frames = obj.element_u32(NUMBER_OF_FRAMES);
+
+    for target in obj.element_tags(FRAME_INCREMENT_POINTER) {
+        if !obj.has_element(target) {
+            problems.push(format!(
+                "Frame Increment Pointer {} references {}, which is absent",
+                FRAME_INCREMENT_POINTER, target
+            ));
+        } else if target == FRAME_TIME && !obj.element_f64(FRAME_TIME).is_some_and(|t| t > 0.0) {
+            problems.push(format!(
+                "Frame Time {} must be a positive number of milliseconds",
+                FRAME_TIME
+            ));
+        } else if target == FRAME_TIME_VECTOR {
+            let length = numbers(obj, FRAME_TIME_VECTOR).len();
+            if frames.is_some_and(|frames| frames as usize != length) {
+                problems.push(format!(
+                    "Frame Time Vector {} has {} value(s) for {} frame(s)",
+                    FRAME_TIME_VECTOR,
+                    length,
+                    frames.unwrap_or_default()
+                ));
+            }
+        }
+    }
+
+    if numbers(obj, FRAME_TIME_VECTOR).iter().any(|&t| t < 0.0) {
+        problems.push(format!(
+            "Frame Time Vector {} holds negative increments",
+            FRAME_TIME_VECTOR
+        ));
+    }
+
+    for (tag, name) in [
+        (
+            RECOMMENDED_DISPLAY_FRAME_RATE,
+            "Recommended Display Frame Rate",
+        ),
+        (CINE_RATE, "Cine Rate"),
+    ] {
+        if let Some(rate) = obj.element_f64(tag) {
+            if rate <= 0.0 || rate > MAX_PLAUSIBLE_FPS {
+                problems.push(format!("{} {} of {} fps is implausible", name, tag, rate));
+            }
+        }
+    }
+
+    problems
+}
+
+/// Every value of a multi-valued decimal string; unparsable entries are skipped.
+fn numbers<T: ElementAccess>(obj: &T, tag: Tag) -> Vec<f64> {
+    obj.element_str(tag)
+        .map(|text| {
+            text.trim_end_matches(['\0', ' '])
+                .split('\\')
+                .filter_map(|v| v.trim().parse().ok())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dicom::core::{DataElement, PrimitiveValue, VR};
+    use dicom::object::InMemDicomObject;
+
+    fn multi_frame(frames: &str, pointer: Tag) -> InMemDicomObject {
+        let mut obj = InMemDicomObject::new_empty();
+        obj.put(DataElement::new(
+            NUMBER_OF_FRAMES,
+            VR::IS,
+            PrimitiveValue::from(frames),
+        ));
+        obj.put(DataElement::new(
+            FRAME_INCREMENT_POINTER,
+            VR::AT,
+            PrimitiveValue::Tags(vec![pointer].into()),
+        ));
+        obj
+    }
+
+    #[test]
+    fn frame_time_gives_duration_and_must_be_present() {
+        let mut obj = multi_frame("10", FRAME_TIME);
+        assert_eq!(check(&obj).len(), 1);
+        assert_eq!(duration_ms(&obj), None);
+
+        obj.put(DataElement::new(
+            FRAME_TIME,
+            VR::DS,
+            PrimitiveValue::from("33.3"),
+        ));
+        assert!(check(&obj).is_empty());
+        assert!((duration_ms(&obj).unwrap() - 333.0).abs() < 1e-9);
+
+        obj.put(DataElement::new(
+            RECOMMENDED_DISPLAY_FRAME_RATE,
+            VR::IS,
+            PrimitiveValue::from("0"),
+        ));
+        assert!(check(&obj)[0].contains("Recommended Display Frame Rate"));
+    }
+
+    #[test]
+    fn frame_time_vector_length_follows_number_of_frames() {
+        let mut obj = multi_frame("3", FRAME_TIME_VECTOR);
+        obj.put(DataElement::new(
+            FRAME_TIME_VECTOR,
+            VR::DS,
+            PrimitiveValue::from("0\\40"),
+        ));
+        let problems = check(&obj);
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].contains("2 value(s) for 3 frame(s)"));
+
+        obj.put(DataElement::new(
+            FRAME_TIME_VECTOR,
+            VR::DS,
+            PrimitiveValue::from("0\\40\\50"),
+        ));
+        assert!(check(&obj).is_empty());
+        assert_eq!(duration_ms(&obj), Some(90.0));
+    }
+}
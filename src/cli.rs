@@ -7,12 +7,41 @@
 // Thales Matheus Mendonça Santos - November 2025
 
 use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
 
 use anyhow::{anyhow, bail};
-use clap::{Parser, Subcommand, ValueEnum};
-use dicom_pixeldata::WindowLevel;
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use dicom_pixeldata::{VoiLutFunction, WindowLevel};
+use dicom_ul::pdu::reader::DEFAULT_MAX_PDU;
+use serde::Deserialize;
 
-use crate::{anonymize, batch, dump, image, json, metadata, scu, stats, transcode, validate, web};
+use crate::audit::{AuditEvent, AuditLog, AuditOutcome, AuditTarget, AuditedStudies};
+use crate::availability::RemoteAe;
+use crate::dimse::AssociationSettings;
+use crate::dimse_trace::DimseTracer;
+use crate::encryption::EncryptionKey;
+use crate::preview_cache::PreviewCache;
+use crate::progress::ProgressBarSink;
+use crate::router::Router;
+use crate::scp::{AeMap, ScpConfig};
+use crate::screening::{CommandScreen, DicomSanityScreen, ScreeningPipeline};
+use crate::scu::{PushDirOptions, RetrieveOptions, RetrieveTarget};
+use crate::send_queue::{RetryPolicy, SendQueue};
+use crate::sharing::ShareSigner;
+use crate::storage::{EvictionPolicy, FileStore, StorageLocation, StoreLimits};
+use crate::transfer_limits::TransferWindow;
+use crate::ts_preference::TransferSyntaxPreference;
+use crate::upload_pipeline::{UploadPipeline, UploadStep};
+use crate::web::WorkerLimits;
+use crate::worklist::WorklistSource;
+use crate::{
+    annotations, anonymize, availability, batch, concatenation, derivation, dump, echo_scan, fhir,
+    frame_extract, harmonize, icon, image, joint_histogram, json, measure, measurement_report,
+    metadata, mpps, padding, parametric_map, registration, rescale, scp, scu, scu_async,
+    send_queue, series_split, size_report, stats, stow, synth, tag_stats, time_curves, transcode,
+    validate, volume, wado, web, worklist,
+};
 
 /// Command-line interface glue code: defines the available verbs and dispatches to modules.
 #[derive(Parser)]
@@ -23,6 +52,84 @@ pub struct Cli {
     pub command: Commands,
 }
 
+/// Wire-level tracing shared by the network verbs.
+#[derive(Args)]
+pub struct TraceArgs {
+    /// Log PDUs, presentation contexts and DIMSE element summaries to this file
+    #[arg(long)]
+    dimse_trace: Option<PathBuf>,
+    /// Mask patient-identifying values in the trace
+    #[arg(long, requires = "dimse_trace")]
+    redact_phi: bool,
+}
+
+impl TraceArgs {
+    fn open(&self) -> anyhow::Result<Option<Arc<DimseTracer>>> {
+        self.dimse_trace
+            .as_deref()
+            .map(|path| DimseTracer::create(path, self.redact_phi))
+            .transpose()
+    }
+}
+
+/// Audit trail shared by the verbs that move or de-identify PHI.
+#[derive(Args)]
+pub struct AuditArgs {
+    /// Record ATNA audit messages to this file, or to a syslog collector as udp://host:port
+    #[arg(long, value_name = "FILE|udp://HOST:PORT")]
+    audit: Option<AuditTarget>,
+}
+
+impl AuditArgs {
+    fn open(&self) -> anyhow::Result<Option<Arc<AuditLog>>> {
+        self.audit.as_ref().map(AuditLog::open).transpose()
+    }
+}
+
+/// Pacing shared by the bulk upload verbs.
+#[derive(Args)]
+pub struct TransferLimitArgs {
+    /// Average bandwidth cap in MB/s
+    #[arg(long, value_name = "MB/s")]
+    rate_limit: Option<f64>,
+    /// Only send during this daily local-time window, e.g. 22:00-06:00
+    #[arg(long, value_name = "HH:MM-HH:MM")]
+    between: Option<TransferWindow>,
+}
+
+/// Association parameters shared by the SCU verbs.
+#[derive(Args)]
+pub struct AssociationArgs {
+    /// Our AE title; for C-MOVE also the destination the PACS must know
+    #[arg(long, alias = "ae-title", default_value = "DICOM-TOOLS")]
+    calling_aet: String,
+    /// AE title of the remote node
+    #[arg(long, alias = "called-ae-title", default_value = "ANY-SCP")]
+    called_aet: String,
+    /// Largest PDU we accept, in bytes
+    #[arg(long, default_value_t = DEFAULT_MAX_PDU)]
+    max_pdu: u32,
+    /// Seconds allowed to connect and negotiate the association
+    #[arg(long, value_name = "SECS")]
+    connect_timeout: Option<u64>,
+    /// Seconds to wait for each read or write once associated
+    #[arg(long, value_name = "SECS")]
+    read_timeout: Option<u64>,
+}
+
+impl AssociationArgs {
+    fn settings(self) -> AssociationSettings {
+        AssociationSettings {
+            calling_ae_title: self.calling_aet,
+            called_ae_title: self.called_aet,
+            max_pdu_length: self.max_pdu,
+            connect_timeout: self.connect_timeout.map(Duration::from_secs),
+            read_timeout: self.read_timeout.map(Duration::from_secs),
+            transfer_syntaxes: TransferSyntaxPreference::default(),
+        }
+    }
+}
+
 #[derive(Subcommand)]
 pub enum Commands {
     /// Extract metadata (analogue to extract_metadata.py / dicom_info.py)
@@ -36,6 +143,26 @@ pub enum Commands {
         input: PathBuf,
         #[arg(short, long)]
         output: Option<PathBuf>,
+        /// Provenance recorded on the written copy
+        #[arg(long, value_enum, default_value_t = Derivation::Preserve)]
+        derivation: Derivation,
+        /// Keep institution, station, device serial and operator names
+        #[arg(long)]
+        retain_device_identity: bool,
+        /// Keep only these device identity attributes (repeatable)
+        #[arg(long, value_enum, conflicts_with = "retain_device_identity")]
+        retain_device: Vec<DeviceField>,
+        /// Redact names and dates inside study/series descriptions, protocol name and comments
+        #[arg(long)]
+        clean_descriptors: bool,
+        /// Drop whole groups from the output (repeatable)
+        #[arg(long, value_enum)]
+        remove: Vec<RemovableGroup>,
+        /// Record previous values in Original Attributes Sequence with this reason
+        #[arg(long, value_enum)]
+        record_original: Option<ModificationReason>,
+        #[command(flatten)]
+        audit: AuditArgs,
     },
     /// Convert to an image (similar to convert_to_image.py)
     ToImage {
@@ -50,6 +177,12 @@ pub enum Commands {
         window_center: Option<f64>,
         #[arg(long)]
         window_width: Option<f64>,
+        /// Use the Nth (0-based) of the file's windows, e.g. lung vs mediastinum
+        #[arg(long, conflicts_with = "window_center")]
+        window_index: Option<usize>,
+        /// Override the VOI LUT Function of the applied window
+        #[arg(long, value_enum)]
+        voi_function: Option<VoiFunction>,
         #[arg(long)]
         normalize: bool,
         #[arg(long)]
@@ -60,32 +193,376 @@ pub enum Commands {
         force_8bit: bool,
         #[arg(long)]
         force_16bit: bool,
+        /// Grayscale inversion: auto follows MONOCHROME1 and Presentation LUT Shape
+        #[arg(long, value_enum, default_value_t = DisplayInversion::Auto)]
+        invert: DisplayInversion,
+        /// Frames exported in parallel (default or 0: one per core)
+        #[arg(long, short = 'j')]
+        jobs: Option<usize>,
+    },
+    /// Validate file integrity of a file or every `.dcm` file under a directory
+    Validate {
+        path: PathBuf,
+        /// `ndjson` streams one JSON finding per file, ready for a log aggregator
+        #[arg(long, value_enum, default_value_t = ValidateFormat::Text)]
+        format: ValidateFormat,
     },
-    /// Validate file integrity
-    Validate { file: PathBuf },
     /// Start the web server
     Web {
         #[arg(short, long, default_value = "127.0.0.1")]
         host: String,
         #[arg(short, long, default_value_t = 3000)]
         port: u16,
+        /// Upload store: a directory or `s3://bucket/prefix` (requires the `s3` feature)
+        #[arg(long, default_value = "target/uploads")]
+        store: StorageLocation,
+        /// Encrypt stored files at rest with AES-256-GCM using this key file
+        /// (32 raw bytes or 64 hex characters)
+        #[arg(long)]
+        encryption_key: Option<PathBuf>,
+        /// Reject single uploads larger than this many MiB (HTTP 507)
+        #[arg(long)]
+        max_upload_mb: Option<u64>,
+        /// Cap the store at this many MiB, uploads and derived files included
+        #[arg(long)]
+        max_store_mb: Option<u64>,
+        /// What to do when the store cap is reached
+        #[arg(long, value_enum, default_value_t = Eviction::Lru)]
+        eviction: Eviction,
+        /// Only accept uploads of these SOP Class UIDs (repeatable; default accepts all)
+        #[arg(long = "allow-sop-class")]
+        allowed_sop_classes: Vec<String>,
+        /// Reject uploads whose Rows or Columns exceed this value
+        #[arg(long, default_value_t = 16384)]
+        max_dimension: u32,
+        /// Reject uploads declaring more frames than this
+        #[arg(long, default_value_t = 10_000)]
+        max_frames: u32,
+        /// External scanner run on each upload (file path appended); non-zero exit rejects it
+        #[arg(long)]
+        screen_command: Option<String>,
+        /// Decode/IO jobs run at once; other requests queue (default: available cores)
+        #[arg(long)]
+        max_concurrent_jobs: Option<usize>,
+        /// Answer 503 when a request has not finished its job within this many seconds
+        #[arg(long, default_value_t = 120)]
+        request_timeout_secs: u64,
+        /// Memory kept for rendered previews, in MiB (0 disables the memory tier)
+        #[arg(long, default_value_t = 64)]
+        preview_cache_mb: usize,
+        /// Also keep rendered previews in this directory, across restarts
+        #[arg(long)]
+        preview_cache_dir: Option<PathBuf>,
+        /// Sign share links with this key file (32 raw bytes or 64 hex characters) so they
+        /// survive restarts; by default a random key is used and links die with the process
+        #[arg(long)]
+        share_key: Option<PathBuf>,
+        /// Steps run on every upload, in order, e.g. anonymize,validate,index,thumbnail; later
+        /// steps work on the anonymized copy and the upload response reports each one
+        #[arg(long, value_enum, value_delimiter = ',')]
+        on_upload: Vec<UploadAction>,
+        /// JSON Lines file the `index` upload step appends to
+        #[arg(long, default_value = "target/upload-index.jsonl")]
+        upload_index: PathBuf,
+        /// Let POST /api/push send to this node, as AE=host:port (repeatable); the endpoint
+        /// is off unless a destination is configured
+        #[arg(long = "push-ae")]
+        push_destinations: Vec<String>,
+        /// File of AE=host:port lines POST /api/push may send to
+        #[arg(long)]
+        push_ae_map: Option<PathBuf>,
     },
     /// Batch processing over a directory
     Batch {
+        /// Directory to scan for `.dcm` files, or a `.zip`/`.tar`/`.tar.gz` study export
         #[arg(short, long)]
         directory: PathBuf,
         #[arg(short, long, value_enum)]
         operation: BatchOperation,
+        /// Also copy anonymized outputs to a directory or `s3://bucket/prefix`
+        #[arg(long)]
+        output_store: Option<StorageLocation>,
+    },
+    /// Run files or directories through a TOML rules file (validate, anonymize, transcode, index, push, store)
+    Route {
+        #[arg(long)]
+        rules: PathBuf,
+        #[arg(required = true)]
+        inputs: Vec<PathBuf>,
+    },
+    /// Serve a directory over DICOM: C-ECHO, Study Root C-FIND, C-MOVE/C-GET retrieval, and optionally C-STORE reception
+    Scp {
+        #[arg(long, default_value = "0.0.0.0")]
+        host: String,
+        #[arg(short, long, default_value_t = 11112)]
+        port: u16,
+        #[arg(long, default_value = "DICOM-TOOLS")]
+        ae_title: String,
+        /// Directory of DICOM files to serve
+        #[arg(long, default_value = "target/uploads")]
+        dir: PathBuf,
+        /// C-MOVE destination as AE=host:port (repeatable)
+        #[arg(long = "ae")]
+        destinations: Vec<String>,
+        /// File of AE=host:port lines
+        #[arg(long)]
+        ae_map: Option<PathBuf>,
+        /// Accept C-STOREs and save them to the upload store at --dir
+        #[arg(long)]
+        store_received: bool,
+        /// Accept C-STOREs and route them with this TOML rules file; a failing rule refuses the
+        /// instance unless its on_failure policy is "ignore"
+        #[arg(long)]
+        rules: Option<PathBuf>,
+        /// Answer modality worklist C-FINDs from this .json or .csv file
+        #[arg(long)]
+        worklist: Option<PathBuf>,
+        /// Answer modality worklist C-FINDs with one item per study under --dir
+        #[arg(long, conflicts_with = "worklist")]
+        worklist_from_index: bool,
+        /// Accept Modality Performed Procedure Step N-CREATE/N-SET and keep the steps in this directory
+        #[arg(long, value_name = "DIR")]
+        mpps: Option<PathBuf>,
+        #[command(flatten)]
+        trace: TraceArgs,
+        #[command(flatten)]
+        audit: AuditArgs,
     },
     /// Perform a DICOM C-ECHO (Ping)
-    Echo { addr: String },
+    Echo {
+        addr: String,
+        #[command(flatten)]
+        association: AssociationArgs,
+        #[command(flatten)]
+        trace: TraceArgs,
+    },
+    /// C-ECHO every port of a host list or CIDR block and report the endpoints that answered
+    EchoScan {
+        /// Comma-separated host names, IP addresses and IPv4 CIDR blocks (e.g. 10.0.0.0/24,pacs.local)
+        targets: String,
+        /// Ports and `first-last` ranges to try on every host
+        #[arg(long, default_value = "104,11112")]
+        ports: String,
+        /// Probes in flight at once
+        #[arg(long, default_value_t = 64)]
+        concurrency: usize,
+        #[command(flatten)]
+        association: AssociationArgs,
+        /// Also list the endpoints that did not answer
+        #[arg(long)]
+        all: bool,
+        /// Print the results as JSON instead of a table
+        #[arg(long)]
+        json: bool,
+    },
     /// Perform a DICOM C-STORE (Push)
-    Push { addr: String, file: PathBuf },
+    Push {
+        addr: String,
+        file: PathBuf,
+        #[command(flatten)]
+        association: AssociationArgs,
+        /// Transfer syntaxes to offer, best first (UIDs or e.g. jpeg-ls,j2k,explicit); the
+        /// instance is transcoded to the first one the peer accepts
+        #[arg(long, value_name = "SYNTAXES")]
+        prefer_ts: Option<TransferSyntaxPreference>,
+        #[command(flatten)]
+        trace: TraceArgs,
+        #[command(flatten)]
+        audit: AuditArgs,
+    },
+    /// C-STORE every DICOM file of a directory, or of a .zip/.tar/.tar.gz export, over one
+    /// association, optionally paced
+    PushDir {
+        addr: String,
+        dir: PathBuf,
+        #[command(flatten)]
+        association: AssociationArgs,
+        /// Transfer syntaxes to offer, best first (UIDs or e.g. jpeg-ls,j2k,explicit); each
+        /// instance is transcoded to the first one the peer accepts for its SOP class
+        #[arg(long, value_name = "SYNTAXES")]
+        prefer_ts: Option<TransferSyntaxPreference>,
+        #[command(flatten)]
+        limits: TransferLimitArgs,
+        /// Queue instances that fail for a transient reason in this directory, for `send-queue`
+        #[arg(long, value_name = "DIR")]
+        queue: Option<PathBuf>,
+        #[command(flatten)]
+        trace: TraceArgs,
+        #[command(flatten)]
+        audit: AuditArgs,
+    },
+    /// Query a Modality Worklist SCP for scheduled procedure steps
+    Mwl {
+        addr: String,
+        #[command(flatten)]
+        association: AssociationArgs,
+        /// Scheduled date: YYYYMMDD, a YYYYMMDD-YYYYMMDD range, or `today`
+        #[arg(long)]
+        date: Option<String>,
+        #[arg(long)]
+        modality: Option<String>,
+        /// Scheduled Station AE Title
+        #[arg(long)]
+        station_aet: Option<String>,
+        /// Print the steps as a JSON array instead of a table
+        #[arg(long)]
+        json: bool,
+        #[command(flatten)]
+        trace: TraceArgs,
+    },
+    /// Report a performed procedure step as a modality would: N-CREATE it IN PROGRESS, then N-SET
+    /// it COMPLETED or DISCONTINUED listing the instances acquired
+    Mpps {
+        /// MPPS SCP address as host:port
+        addr: String,
+        /// Directory of the instances acquired (and pushed) for the procedure
+        dir: PathBuf,
+        #[command(flatten)]
+        association: AssociationArgs,
+        /// Final status; `in-progress` only creates the step
+        #[arg(long, value_enum, default_value_t = MppsStatus::Completed)]
+        status: MppsStatus,
+        /// Finish an existing step with N-SET instead of creating one
+        #[arg(long)]
+        uid: Option<String>,
+        /// Performed Procedure Step ID; defaults to one derived from the current time
+        #[arg(long, conflicts_with = "uid")]
+        step_id: Option<String>,
+        /// AE title the instances can be retrieved from
+        #[arg(long)]
+        retrieve_aet: Option<String>,
+        #[command(flatten)]
+        trace: TraceArgs,
+    },
+    /// Upload DICOM files to a DICOMweb server with STOW-RS (multipart/related application/dicom)
+    Stow {
+        /// DICOMweb service root, e.g. https://pacs.example/dicomweb (`/studies` is appended)
+        url: String,
+        /// DICOM files or directories to upload
+        #[arg(required = true)]
+        inputs: Vec<PathBuf>,
+        /// Bearer token; defaults to the DICOMWEB_TOKEN environment variable
+        #[arg(long)]
+        token: Option<String>,
+        /// Files per STOW-RS request
+        #[arg(long, default_value_t = 50)]
+        batch_size: usize,
+        /// Request timeout in seconds
+        #[arg(long)]
+        timeout: Option<u64>,
+        #[command(flatten)]
+        limits: TransferLimitArgs,
+        /// Print the accepted and rejected instances as JSON
+        #[arg(long)]
+        json: bool,
+        /// Queue instances rejected for a transient reason in this directory, for `send-queue`
+        #[arg(long, value_name = "DIR")]
+        queue: Option<PathBuf>,
+    },
+    /// Retry the instances of a send queue filled by `push-dir --queue` or `stow --queue`
+    SendQueue {
+        /// Queue directory
+        dir: PathBuf,
+        /// List the pending and failed instances instead of sending
+        #[arg(long)]
+        list: bool,
+        /// Keep retrying, sleeping between attempts, until the queue is empty
+        #[arg(long)]
+        wait: bool,
+        /// Attempts, the original push included, before an instance is moved to the failed list
+        #[arg(long, default_value_t = 10)]
+        max_attempts: u32,
+        /// Seconds before the first retry; doubles after each failure
+        #[arg(long, value_name = "SECS", default_value_t = 30)]
+        initial_delay: u64,
+        /// Longest wait between two attempts, in seconds
+        #[arg(long, value_name = "SECS", default_value_t = 3600)]
+        max_delay: u64,
+        /// Bearer token for STOW-RS destinations; defaults to the DICOMWEB_TOKEN environment variable
+        #[arg(long)]
+        token: Option<String>,
+        /// STOW-RS request timeout in seconds
+        #[arg(long)]
+        timeout: Option<u64>,
+    },
+    /// Download a study, series, instance, frames or rendered images from a DICOMweb server with WADO-RS
+    Wado {
+        /// DICOMweb service root, e.g. https://pacs.example/dicomweb
+        url: String,
+        #[arg(long)]
+        study: String,
+        #[arg(long)]
+        series: Option<String>,
+        /// Requires --series
+        #[arg(long, requires = "series")]
+        instance: Option<String>,
+        /// Comma-separated 1-based frame numbers of --instance, returned as pixel data
+        #[arg(long, value_delimiter = ',', requires = "instance")]
+        frames: Vec<u32>,
+        /// Ask for consumer-format images instead of DICOM (of the frames, if given)
+        #[arg(long, value_enum)]
+        rendered: Option<RenderedFormat>,
+        /// Transfer syntax UID to request, or `*` for the stored one
+        #[arg(long, conflicts_with = "rendered")]
+        transfer_syntax: Option<String>,
+        /// Directory the retrieved files are written to
+        #[arg(short, long, default_value = "wado")]
+        output: PathBuf,
+        /// Bearer token; defaults to the DICOMWEB_TOKEN environment variable
+        #[arg(long)]
+        token: Option<String>,
+        /// Request timeout in seconds
+        #[arg(long)]
+        timeout: Option<u64>,
+    },
+    /// Check that every instance of a local directory is on a PACS (and the reverse) with C-FIND
+    VerifyRemote {
+        /// PACS address as host:port
+        addr: String,
+        /// Directory of DICOM files, e.g. the source of a migration
+        dir: PathBuf,
+        #[command(flatten)]
+        association: AssociationArgs,
+        /// Also write the report as JSON
+        #[arg(long)]
+        json: Option<PathBuf>,
+        #[command(flatten)]
+        trace: TraceArgs,
+    },
+    /// Pull a study or series from a PACS with C-MOVE or C-GET, storing the instances it sends back
+    Retrieve {
+        /// PACS address as host:port
+        addr: String,
+        /// `move` has the PACS connect back to our listener; `get` receives on the same association
+        #[arg(long, value_enum, default_value_t = RetrieveMethod::Move)]
+        method: RetrieveMethod,
+        #[arg(long)]
+        study: String,
+        /// Retrieve only this series of the study
+        #[arg(long)]
+        series: Option<String>,
+        #[command(flatten)]
+        association: AssociationArgs,
+        /// Host the incoming C-STORE sub-operations are accepted on (C-MOVE only)
+        #[arg(long, default_value = "0.0.0.0")]
+        listen_host: String,
+        /// Port the incoming C-STORE sub-operations are accepted on (C-MOVE only)
+        #[arg(long, default_value_t = 11113)]
+        listen_port: u16,
+        /// Directory the received instances are written to
+        #[arg(short, long, default_value = "retrieved")]
+        output: PathBuf,
+        #[command(flatten)]
+        trace: TraceArgs,
+    },
     /// Convert DICOM to JSON
     ToJson {
         file: PathBuf,
         #[arg(short, long)]
         output: Option<PathBuf>,
+        /// `standard` DICOM JSON or `simple` keyword -> value pairs
+        #[arg(long, value_enum, default_value_t = JsonStyle::Standard)]
+        style: JsonStyle,
     },
     /// Convert JSON to DICOM
     FromJson {
@@ -105,14 +582,76 @@ pub enum Commands {
             help = "Target transfer syntax (uncompressed only)"
         )]
         transfer_syntax: TransferSyntax,
+        /// Lossy compress instead, recording Lossy Image Compression, ratio and method
+        #[arg(long, value_enum, conflicts_with = "transfer_syntax")]
+        lossy: Option<LossyCodec>,
+        /// Encoder quality (1-100) for lossy compression
+        #[arg(long, default_value_t = 85, requires = "lossy")]
+        quality: u8,
+        /// Lossy compress even if the image has already been lossy compressed
+        #[arg(long, requires = "lossy")]
+        force: bool,
+        /// Provenance recorded on the written copy
+        #[arg(long, value_enum, default_value_t = Derivation::Preserve)]
+        derivation: Derivation,
     },
     /// Calculate Pixel Statistics
-    Stats { file: PathBuf },
+    Stats {
+        file: PathBuf,
+        #[arg(
+            long,
+            value_enum,
+            default_value_t = PaletteSpace::Index,
+            help = "For PALETTE COLOR images, compute on palette indices or mapped RGB values"
+        )]
+        palette_space: PaletteSpace,
+        /// Only count pixels inside a segment of this SEG or an ROI of this RTSTRUCT
+        #[arg(long)]
+        mask: Option<PathBuf>,
+        /// Segment Number (SEG) or ROI Number (RTSTRUCT) to use from --mask
+        #[arg(long, default_value_t = 1, requires = "mask")]
+        segment: u32,
+        /// Count pixels equal to Pixel Padding Value (or within its range), left out by default
+        #[arg(long, conflicts_with = "mask")]
+        include_padding: bool,
+        /// Count pixels hidden by a display shutter, left out by default
+        #[arg(long, conflicts_with = "mask")]
+        include_shuttered: bool,
+    },
     /// Generate an intensity histogram
     Histogram {
         file: PathBuf,
         #[arg(long, default_value_t = 256)]
         bins: usize,
+        /// Fixed bin edges for a modality instead of auto-ranging from the data
+        #[arg(long, value_enum, conflicts_with_all = ["bins", "min"])]
+        preset: Option<HistogramPreset>,
+        /// Lower bound of fixed bin edges (modality units, e.g. HU)
+        #[arg(long, allow_negative_numbers = true, requires_all = ["max", "bin_width"], conflicts_with = "bins")]
+        min: Option<f32>,
+        /// Upper bound of fixed bin edges; the last bin holds it
+        #[arg(long, allow_negative_numbers = true, requires = "min")]
+        max: Option<f32>,
+        #[arg(long, requires = "min")]
+        bin_width: Option<f32>,
+    },
+    /// Joint histogram and mutual information of two aligned frames (CSV and heatmap PNG)
+    JointHistogram {
+        a: PathBuf,
+        b: PathBuf,
+        /// 0-based frame of the first file
+        #[arg(long, default_value_t = 0)]
+        frame_a: u32,
+        #[arg(long, default_value_t = 0)]
+        frame_b: u32,
+        #[arg(long, default_value_t = 64)]
+        bins: usize,
+        /// Non-empty cells as a_bin,a_start,b_bin,b_start,count
+        #[arg(long)]
+        csv: Option<PathBuf>,
+        /// Log-scaled heatmap, one pixel per cell
+        #[arg(long)]
+        png: Option<PathBuf>,
     },
     /// Dump the whole DICOM dataset
     Dump {
@@ -122,6 +661,282 @@ pub enum Commands {
         #[arg(long, default_value_t = 64)]
         max_value_len: usize,
     },
+    /// Measure distances (and enclosed area) between pixel points in millimetres
+    Measure {
+        file: PathBuf,
+        #[arg(long, default_value_t = 0)]
+        frame: u32,
+        /// Pixel point as x,y (column,row); repeat for each vertex
+        #[arg(long = "point", required = true, num_args = 1)]
+        points: Vec<measure::PixelPoint>,
+    },
+    /// Stack a series into a 3D volume and write it as NIfTI-1 (`.nii` or `.nii.gz`)
+    ExportNifti {
+        /// Directory holding the series
+        dir: PathBuf,
+        output: PathBuf,
+        /// Series Instance UID, when the directory holds several series
+        #[arg(long)]
+        series: Option<String>,
+        /// Fill missing slices and even out irregular spacing instead of refusing the series
+        #[arg(long, value_enum, default_value_t = SliceResampling::None)]
+        resample: SliceResampling,
+    },
+    /// Time-intensity curves of a dynamic series (perfusion, DCE) for an ROI, voxels or the volume
+    TimeCurves {
+        /// Directory holding the dynamic series
+        dir: PathBuf,
+        /// Series Instance UID, when the directory holds several series
+        #[arg(long)]
+        series: Option<String>,
+        /// Segmentation or RT Structure Set giving the region
+        #[arg(long)]
+        mask: Option<PathBuf>,
+        /// Segment number (SEG) or ROI number (RTSTRUCT) within the mask
+        #[arg(long, default_value_t = 1, requires = "mask")]
+        segment: u32,
+        /// Voxel as x,y,z (column, row, slice); repeat for each voxel
+        #[arg(long = "voxel", num_args = 1)]
+        voxels: Vec<time_curves::VoxelIndex>,
+        /// Write the curves as CSV
+        #[arg(long)]
+        csv: Option<PathBuf>,
+        /// Plot the mean curves as PNG
+        #[arg(long)]
+        png: Option<PathBuf>,
+        /// Print the timepoints and curves as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Check whether two series share a spatial frame (before fusion or contour reuse)
+    RegistrationCheck {
+        /// Directory holding the first series
+        series_a: PathBuf,
+        /// Directory holding the second series
+        series_b: PathBuf,
+        /// Maximum angle between planes still considered parallel (degrees)
+        #[arg(long, default_value_t = 0.5)]
+        angle_tolerance: f64,
+    },
+    /// Summarize per-tag presence, distinct values, lengths, VM and VR drift across a directory
+    TagStats {
+        directory: PathBuf,
+        /// Write the summary as CSV instead of printing a table
+        #[arg(long)]
+        csv: Option<PathBuf>,
+    },
+    /// Rewrite inconsistent values across a cohort (body part synonyms, institution names,
+    /// series descriptions) following a TOML mapping, into corrected copies with a change
+    /// report
+    Harmonize {
+        directory: PathBuf,
+        /// TOML mapping file ([[rules]] with tag, normalize and values)
+        #[arg(long)]
+        map: PathBuf,
+        /// Directory receiving the corrected copies and harmonize-report.csv
+        #[arg(short, long, required_unless_present = "dry_run")]
+        output: Option<PathBuf>,
+        /// Only report what would change
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Split mixed series (multi-echo, multi-phase, multi-b-value) into one series per value
+    SplitSeries {
+        directory: PathBuf,
+        /// Attribute to split on; by default the first of echo time, temporal position and
+        /// b-value that varies within a series
+        #[arg(long, value_enum)]
+        by: Option<SplitBy>,
+        /// Directory receiving the rewritten copies
+        #[arg(short, long, required_unless_present = "dry_run")]
+        output: Option<PathBuf>,
+        /// Only report how the series would be split
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Find concatenated multiframe objects and optionally reassemble them
+    Concat {
+        directory: PathBuf,
+        /// Write each complete concatenation, reassembled, into this directory
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Split selected frames of a multiframe object into single-frame derived instances
+    ExtractFrames {
+        input: PathBuf,
+        /// 1-based frame numbers, comma separated (e.g. 1,5,9)
+        #[arg(long, value_delimiter = ',', required = true)]
+        frames: Vec<u32>,
+        /// Directory receiving one file per extracted frame
+        #[arg(short, long)]
+        output: PathBuf,
+        /// Provenance recorded on each frame (a new SOP Instance UID is always assigned)
+        #[arg(long, value_enum, default_value_t = Derivation::Full)]
+        derivation: Derivation,
+    },
+    /// Generate synthetic DICOM test data (gradients, noise or a phantom)
+    Synth {
+        /// Directory receiving IMG0001.dcm, IMG0002.dcm, ...
+        output: PathBuf,
+        #[arg(long, default_value = "OT")]
+        modality: String,
+        #[arg(long, default_value_t = 64)]
+        rows: u16,
+        #[arg(long, default_value_t = 64)]
+        columns: u16,
+        /// Bits Stored (1-16); 8 or fewer are stored as bytes
+        #[arg(long, default_value_t = 16)]
+        bits: u16,
+        #[arg(long, default_value_t = 1)]
+        frames: u32,
+        /// Number of instances in the series, stacked along z
+        #[arg(long, default_value_t = 1)]
+        instances: u32,
+        #[arg(long, value_enum, default_value_t = SynthPattern::Gradient)]
+        pattern: SynthPattern,
+        /// Seed for the noise and the UIDs; the same seed reproduces the same files
+        #[arg(long, default_value_t = 0)]
+        seed: u64,
+    },
+    /// Estimate per-series archive size under JPEG-LS / JPEG 2000 lossless
+    SizeReport {
+        directory: PathBuf,
+        /// Write the report as CSV instead of printing a table
+        #[arg(long)]
+        csv: Option<PathBuf>,
+    },
+    /// Convert OsiriX/Horos ROI exports (.xml/.json) or CSV point lists into an SR or a SEG
+    /// referencing the annotated instances of a series
+    ImportAnnotations {
+        annotations: PathBuf,
+        /// Directory holding the annotated series
+        #[arg(long)]
+        series: PathBuf,
+        #[arg(short, long)]
+        output: PathBuf,
+        #[arg(long = "as", value_enum, default_value_t = AnnotationOutput::Sr)]
+        target: AnnotationOutput,
+    },
+    /// Build a TID 1500 Measurement Report SR from a JSON description of findings (e.g. model
+    /// outputs) referencing images of a series
+    MeasurementReport {
+        findings: PathBuf,
+        /// Directory holding the series the findings refer to
+        #[arg(long)]
+        series: PathBuf,
+        #[arg(short, long)]
+        output: PathBuf,
+    },
+    /// Export a directory as FHIR ImagingStudy resources (with Patient stubs) in a
+    /// transaction Bundle
+    ToFhir {
+        directory: PathBuf,
+        /// Write the Bundle to this file instead of printing it
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Store a float array computed elsewhere (raw little-endian float32, frames x rows x
+    /// columns in the order of the reference series) as a Parametric Map
+    ParametricMap {
+        values: PathBuf,
+        /// Directory holding the series the values were computed from
+        #[arg(long)]
+        reference: PathBuf,
+        #[arg(short, long)]
+        output: PathBuf,
+        /// Short name of the quantity (e.g. ADC, SUVbw)
+        #[arg(long, default_value = "MAP")]
+        label: String,
+        #[arg(long, default_value = "Parametric map")]
+        description: String,
+        /// UCUM units of the real-world values (e.g. um2/s, g/ml)
+        #[arg(long, default_value = "1")]
+        units: String,
+        /// Real-world value = slope x stored value + intercept
+        #[arg(long, default_value_t = 1.0, allow_negative_numbers = true)]
+        slope: f64,
+        #[arg(long, default_value_t = 0.0, allow_negative_numbers = true)]
+        intercept: f64,
+    },
+    /// Rewrite Rescale Slope/Intercept, re-encoding stored values losslessly
+    Rescale {
+        input: PathBuf,
+        #[arg(short, long)]
+        output: PathBuf,
+        #[arg(long, default_value_t = 1.0, allow_negative_numbers = true)]
+        slope: f64,
+        #[arg(long, default_value_t = 0.0, allow_negative_numbers = true)]
+        intercept: f64,
+        /// Bake the current rescale into stored values (slope 1, intercept 0)
+        #[arg(long, conflicts_with_all = ["slope", "intercept", "relabel"])]
+        bake: bool,
+        /// Only rewrite the attributes, leaving stored values as they are
+        #[arg(long)]
+        relabel: bool,
+    },
+    /// Save the embedded Icon Image Sequence thumbnail as an image
+    ExtractIcon {
+        file: PathBuf,
+        #[arg(short, long)]
+        output: PathBuf,
+    },
+    /// Generate an Icon Image Sequence from the first frame and embed it
+    AddIcon {
+        input: PathBuf,
+        #[arg(short, long)]
+        output: PathBuf,
+        #[arg(long, value_enum, default_value_t = IconEdge::Px64)]
+        size: IconEdge,
+    },
+    /// Export decoded pixel values (per pixel or per frame) to Parquet or Arrow IPC
+    #[cfg(feature = "parquet")]
+    ExportPixels {
+        /// A DICOM file or a directory of them
+        input: PathBuf,
+        #[arg(short, long)]
+        output: PathBuf,
+        /// One row per pixel, or one summary row per frame
+        #[arg(long, value_enum, default_value_t = PixelRows::Frame)]
+        rows: PixelRows,
+        /// Add row and column to per-pixel records
+        #[arg(long)]
+        coordinates: bool,
+        #[arg(long, value_enum, default_value_t = PixelExportFormat::Parquet)]
+        format: PixelExportFormat,
+    },
+    /// Measure files/sec and MB/sec of a pipeline over a directory of .dcm files
+    #[cfg(feature = "bench")]
+    Bench {
+        #[arg(value_enum)]
+        pipeline: BenchPipeline,
+        directory: PathBuf,
+        /// Passes over the directory; later passes run with a warm page cache
+        #[arg(long, default_value_t = 1)]
+        iterations: usize,
+    },
+}
+
+#[derive(Copy, Clone, Debug, ValueEnum)]
+pub enum RetrieveMethod {
+    Move,
+    Get,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum MppsStatus {
+    InProgress,
+    Completed,
+    Discontinued,
+}
+
+impl From<MppsStatus> for mpps::StepStatus {
+    fn from(value: MppsStatus) -> Self {
+        match value {
+            MppsStatus::InProgress => mpps::StepStatus::InProgress,
+            MppsStatus::Completed => mpps::StepStatus::Completed,
+            MppsStatus::Discontinued => mpps::StepStatus::Discontinued,
+        }
+    }
 }
 
 #[derive(Copy, Clone, Debug, ValueEnum)]
@@ -131,6 +946,140 @@ pub enum BatchOperation {
 }
 
 #[derive(Copy, Clone, Debug, ValueEnum)]
+pub enum AnnotationOutput {
+    /// Comprehensive SR measurement report
+    Sr,
+    /// Binary Segmentation, one segment per label
+    Seg,
+}
+
+impl From<AnnotationOutput> for annotations::ImportTarget {
+    fn from(value: AnnotationOutput) -> Self {
+        match value {
+            AnnotationOutput::Sr => annotations::ImportTarget::Sr,
+            AnnotationOutput::Seg => annotations::ImportTarget::Seg,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, ValueEnum)]
+pub enum ValidateFormat {
+    Text,
+    Ndjson,
+}
+
+impl From<ValidateFormat> for validate::ValidateOutput {
+    fn from(value: ValidateFormat) -> Self {
+        match value {
+            ValidateFormat::Text => validate::ValidateOutput::Text,
+            ValidateFormat::Ndjson => validate::ValidateOutput::Ndjson,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, ValueEnum)]
+pub enum VoiFunction {
+    Linear,
+    LinearExact,
+    Sigmoid,
+}
+
+impl From<VoiFunction> for VoiLutFunction {
+    fn from(value: VoiFunction) -> Self {
+        match value {
+            VoiFunction::Linear => VoiLutFunction::Linear,
+            VoiFunction::LinearExact => VoiLutFunction::LinearExact,
+            VoiFunction::Sigmoid => VoiLutFunction::Sigmoid,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, ValueEnum)]
+pub enum DisplayInversion {
+    Auto,
+    Always,
+    Never,
+}
+
+impl From<DisplayInversion> for image::Inversion {
+    fn from(value: DisplayInversion) -> Self {
+        match value {
+            DisplayInversion::Auto => image::Inversion::Auto,
+            DisplayInversion::Always => image::Inversion::Always,
+            DisplayInversion::Never => image::Inversion::Never,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PaletteSpace {
+    #[default]
+    Index,
+    Rgb,
+}
+
+impl From<PaletteSpace> for stats::PaletteSpace {
+    fn from(value: PaletteSpace) -> Self {
+        match value {
+            PaletteSpace::Index => stats::PaletteSpace::Index,
+            PaletteSpace::Rgb => stats::PaletteSpace::Rgb,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum JsonStyle {
+    Standard,
+    Simple,
+}
+
+impl From<JsonStyle> for json::JsonStyle {
+    fn from(value: JsonStyle) -> Self {
+        match value {
+            JsonStyle::Standard => json::JsonStyle::Standard,
+            JsonStyle::Simple => json::JsonStyle::Simple,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, ValueEnum)]
+pub enum Eviction {
+    /// Evict derived artifacts, then least recently used uploads (shared payloads last)
+    Lru,
+    /// Refuse new files once full
+    Reject,
+}
+
+impl From<Eviction> for EvictionPolicy {
+    fn from(value: Eviction) -> Self {
+        match value {
+            Eviction::Lru => EvictionPolicy::Lru,
+            Eviction::Reject => EvictionPolicy::Reject,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, ValueEnum)]
+pub enum IconEdge {
+    #[value(name = "64")]
+    Px64,
+    #[value(name = "128")]
+    Px128,
+}
+
+impl From<IconEdge> for icon::IconSize {
+    fn from(value: IconEdge) -> Self {
+        match value {
+            IconEdge::Px64 => icon::IconSize::Small,
+            IconEdge::Px128 => icon::IconSize::Large,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
 pub enum TransferSyntax {
     ExplicitVrLittleEndian,
     ImplicitVrLittleEndian,
@@ -149,13 +1098,301 @@ impl From<TransferSyntax> for transcode::UncompressedTransferSyntax {
     }
 }
 
+#[cfg(feature = "parquet")]
+#[derive(Copy, Clone, Debug, ValueEnum)]
+pub enum PixelRows {
+    Pixel,
+    Frame,
+}
+
+#[cfg(feature = "parquet")]
+#[derive(Copy, Clone, Debug, ValueEnum)]
+pub enum PixelExportFormat {
+    Parquet,
+    /// Arrow IPC file (Feather v2)
+    Arrow,
+}
+
+#[cfg(feature = "parquet")]
+impl From<PixelExportFormat> for crate::pixel_export::ExportFormat {
+    fn from(value: PixelExportFormat) -> Self {
+        match value {
+            PixelExportFormat::Parquet => crate::pixel_export::ExportFormat::Parquet,
+            PixelExportFormat::Arrow => crate::pixel_export::ExportFormat::Arrow,
+        }
+    }
+}
+
+#[cfg(feature = "bench")]
+#[derive(Copy, Clone, Debug, ValueEnum)]
+pub enum BenchPipeline {
+    Anonymize,
+    Transcode,
+}
+
+#[cfg(feature = "bench")]
+impl From<BenchPipeline> for crate::throughput::Pipeline {
+    fn from(value: BenchPipeline) -> Self {
+        match value {
+            BenchPipeline::Anonymize => crate::throughput::Pipeline::Anonymize,
+            BenchPipeline::Transcode => crate::throughput::Pipeline::Transcode,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum HistogramPreset {
+    /// -1024..3071 HU in 1 HU bins
+    Ct,
+    /// 0..100000 Bq/ml in 100 Bq/ml bins
+    Pet,
+}
+
+impl From<HistogramPreset> for stats::FixedBins {
+    fn from(value: HistogramPreset) -> Self {
+        match value {
+            HistogramPreset::Ct => stats::FixedBins::CT_HU,
+            HistogramPreset::Pet => stats::FixedBins::PET_BQML,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, ValueEnum)]
+pub enum Derivation {
+    Preserve,
+    NewUid,
+    Full,
+}
+
+impl From<Derivation> for derivation::DerivationPolicy {
+    fn from(value: Derivation) -> Self {
+        match value {
+            Derivation::Preserve => derivation::DerivationPolicy::Preserve,
+            Derivation::NewUid => derivation::DerivationPolicy::NewUid,
+            Derivation::Full => derivation::DerivationPolicy::Full,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, ValueEnum)]
+pub enum LossyCodec {
+    JpegBaseline,
+}
+
+impl From<LossyCodec> for transcode::LossyTransferSyntax {
+    fn from(value: LossyCodec) -> Self {
+        match value {
+            LossyCodec::JpegBaseline => transcode::LossyTransferSyntax::JpegBaseline,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AnonymizationProfile {
+    #[default]
+    Basic,
+    RetainDates,
+}
+
+impl From<AnonymizationProfile> for anonymize::Profile {
+    fn from(value: AnonymizationProfile) -> Self {
+        match value {
+            AnonymizationProfile::Basic => anonymize::Profile::Basic,
+            AnonymizationProfile::RetainDates => anonymize::Profile::RetainDates,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DeviceField {
+    /// Institution name, address and department
+    Institution,
+    Station,
+    DeviceSerial,
+    Operators,
+}
+
+/// Device identity kept by anonymization: all of it with `all`, otherwise the listed fields.
+pub fn device_retention(all: bool, fields: &[DeviceField]) -> anonymize::DeviceRetention {
+    if all {
+        return anonymize::DeviceRetention::ALL;
+    }
+    anonymize::DeviceRetention {
+        institution: fields.contains(&DeviceField::Institution),
+        station: fields.contains(&DeviceField::Station),
+        device_serial: fields.contains(&DeviceField::DeviceSerial),
+        operators: fields.contains(&DeviceField::Operators),
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RemovableGroup {
+    /// Curve data, groups 50xx
+    Curves,
+    /// Overlay planes, groups 60xx
+    Overlays,
+    /// Retired audio elements in groups 50xx
+    Audio,
+    /// Identifying Comments (0008,4000)
+    IdentifyingComments,
+    /// Original Attributes Sequence and the rest of group 0400
+    OriginalAttributes,
+}
+
+/// Groups dropped by anonymization: the listed ones.
+pub fn group_removal(groups: &[RemovableGroup]) -> anonymize::GroupRemoval {
+    anonymize::GroupRemoval {
+        curves: groups.contains(&RemovableGroup::Curves),
+        overlays: groups.contains(&RemovableGroup::Overlays),
+        audio: groups.contains(&RemovableGroup::Audio),
+        identifying_comments: groups.contains(&RemovableGroup::IdentifyingComments),
+        original_attributes: groups.contains(&RemovableGroup::OriginalAttributes),
+    }
+}
+
+#[derive(Copy, Clone, Debug, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ModificationReason {
+    Coerce,
+    Correct,
+}
+
+impl From<ModificationReason> for anonymize::ModificationReason {
+    fn from(value: ModificationReason) -> Self {
+        match value {
+            ModificationReason::Coerce => anonymize::ModificationReason::Coerce,
+            ModificationReason::Correct => anonymize::ModificationReason::Correct,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, ValueEnum)]
+pub enum SliceResampling {
+    None,
+    Nearest,
+    Linear,
+}
+
+impl From<SliceResampling> for volume::Resampling {
+    fn from(value: SliceResampling) -> Self {
+        match value {
+            SliceResampling::None => volume::Resampling::None,
+            SliceResampling::Nearest => volume::Resampling::Nearest,
+            SliceResampling::Linear => volume::Resampling::Linear,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, ValueEnum)]
+pub enum SplitBy {
+    EchoTime,
+    TemporalPosition,
+    BValue,
+}
+
+impl From<SplitBy> for series_split::SplitKey {
+    fn from(value: SplitBy) -> Self {
+        match value {
+            SplitBy::EchoTime => series_split::SplitKey::EchoTime,
+            SplitBy::TemporalPosition => series_split::SplitKey::TemporalPosition,
+            SplitBy::BValue => series_split::SplitKey::BValue,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, ValueEnum)]
+pub enum RenderedFormat {
+    Jpeg,
+    Png,
+}
+
+impl RenderedFormat {
+    fn media_type(self) -> &'static str {
+        match self {
+            RenderedFormat::Jpeg => "image/jpeg",
+            RenderedFormat::Png => "image/png",
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, ValueEnum)]
+pub enum UploadAction {
+    Anonymize,
+    Validate,
+    Index,
+    Thumbnail,
+}
+
+impl From<UploadAction> for UploadStep {
+    fn from(value: UploadAction) -> Self {
+        match value {
+            UploadAction::Anonymize => UploadStep::Anonymize,
+            UploadAction::Validate => UploadStep::Validate,
+            UploadAction::Index => UploadStep::Index,
+            UploadAction::Thumbnail => UploadStep::Thumbnail,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, ValueEnum)]
+pub enum SynthPattern {
+    Gradient,
+    Noise,
+    Phantom,
+}
+
+impl From<SynthPattern> for synth::Pattern {
+    fn from(value: SynthPattern) -> Self {
+        match value {
+            SynthPattern::Gradient => synth::Pattern::Gradient,
+            SynthPattern::Noise => synth::Pattern::Noise,
+            SynthPattern::Phantom => synth::Pattern::Phantom,
+        }
+    }
+}
+
 pub async fn run() -> anyhow::Result<()> {
     // Parse the raw CLI arguments once and dispatch to a subcommand handler.
     let cli = Cli::parse();
 
     match cli.command {
         Commands::Info { file, verbose } => metadata::print_info(&file, verbose)?,
-        Commands::Anonymize { input, output } => anonymize::process_file(&input, output)?,
+        Commands::Anonymize {
+            input,
+            output,
+            derivation,
+            retain_device_identity,
+            retain_device,
+            clean_descriptors,
+            remove,
+            record_original,
+            audit,
+        } => {
+            let audit = audit.open()?;
+            let result = anonymize::process_file_with(
+                &input,
+                output,
+                anonymize::AnonymizeOptions {
+                    retain_device: device_retention(retain_device_identity, &retain_device),
+                    clean_descriptors,
+                    remove_groups: group_removal(&remove),
+                    record_original: record_original.map(Into::into),
+                    ..Default::default()
+                },
+                derivation.into(),
+            );
+            if let Some(audit) = audit {
+                audit.record(&AuditEvent::de_identification(
+                    audited_files(&[&input]),
+                    outcome_of(&result),
+                ));
+            }
+            result?
+        }
         Commands::ToImage {
             input,
             output,
@@ -163,46 +1400,610 @@ pub async fn run() -> anyhow::Result<()> {
             frame,
             window_center,
             window_width,
+            window_index,
+            voi_function,
             normalize,
             disable_modality_lut,
             disable_voi_lut,
             force_8bit,
             force_16bit,
+            invert,
+            jobs,
         } => {
             let window = parse_window(window_center, window_width)?;
             let options = image::ImageExportOptions {
                 frame,
                 window,
+                window_index,
+                voi_function: voi_function.map(Into::into),
                 normalize,
                 disable_modality_lut,
                 disable_voi_lut,
                 force_8bit,
                 force_16bit,
+                inversion: invert.into(),
+                jobs,
             };
             image::convert(&input, output, &format, &options)?
         }
-        Commands::Validate { file } => validate::check_file(&file)?,
-        Commands::Web { host, port } => web::start_server(&host, port).await?,
+        Commands::Validate { path, format } => validate::check_path(&path, format.into())?,
+        Commands::Web {
+            host,
+            port,
+            store,
+            encryption_key,
+            max_upload_mb,
+            max_store_mb,
+            eviction,
+            allowed_sop_classes,
+            max_dimension,
+            max_frames,
+            screen_command,
+            max_concurrent_jobs,
+            request_timeout_secs,
+            preview_cache_mb,
+            preview_cache_dir,
+            share_key,
+            on_upload,
+            upload_index,
+            push_destinations,
+            push_ae_map,
+        } => {
+            let key = encryption_key
+                .map(|path| EncryptionKey::from_file(&path))
+                .transpose()?;
+            let mib = |value: u64| value * 1024 * 1024;
+            let store = FileStore::open(&store, "target/uploads", key)?.with_limits(StoreLimits {
+                max_upload_bytes: max_upload_mb.map(mib),
+                max_total_bytes: max_store_mb.map(mib),
+                eviction: eviction.into(),
+            });
+            let mut screen = ScreeningPipeline::new().with(DicomSanityScreen {
+                allowed_sop_classes: allowed_sop_classes.into_iter().collect(),
+                max_dimension,
+                max_frames,
+            });
+            if let Some(command) = screen_command {
+                screen = screen.with(CommandScreen::parse(&command)?);
+            }
+            let defaults = WorkerLimits::default();
+            let limits = WorkerLimits {
+                concurrency: max_concurrent_jobs.unwrap_or(defaults.concurrency),
+                timeout: Duration::from_secs(request_timeout_secs),
+            };
+            let mut previews = PreviewCache::new(preview_cache_mb * 1024 * 1024);
+            if let Some(dir) = preview_cache_dir {
+                previews = previews.with_disk(dir)?;
+            }
+            let shares = match share_key {
+                Some(path) => ShareSigner::from_file(&path)?,
+                None => ShareSigner::random(),
+            };
+            let mut push = AeMap::default();
+            if let Some(path) = push_ae_map {
+                push.load(&path)?;
+            }
+            for entry in &push_destinations {
+                push.insert_entry(entry)?;
+            }
+            web::start_server(
+                &host,
+                port,
+                store,
+                Arc::new(screen),
+                limits,
+                previews,
+                shares,
+                UploadPipeline {
+                    steps: on_upload.into_iter().map(Into::into).collect(),
+                    index: upload_index,
+                },
+                push,
+            )
+            .await?
+        }
         Commands::Batch {
             directory,
             operation,
-        } => batch::process_directory(&directory, operation)?,
-        Commands::Echo { addr } => scu::echo(&addr)?,
-        Commands::Push { addr, file } => scu::push(&addr, &file)?,
-        Commands::ToJson { file, output } => json::to_json(&file, output.as_deref())?,
+            output_store,
+        } => {
+            let output = output_store
+                .map(|location| location.backend())
+                .transpose()?;
+            let progress = ProgressBarSink::new();
+            batch::process_directory_with_progress(
+                &directory,
+                operation,
+                output.as_deref(),
+                &progress,
+            )?;
+            progress.finish();
+        }
+        Commands::Scp {
+            host,
+            port,
+            ae_title,
+            dir,
+            destinations,
+            ae_map,
+            store_received,
+            rules,
+            worklist,
+            worklist_from_index,
+            mpps,
+            trace,
+            audit,
+        } => {
+            let mut map = AeMap::default();
+            if let Some(path) = ae_map {
+                map.load(&path)?;
+            }
+            for entry in &destinations {
+                map.insert_entry(entry)?;
+            }
+            let worklist = match worklist {
+                Some(path) => Some(WorklistSource::File(path)),
+                None if worklist_from_index => Some(WorklistSource::Directory(dir.clone())),
+                None => None,
+            };
+            if let Some(source) = &worklist {
+                // Fail at startup rather than on the first query.
+                let items = source.load()?.len();
+                println!("Modality worklist: {} scheduled steps", items);
+            }
+            let store = store_received.then(|| FileStore::new(&dir)).transpose()?;
+            let config = ScpConfig {
+                ae_title,
+                root: dir,
+                destinations: map,
+                trace: trace.open()?,
+                store,
+                router: rules
+                    .map(|path| Router::load(&path).map(Arc::new))
+                    .transpose()?,
+                worklist,
+                mpps: mpps
+                    .map(|dir| mpps::MppsStore::new(&dir).map(Arc::new))
+                    .transpose()?,
+                audit: audit.open()?,
+            };
+            scp::run(&format!("{}:{}", host, port), config)?
+        }
+        Commands::Route { rules, inputs } => {
+            let failures = Router::load(&rules)?.route_paths(&inputs);
+            if failures > 0 {
+                bail!("{} routing rule(s) failed", failures);
+            }
+        }
+        Commands::Echo {
+            addr,
+            association,
+            trace,
+        } => scu_async::echo(addr, association.settings(), trace.open()?).await?,
+        Commands::EchoScan {
+            targets,
+            ports,
+            concurrency,
+            association,
+            all,
+            json,
+        } => {
+            let hosts = echo_scan::parse_targets(&targets)?;
+            let ports = echo_scan::parse_ports(&ports)?;
+            let settings = association.settings();
+            tokio::task::spawn_blocking(move || {
+                echo_scan::print_scan(&hosts, &ports, &settings, concurrency, json, all)
+            })
+            .await??;
+        }
+        Commands::Push {
+            addr,
+            file,
+            association,
+            prefer_ts,
+            trace,
+            audit,
+        } => {
+            let mut settings = association.settings();
+            settings.transfer_syntaxes = prefer_ts.unwrap_or_default();
+            let audit = audit.open()?;
+            let progress = Arc::new(ProgressBarSink::new());
+            let result = scu_async::push(
+                addr.clone(),
+                file.clone(),
+                settings.clone(),
+                progress.clone(),
+                trace.open()?,
+            )
+            .await;
+            progress.finish();
+            if let Some(audit) = audit {
+                // Stored, possibly with coercion (0xB000, 0xB006, 0xB007).
+                let stored = result
+                    .as_ref()
+                    .is_ok_and(|code| *code == 0 || code & 0xF000 == 0xB000);
+                audit.record(&AuditEvent::export(
+                    &settings.calling_ae_title,
+                    &settings.called_ae_title,
+                    &addr,
+                    audited_files(&[&file]),
+                    if stored {
+                        AuditOutcome::Success
+                    } else {
+                        AuditOutcome::SeriousFailure
+                    },
+                ));
+            }
+            result?;
+        }
+        Commands::PushDir {
+            addr,
+            dir,
+            association,
+            prefer_ts,
+            limits,
+            queue,
+            trace,
+            audit,
+        } => {
+            let mut settings = association.settings();
+            settings.transfer_syntaxes = prefer_ts.unwrap_or_default();
+            let options = PushDirOptions {
+                association: settings,
+                rate_limit: limits.rate_limit,
+                window: limits.between,
+                audit: audit.open()?,
+                queue: queue.map(SendQueue::open).transpose()?,
+            };
+            let progress = Arc::new(ProgressBarSink::bytes());
+            let summary =
+                scu_async::push_dir(addr, dir, options, progress.clone(), trace.open()?).await?;
+            progress.finish();
+            // Queued instances are not lost; only the others fail the run.
+            let lost = summary.failed.len() - summary.queued;
+            if lost > 0 {
+                bail!("{} instance(s) were not stored", lost);
+            }
+        }
+        Commands::Mwl {
+            addr,
+            association,
+            date,
+            modality,
+            station_aet,
+            json,
+            trace,
+        } => {
+            let query = worklist::WorklistQuery {
+                scheduled_date: date.map(|date| {
+                    if date.eq_ignore_ascii_case("today") {
+                        chrono::Local::now().format("%Y%m%d").to_string()
+                    } else {
+                        date
+                    }
+                }),
+                modality,
+                station_ae_title: station_aet,
+            };
+            let answers = scu_async::find(
+                addr,
+                association.settings(),
+                worklist::MODALITY_WORKLIST_FIND.to_string(),
+                query.identifier(),
+                trace.open()?,
+            )
+            .await?;
+            let items: Vec<_> = answers
+                .iter()
+                .map(worklist::WorklistItem::from_identifier)
+                .collect();
+            worklist::print_items(&items, json)?;
+        }
+        Commands::Mpps {
+            addr,
+            dir,
+            association,
+            status,
+            uid,
+            step_id,
+            retrieve_aet,
+            trace,
+        } => {
+            let procedure = mpps::Procedure::scan(&dir)?;
+            let settings = association.settings();
+            let tracer = trace.open()?;
+            let sop_instance = match uid {
+                Some(uid) if status == MppsStatus::InProgress => {
+                    bail!("Step {} already exists; --status must finish it", uid)
+                }
+                Some(uid) => uid,
+                None => {
+                    let now = chrono::Local::now().naive_local();
+                    let step_id = step_id.unwrap_or_else(|| now.format("%y%m%d%H%M%S").to_string());
+                    let uid = derivation::new_instance_uid(&dir.to_string_lossy());
+                    scu_async::n_create(
+                        addr.clone(),
+                        settings.clone(),
+                        mpps::MODALITY_PERFORMED_PROCEDURE_STEP.to_string(),
+                        uid.clone(),
+                        procedure.in_progress(&step_id, &settings.calling_ae_title, now),
+                        tracer.clone(),
+                    )
+                    .await?;
+                    println!("Created performed procedure step {} (IN PROGRESS)", uid);
+                    uid
+                }
+            };
+            if status != MppsStatus::InProgress {
+                let status = mpps::StepStatus::from(status);
+                let ended = chrono::Local::now().naive_local();
+                scu_async::n_set(
+                    addr,
+                    settings,
+                    mpps::MODALITY_PERFORMED_PROCEDURE_STEP.to_string(),
+                    sop_instance.clone(),
+                    procedure.completion(status, ended, retrieve_aet.as_deref()),
+                    tracer,
+                )
+                .await?;
+                println!(
+                    "Set performed procedure step {} to {}: {} instances in {} series",
+                    sop_instance,
+                    status,
+                    procedure.instance_count(),
+                    procedure.series_count()
+                );
+            }
+        }
+        Commands::Stow {
+            url,
+            inputs,
+            token,
+            batch_size,
+            timeout,
+            limits,
+            json,
+            queue,
+        } => {
+            let queue = queue.map(SendQueue::open).transpose()?;
+            let options = stow::StowOptions {
+                url,
+                token: token.or_else(|| std::env::var("DICOMWEB_TOKEN").ok()),
+                batch_size,
+                timeout: timeout.map(Duration::from_secs),
+                rate_limit: limits.rate_limit,
+                window: limits.between,
+            };
+            let destination = send_queue::Destination::Stow {
+                url: options.url.clone(),
+            };
+            let summary =
+                tokio::task::spawn_blocking(move || stow::print_store(&inputs, &options, json))
+                    .await??;
+            let mut lost = summary.rejected.len();
+            if let Some(queue) = &queue {
+                for rejected in summary.rejected.iter().filter(|r| r.is_transient()) {
+                    let Some(file) = &rejected.file else {
+                        continue;
+                    };
+                    queue.enqueue(
+                        file,
+                        file,
+                        &rejected.sop_instance_uid,
+                        destination.clone(),
+                        &rejected.reason(),
+                    )?;
+                    lost -= 1;
+                }
+                let queued = summary.rejected.len() - lost;
+                if queued > 0 {
+                    eprintln!(
+                        "{} instance(s) queued for retry in {:?}",
+                        queued,
+                        queue.root()
+                    );
+                }
+            }
+            if lost > 0 {
+                bail!("{} instance(s) rejected", lost);
+            }
+        }
+        Commands::SendQueue {
+            dir,
+            list,
+            wait,
+            max_attempts,
+            initial_delay,
+            max_delay,
+            token,
+            timeout,
+        } => {
+            let queue = SendQueue::open(dir)?.with_policy(RetryPolicy {
+                initial_delay: Duration::from_secs(initial_delay),
+                max_delay: Duration::from_secs(max_delay),
+                max_attempts,
+            });
+            if list {
+                send_queue::print_queue(&queue)?;
+            } else {
+                let options = send_queue::DeliveryOptions {
+                    token: token.or_else(|| std::env::var("DICOMWEB_TOKEN").ok()),
+                    timeout: timeout.map(Duration::from_secs),
+                };
+                let summary = tokio::task::spawn_blocking(move || {
+                    send_queue::print_drain(&queue, &options, wait)
+                })
+                .await??;
+                if summary.failed > 0 {
+                    bail!("{} instance(s) moved to the failed list", summary.failed);
+                }
+            }
+        }
+        Commands::Wado {
+            url,
+            study,
+            series,
+            instance,
+            frames,
+            rendered,
+            transfer_syntax,
+            output,
+            token,
+            timeout,
+        } => {
+            let options = wado::WadoOptions {
+                url,
+                token: token.or_else(|| std::env::var("DICOMWEB_TOKEN").ok()),
+                timeout: timeout.map(Duration::from_secs),
+                transfer_syntax,
+            };
+            let target = wado::WadoTarget {
+                study,
+                series,
+                instance,
+            };
+            let resource = match (rendered, frames.is_empty()) {
+                (Some(format), _) => wado::WadoResource::Rendered {
+                    media_type: format.media_type().to_string(),
+                    frames,
+                },
+                (None, false) => wado::WadoResource::Frames(frames),
+                (None, true) => wado::WadoResource::Instances,
+            };
+            tokio::task::spawn_blocking(move || {
+                wado::print_retrieve(&options, &target, &resource, &output)
+            })
+            .await??;
+        }
+        Commands::VerifyRemote {
+            addr,
+            dir,
+            association,
+            json,
+            trace,
+        } => {
+            let remote = RemoteAe {
+                addr,
+                association: association.settings(),
+            };
+            let report = availability::print_report(&dir, &remote, json.as_deref(), trace.open()?)?;
+            if report.missing_remotely() + report.missing_locally() > 0 {
+                bail!(
+                    "{} instance(s) missing remotely, {} missing locally",
+                    report.missing_remotely(),
+                    report.missing_locally()
+                );
+            }
+        }
+        Commands::Retrieve {
+            addr,
+            method,
+            study,
+            series,
+            association,
+            listen_host,
+            listen_port,
+            output,
+            trace,
+        } => {
+            let target = match series {
+                Some(series) => RetrieveTarget::Series { study, series },
+                None => RetrieveTarget::Study(study),
+            };
+            let options = RetrieveOptions {
+                association: association.settings(),
+                output,
+            };
+            let progress = ProgressBarSink::new();
+            match method {
+                RetrieveMethod::Move => scu::retrieve(
+                    &addr,
+                    &format!("{}:{}", listen_host, listen_port),
+                    options,
+                    &target,
+                    &progress,
+                    trace.open()?,
+                )?,
+                RetrieveMethod::Get => {
+                    scu::get(&addr, &options, &target, &progress, trace.open()?)?
+                }
+            };
+            progress.finish();
+        }
+        Commands::ToJson {
+            file,
+            output,
+            style,
+        } => json::to_json(&file, output.as_deref(), style.into())?,
         Commands::FromJson { input, output } => json::from_json(&input, &output)?,
+        Commands::Transcode {
+            input,
+            output,
+            lossy: Some(codec),
+            quality,
+            force,
+            derivation,
+            ..
+        } => {
+            let options = transcode::LossyOptions {
+                quality,
+                force,
+                derivation: derivation.into(),
+            };
+            transcode::transcode_lossy(&input, &output, codec.into(), &options)?;
+        }
         Commands::Transcode {
             input,
             output,
             transfer_syntax,
-        } => transcode::transcode(&input, &output, transfer_syntax.into())?,
-        Commands::Stats { file } => stats::stats(&file)?,
-        Commands::Histogram { file, bins } => {
-            if bins == 0 {
-                bail!("Number of bins must be greater than zero");
-            }
-            let histogram = stats::histogram_for_file(&file, bins)?;
-            let total: u64 = histogram.bins.iter().sum();
+            derivation,
+            ..
+        } => {
+            let progress = ProgressBarSink::new();
+            transcode::transcode_with_derivation(
+                &input,
+                &output,
+                transfer_syntax.into(),
+                derivation.into(),
+                &progress,
+            )?;
+            progress.finish();
+        }
+        Commands::Stats {
+            file,
+            palette_space,
+            mask,
+            segment,
+            include_padding,
+            include_shuttered,
+        } => match mask {
+            Some(mask) => stats::stats_within(&file, &mask, segment)?,
+            None => {
+                let exclusions = padding::Exclusions {
+                    padding: !include_padding,
+                    shutters: !include_shuttered,
+                };
+                stats::stats(&file, palette_space.into(), exclusions)?
+            }
+        },
+        Commands::Histogram {
+            file,
+            bins,
+            preset,
+            min,
+            max,
+            bin_width,
+        } => {
+            let binning = match (preset, min, max, bin_width) {
+                (Some(preset), ..) => stats::Binning::Fixed(preset.into()),
+                (None, Some(min), Some(max), Some(width)) => {
+                    stats::Binning::Fixed(stats::FixedBins::new(min, max, width)?)
+                }
+                _ if bins == 0 => bail!("Number of bins must be greater than zero"),
+                _ => stats::Binning::Auto(bins),
+            };
+            let histogram = stats::histogram_for_file_with(&file, binning)?;
+            let total: u64 = histogram.bins.iter().sum::<u64>() + histogram.below + histogram.above;
             println!(
                 "Histogram for {:?} | bins: {} | total pixels: {}",
                 file,
@@ -211,21 +2012,40 @@ pub async fn run() -> anyhow::Result<()> {
             );
             println!("  Min: {:.2}", histogram.min);
             println!("  Max: {:.2}", histogram.max);
-            let range = if histogram.bins.len() > 1 {
-                (histogram.max - histogram.min) / histogram.bins.len() as f32
-            } else {
-                0.0
-            };
+            if histogram.below + histogram.above > 0 {
+                println!(
+                    "  Out of range: {} below, {} above",
+                    histogram.below, histogram.above
+                );
+            }
+            let width = histogram.bin_width;
             let preview = histogram.bins.iter().take(16);
             for (idx, count) in preview.enumerate() {
-                let start = histogram.min + (idx as f32) * range;
-                let end = start + range;
+                let start = histogram.min + (idx as f32) * width;
+                let end = start + width;
                 println!("  Bin {:03}: [{:.2}, {:.2}] -> {}", idx, start, end, count);
             }
             if histogram.bins.len() > 16 {
                 println!("  ... {} more bins omitted", histogram.bins.len() - 16);
             }
         }
+        Commands::JointHistogram {
+            a,
+            b,
+            frame_a,
+            frame_b,
+            bins,
+            csv,
+            png,
+        } => joint_histogram::compare_files(
+            &a,
+            frame_a,
+            &b,
+            frame_b,
+            bins,
+            csv.as_deref(),
+            png.as_deref(),
+        )?,
         Commands::Dump {
             file,
             max_depth,
@@ -233,6 +2053,186 @@ pub async fn run() -> anyhow::Result<()> {
         } => {
             dump::dump_file(&file, max_depth, max_value_len)?;
         }
+        Commands::Measure {
+            file,
+            frame,
+            points,
+        } => measure::measure_file(&file, frame, &points)?,
+        Commands::ExportNifti {
+            dir,
+            output,
+            series,
+            resample,
+        } => {
+            let options = volume::VolumeOptions {
+                series,
+                resampling: resample.into(),
+            };
+            volume::export_nifti(&dir, &output, &options)?;
+        }
+        Commands::TimeCurves {
+            dir,
+            series,
+            mask,
+            segment,
+            voxels,
+            csv,
+            png,
+            json,
+        } => {
+            let request = time_curves::CurveRequest {
+                series,
+                mask: mask.map(|mask| (mask, segment)),
+                voxels,
+            };
+            time_curves::print_curves(&dir, &request, csv.as_deref(), png.as_deref(), json)?;
+        }
+        Commands::RegistrationCheck {
+            series_a,
+            series_b,
+            angle_tolerance,
+        } => registration::check_directories(&series_a, &series_b, angle_tolerance)?,
+        Commands::TagStats { directory, csv } => {
+            tag_stats::print_tag_stats(&directory, csv.as_deref())?
+        }
+        Commands::Harmonize {
+            directory,
+            map,
+            output,
+            dry_run,
+        } => harmonize::print_harmonize(&directory, &map, output.as_deref().filter(|_| !dry_run))?,
+        Commands::SplitSeries {
+            directory,
+            by,
+            output,
+            dry_run,
+        } => series_split::print_split(
+            &directory,
+            by.map(Into::into),
+            output.as_deref().filter(|_| !dry_run),
+        )?,
+        Commands::Concat { directory, output } => {
+            concatenation::concat_directory(&directory, output.as_deref())?
+        }
+        Commands::ExtractFrames {
+            input,
+            frames,
+            output,
+            derivation,
+        } => {
+            frame_extract::extract_frames_file(&input, &frames, &output, derivation.into())?;
+        }
+        Commands::Synth {
+            output,
+            modality,
+            rows,
+            columns,
+            bits,
+            frames,
+            instances,
+            pattern,
+            seed,
+        } => {
+            let spec = synth::SynthSpec {
+                modality,
+                rows,
+                columns,
+                bits_stored: bits,
+                frames,
+                instances,
+                pattern: pattern.into(),
+                seed,
+            };
+            synth::write_series(&spec, &output)?;
+        }
+        Commands::SizeReport { directory, csv } => {
+            size_report::print_size_report(&directory, csv.as_deref())?
+        }
+        Commands::ImportAnnotations {
+            annotations: source,
+            series,
+            output,
+            target,
+        } => annotations::print_import(&source, &series, target.into(), &output)?,
+        Commands::MeasurementReport {
+            findings,
+            series,
+            output,
+        } => measurement_report::print_write(&findings, &series, &output)?,
+        Commands::ToFhir { directory, output } => {
+            fhir::write_bundle(&directory, output.as_deref())?;
+        }
+        Commands::ParametricMap {
+            values,
+            reference,
+            output,
+            label,
+            description,
+            units,
+            slope,
+            intercept,
+        } => {
+            let units = if units == "1" {
+                parametric_map::ucum("1", "no units")
+            } else {
+                parametric_map::ucum(&units, &units)
+            };
+            let options = parametric_map::ParametricMapOptions {
+                label,
+                description,
+                units,
+                quantity: None,
+                slope,
+                intercept,
+            };
+            parametric_map::print_write(&values, &reference, &options, &output)?;
+        }
+        Commands::Rescale {
+            input,
+            output,
+            slope,
+            intercept,
+            bake,
+            relabel,
+        } => {
+            let target = if bake {
+                rescale::Rescale::IDENTITY
+            } else {
+                rescale::Rescale { slope, intercept }
+            };
+            let mode = if relabel {
+                rescale::RescaleMode::Relabel(target)
+            } else {
+                rescale::RescaleMode::Reencode(target)
+            };
+            rescale::rescale_file(&input, &output, mode)?
+        }
+        Commands::ExtractIcon { file, output } => icon::extract_icon_file(&file, &output)?,
+        Commands::AddIcon {
+            input,
+            output,
+            size,
+        } => icon::add_icon_file(&input, &output, size.into())?,
+        #[cfg(feature = "parquet")]
+        Commands::ExportPixels {
+            input,
+            output,
+            rows,
+            coordinates,
+            format,
+        } => {
+            let granularity = match rows {
+                PixelRows::Pixel => crate::pixel_export::Granularity::Pixel { coordinates },
+                PixelRows::Frame => crate::pixel_export::Granularity::Frame,
+            };
+            crate::pixel_export::export_pixels(&input, &output, granularity, format.into())?
+        }
+        #[cfg(feature = "bench")]
+        Commands::Bench {
+            pipeline,
+            directory,
+            iterations,
+        } => crate::throughput::bench_directory(pipeline.into(), &directory, iterations)?,
     }
 
     Ok(())
@@ -251,3 +2251,23 @@ fn parse_window(center: Option<f64>, width: Option<f64>) -> anyhow::Result<Optio
         )),
     }
 }
+
+/// Studies and patients of `files` for an audit message; unreadable files are left out.
+fn audited_files(files: &[&std::path::Path]) -> AuditedStudies {
+    let mut studies = AuditedStudies::default();
+    for instance in files
+        .iter()
+        .filter_map(|path| scp::IndexedInstance::read(path))
+    {
+        studies.add_instance(&instance);
+    }
+    studies
+}
+
+fn outcome_of<T>(result: &anyhow::Result<T>) -> AuditOutcome {
+    if result.is_ok() {
+        AuditOutcome::Success
+    } else {
+        AuditOutcome::SeriousFailure
+    }
+}
@@ -0,0 +1,218 @@
+//
+// codes.rs
+// Dicom-Tools-rs
+//
+// Resolves Code Sequence items (Code Value, Coding Scheme Designator, Code Meaning) into coded
+// concepts, filling in missing meanings from a small bundled table of SNOMED CT, LOINC and DCM
+// codes so coded content reads as text in metadata, dumps and validation findings.
+//
+// Thales Matheus Mendonça Santos - November 2025
+
+use std::fmt;
+
+use dicom::core::Tag;
+use serde::{Deserialize, Serialize};
+
+use crate::dicom_access::ElementAccess;
+
+pub const CODE_VALUE: Tag = Tag(0x0008, 0x0100);
+pub const CODING_SCHEME_DESIGNATOR: Tag = Tag(0x0008, 0x0102);
+pub const CODE_MEANING: Tag = Tag(0x0008, 0x0104);
+/// Code values longer than 16 characters go here instead of Code Value.
+const LONG_CODE_VALUE: Tag = Tag(0x0008, 0x0119);
+/// URN or URL code values, which carry no Coding Scheme Designator.
+const URN_CODE_VALUE: Tag = Tag(0x0008, 0x0120);
+
+/// Code sequences worth surfacing in metadata and checking in validation.
+pub const CODE_SEQUENCES: [(Tag, &str); 7] = [
+    (Tag(0x0008, 0x2218), "Anatomic Region Sequence"),
+    (Tag(0x0008, 0x2228), "Primary Anatomic Structure Sequence"),
+    (Tag(0x0008, 0x1032), "Procedure Code Sequence"),
+    (Tag(0x0032, 0x1064), "Requested Procedure Code Sequence"),
+    (Tag(0x0054, 0x0220), "View Code Sequence"),
+    (Tag(0x0008, 0x9215), "Derivation Code Sequence"),
+    (Tag(0x0040, 0xA043), "Concept Name Code Sequence"),
+];
+
+/// Meanings of frequently seen codes, by coding scheme and code value. Legacy SNOMED RT
+/// (`SRT`) codes are listed next to their SNOMED CT (`SCT`) successors.
+const KNOWN_CODES: &[(&str, &str, &str)] = &[
+    ("SCT", "51185008", "Chest"),
+    ("SCT", "12738006", "Brain"),
+    ("SCT", "69536005", "Head"),
+    ("SCT", "45048000", "Neck"),
+    ("SCT", "818981001", "Abdomen"),
+    ("SCT", "816092008", "Pelvis"),
+    ("SCT", "421060004", "Spine"),
+    ("SCT", "80891009", "Heart"),
+    ("SCT", "39607008", "Lung"),
+    ("SCT", "10200004", "Liver"),
+    ("SCT", "64033007", "Kidney"),
+    ("SCT", "76752008", "Breast"),
+    ("SCT", "72696002", "Knee"),
+    ("SCT", "24028007", "Right"),
+    ("SCT", "7771000", "Left"),
+    ("SCT", "51440002", "Right and left"),
+    ("SRT", "T-D3000", "Chest"),
+    ("SRT", "T-A0100", "Brain"),
+    ("SRT", "T-D1100", "Head"),
+    ("SRT", "T-D4000", "Abdomen"),
+    ("SRT", "G-A100", "Right"),
+    ("SRT", "G-A101", "Left"),
+    ("LN", "18748-4", "Diagnostic imaging study"),
+    ("LN", "18782-3", "Radiology Study observation"),
+    ("LN", "11329-0", "History"),
+    ("LN", "19005-8", "Radiology Imaging study [Impression]"),
+    ("LN", "36643-5", "XR Chest 2 Views"),
+    ("LN", "24627-2", "CT Chest"),
+    ("DCM", "113040", "Lossy Compression"),
+    ("DCM", "113072", "Multiplanar reformatting"),
+    ("DCM", "113076", "Segmentation"),
+    (
+        "DCM",
+        "121322",
+        "Source image for image processing operation",
+    ),
+    ("DCM", "121060", "History"),
+    ("DCM", "121070", "Findings"),
+    ("DCM", "121071", "Finding"),
+    ("DCM", "121073", "Impression"),
+    ("DCM", "111030", "Image Region"),
+    ("DCM", "112039", "Tracking Identifier"),
+    ("DCM", "112040", "Tracking Unique Identifier"),
+];
+
+/// A coded concept read from one Code Sequence item.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Code {
+    pub value: String,
+    /// Coding Scheme Designator; `None` for URN code values.
+    pub scheme: Option<String>,
+    /// Code Meaning from the item, else from the bundled table.
+    pub meaning: Option<String>,
+}
+
+impl Code {
+    /// The concept in `item`, when it has a code value (short, long or URN).
+    pub fn from_item<T: ElementAccess>(item: &T) -> Option<Self> {
+        let text = |tag: Tag| {
+            item.element_str(tag)
+                .map(|s| s.trim_matches(['\0', ' ']).to_string())
+                .filter(|s| !s.is_empty())
+        };
+        let value = text(CODE_VALUE)
+            .or_else(|| text(LONG_CODE_VALUE))
+            .or_else(|| text(URN_CODE_VALUE))?;
+        let scheme = text(CODING_SCHEME_DESIGNATOR);
+        let meaning =
+            text(CODE_MEANING).or_else(|| lookup(scheme.as_deref()?, &value).map(str::to_string));
+        Some(Code {
+            value,
+            scheme,
+            meaning,
+        })
+    }
+}
+
+/// PS3.16 notation: `(113040, DCM, "Lossy Compression")`.
+impl fmt::Display for Code {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "({}", self.value)?;
+        if let Some(scheme) = &self.scheme {
+            write!(f, ", {}", scheme)?;
+        }
+        if let Some(meaning) = &self.meaning {
+            write!(f, ", \"{}\"", meaning)?;
+        }
+        write!(f, ")")
+    }
+}
+
+/// Bundled meaning of a code. `SNM3` is accepted for legacy SNOMED RT codes.
+pub fn lookup(scheme: &str, value: &str) -> Option<&'static str> {
+    let scheme = match scheme.trim() {
+        "SNM3" => "SRT",
+        other => other,
+    };
+    KNOWN_CODES
+        .iter()
+        .find(|(s, v, _)| *s == scheme && *v == value.trim())
+        .map(|(_, _, meaning)| *meaning)
+}
+
+/// Concepts of every coded item of the sequence `tag`.
+pub fn codes_in<T: ElementAccess>(obj: &T, tag: Tag) -> Vec<Code> {
+    obj.sequence_items(tag)
+        .iter()
+        .filter_map(Code::from_item)
+        .collect()
+}
+
+/// Missing Type 1 attributes of a Code Sequence item (PS3.3 Table 8.8-1).
+pub fn missing_in_item<T: ElementAccess>(item: &T) -> Vec<String> {
+    let present = |tag: Tag| {
+        item.element_str(tag)
+            .is_some_and(|s| !s.trim_matches(['\0', ' ']).is_empty())
+    };
+    let mut missing = Vec::new();
+    let urn = present(URN_CODE_VALUE);
+    if !present(CODE_VALUE) && !present(LONG_CODE_VALUE) && !urn {
+        missing.push(format!("Code Value {}", CODE_VALUE));
+    }
+    if !urn && !present(CODING_SCHEME_DESIGNATOR) {
+        missing.push(format!(
+            "Coding Scheme Designator {}",
+            CODING_SCHEME_DESIGNATOR
+        ));
+    }
+    if !present(CODE_MEANING) {
+        missing.push(format!("Code Meaning {}", CODE_MEANING));
+    }
+    missing
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dicom::core::{DataElement, PrimitiveValue, VR};
+    use dicom::object::InMemDicomObject;
+
+    fn code_item(value: &str, scheme: &str, meaning: Option<&str>) -> InMemDicomObject {
+        let mut item = InMemDicomObject::new_empty();
+        item.put(DataElement::new(
+            CODE_VALUE,
+            VR::SH,
+            PrimitiveValue::from(value),
+        ));
+        item.put(DataElement::new(
+            CODING_SCHEME_DESIGNATOR,
+            VR::SH,
+            PrimitiveValue::from(scheme),
+        ));
+        if let Some(meaning) = meaning {
+            item.put(DataElement::new(
+                CODE_MEANING,
+                VR::LO,
+                PrimitiveValue::from(meaning),
+            ));
+        }
+        item
+    }
+
+    #[test]
+    fn meanings_come_from_the_item_or_the_bundled_table() {
+        let code = Code::from_item(&code_item("T-D3000", "SRT", None)).unwrap();
+        assert_eq!(code.meaning.as_deref(), Some("Chest"));
+        assert_eq!(code.to_string(), "(T-D3000, SRT, \"Chest\")");
+        assert_eq!(missing_in_item(&code_item("T-D3000", "SRT", None)).len(), 1);
+
+        let code = Code::from_item(&code_item("113040", "DCM", Some("Lossy"))).unwrap();
+        assert_eq!(code.meaning.as_deref(), Some("Lossy"));
+        assert!(missing_in_item(&code_item("113040", "DCM", Some("Lossy"))).is_empty());
+
+        let code = Code::from_item(&code_item("99999", "99LOCAL", None)).unwrap();
+        assert_eq!(code.to_string(), "(99999, 99LOCAL)");
+        assert_eq!(lookup("SNM3", "T-A0100"), Some("Brain"));
+        assert!(Code::from_item(&InMemDicomObject::new_empty()).is_none());
+    }
+}
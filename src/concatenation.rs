@@ -0,0 +1,441 @@
+//
+// concatenation.rs
+// Dicom-Tools-rs
+//
+// Detects concatenations (Concatenation UID / In-concatenation Number) and reassembles them into one logical multiframe.
+//
+// Thales Matheus Mendonça Santos - November 2025
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use dicom::core::value::{DataSetSequence, PixelFragmentSequence, Value};
+use dicom::core::{DataElement, PrimitiveValue, Tag, VR};
+use dicom::object::{DefaultDicomObject, InMemDicomObject, OpenFileOptions};
+use rayon::prelude::*;
+use walkdir::WalkDir;
+
+use crate::atomic_file;
+use crate::dicom_access::{open_dicom, ElementAccess};
+
+const SOP_INSTANCE_UID: Tag = Tag(0x0008, 0x0018);
+const NUMBER_OF_FRAMES: Tag = Tag(0x0028, 0x0008);
+const ROWS: Tag = Tag(0x0028, 0x0010);
+const COLUMNS: Tag = Tag(0x0028, 0x0011);
+const BITS_ALLOCATED: Tag = Tag(0x0028, 0x0100);
+const SOP_INSTANCE_UID_OF_CONCATENATION_SOURCE: Tag = Tag(0x0020, 0x0242);
+const CONCATENATION_UID: Tag = Tag(0x0020, 0x9161);
+const IN_CONCATENATION_NUMBER: Tag = Tag(0x0020, 0x9162);
+const IN_CONCATENATION_TOTAL_NUMBER: Tag = Tag(0x0020, 0x9163);
+const CONCATENATION_FRAME_OFFSET_NUMBER: Tag = Tag(0x0020, 0x9228);
+const PER_FRAME_FUNCTIONAL_GROUPS_SEQUENCE: Tag = Tag(0x5200, 0x9230);
+const PIXEL_DATA: Tag = Tag(0x7FE0, 0x0010);
+
+/// Header facts about one instance of a concatenation.
+#[derive(Debug, Clone)]
+pub struct ConcatenationPart {
+    pub path: PathBuf,
+    pub number: u32,
+    pub frames: u32,
+    pub frame_offset: Option<u32>,
+}
+
+/// The instances sharing one Concatenation UID, ordered by In-concatenation Number.
+#[derive(Debug, Clone)]
+pub struct Concatenation {
+    pub uid: String,
+    /// SOP Instance UID of the object that was split; the reassembled object takes it back.
+    pub source_sop_instance_uid: String,
+    /// In-concatenation Total Number, when the parts declare it.
+    pub total: Option<u32>,
+    pub parts: Vec<ConcatenationPart>,
+}
+
+impl Concatenation {
+    pub fn frames(&self) -> u32 {
+        self.parts.iter().map(|p| p.frames).sum()
+    }
+
+    /// Reasons the parts cannot be reassembled: gaps, duplicates, or inconsistent frame offsets.
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        let expected = self.total.unwrap_or(self.parts.len() as u32);
+        let numbers: Vec<u32> = self.parts.iter().map(|p| p.number).collect();
+        if numbers != (1..=expected).collect::<Vec<_>>() {
+            problems.push(format!(
+                "expected parts 1..={}, found {:?}",
+                expected, numbers
+            ));
+        }
+        let mut offset = 0;
+        for part in &self.parts {
+            if part.frame_offset.is_some_and(|o| o != offset) {
+                problems.push(format!(
+                    "part {} starts at frame offset {:?}, expected {}",
+                    part.number, part.frame_offset, offset
+                ));
+            }
+            offset += part.frames;
+        }
+        problems
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.problems().is_empty()
+    }
+}
+
+fn text(obj: &DefaultDicomObject, tag: Tag) -> Option<String> {
+    obj.element_str(tag)
+        .map(|s| s.trim_end_matches(['\0', ' ']).to_string())
+        .filter(|s| !s.is_empty())
+}
+
+/// Group the concatenation parts found among `paths` (headers only; other files are ignored).
+fn group_parts(paths: Vec<PathBuf>) -> Vec<Concatenation> {
+    let headers: Vec<(String, String, Option<u32>, ConcatenationPart)> = paths
+        .into_par_iter()
+        .filter_map(|path| {
+            let obj = OpenFileOptions::new()
+                .read_until(PIXEL_DATA)
+                .open_file(&path)
+                .ok()?;
+            let uid = text(&obj, CONCATENATION_UID)?;
+            let source = text(&obj, SOP_INSTANCE_UID_OF_CONCATENATION_SOURCE).unwrap_or_default();
+            let part = ConcatenationPart {
+                number: obj.element_u32(IN_CONCATENATION_NUMBER)?,
+                frames: obj.element_u32(NUMBER_OF_FRAMES).unwrap_or(1),
+                frame_offset: obj.element_u32(CONCATENATION_FRAME_OFFSET_NUMBER),
+                path,
+            };
+            Some((
+                uid,
+                source,
+                obj.element_u32(IN_CONCATENATION_TOTAL_NUMBER),
+                part,
+            ))
+        })
+        .collect();
+
+    let mut groups: BTreeMap<String, Concatenation> = BTreeMap::new();
+    for (uid, source, total, part) in headers {
+        let group = groups.entry(uid.clone()).or_insert_with(|| Concatenation {
+            uid,
+            source_sop_instance_uid: source,
+            total: None,
+            parts: Vec::new(),
+        });
+        group.total = group.total.or(total);
+        group.parts.push(part);
+    }
+    let mut groups: Vec<Concatenation> = groups.into_values().collect();
+    for group in &mut groups {
+        group.parts.sort_by_key(|p| p.number);
+    }
+    groups
+}
+
+fn files_under(dir: &Path, recursive: bool) -> Vec<PathBuf> {
+    WalkDir::new(dir)
+        .max_depth(if recursive { usize::MAX } else { 1 })
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file())
+        .map(|e| e.into_path())
+        .collect()
+}
+
+/// Find every concatenation under `dir`.
+pub fn scan_directory(dir: &Path) -> Result<Vec<Concatenation>> {
+    if !dir.is_dir() {
+        bail!("{:?} is not a directory", dir);
+    }
+    Ok(group_parts(files_under(dir, true)))
+}
+
+/// Join the parts into one multiframe object carrying the source SOP Instance UID.
+pub fn reassemble(concatenation: &Concatenation) -> Result<DefaultDicomObject> {
+    let problems = concatenation.problems();
+    if !problems.is_empty() {
+        bail!(
+            "Concatenation {} is incomplete: {}",
+            concatenation.uid,
+            problems.join("; ")
+        );
+    }
+    let mut parts = concatenation.parts.iter().map(|part| {
+        open_dicom(&part.path).with_context(|| format!("Failed to open {:?}", part.path))
+    });
+    let mut base = parts.next().context("Concatenation has no parts")??;
+    let layout = |obj: &DefaultDicomObject| {
+        (
+            obj.element_u32(ROWS),
+            obj.element_u32(COLUMNS),
+            obj.element_u32(BITS_ALLOCATED),
+            obj.meta()
+                .transfer_syntax()
+                .trim_end_matches('\0')
+                .to_string(),
+        )
+    };
+    let expected_layout = layout(&base);
+
+    let mut pixels = PixelAccumulator::default();
+    pixels.push(&base, concatenation.parts[0].frames)?;
+    let mut per_frame = functional_groups(&base);
+    for (part, info) in parts.zip(&concatenation.parts[1..]) {
+        let part = part?;
+        if layout(&part) != expected_layout {
+            bail!(
+                "Part {} differs in size, bit depth or transfer syntax",
+                info.number
+            );
+        }
+        pixels.push(&part, info.frames)?;
+        per_frame.extend(functional_groups(&part));
+    }
+
+    pixels.store(&mut base)?;
+    base.put(DataElement::new(
+        NUMBER_OF_FRAMES,
+        VR::IS,
+        PrimitiveValue::from(concatenation.frames().to_string()),
+    ));
+    if !per_frame.is_empty() {
+        base.put(DataElement::new(
+            PER_FRAME_FUNCTIONAL_GROUPS_SEQUENCE,
+            VR::SQ,
+            DataSetSequence::from(per_frame),
+        ));
+    }
+    let source = &concatenation.source_sop_instance_uid;
+    if !source.is_empty() {
+        base.put(DataElement::new(
+            SOP_INSTANCE_UID,
+            VR::UI,
+            PrimitiveValue::from(source.as_str()),
+        ));
+        base.meta_mut().media_storage_sop_instance_uid = source.clone();
+        base.meta_mut().update_information_group_length();
+    }
+    for tag in [
+        SOP_INSTANCE_UID_OF_CONCATENATION_SOURCE,
+        CONCATENATION_UID,
+        IN_CONCATENATION_NUMBER,
+        IN_CONCATENATION_TOTAL_NUMBER,
+        CONCATENATION_FRAME_OFFSET_NUMBER,
+    ] {
+        base.remove_element(tag);
+    }
+    Ok(base)
+}
+
+fn functional_groups(obj: &InMemDicomObject) -> Vec<InMemDicomObject> {
+    obj.element(PER_FRAME_FUNCTIONAL_GROUPS_SEQUENCE)
+        .ok()
+        .and_then(|e| e.items())
+        .map(|items| items.to_vec())
+        .unwrap_or_default()
+}
+
+/// Pixel data of the parts, appended frame by frame.
+#[derive(Default)]
+struct PixelAccumulator {
+    bytes: Vec<u8>,
+    words: Vec<u16>,
+    fragments: Vec<Vec<u8>>,
+    vr: Option<VR>,
+}
+
+impl PixelAccumulator {
+    fn push(&mut self, obj: &InMemDicomObject, frames: u32) -> Result<()> {
+        let element = obj.element(PIXEL_DATA).context("Part has no Pixel Data")?;
+        self.vr = Some(element.vr());
+        match element.value() {
+            Value::Primitive(PrimitiveValue::U16(words)) => self.words.extend(words.iter()),
+            Value::Primitive(other) => self.bytes.extend(other.to_bytes().iter()),
+            Value::PixelSequence(seq) => {
+                // Without an offset table, frames can only be told apart one fragment each.
+                if seq.fragments().len() != frames as usize {
+                    bail!(
+                        "Encapsulated part has {} fragments for {} frames",
+                        seq.fragments().len(),
+                        frames
+                    );
+                }
+                self.fragments.extend(seq.fragments().iter().cloned());
+            }
+            Value::Sequence(_) => bail!("Pixel Data is not a pixel value"),
+        }
+        Ok(())
+    }
+
+    fn store(self, obj: &mut InMemDicomObject) -> Result<()> {
+        let vr = self.vr.unwrap_or(VR::OB);
+        let value = match (self.fragments.is_empty(), self.words.is_empty()) {
+            (false, _) => {
+                if !self.bytes.is_empty() || !self.words.is_empty() {
+                    bail!("Parts mix native and encapsulated Pixel Data");
+                }
+                obj.put(DataElement::new(
+                    PIXEL_DATA,
+                    vr,
+                    PixelFragmentSequence::new_fragments(self.fragments),
+                ));
+                return Ok(());
+            }
+            (true, false) if self.bytes.is_empty() => PrimitiveValue::U16(self.words.into()),
+            (true, true) => PrimitiveValue::U8(self.bytes.into()),
+            (true, false) => bail!("Parts mix 8-bit and 16-bit Pixel Data values"),
+        };
+        obj.put(DataElement::new(PIXEL_DATA, vr, value));
+        Ok(())
+    }
+}
+
+/// Open `path` as the logical object it belongs to: a part of a complete concatenation is
+/// reassembled with its siblings from the same directory; anything else opens as is.
+pub fn open_logical(path: &Path) -> Result<DefaultDicomObject> {
+    let obj = open_dicom(path)?;
+    let Some(uid) = text(&obj, CONCATENATION_UID) else {
+        return Ok(obj);
+    };
+    let dir = path.parent().unwrap_or(Path::new("."));
+    let Some(concatenation) = group_parts(files_under(dir, false))
+        .into_iter()
+        .find(|c| c.uid == uid)
+    else {
+        return Ok(obj);
+    };
+    if !concatenation.is_complete() {
+        eprintln!(
+            "Warning: concatenation {} is incomplete ({}); using this part only",
+            uid,
+            concatenation.problems().join("; ")
+        );
+        return Ok(obj);
+    }
+    println!(
+        "Reassembled concatenation of {} parts ({} frames)",
+        concatenation.parts.len(),
+        concatenation.frames()
+    );
+    reassemble(&concatenation)
+}
+
+/// CLI entry point: list the concatenations under `dir` and, with `output`, write each
+/// complete one reassembled as `<source SOP Instance UID>.dcm`.
+pub fn concat_directory(dir: &Path, output: Option<&Path>) -> Result<()> {
+    let concatenations = scan_directory(dir)?;
+    println!(
+        "Found {} concatenation(s) in {:?}",
+        concatenations.len(),
+        dir
+    );
+    if let Some(output) = output {
+        std::fs::create_dir_all(output)
+            .with_context(|| format!("Failed to create {:?}", output))?;
+    }
+    for concatenation in &concatenations {
+        println!(
+            "  {} | {} part(s), {} frame(s) | source {}",
+            concatenation.uid,
+            concatenation.parts.len(),
+            concatenation.frames(),
+            concatenation.source_sop_instance_uid
+        );
+        for problem in concatenation.problems() {
+            println!("    [WARN] {}", problem);
+        }
+        let Some(output) = output else { continue };
+        if !concatenation.is_complete() {
+            continue;
+        }
+        let name = if concatenation.source_sop_instance_uid.is_empty() {
+            concatenation.uid.clone()
+        } else {
+            concatenation.source_sop_instance_uid.clone()
+        };
+        let path = output.join(format!("{}.dcm", name));
+        atomic_file::write_dicom(&path, &reassemble(concatenation)?)?;
+        println!("    Reassembled into {:?}", path);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dicom::object::{FileDicomObject, FileMetaTableBuilder};
+
+    fn write_part(dir: &Path, number: u32, pixels: &[u8]) -> PathBuf {
+        let uid = format!("1.2.3.{}", number);
+        let meta = FileMetaTableBuilder::new()
+            .transfer_syntax("1.2.840.10008.1.2.1")
+            .media_storage_sop_class_uid("1.2.840.10008.5.1.4.1.1.4.1")
+            .media_storage_sop_instance_uid(uid.as_str())
+            .build()
+            .expect("meta");
+        let mut obj = FileDicomObject::new_empty_with_meta(meta);
+        let mut put = |tag, vr, value: PrimitiveValue| obj.put(DataElement::new(tag, vr, value));
+        put(SOP_INSTANCE_UID, VR::UI, uid.as_str().into());
+        put(CONCATENATION_UID, VR::UI, "1.2.3.99".into());
+        put(
+            SOP_INSTANCE_UID_OF_CONCATENATION_SOURCE,
+            VR::UI,
+            "1.2.3.100".into(),
+        );
+        put(
+            IN_CONCATENATION_NUMBER,
+            VR::US,
+            PrimitiveValue::from(number as u16),
+        );
+        put(
+            IN_CONCATENATION_TOTAL_NUMBER,
+            VR::US,
+            PrimitiveValue::from(2u16),
+        );
+        put(
+            CONCATENATION_FRAME_OFFSET_NUMBER,
+            VR::UL,
+            PrimitiveValue::from((number - 1) * 2),
+        );
+        put(NUMBER_OF_FRAMES, VR::IS, "2".into());
+        put(ROWS, VR::US, PrimitiveValue::from(1u16));
+        put(COLUMNS, VR::US, PrimitiveValue::from(2u16));
+        put(BITS_ALLOCATED, VR::US, PrimitiveValue::from(8u16));
+        put(PIXEL_DATA, VR::OB, PrimitiveValue::from(pixels.to_vec()));
+        let path = dir.join(format!("part{}.dcm", number));
+        obj.write_to_file(&path).expect("write part");
+        path
+    }
+
+    #[test]
+    fn parts_reassemble_in_concatenation_order() {
+        let dir = tempfile::tempdir().expect("tmpdir");
+        // Written out of order to check that ordering follows In-concatenation Number.
+        let second = write_part(dir.path(), 2, &[5, 6, 7, 8]);
+        write_part(dir.path(), 1, &[1, 2, 3, 4]);
+
+        let found = scan_directory(dir.path()).expect("scan");
+        assert_eq!(found.len(), 1);
+        assert!(found[0].is_complete());
+        assert_eq!(found[0].frames(), 4);
+
+        let logical = open_logical(&second).expect("reassemble");
+        assert_eq!(logical.element_u32(NUMBER_OF_FRAMES), Some(4));
+        assert_eq!(
+            text(&logical, SOP_INSTANCE_UID).as_deref(),
+            Some("1.2.3.100")
+        );
+        assert!(!logical.has_element(CONCATENATION_UID));
+        let pixels = logical.element(PIXEL_DATA).unwrap().to_bytes().unwrap();
+        assert_eq!(&pixels[..], [1, 2, 3, 4, 5, 6, 7, 8]);
+
+        std::fs::remove_file(dir.path().join("part1.dcm")).unwrap();
+        let found = scan_directory(dir.path()).expect("scan");
+        assert!(!found[0].is_complete());
+        assert!(reassemble(&found[0]).is_err());
+    }
+}
@@ -0,0 +1,151 @@
+//
+// consistency.rs
+// Dicom-Tools-rs
+//
+// Cross-attribute plausibility checks for demographics and dates: Patient's Age against
+// Birth Date and Study Date, and the Study ≤ Series ≤ Acquisition date ordering.
+//
+// Thales Matheus Mendonça Santos - November 2025
+
+use chrono::{Datelike, NaiveDate};
+use dicom::core::Tag;
+
+use crate::dicom_access::ElementAccess;
+
+const STUDY_DATE: Tag = Tag(0x0008, 0x0020);
+const SERIES_DATE: Tag = Tag(0x0008, 0x0021);
+const ACQUISITION_DATE: Tag = Tag(0x0008, 0x0022);
+const PATIENT_BIRTH_DATE: Tag = Tag(0x0010, 0x0030);
+const PATIENT_AGE: Tag = Tag(0x0010, 0x1010);
+
+/// How far Patient's Age may drift from the age computed from the dates, in its own unit;
+/// sites round ages differently, so one unit either way is accepted.
+pub const AGE_TOLERANCE: i64 = 1;
+
+/// Data-entry inconsistencies between demographic and date attributes. Absent or
+/// unparsable values are skipped; they are not what these checks are about.
+pub fn check<T: ElementAccess>(obj: &T) -> Vec<String> {
+    let mut problems = Vec::new();
+    let study = date(obj, STUDY_DATE);
+    let birth = date(obj, PATIENT_BIRTH_DATE);
+
+    if let (Some(birth), Some(study)) = (birth, study) {
+        if birth > study {
+            problems.push(format!(
+                "Patient's Birth Date {} is after Study Date {}",
+                birth, study
+            ));
+        } else if let Some((stated, unit)) = obj.element_str(PATIENT_AGE).and_then(|a| age(&a)) {
+            let actual = elapsed(birth, study, unit);
+            if (actual - stated).abs() > AGE_TOLERANCE {
+                problems.push(format!(
+                    "Patient's Age {:03}{} does not match Birth Date {} at Study Date {} ({}{})",
+                    stated, unit, birth, study, actual, unit
+                ));
+            }
+        }
+    }
+
+    // Each later step of the acquisition cannot precede the one that contains it.
+    let series = date(obj, SERIES_DATE);
+    let acquisition = date(obj, ACQUISITION_DATE);
+    for (earlier, earlier_name, later, later_name) in [
+        (study, "Study Date", series, "Series Date"),
+        (series, "Series Date", acquisition, "Acquisition Date"),
+        (study, "Study Date", acquisition, "Acquisition Date"),
+    ] {
+        if let (Some(earlier), Some(later)) = (earlier, later) {
+            if later < earlier {
+                problems.push(format!(
+                    "{} {} is before {} {}",
+                    later_name, later, earlier_name, earlier
+                ));
+            }
+        }
+    }
+
+    problems
+}
+
+fn date<T: ElementAccess>(obj: &T, tag: Tag) -> Option<NaiveDate> {
+    let text = obj.element_str(tag)?;
+    NaiveDate::parse_from_str(text.trim_end_matches(['\0', ' ']).trim(), "%Y%m%d").ok()
+}
+
+/// Parse an AS value such as `045Y` into its number and unit (D, W, M or Y).
+fn age(text: &str) -> Option<(i64, char)> {
+    let text = text.trim_end_matches(['\0', ' ']).trim();
+    let unit = text
+        .chars()
+        .last()
+        .filter(|c| matches!(c, 'D' | 'W' | 'M' | 'Y'))?;
+    let number = text[..text.len() - 1].parse().ok()?;
+    Some((number, unit))
+}
+
+/// Completed days, weeks, months or years between `from` and `to`.
+fn elapsed(from: NaiveDate, to: NaiveDate, unit: char) -> i64 {
+    let days = (to - from).num_days();
+    let mut months =
+        (to.year() - from.year()) as i64 * 12 + to.month() as i64 - from.month() as i64;
+    if to.day() < from.day() {
+        months -= 1;
+    }
+    match unit {
+        'D' => days,
+        'W' => days / 7,
+        'M' => months,
+        _ => months / 12,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dicom::core::{DataElement, PrimitiveValue, VR};
+    use dicom::object::InMemDicomObject;
+
+    fn with_dates(entries: &[(Tag, VR, &str)]) -> InMemDicomObject {
+        let mut obj = InMemDicomObject::new_empty();
+        for (tag, vr, value) in entries {
+            obj.put(DataElement::new(*tag, *vr, PrimitiveValue::from(*value)));
+        }
+        obj
+    }
+
+    #[test]
+    fn patient_age_is_checked_against_birth_and_study_dates() {
+        let base = [
+            (PATIENT_BIRTH_DATE, VR::DA, "19800615"),
+            (STUDY_DATE, VR::DA, "20240610"),
+        ];
+        // 43 completed years; rounding up to 44 is tolerated, 50 is not.
+        for (stated, ok) in [("043Y", true), ("044Y", true), ("050Y", false)] {
+            let mut entries = base.to_vec();
+            entries.push((PATIENT_AGE, VR::AS, stated));
+            assert_eq!(check(&with_dates(&entries)).is_empty(), ok, "{}", stated);
+        }
+
+        let newborn = with_dates(&[
+            (PATIENT_BIRTH_DATE, VR::DA, "20240601"),
+            (STUDY_DATE, VR::DA, "20240610"),
+            (PATIENT_AGE, VR::AS, "009D"),
+        ]);
+        assert!(check(&newborn).is_empty());
+    }
+
+    #[test]
+    fn dates_out_of_order_are_flagged() {
+        let obj = with_dates(&[
+            (PATIENT_BIRTH_DATE, VR::DA, "20250101"),
+            (STUDY_DATE, VR::DA, "20240610"),
+            (SERIES_DATE, VR::DA, "20240609"),
+            (ACQUISITION_DATE, VR::DA, "20240609"),
+        ]);
+        let problems = check(&obj);
+        assert_eq!(problems.len(), 3, "{:?}", problems);
+        assert!(problems[0].starts_with("Patient's Birth Date"));
+        assert!(problems[1].starts_with("Series Date 2024-06-09 is before Study Date"));
+        assert!(problems[2].starts_with("Acquisition Date 2024-06-09 is before Study Date"));
+    }
+}
@@ -0,0 +1,321 @@
+//
+// derivation.rs
+// Dicom-Tools-rs
+//
+// Records derived-image provenance (new SOP Instance UID, Source Image and Derivation Code
+// Sequences) on objects written by anonymize, transcode and frame extraction.
+//
+// Thales Matheus Mendonça Santos - November 2025
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use dicom::core::value::DataSetSequence;
+use dicom::core::{DataElement, PrimitiveValue, Tag, VR};
+use dicom::object::{DefaultDicomObject, InMemDicomObject};
+
+use crate::codes::{CODE_MEANING, CODE_VALUE, CODING_SCHEME_DESIGNATOR};
+use crate::dicom_access::{hashed_uid, ElementAccess};
+
+const IMAGE_TYPE: Tag = Tag(0x0008, 0x0008);
+const SOP_CLASS_UID: Tag = Tag(0x0008, 0x0016);
+const SOP_INSTANCE_UID: Tag = Tag(0x0008, 0x0018);
+const REFERENCED_SOP_CLASS_UID: Tag = Tag(0x0008, 0x1150);
+const REFERENCED_SOP_INSTANCE_UID: Tag = Tag(0x0008, 0x1155);
+const REFERENCED_FRAME_NUMBER: Tag = Tag(0x0008, 0x1160);
+const DERIVATION_DESCRIPTION: Tag = Tag(0x0008, 0x2111);
+const SOURCE_IMAGE_SEQUENCE: Tag = Tag(0x0008, 0x2112);
+const DERIVATION_CODE_SEQUENCE: Tag = Tag(0x0008, 0x9215);
+
+/// How much provenance a written object records about the object it was made from.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum DerivationPolicy {
+    /// Keep the source's identity untouched.
+    #[default]
+    Preserve,
+    /// Assign a new SOP Instance UID only.
+    NewUid,
+    /// New SOP Instance UID, DERIVED Image Type, Source Image Sequence, Derivation Code
+    /// Sequence and Derivation Description (PS3.3 C.7.6.1.1.3).
+    Full,
+}
+
+/// Why an object differs from its source.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Derivation {
+    Anonymization,
+    Transcode,
+    LossyCompression,
+    /// Frame `number` (1-based) of a source with `of` frames.
+    FrameExtraction {
+        number: u32,
+        of: u32,
+    },
+}
+
+impl Derivation {
+    /// Code from CID 7203 (Image Derivation), when one describes the change.
+    fn code(self) -> Option<(&'static str, &'static str)> {
+        match self {
+            Derivation::LossyCompression => Some(("113040", "Lossy Compression")),
+            Derivation::FrameExtraction { .. } => Some((
+                "113091",
+                "Spatially-related frames extracted from the volume",
+            )),
+            Derivation::Anonymization | Derivation::Transcode => None,
+        }
+    }
+
+    fn description(self) -> String {
+        match self {
+            Derivation::Anonymization => "De-identified copy".to_string(),
+            Derivation::Transcode => "Transfer syntax changed".to_string(),
+            Derivation::LossyCompression => "Lossy compressed copy".to_string(),
+            Derivation::FrameExtraction { number, of } => {
+                format!("Frame {} of {} extracted", number, of)
+            }
+        }
+    }
+
+    /// De-identified objects must not point back at the identified original.
+    fn references_source(self) -> bool {
+        !matches!(self, Derivation::Anonymization)
+    }
+}
+
+/// Identity of the object a derived instance was made from, captured before it changes.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SourceImage {
+    pub sop_class_uid: String,
+    pub sop_instance_uid: String,
+}
+
+impl SourceImage {
+    pub fn of(obj: &InMemDicomObject) -> Self {
+        let text = |tag: Tag| {
+            obj.element_str(tag)
+                .map(|s| s.trim_end_matches(['\0', ' ']).to_string())
+                .unwrap_or_default()
+        };
+        Self {
+            sop_class_uid: text(SOP_CLASS_UID),
+            sop_instance_uid: text(SOP_INSTANCE_UID),
+        }
+    }
+}
+
+/// A fresh UID under the 2.25 (UUID-derived) root, unique per seed and call time.
+pub fn new_instance_uid(seed: &str) -> String {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or_default();
+    hashed_uid(&format!("{}|{}|{}", seed, nanos, std::process::id()))
+}
+
+/// Record on `obj` that it was derived from `source` as `derivation`, to the extent
+/// `policy` asks for. Returns the new SOP Instance UID, if one was assigned.
+pub fn apply(
+    obj: &mut DefaultDicomObject,
+    source: &SourceImage,
+    derivation: Derivation,
+    policy: DerivationPolicy,
+) -> Option<String> {
+    if policy == DerivationPolicy::Preserve {
+        return None;
+    }
+
+    if policy == DerivationPolicy::Full {
+        mark_derived(obj, source, derivation);
+    }
+
+    let frame = match derivation {
+        Derivation::FrameExtraction { number, .. } => number,
+        _ => 0,
+    };
+    let uid = new_instance_uid(&format!(
+        "{}|{:?}|{}",
+        source.sop_instance_uid, derivation, frame
+    ));
+    obj.put(DataElement::new(
+        SOP_INSTANCE_UID,
+        VR::UI,
+        PrimitiveValue::from(uid.as_str()),
+    ));
+    obj.meta_mut().media_storage_sop_instance_uid = uid.clone();
+    obj.meta_mut().update_information_group_length();
+    Some(uid)
+}
+
+fn mark_derived(obj: &mut InMemDicomObject, source: &SourceImage, derivation: Derivation) {
+    let image_type = obj
+        .element_str(IMAGE_TYPE)
+        .map(|s| s.trim_end_matches(['\0', ' ']).to_string())
+        .unwrap_or_default();
+    let mut image_type: Vec<String> = image_type.split('\\').map(str::to_string).collect();
+    if image_type.first().is_some_and(|value| !value.is_empty()) {
+        image_type[0] = "DERIVED".to_string();
+        obj.put(DataElement::new(
+            IMAGE_TYPE,
+            VR::CS,
+            PrimitiveValue::Strs(image_type.into()),
+        ));
+    }
+
+    if derivation.references_source() && !source.sop_instance_uid.is_empty() {
+        let mut reference = InMemDicomObject::new_empty();
+        reference.put(DataElement::new(
+            REFERENCED_SOP_CLASS_UID,
+            VR::UI,
+            PrimitiveValue::from(source.sop_class_uid.as_str()),
+        ));
+        reference.put(DataElement::new(
+            REFERENCED_SOP_INSTANCE_UID,
+            VR::UI,
+            PrimitiveValue::from(source.sop_instance_uid.as_str()),
+        ));
+        if let Derivation::FrameExtraction { number, .. } = derivation {
+            reference.put(DataElement::new(
+                REFERENCED_FRAME_NUMBER,
+                VR::IS,
+                PrimitiveValue::from(number.to_string()),
+            ));
+        }
+        obj.put(DataElement::new(
+            SOURCE_IMAGE_SEQUENCE,
+            VR::SQ,
+            DataSetSequence::from(vec![reference]),
+        ));
+    } else {
+        obj.remove_element(SOURCE_IMAGE_SEQUENCE);
+    }
+
+    if let Some((value, meaning)) = derivation.code() {
+        let mut code = InMemDicomObject::new_empty();
+        code.put(DataElement::new(
+            CODE_VALUE,
+            VR::SH,
+            PrimitiveValue::from(value),
+        ));
+        code.put(DataElement::new(
+            CODING_SCHEME_DESIGNATOR,
+            VR::SH,
+            PrimitiveValue::from("DCM"),
+        ));
+        code.put(DataElement::new(
+            CODE_MEANING,
+            VR::LO,
+            PrimitiveValue::from(meaning),
+        ));
+        obj.put(DataElement::new(
+            DERIVATION_CODE_SEQUENCE,
+            VR::SQ,
+            DataSetSequence::from(vec![code]),
+        ));
+    }
+    obj.put(DataElement::new(
+        DERIVATION_DESCRIPTION,
+        VR::ST,
+        PrimitiveValue::from(derivation.description()),
+    ));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dicom::object::{FileDicomObject, FileMetaTableBuilder};
+
+    fn image() -> DefaultDicomObject {
+        let meta = FileMetaTableBuilder::new()
+            .transfer_syntax("1.2.840.10008.1.2.1")
+            .media_storage_sop_class_uid("1.2.840.10008.5.1.4.1.1.7")
+            .media_storage_sop_instance_uid("1.2.3.4")
+            .build()
+            .expect("meta");
+        let mut obj = FileDicomObject::new_empty_with_meta(meta);
+        obj.put(DataElement::new(
+            IMAGE_TYPE,
+            VR::CS,
+            PrimitiveValue::Strs(["ORIGINAL".into(), "PRIMARY".into()][..].into()),
+        ));
+        obj.put(DataElement::new(
+            SOP_CLASS_UID,
+            VR::UI,
+            PrimitiveValue::from("1.2.840.10008.5.1.4.1.1.7"),
+        ));
+        obj.put(DataElement::new(
+            SOP_INSTANCE_UID,
+            VR::UI,
+            PrimitiveValue::from("1.2.3.4"),
+        ));
+        obj
+    }
+
+    #[test]
+    fn policies_record_increasing_provenance() {
+        let mut obj = image();
+        let source = SourceImage::of(&obj);
+        assert_eq!(
+            apply(
+                &mut obj,
+                &source,
+                Derivation::LossyCompression,
+                DerivationPolicy::Preserve
+            ),
+            None
+        );
+        assert_eq!(
+            obj.element_str(SOP_INSTANCE_UID).as_deref(),
+            Some("1.2.3.4")
+        );
+
+        let uid = apply(
+            &mut obj,
+            &source,
+            Derivation::LossyCompression,
+            DerivationPolicy::NewUid,
+        )
+        .expect("new uid");
+        assert!(uid.starts_with("2.25."));
+        assert_eq!(obj.meta().media_storage_sop_instance_uid, uid);
+        assert!(!obj.has_element(SOURCE_IMAGE_SEQUENCE));
+
+        apply(
+            &mut obj,
+            &source,
+            Derivation::LossyCompression,
+            DerivationPolicy::Full,
+        );
+        assert_eq!(
+            obj.element_str(IMAGE_TYPE).as_deref(),
+            Some("DERIVED\\PRIMARY")
+        );
+        let reference = &obj.element(SOURCE_IMAGE_SEQUENCE).unwrap().items().unwrap()[0];
+        assert_eq!(
+            reference
+                .element_str(REFERENCED_SOP_INSTANCE_UID)
+                .as_deref(),
+            Some("1.2.3.4")
+        );
+        let code = &obj
+            .element(DERIVATION_CODE_SEQUENCE)
+            .unwrap()
+            .items()
+            .unwrap()[0];
+        assert_eq!(code.element_str(CODE_VALUE).as_deref(), Some("113040"));
+    }
+
+    #[test]
+    fn anonymized_copies_do_not_reference_their_source() {
+        let mut obj = image();
+        let source = SourceImage::of(&obj);
+        apply(
+            &mut obj,
+            &source,
+            Derivation::Anonymization,
+            DerivationPolicy::Full,
+        );
+        assert!(!obj.has_element(SOURCE_IMAGE_SEQUENCE));
+        assert!(!obj.has_element(DERIVATION_CODE_SEQUENCE));
+        assert!(obj.has_element(DERIVATION_DESCRIPTION));
+    }
+}
@@ -0,0 +1,208 @@
+//
+// descriptors.rs
+// Dicom-Tools-rs
+//
+// Clean Descriptors option (PS3.15 E.3.5): redacts name-like tokens and dates from free-text
+// descriptions while keeping the clinical vocabulary that makes them useful.
+//
+// Thales Matheus Mendonça Santos - November 2025
+
+use chrono::NaiveDate;
+use dicom::core::Tag;
+
+use crate::person_name::PersonName;
+
+/// Free-text descriptors scrubbed by the option.
+pub const DESCRIPTOR_TAGS: [Tag; 4] = [
+    Tag(0x0008, 0x1030), // Study Description
+    Tag(0x0008, 0x103E), // Series Description
+    Tag(0x0018, 0x1030), // Protocol Name
+    Tag(0x0020, 0x4000), // Image Comments
+];
+
+pub const NAME_PLACEHOLDER: &str = "[NAME]";
+pub const DATE_PLACEHOLDER: &str = "[DATE]";
+
+/// Capitalized words that are kept; any other capitalized word reads as a name.
+const CLINICAL_WORDS: &[&str] = &[
+    "abdomen",
+    "abdominal",
+    "acute",
+    "angio",
+    "angiography",
+    "ankle",
+    "anterior",
+    "aorta",
+    "arterial",
+    "axial",
+    "bilateral",
+    "bone",
+    "brain",
+    "breast",
+    "cardiac",
+    "cervical",
+    "chest",
+    "contrast",
+    "coronal",
+    "cranial",
+    "delayed",
+    "diffusion",
+    "elbow",
+    "exam",
+    "femur",
+    "flair",
+    "follow",
+    "foot",
+    "frontal",
+    "gradient",
+    "hand",
+    "head",
+    "heart",
+    "hip",
+    "kidney",
+    "knee",
+    "lateral",
+    "left",
+    "liver",
+    "localizer",
+    "lower",
+    "lumbar",
+    "lung",
+    "lungs",
+    "neck",
+    "oblique",
+    "orbit",
+    "pelvis",
+    "perfusion",
+    "portal",
+    "post",
+    "posterior",
+    "pre",
+    "prostate",
+    "protocol",
+    "recon",
+    "reconstruction",
+    "renal",
+    "right",
+    "routine",
+    "sagittal",
+    "scout",
+    "screening",
+    "series",
+    "shoulder",
+    "sinus",
+    "soft",
+    "spine",
+    "study",
+    "survey",
+    "thoracic",
+    "thorax",
+    "tissue",
+    "topogram",
+    "trauma",
+    "upper",
+    "venous",
+    "view",
+    "with",
+    "without",
+    "wrist",
+];
+
+/// Titles after which the next word is a name whatever it looks like.
+const HONORIFICS: &[&str] = &["dr", "mr", "mrs", "ms", "prof"];
+
+/// Redact `text`: dates become [`DATE_PLACEHOLDER`]; `^`-joined names, words equal to a
+/// component of `names` (case-insensitive), words after a title and capitalized words outside
+/// the clinical vocabulary become [`NAME_PLACEHOLDER`]. Spacing and punctuation are kept.
+pub fn clean(text: &str, names: &[String]) -> String {
+    let mut after_honorific = false;
+    text.split(' ')
+        .map(|token| {
+            let core = token.trim_matches(|c: char| ",;:()[]\"".contains(c));
+            if core.is_empty() {
+                return token.to_string();
+            }
+            let lower = core.trim_end_matches('.').to_lowercase();
+            let redaction = if is_date(core) {
+                Some(DATE_PLACEHOLDER)
+            } else if after_honorific
+                || core.contains('^')
+                || names.iter().any(|name| name.eq_ignore_ascii_case(&lower))
+                || (is_capitalized(core)
+                    && !CLINICAL_WORDS.contains(&lower.as_str())
+                    && !HONORIFICS.contains(&lower.as_str()))
+            {
+                Some(NAME_PLACEHOLDER)
+            } else {
+                None
+            };
+            after_honorific = HONORIFICS.contains(&lower.as_str());
+            match redaction {
+                Some(placeholder) => token.replacen(core, placeholder, 1),
+                None => token.to_string(),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Words of every component of person name values worth looking for in descriptions.
+pub fn name_components(value: &str) -> impl Iterator<Item = String> {
+    PersonName::parse_all(value)
+        .iter()
+        .flat_map(|name| name.components().flat_map(str::split_whitespace))
+        .map(|part| part.trim_end_matches('.').to_lowercase())
+        .filter(|part| part.chars().filter(|c| c.is_alphabetic()).count() >= 2)
+        .collect::<Vec<_>>()
+        .into_iter()
+}
+
+/// `Smith` but not `CT`, `chest` or `T1`.
+fn is_capitalized(word: &str) -> bool {
+    let word = word.trim_end_matches('.');
+    let mut chars = word.chars();
+    chars.next().is_some_and(|c| c.is_uppercase())
+        && word.chars().count() >= 2
+        && chars.all(|c| c.is_lowercase() || c == '\'' || c == '-')
+}
+
+/// `20240131`, `2024-01-31`, `31/01/2024`, `1.31.24` and the like.
+fn is_date(word: &str) -> bool {
+    if word.len() == 8 && word.chars().all(|c| c.is_ascii_digit()) {
+        return NaiveDate::parse_from_str(word, "%Y%m%d").is_ok();
+    }
+    let parts: Vec<&str> = word.split(['/', '-', '.']).collect();
+    if parts.len() != 3
+        || parts
+            .iter()
+            .any(|p| p.is_empty() || !p.chars().all(|c| c.is_ascii_digit()))
+    {
+        return false;
+    }
+    let lengths: Vec<usize> = parts.iter().map(|p| p.len()).collect();
+    match lengths.as_slice() {
+        [4, m, d] => *m <= 2 && *d <= 2,
+        [a, b, y] => *a <= 2 && *b <= 2 && (*y == 2 || *y == 4),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_and_dates_are_redacted_but_clinical_words_kept() {
+        let names: Vec<String> = name_components("DOE^JOHN").collect();
+        assert_eq!(
+            clean("CT CHEST W/O contrast for doe 2024-01-31", &names),
+            "CT CHEST W/O contrast for [NAME] [DATE]"
+        );
+        assert_eq!(
+            clean("Chest follow-up, Dr. Smith (Left knee) 20240131", &[]),
+            "Chest follow-up, Dr. [NAME] (Left knee) [DATE]"
+        );
+        assert_eq!(clean("Brain MRI T1 axial", &[]), "Brain MRI T1 axial");
+        assert_eq!(clean("ref Roe^Jane", &[]), "ref [NAME]");
+    }
+}
@@ -6,16 +6,31 @@
 //
 // Thales Matheus Mendonça Santos - November 2025
 
-use dicom::core::Tag;
+use std::path::Path;
+
+use dicom::core::value::{PrimitiveValue, Value};
+use dicom::core::{DataElement, Tag, VR};
 use dicom::dictionary_std::StandardDataDictionary;
-use dicom::object::{DefaultDicomObject, InMemDicomObject};
+use dicom::object::{open_file, DefaultDicomObject, InMemDicomObject};
+use sha2::{Digest, Sha256};
+
+/// Retired Explicit VR Big Endian transfer syntax (PS3.5 A.3).
+pub const EXPLICIT_VR_BIG_ENDIAN: &str = "1.2.840.10008.1.2.2";
+
+const PIXEL_DATA: Tag = Tag(0x7FE0, 0x0010);
+const BITS_ALLOCATED: Tag = Tag(0x0028, 0x0100);
 
 /// Small helper trait to pull string values from different DICOM object shapes.
 pub trait ElementAccess {
     fn element_str(&self, tag: Tag) -> Option<String>;
     fn element_u32(&self, tag: Tag) -> Option<u32>;
+    fn element_f64(&self, tag: Tag) -> Option<f64>;
     fn has_element(&self, tag: Tag) -> bool;
     fn transfer_syntax(&self) -> Option<String>;
+    /// Items of a sequence element; empty when absent or not a sequence.
+    fn sequence_items(&self, tag: Tag) -> &[InMemDicomObject];
+    /// Values of an AT element; empty when absent or of another VR.
+    fn element_tags(&self, tag: Tag) -> Vec<Tag>;
 }
 
 impl ElementAccess for DefaultDicomObject {
@@ -35,6 +50,11 @@ impl ElementAccess for DefaultDicomObject {
             .and_then(|s| s.into_owned().trim().parse::<u32>().ok())
     }
 
+    fn element_f64(&self, tag: Tag) -> Option<f64> {
+        // Decimal strings may be multi-valued; the first value is the one callers want.
+        self.element_str(tag).and_then(|s| first_f64(&s))
+    }
+
     fn has_element(&self, tag: Tag) -> bool {
         self.element(tag).is_ok()
     }
@@ -42,6 +62,19 @@ impl ElementAccess for DefaultDicomObject {
     fn transfer_syntax(&self) -> Option<String> {
         Some(self.meta().transfer_syntax().to_string())
     }
+
+    fn sequence_items(&self, tag: Tag) -> &[InMemDicomObject] {
+        self.element(tag)
+            .ok()
+            .and_then(|e| e.items())
+            .unwrap_or_default()
+    }
+    fn element_tags(&self, tag: Tag) -> Vec<Tag> {
+        match self.element(tag).ok().map(|e| e.value()) {
+            Some(Value::Primitive(PrimitiveValue::Tags(tags))) => tags.to_vec(),
+            _ => Vec::new(),
+        }
+    }
 }
 
 impl ElementAccess for InMemDicomObject<StandardDataDictionary> {
@@ -61,6 +94,11 @@ impl ElementAccess for InMemDicomObject<StandardDataDictionary> {
             .and_then(|s| s.into_owned().trim().parse::<u32>().ok())
     }
 
+    fn element_f64(&self, tag: Tag) -> Option<f64> {
+        // Decimal strings may be multi-valued; the first value is the one callers want.
+        self.element_str(tag).and_then(|s| first_f64(&s))
+    }
+
     fn has_element(&self, tag: Tag) -> bool {
         self.element(tag).is_ok()
     }
@@ -68,4 +106,68 @@ impl ElementAccess for InMemDicomObject<StandardDataDictionary> {
     fn transfer_syntax(&self) -> Option<String> {
         None
     }
+
+    fn sequence_items(&self, tag: Tag) -> &[InMemDicomObject] {
+        self.element(tag)
+            .ok()
+            .and_then(|e| e.items())
+            .unwrap_or_default()
+    }
+    fn element_tags(&self, tag: Tag) -> Vec<Tag> {
+        match self.element(tag).ok().map(|e| e.value()) {
+            Some(Value::Primitive(PrimitiveValue::Tags(tags))) => tags.to_vec(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+fn first_f64(text: &str) -> Option<f64> {
+    text.split('\\').next()?.trim().parse::<f64>().ok()
+}
+
+/// A `2.25` UID derived from the SHA-256 of `seed`; equal seeds give equal UIDs.
+pub fn hashed_uid(seed: &str) -> String {
+    let digest = Sha256::digest(seed.as_bytes());
+    let mut bytes = [0u8; 16];
+    bytes.copy_from_slice(&digest[..16]);
+    format!("2.25.{}", u128::from_be_bytes(bytes))
+}
+
+/// Whether the object was encoded with the retired Explicit VR Big Endian syntax.
+pub fn is_big_endian(obj: &DefaultDicomObject) -> bool {
+    obj.meta().transfer_syntax().trim_end_matches('\0') == EXPLICIT_VR_BIG_ENDIAN
+}
+
+/// Open a DICOM file for pixel work, normalizing legacy big endian encodings.
+///
+/// The parser already swaps OW pixel words, but big endian files that carry 16-bit
+/// pixel data as OB/UN keep the raw byte order; those are swapped into native words here
+/// so decoding, stats and transcoding see the same values as for little endian input.
+pub fn open_dicom(path: &Path) -> anyhow::Result<DefaultDicomObject> {
+    let mut obj = open_file(path)?;
+    if is_big_endian(&obj) {
+        normalize_big_endian_pixels(&mut obj);
+    }
+    Ok(obj)
+}
+
+pub(crate) fn normalize_big_endian_pixels(obj: &mut DefaultDicomObject) {
+    if obj.element_u32(BITS_ALLOCATED).unwrap_or(0) <= 8 {
+        return;
+    }
+    let Ok(element) = obj.element(PIXEL_DATA) else {
+        return;
+    };
+    let Value::Primitive(PrimitiveValue::U8(bytes)) = element.value() else {
+        return;
+    };
+    let words: Vec<u16> = bytes
+        .chunks_exact(2)
+        .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+        .collect();
+    obj.put(DataElement::new(
+        PIXEL_DATA,
+        VR::OW,
+        PrimitiveValue::U16(words.into()),
+    ));
 }
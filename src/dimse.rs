@@ -0,0 +1,714 @@
+//
+// dimse.rs
+// Dicom-Tools-rs
+//
+// Shared DIMSE message plumbing: command set encoding, message reassembly from P-DATA, C-STORE sending,
+// requestor association settings (AE titles, PDU size, timeouts), and a requestor association
+// proposing SCP/SCU role selection for C-GET.
+//
+// Thales Matheus Mendonça Santos - November 2025
+
+use std::io::Write;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use dicom::core::{DataElement, PrimitiveValue, Tag, VR};
+use dicom::encoding::TransferSyntaxIndex;
+use dicom::object::InMemDicomObject;
+use dicom::transfer_syntax::{TransferSyntax, TransferSyntaxRegistry};
+use dicom_ul::pdu::reader::{DEFAULT_MAX_PDU, MAXIMUM_PDU_SIZE, MINIMUM_PDU_SIZE};
+use dicom_ul::pdu::{
+    AssociationRQ, PDataValue, PDataValueType, Pdu, PresentationContextProposed,
+    PresentationContextResult, UserVariableItem,
+};
+use dicom_ul::{
+    read_pdu, write_pdu, ClientAssociation, ClientAssociationOptions, ServerAssociation,
+    IMPLEMENTATION_CLASS_UID, IMPLEMENTATION_VERSION_NAME,
+};
+
+use crate::dicom_access::ElementAccess;
+use crate::dimse_trace::{DimseTracer, Direction};
+use crate::ts_preference::TransferSyntaxPreference;
+
+pub const IMPLICIT_VR_LITTLE_ENDIAN: &str = "1.2.840.10008.1.2";
+pub const EXPLICIT_VR_LITTLE_ENDIAN: &str = "1.2.840.10008.1.2.1";
+
+pub const COMMAND_GROUP_LENGTH: Tag = Tag(0x0000, 0x0000);
+pub const AFFECTED_SOP_CLASS_UID: Tag = Tag(0x0000, 0x0002);
+pub const REQUESTED_SOP_CLASS_UID: Tag = Tag(0x0000, 0x0003);
+pub const COMMAND_FIELD: Tag = Tag(0x0000, 0x0100);
+pub const MESSAGE_ID: Tag = Tag(0x0000, 0x0110);
+pub const MESSAGE_ID_BEING_RESPONDED_TO: Tag = Tag(0x0000, 0x0120);
+pub const MOVE_DESTINATION: Tag = Tag(0x0000, 0x0600);
+pub const PRIORITY: Tag = Tag(0x0000, 0x0700);
+pub const COMMAND_DATA_SET_TYPE: Tag = Tag(0x0000, 0x0800);
+pub const STATUS: Tag = Tag(0x0000, 0x0900);
+pub const AFFECTED_SOP_INSTANCE_UID: Tag = Tag(0x0000, 0x1000);
+pub const REQUESTED_SOP_INSTANCE_UID: Tag = Tag(0x0000, 0x1001);
+pub const REMAINING_SUBOPERATIONS: Tag = Tag(0x0000, 0x1020);
+pub const COMPLETED_SUBOPERATIONS: Tag = Tag(0x0000, 0x1021);
+pub const FAILED_SUBOPERATIONS: Tag = Tag(0x0000, 0x1022);
+pub const WARNING_SUBOPERATIONS: Tag = Tag(0x0000, 0x1023);
+pub const MOVE_ORIGINATOR_AE_TITLE: Tag = Tag(0x0000, 0x1030);
+pub const MOVE_ORIGINATOR_MESSAGE_ID: Tag = Tag(0x0000, 0x1031);
+
+/// Command Field values (PS3.7 E.1).
+pub mod command {
+    pub const C_STORE_RQ: u16 = 0x0001;
+    pub const C_GET_RQ: u16 = 0x0010;
+    pub const C_FIND_RQ: u16 = 0x0020;
+    pub const C_MOVE_RQ: u16 = 0x0021;
+    pub const C_ECHO_RQ: u16 = 0x0030;
+    pub const C_CANCEL_RQ: u16 = 0x0FFF;
+    pub const N_SET_RQ: u16 = 0x0120;
+    pub const N_CREATE_RQ: u16 = 0x0140;
+    /// Responses set the high bit of the request's command field.
+    pub const RESPONSE: u16 = 0x8000;
+}
+
+/// Status codes used by this crate (PS3.7 C and PS3.4 C.4).
+pub mod status {
+    pub const SUCCESS: u16 = 0x0000;
+    pub const PENDING: u16 = 0xFF00;
+    pub const CANCEL: u16 = 0xFE00;
+    pub const SUBOPERATIONS_FAILED: u16 = 0xB000;
+    pub const MOVE_DESTINATION_UNKNOWN: u16 = 0xA801;
+    pub const OUT_OF_RESOURCES: u16 = 0xA700;
+    pub const IDENTIFIER_DOES_NOT_MATCH_SOP_CLASS: u16 = 0xA900;
+    pub const UNABLE_TO_PROCESS: u16 = 0xC000;
+    pub const UNRECOGNIZED_OPERATION: u16 = 0x0211;
+    pub const SOP_CLASS_NOT_SUPPORTED: u16 = 0x0122;
+    pub const INVALID_ATTRIBUTE_VALUE: u16 = 0x0106;
+    pub const PROCESSING_FAILURE: u16 = 0x0110;
+    pub const DUPLICATE_SOP_INSTANCE: u16 = 0x0111;
+    pub const NO_SUCH_SOP_INSTANCE: u16 = 0x0112;
+}
+
+/// Command Data Set Type value meaning "no data set follows".
+const NO_DATA_SET: u16 = 0x0101;
+
+/// The association operations DIMSE exchanges need, for either side of an association.
+pub trait DimseChannel {
+    fn send_pdu(&mut self, pdu: &Pdu) -> Result<()>;
+    fn receive_pdu(&mut self) -> Result<Pdu>;
+    /// Send an encoded data set as P-DATA fragments no larger than the peer accepts.
+    fn send_data(&mut self, pc_id: u8, data: &[u8]) -> Result<()>;
+    fn contexts(&self) -> &[PresentationContextResult];
+    /// Tracer observing this channel, if any (see [`crate::dimse_trace::TracedChannel`]).
+    fn tracer(&self) -> Option<&DimseTracer> {
+        None
+    }
+}
+
+impl DimseChannel for ServerAssociation {
+    fn send_pdu(&mut self, pdu: &Pdu) -> Result<()> {
+        Ok(self.send(pdu)?)
+    }
+
+    fn receive_pdu(&mut self) -> Result<Pdu> {
+        Ok(self.receive()?)
+    }
+
+    fn send_data(&mut self, pc_id: u8, data: &[u8]) -> Result<()> {
+        let mut writer = self.send_pdata(pc_id);
+        writer.write_all(data)?;
+        writer.finish()?;
+        Ok(())
+    }
+
+    fn contexts(&self) -> &[PresentationContextResult] {
+        self.presentation_contexts()
+    }
+}
+
+impl DimseChannel for ClientAssociation {
+    fn send_pdu(&mut self, pdu: &Pdu) -> Result<()> {
+        Ok(self.send(pdu)?)
+    }
+
+    fn receive_pdu(&mut self) -> Result<Pdu> {
+        Ok(self.receive()?)
+    }
+
+    fn send_data(&mut self, pc_id: u8, data: &[u8]) -> Result<()> {
+        let mut writer = self.send_pdata(pc_id);
+        writer.write_all(data)?;
+        writer.finish()?;
+        Ok(())
+    }
+
+    fn contexts(&self) -> &[PresentationContextResult] {
+        self.presentation_contexts()
+    }
+}
+
+/// Requestor-side association parameters shared by the SCU commands. The defaults are
+/// dicom-ul's: `THIS-SCU` calling `ANY-SCP`, 16 KiB PDUs and no timeouts.
+#[derive(Debug, Clone)]
+pub struct AssociationSettings {
+    pub calling_ae_title: String,
+    pub called_ae_title: String,
+    /// Largest PDU we accept, announced in the request.
+    pub max_pdu_length: u32,
+    /// Time allowed to connect and negotiate the association.
+    pub connect_timeout: Option<Duration>,
+    /// Socket read and write timeout once associated.
+    pub read_timeout: Option<Duration>,
+    /// Transfer syntaxes to propose for C-STORE, most wanted first.
+    pub transfer_syntaxes: TransferSyntaxPreference,
+}
+
+impl Default for AssociationSettings {
+    fn default() -> Self {
+        Self {
+            calling_ae_title: "THIS-SCU".to_string(),
+            called_ae_title: "ANY-SCP".to_string(),
+            max_pdu_length: DEFAULT_MAX_PDU,
+            connect_timeout: None,
+            read_timeout: None,
+            transfer_syntaxes: TransferSyntaxPreference::default(),
+        }
+    }
+}
+
+impl AssociationSettings {
+    /// Fail early on AE titles and PDU sizes the peer would reject.
+    pub fn validate(&self) -> Result<()> {
+        for (what, title) in [
+            ("Calling", &self.calling_ae_title),
+            ("Called", &self.called_ae_title),
+        ] {
+            let title = title.trim();
+            if title.is_empty() || title.len() > 16 || title.contains('\\') {
+                bail!(
+                    "{} AE title '{}' must be 1-16 characters without '\\'",
+                    what,
+                    title
+                );
+            }
+        }
+        if !(MINIMUM_PDU_SIZE..=MAXIMUM_PDU_SIZE).contains(&self.max_pdu_length) {
+            bail!(
+                "Maximum PDU length must be between {} and {} bytes",
+                MINIMUM_PDU_SIZE,
+                MAXIMUM_PDU_SIZE
+            );
+        }
+        Ok(())
+    }
+
+    /// Associate with `addr`, proposing `contexts` (abstract syntax and transfer syntaxes,
+    /// numbered 1, 3, 5...).
+    pub fn establish(
+        &self,
+        addr: &str,
+        contexts: &[(String, Vec<String>)],
+    ) -> Result<ClientAssociation> {
+        self.validate()?;
+        let mut options = ClientAssociationOptions::new()
+            .calling_ae_title(self.calling_ae_title.clone())
+            .called_ae_title(self.called_ae_title.clone())
+            .max_pdu_length(self.max_pdu_length);
+        for (abstract_syntax, transfer_syntaxes) in contexts {
+            options = options
+                .with_presentation_context(abstract_syntax.clone(), transfer_syntaxes.clone());
+        }
+        if let Some(timeout) = self.read_timeout {
+            options = options.read_timeout(timeout).write_timeout(timeout);
+        }
+        let Some(limit) = self.connect_timeout else {
+            return options
+                .establish(addr)
+                .with_context(|| format!("Failed to establish association with {}", addr));
+        };
+        // dicom-ul connects without a timeout; negotiate on a helper thread and stop waiting
+        // for it at the limit. A late association is dropped, which releases it.
+        let (sender, receiver) = mpsc::channel();
+        let target = addr.to_string();
+        thread::spawn(move || {
+            let _ = sender.send(options.establish(target.as_str()));
+        });
+        match receiver.recv_timeout(limit) {
+            Ok(result) => {
+                result.with_context(|| format!("Failed to establish association with {}", addr))
+            }
+            Err(_) => bail!(
+                "Timed out after {:?} establishing association with {}",
+                limit,
+                addr
+            ),
+        }
+    }
+
+    /// TCP connection to `addr` within the connect timeout, trying each resolved address.
+    fn connect(&self, addr: &str) -> Result<TcpStream> {
+        let Some(limit) = self.connect_timeout else {
+            return TcpStream::connect(addr)
+                .with_context(|| format!("Failed to connect to {}", addr));
+        };
+        let mut last_error = None;
+        for socket_addr in addr
+            .to_socket_addrs()
+            .with_context(|| format!("Failed to resolve {}", addr))?
+        {
+            match TcpStream::connect_timeout(&socket_addr, limit) {
+                Ok(stream) => return Ok(stream),
+                Err(err) => last_error = Some(err),
+            }
+        }
+        match last_error {
+            Some(err) => Err(err).with_context(|| format!("Failed to connect to {}", addr)),
+            None => bail!("{} resolved to no address", addr),
+        }
+    }
+}
+
+/// Application context of every DICOM association (PS3.7 A.2.1).
+const APPLICATION_CONTEXT: &str = "1.2.840.10008.3.1.1.1";
+/// SCP/SCU Role Selection sub-item type (PS3.7 D.3.3.4).
+const ROLE_SELECTION_ITEM: u8 = 0x54;
+/// P-DATA-TF bytes in each fragment besides the data: PDV length, context id and header.
+const PDV_OVERHEAD: u32 = 6;
+
+/// A-ASSOCIATE-RJ from the peer: a DICOM node is listening but refused the association.
+#[derive(Debug, thiserror::Error)]
+#[error("Association rejected: {0}")]
+pub struct AssociationRejected(pub String);
+
+/// Implementation Class UID and Version Name an acceptor announced (PS3.7 D.3.3.2-3).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PeerImplementation {
+    pub class_uid: Option<String>,
+    pub version_name: Option<String>,
+}
+
+/// Requestor side of an association negotiated without dicom-ul's client, which cannot
+/// propose SCP/SCU Role Selection. C-GET needs it: the peer may only send C-STOREs back on
+/// the same association for SOP classes where the requestor has taken the SCP role. Unlike
+/// dicom-ul's client, it also keeps the acceptor's implementation identification.
+pub struct RoleSelectingAssociation {
+    stream: TcpStream,
+    contexts: Vec<PresentationContextResult>,
+    acceptor_max_pdu_length: u32,
+    peer: PeerImplementation,
+}
+
+impl RoleSelectingAssociation {
+    /// Propose `contexts` (abstract syntax and transfer syntaxes, numbered 1, 3, 5...),
+    /// asking for the SCP role for each abstract syntax in `scp_role`.
+    pub fn establish(
+        addr: &str,
+        settings: &AssociationSettings,
+        contexts: &[(String, Vec<String>)],
+        scp_role: &[String],
+    ) -> Result<Self> {
+        settings.validate()?;
+        let mut user_variables = vec![
+            UserVariableItem::MaxLength(settings.max_pdu_length),
+            UserVariableItem::ImplementationClassUID(IMPLEMENTATION_CLASS_UID.to_string()),
+            UserVariableItem::ImplementationVersionName(IMPLEMENTATION_VERSION_NAME.to_string()),
+        ];
+        for sop_class in scp_role {
+            let mut item = Vec::with_capacity(sop_class.len() + 4);
+            item.extend_from_slice(&(sop_class.len() as u16).to_be_bytes());
+            item.extend_from_slice(sop_class.as_bytes());
+            // SCU role not requested, SCP role requested.
+            item.extend_from_slice(&[0, 1]);
+            user_variables.push(UserVariableItem::Unknown(ROLE_SELECTION_ITEM, item));
+        }
+        let request = Pdu::AssociationRQ(AssociationRQ {
+            protocol_version: 1,
+            calling_ae_title: settings.calling_ae_title.clone(),
+            called_ae_title: settings.called_ae_title.clone(),
+            application_context_name: APPLICATION_CONTEXT.to_string(),
+            presentation_contexts: contexts
+                .iter()
+                .enumerate()
+                .map(
+                    |(i, (abstract_syntax, transfer_syntaxes))| PresentationContextProposed {
+                        id: (2 * i + 1) as u8,
+                        abstract_syntax: abstract_syntax.clone(),
+                        transfer_syntaxes: transfer_syntaxes.clone(),
+                    },
+                )
+                .collect(),
+            user_variables,
+        });
+
+        let stream = settings.connect(addr)?;
+        // The connect timeout also bounds the negotiation.
+        stream.set_read_timeout(settings.connect_timeout.or(settings.read_timeout))?;
+        stream.set_write_timeout(settings.read_timeout)?;
+        let mut association = Self {
+            stream,
+            contexts: Vec::new(),
+            acceptor_max_pdu_length: DEFAULT_MAX_PDU,
+            peer: PeerImplementation::default(),
+        };
+        association.send_pdu(&request)?;
+        match association.receive_pdu()? {
+            Pdu::AssociationAC(ac) => {
+                let max = ac.user_variables.iter().find_map(|item| match item {
+                    UserVariableItem::MaxLength(len) => Some(*len),
+                    _ => None,
+                });
+                // 0 means unlimited; fragments are still kept to a size peers can read. A
+                // length too small for the PDV header still gets one data byte per fragment.
+                association.acceptor_max_pdu_length = match max {
+                    Some(0) => MAXIMUM_PDU_SIZE,
+                    Some(len) => len.clamp(PDV_OVERHEAD + 1, MAXIMUM_PDU_SIZE),
+                    None => DEFAULT_MAX_PDU,
+                };
+                for item in ac.user_variables {
+                    match item {
+                        UserVariableItem::ImplementationClassUID(uid) => {
+                            association.peer.class_uid = Some(uid)
+                        }
+                        UserVariableItem::ImplementationVersionName(name) => {
+                            association.peer.version_name = Some(name)
+                        }
+                        _ => {}
+                    }
+                }
+                association.contexts = ac.presentation_contexts;
+                association.stream.set_read_timeout(settings.read_timeout)?;
+                Ok(association)
+            }
+            Pdu::AssociationRJ(rj) => Err(AssociationRejected(format!("{:?}", rj)).into()),
+            other => bail!("Unexpected PDU {:?} in answer to A-ASSOCIATE-RQ", other),
+        }
+    }
+
+    pub fn peer_implementation(&self) -> &PeerImplementation {
+        &self.peer
+    }
+
+    /// Release the association, waiting for the peer's confirmation.
+    pub fn release(mut self) -> Result<()> {
+        self.send_pdu(&Pdu::ReleaseRQ)?;
+        match self.receive_pdu()? {
+            Pdu::ReleaseRP => Ok(()),
+            other => bail!("Unexpected PDU {:?} in answer to A-RELEASE-RQ", other),
+        }
+    }
+}
+
+impl DimseChannel for RoleSelectingAssociation {
+    fn send_pdu(&mut self, pdu: &Pdu) -> Result<()> {
+        let mut bytes = Vec::new();
+        write_pdu(&mut bytes, pdu)?;
+        self.stream.write_all(&bytes)?;
+        Ok(())
+    }
+
+    fn receive_pdu(&mut self) -> Result<Pdu> {
+        Ok(read_pdu(&mut self.stream, MAXIMUM_PDU_SIZE, false)?)
+    }
+
+    fn send_data(&mut self, pc_id: u8, data: &[u8]) -> Result<()> {
+        let chunk = self.acceptor_max_pdu_length.saturating_sub(PDV_OVERHEAD) as usize;
+        let mut fragments = data.chunks(chunk.max(1)).collect::<Vec<_>>();
+        // An empty data set still needs its last fragment.
+        if fragments.is_empty() {
+            fragments.push(&[]);
+        }
+        let last = fragments.len() - 1;
+        for (index, fragment) in fragments.into_iter().enumerate() {
+            self.send_pdu(&Pdu::PData {
+                data: vec![PDataValue {
+                    presentation_context_id: pc_id,
+                    value_type: PDataValueType::Data,
+                    is_last: index == last,
+                    data: fragment.to_vec(),
+                }],
+            })?;
+        }
+        Ok(())
+    }
+
+    fn contexts(&self) -> &[PresentationContextResult] {
+        &self.contexts
+    }
+}
+
+/// A complete DIMSE message: command set plus the raw data set, if one was sent.
+#[derive(Debug)]
+pub struct DimseMessage {
+    pub pc_id: u8,
+    pub command: InMemDicomObject,
+    pub data: Option<Vec<u8>>,
+}
+
+impl DimseMessage {
+    pub fn command_field(&self) -> u16 {
+        self.command.element_u32(COMMAND_FIELD).unwrap_or(0) as u16
+    }
+
+    pub fn message_id(&self) -> u16 {
+        self.command.element_u32(MESSAGE_ID).unwrap_or(0) as u16
+    }
+
+    pub fn status(&self) -> Option<u16> {
+        self.command.element_u32(STATUS).map(|s| s as u16)
+    }
+
+    /// Decode the data set with the transfer syntax negotiated for its presentation context.
+    pub fn dataset(&self, channel: &dyn DimseChannel) -> Result<Option<InMemDicomObject>> {
+        let Some(data) = &self.data else {
+            return Ok(None);
+        };
+        let ts = negotiated_ts(channel, self.pc_id)?;
+        let obj = InMemDicomObject::read_dataset_with_ts(data.as_slice(), ts)
+            .context("Failed to decode data set")?;
+        Ok(Some(obj))
+    }
+}
+
+/// Look up the transfer syntax accepted for a presentation context.
+pub fn negotiated_ts(channel: &dyn DimseChannel, pc_id: u8) -> Result<&'static TransferSyntax> {
+    let uid = &channel
+        .contexts()
+        .iter()
+        .find(|pc| pc.id == pc_id)
+        .with_context(|| format!("Unknown presentation context {}", pc_id))?
+        .transfer_syntax;
+    transfer_syntax(uid)
+}
+
+pub fn transfer_syntax(uid: &str) -> Result<&'static TransferSyntax> {
+    TransferSyntaxRegistry
+        .get(uid.trim_end_matches(['\0', ' ']))
+        .with_context(|| format!("Transfer syntax {} not supported", uid))
+}
+
+/// Encode a command set in Implicit VR Little Endian, prefixed by its group length.
+pub fn encode_command(mut command: InMemDicomObject) -> Result<Vec<u8>> {
+    let ts = transfer_syntax(IMPLICIT_VR_LITTLE_ENDIAN)?;
+    command.remove_element(COMMAND_GROUP_LENGTH);
+    let mut body = Vec::new();
+    command
+        .write_dataset_with_ts(&mut body, ts)
+        .context("Failed to encode command set")?;
+    command.put(DataElement::new(
+        COMMAND_GROUP_LENGTH,
+        VR::UL,
+        PrimitiveValue::from(body.len() as u32),
+    ));
+    let mut bytes = Vec::new();
+    command
+        .write_dataset_with_ts(&mut bytes, ts)
+        .context("Failed to encode command set")?;
+    Ok(bytes)
+}
+
+/// Start a command set with the elements every message carries.
+pub fn command_set(sop_class: &str, field: u16, has_data: bool) -> InMemDicomObject {
+    let mut cmd = InMemDicomObject::new_empty();
+    cmd.put(DataElement::new(
+        AFFECTED_SOP_CLASS_UID,
+        VR::UI,
+        PrimitiveValue::from(sop_class),
+    ));
+    cmd.put(us(COMMAND_FIELD, field));
+    cmd.put(us(
+        COMMAND_DATA_SET_TYPE,
+        if has_data { 0x0000 } else { NO_DATA_SET },
+    ));
+    cmd
+}
+
+pub fn us(tag: Tag, value: u16) -> DataElement<InMemDicomObject> {
+    DataElement::new(tag, VR::US, PrimitiveValue::from(value))
+}
+
+/// Send one message; the data set (already encoded in the negotiated transfer syntax) is
+/// fragmented to the peer's maximum PDU length.
+pub fn send_message(
+    channel: &mut dyn DimseChannel,
+    pc_id: u8,
+    command: InMemDicomObject,
+    data: Option<&[u8]>,
+) -> Result<()> {
+    trace_message(channel, Direction::Sent, pc_id, &command, data);
+    channel.send_pdu(&Pdu::PData {
+        data: vec![PDataValue {
+            presentation_context_id: pc_id,
+            value_type: PDataValueType::Command,
+            is_last: true,
+            data: encode_command(command)?,
+        }],
+    })?;
+    if let Some(data) = data {
+        channel
+            .send_data(pc_id, data)
+            .context("Failed to send data set")?;
+    }
+    Ok(())
+}
+
+/// Read the next complete message. A release request is answered and yields `None`.
+pub fn read_message(channel: &mut dyn DimseChannel) -> Result<Option<DimseMessage>> {
+    let ts = transfer_syntax(IMPLICIT_VR_LITTLE_ENDIAN)?;
+    let mut command_bytes = Vec::new();
+    let mut command: Option<InMemDicomObject> = None;
+    let mut data = Vec::new();
+    let mut pc_id = 0;
+
+    loop {
+        match channel.receive_pdu()? {
+            Pdu::PData { data: values } => {
+                for value in values {
+                    pc_id = value.presentation_context_id;
+                    match value.value_type {
+                        PDataValueType::Command => {
+                            command_bytes.extend_from_slice(&value.data);
+                            if value.is_last {
+                                command = Some(
+                                    InMemDicomObject::read_dataset_with_ts(
+                                        command_bytes.as_slice(),
+                                        ts,
+                                    )
+                                    .context("Failed to decode command set")?,
+                                );
+                            }
+                        }
+                        PDataValueType::Data => {
+                            data.extend_from_slice(&value.data);
+                            if value.is_last {
+                                let command = command
+                                    .take()
+                                    .context("Data set received before its command")?;
+                                trace_message(
+                                    channel,
+                                    Direction::Received,
+                                    pc_id,
+                                    &command,
+                                    Some(&data),
+                                );
+                                return Ok(Some(DimseMessage {
+                                    pc_id,
+                                    command,
+                                    data: Some(data),
+                                }));
+                            }
+                        }
+                    }
+                }
+                if let Some(cmd) = &command {
+                    if cmd.element_u32(COMMAND_DATA_SET_TYPE) == Some(NO_DATA_SET as u32) {
+                        trace_message(channel, Direction::Received, pc_id, cmd, None);
+                        return Ok(Some(DimseMessage {
+                            pc_id,
+                            command: command.take().expect("checked above"),
+                            data: None,
+                        }));
+                    }
+                }
+            }
+            Pdu::ReleaseRQ => {
+                channel.send_pdu(&Pdu::ReleaseRP)?;
+                return Ok(None);
+            }
+            Pdu::AbortRQ { source } => bail!("Association aborted by peer ({:?})", source),
+            other => bail!("Unexpected PDU {:?}", other),
+        }
+    }
+}
+
+fn trace_message(
+    channel: &dyn DimseChannel,
+    direction: Direction,
+    pc_id: u8,
+    command: &InMemDicomObject,
+    data: Option<&[u8]>,
+) {
+    let Some(tracer) = channel.tracer() else {
+        return;
+    };
+    let dataset = data.map(|bytes| {
+        negotiated_ts(channel, pc_id)
+            .and_then(|ts| Ok(InMemDicomObject::read_dataset_with_ts(bytes, ts)?))
+            .map_err(|_| bytes.len())
+    });
+    tracer.message(
+        direction,
+        pc_id,
+        command,
+        dataset.as_ref().and_then(|d| d.as_ref().ok()),
+    );
+    if let Some(Err(len)) = dataset {
+        tracer.note(&format!(
+            "     data set of {} bytes could not be decoded",
+            len
+        ));
+    }
+}
+
+/// Response command set answering `request`. N-SET requests name their SOP class as the
+/// Requested rather than the Affected SOP Class UID.
+pub fn response_to(request: &DimseMessage, status: u16, has_data: bool) -> InMemDicomObject {
+    let sop_class = request
+        .command
+        .element_str(AFFECTED_SOP_CLASS_UID)
+        .or_else(|| request.command.element_str(REQUESTED_SOP_CLASS_UID))
+        .unwrap_or_default();
+    let mut cmd = command_set(
+        sop_class.trim_end_matches('\0'),
+        request.command_field() | command::RESPONSE,
+        has_data,
+    );
+    cmd.put(us(MESSAGE_ID_BEING_RESPONDED_TO, request.message_id()));
+    cmd.put(us(STATUS, status));
+    cmd
+}
+
+/// Move originator details added to C-STORE sub-operations of a C-MOVE.
+pub struct MoveOriginator<'a> {
+    pub ae_title: &'a str,
+    pub message_id: u16,
+}
+
+/// Send one instance as a C-STORE-RQ and wait for the response status.
+///
+/// `on_other` sees any other message that arrives while waiting (e.g. a C-CANCEL-RQ on the
+/// association a C-GET is running on).
+#[allow(clippy::too_many_arguments)]
+pub fn store_instance(
+    channel: &mut dyn DimseChannel,
+    pc_id: u8,
+    message_id: u16,
+    sop_class: &str,
+    sop_instance: &str,
+    data: &[u8],
+    originator: Option<MoveOriginator<'_>>,
+    on_other: &mut dyn FnMut(&DimseMessage),
+) -> Result<u16> {
+    let mut cmd = command_set(sop_class, command::C_STORE_RQ, true);
+    cmd.put(us(MESSAGE_ID, message_id));
+    cmd.put(us(PRIORITY, 0));
+    cmd.put(DataElement::new(
+        AFFECTED_SOP_INSTANCE_UID,
+        VR::UI,
+        PrimitiveValue::from(sop_instance),
+    ));
+    if let Some(originator) = originator {
+        cmd.put(DataElement::new(
+            MOVE_ORIGINATOR_AE_TITLE,
+            VR::AE,
+            PrimitiveValue::from(originator.ae_title),
+        ));
+        cmd.put(us(MOVE_ORIGINATOR_MESSAGE_ID, originator.message_id));
+    }
+    send_message(channel, pc_id, cmd, Some(data))?;
+
+    loop {
+        let response = read_message(channel)?.context("Peer released during C-STORE")?;
+        if response.command_field() == command::C_STORE_RQ | command::RESPONSE {
+            return response.status().context("C-STORE-RSP without status");
+        }
+        on_other(&response);
+    }
+}
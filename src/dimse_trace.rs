@@ -0,0 +1,320 @@
+//
+// dimse_trace.rs
+// Dicom-Tools-rs
+//
+// Wire-level tracing for network verbs: PDUs, negotiated presentation contexts, and DIMSE message summaries.
+//
+// Thales Matheus Mendonça Santos - November 2025
+
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use anyhow::{Context, Result};
+use dicom::core::header::Header;
+use dicom::core::value::Value;
+use dicom::core::{Tag, VR};
+use dicom::dictionary_std::StandardDataDictionary;
+use dicom::object::mem::InMemElement;
+use dicom::object::InMemDicomObject;
+use dicom_ul::pdu::{AssociationRQ, Pdu, PresentationContextResult};
+
+use crate::anonymize;
+use crate::dimse::DimseChannel;
+
+/// Longest value printed for an element before it is cut.
+const MAX_VALUE_CHARS: usize = 64;
+
+#[derive(Debug, Clone, Copy)]
+pub enum Direction {
+    Sent,
+    Received,
+}
+
+impl Direction {
+    fn arrow(self) -> &'static str {
+        match self {
+            Direction::Sent => ">>",
+            Direction::Received => "<<",
+        }
+    }
+}
+
+/// Appends a human-readable trace of DIMSE traffic to a file. Write failures are ignored so
+/// that tracing never breaks the exchange it observes.
+#[derive(Debug)]
+pub struct DimseTracer {
+    out: Mutex<BufWriter<File>>,
+    redact_phi: bool,
+    start: Instant,
+}
+
+impl DimseTracer {
+    /// Trace to `path`; with `redact_phi`, patient-identifying values are masked.
+    pub fn create(path: &Path, redact_phi: bool) -> Result<Arc<Self>> {
+        let file = File::create(path)
+            .with_context(|| format!("Failed to create trace file {:?}", path))?;
+        Ok(Arc::new(Self {
+            out: Mutex::new(BufWriter::new(file)),
+            redact_phi,
+            start: Instant::now(),
+        }))
+    }
+
+    pub fn note(&self, text: &str) {
+        let elapsed = self.start.elapsed().as_secs_f64();
+        if let Ok(mut out) = self.out.lock() {
+            for line in text.lines() {
+                let _ = writeln!(out, "[{:>9.3}s] {}", elapsed, line);
+            }
+            let _ = out.flush();
+        }
+    }
+
+    /// Full A-ASSOCIATE-RQ as received by an acceptor.
+    pub fn association_request(&self, rq: &AssociationRQ) {
+        let mut text = format!(
+            "<< A-ASSOCIATE-RQ calling={} called={} context={}",
+            rq.calling_ae_title.trim(),
+            rq.called_ae_title.trim(),
+            rq.application_context_name
+        );
+        for pc in &rq.presentation_contexts {
+            let _ = write!(
+                text,
+                "\n     pc {} {} [{}]",
+                pc.id,
+                pc.abstract_syntax.trim_end_matches('\0'),
+                pc.transfer_syntaxes.join(", ")
+            );
+        }
+        for item in &rq.user_variables {
+            let _ = write!(text, "\n     user {:?}", item);
+        }
+        self.note(&text);
+    }
+
+    /// Contexts proposed by a requestor (in proposal order, ids 1, 3, 5...).
+    pub fn proposed_contexts(&self, peer: &str, proposals: &[(String, Vec<String>)]) {
+        let mut text = format!(">> A-ASSOCIATE-RQ to {}", peer);
+        for (i, (abstract_syntax, syntaxes)) in proposals.iter().enumerate() {
+            let _ = write!(
+                text,
+                "\n     pc {} {} [{}]",
+                2 * i + 1,
+                abstract_syntax,
+                syntaxes.join(", ")
+            );
+        }
+        self.note(&text);
+    }
+
+    /// Outcome of negotiation, from either side.
+    pub fn negotiated_contexts(&self, results: &[PresentationContextResult]) {
+        let mut text = String::from("== presentation contexts");
+        for pc in results {
+            let _ = write!(
+                text,
+                "\n     pc {} {:?} {}",
+                pc.id,
+                pc.reason,
+                pc.transfer_syntax.trim_end_matches('\0')
+            );
+        }
+        self.note(&text);
+    }
+
+    pub fn pdu(&self, direction: Direction, pdu: &Pdu) {
+        let arrow = direction.arrow();
+        let text = match pdu {
+            Pdu::PData { data } => {
+                let mut text = format!("{} P-DATA-TF", arrow);
+                for pdv in data {
+                    let _ = write!(
+                        text,
+                        "\n     pdv pc={} {:?} last={} {} bytes",
+                        pdv.presentation_context_id,
+                        pdv.value_type,
+                        pdv.is_last,
+                        pdv.data.len()
+                    );
+                }
+                text
+            }
+            Pdu::AssociationRQ(rq) => return self.association_request(rq),
+            other => format!("{} {:?}", arrow, other),
+        };
+        self.note(&text);
+    }
+
+    /// Decoded command set and a one-line-per-element summary of the data set.
+    pub fn message(
+        &self,
+        direction: Direction,
+        pc_id: u8,
+        command: &InMemDicomObject,
+        dataset: Option<&InMemDicomObject>,
+    ) {
+        let mut text = format!("{} DIMSE message on pc {}", direction.arrow(), pc_id);
+        self.append_elements(&mut text, "command", command);
+        if let Some(dataset) = dataset {
+            self.append_elements(&mut text, "data", dataset);
+        }
+        self.note(&text);
+    }
+
+    fn append_elements(&self, text: &mut String, label: &str, obj: &InMemDicomObject) {
+        for element in obj.iter() {
+            let _ = write!(
+                text,
+                "\n     {} {} {:?} {} = {}",
+                label,
+                tag_string(element.tag()),
+                element.vr(),
+                keyword(element.tag()),
+                self.value_summary(element)
+            );
+        }
+    }
+
+    fn value_summary(&self, element: &InMemElement) -> String {
+        let (tag, vr) = (element.tag(), element.vr());
+        match element.value() {
+            Value::Sequence(seq) => format!("<{} items>", seq.items().len()),
+            Value::PixelSequence(seq) => format!("<{} fragments>", seq.fragments().len()),
+            Value::Primitive(_) if self.redact_phi && is_identifying(tag, vr) => {
+                "<redacted>".to_string()
+            }
+            Value::Primitive(value) => match vr {
+                VR::OB | VR::OW | VR::OF | VR::OD | VR::OL | VR::OV | VR::UN => {
+                    format!("<{} bytes>", value.calculate_byte_len())
+                }
+                _ => {
+                    let text = value.to_str();
+                    let text = text.trim_end_matches(['\0', ' ']);
+                    if text.chars().count() > MAX_VALUE_CHARS {
+                        let cut: String = text.chars().take(MAX_VALUE_CHARS).collect();
+                        format!("{}...", cut)
+                    } else {
+                        text.to_string()
+                    }
+                }
+            },
+        }
+    }
+}
+
+/// Patient module attributes plus everything the anonymizer would scrub.
+fn is_identifying(tag: Tag, vr: VR) -> bool {
+    tag.group() == 0x0010 || anonymize::is_identifying(tag, vr)
+}
+
+fn tag_string(tag: Tag) -> String {
+    format!("({:04X},{:04X})", tag.group(), tag.element())
+}
+
+fn keyword(tag: Tag) -> &'static str {
+    use dicom::core::dictionary::{DataDictionary, DataDictionaryEntry};
+    StandardDataDictionary
+        .by_tag(tag)
+        .map(|entry| entry.alias())
+        .unwrap_or("?")
+}
+
+/// A channel whose traffic is mirrored to a tracer when one is configured.
+pub struct TracedChannel<C> {
+    inner: C,
+    tracer: Option<Arc<DimseTracer>>,
+}
+
+impl<C: DimseChannel> TracedChannel<C> {
+    pub fn new(inner: C, tracer: Option<Arc<DimseTracer>>) -> Self {
+        if let Some(tracer) = &tracer {
+            tracer.negotiated_contexts(inner.contexts());
+        }
+        Self { inner, tracer }
+    }
+
+    pub fn get_ref(&self) -> &C {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut C {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> C {
+        if let Some(tracer) = &self.tracer {
+            tracer.note("-- association closing");
+        }
+        self.inner
+    }
+}
+
+impl<C: DimseChannel> DimseChannel for TracedChannel<C> {
+    fn send_pdu(&mut self, pdu: &Pdu) -> Result<()> {
+        if let Some(tracer) = &self.tracer {
+            tracer.pdu(Direction::Sent, pdu);
+        }
+        self.inner.send_pdu(pdu)
+    }
+
+    fn receive_pdu(&mut self) -> Result<Pdu> {
+        let pdu = self.inner.receive_pdu()?;
+        if let Some(tracer) = &self.tracer {
+            tracer.pdu(Direction::Received, &pdu);
+        }
+        Ok(pdu)
+    }
+
+    fn send_data(&mut self, pc_id: u8, data: &[u8]) -> Result<()> {
+        self.inner.send_data(pc_id, data)
+    }
+
+    fn contexts(&self) -> &[PresentationContextResult] {
+        self.inner.contexts()
+    }
+
+    fn tracer(&self) -> Option<&DimseTracer> {
+        self.tracer.as_deref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dicom::core::{DataElement, PrimitiveValue};
+
+    #[test]
+    fn redacted_trace_masks_patient_values_only() {
+        let dir = tempfile::tempdir().expect("tmpdir");
+        let path = dir.path().join("trace.log");
+        let tracer = DimseTracer::create(&path, true).expect("tracer");
+
+        let mut dataset = InMemDicomObject::new_empty();
+        dataset.put(DataElement::new(
+            Tag(0x0010, 0x0010),
+            VR::PN,
+            PrimitiveValue::from("DOE^JANE"),
+        ));
+        dataset.put(DataElement::new(
+            Tag(0x0008, 0x0060),
+            VR::CS,
+            PrimitiveValue::from("CT"),
+        ));
+        tracer.message(
+            Direction::Sent,
+            1,
+            &InMemDicomObject::new_empty(),
+            Some(&dataset),
+        );
+
+        let log = std::fs::read_to_string(&path).expect("read trace");
+        assert!(log.contains("(0010,0010) PN PatientName = <redacted>"));
+        assert!(log.contains("(0008,0060) CS Modality = CT"));
+        assert!(!log.contains("DOE"));
+    }
+}
@@ -16,6 +16,8 @@ use dicom::core::{PrimitiveValue, Tag};
 use dicom::dictionary_std::StandardDataDictionary;
 use dicom::object::{open_file, InMemDicomObject};
 
+use crate::codes::Code;
+
 /// Print a textual dump of all elements in the file, resolving names via the standard dictionary.
 pub fn dump_file(path: &Path, max_depth: usize, max_value_len: usize) -> Result<()> {
     let output = dump_to_string(path, max_depth, max_value_len)?;
@@ -72,7 +74,15 @@ fn dump_object(
                 );
                 if depth < max_depth {
                     for (idx, item) in seq.items().iter().enumerate() {
-                        let _ = writeln!(out, "{}  Item {}", indent, idx + 1);
+                        // Coded items are summarized as their concept, meaning included.
+                        match Code::from_item(item) {
+                            Some(code) => {
+                                let _ = writeln!(out, "{}  Item {} = {}", indent, idx + 1, code);
+                            }
+                            None => {
+                                let _ = writeln!(out, "{}  Item {}", indent, idx + 1);
+                            }
+                        }
                         dump_object(item, depth + 2, max_depth, max_value_len, out);
                     }
                 }
@@ -118,7 +128,7 @@ fn format_tag(tag: Tag) -> String {
 }
 
 fn tag_name(tag: Tag) -> String {
-    StandardDataDictionary::default()
+    StandardDataDictionary
         .by_tag(tag)
         .map(|e| e.alias.to_string())
         .unwrap_or_else(|| "UnknownTag".to_string())
@@ -0,0 +1,327 @@
+//
+// echo_scan.rs
+// Dicom-Tools-rs
+//
+// Verification sweep (`echo-scan`): C-ECHO against every host and port of a CIDR range or
+// host list, a bounded number at a time, reporting which endpoints answered and the
+// implementation class UID and version name they announced.
+//
+// Thales Matheus Mendonça Santos - November 2025
+
+use std::net::{IpAddr, Ipv4Addr};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Context, Result};
+use serde::Serialize;
+
+use crate::dimse::{self, command, AssociationRejected, AssociationSettings, MESSAGE_ID};
+use crate::dimse::{DimseChannel, RoleSelectingAssociation};
+use crate::scu::{accepted_context, default_proposal, VERIFICATION};
+
+/// Connect and read timeout of each probe unless the association settings give one; a sweep
+/// mostly meets silent addresses, so the OS default of minutes is not an option.
+pub const DEFAULT_PROBE_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Largest sweep accepted from one CIDR block (a /16).
+const MAX_RANGE_HOSTS: u64 = 1 << 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EchoOutcome {
+    /// A C-ECHO-RSP came back.
+    Answered,
+    /// A DICOM node refused the association or the Verification SOP class.
+    Rejected,
+    /// Nothing listening, or not speaking DICOM.
+    Unreachable,
+}
+
+impl EchoOutcome {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            EchoOutcome::Answered => "answered",
+            EchoOutcome::Rejected => "rejected",
+            EchoOutcome::Unreachable => "unreachable",
+        }
+    }
+}
+
+/// Result of probing one endpoint.
+#[derive(Debug, Clone, Serialize)]
+pub struct EchoResult {
+    pub addr: String,
+    pub outcome: EchoOutcome,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub implementation_class_uid: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub implementation_version_name: Option<String>,
+    pub elapsed_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Hosts of a comma-separated list of host names, IP addresses and IPv4 CIDR blocks. Blocks
+/// larger than /31 leave out their network and broadcast addresses.
+pub fn parse_targets(spec: &str) -> Result<Vec<String>> {
+    let mut hosts = Vec::new();
+    for item in spec.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+        let Some((network, prefix)) = item.split_once('/') else {
+            hosts.push(item.to_string());
+            continue;
+        };
+        let network: Ipv4Addr = network
+            .parse()
+            .with_context(|| format!("{} is not an IPv4 CIDR block", item))?;
+        let prefix: u32 = prefix
+            .parse()
+            .ok()
+            .filter(|p| *p <= 32)
+            .with_context(|| format!("Invalid prefix length in {}", item))?;
+        let size = 1u64 << (32 - prefix);
+        if size > MAX_RANGE_HOSTS {
+            bail!(
+                "{} spans {} addresses; split it into blocks of at most /16",
+                item,
+                size
+            );
+        }
+        let mask = if prefix == 0 {
+            0
+        } else {
+            u32::MAX << (32 - prefix)
+        };
+        let first = u32::from(network) & mask;
+        let (start, end) = if size > 2 {
+            (first as u64 + 1, first as u64 + size - 1)
+        } else {
+            (first as u64, first as u64 + size)
+        };
+        hosts.extend((start..end).map(|ip| Ipv4Addr::from(ip as u32).to_string()));
+    }
+    if hosts.is_empty() {
+        bail!("No targets given");
+    }
+    Ok(hosts)
+}
+
+/// Ports of a comma-separated list of ports and `first-last` ranges.
+pub fn parse_ports(spec: &str) -> Result<Vec<u16>> {
+    let mut ports = Vec::new();
+    for item in spec.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+        let port = |text: &str| {
+            text.trim()
+                .parse::<u16>()
+                .ok()
+                .filter(|p| *p > 0)
+                .with_context(|| format!("Invalid port in {}", item))
+        };
+        match item.split_once('-') {
+            Some((first, last)) => {
+                let (first, last) = (port(first)?, port(last)?);
+                if first > last {
+                    bail!("Port range {} is reversed", item);
+                }
+                ports.extend(first..=last);
+            }
+            None => ports.push(port(item)?),
+        }
+    }
+    ports.sort_unstable();
+    ports.dedup();
+    if ports.is_empty() {
+        bail!("No ports given");
+    }
+    Ok(ports)
+}
+
+/// `host:port`, bracketing IPv6 addresses.
+fn endpoint(host: &str, port: u16) -> String {
+    match host.parse::<IpAddr>() {
+        Ok(IpAddr::V6(ip)) => format!("[{}]:{}", ip, port),
+        _ => format!("{}:{}", host, port),
+    }
+}
+
+/// C-ECHO `addr` over one association.
+pub fn probe(addr: &str, settings: &AssociationSettings) -> EchoResult {
+    let started = Instant::now();
+    let mut result = EchoResult {
+        addr: addr.to_string(),
+        outcome: EchoOutcome::Unreachable,
+        status: None,
+        implementation_class_uid: None,
+        implementation_version_name: None,
+        elapsed_ms: 0,
+        error: None,
+    };
+    let contexts = default_proposal(VERIFICATION);
+    match RoleSelectingAssociation::establish(addr, settings, &contexts, &[]) {
+        Ok(mut association) => {
+            let peer = association.peer_implementation().clone();
+            result.implementation_class_uid = peer.class_uid;
+            result.implementation_version_name = peer.version_name;
+            match echo(&mut association) {
+                Ok(status) => {
+                    result.outcome = EchoOutcome::Answered;
+                    result.status = Some(status);
+                    let _ = association.release();
+                }
+                Err(err) => {
+                    // Associated, so a DICOM node: it just would not verify.
+                    result.outcome = EchoOutcome::Rejected;
+                    result.error = Some(format!("{:#}", err));
+                }
+            }
+        }
+        Err(err) => {
+            if err.downcast_ref::<AssociationRejected>().is_some() {
+                result.outcome = EchoOutcome::Rejected;
+            }
+            result.error = Some(format!("{:#}", err));
+        }
+    }
+    result.elapsed_ms = started.elapsed().as_millis() as u64;
+    result
+}
+
+fn echo(channel: &mut dyn DimseChannel) -> Result<u16> {
+    let pc_id = accepted_context(channel).context("Verification not accepted")?;
+    let mut cmd = dimse::command_set(VERIFICATION, command::C_ECHO_RQ, false);
+    cmd.put(dimse::us(MESSAGE_ID, 1));
+    dimse::send_message(channel, pc_id, cmd, None).context("Failed to send C-ECHO-RQ")?;
+    let msg = dimse::read_message(channel)
+        .context("Failed to receive C-ECHO-RSP")?
+        .context("Association released before C-ECHO-RSP")?;
+    Ok(msg.status().unwrap_or(0))
+}
+
+/// Probe every host on every port, `concurrency` at a time; results follow the order of
+/// `hosts`, then `ports`.
+pub fn scan(
+    hosts: &[String],
+    ports: &[u16],
+    settings: &AssociationSettings,
+    concurrency: usize,
+) -> Vec<EchoResult> {
+    let mut settings = settings.clone();
+    settings.connect_timeout = settings.connect_timeout.or(Some(DEFAULT_PROBE_TIMEOUT));
+    settings.read_timeout = settings.read_timeout.or(Some(DEFAULT_PROBE_TIMEOUT));
+    let targets: Vec<String> = hosts
+        .iter()
+        .flat_map(|host| ports.iter().map(move |port| endpoint(host, *port)))
+        .collect();
+    let next = AtomicUsize::new(0);
+    let results = Mutex::new(vec![None; targets.len()]);
+    thread::scope(|scope| {
+        for _ in 0..concurrency.clamp(1, targets.len().max(1)) {
+            scope.spawn(|| loop {
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                let Some(addr) = targets.get(index) else {
+                    break;
+                };
+                let result = probe(addr, &settings);
+                if let Ok(mut results) = results.lock() {
+                    results[index] = Some(result);
+                }
+            });
+        }
+    });
+    results
+        .into_inner()
+        .unwrap_or_default()
+        .into_iter()
+        .flatten()
+        .collect()
+}
+
+/// Run a sweep and print the endpoints that answered or refused (every endpoint with `all`),
+/// or the results as JSON.
+pub fn print_scan(
+    hosts: &[String],
+    ports: &[u16],
+    settings: &AssociationSettings,
+    concurrency: usize,
+    json: bool,
+    all: bool,
+) -> Result<Vec<EchoResult>> {
+    let total = hosts.len() * ports.len();
+    eprintln!(
+        "Sending C-ECHO to {} endpoint(s) ({} host(s) x {} port(s)), {} at a time",
+        total,
+        hosts.len(),
+        ports.len(),
+        concurrency.max(1)
+    );
+    let results = scan(hosts, ports, settings, concurrency);
+    let shown: Vec<&EchoResult> = results
+        .iter()
+        .filter(|r| all || r.outcome != EchoOutcome::Unreachable)
+        .collect();
+    if json {
+        println!("{}", serde_json::to_string_pretty(&shown)?);
+        return Ok(results);
+    }
+    for result in &shown {
+        let implementation = match (
+            &result.implementation_class_uid,
+            &result.implementation_version_name,
+        ) {
+            (Some(uid), Some(version)) => format!("{} ({})", uid, version.trim()),
+            (Some(uid), None) => uid.clone(),
+            (None, _) => "-".to_string(),
+        };
+        let detail = match (result.status, &result.error) {
+            (Some(status), _) => format!("status 0x{:04X}", status),
+            (None, Some(error)) => error.clone(),
+            (None, None) => String::new(),
+        };
+        println!(
+            "{:<24} {:<11} {:>6} ms  {}  {}",
+            result.addr,
+            result.outcome.as_str(),
+            result.elapsed_ms,
+            implementation,
+            detail
+        );
+    }
+    let count = |outcome| results.iter().filter(|r| r.outcome == outcome).count();
+    println!(
+        "{} of {} endpoint(s) answered, {} refused",
+        count(EchoOutcome::Answered),
+        total,
+        count(EchoOutcome::Rejected)
+    );
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cidr_blocks_and_port_ranges_expand() {
+        let hosts = parse_targets("10.0.0.0/30, pacs.local,10.0.1.7/32").unwrap();
+        assert_eq!(
+            hosts,
+            vec!["10.0.0.1", "10.0.0.2", "pacs.local", "10.0.1.7"]
+        );
+        // Host bits of the block are ignored.
+        assert_eq!(parse_targets("192.168.1.77/31").unwrap().len(), 2);
+        assert_eq!(parse_targets("10.0.0.0/24").unwrap().len(), 254);
+        assert!(parse_targets("10.0.0.0/8").is_err());
+        assert!(parse_targets("10.0.0.0/33").is_err());
+
+        assert_eq!(
+            parse_ports("11112, 104,4242-4244,104").unwrap(),
+            vec![104, 4242, 4243, 4244, 11112]
+        );
+        assert!(parse_ports("200-100").is_err());
+        assert!(parse_ports("0").is_err());
+        assert_eq!(endpoint("::1", 104), "[::1]:104");
+    }
+}
@@ -0,0 +1,316 @@
+//
+// element_stream.rs
+// Dicom-Tools-rs
+//
+// Pull-based element stream for selective reads: a callback sees each data element as it is
+// decoded and can stop the read, so pulling one attribute out of a large archive never parses
+// the rest of each file. Top-level Pixel Data is reported by its header only and ends the stream.
+//
+// Thales Matheus Mendonça Santos - November 2025
+
+use std::fs::File;
+use std::io::{self, BufReader, Cursor, Read};
+use std::ops::ControlFlow;
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use dicom::core::{PrimitiveValue, Tag, VR};
+use dicom::encoding::TransferSyntaxIndex;
+use dicom::object::meta::FileMetaTable;
+use dicom::parser::dataset::{DataSetReader, DataToken};
+use dicom::transfer_syntax::entries::{
+    DEFLATED_EXPLICIT_VR_LITTLE_ENDIAN, EXPLICIT_VR_LITTLE_ENDIAN, JPIP_REFERENCED_DEFLATE,
+};
+use dicom::transfer_syntax::TransferSyntaxRegistry;
+use flate2::read::DeflateDecoder;
+
+const PIXEL_DATA: Tag = Tag(0x7FE0, 0x0010);
+const PREAMBLE_LENGTH: usize = 128;
+
+/// One data element, as decoded.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StreamedElement {
+    pub tag: Tag,
+    pub vr: VR,
+    /// Value length in bytes; `None` when undefined (sequences, encapsulated pixel data).
+    pub length: Option<u32>,
+    /// 0 in the top-level data set, 1 inside an item of a top-level sequence, and so on.
+    pub depth: usize,
+    /// `None` for sequences, whose items follow as elements one level deeper, and for
+    /// Pixel Data.
+    pub value: Option<PrimitiveValue>,
+}
+
+/// How far a read went.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StreamSummary {
+    /// Elements handed to the callback.
+    pub elements: u64,
+    /// Bytes pulled from the file, read-ahead buffering included.
+    pub bytes_read: u64,
+    /// Whether the callback stopped the read.
+    pub stopped: bool,
+}
+
+/// Counts the bytes read from the file.
+struct Counted<R> {
+    inner: R,
+    count: u64,
+}
+
+impl<R: Read> Read for Counted<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.count += n as u64;
+        Ok(n)
+    }
+}
+
+/// Hand each data set element of the DICOM file at `path` to `visit`, in file order, until it
+/// returns `ControlFlow::Break`, the data set ends or top-level Pixel Data is reached. Nothing
+/// past the element `visit` stops at is read, apart from buffering.
+pub fn read_elements(
+    path: &Path,
+    mut visit: impl FnMut(StreamedElement) -> ControlFlow<()>,
+) -> Result<StreamSummary> {
+    let file = File::open(path).with_context(|| format!("Failed to open {:?}", path))?;
+    let mut source = BufReader::new(Counted {
+        inner: file,
+        count: 0,
+    });
+    let mut head = Vec::with_capacity(PREAMBLE_LENGTH + 4);
+    (&mut source)
+        .take(PREAMBLE_LENGTH as u64 + 4)
+        .read_to_end(&mut head)?;
+    // With or without the 128-byte preamble, the meta group reader starts at the magic code.
+    let magic = if head.get(PREAMBLE_LENGTH..) == Some(b"DICM") {
+        PREAMBLE_LENGTH
+    } else if head.starts_with(b"DICM") {
+        0
+    } else {
+        bail!("{:?} is not a DICOM file (no DICM magic code)", path);
+    };
+    let mut source = Cursor::new(head.split_off(magic)).chain(source);
+    let meta = FileMetaTable::from_reader(&mut source)
+        .with_context(|| format!("Failed to read the file meta group of {:?}", path))?;
+
+    let ts_uid = meta.transfer_syntax();
+    let deflated = ts_uid == DEFLATED_EXPLICIT_VR_LITTLE_ENDIAN.uid()
+        || ts_uid == JPIP_REFERENCED_DEFLATE.uid();
+    let (dataset, ts_uid): (Box<dyn Read + '_>, &str) = if deflated {
+        (
+            Box::new(DeflateDecoder::new(&mut source)),
+            EXPLICIT_VR_LITTLE_ENDIAN.uid(),
+        )
+    } else {
+        (Box::new(&mut source), ts_uid)
+    };
+    let Some(ts) = TransferSyntaxRegistry.get(ts_uid) else {
+        bail!("Unknown transfer syntax {} in {:?}", ts_uid, path);
+    };
+    let mut tokens = DataSetReader::new_with_ts(dataset, ts)
+        .with_context(|| format!("Failed to read the data set of {:?}", path))?;
+
+    let mut summary = StreamSummary::default();
+    let mut depth = 0;
+    let mut header = None;
+    while let Some(token) = tokens.next() {
+        let token = token.with_context(|| format!("Failed to parse {:?}", path))?;
+        let element = match token {
+            DataToken::ElementHeader(h) if h.tag == PIXEL_DATA && depth == 0 => {
+                // Stop before the value is read.
+                summary.elements += 1;
+                let _ = visit(StreamedElement {
+                    tag: h.tag,
+                    vr: h.vr,
+                    length: h.len.get(),
+                    depth,
+                    value: None,
+                });
+                break;
+            }
+            DataToken::ElementHeader(h) => {
+                header = Some(h);
+                continue;
+            }
+            DataToken::PrimitiveValue(value) => {
+                let Some(h) = header.take() else {
+                    continue;
+                };
+                StreamedElement {
+                    tag: h.tag,
+                    vr: h.vr,
+                    length: h.len.get(),
+                    depth,
+                    value: Some(value),
+                }
+            }
+            DataToken::SequenceStart { tag, len } => StreamedElement {
+                tag,
+                vr: VR::SQ,
+                length: len.get(),
+                depth,
+                value: None,
+            },
+            DataToken::PixelSequenceStart => {
+                let element = StreamedElement {
+                    tag: PIXEL_DATA,
+                    vr: VR::OB,
+                    length: None,
+                    depth,
+                    value: None,
+                };
+                if depth == 0 {
+                    summary.elements += 1;
+                    let _ = visit(element);
+                    break;
+                }
+                // Encapsulated Pixel Data inside an item (an icon): skip its fragments.
+                for token in tokens.by_ref() {
+                    let token = token.with_context(|| format!("Failed to parse {:?}", path))?;
+                    if matches!(token, DataToken::SequenceEnd) {
+                        break;
+                    }
+                }
+                element
+            }
+            DataToken::ItemStart { .. } => {
+                depth += 1;
+                continue;
+            }
+            DataToken::ItemEnd => {
+                depth = depth.saturating_sub(1);
+                continue;
+            }
+            _ => continue,
+        };
+        summary.elements += 1;
+        if visit(element).is_break() {
+            summary.stopped = true;
+            break;
+        }
+    }
+    drop(tokens);
+    summary.bytes_read = source.into_inner().1.into_inner().count;
+    Ok(summary)
+}
+
+/// Value of the top-level attribute `tag`, reading no further than where it is or would be
+/// (the top-level data set is sorted by tag).
+pub fn read_tag(path: &Path, tag: Tag) -> Result<Option<PrimitiveValue>> {
+    let mut found = None;
+    read_elements(path, |element| {
+        if element.depth > 0 || element.tag < tag {
+            return ControlFlow::Continue(());
+        }
+        if element.tag == tag {
+            found = element.value;
+        }
+        ControlFlow::Break(())
+    })?;
+    Ok(found)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dicom::core::value::DataSetSequence;
+    use dicom::core::DataElement;
+    use dicom::object::{open_file, FileDicomObject, FileMetaTableBuilder, InMemDicomObject};
+
+    const STUDY_INSTANCE_UID: Tag = Tag(0x0020, 0x000D);
+
+    #[test]
+    fn a_selective_read_stops_long_before_the_pixel_data() {
+        let dir = tempfile::tempdir().unwrap();
+        let spec = crate::synth::SynthSpec {
+            rows: 512,
+            columns: 512,
+            ..Default::default()
+        };
+        let files = crate::synth::write_series(&spec, dir.path()).unwrap();
+        let expected = open_file(&files[0])
+            .unwrap()
+            .element(STUDY_INSTANCE_UID)
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .into_owned();
+
+        let value = read_tag(&files[0], STUDY_INSTANCE_UID).unwrap().unwrap();
+        assert_eq!(value.to_str().trim_end_matches('\0'), expected);
+
+        let mut last = None;
+        let summary = read_elements(&files[0], |element| {
+            last = Some(element.tag);
+            if element.tag == STUDY_INSTANCE_UID {
+                ControlFlow::Break(())
+            } else {
+                ControlFlow::Continue(())
+            }
+        })
+        .unwrap();
+        assert!(summary.stopped);
+        assert_eq!(last, Some(STUDY_INSTANCE_UID));
+        let size = std::fs::metadata(&files[0]).unwrap().len();
+        assert!(summary.bytes_read * 10 < size, "{:?} of {}", summary, size);
+
+        // Without stopping, the stream ends at the Pixel Data header.
+        let summary = read_elements(&files[0], |_| ControlFlow::Continue(())).unwrap();
+        assert!(!summary.stopped);
+        assert!(summary.bytes_read * 10 < size);
+    }
+
+    #[test]
+    fn sequence_items_are_one_level_deeper() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("seq.dcm");
+        let meta = FileMetaTableBuilder::new()
+            .transfer_syntax("1.2.840.10008.1.2")
+            .media_storage_sop_class_uid("1.2.840.10008.5.1.4.1.1.7")
+            .media_storage_sop_instance_uid("1.2.3.4")
+            .build()
+            .unwrap();
+        let mut obj = FileDicomObject::new_empty_with_meta(meta);
+        let mut item = InMemDicomObject::new_empty();
+        item.put(DataElement::new(
+            Tag(0x0008, 0x1155),
+            VR::UI,
+            PrimitiveValue::from("1.2.3"),
+        ));
+        obj.put(DataElement::new(
+            Tag(0x0008, 0x1140),
+            VR::SQ,
+            DataSetSequence::from(vec![item]),
+        ));
+        obj.put(DataElement::new(
+            Tag(0x0010, 0x0020),
+            VR::LO,
+            PrimitiveValue::from("PAT1"),
+        ));
+        obj.write_to_file(&path).unwrap();
+
+        let mut seen = Vec::new();
+        read_elements(&path, |element| {
+            seen.push((element.tag, element.depth));
+            ControlFlow::Continue(())
+        })
+        .unwrap();
+        assert_eq!(
+            seen,
+            vec![
+                (Tag(0x0008, 0x1140), 0),
+                (Tag(0x0008, 0x1155), 1),
+                (Tag(0x0010, 0x0020), 0),
+            ]
+        );
+        // The nested Referenced SOP Instance UID is not mistaken for a top-level one.
+        assert_eq!(read_tag(&path, Tag(0x0008, 0x1155)).unwrap(), None);
+        assert_eq!(
+            read_tag(&path, Tag(0x0010, 0x0020))
+                .unwrap()
+                .map(|v| v.to_str().trim_end().to_string()),
+            Some("PAT1".to_string())
+        );
+    }
+}
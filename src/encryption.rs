@@ -0,0 +1,179 @@
+//
+// encryption.rs
+// Dicom-Tools-rs
+//
+// AES-256-GCM encryption at rest for store backends, keyed by a file shared between deployments.
+//
+// Thales Matheus Mendonça Santos - November 2025
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use anyhow::{anyhow, bail, Context, Result};
+
+use crate::atomic_file;
+use crate::storage::StorageBackend;
+
+/// Header identifying encrypted blobs (format version 1).
+const MAGIC: &[u8; 6] = b"DTENC1";
+const NONCE_LEN: usize = 12;
+
+/// 256-bit key loaded from a key file.
+#[derive(Clone)]
+pub struct EncryptionKey(Key<Aes256Gcm>);
+
+/// Read a key file holding either 32 raw bytes or 64 hex characters (e.g. generated with
+/// `openssl rand -hex 32`). Share links are signed with keys from the same kind of file.
+pub fn read_key_file(path: &Path) -> Result<[u8; 32]> {
+    let bytes = fs::read(path).with_context(|| format!("Failed to read key file {:?}", path))?;
+    parse_key(&bytes)
+}
+
+fn parse_key(bytes: &[u8]) -> Result<[u8; 32]> {
+    let raw = match std::str::from_utf8(bytes).map(str::trim) {
+        Ok(text) if text.len() == 64 => hex::decode(text).context("Key file is not valid hex")?,
+        _ if bytes.len() == 32 => bytes.to_vec(),
+        _ => bail!("Key file must contain 32 raw bytes or 64 hex characters"),
+    };
+    let mut key = [0u8; 32];
+    key.copy_from_slice(&raw);
+    Ok(key)
+}
+
+impl EncryptionKey {
+    /// Key from a file read by [`read_key_file`].
+    pub fn from_file(path: &Path) -> Result<Self> {
+        Ok(Self::from_raw(read_key_file(path)?))
+    }
+
+    #[cfg(test)]
+    fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        Ok(Self::from_raw(parse_key(bytes)?))
+    }
+
+    fn from_raw(key: [u8; 32]) -> Self {
+        Self(*Key::<Aes256Gcm>::from_slice(&key))
+    }
+
+    /// Encrypt `plaintext` as `MAGIC || nonce || ciphertext+tag`. The blob name is bound as
+    /// associated data so that ciphertexts cannot be swapped between names.
+    pub fn seal(&self, name: &str, plaintext: &[u8]) -> Result<Vec<u8>> {
+        let cipher = Aes256Gcm::new(&self.0);
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = cipher
+            .encrypt(
+                &nonce,
+                Payload {
+                    msg: plaintext,
+                    aad: name.as_bytes(),
+                },
+            )
+            .map_err(|_| anyhow!("Failed to encrypt {}", name))?;
+        let mut blob = Vec::with_capacity(MAGIC.len() + NONCE_LEN + ciphertext.len());
+        blob.extend_from_slice(MAGIC);
+        blob.extend_from_slice(&nonce);
+        blob.extend_from_slice(&ciphertext);
+        Ok(blob)
+    }
+
+    /// Reverse of [`EncryptionKey::seal`]; fails on a wrong key, name, or tampered blob.
+    pub fn open(&self, name: &str, blob: &[u8]) -> Result<Vec<u8>> {
+        let body = blob
+            .strip_prefix(MAGIC.as_slice())
+            .with_context(|| format!("{} is not an encrypted blob", name))?;
+        if body.len() < NONCE_LEN {
+            bail!("{} is truncated", name);
+        }
+        let (nonce, ciphertext) = body.split_at(NONCE_LEN);
+        Aes256Gcm::new(&self.0)
+            .decrypt(
+                Nonce::from_slice(nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad: name.as_bytes(),
+                },
+            )
+            .map_err(|_| anyhow!("Failed to decrypt {} (wrong key or corrupted data)", name))
+    }
+}
+
+/// Wraps another backend so that it only ever receives ciphertext.
+pub struct EncryptedBackend {
+    inner: Arc<dyn StorageBackend>,
+    key: EncryptionKey,
+}
+
+impl EncryptedBackend {
+    pub fn new(inner: Arc<dyn StorageBackend>, key: EncryptionKey) -> Self {
+        Self { inner, key }
+    }
+}
+
+/// Scratch path next to `local` holding the ciphertext while it moves through the backend.
+fn sealed_path(local: &Path) -> PathBuf {
+    let name = local
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    local.with_file_name(format!(".{}.sealed", name))
+}
+
+impl StorageBackend for EncryptedBackend {
+    fn upload(&self, name: &str, local: &Path) -> Result<()> {
+        let plaintext = fs::read(local).with_context(|| format!("Failed to read {:?}", local))?;
+        let sealed = sealed_path(local);
+        atomic_file::write(&sealed, self.key.seal(name, &plaintext)?)?;
+        let result = self.inner.upload(name, &sealed);
+        let _ = fs::remove_file(&sealed);
+        result
+    }
+
+    fn download(&self, name: &str, local: &Path) -> Result<bool> {
+        let sealed = sealed_path(local);
+        if !self.inner.download(name, &sealed)? {
+            return Ok(false);
+        }
+        let blob = fs::read(&sealed);
+        let _ = fs::remove_file(&sealed);
+        let plaintext = self.key.open(name, &blob?)?;
+        atomic_file::write(local, plaintext)?;
+        Ok(true)
+    }
+
+    fn contains(&self, name: &str) -> Result<bool> {
+        self.inner.contains(name)
+    }
+
+    fn remove(&self, name: &str) -> Result<()> {
+        self.inner.remove(name)
+    }
+
+    fn is_remote(&self) -> bool {
+        self.inner.is_remote()
+    }
+
+    fn describe(&self) -> String {
+        format!("{} (AES-256-GCM encrypted)", self.inner.describe())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sealed_blobs_are_bound_to_key_and_name() {
+        let key = EncryptionKey::from_bytes(&[7u8; 32]).expect("raw key");
+        let blob = key.seal("a.dcm", b"PATIENT^NAME").expect("seal");
+        assert!(!blob.windows(12).any(|w| w == b"PATIENT^NAME"));
+        assert_eq!(key.open("a.dcm", &blob).unwrap(), b"PATIENT^NAME");
+
+        assert!(key.open("b.dcm", &blob).is_err());
+        let other = EncryptionKey::from_bytes(hex::encode([8u8; 32]).as_bytes()).expect("hex key");
+        assert!(other.open("a.dcm", &blob).is_err());
+        assert!(EncryptionKey::from_bytes(b"too short").is_err());
+    }
+}
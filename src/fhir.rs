@@ -0,0 +1,468 @@
+//
+// fhir.rs
+// Dicom-Tools-rs
+//
+// Builds HL7 FHIR R4 ImagingStudy resources, with stub Patient resources for their subjects,
+// from the study/series/instance hierarchy of a directory, as a transaction Bundle that a
+// FHIR server can ingest as is.
+//
+// Thales Matheus Mendonça Santos - November 2025
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use dicom::core::Tag;
+use dicom::object::OpenFileOptions;
+use rayon::prelude::*;
+use serde_json::{json, Map, Value};
+use walkdir::WalkDir;
+
+use crate::atomic_file;
+use crate::dicom_access::ElementAccess;
+use crate::person_name::PersonName;
+use crate::temporal::{self, DicomDate, DicomTime, Precision};
+
+const SOP_CLASS_UID: Tag = Tag(0x0008, 0x0016);
+const SOP_INSTANCE_UID: Tag = Tag(0x0008, 0x0018);
+const STUDY_DATE: Tag = Tag(0x0008, 0x0020);
+const SERIES_DATE: Tag = Tag(0x0008, 0x0021);
+const STUDY_TIME: Tag = Tag(0x0008, 0x0030);
+const SERIES_TIME: Tag = Tag(0x0008, 0x0031);
+const ACCESSION_NUMBER: Tag = Tag(0x0008, 0x0050);
+const MODALITY: Tag = Tag(0x0008, 0x0060);
+const TIMEZONE_OFFSET: Tag = Tag(0x0008, 0x0201);
+const STUDY_DESCRIPTION: Tag = Tag(0x0008, 0x1030);
+const SERIES_DESCRIPTION: Tag = Tag(0x0008, 0x103E);
+const PATIENT_NAME: Tag = Tag(0x0010, 0x0010);
+const PATIENT_ID: Tag = Tag(0x0010, 0x0020);
+const PATIENT_BIRTH_DATE: Tag = Tag(0x0010, 0x0030);
+const PATIENT_SEX: Tag = Tag(0x0010, 0x0040);
+const STUDY_INSTANCE_UID: Tag = Tag(0x0020, 0x000D);
+const SERIES_INSTANCE_UID: Tag = Tag(0x0020, 0x000E);
+const SERIES_NUMBER: Tag = Tag(0x0020, 0x0011);
+const INSTANCE_NUMBER: Tag = Tag(0x0020, 0x0013);
+const PIXEL_DATA: Tag = Tag(0x7FE0, 0x0010);
+
+/// Code system of DICOM modalities (PS3.16 CID 29).
+const DCM_SYSTEM: &str = "http://dicom.nema.org/resources/ontology/DCM";
+const URI_SYSTEM: &str = "urn:ietf:rfc:3986";
+const V2_0203_SYSTEM: &str = "http://terminology.hl7.org/CodeSystem/v2-0203";
+/// Patient id used when instances carry no Patient ID.
+const UNKNOWN_PATIENT: &str = "unknown";
+
+/// Header attributes of one instance that the resources are built from.
+#[derive(Debug, Clone, Default)]
+struct InstanceHeader {
+    patient_id: String,
+    patient_name: String,
+    birth_date: String,
+    sex: String,
+    study_instance_uid: String,
+    study_date: String,
+    study_time: String,
+    study_description: String,
+    accession_number: String,
+    timezone_offset: String,
+    series_instance_uid: String,
+    series_number: Option<u32>,
+    series_date: String,
+    series_time: String,
+    series_description: String,
+    modality: String,
+    sop_instance_uid: String,
+    sop_class_uid: String,
+    instance_number: Option<u32>,
+}
+
+impl InstanceHeader {
+    /// `None` when `path` is not DICOM or lacks the study, series or instance UID.
+    fn read(path: &Path) -> Option<Self> {
+        let obj = OpenFileOptions::new()
+            .read_until(PIXEL_DATA)
+            .open_file(path)
+            .ok()?;
+        let text = |tag| {
+            obj.element_str(tag)
+                .map(|s| s.trim_end_matches(['\0', ' ']).trim().to_string())
+                .unwrap_or_default()
+        };
+        let header = InstanceHeader {
+            patient_id: text(PATIENT_ID),
+            patient_name: text(PATIENT_NAME),
+            birth_date: text(PATIENT_BIRTH_DATE),
+            sex: text(PATIENT_SEX),
+            study_instance_uid: text(STUDY_INSTANCE_UID),
+            study_date: text(STUDY_DATE),
+            study_time: text(STUDY_TIME),
+            study_description: text(STUDY_DESCRIPTION),
+            accession_number: text(ACCESSION_NUMBER),
+            timezone_offset: text(TIMEZONE_OFFSET),
+            series_instance_uid: text(SERIES_INSTANCE_UID),
+            series_number: obj.element_u32(SERIES_NUMBER),
+            series_date: text(SERIES_DATE),
+            series_time: text(SERIES_TIME),
+            series_description: text(SERIES_DESCRIPTION),
+            modality: text(MODALITY),
+            sop_instance_uid: text(SOP_INSTANCE_UID),
+            sop_class_uid: text(SOP_CLASS_UID),
+            instance_number: obj.element_u32(INSTANCE_NUMBER),
+        };
+        let complete = !header.study_instance_uid.is_empty()
+            && !header.series_instance_uid.is_empty()
+            && !header.sop_instance_uid.is_empty();
+        complete.then_some(header)
+    }
+}
+
+/// Resources built from a directory.
+#[derive(Debug, Clone)]
+pub struct FhirExport {
+    pub imaging_studies: Vec<Value>,
+    pub patients: Vec<Value>,
+    /// Files that were not DICOM or lacked a study, series or instance UID.
+    pub skipped: Vec<PathBuf>,
+}
+
+impl FhirExport {
+    /// A transaction Bundle that creates or updates every resource under its own id, so
+    /// posting the same export twice leaves the server unchanged.
+    pub fn bundle(&self) -> Value {
+        let entries: Vec<Value> = self
+            .patients
+            .iter()
+            .chain(&self.imaging_studies)
+            .map(|resource| {
+                let url = format!(
+                    "{}/{}",
+                    resource["resourceType"].as_str().unwrap_or_default(),
+                    resource["id"].as_str().unwrap_or_default()
+                );
+                json!({
+                    "fullUrl": url,
+                    "resource": resource,
+                    "request": { "method": "PUT", "url": url },
+                })
+            })
+            .collect();
+        json!({
+            "resourceType": "Bundle",
+            "type": "transaction",
+            "entry": entries,
+        })
+    }
+}
+
+/// Read every DICOM file under `dir` and build one ImagingStudy per Study Instance UID and
+/// one Patient stub per Patient ID.
+pub fn export_directory(dir: &Path) -> Result<FhirExport> {
+    if !dir.is_dir() {
+        bail!("{:?} is not a directory", dir);
+    }
+    let paths: Vec<PathBuf> = WalkDir::new(dir)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file())
+        .map(|e| e.into_path())
+        .collect();
+    let headers: Vec<(PathBuf, Option<InstanceHeader>)> = paths
+        .into_par_iter()
+        .map(|path| {
+            let header = InstanceHeader::read(&path);
+            (path, header)
+        })
+        .collect();
+
+    let mut studies: BTreeMap<String, Vec<InstanceHeader>> = BTreeMap::new();
+    let mut skipped = Vec::new();
+    for (path, header) in headers {
+        match header {
+            Some(header) => studies
+                .entry(header.study_instance_uid.clone())
+                .or_default()
+                .push(header),
+            None => skipped.push(path),
+        }
+    }
+
+    let mut patients: BTreeMap<String, Value> = BTreeMap::new();
+    let imaging_studies = studies
+        .values()
+        .map(|instances| {
+            let first = &instances[0];
+            let patient = patient_id(&first.patient_id);
+            patients
+                .entry(patient.clone())
+                .or_insert_with(|| patient_stub(&patient, first));
+            imaging_study(&patient, instances)
+        })
+        .collect();
+    Ok(FhirExport {
+        imaging_studies,
+        patients: patients.into_values().collect(),
+        skipped,
+    })
+}
+
+/// Build the resources of `dir` and write the Bundle to `output`, or print it when `None`.
+pub fn write_bundle(dir: &Path, output: Option<&Path>) -> Result<FhirExport> {
+    let export = export_directory(dir)?;
+    let text =
+        serde_json::to_string_pretty(&export.bundle()).context("Failed to serialize bundle")?;
+    match output {
+        Some(path) => {
+            atomic_file::write(path, text)
+                .with_context(|| format!("Failed to write bundle to {:?}", path))?;
+            println!(
+                "{} ImagingStudy and {} Patient resource(s) saved to {:?}; {} file(s) skipped",
+                export.imaging_studies.len(),
+                export.patients.len(),
+                path,
+                export.skipped.len()
+            );
+        }
+        None => println!("{}", text),
+    }
+    Ok(export)
+}
+
+/// FHIR ids allow `[A-Za-z0-9.-]{1,64}`; other characters of the Patient ID become `-`.
+fn patient_id(dicom_id: &str) -> String {
+    let id: String = dicom_id
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '.' || c == '-' {
+                c
+            } else {
+                '-'
+            }
+        })
+        .take(64)
+        .collect();
+    if id.is_empty() {
+        UNKNOWN_PATIENT.to_string()
+    } else {
+        id
+    }
+}
+
+fn patient_stub(id: &str, header: &InstanceHeader) -> Value {
+    let mut patient = Map::new();
+    patient.insert("resourceType".into(), json!("Patient"));
+    patient.insert("id".into(), json!(id));
+    if !header.patient_id.is_empty() {
+        patient.insert("identifier".into(), json!([{ "value": header.patient_id }]));
+    }
+    if let Some(name) = PersonName::parse(&header.patient_name) {
+        if let Some(components) = &name.alphabetic {
+            let mut human = Map::new();
+            human.insert("text".into(), json!(components.display()));
+            if let Some(family) = &components.family {
+                human.insert("family".into(), json!(family));
+            }
+            let given: Vec<&str> = [&components.given, &components.middle]
+                .into_iter()
+                .flatten()
+                .map(String::as_str)
+                .collect();
+            if !given.is_empty() {
+                human.insert("given".into(), json!(given));
+            }
+            patient.insert("name".into(), json!([human]));
+        }
+    }
+    let gender = match header.sex.as_str() {
+        "M" => Some("male"),
+        "F" => Some("female"),
+        "O" => Some("other"),
+        _ => None,
+    };
+    if let Some(gender) = gender {
+        patient.insert("gender".into(), json!(gender));
+    }
+    if let Some(birth) = temporal::parse_date(&header.birth_date) {
+        patient.insert("birthDate".into(), json!(fhir_date(&birth)));
+    }
+    Value::Object(patient)
+}
+
+fn imaging_study(patient: &str, instances: &[InstanceHeader]) -> Value {
+    let first = &instances[0];
+    let mut series: BTreeMap<&str, Vec<&InstanceHeader>> = BTreeMap::new();
+    for instance in instances {
+        series
+            .entry(instance.series_instance_uid.as_str())
+            .or_default()
+            .push(instance);
+    }
+    let mut modalities: Vec<&str> = Vec::new();
+    for instance in instances {
+        if !instance.modality.is_empty() && !modalities.contains(&instance.modality.as_str()) {
+            modalities.push(&instance.modality);
+        }
+    }
+
+    let mut study = Map::new();
+    study.insert("resourceType".into(), json!("ImagingStudy"));
+    study.insert("id".into(), json!(first.study_instance_uid));
+    let mut identifiers = vec![json!({
+        "system": URI_SYSTEM,
+        "value": format!("urn:oid:{}", first.study_instance_uid),
+    })];
+    if !first.accession_number.is_empty() {
+        identifiers.push(json!({
+            "type": { "coding": [{ "system": V2_0203_SYSTEM, "code": "ACSN" }] },
+            "value": first.accession_number,
+        }));
+    }
+    study.insert("identifier".into(), json!(identifiers));
+    study.insert("status".into(), json!("available"));
+    if !modalities.is_empty() {
+        let codings: Vec<Value> = modalities.iter().map(|m| modality_coding(m)).collect();
+        study.insert("modality".into(), json!(codings));
+    }
+    study.insert(
+        "subject".into(),
+        json!({ "reference": format!("Patient/{}", patient) }),
+    );
+    if let Some(started) = started(&first.study_date, &first.study_time, &first.timezone_offset) {
+        study.insert("started".into(), json!(started));
+    }
+    study.insert("numberOfSeries".into(), json!(series.len()));
+    study.insert("numberOfInstances".into(), json!(instances.len()));
+    if !first.study_description.is_empty() {
+        study.insert("description".into(), json!(first.study_description));
+    }
+    let series: Vec<Value> = series
+        .into_iter()
+        .map(|(uid, instances)| imaging_series(uid, instances))
+        .collect();
+    study.insert("series".into(), json!(series));
+    Value::Object(study)
+}
+
+fn imaging_series(uid: &str, mut instances: Vec<&InstanceHeader>) -> Value {
+    instances.sort_by(|a, b| {
+        (a.instance_number, &a.sop_instance_uid).cmp(&(b.instance_number, &b.sop_instance_uid))
+    });
+    let first = instances[0];
+    let mut series = Map::new();
+    series.insert("uid".into(), json!(uid));
+    if let Some(number) = first.series_number {
+        series.insert("number".into(), json!(number));
+    }
+    // Modality is required on a series; OT (other) stands in when the header has none.
+    let modality = if first.modality.is_empty() {
+        "OT"
+    } else {
+        &first.modality
+    };
+    series.insert("modality".into(), modality_coding(modality));
+    if !first.series_description.is_empty() {
+        series.insert("description".into(), json!(first.series_description));
+    }
+    series.insert("numberOfInstances".into(), json!(instances.len()));
+    if let Some(started) = started(
+        &first.series_date,
+        &first.series_time,
+        &first.timezone_offset,
+    ) {
+        series.insert("started".into(), json!(started));
+    }
+    let instances: Vec<Value> = instances
+        .iter()
+        .map(|instance| {
+            let mut entry = Map::new();
+            entry.insert("uid".into(), json!(instance.sop_instance_uid));
+            entry.insert(
+                "sopClass".into(),
+                json!({
+                    "system": URI_SYSTEM,
+                    "code": format!("urn:oid:{}", instance.sop_class_uid),
+                }),
+            );
+            if let Some(number) = instance.instance_number {
+                entry.insert("number".into(), json!(number));
+            }
+            Value::Object(entry)
+        })
+        .collect();
+    series.insert("instance".into(), json!(instances));
+    Value::Object(series)
+}
+
+fn modality_coding(modality: &str) -> Value {
+    json!({ "system": DCM_SYSTEM, "code": modality })
+}
+
+/// DA at its own precision (`YYYY`, `YYYY-MM` or `YYYY-MM-DD`).
+fn fhir_date(date: &DicomDate) -> String {
+    let format = match date.precision {
+        Precision::Year => "%Y",
+        Precision::Month => "%Y-%m",
+        _ => "%Y-%m-%d",
+    };
+    date.value.format(format).to_string()
+}
+
+/// FHIR `dateTime` from DA, TM and Timezone Offset From UTC. FHIR requires a zone whenever
+/// a time is given, so without the offset only the date is kept.
+fn started(date: &str, time: &str, offset: &str) -> Option<String> {
+    let date = temporal::parse_date(date)?;
+    let day = fhir_date(&date);
+    let (Some(time), Some(offset)) = (temporal::parse_time(time), zone(offset)) else {
+        return Some(day);
+    };
+    if date.precision != Precision::Day {
+        return Some(day);
+    }
+    Some(format!("{}T{}{}", day, fhir_time(&time), offset))
+}
+
+fn fhir_time(time: &DicomTime) -> String {
+    let format = match time.precision {
+        Precision::Fraction => "%H:%M:%S%.f",
+        _ => "%H:%M:%S",
+    };
+    time.value.format(format).to_string()
+}
+
+/// `&ZZXX` as `±ZZ:XX`.
+fn zone(offset: &str) -> Option<String> {
+    let (sign, digits) = (offset.get(..1)?, offset.get(1..)?);
+    let valid = matches!(sign, "+" | "-")
+        && digits.len() == 4
+        && digits.bytes().all(|b| b.is_ascii_digit());
+    valid.then(|| format!("{}{}:{}", sign, &digits[..2], &digits[2..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn started_needs_a_zone_to_keep_the_time() {
+        assert_eq!(
+            started("20240131", "101502.5", "+0100").as_deref(),
+            Some("2024-01-31T10:15:02.500+01:00")
+        );
+        assert_eq!(
+            started("20240131", "1015", "-0330").as_deref(),
+            Some("2024-01-31T10:15:00-03:30")
+        );
+        assert_eq!(
+            started("20240131", "101502", "").as_deref(),
+            Some("2024-01-31")
+        );
+        assert_eq!(started("202401", "", "").as_deref(), Some("2024-01"));
+        assert_eq!(started("", "101502", "+0100"), None);
+    }
+
+    #[test]
+    fn patient_ids_are_made_valid_fhir_ids() {
+        assert_eq!(patient_id("MRN 12/34"), "MRN-12-34");
+        assert_eq!(patient_id(""), UNKNOWN_PATIENT);
+        assert_eq!(patient_id(&"9".repeat(80)).len(), 64);
+    }
+}
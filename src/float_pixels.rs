@@ -0,0 +1,225 @@
+//
+// float_pixels.rs
+// Dicom-Tools-rs
+//
+// Float Pixel Data (7FE0,0008) and Double Float Pixel Data (7FE0,0009), as carried by
+// Parametric Maps: read the values directly and scale them robustly for display.
+//
+// Thales Matheus Mendonça Santos - November 2025
+
+use anyhow::{bail, Context, Result};
+use dicom::core::Tag;
+use dicom::object::InMemDicomObject;
+use dicom_pixeldata::WindowLevel;
+use image::{DynamicImage, GrayImage, ImageBuffer, Luma};
+
+use crate::dicom_access::ElementAccess;
+
+pub const FLOAT_PIXEL_DATA: Tag = Tag(0x7FE0, 0x0008);
+pub const DOUBLE_FLOAT_PIXEL_DATA: Tag = Tag(0x7FE0, 0x0009);
+const NUMBER_OF_FRAMES: Tag = Tag(0x0028, 0x0008);
+const ROWS: Tag = Tag(0x0028, 0x0010);
+const COLUMNS: Tag = Tag(0x0028, 0x0011);
+
+/// Fractions of the finite values left below black and above white by robust scaling, so
+/// a few extreme voxels (fit failures, edge artefacts) do not flatten the rest.
+pub const ROBUST_CLIP: f64 = 0.01;
+
+/// Float pixel values of every frame, frame after frame in row-major order.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FloatPixels {
+    pub rows: u32,
+    pub columns: u32,
+    pub frames: u32,
+    /// Double Float values are narrowed to `f32` like every other pixel path here.
+    pub values: Vec<f32>,
+}
+
+impl FloatPixels {
+    /// The float pixels of `obj`, or `None` when it carries integer Pixel Data instead.
+    pub fn from_object(obj: &InMemDicomObject) -> Result<Option<Self>> {
+        let values: Vec<f32> = if let Ok(element) = obj.element(FLOAT_PIXEL_DATA) {
+            element
+                .to_multi_float32()
+                .context("Failed to read Float Pixel Data")?
+        } else if let Ok(element) = obj.element(DOUBLE_FLOAT_PIXEL_DATA) {
+            element
+                .to_multi_float64()
+                .context("Failed to read Double Float Pixel Data")?
+                .into_iter()
+                .map(|v| v as f32)
+                .collect()
+        } else {
+            return Ok(None);
+        };
+        let (Some(rows), Some(columns)) = (obj.element_u32(ROWS), obj.element_u32(COLUMNS)) else {
+            bail!("Float pixel data without Rows/Columns");
+        };
+        let frames = obj.element_u32(NUMBER_OF_FRAMES).unwrap_or(1).max(1);
+        let expected = rows as usize * columns as usize * frames as usize;
+        if values.len() < expected {
+            bail!(
+                "Float pixel data holds {} value(s); {} frame(s) of {}x{} need {}",
+                values.len(),
+                frames,
+                columns,
+                rows,
+                expected
+            );
+        }
+        Ok(Some(FloatPixels {
+            rows,
+            columns,
+            frames,
+            values,
+        }))
+    }
+
+    /// Same layout as decoded integer pixel data: frames, rows, columns, samples.
+    pub fn shape(&self) -> Vec<usize> {
+        vec![
+            self.frames as usize,
+            self.rows as usize,
+            self.columns as usize,
+            1,
+        ]
+    }
+
+    pub fn frame(&self, frame: u32) -> Result<&[f32]> {
+        if frame >= self.frames {
+            bail!(
+                "Requested frame {} but file has {} frame(s)",
+                frame,
+                self.frames
+            );
+        }
+        let size = self.rows as usize * self.columns as usize;
+        let start = frame as usize * size;
+        Ok(&self.values[start..start + size])
+    }
+
+    /// Finite values of every frame; NaN and infinities usually mark masked-out voxels.
+    pub fn finite_values(&self) -> Vec<f32> {
+        self.values
+            .iter()
+            .copied()
+            .filter(|v| v.is_finite())
+            .collect()
+    }
+
+    /// Grayscale rendering of `frame`: linear between the window edges when one is given,
+    /// otherwise between the robust range of the frame. Non-finite values render black.
+    pub fn render_frame(
+        &self,
+        frame: u32,
+        window: Option<WindowLevel>,
+        sixteen_bit: bool,
+    ) -> Result<DynamicImage> {
+        let values = self.frame(frame)?;
+        let (low, high) = match window {
+            Some(w) => (
+                (w.center - w.width / 2.0) as f32,
+                (w.center + w.width / 2.0) as f32,
+            ),
+            None => robust_range(values).unwrap_or((0.0, 0.0)),
+        };
+        let scale = |v: f32| -> f32 {
+            if !v.is_finite() || high <= low {
+                0.0
+            } else {
+                ((v - low) / (high - low)).clamp(0.0, 1.0)
+            }
+        };
+        Ok(if sixteen_bit {
+            let pixels = values
+                .iter()
+                .map(|&v| (scale(v) * u16::MAX as f32).round() as u16)
+                .collect();
+            DynamicImage::ImageLuma16(
+                ImageBuffer::<Luma<u16>, Vec<u16>>::from_raw(self.columns, self.rows, pixels)
+                    .context("Failed to build image buffer")?,
+            )
+        } else {
+            let pixels = values
+                .iter()
+                .map(|&v| (scale(v) * u8::MAX as f32).round() as u8)
+                .collect();
+            DynamicImage::ImageLuma8(
+                GrayImage::from_raw(self.columns, self.rows, pixels)
+                    .context("Failed to build image buffer")?,
+            )
+        })
+    }
+}
+
+/// The [`ROBUST_CLIP`] and `1 - ROBUST_CLIP` quantiles of the finite values, or `None`
+/// when there are none.
+pub fn robust_range(values: &[f32]) -> Option<(f32, f32)> {
+    let mut finite: Vec<f32> = values.iter().copied().filter(|v| v.is_finite()).collect();
+    if finite.is_empty() {
+        return None;
+    }
+    finite.sort_by(|a, b| a.total_cmp(b));
+    // Rounding inwards drops the extremes of small frames too, where a 1% tail is less
+    // than one value; too few values to clip keep the full range.
+    let last = (finite.len() - 1) as f64;
+    let low = (last * ROBUST_CLIP).ceil() as usize;
+    let high = (last * (1.0 - ROBUST_CLIP)).floor() as usize;
+    if low >= high {
+        return Some((finite[0], finite[finite.len() - 1]));
+    }
+    Some((finite[low], finite[high]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dicom::core::{DataElement, PrimitiveValue, VR};
+
+    fn parametric_map(tag: Tag, value: PrimitiveValue) -> InMemDicomObject {
+        let mut obj = InMemDicomObject::new_empty();
+        obj.put(DataElement::new(ROWS, VR::US, PrimitiveValue::from(2_u16)));
+        obj.put(DataElement::new(
+            COLUMNS,
+            VR::US,
+            PrimitiveValue::from(50_u16),
+        ));
+        let vr = if tag == FLOAT_PIXEL_DATA {
+            VR::OF
+        } else {
+            VR::OD
+        };
+        obj.put(DataElement::new(tag, vr, value));
+        obj
+    }
+
+    #[test]
+    fn robust_scaling_ignores_outliers_and_nan() {
+        let mut values: Vec<f32> = (0..100).map(|v| v as f32 / 100.0).collect();
+        values[0] = f32::NAN;
+        values[99] = 1.0e6;
+        let obj = parametric_map(FLOAT_PIXEL_DATA, PrimitiveValue::F32(values.into()));
+        let pixels = FloatPixels::from_object(&obj).unwrap().unwrap();
+        assert_eq!(pixels.shape(), vec![1, 2, 50, 1]);
+        assert_eq!(pixels.finite_values().len(), 99);
+
+        let (low, high) = robust_range(&pixels.values).unwrap();
+        assert!(low > 0.0 && high < 1.0, "{} {}", low, high);
+        let image = pixels.render_frame(0, None, false).unwrap().to_luma8();
+        assert_eq!(image.get_pixel(0, 0)[0], 0);
+        assert_eq!(image.get_pixel(49, 1)[0], 255);
+        assert!(image.get_pixel(25, 0)[0] > 0 && image.get_pixel(25, 0)[0] < 255);
+    }
+
+    #[test]
+    fn double_float_pixels_are_read() {
+        let values: Vec<f64> = (0..100).map(f64::from).collect();
+        let obj = parametric_map(DOUBLE_FLOAT_PIXEL_DATA, PrimitiveValue::F64(values.into()));
+        let pixels = FloatPixels::from_object(&obj).unwrap().unwrap();
+        assert_eq!(pixels.values[99], 99.0);
+        assert!(pixels.frame(1).is_err());
+        assert!(FloatPixels::from_object(&InMemDicomObject::new_empty())
+            .unwrap()
+            .is_none());
+    }
+}
@@ -0,0 +1,280 @@
+//
+// frame_extract.rs
+// Dicom-Tools-rs
+//
+// Splits selected frames of a multiframe object into standalone single-frame derived instances.
+//
+// Thales Matheus Mendonça Santos - November 2025
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use dicom::core::value::{DataSetSequence, PixelFragmentSequence, Value};
+use dicom::core::{DataElement, PrimitiveValue, Tag, VR};
+use dicom::object::{DefaultDicomObject, InMemDicomObject};
+
+use crate::atomic_file;
+use crate::derivation::{self, Derivation, DerivationPolicy, SourceImage};
+use crate::dicom_access::{open_dicom, ElementAccess};
+
+const SAMPLES_PER_PIXEL: Tag = Tag(0x0028, 0x0002);
+const NUMBER_OF_FRAMES: Tag = Tag(0x0028, 0x0008);
+const FRAME_INCREMENT_POINTER: Tag = Tag(0x0028, 0x0009);
+const ROWS: Tag = Tag(0x0028, 0x0010);
+const COLUMNS: Tag = Tag(0x0028, 0x0011);
+const BITS_ALLOCATED: Tag = Tag(0x0028, 0x0100);
+const PER_FRAME_FUNCTIONAL_GROUPS_SEQUENCE: Tag = Tag(0x5200, 0x9230);
+const PIXEL_DATA: Tag = Tag(0x7FE0, 0x0010);
+
+/// Pixel Data of one frame (0-based), in the source's encoding.
+fn frame_pixels(
+    obj: &InMemDicomObject,
+    frame: usize,
+    frames: usize,
+) -> Result<Value<InMemDicomObject>> {
+    let element = obj.element(PIXEL_DATA).context("No Pixel Data")?;
+    match element.value() {
+        Value::Primitive(value) => {
+            let bits = obj
+                .element_u32(BITS_ALLOCATED)
+                .context("No Bits Allocated")? as usize;
+            if !bits.is_multiple_of(8) {
+                bail!("Bits Allocated {} is not byte aligned", bits);
+            }
+            let samples = obj.element_u32(ROWS).unwrap_or(0) as usize
+                * obj.element_u32(COLUMNS).unwrap_or(0) as usize
+                * obj.element_u32(SAMPLES_PER_PIXEL).unwrap_or(1) as usize;
+            let range = frame * samples..(frame + 1) * samples;
+            Ok(Value::Primitive(match value {
+                PrimitiveValue::U16(words) if bits == 16 => PrimitiveValue::U16(
+                    words
+                        .get(range)
+                        .context("Pixel Data is shorter than its frame count")?
+                        .into(),
+                ),
+                other => {
+                    let bytes = other.to_bytes();
+                    let size = samples * bits / 8;
+                    PrimitiveValue::U8(
+                        bytes
+                            .get(frame * size..(frame + 1) * size)
+                            .context("Pixel Data is shorter than its frame count")?
+                            .into(),
+                    )
+                }
+            }))
+        }
+        Value::PixelSequence(seq) => {
+            let fragments = seq.fragments();
+            let offsets = seq.offset_table();
+            let selected: Vec<Vec<u8>> = if fragments.len() == frames {
+                vec![fragments[frame].clone()]
+            } else if offsets.len() == frames {
+                // Offsets count from the first fragment item, each item having an 8-byte header.
+                let start = offsets[frame];
+                let end = offsets.get(frame + 1).copied().unwrap_or(u32::MAX);
+                let mut position = 0u32;
+                let mut selected = Vec::new();
+                for fragment in fragments {
+                    if (start..end).contains(&position) {
+                        selected.push(fragment.clone());
+                    }
+                    position += 8 + fragment.len() as u32;
+                }
+                selected
+            } else {
+                bail!("Cannot locate frame boundaries in encapsulated Pixel Data; transcode first");
+            };
+            Ok(Value::PixelSequence(PixelFragmentSequence::new_fragments(
+                selected,
+            )))
+        }
+        Value::Sequence(_) => bail!("Pixel Data is not a pixel value"),
+    }
+}
+
+/// Build a single-frame derived instance from frame `number` (1-based) of `source`.
+pub fn extract_frame(source: &DefaultDicomObject, number: u32) -> Result<DefaultDicomObject> {
+    extract_frame_with(source, number, DerivationPolicy::Full)
+}
+
+/// Like [`extract_frame`], recording as much provenance as `policy` asks for.
+pub fn extract_frame_with(
+    source: &DefaultDicomObject,
+    number: u32,
+    policy: DerivationPolicy,
+) -> Result<DefaultDicomObject> {
+    let frames = source.element_u32(NUMBER_OF_FRAMES).unwrap_or(1);
+    if number == 0 || number > frames {
+        bail!("Frame {} is out of range 1..={}", number, frames);
+    }
+    let index = (number - 1) as usize;
+    let source_image = SourceImage::of(source);
+
+    let mut out = source.clone();
+    let pixel_vr = source.element(PIXEL_DATA).map(|e| e.vr()).unwrap_or(VR::OB);
+    out.put(DataElement::new(
+        PIXEL_DATA,
+        pixel_vr,
+        frame_pixels(source, index, frames as usize)?,
+    ));
+    out.put(DataElement::new(
+        NUMBER_OF_FRAMES,
+        VR::IS,
+        PrimitiveValue::from("1"),
+    ));
+
+    if let Some(item) = source
+        .element(PER_FRAME_FUNCTIONAL_GROUPS_SEQUENCE)
+        .ok()
+        .and_then(|e| e.items())
+        .and_then(|items| items.get(index))
+    {
+        out.put(DataElement::new(
+            PER_FRAME_FUNCTIONAL_GROUPS_SEQUENCE,
+            VR::SQ,
+            DataSetSequence::from(vec![item.clone()]),
+        ));
+    }
+
+    // Attributes named by the Frame Increment Pointer hold one value per frame.
+    if let Ok(pointer) = source.element(FRAME_INCREMENT_POINTER) {
+        if let Some(PrimitiveValue::Tags(tags)) = pointer.value().primitive() {
+            for tag in tags.iter() {
+                let Ok(element) = source.element(*tag) else {
+                    continue;
+                };
+                let values = element.to_str().unwrap_or_default();
+                let values: Vec<&str> = values.split('\\').collect();
+                if values.len() == frames as usize {
+                    out.put(DataElement::new(
+                        *tag,
+                        element.vr(),
+                        PrimitiveValue::from(values[index].trim()),
+                    ));
+                }
+            }
+        }
+    }
+
+    // Every extracted frame needs its own identity, so Preserve still assigns a new UID.
+    let policy = match policy {
+        DerivationPolicy::Preserve => DerivationPolicy::NewUid,
+        policy => policy,
+    };
+    let derivation = Derivation::FrameExtraction { number, of: frames };
+    derivation::apply(&mut out, &source_image, derivation, policy);
+    Ok(out)
+}
+
+/// CLI entry point: write each requested frame (1-based) to `<stem>_frameNNN.dcm` in `output`.
+pub fn extract_frames_file(
+    input: &Path,
+    frames: &[u32],
+    output: &Path,
+    policy: DerivationPolicy,
+) -> Result<Vec<PathBuf>> {
+    let source = open_dicom(input).context("Failed to open DICOM file")?;
+    std::fs::create_dir_all(output).with_context(|| format!("Failed to create {:?}", output))?;
+    let stem = input
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "frame".to_string());
+
+    let mut written = Vec::new();
+    for &number in frames {
+        let instance = extract_frame_with(&source, number, policy)?;
+        let path = output.join(format!("{}_frame{:03}.dcm", stem, number));
+        atomic_file::write_dicom(&path, &instance)?;
+        println!("Frame {} saved to {:?}", number, path);
+        written.push(path);
+    }
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dicom::object::{FileDicomObject, FileMetaTableBuilder};
+
+    const IMAGE_TYPE: Tag = Tag(0x0008, 0x0008);
+    const SOP_CLASS_UID: Tag = Tag(0x0008, 0x0016);
+    const SOP_INSTANCE_UID: Tag = Tag(0x0008, 0x0018);
+    const REFERENCED_SOP_INSTANCE_UID: Tag = Tag(0x0008, 0x1155);
+    const REFERENCED_FRAME_NUMBER: Tag = Tag(0x0008, 0x1160);
+    const SOURCE_IMAGE_SEQUENCE: Tag = Tag(0x0008, 0x2112);
+
+    fn text(obj: &InMemDicomObject, tag: Tag) -> String {
+        obj.element_str(tag)
+            .map(|s| s.trim_end_matches(['\0', ' ']).to_string())
+            .unwrap_or_default()
+    }
+
+    fn cine() -> DefaultDicomObject {
+        let meta = FileMetaTableBuilder::new()
+            .transfer_syntax("1.2.840.10008.1.2.1")
+            .media_storage_sop_class_uid("1.2.840.10008.5.1.4.1.1.7.2")
+            .media_storage_sop_instance_uid("1.2.3.4")
+            .build()
+            .expect("meta");
+        let mut obj = FileDicomObject::new_empty_with_meta(meta);
+        let mut put = |tag, vr, value: PrimitiveValue| obj.put(DataElement::new(tag, vr, value));
+        put(
+            IMAGE_TYPE,
+            VR::CS,
+            PrimitiveValue::Strs(["ORIGINAL".into(), "PRIMARY".into()].into()),
+        );
+        put(SOP_CLASS_UID, VR::UI, "1.2.840.10008.5.1.4.1.1.7.2".into());
+        put(SOP_INSTANCE_UID, VR::UI, "1.2.3.4".into());
+        put(NUMBER_OF_FRAMES, VR::IS, "3".into());
+        put(
+            FRAME_INCREMENT_POINTER,
+            VR::AT,
+            PrimitiveValue::Tags([Tag(0x0018, 0x1065)][..].into()),
+        );
+        put(Tag(0x0018, 0x1065), VR::DS, "10\\20\\30".into());
+        put(ROWS, VR::US, PrimitiveValue::from(1u16));
+        put(COLUMNS, VR::US, PrimitiveValue::from(2u16));
+        put(BITS_ALLOCATED, VR::US, PrimitiveValue::from(16u16));
+        put(
+            PIXEL_DATA,
+            VR::OW,
+            PrimitiveValue::U16([1u16, 2, 3, 4, 5, 6][..].into()),
+        );
+        obj
+    }
+
+    #[test]
+    fn extracted_frame_is_a_derived_single_frame_instance() {
+        let source = cine();
+        let frame = extract_frame(&source, 2).expect("extract");
+
+        assert_eq!(frame.element_u32(NUMBER_OF_FRAMES), Some(1));
+        assert_eq!(
+            frame
+                .element(PIXEL_DATA)
+                .unwrap()
+                .value()
+                .to_bytes()
+                .unwrap()
+                .as_ref(),
+            [3u8, 0, 4, 0]
+        );
+        assert_eq!(text(&frame, Tag(0x0018, 0x1065)), "20");
+        assert_eq!(text(&frame, IMAGE_TYPE), "DERIVED\\PRIMARY");
+
+        let uid = text(&frame, SOP_INSTANCE_UID);
+        assert!(uid.starts_with("2.25.") && uid.len() <= 64);
+        assert_eq!(frame.meta().media_storage_sop_instance_uid, uid);
+        let reference = &frame
+            .element(SOURCE_IMAGE_SEQUENCE)
+            .unwrap()
+            .items()
+            .unwrap()[0];
+        assert_eq!(text(reference, REFERENCED_SOP_INSTANCE_UID), "1.2.3.4");
+        assert_eq!(text(reference, REFERENCED_FRAME_NUMBER), "2");
+
+        assert!(extract_frame(&source, 0).is_err());
+        assert!(extract_frame(&source, 4).is_err());
+    }
+}
@@ -0,0 +1,518 @@
+//
+// harmonize.rs
+// Dicom-Tools-rs
+//
+// Bulk metadata harmonization: TOML rules normalize attribute values and map site-specific
+// synonyms (body parts, institution names, series descriptions) onto one canonical value
+// across a cohort, writing corrected copies and a CSV report of every change.
+//
+// Thales Matheus Mendonça Santos - November 2025
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use dicom::core::dictionary::{DataDictionary, DataDictionaryEntry};
+use dicom::core::{DataElement, PrimitiveValue, Tag, VR};
+use dicom::dictionary_std::StandardDataDictionary;
+use dicom::object::{open_file, InMemDicomObject};
+use rayon::prelude::*;
+use serde::Deserialize;
+use walkdir::WalkDir;
+
+use crate::atomic_file;
+use crate::dicom_access::ElementAccess;
+
+/// Name of the change report written next to the corrected copies.
+pub const REPORT_FILE: &str = "harmonize-report.csv";
+
+/// A mapping file:
+///
+/// ```toml
+/// [[rules]]
+/// tag = "BodyPartExamined"
+/// [rules.values]
+/// CHEST = ["THORAX", "TORAX", "LUNG"]
+/// ABDOMEN = ["ABD", "ABDOMEN "]
+///
+/// [[rules]]
+/// tag = "InstitutionName"
+/// normalize = ["trim", "collapse-spaces"]
+/// [rules.values]
+/// "General Hospital" = ["GEN HOSP", "General Hosp."]
+///
+/// [[rules]]
+/// tag = "0008,103E"
+/// normalize = ["trim", "collapse-spaces", "upper"]
+/// ```
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HarmonizeConfig {
+    #[serde(default)]
+    pub rules: Vec<HarmonizeRule>,
+}
+
+/// How one attribute is harmonized: `normalize` runs first, then a value matching one of
+/// the synonyms of `values` (ignoring case) is replaced by its canonical key.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HarmonizeRule {
+    /// Keyword (`BodyPartExamined`) or `GGGG,EEEE`.
+    pub tag: String,
+    #[serde(default)]
+    pub normalize: Vec<Normalize>,
+    /// Canonical value -> synonyms.
+    #[serde(default)]
+    pub values: BTreeMap<String, Vec<String>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Normalize {
+    /// Strip leading and trailing spaces.
+    Trim,
+    /// Replace runs of whitespace with one space.
+    CollapseSpaces,
+    Upper,
+    Lower,
+}
+
+impl Normalize {
+    fn apply(self, value: &str) -> String {
+        match self {
+            Normalize::Trim => value.trim().to_string(),
+            Normalize::CollapseSpaces => value.split_whitespace().collect::<Vec<_>>().join(" "),
+            Normalize::Upper => value.to_uppercase(),
+            Normalize::Lower => value.to_lowercase(),
+        }
+    }
+}
+
+/// A rule ready to run: its tag resolved and its synonyms indexed.
+#[derive(Debug, Clone)]
+struct CompiledRule {
+    tag: Tag,
+    keyword: String,
+    normalize: Vec<Normalize>,
+    /// Upper-cased, trimmed synonym (canonical values included) -> canonical value.
+    synonyms: BTreeMap<String, String>,
+}
+
+impl CompiledRule {
+    fn new(rule: &HarmonizeRule) -> Result<Self> {
+        let tag = resolve_tag(&rule.tag)?;
+        let entry = StandardDataDictionary.by_tag(tag);
+        let keyword = entry
+            .map(|e| e.alias().to_string())
+            .unwrap_or_else(|| rule.tag.clone());
+        let vr = entry.map(|e| e.vr().relaxed());
+        let mut synonyms = BTreeMap::new();
+        for (canonical, aliases) in &rule.values {
+            if let Some(vr) = vr {
+                check_fits(canonical, vr, &keyword)?;
+            }
+            for alias in aliases.iter().chain([canonical]) {
+                let key = synonym_key(alias);
+                if let Some(previous) = synonyms.insert(key, canonical.clone()) {
+                    if previous != *canonical {
+                        bail!(
+                            "{:?} maps {} to both {:?} and {:?}",
+                            alias,
+                            keyword,
+                            previous,
+                            canonical
+                        );
+                    }
+                }
+            }
+        }
+        Ok(Self {
+            tag,
+            keyword,
+            normalize: rule.normalize.clone(),
+            synonyms,
+        })
+    }
+
+    /// The harmonized form of one value.
+    fn harmonize(&self, value: &str) -> String {
+        let normalized = self
+            .normalize
+            .iter()
+            .fold(value.to_string(), |value, step| step.apply(&value));
+        self.synonyms
+            .get(&synonym_key(&normalized))
+            .cloned()
+            .unwrap_or(normalized)
+    }
+}
+
+fn synonym_key(value: &str) -> String {
+    value.trim().to_uppercase()
+}
+
+/// A keyword from the standard dictionary, or `GGGG,EEEE` (any separator).
+fn resolve_tag(key: &str) -> Result<Tag> {
+    if let Some(entry) = StandardDataDictionary.by_name(key.trim()) {
+        return Ok(entry.tag());
+    }
+    let digits: String = key.chars().filter(char::is_ascii_hexdigit).collect();
+    if digits.len() != 8
+        || key
+            .chars()
+            .any(|c| c.is_ascii_alphabetic() && !c.is_ascii_hexdigit())
+    {
+        bail!("{:?} is neither a DICOM keyword nor a GGGG,EEEE tag", key);
+    }
+    Ok(Tag(
+        u16::from_str_radix(&digits[..4], 16)?,
+        u16::from_str_radix(&digits[4..], 16)?,
+    ))
+}
+
+/// Reject canonical values the attribute's VR cannot hold, before any file is touched.
+fn check_fits(value: &str, vr: VR, keyword: &str) -> Result<()> {
+    let limit = match vr {
+        VR::CS | VR::SH => 16,
+        VR::LO => 64,
+        _ => return Ok(()),
+    };
+    if value.len() > limit {
+        bail!(
+            "{:?} is longer than the {} characters {} ({}) allows",
+            value,
+            limit,
+            keyword,
+            vr.to_string()
+        );
+    }
+    if vr == VR::CS
+        && !value
+            .chars()
+            .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == ' ' || c == '_')
+    {
+        bail!(
+            "{:?} is not a valid Code String for {}: use upper case letters, digits, space and _",
+            value,
+            keyword
+        );
+    }
+    Ok(())
+}
+
+/// The rules of a mapping file.
+#[derive(Debug)]
+pub struct Harmonizer {
+    rules: Vec<CompiledRule>,
+}
+
+impl Harmonizer {
+    pub fn new(config: &HarmonizeConfig) -> Result<Self> {
+        if config.rules.is_empty() {
+            bail!("The mapping has no rule");
+        }
+        let rules = config
+            .rules
+            .iter()
+            .map(CompiledRule::new)
+            .collect::<Result<_>>()?;
+        Ok(Self { rules })
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let text =
+            fs::read_to_string(path).with_context(|| format!("Failed to read {:?}", path))?;
+        let config: HarmonizeConfig =
+            toml::from_str(&text).with_context(|| format!("Invalid mapping file {:?}", path))?;
+        Self::new(&config)
+    }
+
+    /// Rewrite the attributes of `obj` in place; returns what changed. Each value of a
+    /// multi-valued attribute is harmonized on its own; absent attributes are not added.
+    pub fn apply(&self, obj: &mut InMemDicomObject) -> Vec<Change> {
+        let mut changes = Vec::new();
+        for rule in &self.rules {
+            let Ok(element) = obj.element(rule.tag) else {
+                continue;
+            };
+            let vr = element.vr();
+            let Some(before) = obj.element_str(rule.tag) else {
+                continue;
+            };
+            let before = before.trim_end_matches(['\0', ' ']).to_string();
+            let after = before
+                .split('\\')
+                .map(|value| rule.harmonize(value))
+                .collect::<Vec<_>>()
+                .join("\\");
+            if after == before {
+                continue;
+            }
+            let values: Vec<String> = after.split('\\').map(str::to_string).collect();
+            obj.put(DataElement::new(
+                rule.tag,
+                vr,
+                PrimitiveValue::Strs(values.into()),
+            ));
+            changes.push(Change {
+                file: PathBuf::new(),
+                tag: rule.tag,
+                keyword: rule.keyword.clone(),
+                before,
+                after,
+            });
+        }
+        changes
+    }
+}
+
+/// One rewritten attribute of one file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Change {
+    /// Path relative to the harmonized directory.
+    pub file: PathBuf,
+    pub tag: Tag,
+    pub keyword: String,
+    pub before: String,
+    pub after: String,
+}
+
+/// Outcome of a harmonization run.
+#[derive(Debug, Clone, Default)]
+pub struct HarmonizeSummary {
+    pub files: usize,
+    pub changed_files: usize,
+    /// Files that could not be read as DICOM; copied unchanged.
+    pub skipped: usize,
+    pub changes: Vec<Change>,
+    pub failed: Vec<(PathBuf, String)>,
+}
+
+impl HarmonizeSummary {
+    /// How often each attribute went from one value to another, most frequent first.
+    pub fn tallies(&self) -> Vec<(&str, &str, &str, usize)> {
+        let mut counts: BTreeMap<(&str, &str, &str), usize> = BTreeMap::new();
+        for change in &self.changes {
+            *counts
+                .entry((&change.keyword, &change.before, &change.after))
+                .or_default() += 1;
+        }
+        let mut tallies: Vec<_> = counts
+            .into_iter()
+            .map(|((keyword, before, after), count)| (keyword, before, after, count))
+            .collect();
+        tallies.sort_by(|a, b| a.0.cmp(b.0).then(b.3.cmp(&a.3)));
+        tallies
+    }
+
+    pub fn to_csv(&self) -> Result<String> {
+        let mut writer = csv::Writer::from_writer(Vec::new());
+        writer.write_record(["file", "tag", "keyword", "before", "after"])?;
+        for change in &self.changes {
+            writer.write_record([
+                change.file.to_string_lossy().into_owned(),
+                format!("({:04X},{:04X})", change.tag.group(), change.tag.element()),
+                change.keyword.clone(),
+                change.before.clone(),
+                change.after.clone(),
+            ])?;
+        }
+        let bytes = writer
+            .into_inner()
+            .map_err(|e| anyhow::anyhow!("Failed to finish the change report: {}", e.error()))?;
+        Ok(String::from_utf8(bytes)?)
+    }
+}
+
+/// Harmonize every file under `dir` into `output`, at the same relative paths, and write
+/// the change report there. Without `output` (a dry run) nothing is written and only the
+/// changes are computed. Files the rules leave alone (and non-DICOM files) are copied as
+/// they are.
+pub fn harmonize_directory(
+    dir: &Path,
+    harmonizer: &Harmonizer,
+    output: Option<&Path>,
+) -> Result<HarmonizeSummary> {
+    if !dir.is_dir() {
+        bail!("{:?} is not a directory", dir);
+    }
+    if let Some(output) = output.filter(|o| o.starts_with(dir) || dir.starts_with(o)) {
+        bail!(
+            "The output {:?} must not overlap the input {:?}",
+            output,
+            dir
+        );
+    }
+    let mut paths: Vec<PathBuf> = WalkDir::new(dir)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file())
+        .map(|e| e.into_path())
+        .collect();
+    paths.sort();
+
+    enum Outcome {
+        Harmonized(Vec<Change>),
+        Skipped,
+        Failed(String),
+    }
+    let outcomes: Vec<(PathBuf, Outcome)> = paths
+        .par_iter()
+        .map(|path| {
+            let relative = path.strip_prefix(dir).unwrap_or(path).to_path_buf();
+            let target = output.map(|output| output.join(&relative));
+            let outcome = match harmonize_file(path, harmonizer, target.as_deref()) {
+                Ok(Some(mut changes)) => {
+                    for change in &mut changes {
+                        change.file = relative.clone();
+                    }
+                    Outcome::Harmonized(changes)
+                }
+                Ok(None) => Outcome::Skipped,
+                Err(e) => Outcome::Failed(format!("{:#}", e)),
+            };
+            (relative, outcome)
+        })
+        .collect();
+
+    let mut summary = HarmonizeSummary::default();
+    for (relative, outcome) in outcomes {
+        match outcome {
+            Outcome::Harmonized(changes) => {
+                summary.files += 1;
+                if !changes.is_empty() {
+                    summary.changed_files += 1;
+                }
+                summary.changes.extend(changes);
+            }
+            Outcome::Skipped => summary.skipped += 1,
+            Outcome::Failed(reason) => summary.failed.push((relative, reason)),
+        }
+    }
+    if let Some(output) = output {
+        atomic_file::write(&output.join(REPORT_FILE), summary.to_csv()?)?;
+    }
+    Ok(summary)
+}
+
+/// The changes made to one file, or `None` when it is not DICOM.
+fn harmonize_file(
+    path: &Path,
+    harmonizer: &Harmonizer,
+    target: Option<&Path>,
+) -> Result<Option<Vec<Change>>> {
+    let Ok(mut obj) = open_file(path) else {
+        if let Some(target) = target {
+            copy(path, target)?;
+        }
+        return Ok(None);
+    };
+    let changes = harmonizer.apply(&mut obj);
+    if let Some(target) = target {
+        if changes.is_empty() {
+            copy(path, target)?;
+        } else {
+            if let Some(parent) = target.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            atomic_file::write_dicom(target, &obj)
+                .with_context(|| format!("Failed to write {:?}", target))?;
+        }
+    }
+    Ok(Some(changes))
+}
+
+fn copy(path: &Path, target: &Path) -> Result<()> {
+    if let Some(parent) = target.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::copy(path, target).with_context(|| format!("Failed to copy {:?}", path))?;
+    Ok(())
+}
+
+/// CLI entry point; `output: None` is a dry run.
+pub fn print_harmonize(dir: &Path, mapping: &Path, output: Option<&Path>) -> Result<()> {
+    let harmonizer = Harmonizer::load(mapping)?;
+    let summary = harmonize_directory(dir, &harmonizer, output)?;
+    println!(
+        "{} DICOM file(s), {} changed ({} change(s)), {} other file(s) copied as is",
+        summary.files,
+        summary.changed_files,
+        summary.changes.len(),
+        summary.skipped
+    );
+    for (keyword, before, after, count) in summary.tallies() {
+        println!("  {:<28} {:?} -> {:?} ({})", keyword, before, after, count);
+    }
+    for (file, reason) in &summary.failed {
+        eprintln!("Failed: {:?}: {}", file, reason);
+    }
+    match output {
+        Some(output) => println!(
+            "Corrected copies in {:?}, report in {:?}",
+            output,
+            output.join(REPORT_FILE)
+        ),
+        None => println!("Dry run: nothing written"),
+    }
+    if !summary.failed.is_empty() {
+        bail!("{} file(s) could not be harmonized", summary.failed.len());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn harmonizer(toml: &str) -> Result<Harmonizer> {
+        Harmonizer::new(&toml::from_str(toml)?)
+    }
+
+    #[test]
+    fn synonyms_map_to_canonical_values_after_normalization() {
+        let harmonizer = harmonizer(
+            r#"
+            [[rules]]
+            tag = "BodyPartExamined"
+            values = { CHEST = ["thorax", "TORAX"] }
+
+            [[rules]]
+            tag = "0008,103E"
+            normalize = ["trim", "collapse-spaces", "upper"]
+            "#,
+        )
+        .unwrap();
+        let [body, description] = &harmonizer.rules[..] else {
+            panic!("two rules expected");
+        };
+        assert_eq!(body.tag, Tag(0x0018, 0x0015));
+        assert_eq!(body.harmonize("Thorax"), "CHEST");
+        assert_eq!(body.harmonize("chest"), "CHEST");
+        assert_eq!(body.harmonize("HEAD"), "HEAD");
+        assert_eq!(description.keyword, "SeriesDescription");
+        assert_eq!(description.harmonize("  t1   axial post "), "T1 AXIAL POST");
+    }
+
+    #[test]
+    fn invalid_mappings_are_rejected_up_front() {
+        let lower_case_code = r#"
+            [[rules]]
+            tag = "BodyPartExamined"
+            values = { Chest = ["THORAX"] }
+        "#;
+        assert!(harmonizer(lower_case_code).is_err());
+        let ambiguous = r#"
+            [[rules]]
+            tag = "InstitutionName"
+            values = { "General Hospital" = ["GH"], "Green Hills" = ["gh"] }
+        "#;
+        assert!(harmonizer(ambiguous)
+            .unwrap_err()
+            .to_string()
+            .contains("both"));
+        assert!(harmonizer("[[rules]]\ntag = \"NoSuchKeyword\"").is_err());
+        assert!(harmonizer("").is_err());
+    }
+}
@@ -0,0 +1,234 @@
+//
+// icon.rs
+// Dicom-Tools-rs
+//
+// Extracts embedded Icon Image Sequence thumbnails and generates compliant 8-bit icons for insertion into instances.
+//
+// Thales Matheus Mendonça Santos - November 2025
+
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use dicom::core::value::DataSetSequence;
+use dicom::core::{DataElement, PrimitiveValue, Tag, VR};
+use dicom::object::InMemDicomObject;
+use image::imageops::FilterType;
+use image::{DynamicImage, GrayImage, RgbImage};
+
+use crate::atomic_file;
+use crate::dicom_access::{open_dicom, ElementAccess};
+use crate::image::{render_object_frame, ImageExportOptions};
+
+const ICON_IMAGE_SEQUENCE: Tag = Tag(0x0088, 0x0200);
+const SAMPLES_PER_PIXEL: Tag = Tag(0x0028, 0x0002);
+const PHOTOMETRIC_INTERPRETATION: Tag = Tag(0x0028, 0x0004);
+const PLANAR_CONFIGURATION: Tag = Tag(0x0028, 0x0006);
+const ROWS: Tag = Tag(0x0028, 0x0010);
+const COLUMNS: Tag = Tag(0x0028, 0x0011);
+const BITS_ALLOCATED: Tag = Tag(0x0028, 0x0100);
+const BITS_STORED: Tag = Tag(0x0028, 0x0101);
+const HIGH_BIT: Tag = Tag(0x0028, 0x0102);
+const PIXEL_REPRESENTATION: Tag = Tag(0x0028, 0x0103);
+const RED_PALETTE_DATA: Tag = Tag(0x0028, 0x1201);
+const GREEN_PALETTE_DATA: Tag = Tag(0x0028, 0x1202);
+const BLUE_PALETTE_DATA: Tag = Tag(0x0028, 0x1203);
+const PIXEL_DATA: Tag = Tag(0x7FE0, 0x0010);
+
+/// Icon edge lengths suggested by PS3.3 F.7 (icons should not exceed 128x128).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IconSize {
+    Small,
+    Large,
+}
+
+impl IconSize {
+    pub fn edge(self) -> u32 {
+        match self {
+            IconSize::Small => 64,
+            IconSize::Large => 128,
+        }
+    }
+}
+
+/// Decode the icon embedded in an object, if any.
+pub fn extract_icon(obj: &InMemDicomObject) -> Result<Option<DynamicImage>> {
+    let Some(item) = obj
+        .element(ICON_IMAGE_SEQUENCE)
+        .ok()
+        .and_then(|e| e.items())
+        .and_then(|items| items.first())
+    else {
+        return Ok(None);
+    };
+
+    let rows = item.element_u32(ROWS).context("Icon is missing Rows")?;
+    let columns = item
+        .element_u32(COLUMNS)
+        .context("Icon is missing Columns")?;
+    let bits = item.element_u32(BITS_ALLOCATED).unwrap_or(8);
+    if bits != 8 {
+        bail!("Only 8-bit icons are supported (found {} bits)", bits);
+    }
+    let photometric = item
+        .element_str(PHOTOMETRIC_INTERPRETATION)
+        .unwrap_or_else(|| "MONOCHROME2".into());
+    let pixels = item
+        .element(PIXEL_DATA)
+        .context("Icon is missing Pixel Data")?
+        .to_bytes()?
+        .into_owned();
+    let len = (rows * columns) as usize;
+
+    let image = match photometric.trim() {
+        "MONOCHROME2" | "MONOCHROME1" => {
+            let mut data = pixels
+                .get(..len)
+                .context("Icon pixel data too short")?
+                .to_vec();
+            if photometric.trim() == "MONOCHROME1" {
+                data.iter_mut().for_each(|v| *v = 255 - *v);
+            }
+            DynamicImage::ImageLuma8(
+                GrayImage::from_raw(columns, rows, data).context("Invalid icon dimensions")?,
+            )
+        }
+        "RGB" => {
+            let data = pixels.get(..len * 3).context("Icon pixel data too short")?;
+            // Planar configuration 1 stores RRR..GGG..BBB; interleave it for the image crate.
+            let interleaved = if item.element_u32(PLANAR_CONFIGURATION) == Some(1) {
+                (0..len)
+                    .flat_map(|i| [data[i], data[len + i], data[2 * len + i]])
+                    .collect()
+            } else {
+                data.to_vec()
+            };
+            DynamicImage::ImageRgb8(
+                RgbImage::from_raw(columns, rows, interleaved)
+                    .context("Invalid icon dimensions")?,
+            )
+        }
+        "PALETTE COLOR" => {
+            let channel = |tag: Tag| -> Result<Vec<u8>> {
+                let bytes = item
+                    .element(tag)
+                    .context("Missing palette data")?
+                    .to_bytes()?;
+                // 16-bit palette entries keep their high byte; 8-bit entries are used directly.
+                Ok(if bytes.len() >= 512 {
+                    bytes.chunks_exact(2).map(|c| c[1]).collect()
+                } else {
+                    bytes.into_owned()
+                })
+            };
+            let (red, green, blue) = (
+                channel(RED_PALETTE_DATA)?,
+                channel(GREEN_PALETTE_DATA)?,
+                channel(BLUE_PALETTE_DATA)?,
+            );
+            let lookup = |lut: &[u8], idx: u8| lut.get(idx as usize).copied().unwrap_or(0);
+            let data = pixels
+                .get(..len)
+                .context("Icon pixel data too short")?
+                .iter()
+                .flat_map(|&i| [lookup(&red, i), lookup(&green, i), lookup(&blue, i)])
+                .collect();
+            DynamicImage::ImageRgb8(
+                RgbImage::from_raw(columns, rows, data).context("Invalid icon dimensions")?,
+            )
+        }
+        other => bail!("Unsupported icon photometric interpretation {}", other),
+    };
+    Ok(Some(image))
+}
+
+/// Build an Icon Image Sequence item from a rendered image, downsampled to fit `size`.
+pub fn build_icon_item(image: &DynamicImage, size: IconSize) -> InMemDicomObject {
+    let thumbnail = image.resize(size.edge(), size.edge(), FilterType::Triangle);
+    let color = thumbnail.color().has_color();
+    let (photometric, samples, pixels) = if color {
+        ("RGB", 3_u16, thumbnail.to_rgb8().into_raw())
+    } else {
+        ("MONOCHROME2", 1_u16, thumbnail.to_luma8().into_raw())
+    };
+
+    let mut item = InMemDicomObject::new_empty();
+    let us = |tag: Tag, value: u16| DataElement::new(tag, VR::US, PrimitiveValue::from(value));
+    item.put(us(SAMPLES_PER_PIXEL, samples));
+    item.put(DataElement::new(
+        PHOTOMETRIC_INTERPRETATION,
+        VR::CS,
+        PrimitiveValue::from(photometric),
+    ));
+    if color {
+        item.put(us(PLANAR_CONFIGURATION, 0));
+    }
+    item.put(us(ROWS, thumbnail.height() as u16));
+    item.put(us(COLUMNS, thumbnail.width() as u16));
+    item.put(us(BITS_ALLOCATED, 8));
+    item.put(us(BITS_STORED, 8));
+    item.put(us(HIGH_BIT, 7));
+    item.put(us(PIXEL_REPRESENTATION, 0));
+    item.put(DataElement::new(
+        PIXEL_DATA,
+        VR::OB,
+        PrimitiveValue::from(pixels),
+    ));
+    item
+}
+
+/// Replace (or add) the Icon Image Sequence of an object with `item`.
+pub fn insert_icon(obj: &mut InMemDicomObject, item: InMemDicomObject) {
+    obj.put(DataElement::new(
+        ICON_IMAGE_SEQUENCE,
+        VR::SQ,
+        DataSetSequence::from(vec![item]),
+    ));
+}
+
+/// CLI helper: write the embedded icon of `input` to an image file.
+pub fn extract_icon_file(input: &Path, output: &Path) -> Result<()> {
+    let obj = open_dicom(input).context("Failed to open DICOM file")?;
+    let icon = extract_icon(&obj)?.context("No Icon Image Sequence present")?;
+    atomic_file::write_image(output, &icon)
+        .with_context(|| format!("Failed to save icon to {:?}", output))?;
+    println!(
+        "Icon ({}x{}) saved to {:?}",
+        icon.width(),
+        icon.height(),
+        output
+    );
+    Ok(())
+}
+
+/// CLI helper: render the first frame, embed it as an icon and save the instance.
+pub fn add_icon_file(input: &Path, output: &Path, size: IconSize) -> Result<()> {
+    let mut obj = open_dicom(input).context("Failed to open DICOM file")?;
+    let rendered = render_object_frame(&obj, 0, &ImageExportOptions::default())?;
+    let item = build_icon_item(&rendered, size);
+    insert_icon(&mut obj, item);
+    atomic_file::write_dicom(output, &obj)?;
+    println!("Icon Image Sequence added: {:?}", output);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generated_icon_round_trips_through_extraction() {
+        let source = DynamicImage::ImageLuma8(GrayImage::from_fn(512, 256, |x, _| {
+            image::Luma([(x / 2) as u8])
+        }));
+        let item = build_icon_item(&source, IconSize::Small);
+        assert_eq!(item.element_u32(COLUMNS), Some(64));
+        assert_eq!(item.element_u32(ROWS), Some(32));
+
+        let mut obj = InMemDicomObject::new_empty();
+        insert_icon(&mut obj, item);
+        let icon = extract_icon(&obj).expect("extract").expect("icon present");
+        assert_eq!((icon.width(), icon.height()), (64, 32));
+        let gray = icon.to_luma8();
+        assert!(gray.get_pixel(0, 0).0[0] < gray.get_pixel(63, 0).0[0]);
+    }
+}
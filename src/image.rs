@@ -7,23 +7,132 @@
 // Thales Matheus Mendonça Santos - November 2025
 
 use anyhow::{bail, Context, Result};
-use dicom::object::open_file;
+use dicom::object::DefaultDicomObject;
 use dicom::pixeldata::PixelDecoder;
-use dicom_pixeldata::{ConvertOptions, ModalityLutOption, VoiLutOption, WindowLevel};
-use image::{DynamicImage, ImageFormat};
+use dicom_pixeldata::{
+    ConvertOptions, DecodedPixelData, ModalityLutOption, PhotometricInterpretation, VoiLutFunction,
+    VoiLutOption, WindowLevel, WindowLevelTransform,
+};
+use image::{DynamicImage, GrayImage, ImageBuffer, ImageFormat, Luma, Rgb, RgbImage};
+use rayon::prelude::*;
+use serde::Deserialize;
 use std::io::Cursor;
 use std::path::{Path, PathBuf};
 
+use crate::atomic_file;
+use crate::concatenation::open_logical;
+use crate::dicom_access::open_dicom;
+use crate::float_pixels::FloatPixels;
+use crate::kernels;
+use crate::lut::{voi_windows, ExplicitLuts, PaletteLut, PresentationLutShape};
+use crate::models::VoiWindow;
+
 /// Options controlling how pixel data is converted into a displayable image.
 #[derive(Debug, Clone, Default)]
 pub struct ImageExportOptions {
     pub frame: Option<u32>,
     pub window: Option<WindowLevel>,
+    /// Which of the object's windows to use (0-based); the first when unset.
+    pub window_index: Option<usize>,
+    /// VOI LUT Function overriding the one declared for the selected window.
+    pub voi_function: Option<VoiLutFunction>,
     pub normalize: bool,
     pub disable_modality_lut: bool,
     pub disable_voi_lut: bool,
     pub force_8bit: bool,
     pub force_16bit: bool,
+    pub inversion: Inversion,
+    /// Frames converted and encoded at once when exporting every frame of a multi-frame
+    /// object; one per core when unset.
+    pub jobs: Option<usize>,
+}
+
+/// Whether grayscale output is inverted for display.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Inversion {
+    /// Follow the object: MONOCHROME1 and a Presentation LUT Shape of INVERSE each flip the
+    /// display, so that low values render bright as intended.
+    #[default]
+    Auto,
+    /// Always invert grayscale output.
+    Always,
+    /// Never invert, exporting MONOCHROME1 in its stored polarity.
+    Never,
+}
+
+impl Inversion {
+    fn applies_to(self, decoded: &DecodedPixelData, shape: Option<PresentationLutShape>) -> bool {
+        match self {
+            Inversion::Auto => {
+                is_monochrome1(decoded) != (shape == Some(PresentationLutShape::Inverse))
+            }
+            Inversion::Always => decoded.samples_per_pixel() == 1,
+            Inversion::Never => false,
+        }
+    }
+}
+
+/// Encoding of rendered previews served over HTTP.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PreviewFormat {
+    #[default]
+    Png,
+    #[serde(alias = "jpg")]
+    Jpeg,
+    Webp,
+}
+
+impl PreviewFormat {
+    pub fn mime(self) -> &'static str {
+        match self {
+            PreviewFormat::Png => "image/png",
+            PreviewFormat::Jpeg => "image/jpeg",
+            PreviewFormat::Webp => "image/webp",
+        }
+    }
+
+    pub fn from_mime(mime: &str) -> Option<Self> {
+        match mime.trim().to_ascii_lowercase().as_str() {
+            "image/png" => Some(PreviewFormat::Png),
+            "image/jpeg" | "image/jpg" => Some(PreviewFormat::Jpeg),
+            "image/webp" => Some(PreviewFormat::Webp),
+            _ => None,
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            PreviewFormat::Png => "png",
+            PreviewFormat::Jpeg => "jpg",
+            PreviewFormat::Webp => "webp",
+        }
+    }
+
+    /// Encode `image`. JPEG and WebP only take 8-bit samples, so deeper images are reduced
+    /// first; PNG keeps 16-bit output.
+    pub fn encode(self, image: &DynamicImage) -> Result<Vec<u8>> {
+        match self {
+            PreviewFormat::Png => encode_image(image, ImageFormat::Png),
+            PreviewFormat::Jpeg | PreviewFormat::Webp => {
+                let eight_bit = if image.color().has_color() {
+                    DynamicImage::ImageRgb8(image.to_rgb8())
+                } else {
+                    DynamicImage::ImageLuma8(image.to_luma8())
+                };
+                let format = if self == PreviewFormat::Jpeg {
+                    ImageFormat::Jpeg
+                } else {
+                    ImageFormat::WebP
+                };
+                encode_image(&eight_bit, format)
+            }
+        }
+    }
+}
+
+fn is_monochrome1(decoded: &DecodedPixelData) -> bool {
+    decoded.photometric_interpretation() == &PhotometricInterpretation::Monochrome1
 }
 
 pub fn convert(
@@ -32,14 +141,15 @@ pub fn convert(
     format: &str,
     options: &ImageExportOptions,
 ) -> Result<()> {
-    let obj = open_file(input).context("Failed to open DICOM file")?;
+    // Parts of a concatenation are rendered as the whole multiframe they belong to.
+    let obj = open_logical(input).context("Failed to open DICOM file")?;
+    let luts = ExplicitLuts::from_object(&obj);
+    let windows = voi_windows(&obj);
 
     // Decode pixel data (handles compression when features are enabled).
     // We do this once and reuse the decoded buffer for any frames requested.
-    let decoded_image = obj
-        .decode_pixel_data()
-        .context("Failed to decode pixel data")?;
-    let num_frames = decoded_image.number_of_frames();
+    let pixels = FramePixels::of(&obj)?;
+    let num_frames = pixels.number_of_frames();
 
     let base_output = output.unwrap_or_else(|| {
         let mut p = input.to_path_buf();
@@ -63,10 +173,8 @@ pub fn convert(
     let convert_options = build_convert_options(options);
 
     if frames.len() == 1 {
-        let dynamic_image =
-            decoded_image.to_dynamic_image_with_options(frames[0], &convert_options)?;
-        dynamic_image
-            .save(&base_output)
+        let dynamic_image = pixels.render(frames[0], &luts, &windows, options, &convert_options)?;
+        atomic_file::write_image(&base_output, &dynamic_image)
             .with_context(|| format!("Failed to save image to {:?}", base_output))?;
         println!("Image saved to: {:?} (frame {})", base_output, frames[0]);
         return Ok(());
@@ -77,14 +185,27 @@ pub fn convert(
     let parent = base_output.parent().unwrap_or_else(|| Path::new("."));
     let stem = base_output.file_stem().unwrap().to_string_lossy();
 
-    for i in frames {
-        let dynamic_image = decoded_image.to_dynamic_image_with_options(i, &convert_options)?;
-        let frame_name = format!("{}_frame{:03}.{}", stem, i, format);
-        let frame_path = parent.join(frame_name);
+    // Frames are independent, so they are rendered and encoded in parallel; each worker
+    // saves its frame straight away, keeping at most `jobs` rendered frames in memory.
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(options.jobs.unwrap_or(0))
+        .build()
+        .context("Failed to start export workers")?;
+    let saved: Vec<PathBuf> = pool.install(|| {
+        frames
+            .par_iter()
+            .map(|&i| {
+                let dynamic_image = pixels.render(i, &luts, &windows, options, &convert_options)?;
+                let frame_name = format!("{}_frame{:03}.{}", stem, i, format);
+                let frame_path = parent.join(frame_name);
 
-        dynamic_image
-            .save(&frame_path)
-            .with_context(|| format!("Failed to save image to {:?}", frame_path))?;
+                atomic_file::write_image(&frame_path, &dynamic_image)
+                    .with_context(|| format!("Failed to save image to {:?}", frame_path))?;
+                Ok(frame_path)
+            })
+            .collect::<Result<_>>()
+    })?;
+    for (i, frame_path) in frames.iter().zip(&saved) {
         println!("Saved frame {} to {:?}", i, frame_path);
     }
 
@@ -92,11 +213,382 @@ pub fn convert(
 }
 
 pub fn first_frame_png_bytes(input: &Path) -> Result<Vec<u8>> {
-    let obj = open_file(input)?;
-    // Use the default conversion pipeline to render a thumbnail-friendly PNG.
-    let decoded_image = obj.decode_pixel_data()?;
-    let dynamic_image = decoded_image.to_dynamic_image(0)?;
-    encode_image(&dynamic_image, ImageFormat::Png)
+    preview_png_bytes(input, 0, None, None)
+}
+
+/// Render `frame` as a PNG with the default pipeline, optionally with a custom window and
+/// scaled down to fit within `max_edge` pixels.
+pub fn preview_png_bytes(
+    input: &Path,
+    frame: u32,
+    window: Option<WindowLevel>,
+    max_edge: Option<u32>,
+) -> Result<Vec<u8>> {
+    preview_bytes(input, frame, window, max_edge, PreviewFormat::Png)
+}
+
+/// Like [`preview_png_bytes`], encoded as `format`.
+pub fn preview_bytes(
+    input: &Path,
+    frame: u32,
+    window: Option<WindowLevel>,
+    max_edge: Option<u32>,
+    format: PreviewFormat,
+) -> Result<Vec<u8>> {
+    let obj = open_dicom(input)?;
+    let options = ImageExportOptions {
+        window,
+        ..Default::default()
+    };
+    let mut dynamic_image = render_object_frame(&obj, frame, &options)?;
+    if let Some(edge) = max_edge {
+        if dynamic_image.width() > edge || dynamic_image.height() > edge {
+            dynamic_image = dynamic_image.thumbnail(edge, edge);
+        }
+    }
+    format.encode(&dynamic_image)
+}
+
+/// Lay encoded thumbnails side by side in one image, each centered in a `cell` × `cell`
+/// square so tile `i` starts at x = `i * cell`.
+pub fn sprite_bytes(tiles: &[Vec<u8>], cell: u32, format: PreviewFormat) -> Result<Vec<u8>> {
+    let mut sprite = RgbImage::new(cell * tiles.len().max(1) as u32, cell);
+    for (i, tile) in tiles.iter().enumerate() {
+        let tile = image::load_from_memory(tile)
+            .context("Failed to decode thumbnail")?
+            .thumbnail(cell, cell)
+            .to_rgb8();
+        let x = i as u32 * cell + (cell - tile.width()) / 2;
+        let y = (cell - tile.height()) / 2;
+        image::imageops::replace(&mut sprite, &tile, x as i64, y as i64);
+    }
+    format.encode(&DynamicImage::ImageRgb8(sprite))
+}
+
+/// Decode and render a single frame of an already opened object.
+pub fn render_object_frame(
+    obj: &DefaultDicomObject,
+    frame: u32,
+    options: &ImageExportOptions,
+) -> Result<DynamicImage> {
+    let luts = ExplicitLuts::from_object(obj);
+    let windows = voi_windows(obj);
+    FramePixels::of(obj)?.render(
+        frame,
+        &luts,
+        &windows,
+        options,
+        &build_convert_options(options),
+    )
+}
+
+/// Pixels of an opened object: decoded Pixel Data, or Float/Double Float Pixel Data that
+/// dicom-pixeldata cannot decode and that is scaled robustly instead.
+enum FramePixels<'a> {
+    Decoded(DecodedPixelData<'a>),
+    Float(FloatPixels),
+}
+
+impl<'a> FramePixels<'a> {
+    fn of(obj: &'a DefaultDicomObject) -> Result<Self> {
+        Ok(match FloatPixels::from_object(obj)? {
+            Some(float) => FramePixels::Float(float),
+            None => FramePixels::Decoded(
+                obj.decode_pixel_data()
+                    .context("Failed to decode pixel data")?,
+            ),
+        })
+    }
+
+    fn number_of_frames(&self) -> u32 {
+        match self {
+            FramePixels::Decoded(decoded) => decoded.number_of_frames(),
+            FramePixels::Float(float) => float.frames,
+        }
+    }
+
+    fn render(
+        &self,
+        frame: u32,
+        luts: &ExplicitLuts,
+        windows: &[VoiWindow],
+        options: &ImageExportOptions,
+        convert_options: &ConvertOptions,
+    ) -> Result<DynamicImage> {
+        match self {
+            FramePixels::Decoded(decoded) => {
+                render_frame(decoded, frame, luts, windows, options, convert_options)
+            }
+            FramePixels::Float(float) => {
+                float.render_frame(frame, options.window, options.force_16bit)
+            }
+        }
+    }
+}
+
+/// Render one frame, routing through the explicit LUT pipeline when the object carries
+/// Modality/VOI LUT sequences that dicom-pixeldata would otherwise ignore, and through the
+/// palette when it carries Palette Color LUTs.
+fn render_frame(
+    decoded: &DecodedPixelData,
+    frame: u32,
+    luts: &ExplicitLuts,
+    windows: &[VoiWindow],
+    options: &ImageExportOptions,
+    convert_options: &ConvertOptions,
+) -> Result<DynamicImage> {
+    match &luts.palette {
+        Some(palette) => render_palette(
+            decoded,
+            frame,
+            palette,
+            luts,
+            windows,
+            options,
+            convert_options,
+        ),
+        None if decoded.photometric_interpretation()
+            == &PhotometricInterpretation::PaletteColor =>
+        {
+            bail!("PALETTE COLOR image without readable Palette Color LUTs (segmented palettes are not supported)")
+        }
+        None => render_grayscale(decoded, frame, luts, windows, options, convert_options),
+    }
+}
+
+fn render_grayscale(
+    decoded: &DecodedPixelData,
+    frame: u32,
+    luts: &ExplicitLuts,
+    windows: &[VoiWindow],
+    options: &ImageExportOptions,
+    convert_options: &ConvertOptions,
+) -> Result<DynamicImage> {
+    let modality_lut_applies = luts.modality.is_some() && !options.disable_modality_lut;
+    let voi_lut_applies = !luts.voi.is_empty()
+        && !options.disable_voi_lut
+        && options.window.is_none()
+        && options.window_index.is_none()
+        && !options.normalize;
+    // dicom-pixeldata reads multiple windows as one per frame and indexes functions by
+    // frame, so anything beyond a single LINEAR window goes through the explicit pipeline.
+    let explicit_window = !options.disable_voi_lut
+        && !options.normalize
+        && (options.window_index.is_some()
+            || windows.len() > 1
+            || ((!windows.is_empty() || options.window.is_some())
+                && voi_function(windows, options, 0) != VoiLutFunction::Linear));
+
+    // Windows rendered to 8 bits go through the windowing kernel, SIMD with the `simd` feature.
+    let kernel_window = !options.disable_voi_lut
+        && !options.normalize
+        && !options.force_16bit
+        && (options.force_8bit || decoded.bits_allocated() == 8)
+        && (options.window.is_some() || !windows.is_empty());
+
+    if decoded.samples_per_pixel() == 1
+        && (modality_lut_applies || voi_lut_applies || explicit_window || kernel_window)
+    {
+        return render_with_luts(decoded, frame, luts, windows, options);
+    }
+
+    let mut image = decoded.to_dynamic_image_with_options(frame, convert_options)?;
+    // dicom-pixeldata always inverts MONOCHROME1; undo or add inversion to honour the option.
+    if options
+        .inversion
+        .applies_to(decoded, luts.presentation_shape)
+        != is_monochrome1(decoded)
+    {
+        image.invert();
+    }
+    Ok(image)
+}
+
+/// Map stored values through the palette into RGB. A supplemental palette only colors the
+/// values it covers; the rest keep their grayscale rendering.
+fn render_palette(
+    decoded: &DecodedPixelData,
+    frame: u32,
+    palette: &PaletteLut,
+    luts: &ExplicitLuts,
+    windows: &[VoiWindow],
+    options: &ImageExportOptions,
+    convert_options: &ConvertOptions,
+) -> Result<DynamicImage> {
+    let raw_options = ConvertOptions::new().with_modality_lut(ModalityLutOption::None);
+    let stored: Vec<f64> = decoded.to_vec_frame_with_options(frame, &raw_options)?;
+    let grayscale = if palette.supplemental {
+        Some(
+            render_grayscale(decoded, frame, luts, windows, options, convert_options)?.into_rgb16(),
+        )
+    } else {
+        None
+    };
+
+    let colors: Vec<f64> = stored
+        .iter()
+        .enumerate()
+        .flat_map(|(i, v)| match &grayscale {
+            Some(gray) if !palette.covers(*v) => {
+                let pixel = gray.as_raw();
+                [0, 1, 2].map(|c| pixel[i * 3 + c] as f64 / 65535.0)
+            }
+            _ => palette.rgb(*v),
+        })
+        .collect();
+
+    let (width, height) = (decoded.columns(), decoded.rows());
+    if options.force_16bit {
+        let pixels: Vec<u16> = colors
+            .iter()
+            .map(|v| (v * 65535.0).round() as u16)
+            .collect();
+        let buffer = ImageBuffer::<Rgb<u16>, _>::from_raw(width, height, pixels)
+            .context("Frame size does not match image dimensions")?;
+        Ok(DynamicImage::ImageRgb16(buffer))
+    } else {
+        let pixels: Vec<u8> = colors.iter().map(|v| (v * 255.0).round() as u8).collect();
+        let buffer = RgbImage::from_raw(width, height, pixels)
+            .context("Frame size does not match image dimensions")?;
+        Ok(DynamicImage::ImageRgb8(buffer))
+    }
+}
+
+fn render_with_luts(
+    decoded: &DecodedPixelData,
+    frame: u32,
+    luts: &ExplicitLuts,
+    windows: &[VoiWindow],
+    options: &ImageExportOptions,
+) -> Result<DynamicImage> {
+    // Start from raw stored values; every transform below is applied explicitly.
+    let raw_options = ConvertOptions::new().with_modality_lut(ModalityLutOption::None);
+    let stored: Vec<f64> = decoded.to_vec_frame_with_options(frame, &raw_options)?;
+
+    // Modality stage: explicit LUT first, then linear rescale.
+    let modality: Vec<f64> = if options.disable_modality_lut {
+        stored
+    } else if let Some(lut) = &luts.modality {
+        stored.iter().map(|v| lut.apply(*v)).collect()
+    } else {
+        let rescale = decoded.rescale()?;
+        match rescale.get(frame as usize).or(rescale.first()) {
+            Some(r) => stored.iter().map(|v| v * r.slope + r.intercept).collect(),
+            None => stored,
+        }
+    };
+
+    // VOI stage: the custom window wins, then a VOI LUT (unless a window is selected by
+    // index), then the file's window; normalization stands in when none of them applies.
+    let voi_applies = !options.disable_voi_lut && !options.normalize;
+    let lut_applies = voi_applies
+        && !luts.voi.is_empty()
+        && options.window.is_none()
+        && options.window_index.is_none();
+    let window = match selected_window(windows, options)? {
+        Some(window) if voi_applies && !lut_applies => Some(window),
+        _ => None,
+    };
+
+    let invert = options
+        .inversion
+        .applies_to(decoded, luts.presentation_shape);
+    let (width, height) = (decoded.columns(), decoded.rows());
+
+    if let Some((VoiLutFunction::Linear, level)) = window.filter(|_| !options.force_16bit) {
+        let values: Vec<f32> = modality.iter().map(|v| *v as f32).collect();
+        let mut pixels = kernels::window_to_u8(&values, level.center, level.width);
+        if invert {
+            pixels.iter_mut().for_each(|p| *p = 255 - *p);
+        }
+        let buffer = GrayImage::from_raw(width, height, pixels)
+            .context("Frame size does not match image dimensions")?;
+        return Ok(DynamicImage::ImageLuma8(buffer));
+    }
+
+    // Display values in [0, 1].
+    let normalized: Vec<f64> = if let Some((function, level)) = window {
+        let transform = WindowLevelTransform::new(function, level);
+        modality.iter().map(|v| transform.apply(*v, 1.0)).collect()
+    } else if let Some(lut) = luts.voi.first().filter(|_| lut_applies) {
+        let max = lut.max_output();
+        modality.iter().map(|v| lut.apply(*v) / max).collect()
+    } else {
+        min_max_normalize(&modality)
+    };
+
+    let display = |v: f64| if invert { 1.0 - v } else { v };
+    if options.force_16bit {
+        let pixels: Vec<u16> = normalized
+            .iter()
+            .map(|v| (display(*v) * 65535.0).round() as u16)
+            .collect();
+        let buffer = ImageBuffer::<Luma<u16>, _>::from_raw(width, height, pixels)
+            .context("Frame size does not match image dimensions")?;
+        Ok(DynamicImage::ImageLuma16(buffer))
+    } else {
+        let pixels: Vec<u8> = normalized
+            .iter()
+            .map(|v| (display(*v) * 255.0).round() as u8)
+            .collect();
+        let buffer = GrayImage::from_raw(width, height, pixels)
+            .context("Frame size does not match image dimensions")?;
+        Ok(DynamicImage::ImageLuma8(buffer))
+    }
+}
+
+/// The window to apply: the custom one, else the selected (or first) window of the object,
+/// with its VOI LUT Function (PS3.3 C.11.2.1.2).
+fn selected_window(
+    windows: &[VoiWindow],
+    options: &ImageExportOptions,
+) -> Result<Option<(VoiLutFunction, WindowLevel)>> {
+    let index = options.window_index.unwrap_or(0);
+    let window = match options.window {
+        Some(window) => window,
+        None => match windows.get(index) {
+            Some(window) => WindowLevel {
+                center: window.center,
+                width: window.width,
+            },
+            None if options.window_index.is_some() => bail!(
+                "Requested window {} but the file has {} window(s)",
+                index,
+                windows.len()
+            ),
+            None => return Ok(None),
+        },
+    };
+    Ok(Some((voi_function(windows, options, index), window)))
+}
+
+/// VOI LUT Function for window `index`: the override, else the one declared for that
+/// window; unknown names fall back to LINEAR.
+fn voi_function(
+    windows: &[VoiWindow],
+    options: &ImageExportOptions,
+    index: usize,
+) -> VoiLutFunction {
+    options.voi_function.unwrap_or_else(|| {
+        windows
+            .get(index)
+            .or(windows.first())
+            .and_then(|w| VoiLutFunction::try_from(w.function.as_str()).ok())
+            .unwrap_or_default()
+    })
+}
+
+fn min_max_normalize(values: &[f64]) -> Vec<f64> {
+    let (min, max) = values
+        .iter()
+        .fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), v| {
+            (lo.min(*v), hi.max(*v))
+        });
+    let range = max - min;
+    values
+        .iter()
+        .map(|v| if range > 0.0 { (v - min) / range } else { 0.0 })
+        .collect()
 }
 
 fn encode_image(image: &DynamicImage, format: ImageFormat) -> Result<Vec<u8>> {
@@ -0,0 +1,356 @@
+//
+// jobs.rs
+// Dicom-Tools-rs
+//
+// Background job queue for operations over many stored files (anonymize, transcode,
+// validate), with status polling and result artifacts; the web counterpart of `batch`.
+//
+// Thales Matheus Mendonça Santos - November 2025
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, Result};
+use dicom::object::open_file;
+use serde::Serialize;
+
+use crate::atomic_file;
+use crate::progress::NoProgress;
+use crate::storage::FileStore;
+use crate::transcode::UncompressedTransferSyntax;
+use crate::{anonymize, derivation::DerivationPolicy, lenient, transcode, validate};
+
+/// Finished jobs kept for polling; the oldest are forgotten beyond this.
+pub const MAX_RETAINED_JOBS: usize = 256;
+
+/// What a job does to each of its inputs.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum JobOperation {
+    /// Anonymize every input with UIDs remapped consistently across them, into one ZIP.
+    Anonymize {
+        options: anonymize::AnonymizeOptions,
+    },
+    /// Transcode each input to an uncompressed transfer syntax.
+    Transcode { target: UncompressedTransferSyntax },
+    /// Validate each input; produces no artifacts.
+    Validate,
+}
+
+impl JobOperation {
+    fn name(self) -> &'static str {
+        match self {
+            JobOperation::Anonymize { .. } => "anonymize",
+            JobOperation::Transcode { .. } => "transcode",
+            JobOperation::Validate => "validate",
+        }
+    }
+}
+
+/// A stored file a job runs on.
+#[derive(Clone, Debug)]
+pub struct JobInput {
+    pub name: String,
+    pub path: PathBuf,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JobStatus {
+    Queued,
+    Running,
+    /// Every input was processed; individual inputs may still have failed.
+    Completed,
+    /// The job as a whole could not produce its result.
+    Failed,
+}
+
+/// A file a job published to the store.
+#[derive(Clone, Debug, Serialize)]
+pub struct JobArtifact {
+    pub filename: String,
+    /// Input it was made from, when it comes from a single one.
+    pub source: Option<String>,
+    pub download_url: String,
+}
+
+/// Outcome for one input.
+#[derive(Clone, Debug, Serialize)]
+pub struct JobItemResult {
+    pub source: String,
+    pub ok: bool,
+    pub message: Option<String>,
+}
+
+/// Everything a client polls about a job.
+#[derive(Clone, Debug, Serialize)]
+pub struct JobRecord {
+    pub id: u64,
+    pub operation: &'static str,
+    pub status: JobStatus,
+    pub total: usize,
+    pub done: usize,
+    /// Seconds since the Unix epoch.
+    pub submitted_at: u64,
+    pub finished_at: Option<u64>,
+    pub error: Option<String>,
+    pub artifacts: Vec<JobArtifact>,
+    pub results: Vec<JobItemResult>,
+}
+
+struct QueuedJob {
+    id: u64,
+    operation: JobOperation,
+    inputs: Vec<JobInput>,
+}
+
+struct QueueState {
+    jobs: Mutex<BTreeMap<u64, JobRecord>>,
+    next_id: AtomicU64,
+    store: FileStore,
+}
+
+/// Jobs run one at a time on a dedicated thread, in submission order, so directory-scale
+/// work never competes with interactive requests for their worker slots.
+#[derive(Clone)]
+pub struct JobQueue {
+    state: Arc<QueueState>,
+    sender: Sender<QueuedJob>,
+}
+
+impl JobQueue {
+    /// Start the worker thread; it stops once every clone of the queue is dropped.
+    pub fn start(store: FileStore) -> Self {
+        let state = Arc::new(QueueState {
+            jobs: Mutex::new(BTreeMap::new()),
+            next_id: AtomicU64::new(1),
+            store,
+        });
+        let (sender, receiver) = mpsc::channel();
+        let worker = state.clone();
+        thread::spawn(move || run_worker(&worker, receiver));
+        Self { state, sender }
+    }
+
+    /// Queue `operation` over `inputs` and return the new job's id.
+    pub fn submit(&self, operation: JobOperation, inputs: Vec<JobInput>) -> Result<u64> {
+        let id = self.state.next_id.fetch_add(1, Ordering::Relaxed);
+        {
+            let mut jobs = self.state.lock()?;
+            jobs.insert(
+                id,
+                JobRecord {
+                    id,
+                    operation: operation.name(),
+                    status: JobStatus::Queued,
+                    total: inputs.len(),
+                    done: 0,
+                    submitted_at: now(),
+                    finished_at: None,
+                    error: None,
+                    artifacts: Vec::new(),
+                    results: Vec::new(),
+                },
+            );
+            forget_old_jobs(&mut jobs);
+        }
+        self.sender
+            .send(QueuedJob {
+                id,
+                operation,
+                inputs,
+            })
+            .map_err(|_| anyhow!("Job worker has stopped"))?;
+        Ok(id)
+    }
+
+    pub fn get(&self, id: u64) -> Result<Option<JobRecord>> {
+        Ok(self.state.lock()?.get(&id).cloned())
+    }
+
+    /// Every retained job, most recent first.
+    pub fn list(&self) -> Result<Vec<JobRecord>> {
+        Ok(self.state.lock()?.values().rev().cloned().collect())
+    }
+}
+
+impl QueueState {
+    fn lock(&self) -> Result<MutexGuard<'_, BTreeMap<u64, JobRecord>>> {
+        self.jobs
+            .lock()
+            .map_err(|_| anyhow!("Job table lock poisoned"))
+    }
+
+    fn update(&self, id: u64, change: impl FnOnce(&mut JobRecord)) {
+        if let Ok(mut jobs) = self.lock() {
+            if let Some(record) = jobs.get_mut(&id) {
+                change(record);
+            }
+        }
+    }
+}
+
+fn run_worker(state: &QueueState, receiver: Receiver<QueuedJob>) {
+    for job in receiver {
+        state.update(job.id, |record| record.status = JobStatus::Running);
+        let outcome = run_job(state, &job);
+        state.update(job.id, |record| {
+            record.finished_at = Some(now());
+            match outcome {
+                Ok(()) => record.status = JobStatus::Completed,
+                Err(err) => {
+                    record.status = JobStatus::Failed;
+                    record.error = Some(err.to_string());
+                }
+            }
+        });
+    }
+}
+
+fn run_job(state: &QueueState, job: &QueuedJob) -> Result<()> {
+    let store = &state.store;
+    match job.operation {
+        JobOperation::Anonymize { options } => {
+            let first = job
+                .inputs
+                .first()
+                .ok_or_else(|| anyhow!("Job has no inputs"))?;
+            let paths: Vec<PathBuf> = job.inputs.iter().map(|i| i.path.clone()).collect();
+            let (name, path) =
+                store.derived_path(&first.name, &format!("job{}", job.id), "", "zip")?;
+            atomic_file::write_with(&path, |writer| {
+                anonymize::anonymize_study_zip(&paths, options, writer)?;
+                Ok(())
+            })?;
+            store.publish(&name)?;
+            state.update(job.id, |record| {
+                record.done = record.total;
+                record.artifacts.push(artifact(name, None));
+                record.results = job
+                    .inputs
+                    .iter()
+                    .map(|input| JobItemResult {
+                        source: input.name.clone(),
+                        ok: true,
+                        message: None,
+                    })
+                    .collect();
+            });
+        }
+        JobOperation::Transcode { target } => {
+            for input in &job.inputs {
+                // An earlier transcode of the same content to the same syntax is reused.
+                let result = store
+                    .find_derived(&input.name, "transcoded", target.uid())
+                    .and_then(|reused| match reused {
+                        Some(name) => Ok(name),
+                        None => {
+                            let (name, path) = store.derived_path(
+                                &input.name,
+                                "transcoded",
+                                target.uid(),
+                                "dcm",
+                            )?;
+                            transcode::transcode_with_derivation(
+                                &input.path,
+                                &path,
+                                target,
+                                DerivationPolicy::Preserve,
+                                &NoProgress,
+                            )?;
+                            store.publish(&name)?;
+                            Ok(name)
+                        }
+                    });
+                state.update(job.id, |record| {
+                    record.done += 1;
+                    record
+                        .results
+                        .push(item_result(input, result.as_ref().err()));
+                    if let Ok(name) = result {
+                        record
+                            .artifacts
+                            .push(artifact(name, Some(input.name.clone())));
+                    }
+                });
+            }
+        }
+        JobOperation::Validate => {
+            for input in &job.inputs {
+                let findings = std::fs::read(&input.path)
+                    .map_err(anyhow::Error::from)
+                    .and_then(|bytes| {
+                        let obj = open_file(&input.path)?;
+                        let report = validate::validate_obj(&obj);
+                        Ok(report
+                            .missing_tags
+                            .iter()
+                            .map(|tag| format!("Missing: {}", tag))
+                            .chain(report.conditional_violations)
+                            .chain(validate::check_file_meta(
+                                &obj,
+                                lenient::observe_encoding(&bytes),
+                            ))
+                            .collect::<Vec<_>>())
+                    });
+                let result = match findings {
+                    Ok(findings) => JobItemResult {
+                        source: input.name.clone(),
+                        ok: findings.is_empty(),
+                        message: (!findings.is_empty()).then(|| findings.join("; ")),
+                    },
+                    Err(err) => JobItemResult {
+                        source: input.name.clone(),
+                        ok: false,
+                        message: Some(err.to_string()),
+                    },
+                };
+                state.update(job.id, |record| {
+                    record.done += 1;
+                    record.results.push(result);
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+fn artifact(filename: String, source: Option<String>) -> JobArtifact {
+    JobArtifact {
+        download_url: format!("/api/download/{}", filename),
+        filename,
+        source,
+    }
+}
+
+fn item_result(input: &JobInput, error: Option<&anyhow::Error>) -> JobItemResult {
+    JobItemResult {
+        source: input.name.clone(),
+        ok: error.is_none(),
+        message: error.map(|err| err.to_string()),
+    }
+}
+
+/// Drop the oldest finished jobs beyond [`MAX_RETAINED_JOBS`]; queued and running jobs stay.
+fn forget_old_jobs(jobs: &mut BTreeMap<u64, JobRecord>) {
+    let finished: Vec<u64> = jobs
+        .values()
+        .filter(|record| record.finished_at.is_some())
+        .map(|record| record.id)
+        .collect();
+    let excess = jobs.len().saturating_sub(MAX_RETAINED_JOBS);
+    for id in finished.into_iter().take(excess) {
+        jobs.remove(&id);
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
@@ -0,0 +1,328 @@
+//
+// joint_histogram.rs
+// Dicom-Tools-rs
+//
+// 2D joint histogram of two aligned frames with mutual information, for checking that a
+// transcode kept the pixels or how well two registered images agree.
+//
+// Thales Matheus Mendonça Santos - November 2025
+
+use std::fmt::Write as _;
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use dicom::pixeldata::PixelDecoder;
+use dicom_pixeldata::{ConvertOptions, ModalityLutOption};
+use image::GrayImage;
+
+use crate::atomic_file;
+use crate::concatenation::open_logical;
+use crate::kernels::{self, MinMaxSum};
+
+/// Bins per axis at most; the table holds the square of this many counts.
+pub const MAX_BINS: usize = 4096;
+
+/// Co-occurrence counts of two images over `bins` x `bins` cells: row `i` is the bin of the
+/// first image's value, column `j` the bin of the second's, at the same pixel.
+#[derive(Debug, Clone)]
+pub struct JointHistogram {
+    pub bins: usize,
+    pub a_range: (f32, f32),
+    pub b_range: (f32, f32),
+    /// Row-major `bins * bins` counts.
+    pub counts: Vec<u64>,
+    pub total: u64,
+    /// Pearson correlation of the paired values; 0 when either image is constant.
+    pub correlation: f64,
+}
+
+impl JointHistogram {
+    /// Bin the pairs `(a[k], b[k])`, each axis spanning the observed range of its image.
+    pub fn compute(a: &[f32], b: &[f32], bins: usize) -> Result<Self> {
+        if a.len() != b.len() {
+            bail!(
+                "Images are not aligned: {} pixels against {}",
+                a.len(),
+                b.len()
+            );
+        }
+        if a.is_empty() {
+            bail!("Images hold no pixels");
+        }
+        if bins == 0 || bins > MAX_BINS {
+            bail!("Number of bins must be between 1 and {}", MAX_BINS);
+        }
+
+        let a_range = range(a);
+        let b_range = range(b);
+        let mut counts = vec![0u64; bins * bins];
+        for (&x, &y) in a.iter().zip(b) {
+            counts[bin(x, a_range, bins) * bins + bin(y, b_range, bins)] += 1;
+        }
+
+        Ok(JointHistogram {
+            bins,
+            a_range,
+            b_range,
+            counts,
+            total: a.len() as u64,
+            correlation: correlation(a, b),
+        })
+    }
+
+    pub fn count(&self, row: usize, column: usize) -> u64 {
+        self.counts[row * self.bins + column]
+    }
+
+    fn marginal_a(&self) -> Vec<u64> {
+        self.counts
+            .chunks(self.bins)
+            .map(|row| row.iter().sum())
+            .collect()
+    }
+
+    fn marginal_b(&self) -> Vec<u64> {
+        let mut marginal = vec![0u64; self.bins];
+        for row in self.counts.chunks(self.bins) {
+            for (sum, count) in marginal.iter_mut().zip(row) {
+                *sum += count;
+            }
+        }
+        marginal
+    }
+
+    /// Shannon entropy of the first image's binned values, in bits.
+    pub fn entropy_a(&self) -> f64 {
+        entropy(&self.marginal_a(), self.total)
+    }
+
+    pub fn entropy_b(&self) -> f64 {
+        entropy(&self.marginal_b(), self.total)
+    }
+
+    pub fn joint_entropy(&self) -> f64 {
+        entropy(&self.counts, self.total)
+    }
+
+    /// I(A;B) = H(A) + H(B) - H(A,B), in bits.
+    pub fn mutual_information(&self) -> f64 {
+        (self.entropy_a() + self.entropy_b() - self.joint_entropy()).max(0.0)
+    }
+
+    /// Studholme's (H(A) + H(B)) / H(A,B): 1 for independent images, 2 when each
+    /// determines the other. Two constant images count as identical.
+    pub fn normalized_mutual_information(&self) -> f64 {
+        let joint = self.joint_entropy();
+        if joint == 0.0 {
+            2.0
+        } else {
+            (self.entropy_a() + self.entropy_b()) / joint
+        }
+    }
+
+    /// Non-empty cells as `a_bin,a_start,b_bin,b_start,count` rows.
+    pub fn to_csv(&self) -> String {
+        let a_width = width(self.a_range, self.bins);
+        let b_width = width(self.b_range, self.bins);
+        let mut out = String::from("a_bin,a_start,b_bin,b_start,count\n");
+        for row in 0..self.bins {
+            for column in 0..self.bins {
+                let count = self.count(row, column);
+                if count > 0 {
+                    let _ = writeln!(
+                        out,
+                        "{},{},{},{},{}",
+                        row,
+                        self.a_range.0 + row as f32 * a_width,
+                        column,
+                        self.b_range.0 + column as f32 * b_width,
+                        count
+                    );
+                }
+            }
+        }
+        out
+    }
+
+    /// One pixel per cell, brightness on a log scale of the count so sparse off-diagonal
+    /// structure stays visible next to the dense diagonal. The first image runs bottom to
+    /// top, the second left to right, so identical images draw a rising diagonal.
+    pub fn heatmap(&self) -> GrayImage {
+        let peak = self.counts.iter().copied().max().unwrap_or(0);
+        let scale = if peak > 0 {
+            255.0 / (peak as f64).ln_1p()
+        } else {
+            0.0
+        };
+        let bins = self.bins as u32;
+        GrayImage::from_fn(bins, bins, |x, y| {
+            let count = self.count((bins - 1 - y) as usize, x as usize);
+            image::Luma([((count as f64).ln_1p() * scale).round() as u8])
+        })
+    }
+}
+
+fn range(values: &[f32]) -> (f32, f32) {
+    let MinMaxSum { min, max, .. } = kernels::min_max_sum(values);
+    (min, max)
+}
+
+fn width((min, max): (f32, f32), bins: usize) -> f32 {
+    (max - min) / bins as f32
+}
+
+fn bin(value: f32, (min, max): (f32, f32), bins: usize) -> usize {
+    if max == min {
+        return 0;
+    }
+    let idx = (((value - min) / (max - min)) * bins as f32).floor() as usize;
+    idx.min(bins - 1)
+}
+
+fn entropy(counts: &[u64], total: u64) -> f64 {
+    counts
+        .iter()
+        .filter(|&&c| c > 0)
+        .map(|&c| {
+            let p = c as f64 / total as f64;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+fn correlation(a: &[f32], b: &[f32]) -> f64 {
+    let n = a.len() as f64;
+    let mean_a = a.iter().map(|&v| v as f64).sum::<f64>() / n;
+    let mean_b = b.iter().map(|&v| v as f64).sum::<f64>() / n;
+    let (mut cov, mut var_a, mut var_b) = (0.0, 0.0, 0.0);
+    for (&x, &y) in a.iter().zip(b) {
+        let (dx, dy) = (x as f64 - mean_a, y as f64 - mean_b);
+        cov += dx * dy;
+        var_a += dx * dx;
+        var_b += dy * dy;
+    }
+    if var_a == 0.0 || var_b == 0.0 {
+        0.0
+    } else {
+        cov / (var_a * var_b).sqrt()
+    }
+}
+
+/// Modality values of one frame of a single-sample image, with its rows and columns.
+pub fn frame_values(path: &Path, frame: u32) -> Result<(Vec<f32>, u32, u32)> {
+    let obj = open_logical(path).with_context(|| format!("Failed to open {:?}", path))?;
+    let decoded = obj
+        .decode_pixel_data()
+        .with_context(|| format!("Failed to decode pixel data of {:?}", path))?;
+    if decoded.samples_per_pixel() != 1 {
+        bail!("{:?} is not a single-sample (grayscale) image", path);
+    }
+    if frame >= decoded.number_of_frames() {
+        bail!(
+            "{:?} has {} frame(s); frame {} is out of range",
+            path,
+            decoded.number_of_frames(),
+            frame
+        );
+    }
+    let options = ConvertOptions::new().with_modality_lut(ModalityLutOption::Default);
+    let values = decoded
+        .to_vec_frame_with_options(frame, &options)
+        .context("Failed to convert pixel data")?;
+    Ok((values, decoded.rows(), decoded.columns()))
+}
+
+/// CLI entry point: joint histogram of `frame_a` of `a` against `frame_b` of `b`, printing
+/// the information measures and optionally writing the CSV and heatmap PNG.
+pub fn compare_files(
+    a: &Path,
+    frame_a: u32,
+    b: &Path,
+    frame_b: u32,
+    bins: usize,
+    csv: Option<&Path>,
+    png: Option<&Path>,
+) -> Result<()> {
+    let (values_a, rows_a, columns_a) = frame_values(a, frame_a)?;
+    let (values_b, rows_b, columns_b) = frame_values(b, frame_b)?;
+    if (rows_a, columns_a) != (rows_b, columns_b) {
+        bail!(
+            "Frames differ in size: {}x{} against {}x{}",
+            columns_a,
+            rows_a,
+            columns_b,
+            rows_b
+        );
+    }
+    let histogram = JointHistogram::compute(&values_a, &values_b, bins)?;
+
+    println!(
+        "Joint histogram of {:?} frame {} vs {:?} frame {} | {}x{} bins | {} pixels",
+        a, frame_a, b, frame_b, bins, bins, histogram.total
+    );
+    println!(
+        "  A range: [{:.2}, {:.2}]  B range: [{:.2}, {:.2}]",
+        histogram.a_range.0, histogram.a_range.1, histogram.b_range.0, histogram.b_range.1
+    );
+    println!(
+        "  H(A): {:.4} bits  H(B): {:.4} bits  H(A,B): {:.4} bits",
+        histogram.entropy_a(),
+        histogram.entropy_b(),
+        histogram.joint_entropy()
+    );
+    println!(
+        "  Mutual information: {:.4} bits  Normalized: {:.4}  Correlation: {:.4}",
+        histogram.mutual_information(),
+        histogram.normalized_mutual_information(),
+        histogram.correlation
+    );
+
+    if let Some(path) = csv {
+        atomic_file::write(path, histogram.to_csv())
+            .with_context(|| format!("Failed to write CSV to {:?}", path))?;
+        println!("CSV saved to {:?}", path);
+    }
+    if let Some(path) = png {
+        atomic_file::write_with(path, |writer| {
+            Ok(histogram
+                .heatmap()
+                .write_to(writer, image::ImageFormat::Png)?)
+        })
+        .with_context(|| format!("Failed to write heatmap to {:?}", path))?;
+        println!("Heatmap saved to {:?}", path);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn identical_images_share_all_their_information() {
+        let a: Vec<f32> = (0..256).map(|v| (v % 16) as f32).collect();
+        let histogram = JointHistogram::compute(&a, &a, 16).unwrap();
+        assert!((histogram.entropy_a() - 4.0).abs() < 1e-9);
+        assert!((histogram.mutual_information() - 4.0).abs() < 1e-9);
+        assert!((histogram.normalized_mutual_information() - 2.0).abs() < 1e-9);
+        assert!((histogram.correlation - 1.0).abs() < 1e-9);
+        // Only the diagonal is populated, drawn rising from bottom left.
+        assert_eq!(histogram.to_csv().lines().count(), 17);
+        let heatmap = histogram.heatmap();
+        assert_eq!(heatmap.get_pixel(0, 15)[0], 255);
+        assert_eq!(heatmap.get_pixel(15, 15)[0], 0);
+    }
+
+    #[test]
+    fn independent_images_have_no_mutual_information() {
+        // Every pairing of four values with four values occurs equally often.
+        let a: Vec<f32> = (0..16).map(|v| (v / 4) as f32).collect();
+        let b: Vec<f32> = (0..16).map(|v| (v % 4) as f32).collect();
+        let histogram = JointHistogram::compute(&a, &b, 4).unwrap();
+        assert!(histogram.mutual_information().abs() < 1e-9);
+        assert!((histogram.normalized_mutual_information() - 1.0).abs() < 1e-9);
+        assert!(histogram.correlation.abs() < 1e-9);
+
+        assert!(JointHistogram::compute(&a, &b[..8], 4).is_err());
+    }
+}
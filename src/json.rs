@@ -7,6 +7,9 @@
 // Thales Matheus Mendonça Santos - November 2025
 
 use anyhow::{Context, Result};
+use dicom::core::dictionary::DataDictionary;
+use dicom::core::value::Value as DicomValue;
+use dicom::core::{Tag, VR};
 use dicom::object::{open_file, InMemDicomObject};
 // Re-export StandardDataDictionary from dicom crate (v0.7 uses dicom_dictionary_std v0.7 internally)
 // We can access it via dicom::dictionary_std or similar if exposed,
@@ -15,18 +18,33 @@ use dicom::object::{open_file, InMemDicomObject};
 use dicom::object::FileMetaTableBuilder;
 use dicom::object::StandardDataDictionary;
 use dicom_json::{from_value, DicomJson};
-use serde_json::Value;
+use serde_json::{Map, Value};
 use std::fs::File;
 use std::path::Path;
 
+use crate::atomic_file;
+
+/// Shape of the JSON produced by `to-json`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum JsonStyle {
+    /// Standard DICOM JSON (PS3.18 F.2) with `vr`/`Value` per tag; round-trips via `from-json`.
+    #[default]
+    Standard,
+    /// Flat keyword -> string pairs, with sequences as nested arrays of objects.
+    Simple,
+}
+
 /// Convert a DICOM file to JSON and print it to stdout.
-pub fn to_json(input: &Path, output: Option<&Path>) -> Result<()> {
+pub fn to_json(input: &Path, output: Option<&Path>, style: JsonStyle) -> Result<()> {
     // Delegate to the pure function so behavior is consistent across CLI and API.
-    let json_string = to_json_string(input)?;
+    let json_string = match style {
+        JsonStyle::Standard => to_json_string(input)?,
+        JsonStyle::Simple => to_simple_json_string(input)?,
+    };
 
     match output {
         Some(path) => {
-            std::fs::write(path, json_string).context("Failed to write JSON to file")?;
+            atomic_file::write(path, json_string).context("Failed to write JSON to file")?;
             println!("JSON saved to {:?}", path);
         }
         None => {
@@ -42,7 +60,7 @@ pub fn to_json_string(input: &Path) -> Result<String> {
     let obj = open_file(input).context("Failed to open DICOM file")?;
 
     // The in-memory object implements serde-friendly conversions via dicom-json.
-    let inner_obj: &InMemDicomObject<StandardDataDictionary> = &*obj;
+    let inner_obj: &InMemDicomObject<StandardDataDictionary> = &obj;
     let json_obj = DicomJson::from(inner_obj);
 
     let json_string =
@@ -50,6 +68,48 @@ pub fn to_json_string(input: &Path) -> Result<String> {
     Ok(json_string)
 }
 
+/// Convert a DICOM file into the flat, analyst-friendly JSON style.
+pub fn to_simple_json_string(input: &Path) -> Result<String> {
+    let obj = open_file(input).context("Failed to open DICOM file")?;
+    serde_json::to_string_pretty(&simple_value(&obj)).context("Failed to serialize to JSON")
+}
+
+/// Flatten a dataset into keyword -> string pairs. Tags without a dictionary keyword
+/// (private or unknown) are keyed by their `GGGGEEEE` hex form; binary values are summarized.
+pub fn simple_value(obj: &InMemDicomObject) -> Value {
+    let mut map = Map::new();
+    for elem in obj.iter() {
+        let header = elem.header();
+        let value = match elem.value() {
+            DicomValue::Sequence(seq) => {
+                Value::Array(seq.items().iter().map(simple_value).collect())
+            }
+            DicomValue::PixelSequence(p) => Value::String(format!(
+                "<encapsulated: {} fragment(s)>",
+                p.fragments().len()
+            )),
+            DicomValue::Primitive(p) => match header.vr {
+                VR::OB | VR::OW | VR::OF | VR::OD | VR::OL | VR::OV | VR::UN => {
+                    Value::String(format!("<{} bytes>", p.to_bytes().len()))
+                }
+                _ => Value::String(p.to_str().trim_end_matches(['\0', ' ']).to_string()),
+            },
+        };
+        map.insert(keyword(header.tag), value);
+    }
+    Value::Object(map)
+}
+
+fn keyword(tag: Tag) -> String {
+    // Private groups are keyed by tag even when the dictionary has a generic alias for them.
+    let is_private = tag.group() % 2 == 1;
+    (!is_private)
+        .then(|| StandardDataDictionary.by_tag(tag))
+        .flatten()
+        .map(|e| e.alias.to_string())
+        .unwrap_or_else(|| format!("{:04X}{:04X}", tag.group(), tag.element()))
+}
+
 /// Create a DICOM file from a JSON source.
 pub fn from_json(input: &Path, output: &Path) -> Result<()> {
     let file = File::open(input).context("Failed to open JSON file")?;
@@ -66,16 +126,14 @@ pub fn from_json(input: &Path, output: &Path) -> Result<()> {
         .build()?;
 
     let mut file_obj =
-        FileDicomObject::new_empty_with_dict_and_meta(StandardDataDictionary::default(), file_meta);
+        FileDicomObject::new_empty_with_dict_and_meta(StandardDataDictionary, file_meta);
 
     // Copy elements into the file object in insertion order.
     for elem in obj {
         file_obj.put(elem);
     }
 
-    file_obj
-        .write_to_file(output)
-        .context("Failed to write DICOM file")?;
+    atomic_file::write_dicom(output, &file_obj).context("Failed to write DICOM file")?;
 
     println!("DICOM saved to {:?}", output);
 
@@ -115,4 +173,46 @@ mod tests {
             .unwrap();
         assert_eq!(name, "Test^Patient");
     }
+
+    #[test]
+    fn simple_style_flattens_keywords_and_sequences() {
+        use dicom::core::value::DataSetSequence;
+
+        let mut item = InMemDicomObject::new_empty();
+        item.put(DataElement::new(
+            Tag(0x0008, 0x1150),
+            VR::UI,
+            PrimitiveValue::from("1.2.840.10008.5.1.4.1.1.2\0"),
+        ));
+        let mut obj = InMemDicomObject::new_empty();
+        obj.put(DataElement::new(
+            Tag(0x0010, 0x0010),
+            VR::PN,
+            PrimitiveValue::from("Test^Patient"),
+        ));
+        obj.put(DataElement::new(
+            Tag(0x0028, 0x0030),
+            VR::DS,
+            PrimitiveValue::from("0.5\\0.5"),
+        ));
+        obj.put(DataElement::new(
+            Tag(0x0009, 0x0010),
+            VR::LO,
+            PrimitiveValue::from("ACME"),
+        ));
+        obj.put(DataElement::new(
+            Tag(0x0008, 0x1140),
+            VR::SQ,
+            DataSetSequence::from(vec![item]),
+        ));
+
+        let value = simple_value(&obj);
+        assert_eq!(value["PatientName"], "Test^Patient");
+        assert_eq!(value["PixelSpacing"], "0.5\\0.5");
+        assert_eq!(value["00090010"], "ACME");
+        assert_eq!(
+            value["ReferencedImageSequence"][0]["ReferencedSOPClassUID"],
+            "1.2.840.10008.5.1.4.1.1.2"
+        );
+    }
 }
@@ -0,0 +1,230 @@
+//
+// kernels.rs
+// Dicom-Tools-rs
+//
+// Hot-loop pixel kernels (min/max/sum, variance, linear windowing) with an optional SIMD path behind the `simd` feature.
+//
+// Thales Matheus Mendonça Santos - November 2025
+
+/// Running extrema and sum over a pixel buffer.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MinMaxSum {
+    pub min: f32,
+    pub max: f32,
+    pub sum: f64,
+}
+
+/// Compute min, max and sum in a single pass.
+pub fn min_max_sum(values: &[f32]) -> MinMaxSum {
+    #[cfg(feature = "simd")]
+    {
+        simd::min_max_sum(values)
+    }
+    #[cfg(not(feature = "simd"))]
+    {
+        scalar::min_max_sum(values)
+    }
+}
+
+/// Sum of squared deviations from `mean`, used for the standard deviation.
+pub fn sum_squared_diff(values: &[f32], mean: f64) -> f64 {
+    #[cfg(feature = "simd")]
+    {
+        simd::sum_squared_diff(values, mean)
+    }
+    #[cfg(not(feature = "simd"))]
+    {
+        scalar::sum_squared_diff(values, mean)
+    }
+}
+
+/// Apply the DICOM LINEAR window function (PS3.3 C.11.2.1.2.1) and map to 8-bit output; image
+/// export renders 8-bit LINEAR windows through it.
+pub fn window_to_u8(values: &[f32], center: f64, width: f64) -> Vec<u8> {
+    #[cfg(feature = "simd")]
+    {
+        simd::window_to_u8(values, center, width)
+    }
+    #[cfg(not(feature = "simd"))]
+    {
+        scalar::window_to_u8(values, center, width)
+    }
+}
+
+/// Portable reference implementation; always compiled so the SIMD path can be checked against it.
+pub mod scalar {
+    use super::MinMaxSum;
+
+    pub fn min_max_sum(values: &[f32]) -> MinMaxSum {
+        let mut min = f32::INFINITY;
+        let mut max = f32::NEG_INFINITY;
+        let mut sum = 0f64;
+        for &v in values {
+            min = min.min(v);
+            max = max.max(v);
+            sum += v as f64;
+        }
+        MinMaxSum { min, max, sum }
+    }
+
+    pub fn sum_squared_diff(values: &[f32], mean: f64) -> f64 {
+        let mut acc = 0f64;
+        for &v in values {
+            let diff = v as f64 - mean;
+            acc += diff * diff;
+        }
+        acc
+    }
+
+    pub fn window_to_u8(values: &[f32], center: f64, width: f64) -> Vec<u8> {
+        let (lower, scale) = window_params(center, width);
+        values
+            .iter()
+            .map(|&v| window_sample(v, lower, scale))
+            .collect()
+    }
+
+    /// Precompute the offset and slope of the linear ramp so the per-pixel work is one FMA + clamp.
+    pub(super) fn window_params(center: f64, width: f64) -> (f32, f32) {
+        let width = width.max(1.0);
+        let lower = center - 0.5 - (width - 1.0) / 2.0;
+        let scale = 255.0 / (width - 1.0).max(1.0);
+        (lower as f32, scale as f32)
+    }
+
+    pub(super) fn window_sample(v: f32, lower: f32, scale: f32) -> u8 {
+        ((v - lower) * scale).round().clamp(0.0, 255.0) as u8
+    }
+}
+
+#[cfg(feature = "simd")]
+pub mod simd {
+    use super::scalar::{window_params, window_sample};
+    use super::MinMaxSum;
+    use wide::{f32x8, f64x4};
+
+    const LANES: usize = 8;
+
+    pub fn min_max_sum(values: &[f32]) -> MinMaxSum {
+        let chunks = values.chunks_exact(LANES);
+        let tail = chunks.remainder();
+
+        let mut vmin = f32x8::splat(f32::INFINITY);
+        let mut vmax = f32x8::splat(f32::NEG_INFINITY);
+        // Accumulate in f64 lanes to keep the sum as precise as the scalar path.
+        let mut lo = f64x4::ZERO;
+        let mut hi = f64x4::ZERO;
+        for chunk in chunks {
+            let arr: [f32; LANES] = chunk.try_into().unwrap();
+            let v = f32x8::from(arr);
+            vmin = vmin.fast_min(v);
+            vmax = vmax.fast_max(v);
+            lo += f64x4::from([arr[0] as f64, arr[1] as f64, arr[2] as f64, arr[3] as f64]);
+            hi += f64x4::from([arr[4] as f64, arr[5] as f64, arr[6] as f64, arr[7] as f64]);
+        }
+
+        let mut min = vmin.to_array().into_iter().fold(f32::INFINITY, f32::min);
+        let mut max = vmax
+            .to_array()
+            .into_iter()
+            .fold(f32::NEG_INFINITY, f32::max);
+        let mut sum = (lo + hi).reduce_add();
+        for &v in tail {
+            min = min.min(v);
+            max = max.max(v);
+            sum += v as f64;
+        }
+        MinMaxSum { min, max, sum }
+    }
+
+    pub fn sum_squared_diff(values: &[f32], mean: f64) -> f64 {
+        let chunks = values.chunks_exact(LANES);
+        let tail = chunks.remainder();
+
+        let vmean = f64x4::splat(mean);
+        let mut acc = f64x4::ZERO;
+        for chunk in chunks {
+            let lo = f64x4::from([
+                chunk[0] as f64,
+                chunk[1] as f64,
+                chunk[2] as f64,
+                chunk[3] as f64,
+            ]) - vmean;
+            let hi = f64x4::from([
+                chunk[4] as f64,
+                chunk[5] as f64,
+                chunk[6] as f64,
+                chunk[7] as f64,
+            ]) - vmean;
+            acc = lo.mul_add(lo, acc);
+            acc = hi.mul_add(hi, acc);
+        }
+
+        let mut total = acc.reduce_add();
+        for &v in tail {
+            let diff = v as f64 - mean;
+            total += diff * diff;
+        }
+        total
+    }
+
+    pub fn window_to_u8(values: &[f32], center: f64, width: f64) -> Vec<u8> {
+        let (lower, scale) = window_params(center, width);
+        let vlower = f32x8::splat(lower);
+        let vscale = f32x8::splat(scale);
+        let zero = f32x8::ZERO;
+        let top = f32x8::splat(255.0);
+
+        let mut out = Vec::with_capacity(values.len());
+        let chunks = values.chunks_exact(LANES);
+        let tail = chunks.remainder();
+        for chunk in chunks {
+            let arr: [f32; LANES] = chunk.try_into().unwrap();
+            let v = ((f32x8::from(arr) - vlower) * vscale)
+                .round()
+                .fast_max(zero)
+                .fast_min(top);
+            out.extend(v.to_array().into_iter().map(|x| x as u8));
+        }
+        out.extend(tail.iter().map(|&v| window_sample(v, lower, scale)));
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ramp(len: usize) -> Vec<f32> {
+        (0..len).map(|i| (i as f32) * 1.5 - 1024.0).collect()
+    }
+
+    #[test]
+    fn min_max_sum_matches_reference() {
+        let values = ramp(1031);
+        let fast = min_max_sum(&values);
+        let reference = scalar::min_max_sum(&values);
+        assert_eq!(fast.min, reference.min);
+        assert_eq!(fast.max, reference.max);
+        assert!((fast.sum - reference.sum).abs() < 1e-6);
+
+        let mean = reference.sum / values.len() as f64;
+        let fast_var = sum_squared_diff(&values, mean);
+        let ref_var = scalar::sum_squared_diff(&values, mean);
+        assert!((fast_var - ref_var).abs() / ref_var < 1e-9);
+    }
+
+    #[test]
+    fn window_clamps_and_ramps() {
+        let out = window_to_u8(&[-1000.0, 40.0, 1000.0, 39.5], 40.0, 400.0);
+        assert_eq!(out[0], 0);
+        assert_eq!(out[2], 255);
+        assert!(out[1] > 120 && out[1] < 135);
+
+        let values = ramp(517);
+        assert_eq!(
+            window_to_u8(&values, -600.0, 1500.0),
+            scalar::window_to_u8(&values, -600.0, 1500.0)
+        );
+    }
+}
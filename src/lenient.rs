@@ -0,0 +1,476 @@
+//
+// lenient.rs
+// Dicom-Tools-rs
+//
+// Best-effort parsing facade: recovers what it can from malformed files and lists the anomalies found.
+//
+// Thales Matheus Mendonça Santos - November 2025
+
+use std::fmt;
+use std::io::{self, Cursor};
+use std::path::Path;
+
+use dicom::core::header::Header;
+use dicom::core::value::Value;
+use dicom::core::{Tag, VR};
+use dicom::object::file::ReadPreamble;
+use dicom::object::meta::FileMetaTable;
+use dicom::object::{DefaultDicomObject, InMemDicomObject, OpenFileOptions};
+use dicom::transfer_syntax::entries::{
+    DEFLATED_EXPLICIT_VR_LITTLE_ENDIAN, IMPLICIT_VR_LITTLE_ENDIAN, JPIP_REFERENCED_DEFLATE,
+};
+use serde::Serialize;
+
+use crate::anonymize::ElementWalker;
+use crate::dicom_access::{is_big_endian, normalize_big_endian_pixels, EXPLICIT_VR_BIG_ENDIAN};
+
+const PREAMBLE_LENGTH: usize = 128;
+const UNDEFINED_LENGTH: u32 = 0xFFFF_FFFF;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AnomalyKind {
+    /// No DICM magic code or unreadable file meta group; nothing can be recovered.
+    NotDicom,
+    /// An element length that is odd or runs past the end of the file.
+    BadLength,
+    /// The file ends inside an element header or an undefined-length value.
+    PrematureEof,
+    /// An explicit VR field that is not two upper-case letters.
+    InvalidVr,
+    /// A string value holding characters its VR does not allow.
+    IllegalCharacters,
+    /// The full parser rejected a dataset the scan found no structural fault in.
+    Unparseable,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ParseAnomaly {
+    pub kind: AnomalyKind,
+    /// Byte offset of the offending element header, when known.
+    pub offset: Option<u64>,
+    pub tag: Option<String>,
+    pub detail: String,
+}
+
+impl fmt::Display for ParseAnomaly {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.kind)?;
+        if let Some(tag) = &self.tag {
+            write!(f, " {}", tag)?;
+        }
+        if let Some(offset) = self.offset {
+            write!(f, " at byte {}", offset)?;
+        }
+        write!(f, ": {}", self.detail)
+    }
+}
+
+/// How the dataset after the file meta group is actually written, whatever the meta says.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct ObservedEncoding {
+    pub explicit_vr: bool,
+    pub big_endian: bool,
+}
+
+impl fmt::Display for ObservedEncoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} VR {} endian",
+            if self.explicit_vr {
+                "explicit"
+            } else {
+                "implicit"
+            },
+            if self.big_endian { "big" } else { "little" }
+        )
+    }
+}
+
+/// Result of a lenient parse: the object (possibly cut short) plus everything that was wrong.
+pub struct PartialParse {
+    pub object: Option<DefaultDicomObject>,
+    pub anomalies: Vec<ParseAnomaly>,
+    /// When the dataset had to be cut at a structural fault, the byte offset of the cut.
+    /// Elements from there on are missing from `object`.
+    pub truncated_at: Option<u64>,
+}
+
+impl PartialParse {
+    pub fn is_clean(&self) -> bool {
+        self.anomalies.is_empty()
+    }
+
+    /// The recovered object re-encoded as a well-formed file, if anything was cut away.
+    pub fn repaired_bytes(&self) -> Option<Vec<u8>> {
+        self.truncated_at?;
+        let mut bytes = Vec::new();
+        self.object.as_ref()?.write_all(&mut bytes).ok()?;
+        Some(bytes)
+    }
+}
+
+fn anomaly(
+    kind: AnomalyKind,
+    offset: Option<u64>,
+    tag: Option<Tag>,
+    detail: String,
+) -> ParseAnomaly {
+    ParseAnomaly {
+        kind,
+        offset,
+        tag: tag.map(|t| t.to_string()),
+        detail,
+    }
+}
+
+/// Leniently parse the file at `path`; I/O errors are the only hard failure.
+pub fn parse_lenient(path: &Path) -> io::Result<PartialParse> {
+    Ok(parse_lenient_bytes(&std::fs::read(path)?))
+}
+
+/// Parse `bytes` as a DICOM file, cutting the dataset before the first structural fault
+/// instead of failing, and reporting every anomaly met on the way.
+pub fn parse_lenient_bytes(bytes: &[u8]) -> PartialParse {
+    let mut anomalies = Vec::new();
+    let Some(magic) = magic_offset(bytes) else {
+        anomalies.push(anomaly(
+            AnomalyKind::NotDicom,
+            None,
+            None,
+            "no DICM magic code".to_string(),
+        ));
+        return PartialParse {
+            object: None,
+            anomalies,
+            truncated_at: None,
+        };
+    };
+    let meta = match FileMetaTable::from_reader(&bytes[magic..]) {
+        Ok(meta) => meta,
+        Err(e) => {
+            anomalies.push(anomaly(
+                AnomalyKind::NotDicom,
+                Some(magic as u64),
+                None,
+                format!("unreadable file meta group: {}", e),
+            ));
+            return PartialParse {
+                object: None,
+                anomalies,
+                truncated_at: None,
+            };
+        }
+    };
+
+    // Magic code, then the 12-byte group length element, then the rest of the group.
+    let dataset_start = (magic + 4 + 12) as u64 + meta.information_group_length as u64;
+    let ts = meta.transfer_syntax().to_string();
+    let fault = match scan_encoding(&ts) {
+        Some(explicit_vr) => scan_dataset(bytes, dataset_start, explicit_vr, &mut anomalies),
+        None => None,
+    };
+    let end = fault.map_or(bytes.len(), |offset| offset as usize);
+
+    let mut object = match OpenFileOptions::new()
+        .read_preamble(ReadPreamble::Auto)
+        .from_reader(&bytes[..end])
+    {
+        Ok(obj) => Some(obj),
+        Err(e) => {
+            anomalies.push(anomaly(AnomalyKind::Unparseable, None, None, e.to_string()));
+            None
+        }
+    };
+    if let Some(obj) = object.as_mut() {
+        if is_big_endian(obj) {
+            normalize_big_endian_pixels(obj);
+        }
+        check_characters(obj, &mut anomalies);
+    }
+
+    PartialParse {
+        object,
+        anomalies,
+        truncated_at: fault,
+    }
+}
+
+/// Offset of the DICM magic code, after a preamble or at the very start.
+fn magic_offset(bytes: &[u8]) -> Option<usize> {
+    if bytes.get(PREAMBLE_LENGTH..PREAMBLE_LENGTH + 4) == Some(b"DICM") {
+        Some(PREAMBLE_LENGTH)
+    } else if bytes.starts_with(b"DICM") {
+        Some(0)
+    } else {
+        None
+    }
+}
+
+/// Sniff the encoding of the first dataset element; `None` without a readable meta group,
+/// without any dataset element, or for deflated datasets whose bytes are compressed.
+pub fn observe_encoding(bytes: &[u8]) -> Option<ObservedEncoding> {
+    let magic = magic_offset(bytes)?;
+    let meta = FileMetaTable::from_reader(&bytes[magic..]).ok()?;
+    let ts = meta.transfer_syntax();
+    if ts == DEFLATED_EXPLICIT_VR_LITTLE_ENDIAN.uid() || ts == JPIP_REFERENCED_DEFLATE.uid() {
+        return None;
+    }
+    let start = magic + 4 + 12 + meta.information_group_length as usize;
+    let header = bytes.get(start..start + 6)?;
+    // Dataset groups are small numbers, so the byte order reading the smaller group wins.
+    let little = u16::from_le_bytes([header[0], header[1]]);
+    let big = u16::from_be_bytes([header[0], header[1]]);
+    let vr = [header[4], header[5]];
+    Some(ObservedEncoding {
+        explicit_vr: vr.iter().all(u8::is_ascii_uppercase) && VR::from_binary(vr).is_some(),
+        big_endian: big < little,
+    })
+}
+
+/// Encodings the structural scan understands; returns whether VR is explicit.
+fn scan_encoding(ts: &str) -> Option<bool> {
+    if ts == IMPLICIT_VR_LITTLE_ENDIAN.uid() {
+        Some(false)
+    } else if ts == EXPLICIT_VR_BIG_ENDIAN
+        || ts == DEFLATED_EXPLICIT_VR_LITTLE_ENDIAN.uid()
+        || ts == JPIP_REFERENCED_DEFLATE.uid()
+    {
+        None
+    } else {
+        Some(true)
+    }
+}
+
+/// Walk the top-level elements and return the offset of the first one that cannot be read.
+fn scan_dataset(
+    bytes: &[u8],
+    start: u64,
+    explicit_vr: bool,
+    anomalies: &mut Vec<ParseAnomaly>,
+) -> Option<u64> {
+    let total = bytes.len() as u64;
+    let mut walker = ElementWalker::new(Cursor::new(bytes));
+    if walker.skip(start).is_err() || start > total {
+        anomalies.push(anomaly(
+            AnomalyKind::PrematureEof,
+            Some(start),
+            None,
+            "file ends inside the file meta group".to_string(),
+        ));
+        return Some(total.min(start));
+    }
+
+    while walker.pos < total {
+        let offset = walker.pos;
+        let (tag, vr, len) = match walker.read_header(explicit_vr) {
+            Ok(header) => header,
+            Err(_) => {
+                anomalies.push(anomaly(
+                    AnomalyKind::PrematureEof,
+                    Some(offset),
+                    None,
+                    format!(
+                        "{} trailing byte(s) do not hold an element header",
+                        total - offset
+                    ),
+                ));
+                return Some(offset);
+            }
+        };
+        if let Some(vr) = vr {
+            if !vr.iter().all(u8::is_ascii_uppercase) {
+                anomalies.push(anomaly(
+                    AnomalyKind::InvalidVr,
+                    Some(offset),
+                    Some(tag),
+                    format!("VR bytes {:02X} {:02X}", vr[0], vr[1]),
+                ));
+                return Some(offset);
+            }
+        }
+        if len == UNDEFINED_LENGTH {
+            if walker.skip_value(vr, len, explicit_vr).is_err() {
+                anomalies.push(anomaly(
+                    AnomalyKind::PrematureEof,
+                    Some(offset),
+                    Some(tag),
+                    "undefined-length value is never closed".to_string(),
+                ));
+                return Some(offset);
+            }
+            continue;
+        }
+
+        let available = total - walker.pos;
+        if len as u64 > available {
+            anomalies.push(anomaly(
+                AnomalyKind::BadLength,
+                Some(offset),
+                Some(tag),
+                format!("length {} exceeds the {} byte(s) left", len, available),
+            ));
+            return Some(offset);
+        }
+        if len % 2 == 1 {
+            anomalies.push(anomaly(
+                AnomalyKind::BadLength,
+                Some(offset),
+                Some(tag),
+                format!("odd length {}", len),
+            ));
+        }
+        if walker.skip(len as u64).is_err() {
+            return Some(offset);
+        }
+    }
+    None
+}
+
+/// Characters a string VR may hold besides letters and digits, per PS3.5 6.2.
+fn allowed(vr: VR, c: char) -> bool {
+    match vr {
+        VR::UI => c.is_ascii_digit() || c == '.',
+        VR::CS => c.is_ascii_uppercase() || c.is_ascii_digit() || c == ' ' || c == '_',
+        VR::DA | VR::TM | VR::DT | VR::DS | VR::IS => {
+            c.is_ascii_digit() || matches!(c, '+' | '-' | '.' | ' ' | 'e' | 'E' | '&')
+        }
+        VR::AS => c.is_ascii_digit() || matches!(c, 'D' | 'W' | 'M' | 'Y'),
+        VR::LT | VR::ST | VR::UT => {
+            !c.is_control() || matches!(c, '\n' | '\r' | '\t' | '\x0C' | '\x1B')
+        }
+        _ => !c.is_control() || c == '\x1B',
+    }
+}
+
+fn is_string_vr(vr: VR) -> bool {
+    matches!(
+        vr,
+        VR::AE
+            | VR::AS
+            | VR::CS
+            | VR::DA
+            | VR::DS
+            | VR::DT
+            | VR::IS
+            | VR::LO
+            | VR::LT
+            | VR::PN
+            | VR::SH
+            | VR::ST
+            | VR::TM
+            | VR::UC
+            | VR::UI
+            | VR::UT
+    )
+}
+
+fn check_characters(obj: &InMemDicomObject, anomalies: &mut Vec<ParseAnomaly>) {
+    for element in obj.iter() {
+        match element.value() {
+            Value::Sequence(seq) => {
+                for item in seq.items() {
+                    check_characters(item, anomalies);
+                }
+            }
+            Value::Primitive(_) if is_string_vr(element.vr()) => {
+                let Ok(text) = element.to_str() else {
+                    continue;
+                };
+                let vr = element.vr();
+                let bad = text
+                    .trim_end_matches(['\0', ' '])
+                    .split('\\')
+                    .flat_map(|value| value.chars())
+                    .find(|&c| !allowed(vr, c));
+                if let Some(c) = bad {
+                    anomalies.push(anomaly(
+                        AnomalyKind::IllegalCharacters,
+                        None,
+                        Some(element.tag()),
+                        format!("{:?} value holds {:?}", vr, c),
+                    ));
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dicom::core::{DataElement, PrimitiveValue};
+    use dicom::object::{FileDicomObject, FileMetaTableBuilder};
+
+    fn file_bytes(modality: &str) -> Vec<u8> {
+        let meta = FileMetaTableBuilder::new()
+            .transfer_syntax("1.2.840.10008.1.2.1")
+            .media_storage_sop_class_uid("1.2.840.10008.5.1.4.1.1.7")
+            .media_storage_sop_instance_uid("1.2.3.4")
+            .build()
+            .unwrap();
+        let mut obj = FileDicomObject::new_empty_with_meta(meta);
+        obj.put(DataElement::new(
+            Tag(0x0008, 0x0018),
+            VR::UI,
+            PrimitiveValue::from("1.2.3.4"),
+        ));
+        obj.put(DataElement::new(
+            Tag(0x0008, 0x0060),
+            VR::CS,
+            PrimitiveValue::from(modality),
+        ));
+        obj.put(DataElement::new(
+            Tag(0x0010, 0x0010),
+            VR::PN,
+            PrimitiveValue::from("Doe^Jane"),
+        ));
+        obj.put(DataElement::new(
+            Tag(0x7FE0, 0x0010),
+            VR::OB,
+            PrimitiveValue::from(vec![0u8; 64]),
+        ));
+        let mut bytes = Vec::new();
+        obj.write_all(&mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn clean_file_has_no_anomalies() {
+        let parsed = parse_lenient_bytes(&file_bytes("CT"));
+        assert!(parsed.is_clean(), "{:?}", parsed.anomalies);
+        assert!(parsed.truncated_at.is_none());
+        assert!(parsed.repaired_bytes().is_none());
+    }
+
+    #[test]
+    fn truncated_pixel_data_is_cut_and_reported() {
+        let mut bytes = file_bytes("CT");
+        bytes.truncate(bytes.len() - 10);
+
+        let parsed = parse_lenient_bytes(&bytes);
+        assert_eq!(parsed.anomalies.len(), 1);
+        assert_eq!(parsed.anomalies[0].kind, AnomalyKind::BadLength);
+        assert_eq!(parsed.anomalies[0].tag.as_deref(), Some("(7FE0,0010)"));
+        let obj = parsed.object.as_ref().expect("recovered object");
+        assert!(obj.element(Tag(0x0010, 0x0010)).is_ok());
+        assert!(obj.element(Tag(0x7FE0, 0x0010)).is_err());
+
+        let repaired = parsed.repaired_bytes().expect("repaired");
+        assert!(parse_lenient_bytes(&repaired).is_clean());
+    }
+
+    #[test]
+    fn illegal_characters_and_junk_are_reported() {
+        let parsed = parse_lenient_bytes(&file_bytes("c\u{7}"));
+        assert_eq!(parsed.anomalies.len(), 1);
+        assert_eq!(parsed.anomalies[0].kind, AnomalyKind::IllegalCharacters);
+        assert!(parsed.object.is_some());
+
+        let junk = parse_lenient_bytes(b"not a dicom file");
+        assert!(junk.object.is_none());
+        assert_eq!(junk.anomalies[0].kind, AnomalyKind::NotDicom);
+    }
+}
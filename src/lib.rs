@@ -7,20 +7,86 @@
 // Thales Matheus Mendonça Santos - November 2025
 
 // Public surface of the library: each module mirrors a CLI verb or shared utility.
+pub mod annotations;
 pub mod anonymize;
+pub mod archive;
+pub mod atomic_file;
+pub mod audit;
+pub mod availability;
 pub mod batch;
+pub mod capabilities;
+pub mod cine;
 pub mod cli;
+pub mod codes;
+pub mod concatenation;
+pub mod consistency;
+pub mod derivation;
+pub mod descriptors;
 pub mod dicom_access;
+pub mod dimse;
+pub mod dimse_trace;
 pub mod dump;
+pub mod echo_scan;
+pub mod element_stream;
+pub mod encryption;
+pub mod fhir;
+pub mod float_pixels;
+pub mod frame_extract;
+pub mod harmonize;
+pub mod icon;
 pub mod image;
+pub mod jobs;
+pub mod joint_histogram;
 pub mod json;
+pub mod kernels;
+pub mod lenient;
+pub mod listing;
+pub mod lut;
+pub mod measure;
+pub mod measurement_report;
 pub mod metadata;
 pub mod models;
+pub mod mpps;
+pub mod padding;
+pub mod parametric_map;
+pub mod person_name;
+#[cfg(feature = "parquet")]
+pub mod pixel_export;
+pub mod preview_cache;
+pub mod progress;
+pub mod registration;
+pub mod rescale;
+pub mod roi_mask;
+pub mod router;
+pub mod scp;
+pub mod screening;
 pub mod scu;
+pub mod scu_async;
+pub mod send_queue;
+pub mod series_split;
+pub mod sharing;
+pub mod size_report;
 pub mod stats;
 pub mod storage;
+pub mod stow;
+pub mod study_query;
+pub mod synth;
+pub mod tag_stats;
+pub mod temporal;
+pub mod testing;
+#[cfg(feature = "bench")]
+pub mod throughput;
+pub mod time_curves;
+pub mod toolkit;
 pub mod transcode;
+pub mod transfer_limits;
+pub mod ts_preference;
+pub mod upload_pipeline;
 pub mod validate;
+pub mod volume;
+pub mod wado;
 pub mod web;
+pub mod worklist;
 
 pub use cli::{run as run_cli, Cli, Commands};
+pub use toolkit::DicomToolkit;
@@ -0,0 +1,284 @@
+//
+// listing.rs
+// Dicom-Tools-rs
+//
+// Shared pagination, sorting, filtering and field selection for the web listing endpoints
+// (files, studies, series), so every listing accepts the same query parameters.
+//
+// Thales Matheus Mendonça Santos - November 2025
+
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+
+use anyhow::{anyhow, bail, Result};
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+pub const DEFAULT_LIMIT: usize = 50;
+pub const MAX_LIMIT: usize = 500;
+
+/// Sort keys shared by every listing, each backed by a field all listings carry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortKey {
+    Date,
+    Patient,
+    Size,
+}
+
+impl SortKey {
+    fn parse(value: &str) -> Result<Self> {
+        match value {
+            "date" => Ok(SortKey::Date),
+            "patient" => Ok(SortKey::Patient),
+            "size" => Ok(SortKey::Size),
+            other => bail!(
+                "Unknown sort key {:?} (expected date, patient or size)",
+                other
+            ),
+        }
+    }
+
+    pub fn field(self) -> &'static str {
+        match self {
+            SortKey::Date => "study_date",
+            SortKey::Patient => "patient_name",
+            SortKey::Size => "size_bytes",
+        }
+    }
+}
+
+/// Query parameters of a listing: `limit`, `offset`, `sort`, `order` (asc or desc),
+/// `fields` (comma-separated fields to return) and any other `field=value` pair as a
+/// case-insensitive equality filter.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ListQuery {
+    pub limit: usize,
+    pub offset: usize,
+    pub sort: Option<SortKey>,
+    pub descending: bool,
+    pub fields: Option<Vec<String>>,
+    pub filters: BTreeMap<String, String>,
+}
+
+impl Default for ListQuery {
+    fn default() -> Self {
+        Self {
+            limit: DEFAULT_LIMIT,
+            offset: 0,
+            sort: None,
+            descending: false,
+            fields: None,
+            filters: BTreeMap::new(),
+        }
+    }
+}
+
+impl ListQuery {
+    pub fn parse(params: impl IntoIterator<Item = (String, String)>) -> Result<Self> {
+        let mut query = ListQuery::default();
+        for (key, value) in params {
+            match key.as_str() {
+                "limit" => {
+                    query.limit = value
+                        .parse()
+                        .map_err(|_| anyhow!("limit must be a number"))?;
+                    if !(1..=MAX_LIMIT).contains(&query.limit) {
+                        bail!("limit must be between 1 and {}", MAX_LIMIT);
+                    }
+                }
+                "offset" => {
+                    query.offset = value
+                        .parse()
+                        .map_err(|_| anyhow!("offset must be a number"))?
+                }
+                "sort" => query.sort = Some(SortKey::parse(&value)?),
+                "order" => {
+                    query.descending = match value.as_str() {
+                        "asc" => false,
+                        "desc" => true,
+                        other => bail!("Unknown order {:?} (expected asc or desc)", other),
+                    }
+                }
+                "fields" => {
+                    query.fields = Some(
+                        value
+                            .split(',')
+                            .map(|field| field.trim().to_string())
+                            .filter(|field| !field.is_empty())
+                            .collect(),
+                    )
+                }
+                _ => {
+                    query.filters.insert(key, value);
+                }
+            }
+        }
+        Ok(query)
+    }
+}
+
+/// One page of a listing.
+#[derive(Debug, Clone, Serialize)]
+pub struct Page {
+    /// Items matching the filters, before pagination.
+    pub total: usize,
+    pub offset: usize,
+    pub limit: usize,
+    /// Offset of the following page, when there is one.
+    pub next_offset: Option<usize>,
+    pub items: Vec<Value>,
+}
+
+/// Filter, sort, paginate and project `items` (JSON objects). Filters and `fields` may only
+/// name fields in `known_fields`.
+pub fn page(items: Vec<Value>, query: &ListQuery, known_fields: &[&str]) -> Result<Page> {
+    let requested = query.filters.keys().chain(query.fields.iter().flatten());
+    for field in requested {
+        if !known_fields.contains(&field.as_str()) {
+            bail!(
+                "Unknown field {:?} (available: {})",
+                field,
+                known_fields.join(", ")
+            );
+        }
+    }
+
+    let mut items: Vec<Value> = items
+        .into_iter()
+        .filter(|item| {
+            query
+                .filters
+                .iter()
+                .all(|(field, wanted)| matches_filter(&item[field.as_str()], wanted))
+        })
+        .collect();
+    if let Some(key) = query.sort {
+        // Stable, so ties keep the listing's natural order; missing values always sort last.
+        items.sort_by(|a, b| {
+            let (a, b) = (&a[key.field()], &b[key.field()]);
+            match (a.is_null(), b.is_null()) {
+                (true, true) => Ordering::Equal,
+                (true, false) => Ordering::Greater,
+                (false, true) => Ordering::Less,
+                (false, false) if query.descending => compare(b, a),
+                (false, false) => compare(a, b),
+            }
+        });
+    }
+
+    let total = items.len();
+    let items: Vec<Value> = items
+        .into_iter()
+        .skip(query.offset)
+        .take(query.limit)
+        .map(|item| project(item, query.fields.as_deref()))
+        .collect();
+    let end = query.offset.saturating_add(items.len());
+    Ok(Page {
+        total,
+        offset: query.offset,
+        limit: query.limit,
+        next_offset: (end < total).then_some(end),
+        items,
+    })
+}
+
+/// Case-insensitive equality against a scalar, or against any element of an array.
+fn matches_filter(value: &Value, wanted: &str) -> bool {
+    match value {
+        Value::String(text) => text.eq_ignore_ascii_case(wanted),
+        Value::Array(values) => values.iter().any(|v| matches_filter(v, wanted)),
+        Value::Null => false,
+        other => other.to_string().eq_ignore_ascii_case(wanted),
+    }
+}
+
+fn compare(a: &Value, b: &Value) -> Ordering {
+    match (a, b) {
+        (Value::Number(a), Value::Number(b)) => a
+            .as_f64()
+            .partial_cmp(&b.as_f64())
+            .unwrap_or(Ordering::Equal),
+        (Value::String(a), Value::String(b)) => a.to_lowercase().cmp(&b.to_lowercase()),
+        _ => a.to_string().cmp(&b.to_string()),
+    }
+}
+
+fn project(item: Value, fields: Option<&[String]>) -> Value {
+    match (item, fields) {
+        (Value::Object(mut object), Some(fields)) => Value::Object(
+            fields
+                .iter()
+                .filter_map(|field| object.remove(field).map(|value| (field.clone(), value)))
+                .collect::<Map<String, Value>>(),
+        ),
+        (item, _) => item,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const FIELDS: &[&str] = &[
+        "name",
+        "patient_name",
+        "study_date",
+        "size_bytes",
+        "modality",
+    ];
+
+    fn query(params: &[(&str, &str)]) -> ListQuery {
+        ListQuery::parse(params.iter().map(|(k, v)| (k.to_string(), v.to_string()))).expect("query")
+    }
+
+    fn items() -> Vec<Value> {
+        vec![
+            json!({"name": "a", "patient_name": "Zed", "study_date": "20240102", "size_bytes": 30, "modality": "CT"}),
+            json!({"name": "b", "patient_name": "amy", "study_date": null, "size_bytes": 10, "modality": "MR"}),
+            json!({"name": "c", "patient_name": "Bob", "study_date": "20230101", "size_bytes": 20, "modality": "ct"}),
+        ]
+    }
+
+    fn names(page: &Page) -> Vec<&str> {
+        page.items
+            .iter()
+            .map(|item| item["name"].as_str().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn sorts_filters_and_paginates() {
+        let page = page(
+            items(),
+            &query(&[("sort", "size"), ("order", "desc")]),
+            FIELDS,
+        )
+        .unwrap();
+        assert_eq!(names(&page), ["a", "c", "b"]);
+
+        // Missing dates sort last in either direction.
+        let by_date = super::page(items(), &query(&[("sort", "date")]), FIELDS).unwrap();
+        assert_eq!(names(&by_date), ["c", "a", "b"]);
+
+        let ct = super::page(
+            items(),
+            &query(&[("modality", "CT"), ("sort", "patient"), ("limit", "1")]),
+            FIELDS,
+        )
+        .unwrap();
+        assert_eq!((ct.total, ct.next_offset), (2, Some(1)));
+        assert_eq!(names(&ct), ["c"]);
+    }
+
+    #[test]
+    fn projects_fields_and_rejects_unknown_ones() {
+        let page = page(items(), &query(&[("fields", "name,size_bytes")]), FIELDS).unwrap();
+        assert_eq!(page.items[0], json!({"name": "a", "size_bytes": 30}));
+
+        assert!(super::page(items(), &query(&[("colour", "red")]), FIELDS).is_err());
+        assert!(ListQuery::parse([("limit".to_string(), "0".to_string())]).is_err());
+        assert!(ListQuery::parse([("sort".to_string(), "height".to_string())]).is_err());
+    }
+}
@@ -0,0 +1,442 @@
+//
+// lut.rs
+// Dicom-Tools-rs
+//
+// Reads explicit Modality LUT, VOI LUT and Palette Color LUTs and applies them to stored pixel values.
+//
+// Thales Matheus Mendonça Santos - November 2025
+
+use dicom::core::Tag;
+use dicom::object::InMemDicomObject;
+use serde::Serialize;
+
+use crate::dicom_access::ElementAccess;
+use crate::models::VoiWindow;
+
+const MODALITY_LUT_SEQUENCE: Tag = Tag(0x0028, 0x3000);
+const VOI_LUT_SEQUENCE: Tag = Tag(0x0028, 0x3010);
+const LUT_DESCRIPTOR: Tag = Tag(0x0028, 0x3002);
+const LUT_EXPLANATION: Tag = Tag(0x0028, 0x3003);
+const MODALITY_LUT_TYPE: Tag = Tag(0x0028, 0x3004);
+const LUT_DATA: Tag = Tag(0x0028, 0x3006);
+const PIXEL_REPRESENTATION: Tag = Tag(0x0028, 0x0103);
+const WINDOW_CENTER: Tag = Tag(0x0028, 0x1050);
+const WINDOW_WIDTH: Tag = Tag(0x0028, 0x1051);
+const WINDOW_EXPLANATION: Tag = Tag(0x0028, 0x1055);
+const VOI_LUT_FUNCTION: Tag = Tag(0x0028, 0x1056);
+const PRESENTATION_LUT_SHAPE: Tag = Tag(0x2050, 0x0020);
+const PHOTOMETRIC_INTERPRETATION: Tag = Tag(0x0028, 0x0004);
+const PIXEL_PRESENTATION: Tag = Tag(0x0008, 0x9205);
+const PALETTE_DESCRIPTORS: [Tag; 3] = [
+    Tag(0x0028, 0x1101),
+    Tag(0x0028, 0x1102),
+    Tag(0x0028, 0x1103),
+];
+const PALETTE_DATA: [Tag; 3] = [
+    Tag(0x0028, 0x1201),
+    Tag(0x0028, 0x1202),
+    Tag(0x0028, 0x1203),
+];
+
+/// Which transform a LUT implements.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum LutKind {
+    Modality,
+    Voi,
+    Palette,
+}
+
+/// An explicit lookup table decoded from a LUT sequence item.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LookupTable {
+    pub kind: LutKind,
+    /// Stored (or modality) value mapped to the first entry.
+    pub first_mapped: i32,
+    /// Bits per entry from the third descriptor value.
+    pub bits: u16,
+    pub data: Vec<u16>,
+    pub explanation: Option<String>,
+    pub lut_type: Option<String>,
+}
+
+impl LookupTable {
+    /// Parse one LUT sequence item. `signed_input` tells how to read the first-mapped value
+    /// when it is encoded as US (PS3.3 C.11.1.1: it follows the pixel representation).
+    pub fn from_item(item: &InMemDicomObject, kind: LutKind, signed_input: bool) -> Option<Self> {
+        let mut lut = Self::from_tags(item, LUT_DESCRIPTOR, LUT_DATA, kind, signed_input)?;
+        lut.explanation = item
+            .element_str(LUT_EXPLANATION)
+            .map(|s| s.trim().to_string());
+        lut.lut_type = item
+            .element_str(MODALITY_LUT_TYPE)
+            .map(|s| s.trim().to_string());
+        Some(lut)
+    }
+
+    /// Parse a descriptor/data pair, as used by LUT sequence items and the top-level
+    /// Palette Color LUTs alike.
+    fn from_tags(
+        obj: &InMemDicomObject,
+        descriptor_tag: Tag,
+        data_tag: Tag,
+        kind: LutKind,
+        signed_input: bool,
+    ) -> Option<Self> {
+        let descriptor: Vec<i32> = obj
+            .element(descriptor_tag)
+            .ok()?
+            .to_multi_int::<i32>()
+            .ok()?;
+        if descriptor.len() < 3 {
+            return None;
+        }
+        // An entry count of 0 means 2^16 entries.
+        let entries = if descriptor[0] == 0 {
+            65536
+        } else {
+            descriptor[0] as u32 & 0xFFFF
+        } as usize;
+        let mut first_mapped = descriptor[1];
+        if signed_input && first_mapped > i16::MAX as i32 {
+            first_mapped -= 65536;
+        }
+        let bits = descriptor[2].clamp(1, 16) as u16;
+
+        let bytes = obj.element(data_tag).ok()?.to_bytes().ok()?;
+        let mut data: Vec<u16> = bytes
+            .chunks_exact(2)
+            .map(|c| u16::from_le_bytes([c[0], c[1]]))
+            .collect();
+        // Some writers pack two 8-bit entries per 16-bit word.
+        if bits <= 8 && data.len() * 2 == entries {
+            data = bytes.iter().map(|b| *b as u16).collect();
+        }
+        if data.is_empty() {
+            return None;
+        }
+        data.truncate(entries);
+
+        Some(Self {
+            kind,
+            first_mapped,
+            bits,
+            data,
+            explanation: None,
+            lut_type: None,
+        })
+    }
+
+    /// Whether `value` falls inside the mapped input range rather than being clamped.
+    pub fn covers(&self, value: f64) -> bool {
+        let offset = value.round() as i64 - self.first_mapped as i64;
+        (0..self.data.len() as i64).contains(&offset)
+    }
+
+    /// Look up a value, clamping to the first/last entry outside the mapped range.
+    pub fn apply(&self, value: f64) -> f64 {
+        let idx =
+            (value.round() as i64 - self.first_mapped as i64).clamp(0, self.data.len() as i64 - 1);
+        self.data[idx as usize] as f64
+    }
+
+    /// Largest value an entry can hold given the descriptor bit depth.
+    pub fn max_output(&self) -> f64 {
+        ((1u32 << self.bits) - 1) as f64
+    }
+
+    pub fn summary(&self) -> LutSummary {
+        LutSummary {
+            kind: self.kind,
+            entries: self.data.len(),
+            first_mapped: self.first_mapped,
+            bits: self.bits,
+            min_output: self.data.iter().copied().min().unwrap_or(0),
+            max_output: self.data.iter().copied().max().unwrap_or(0),
+            explanation: self.explanation.clone(),
+            lut_type: self.lut_type.clone(),
+        }
+    }
+}
+
+/// Shape of a LUT for display in Info and API responses.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LutSummary {
+    pub kind: LutKind,
+    pub entries: usize,
+    pub first_mapped: i32,
+    pub bits: u16,
+    pub min_output: u16,
+    pub max_output: u16,
+    pub explanation: Option<String>,
+    pub lut_type: Option<String>,
+}
+
+/// Presentation LUT Shape (PS3.3 C.11.6): the last grayscale stage before display.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum PresentationLutShape {
+    Identity,
+    /// Display is inverted: low values render bright.
+    Inverse,
+}
+
+impl PresentationLutShape {
+    pub fn from_object(obj: &InMemDicomObject) -> Option<Self> {
+        match obj
+            .element_str(PRESENTATION_LUT_SHAPE)?
+            .trim_matches(['\0', ' '])
+        {
+            "IDENTITY" => Some(Self::Identity),
+            "INVERSE" => Some(Self::Inverse),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Identity => "IDENTITY",
+            Self::Inverse => "INVERSE",
+        }
+    }
+}
+
+/// Red/Green/Blue Palette Color LUTs (PS3.3 C.7.6.3.1.5), either driving a PALETTE COLOR
+/// image or, when `supplemental`, coloring part of the range of a grayscale image
+/// (PS3.3 C.7.6.19).
+#[derive(Debug, Clone, PartialEq)]
+pub struct PaletteLut {
+    pub red: LookupTable,
+    pub green: LookupTable,
+    pub blue: LookupTable,
+    pub supplemental: bool,
+}
+
+impl PaletteLut {
+    /// Palette of a PALETTE COLOR image, or a supplemental palette of an image whose Pixel
+    /// Presentation is COLOR or MIXED. Segmented palettes are not decoded.
+    pub fn from_object(obj: &InMemDicomObject) -> Option<Self> {
+        let photometric = obj.element_str(PHOTOMETRIC_INTERPRETATION)?;
+        let presentation = obj.element_str(PIXEL_PRESENTATION);
+        let supplemental = match photometric.trim_matches(['\0', ' ']) {
+            "PALETTE COLOR" => false,
+            "MONOCHROME1" | "MONOCHROME2"
+                if matches!(
+                    presentation.as_deref().map(|p| p.trim_matches(['\0', ' '])),
+                    Some("COLOR") | Some("MIXED")
+                ) =>
+            {
+                true
+            }
+            _ => return None,
+        };
+        // Supplemental palettes index stored values, which may be signed.
+        let signed = supplemental && obj.element_u32(PIXEL_REPRESENTATION) == Some(1);
+        let channel = |i: usize| {
+            let mut lut = LookupTable::from_tags(
+                obj,
+                PALETTE_DESCRIPTORS[i],
+                PALETTE_DATA[i],
+                LutKind::Palette,
+                signed,
+            )?;
+            lut.explanation = Some(["Red", "Green", "Blue"][i].to_string());
+            Some(lut)
+        };
+        Some(Self {
+            red: channel(0)?,
+            green: channel(1)?,
+            blue: channel(2)?,
+            supplemental,
+        })
+    }
+
+    /// Whether a stored value is colored by the palette.
+    pub fn covers(&self, value: f64) -> bool {
+        self.red.covers(value)
+    }
+
+    /// RGB color of a stored value, each component scaled to [0, 1].
+    pub fn rgb(&self, value: f64) -> [f64; 3] {
+        self.tables().map(|lut| lut.apply(value) / lut.max_output())
+    }
+
+    fn tables(&self) -> [&LookupTable; 3] {
+        [&self.red, &self.green, &self.blue]
+    }
+}
+
+/// All explicit LUTs attached to an image.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ExplicitLuts {
+    pub modality: Option<LookupTable>,
+    pub voi: Vec<LookupTable>,
+    pub presentation_shape: Option<PresentationLutShape>,
+    pub palette: Option<PaletteLut>,
+}
+
+impl ExplicitLuts {
+    pub fn from_object(obj: &InMemDicomObject) -> Self {
+        let signed = obj.element_u32(PIXEL_REPRESENTATION) == Some(1);
+        let items = |tag: Tag| {
+            obj.element(tag)
+                .ok()
+                .and_then(|e| e.items().map(|items| items.to_vec()))
+                .unwrap_or_default()
+        };
+
+        let modality = items(MODALITY_LUT_SEQUENCE)
+            .first()
+            .and_then(|item| LookupTable::from_item(item, LutKind::Modality, signed));
+        // VOI LUT input is the modality output, which is unsigned when a Modality LUT exists.
+        let voi_signed = signed && modality.is_none();
+        let voi = items(VOI_LUT_SEQUENCE)
+            .iter()
+            .filter_map(|item| LookupTable::from_item(item, LutKind::Voi, voi_signed))
+            .collect();
+
+        Self {
+            modality,
+            voi,
+            presentation_shape: PresentationLutShape::from_object(obj),
+            palette: PaletteLut::from_object(obj),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.modality.is_none() && self.voi.is_empty() && self.palette.is_none()
+    }
+
+    pub fn summaries(&self) -> Vec<LutSummary> {
+        self.modality
+            .iter()
+            .chain(self.voi.iter())
+            .chain(self.palette.iter().flat_map(PaletteLut::tables))
+            .map(LookupTable::summary)
+            .collect()
+    }
+}
+
+/// Every Window Center/Width pair of the object, each with the VOI LUT Function and
+/// explanation at the same value position (the first function applies to all when only
+/// one is given).
+pub fn voi_windows(obj: &InMemDicomObject) -> Vec<VoiWindow> {
+    let values = |tag: Tag| -> Vec<String> {
+        obj.element_str(tag)
+            .map(|s| {
+                s.split('\\')
+                    .map(|v| v.trim_matches(['\0', ' ']).to_string())
+                    .collect()
+            })
+            .unwrap_or_default()
+    };
+    let numbers =
+        |tag: Tag| -> Vec<Option<f64>> { values(tag).iter().map(|v| v.parse().ok()).collect() };
+    let functions = values(VOI_LUT_FUNCTION);
+    let explanations = values(WINDOW_EXPLANATION);
+
+    numbers(WINDOW_CENTER)
+        .into_iter()
+        .zip(numbers(WINDOW_WIDTH))
+        .enumerate()
+        .filter_map(|(i, pair)| match pair {
+            (Some(center), Some(width)) => Some(VoiWindow {
+                center,
+                width,
+                function: functions
+                    .get(i)
+                    .or(functions.first())
+                    .filter(|f| !f.is_empty())
+                    .cloned()
+                    .unwrap_or_else(|| "LINEAR".to_string()),
+                explanation: explanations.get(i).filter(|e| !e.is_empty()).cloned(),
+            }),
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dicom::core::value::DataSetSequence;
+    use dicom::core::{DataElement, PrimitiveValue, VR};
+    use dicom::object::InMemDicomObject;
+
+    fn lut_item(descriptor: [u16; 3], data: Vec<u16>) -> InMemDicomObject {
+        let mut item = InMemDicomObject::new_empty();
+        item.put(DataElement::new(
+            LUT_DESCRIPTOR,
+            VR::US,
+            PrimitiveValue::U16(descriptor.to_vec().into()),
+        ));
+        item.put(DataElement::new(
+            LUT_DATA,
+            VR::OW,
+            PrimitiveValue::U16(data.into()),
+        ));
+        item
+    }
+
+    #[test]
+    fn signed_first_mapped_and_clamping() {
+        let mut obj = InMemDicomObject::new_empty();
+        obj.put(DataElement::new(
+            PIXEL_REPRESENTATION,
+            VR::US,
+            PrimitiveValue::from(1_u16),
+        ));
+        // First mapped value 65534 == -2 for signed pixel data.
+        let item = lut_item([4, 65534, 12], vec![10, 20, 30, 40]);
+        obj.put(DataElement::new(
+            MODALITY_LUT_SEQUENCE,
+            VR::SQ,
+            DataSetSequence::from(vec![item]),
+        ));
+
+        let luts = ExplicitLuts::from_object(&obj);
+        assert_eq!(luts.summaries()[0].entries, 4);
+        let modality = luts.modality.expect("modality lut");
+        assert_eq!(modality.first_mapped, -2);
+        assert_eq!(modality.apply(-5.0), 10.0);
+        assert_eq!(modality.apply(-1.0), 20.0);
+        assert_eq!(modality.apply(100.0), 40.0);
+        assert_eq!(modality.max_output(), 4095.0);
+    }
+
+    #[test]
+    fn supplemental_palette_covers_only_its_range() {
+        let mut obj = InMemDicomObject::new_empty();
+        for (tag, value) in [
+            (PHOTOMETRIC_INTERPRETATION, "MONOCHROME2"),
+            (PIXEL_PRESENTATION, "MIXED"),
+        ] {
+            obj.put(DataElement::new(tag, VR::CS, PrimitiveValue::from(value)));
+        }
+        for (descriptor, data) in PALETTE_DESCRIPTORS.into_iter().zip(PALETTE_DATA) {
+            obj.put(DataElement::new(
+                descriptor,
+                VR::US,
+                PrimitiveValue::U16([2_u16, 1000, 8][..].into()),
+            ));
+            // Two 8-bit entries packed into one word.
+            obj.put(DataElement::new(
+                data,
+                VR::OW,
+                PrimitiveValue::U16([0xFF00_u16][..].into()),
+            ));
+        }
+
+        let palette = PaletteLut::from_object(&obj).expect("supplemental palette");
+        assert!(palette.supplemental);
+        assert!(!palette.covers(999.0));
+        assert!(palette.covers(1001.0));
+        assert_eq!(palette.rgb(1000.0), [0.0; 3]);
+        assert_eq!(palette.rgb(1001.0), [1.0; 3]);
+
+        obj.put(DataElement::new(
+            PIXEL_PRESENTATION,
+            VR::CS,
+            PrimitiveValue::from("MONOCHROME"),
+        ));
+        assert!(PaletteLut::from_object(&obj).is_none());
+    }
+}
@@ -0,0 +1,308 @@
+//
+// measure.rs
+// Dicom-Tools-rs
+//
+// Converts pixel coordinates into millimetres using pixel spacing and plane geometry, and measures distances/areas.
+//
+// Thales Matheus Mendonça Santos - November 2025
+
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use dicom::core::Tag;
+use dicom::object::{open_file, InMemDicomObject};
+use serde::Serialize;
+
+const PIXEL_SPACING: Tag = Tag(0x0028, 0x0030);
+const IMAGER_PIXEL_SPACING: Tag = Tag(0x0018, 0x1164);
+pub(crate) const IMAGE_ORIENTATION: Tag = Tag(0x0020, 0x0037);
+pub(crate) const IMAGE_POSITION: Tag = Tag(0x0020, 0x0032);
+const SHARED_FUNCTIONAL_GROUPS: Tag = Tag(0x5200, 0x9229);
+const PER_FRAME_FUNCTIONAL_GROUPS: Tag = Tag(0x5200, 0x9230);
+const PIXEL_MEASURES_SEQUENCE: Tag = Tag(0x0028, 0x9110);
+pub(crate) const PLANE_ORIENTATION_SEQUENCE: Tag = Tag(0x0020, 0x9116);
+pub(crate) const PLANE_POSITION_SEQUENCE: Tag = Tag(0x0020, 0x9113);
+
+/// Where the pixel spacing used for a measurement came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum SpacingSource {
+    /// Pixel Spacing (0028,0030): calibrated in the patient plane.
+    PixelSpacing,
+    /// Imager Pixel Spacing (0018,1164): at the detector, so it includes geometric magnification.
+    ImagerPixelSpacing,
+}
+
+/// A point in image space: `x` is the column index, `y` the row index (both may be fractional).
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct PixelPoint {
+    pub x: f64,
+    pub y: f64,
+}
+
+impl std::str::FromStr for PixelPoint {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (x, y) = s
+            .split_once(',')
+            .with_context(|| format!("Point '{}' must be formatted as x,y", s))?;
+        Ok(Self {
+            x: x.trim().parse().context("Invalid x coordinate")?,
+            y: y.trim().parse().context("Invalid y coordinate")?,
+        })
+    }
+}
+
+/// Geometry needed to map pixel indices of one frame into millimetres.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FrameGeometry {
+    /// Distance between adjacent rows (mm).
+    pub row_spacing: f64,
+    /// Distance between adjacent columns (mm).
+    pub column_spacing: f64,
+    pub spacing_source: SpacingSource,
+    /// Row then column direction cosines, when the image is placed in patient space.
+    pub orientation: Option<[f64; 6]>,
+    /// Patient position of the centre of the first transmitted pixel.
+    pub position: Option<[f64; 3]>,
+}
+
+impl FrameGeometry {
+    /// Resolve spacing, orientation and position for `frame`, looking at top-level
+    /// attributes first and then the enhanced multi-frame functional groups.
+    pub fn for_frame(obj: &InMemDicomObject, frame: u32) -> Result<Self> {
+        let (spacing, spacing_source) =
+            match frame_multi_f64(obj, frame, PIXEL_MEASURES_SEQUENCE, PIXEL_SPACING) {
+                Some(v) => (v, SpacingSource::PixelSpacing),
+                None => {
+                    match frame_multi_f64(obj, frame, PIXEL_MEASURES_SEQUENCE, IMAGER_PIXEL_SPACING)
+                    {
+                        Some(v) => (v, SpacingSource::ImagerPixelSpacing),
+                        None => bail!("No Pixel Spacing or Imager Pixel Spacing available"),
+                    }
+                }
+            };
+        if spacing.len() < 2 || spacing.iter().any(|s| *s <= 0.0) {
+            bail!(
+                "Pixel spacing must have two positive values, got {:?}",
+                spacing
+            );
+        }
+
+        let orientation =
+            frame_multi_f64(obj, frame, PLANE_ORIENTATION_SEQUENCE, IMAGE_ORIENTATION)
+                .and_then(|v| <[f64; 6]>::try_from(v.get(..6)?).ok());
+        let position = frame_multi_f64(obj, frame, PLANE_POSITION_SEQUENCE, IMAGE_POSITION)
+            .and_then(|v| <[f64; 3]>::try_from(v.get(..3)?).ok());
+
+        Ok(Self {
+            row_spacing: spacing[0],
+            column_spacing: spacing[1],
+            spacing_source,
+            orientation,
+            position,
+        })
+    }
+
+    /// Map a pixel point to millimetres: patient coordinates when the plane is known,
+    /// otherwise in-plane coordinates relative to the top-left pixel (z = 0).
+    pub fn to_mm(&self, point: PixelPoint) -> [f64; 3] {
+        let dx = point.x * self.column_spacing;
+        let dy = point.y * self.row_spacing;
+        match (self.orientation, self.position) {
+            (Some(o), Some(p)) => [
+                p[0] + o[0] * dx + o[3] * dy,
+                p[1] + o[1] * dx + o[4] * dy,
+                p[2] + o[2] * dx + o[5] * dy,
+            ],
+            _ => [dx, dy, 0.0],
+        }
+    }
+
+    /// Euclidean distance between two pixel points, in millimetres.
+    pub fn distance_mm(&self, a: PixelPoint, b: PixelPoint) -> f64 {
+        let (pa, pb) = (self.to_mm(a), self.to_mm(b));
+        pa.iter()
+            .zip(pb.iter())
+            .map(|(x, y)| (x - y) * (x - y))
+            .sum::<f64>()
+            .sqrt()
+    }
+
+    /// Area of the closed polygon through `points`, in square millimetres.
+    pub fn polygon_area_mm2(&self, points: &[PixelPoint]) -> f64 {
+        if points.len() < 3 {
+            return 0.0;
+        }
+        // Shoelace formula in pixel units, then scale by the pixel footprint.
+        let twice_area: f64 = points
+            .iter()
+            .zip(points.iter().cycle().skip(1))
+            .map(|(a, b)| a.x * b.y - b.x * a.y)
+            .sum();
+        (twice_area.abs() / 2.0) * self.row_spacing * self.column_spacing
+    }
+}
+
+/// Result of measuring a polyline (and, with three or more points, the enclosed polygon).
+#[derive(Debug, Clone, Serialize)]
+pub struct Measurement {
+    pub frame: u32,
+    pub geometry: FrameGeometry,
+    pub points_mm: Vec<[f64; 3]>,
+    pub segments_mm: Vec<f64>,
+    pub total_length_mm: f64,
+    pub area_mm2: Option<f64>,
+}
+
+pub fn measure(obj: &InMemDicomObject, frame: u32, points: &[PixelPoint]) -> Result<Measurement> {
+    if points.len() < 2 {
+        bail!("At least two points are required to measure");
+    }
+    let geometry = FrameGeometry::for_frame(obj, frame)?;
+    let points_mm = points.iter().map(|p| geometry.to_mm(*p)).collect();
+    let segments_mm: Vec<f64> = points
+        .windows(2)
+        .map(|w| geometry.distance_mm(w[0], w[1]))
+        .collect();
+    let total_length_mm = segments_mm.iter().sum();
+    let area_mm2 = (points.len() >= 3).then(|| geometry.polygon_area_mm2(points));
+
+    Ok(Measurement {
+        frame,
+        geometry,
+        points_mm,
+        segments_mm,
+        total_length_mm,
+        area_mm2,
+    })
+}
+
+pub fn measure_file(path: &Path, frame: u32, points: &[PixelPoint]) -> Result<()> {
+    let obj = open_file(path).context("Failed to open DICOM file")?;
+    let result = measure(&obj, frame, points)?;
+
+    println!("Measurement on {:?} (frame {})", path, result.frame);
+    println!(
+        "  Spacing: row={} mm column={} mm ({:?})",
+        result.geometry.row_spacing, result.geometry.column_spacing, result.geometry.spacing_source
+    );
+    if result.geometry.spacing_source == SpacingSource::ImagerPixelSpacing {
+        println!("  [WARN] Imager Pixel Spacing is measured at the detector; values include magnification");
+    }
+    for (point, mm) in points.iter().zip(&result.points_mm) {
+        println!(
+            "  ({}, {}) -> [{:.2}, {:.2}, {:.2}] mm",
+            point.x, point.y, mm[0], mm[1], mm[2]
+        );
+    }
+    for (idx, segment) in result.segments_mm.iter().enumerate() {
+        println!("  Segment {}: {:.2} mm", idx + 1, segment);
+    }
+    println!("  Total length: {:.2} mm", result.total_length_mm);
+    if let Some(area) = result.area_mm2 {
+        println!("  Enclosed area: {:.2} mm² ({:.2} cm²)", area, area / 100.0);
+    }
+    Ok(())
+}
+
+/// Read a multi-valued numeric attribute for `frame`, falling back from the top-level dataset
+/// to the per-frame and then shared functional groups (enhanced multi-frame objects).
+pub fn frame_multi_f64(
+    obj: &InMemDicomObject,
+    frame: u32,
+    macro_tag: Tag,
+    tag: Tag,
+) -> Option<Vec<f64>> {
+    if let Some(values) = multi_f64(obj, tag) {
+        return Some(values);
+    }
+    let from_group = |group: &InMemDicomObject| {
+        group
+            .element(macro_tag)
+            .ok()?
+            .items()?
+            .first()
+            .and_then(|item| multi_f64(item, tag))
+    };
+    let per_frame = obj
+        .element(PER_FRAME_FUNCTIONAL_GROUPS)
+        .ok()
+        .and_then(|e| e.items())
+        .and_then(|items| items.get(frame as usize))
+        .and_then(from_group);
+    per_frame.or_else(|| {
+        obj.element(SHARED_FUNCTIONAL_GROUPS)
+            .ok()
+            .and_then(|e| e.items())
+            .and_then(|items| items.first())
+            .and_then(from_group)
+    })
+}
+
+fn multi_f64(obj: &InMemDicomObject, tag: Tag) -> Option<Vec<f64>> {
+    // Go through the string form so both parsed (Strs) and raw backslash-joined values work.
+    let text = obj.element(tag).ok()?.to_str().ok()?;
+    text.split('\\')
+        .map(|v| v.trim().parse::<f64>().ok())
+        .collect::<Option<Vec<_>>>()
+        .filter(|v| !v.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dicom::core::{DataElement, PrimitiveValue, VR};
+
+    fn with_spacing(spacing: &str) -> InMemDicomObject {
+        let mut obj = InMemDicomObject::new_empty();
+        obj.put(DataElement::new(
+            PIXEL_SPACING,
+            VR::DS,
+            PrimitiveValue::from(spacing),
+        ));
+        obj
+    }
+
+    #[test]
+    fn distance_and_area_scale_with_spacing() {
+        let obj = with_spacing("0.5\\0.25");
+        let points = [
+            PixelPoint { x: 0.0, y: 0.0 },
+            PixelPoint { x: 40.0, y: 0.0 },
+            PixelPoint { x: 40.0, y: 20.0 },
+        ];
+        let result = measure(&obj, 0, &points).expect("measure");
+        // 40 columns * 0.25 mm, then 20 rows * 0.5 mm.
+        assert!((result.segments_mm[0] - 10.0).abs() < 1e-9);
+        assert!((result.segments_mm[1] - 10.0).abs() < 1e-9);
+        assert!((result.area_mm2.unwrap() - 50.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn patient_coordinates_follow_orientation() {
+        let mut obj = with_spacing("1\\1");
+        // Sagittal plane: columns advance along +Y, rows along -Z.
+        obj.put(DataElement::new(
+            IMAGE_ORIENTATION,
+            VR::DS,
+            PrimitiveValue::from("0\\1\\0\\0\\0\\-1"),
+        ));
+        obj.put(DataElement::new(
+            IMAGE_POSITION,
+            VR::DS,
+            PrimitiveValue::from("10\\-20\\30"),
+        ));
+        let geometry = FrameGeometry::for_frame(&obj, 0).expect("geometry");
+        let mm = geometry.to_mm(PixelPoint { x: 2.0, y: 3.0 });
+        assert_eq!(mm, [10.0, -18.0, 27.0]);
+    }
+
+    #[test]
+    fn points_parse_from_cli_syntax() {
+        let p: PixelPoint = "12.5, 7".parse().expect("parse");
+        assert_eq!(p, PixelPoint { x: 12.5, y: 7.0 });
+        assert!("12".parse::<PixelPoint>().is_err());
+        assert!(measure(&with_spacing("1\\1"), 0, &[p]).is_err());
+    }
+}
@@ -0,0 +1,513 @@
+//
+// measurement_report.rs
+// Dicom-Tools-rs
+//
+// Builds TID 1500 Measurement Reports from a JSON description of findings (tracking
+// identifiers, coded findings, image regions, measurements and qualitative evaluations), so
+// model outputs can go back to the PACS as a Comprehensive SR referencing the source images.
+//
+// Thales Matheus Mendonça Santos - November 2025
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use dicom::core::{Tag, VR};
+use dicom::object::{DefaultDicomObject, InMemDicomObject};
+use serde::Deserialize;
+
+use crate::annotations::{
+    code, container, content_item, derived_object, put_sequence, put_str, referenced_series,
+    sop_reference, spatial_coordinates, with_meta, ImageRef, Shape, SourceInstance, SourceSeries,
+    COMPREHENSIVE_SR_STORAGE,
+};
+use crate::atomic_file;
+use crate::codes::Code;
+use crate::derivation::new_instance_uid;
+use crate::dicom_access::ElementAccess;
+use crate::measure::PixelPoint;
+
+const MAPPING_RESOURCE: Tag = Tag(0x0008, 0x0105);
+const SOP_INSTANCE_UID: Tag = Tag(0x0008, 0x0018);
+const REFERENCED_INSTANCE_SEQUENCE: Tag = Tag(0x0008, 0x114A);
+const REFERENCED_SERIES_SEQUENCE: Tag = Tag(0x0008, 0x1115);
+const REFERENCED_SOP_SEQUENCE: Tag = Tag(0x0008, 0x1199);
+const STUDY_INSTANCE_UID: Tag = Tag(0x0020, 0x000D);
+const MEASUREMENT_UNITS_CODE_SEQUENCE: Tag = Tag(0x0040, 0x08EA);
+const VALUE_TYPE: Tag = Tag(0x0040, 0xA040);
+const CONCEPT_NAME_CODE_SEQUENCE: Tag = Tag(0x0040, 0xA043);
+const CONTINUITY_OF_CONTENT: Tag = Tag(0x0040, 0xA050);
+const UID_VALUE: Tag = Tag(0x0040, 0xA124);
+const TEXT_VALUE: Tag = Tag(0x0040, 0xA160);
+const CONCEPT_CODE_SEQUENCE: Tag = Tag(0x0040, 0xA168);
+const MEASURED_VALUE_SEQUENCE: Tag = Tag(0x0040, 0xA300);
+const NUMERIC_VALUE: Tag = Tag(0x0040, 0xA30A);
+const CURRENT_REQUESTED_PROCEDURE_EVIDENCE_SEQUENCE: Tag = Tag(0x0040, 0xA375);
+const COMPLETION_FLAG: Tag = Tag(0x0040, 0xA491);
+const VERIFICATION_FLAG: Tag = Tag(0x0040, 0xA493);
+const CONTENT_TEMPLATE_SEQUENCE: Tag = Tag(0x0040, 0xA504);
+const CONTENT_SEQUENCE: Tag = Tag(0x0040, 0xA730);
+const TEMPLATE_IDENTIFIER: Tag = Tag(0x0040, 0xDB00);
+
+/// UID seed, Series Number and Series Description of written reports.
+const REPORT_SERIES: (&str, &str, &str) = ("measurement-report", "9903", "Measurement report");
+
+/// A report as described in JSON.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct ReportSpec {
+    /// The algorithm or device that made the findings.
+    #[serde(default)]
+    pub observer: Option<DeviceObserver>,
+    /// Procedure Reported; defaults to (363679005, SCT, "Imaging").
+    #[serde(default)]
+    pub procedure: Option<Code>,
+    pub findings: Vec<Finding>,
+}
+
+/// Device observer context (TID 1004).
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct DeviceObserver {
+    pub name: String,
+    /// Device Observer UID; derived from the name when absent.
+    #[serde(default)]
+    pub uid: Option<String>,
+    #[serde(default)]
+    pub manufacturer: Option<String>,
+    #[serde(default)]
+    pub model: Option<String>,
+}
+
+/// One measurement group.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Finding {
+    pub tracking_id: String,
+    /// Tracking Unique Identifier, so follow-up reports can refer to the same finding.
+    #[serde(default)]
+    pub tracking_uid: Option<String>,
+    /// What was found, e.g. (27925004, SCT, "Nodule").
+    #[serde(default)]
+    pub finding: Option<Code>,
+    #[serde(default)]
+    pub finding_site: Option<Code>,
+    #[serde(default)]
+    pub image: Option<ImageSpec>,
+    /// Outline of the finding on `image`.
+    #[serde(default)]
+    pub region: Option<RegionSpec>,
+    #[serde(default)]
+    pub measurements: Vec<MeasurementSpec>,
+    #[serde(default)]
+    pub evaluations: Vec<EvaluationSpec>,
+}
+
+/// Image a finding refers to: a SOP Instance UID (with a 1-based frame for multi-frame
+/// images) or a 0-based index among the frames of the series in viewer order.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct ImageSpec {
+    #[serde(default)]
+    pub sop_instance_uid: Option<String>,
+    #[serde(default)]
+    pub frame: Option<u32>,
+    #[serde(default)]
+    pub image_index: Option<usize>,
+}
+
+impl ImageSpec {
+    fn image_ref(&self) -> Result<(ImageRef, u32)> {
+        let frame = self.frame.unwrap_or(1).max(1) - 1;
+        match (&self.sop_instance_uid, self.image_index) {
+            (Some(uid), None) => Ok((ImageRef::SopInstance(uid.clone()), frame)),
+            (None, Some(index)) => Ok((ImageRef::Index(index), 0)),
+            _ => bail!("An image needs exactly one of sop_instance_uid and image_index"),
+        }
+    }
+}
+
+/// Region in pixel indices (`[column, row]`, the centre of the first pixel at 0,0).
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct RegionSpec {
+    pub shape: Shape,
+    pub points: Vec<[f64; 2]>,
+}
+
+/// A numeric measurement with its UCUM units.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct MeasurementSpec {
+    pub name: Code,
+    pub value: f64,
+    pub units: Code,
+}
+
+/// A coded qualitative evaluation, e.g. malignancy likelihood.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct EvaluationSpec {
+    pub name: Code,
+    pub value: Code,
+}
+
+/// Read a report description.
+pub fn read_spec(path: &Path) -> Result<ReportSpec> {
+    let text = fs::read_to_string(path).with_context(|| format!("Failed to read {:?}", path))?;
+    let spec: ReportSpec = serde_json::from_str(&text)
+        .with_context(|| format!("{:?} is not a measurement report description", path))?;
+    for finding in &spec.findings {
+        if finding.region.is_some() && finding.image.is_none() {
+            bail!("Finding {} has a region but no image", finding.tracking_id);
+        }
+        if finding
+            .region
+            .as_ref()
+            .is_some_and(|region| region.points.is_empty())
+        {
+            bail!("The region of finding {} has no point", finding.tracking_id);
+        }
+    }
+    Ok(spec)
+}
+
+/// Build the report, resolving image references against the instances under `series`.
+pub fn build(spec: &ReportSpec, series: &Path) -> Result<DefaultDicomObject> {
+    let series = SourceSeries::scan(series)?;
+    let images = spec
+        .findings
+        .iter()
+        .map(|finding| {
+            finding
+                .image
+                .as_ref()
+                .map(|image| {
+                    let (image, frame) = image.image_ref()?;
+                    series.resolve_image(&image, frame, &finding.tracking_id)
+                })
+                .transpose()
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let mut referenced: Vec<&SourceInstance> = Vec::new();
+    for (instance, _) in images.iter().flatten() {
+        if !referenced
+            .iter()
+            .any(|i| i.sop_instance_uid == instance.sop_instance_uid)
+        {
+            referenced.push(instance);
+        }
+    }
+    let header = referenced.first().copied().unwrap_or(&series.instances[0]);
+
+    let mut obj = derived_object(header, COMPREHENSIVE_SR_STORAGE, "SR", REPORT_SERIES);
+    put_str(&mut obj, VALUE_TYPE, VR::CS, "CONTAINER");
+    put_sequence(
+        &mut obj,
+        CONCEPT_NAME_CODE_SEQUENCE,
+        vec![code("126000", "DCM", "Imaging Measurement Report")],
+    );
+    put_str(&mut obj, CONTINUITY_OF_CONTENT, VR::CS, "SEPARATE");
+    let mut template = InMemDicomObject::new_empty();
+    put_str(&mut template, MAPPING_RESOURCE, VR::CS, "DCMR");
+    put_str(&mut template, TEMPLATE_IDENTIFIER, VR::CS, "1500");
+    put_sequence(&mut obj, CONTENT_TEMPLATE_SEQUENCE, vec![template]);
+    // Machine output: nobody has signed it off.
+    put_str(&mut obj, COMPLETION_FLAG, VR::CS, "COMPLETE");
+    put_str(&mut obj, VERIFICATION_FLAG, VR::CS, "UNVERIFIED");
+    if !referenced.is_empty() {
+        let mut evidence = InMemDicomObject::new_empty();
+        put_str(
+            &mut evidence,
+            STUDY_INSTANCE_UID,
+            VR::UI,
+            &header.text(STUDY_INSTANCE_UID),
+        );
+        let series = referenced_series(referenced.iter().copied())
+            .into_iter()
+            .map(|mut series| {
+                let instances = series.sequence_items(REFERENCED_INSTANCE_SEQUENCE).to_vec();
+                series.remove_element(REFERENCED_INSTANCE_SEQUENCE);
+                put_sequence(&mut series, REFERENCED_SOP_SEQUENCE, instances);
+                series
+            })
+            .collect();
+        put_sequence(&mut evidence, REFERENCED_SERIES_SEQUENCE, series);
+        put_sequence(
+            &mut obj,
+            CURRENT_REQUESTED_PROCEDURE_EVIDENCE_SEQUENCE,
+            vec![evidence],
+        );
+    }
+
+    let mut content = vec![coded(
+        "HAS CONCEPT MOD",
+        code("121049", "DCM", "Language of Content Item and Descendants"),
+        code("eng", "RFC5646", "English"),
+    )];
+    if let Some(observer) = &spec.observer {
+        content.extend(observer_context(observer));
+    }
+    content.push(coded(
+        "HAS CONCEPT MOD",
+        code("121058", "DCM", "Procedure reported"),
+        spec.procedure
+            .as_ref()
+            .map(code_item)
+            .unwrap_or_else(|| code("363679005", "SCT", "Imaging")),
+    ));
+    let groups = spec
+        .findings
+        .iter()
+        .zip(&images)
+        .map(|(finding, image)| measurement_group(finding, *image))
+        .collect();
+    content.push(container(
+        "CONTAINS",
+        code("126010", "DCM", "Imaging Measurements"),
+        groups,
+    ));
+    put_sequence(&mut obj, CONTENT_SEQUENCE, content);
+    with_meta(obj, COMPREHENSIVE_SR_STORAGE)
+}
+
+/// TID 1004 items naming the device observer.
+fn observer_context(observer: &DeviceObserver) -> Vec<InMemDicomObject> {
+    let mut items = vec![coded(
+        "HAS OBS CONTEXT",
+        code("121005", "DCM", "Observer Type"),
+        code("121007", "DCM", "Device"),
+    )];
+    let mut uid = content_item(
+        "HAS OBS CONTEXT",
+        "UIDREF",
+        code("121012", "DCM", "Device Observer UID"),
+    );
+    let value = observer
+        .uid
+        .clone()
+        .unwrap_or_else(|| new_instance_uid(&format!("device-observer|{}", observer.name)));
+    put_str(&mut uid, UID_VALUE, VR::UI, &value);
+    items.push(uid);
+    for (concept, value) in [
+        (
+            code("121013", "DCM", "Device Observer Name"),
+            Some(&observer.name),
+        ),
+        (
+            code("121014", "DCM", "Device Observer Manufacturer"),
+            observer.manufacturer.as_ref(),
+        ),
+        (
+            code("121015", "DCM", "Device Observer Model Name"),
+            observer.model.as_ref(),
+        ),
+    ] {
+        if let Some(value) = value {
+            items.push(text("HAS OBS CONTEXT", concept, value));
+        }
+    }
+    items
+}
+
+/// TID 1501 measurement group of one finding.
+fn measurement_group(finding: &Finding, image: Option<(&SourceInstance, u32)>) -> InMemDicomObject {
+    let mut children = vec![text(
+        "CONTAINS",
+        code("112039", "DCM", "Tracking Identifier"),
+        &finding.tracking_id,
+    )];
+    let mut tracking_uid = content_item(
+        "CONTAINS",
+        "UIDREF",
+        code("112040", "DCM", "Tracking Unique Identifier"),
+    );
+    let uid = finding
+        .tracking_uid
+        .clone()
+        .unwrap_or_else(|| new_instance_uid(&format!("tracking|{}", finding.tracking_id)));
+    put_str(&mut tracking_uid, UID_VALUE, VR::UI, &uid);
+    children.push(tracking_uid);
+    if let Some(value) = &finding.finding {
+        children.push(coded(
+            "CONTAINS",
+            code("121071", "DCM", "Finding"),
+            code_item(value),
+        ));
+    }
+    if let Some(site) = &finding.finding_site {
+        children.push(coded(
+            "HAS CONCEPT MOD",
+            code("363698007", "SCT", "Finding Site"),
+            code_item(site),
+        ));
+    }
+    match (image, &finding.region) {
+        (Some((instance, frame)), Some(region)) => {
+            let points: Vec<PixelPoint> = region
+                .points
+                .iter()
+                .map(|[x, y]| PixelPoint { x: *x, y: *y })
+                .collect();
+            children.push(spatial_coordinates(region.shape, &points, instance, frame));
+        }
+        (Some((instance, frame)), None) => {
+            let mut source = content_item(
+                "CONTAINS",
+                "IMAGE",
+                code("121112", "DCM", "Source of Measurement"),
+            );
+            put_sequence(
+                &mut source,
+                REFERENCED_SOP_SEQUENCE,
+                vec![sop_reference(instance, Some(frame))],
+            );
+            children.push(source);
+        }
+        _ => {}
+    }
+    for measurement in &finding.measurements {
+        let mut item = content_item("CONTAINS", "NUM", code_item(&measurement.name));
+        let mut measured = InMemDicomObject::new_empty();
+        put_str(
+            &mut measured,
+            NUMERIC_VALUE,
+            VR::DS,
+            &format_ds(measurement.value),
+        );
+        put_sequence(
+            &mut measured,
+            MEASUREMENT_UNITS_CODE_SEQUENCE,
+            vec![code_item(&measurement.units)],
+        );
+        put_sequence(&mut item, MEASURED_VALUE_SEQUENCE, vec![measured]);
+        children.push(item);
+    }
+    for evaluation in &finding.evaluations {
+        children.push(coded(
+            "CONTAINS",
+            code_item(&evaluation.name),
+            code_item(&evaluation.value),
+        ));
+    }
+    container(
+        "CONTAINS",
+        code("125007", "DCM", "Measurement Group"),
+        children,
+    )
+}
+
+fn coded(
+    relationship: &str,
+    concept: InMemDicomObject,
+    value: InMemDicomObject,
+) -> InMemDicomObject {
+    let mut item = content_item(relationship, "CODE", concept);
+    put_sequence(&mut item, CONCEPT_CODE_SEQUENCE, vec![value]);
+    item
+}
+
+fn text(relationship: &str, concept: InMemDicomObject, value: &str) -> InMemDicomObject {
+    let mut item = content_item(relationship, "TEXT", concept);
+    put_str(&mut item, TEXT_VALUE, VR::UT, value);
+    item
+}
+
+fn code_item(value: &Code) -> InMemDicomObject {
+    code(
+        &value.value,
+        value.scheme.as_deref().unwrap_or_default(),
+        value.meaning.as_deref().unwrap_or(&value.value),
+    )
+}
+
+/// Decimal String values hold at most 16 characters.
+fn format_ds(value: f64) -> String {
+    let plain = format!("{}", value);
+    if plain.len() <= 16 {
+        return plain;
+    }
+    (1..=15)
+        .rev()
+        .map(|precision| format!("{:.*e}", precision, value))
+        .find(|s| s.len() <= 16)
+        .unwrap_or(plain)
+}
+
+/// Summary of a written report.
+#[derive(Debug, Clone)]
+pub struct ReportSummary {
+    pub findings: usize,
+    pub measurements: usize,
+    pub sop_instance_uid: String,
+    pub output: PathBuf,
+}
+
+/// Read the JSON description at `spec`, build the report against `series` and write it.
+pub fn write(spec: &Path, series: &Path, output: &Path) -> Result<ReportSummary> {
+    let spec = read_spec(spec)?;
+    let obj = build(&spec, series)?;
+    atomic_file::write_dicom(output, &obj)
+        .with_context(|| format!("Failed to write {:?}", output))?;
+    Ok(ReportSummary {
+        findings: spec.findings.len(),
+        measurements: spec.findings.iter().map(|f| f.measurements.len()).sum(),
+        sop_instance_uid: obj.element_str(SOP_INSTANCE_UID).unwrap_or_default(),
+        output: output.to_path_buf(),
+    })
+}
+
+/// CLI entry point.
+pub fn print_write(spec: &Path, series: &Path, output: &Path) -> Result<()> {
+    let summary = write(spec, series, output)?;
+    println!(
+        "{} finding(s) with {} measurement(s) written to {:?} ({})",
+        summary.findings, summary.measurements, summary.output, summary.sop_instance_uid
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn findings_parse_from_json() {
+        let spec: ReportSpec = serde_json::from_str(
+            r#"{
+                "observer": {"name": "LungNet", "manufacturer": "Example AI"},
+                "findings": [{
+                    "tracking_id": "Nodule 1",
+                    "finding": {"value": "27925004", "scheme": "SCT", "meaning": "Nodule"},
+                    "image": {"image_index": 2},
+                    "region": {"shape": "line", "points": [[1, 2], [3.5, 4]]},
+                    "measurements": [{
+                        "name": {"value": "410668003", "scheme": "SCT", "meaning": "Length"},
+                        "value": 4.2,
+                        "units": {"value": "mm", "scheme": "UCUM", "meaning": "millimeter"}
+                    }]
+                }]
+            }"#,
+        )
+        .unwrap();
+        let finding = &spec.findings[0];
+        assert_eq!(spec.observer.unwrap().name, "LungNet");
+        assert_eq!(finding.region.as_ref().unwrap().shape, Shape::Polyline);
+        assert_eq!(
+            finding.image.as_ref().unwrap().image_ref().unwrap(),
+            (ImageRef::Index(2), 0)
+        );
+        assert_eq!(finding.measurements[0].units.value, "mm");
+        assert!(finding.evaluations.is_empty());
+
+        let ambiguous = ImageSpec {
+            sop_instance_uid: Some("1.2.3".into()),
+            frame: None,
+            image_index: Some(0),
+        };
+        assert!(ambiguous.image_ref().is_err());
+    }
+
+    #[test]
+    fn decimal_strings_fit_sixteen_characters() {
+        assert_eq!(format_ds(12.5), "12.5");
+        assert_eq!(format_ds(-0.25), "-0.25");
+        assert!(format_ds(1.0 / 3.0).len() <= 16);
+        assert!(format_ds(-123456789.12345679).len() <= 16);
+        let parsed: f64 = format_ds(1.0 / 3.0).parse().unwrap();
+        assert!((parsed - 1.0 / 3.0).abs() < 1e-9);
+    }
+}
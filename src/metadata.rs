@@ -13,9 +13,18 @@ use anyhow::{Context, Result};
 use dicom::core::Tag;
 use dicom::object::{open_file, DefaultDicomObject};
 
+use crate::capabilities;
+use crate::cine;
+use crate::codes::{self, CODE_SEQUENCES};
 use crate::dicom_access::ElementAccess;
-use crate::models::{BasicMetadata, DetailedMetadata, PixelFormatSummary};
+use crate::lut::{ExplicitLuts, LutSummary};
+use crate::models::{
+    BasicMetadata, Capabilities, CtMetadata, DetailedMetadata, ModalityMetadata, MrMetadata,
+    PixelFormatSummary, UsMetadata, XrayMetadata,
+};
+use crate::person_name::PersonName;
 use crate::stats;
+use crate::temporal::{self, Temporal};
 
 fn text_for_tag<T: ElementAccess>(obj: &T, tag: Tag) -> Option<String> {
     // Thin wrapper to keep tag lookups concise in the call sites.
@@ -27,6 +36,10 @@ fn uint_for_tag<T: ElementAccess>(obj: &T, tag: Tag) -> Option<u32> {
     obj.element_u32(tag)
 }
 
+fn float_for_tag<T: ElementAccess>(obj: &T, tag: Tag) -> Option<f64> {
+    obj.element_f64(tag)
+}
+
 fn insert_if(map: &mut BTreeMap<String, String>, label: &str, value: Option<String>) {
     // Only materialize present values so the API stays clean of empty fields.
     if let Some(value) = value {
@@ -37,8 +50,10 @@ fn insert_if(map: &mut BTreeMap<String, String>, label: &str, value: Option<Stri
 pub fn extract_basic_metadata<T: ElementAccess>(obj: &T) -> BasicMetadata {
     // Pull the handful of fields most callers care about without heavy allocation.
     let patient_name = text_for_tag(obj, Tag(0x0010, 0x0010));
+    let patient_name_parts = patient_name.as_deref().and_then(PersonName::parse);
     let patient_id = text_for_tag(obj, Tag(0x0010, 0x0020));
     let study_date = text_for_tag(obj, Tag(0x0008, 0x0020));
+    let study_date_value = study_date.as_deref().and_then(temporal::parse_date);
     let modality = text_for_tag(obj, Tag(0x0008, 0x0060));
     let sop_class_uid = text_for_tag(obj, Tag(0x0008, 0x0016));
     let has_pixel_data = obj.has_element(Tag(0x7fe0, 0x0010));
@@ -46,11 +61,14 @@ pub fn extract_basic_metadata<T: ElementAccess>(obj: &T) -> BasicMetadata {
     let rows = uint_for_tag(obj, Tag(0x0028, 0x0010));
     let columns = uint_for_tag(obj, Tag(0x0028, 0x0011));
     let number_of_frames = uint_for_tag(obj, Tag(0x0028, 0x0008));
+    let cine_duration_ms = cine::duration_ms(obj);
 
     BasicMetadata {
         patient_name,
+        patient_name_parts,
         patient_id,
         study_date,
+        study_date_value,
         modality,
         sop_class_uid,
         has_pixel_data,
@@ -58,6 +76,7 @@ pub fn extract_basic_metadata<T: ElementAccess>(obj: &T) -> BasicMetadata {
         rows,
         columns,
         number_of_frames,
+        cine_duration_ms,
     }
 }
 
@@ -114,6 +133,11 @@ pub fn extract_detailed_metadata<T: ElementAccess>(obj: &T) -> DetailedMetadata
         "Number of Frames",
         text_for_tag(obj, Tag(0x0028, 0x0008)),
     );
+    insert_if(
+        &mut image,
+        "Cine Duration",
+        cine::duration_ms(obj).map(|ms| format!("{:.1} ms", ms)),
+    );
 
     let mut misc = BTreeMap::new();
     insert_if(
@@ -135,9 +159,143 @@ pub fn extract_detailed_metadata<T: ElementAccess>(obj: &T) -> DetailedMetadata
         study,
         image,
         misc,
+        acquisition: extract_modality_metadata(obj),
+        dates: extract_dates(obj),
+        codes: extract_codes(obj),
     }
 }
 
+/// Concepts of the common code sequences present in `obj`, keyed by sequence name.
+pub fn extract_codes<T: ElementAccess>(obj: &T) -> BTreeMap<String, Vec<codes::Code>> {
+    CODE_SEQUENCES
+        .iter()
+        .map(|&(tag, name)| (name.to_string(), codes::codes_in(obj, tag)))
+        .filter(|(_, codes)| !codes.is_empty())
+        .collect()
+}
+
+/// Date and time attributes commonly needed for sorting and timelines, parsed by VR.
+const DATE_ATTRIBUTES: [(&str, Tag, &str); 10] = [
+    ("Birth Date", Tag(0x0010, 0x0030), "DA"),
+    ("Study Date", Tag(0x0008, 0x0020), "DA"),
+    ("Study Time", Tag(0x0008, 0x0030), "TM"),
+    ("Series Date", Tag(0x0008, 0x0021), "DA"),
+    ("Series Time", Tag(0x0008, 0x0031), "TM"),
+    ("Acquisition Date", Tag(0x0008, 0x0022), "DA"),
+    ("Acquisition Time", Tag(0x0008, 0x0032), "TM"),
+    ("Acquisition DateTime", Tag(0x0008, 0x002A), "DT"),
+    ("Content Date", Tag(0x0008, 0x0023), "DA"),
+    ("Content Time", Tag(0x0008, 0x0033), "TM"),
+];
+
+pub fn extract_dates<T: ElementAccess>(obj: &T) -> BTreeMap<String, Temporal> {
+    DATE_ATTRIBUTES
+        .iter()
+        .filter_map(|&(label, tag, vr)| {
+            let text = text_for_tag(obj, tag)?;
+            let value = match vr {
+                "DA" => Temporal::DA(temporal::parse_date(&text)?),
+                "TM" => Temporal::TM(temporal::parse_time(&text)?),
+                _ => Temporal::DT(temporal::parse_datetime(&text)?),
+            };
+            Some((label.to_string(), value))
+        })
+        .collect()
+}
+
+/// Pull the acquisition parameters relevant to the object's modality, if it is one we know.
+pub fn extract_modality_metadata<T: ElementAccess>(obj: &T) -> Option<ModalityMetadata> {
+    let modality = text_for_tag(obj, Tag(0x0008, 0x0060))?;
+    let kvp = float_for_tag(obj, Tag(0x0018, 0x0060));
+
+    match modality.trim() {
+        "CT" => Some(ModalityMetadata::Ct(CtMetadata {
+            kvp,
+            tube_current_ma: float_for_tag(obj, Tag(0x0018, 0x1151)),
+            exposure_mas: float_for_tag(obj, Tag(0x0018, 0x1152)),
+            reconstruction_kernel: text_for_tag(obj, Tag(0x0018, 0x1210)),
+            slice_thickness_mm: float_for_tag(obj, Tag(0x0018, 0x0050)),
+            ctdi_vol_mgy: float_for_tag(obj, Tag(0x0018, 0x9345)),
+        })),
+        "MR" => Some(ModalityMetadata::Mr(MrMetadata {
+            repetition_time_ms: float_for_tag(obj, Tag(0x0018, 0x0080)),
+            echo_time_ms: float_for_tag(obj, Tag(0x0018, 0x0081)),
+            inversion_time_ms: float_for_tag(obj, Tag(0x0018, 0x0082)),
+            flip_angle_deg: float_for_tag(obj, Tag(0x0018, 0x1314)),
+            field_strength_t: float_for_tag(obj, Tag(0x0018, 0x0087)),
+            sequence_name: text_for_tag(obj, Tag(0x0018, 0x0024)),
+        })),
+        "US" => {
+            let frame_time_ms = float_for_tag(obj, Tag(0x0018, 0x1063));
+            // Prefer the explicit display rate; otherwise derive it from the frame time.
+            let frame_rate_fps = float_for_tag(obj, Tag(0x0008, 0x2144))
+                .or_else(|| float_for_tag(obj, Tag(0x0018, 0x0040)))
+                .or_else(|| frame_time_ms.filter(|t| *t > 0.0).map(|t| 1000.0 / t));
+            Some(ModalityMetadata::Us(UsMetadata {
+                transducer: text_for_tag(obj, Tag(0x0018, 0x5010)),
+                transducer_type: text_for_tag(obj, Tag(0x0018, 0x6031)),
+                frame_rate_fps,
+                frame_time_ms,
+            }))
+        }
+        "DX" | "CR" | "MG" | "IO" | "PX" | "RG" => Some(ModalityMetadata::Xray(XrayMetadata {
+            kvp,
+            // Exposure in mAs, falling back to the µAs variant used by some detectors.
+            exposure_mas: float_for_tag(obj, Tag(0x0018, 0x1152))
+                .or_else(|| float_for_tag(obj, Tag(0x0018, 0x1153)).map(|uas| uas / 1000.0)),
+            exposure_time_ms: float_for_tag(obj, Tag(0x0018, 0x1150)),
+            compression_force_n: float_for_tag(obj, Tag(0x0018, 0x11A2)),
+            body_part_thickness_mm: float_for_tag(obj, Tag(0x0018, 0x11A0)),
+            view_position: text_for_tag(obj, Tag(0x0018, 0x5101)),
+        })),
+        _ => None,
+    }
+}
+
+/// Flatten modality metadata into labelled display rows, skipping absent values.
+pub fn acquisition_fields(acquisition: &ModalityMetadata) -> Vec<(&'static str, String)> {
+    fn num(label: &'static str, value: Option<f64>, unit: &str) -> Option<(&'static str, String)> {
+        value.map(|v| (label, format!("{}{}", v, unit)))
+    }
+    fn text(label: &'static str, value: &Option<String>) -> Option<(&'static str, String)> {
+        value.as_ref().map(|v| (label, v.trim().to_string()))
+    }
+
+    let rows = match acquisition {
+        ModalityMetadata::Ct(ct) => vec![
+            num("kVp", ct.kvp, ""),
+            num("Tube Current", ct.tube_current_ma, " mA"),
+            num("Exposure", ct.exposure_mas, " mAs"),
+            text("Kernel", &ct.reconstruction_kernel),
+            num("Slice Thickness", ct.slice_thickness_mm, " mm"),
+            num("CTDIvol", ct.ctdi_vol_mgy, " mGy"),
+        ],
+        ModalityMetadata::Mr(mr) => vec![
+            num("TR", mr.repetition_time_ms, " ms"),
+            num("TE", mr.echo_time_ms, " ms"),
+            num("TI", mr.inversion_time_ms, " ms"),
+            num("Flip Angle", mr.flip_angle_deg, " deg"),
+            num("Field Strength", mr.field_strength_t, " T"),
+            text("Sequence", &mr.sequence_name),
+        ],
+        ModalityMetadata::Us(us) => vec![
+            text("Transducer", &us.transducer),
+            text("Transducer Type", &us.transducer_type),
+            num("Frame Rate", us.frame_rate_fps, " fps"),
+            num("Frame Time", us.frame_time_ms, " ms"),
+        ],
+        ModalityMetadata::Xray(xr) => vec![
+            num("kVp", xr.kvp, ""),
+            num("Exposure", xr.exposure_mas, " mAs"),
+            num("Exposure Time", xr.exposure_time_ms, " ms"),
+            num("Compression Force", xr.compression_force_n, " N"),
+            num("Body Part Thickness", xr.body_part_thickness_mm, " mm"),
+            text("View Position", &xr.view_position),
+        ],
+    };
+    rows.into_iter().flatten().collect()
+}
+
 pub fn read_basic_metadata(path: &Path) -> Result<BasicMetadata> {
     let obj: DefaultDicomObject = open_file(path).context("Falha ao abrir arquivo DICOM")?;
     Ok(extract_basic_metadata(&obj))
@@ -204,11 +362,38 @@ pub fn print_info(path: &Path, verbose: bool) -> Result<()> {
             .map(|f| format!(" ({} frame{})", f, if f == 1 { "" } else { "s" }))
             .unwrap_or_default()
     );
+    if let (Some(ms), Some(frames)) = (basic.cine_duration_ms, basic.number_of_frames) {
+        println!(
+            "  Cine: {:.2} s ({:.1} fps)",
+            ms / 1000.0,
+            frames as f64 * 1000.0 / ms
+        );
+    }
 
     if let Some(format) = &pixel_format {
         print_pixel_format(format);
     }
 
+    let luts = ExplicitLuts::from_object(&obj);
+    if !luts.is_empty() {
+        println!("\nLOOKUP TABLES");
+        for summary in luts.summaries() {
+            print_lut_summary(&summary);
+        }
+    }
+
+    if let Some(acquisition) = extract_modality_metadata(&obj) {
+        let rows = acquisition_fields(&acquisition);
+        if !rows.is_empty() {
+            println!("\nACQUISITION");
+            for (label, value) in rows {
+                println!("  {}: {}", label, value);
+            }
+        }
+    }
+
+    print_capabilities(&capabilities::detect(&obj));
+
     if verbose {
         // Verbose mode dumps every element header/value tuple for quick inspection.
         println!("\nALL TAGS (Verbose):");
@@ -220,6 +405,56 @@ pub fn print_info(path: &Path, verbose: bool) -> Result<()> {
     Ok(())
 }
 
+fn print_capabilities(caps: &Capabilities) {
+    let yes_no = |flag: bool| if flag { "yes" } else { "no" };
+    println!("\nCAPABILITIES");
+    if let Some(name) = &caps.sop_class_name {
+        println!("  SOP Class Name: {}", name);
+    }
+    if let Some(name) = &caps.transfer_syntax_name {
+        println!("  Transfer Syntax Name: {}", name);
+    }
+    if let Some(bytes) = caps.estimated_decoded_bytes {
+        println!(
+            "  Decoded Size: {:.1} MiB ({} frame{})",
+            bytes as f64 / (1024.0 * 1024.0),
+            caps.frames,
+            if caps.frames == 1 { "" } else { "s" }
+        );
+    }
+    println!(
+        "  Render: {} | Stats: {} | Transcode: {}",
+        yes_no(caps.can_render),
+        yes_no(caps.can_compute_stats),
+        yes_no(caps.can_transcode)
+    );
+    for warning in &caps.warnings {
+        println!("  Warning: {}", warning);
+    }
+}
+
+fn print_lut_summary(summary: &LutSummary) {
+    println!(
+        "  {:?} LUT: {} entries from {} ({} bits), output {}..{}{}{}",
+        summary.kind,
+        summary.entries,
+        summary.first_mapped,
+        summary.bits,
+        summary.min_output,
+        summary.max_output,
+        summary
+            .lut_type
+            .as_deref()
+            .map(|t| format!(" | type {}", t))
+            .unwrap_or_default(),
+        summary
+            .explanation
+            .as_deref()
+            .map(|e| format!(" | {}", e))
+            .unwrap_or_default()
+    );
+}
+
 fn print_pixel_format(format: &PixelFormatSummary) {
     println!("  Samples: {}", format.samples_per_pixel);
     println!("  Photometric: {}", format.photometric_interpretation);
@@ -231,10 +466,89 @@ fn print_pixel_format(format: &PixelFormatSummary) {
         format.bits_stored, format.bits_allocated, format.high_bit
     );
     println!("  Pixel Representation: {}", format.pixel_representation);
-    if let (Some(center), Some(width)) = (format.window_center, format.window_width) {
-        println!("  Window: center={} width={}", center, width);
+    for (index, window) in format.windows.iter().enumerate() {
+        println!(
+            "  Window [{}]: center={} width={} function={}{}",
+            index,
+            window.center,
+            window.width,
+            window.function,
+            window
+                .explanation
+                .as_ref()
+                .map(|e| format!(" ({})", e))
+                .unwrap_or_default()
+        );
+    }
+    if let Some(shape) = &format.presentation_lut_shape {
+        println!("  Presentation LUT Shape: {}", shape);
     }
     if let (Some(slope), Some(intercept)) = (format.rescale_slope, format.rescale_intercept) {
         println!("  Rescale: slope={} intercept={}", slope, intercept);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dicom::core::{DataElement, PrimitiveValue, VR};
+    use dicom::object::InMemDicomObject;
+
+    #[test]
+    fn ct_and_us_parameters_are_typed() {
+        let mut ct = InMemDicomObject::new_empty();
+        ct.put(DataElement::new(
+            Tag(0x0008, 0x0060),
+            VR::CS,
+            PrimitiveValue::from("CT"),
+        ));
+        ct.put(DataElement::new(
+            Tag(0x0018, 0x0060),
+            VR::DS,
+            PrimitiveValue::from("120"),
+        ));
+        ct.put(DataElement::new(
+            Tag(0x0018, 0x1210),
+            VR::SH,
+            PrimitiveValue::from("B30f"),
+        ));
+        ct.put(DataElement::new(
+            Tag(0x0018, 0x9345),
+            VR::FD,
+            PrimitiveValue::from(12.5_f64),
+        ));
+
+        match extract_modality_metadata(&ct) {
+            Some(ModalityMetadata::Ct(meta)) => {
+                assert_eq!(meta.kvp, Some(120.0));
+                assert_eq!(meta.reconstruction_kernel.as_deref(), Some("B30f"));
+                assert_eq!(meta.ctdi_vol_mgy, Some(12.5));
+            }
+            other => panic!("expected CT metadata, got {:?}", other),
+        }
+
+        let mut us = InMemDicomObject::new_empty();
+        us.put(DataElement::new(
+            Tag(0x0008, 0x0060),
+            VR::CS,
+            PrimitiveValue::from("US"),
+        ));
+        us.put(DataElement::new(
+            Tag(0x0018, 0x1063),
+            VR::DS,
+            PrimitiveValue::from("40"),
+        ));
+        match extract_modality_metadata(&us) {
+            Some(ModalityMetadata::Us(meta)) => assert_eq!(meta.frame_rate_fps, Some(25.0)),
+            other => panic!("expected US metadata, got {:?}", other),
+        }
+
+        let mut ot = InMemDicomObject::new_empty();
+        ot.put(DataElement::new(
+            Tag(0x0008, 0x0060),
+            VR::CS,
+            PrimitiveValue::from("OT"),
+        ));
+        assert!(extract_modality_metadata(&ot).is_none());
+    }
+}
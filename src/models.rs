@@ -10,12 +10,22 @@ use std::collections::BTreeMap;
 
 use serde::{Deserialize, Serialize};
 
+use crate::codes::Code;
+use crate::person_name::PersonName;
+use crate::temporal::{DicomDate, Temporal};
+
 /// Lightweight fields shown in CLI summaries and quick API responses.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BasicMetadata {
     pub patient_name: Option<String>,
+    /// `patient_name` split into component groups and components.
+    #[serde(default)]
+    pub patient_name_parts: Option<PersonName>,
     pub patient_id: Option<String>,
     pub study_date: Option<String>,
+    /// `study_date` parsed, when it is a valid DA.
+    #[serde(default)]
+    pub study_date_value: Option<DicomDate>,
     pub modality: Option<String>,
     pub sop_class_uid: Option<String>,
     pub has_pixel_data: bool,
@@ -23,6 +33,8 @@ pub struct BasicMetadata {
     pub rows: Option<u32>,
     pub columns: Option<u32>,
     pub number_of_frames: Option<u32>,
+    /// Playback length of a multi-frame cine, in milliseconds.
+    pub cine_duration_ms: Option<f64>,
 }
 
 /// Expanded, categorized metadata suitable for UI rendering.
@@ -32,6 +44,65 @@ pub struct DetailedMetadata {
     pub study: BTreeMap<String, String>,
     pub image: BTreeMap<String, String>,
     pub misc: BTreeMap<String, String>,
+    pub acquisition: Option<ModalityMetadata>,
+    /// Typed DA/TM/DT attributes by name (e.g. "Study Date"); unparseable values are left out.
+    #[serde(default)]
+    pub dates: BTreeMap<String, Temporal>,
+    /// Coded concepts of the common code sequences, by sequence name.
+    #[serde(default)]
+    pub codes: BTreeMap<String, Vec<Code>>,
+}
+
+/// Modality-specific acquisition parameters, keyed by the family the Modality (0008,0060) belongs to.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum ModalityMetadata {
+    Ct(CtMetadata),
+    Mr(MrMetadata),
+    Us(UsMetadata),
+    Xray(XrayMetadata),
+}
+
+/// CT acquisition and dose parameters.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct CtMetadata {
+    pub kvp: Option<f64>,
+    pub tube_current_ma: Option<f64>,
+    pub exposure_mas: Option<f64>,
+    pub reconstruction_kernel: Option<String>,
+    pub slice_thickness_mm: Option<f64>,
+    pub ctdi_vol_mgy: Option<f64>,
+}
+
+/// MR sequence timing and scanner parameters.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct MrMetadata {
+    pub repetition_time_ms: Option<f64>,
+    pub echo_time_ms: Option<f64>,
+    pub inversion_time_ms: Option<f64>,
+    pub flip_angle_deg: Option<f64>,
+    pub field_strength_t: Option<f64>,
+    pub sequence_name: Option<String>,
+}
+
+/// Ultrasound probe and cine parameters.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct UsMetadata {
+    pub transducer: Option<String>,
+    pub transducer_type: Option<String>,
+    pub frame_rate_fps: Option<f64>,
+    pub frame_time_ms: Option<f64>,
+}
+
+/// Projection radiography (DX/CR/MG) exposure parameters.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct XrayMetadata {
+    pub kvp: Option<f64>,
+    pub exposure_mas: Option<f64>,
+    pub exposure_time_ms: Option<f64>,
+    pub compression_force_n: Option<f64>,
+    pub body_part_thickness_mm: Option<f64>,
+    pub view_position: Option<String>,
 }
 
 /// High-level validation report for required attributes and pixel presence.
@@ -39,6 +110,8 @@ pub struct DetailedMetadata {
 pub struct ValidationSummary {
     pub valid: bool,
     pub missing_tags: Vec<String>,
+    pub conditional_violations: Vec<String>,
+    pub inconsistencies: Vec<String>,
     pub has_pixel_data: bool,
 }
 
@@ -51,15 +124,48 @@ pub struct PixelStatistics {
     pub median: Option<f32>,
     pub std_dev: f32,
     pub total_pixels: usize,
+    /// Pixels left out as padding or behind a display shutter.
+    #[serde(default)]
+    pub excluded_pixels: usize,
     pub shape: Vec<usize>,
 }
 
-/// Histogram buckets alongside the observed range.
+/// Histogram buckets alongside their range: the observed one when auto-ranged, the
+/// requested bounds when the bin edges were fixed.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PixelHistogram {
     pub bins: Vec<u64>,
     pub min: f32,
     pub max: f32,
+    /// Width of every bin; bin `i` starts at `min + i * bin_width`.
+    #[serde(default)]
+    pub bin_width: f32,
+    /// Values below `min` / above `max`, only possible with fixed bin edges.
+    #[serde(default)]
+    pub below: u64,
+    #[serde(default)]
+    pub above: u64,
+}
+
+/// What an object contains and which operations can be performed on it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Capabilities {
+    pub sop_class_uid: Option<String>,
+    /// SOP Class name from the standard dictionary, when known.
+    pub sop_class_name: Option<String>,
+    pub modality: Option<String>,
+    pub transfer_syntax: String,
+    pub transfer_syntax_name: Option<String>,
+    pub has_pixel_data: bool,
+    pub frames: u32,
+    /// Bytes of native pixel data once every frame is decoded.
+    pub estimated_decoded_bytes: Option<u64>,
+    pub can_render: bool,
+    pub can_compute_stats: bool,
+    pub can_transcode: bool,
+    /// Pixel data has been through lossy compression.
+    pub lossy: bool,
+    pub warnings: Vec<String>,
 }
 
 /// Summary of pixel encoding and VOI/LUT hints.
@@ -77,6 +183,23 @@ pub struct PixelFormatSummary {
     pub pixel_representation: String,
     pub rescale_slope: Option<f64>,
     pub rescale_intercept: Option<f64>,
+    /// First window, kept for clients that only handle one.
     pub window_center: Option<f64>,
     pub window_width: Option<f64>,
+    /// Every window the object offers, in attribute order (`--window-index` selects one).
+    #[serde(default)]
+    pub windows: Vec<VoiWindow>,
+    /// Presentation LUT Shape (IDENTITY or INVERSE), when declared.
+    #[serde(default)]
+    pub presentation_lut_shape: Option<String>,
+}
+
+/// One Window Center/Width pair with its VOI LUT Function and explanation.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VoiWindow {
+    pub center: f64,
+    pub width: f64,
+    /// LINEAR, LINEAR_EXACT or SIGMOID.
+    pub function: String,
+    pub explanation: Option<String>,
 }
@@ -0,0 +1,685 @@
+//
+// mpps.rs
+// Dicom-Tools-rs
+//
+// Modality Performed Procedure Step (PS3.4 F.7): the attributes a modality sends with N-CREATE
+// when a procedure starts and with N-SET when it is completed or discontinued, gathered from the
+// instances it acquired, and the SCP-side store that enforces the step's status transitions.
+//
+// Thales Matheus Mendonça Santos - November 2025
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use anyhow::{bail, Context, Result};
+use chrono::NaiveDateTime;
+use dicom::core::value::DataSetSequence;
+use dicom::core::{DataElement, PrimitiveValue, Tag, VR};
+use dicom::dictionary_std::uids;
+use dicom::object::{InMemDicomObject, OpenFileOptions};
+use walkdir::WalkDir;
+
+use crate::atomic_file;
+use crate::dicom_access::ElementAccess;
+
+/// Modality Performed Procedure Step SOP Class.
+pub const MODALITY_PERFORMED_PROCEDURE_STEP: &str = "1.2.840.10008.3.1.2.3.3";
+
+const SPECIFIC_CHARACTER_SET: Tag = Tag(0x0008, 0x0005);
+const SOP_CLASS_UID: Tag = Tag(0x0008, 0x0016);
+const SOP_INSTANCE_UID: Tag = Tag(0x0008, 0x0018);
+const ACCESSION_NUMBER: Tag = Tag(0x0008, 0x0050);
+const RETRIEVE_AE_TITLE: Tag = Tag(0x0008, 0x0054);
+const MODALITY: Tag = Tag(0x0008, 0x0060);
+const STUDY_DESCRIPTION: Tag = Tag(0x0008, 0x1030);
+const PROCEDURE_CODE_SEQUENCE: Tag = Tag(0x0008, 0x1032);
+const SERIES_DESCRIPTION: Tag = Tag(0x0008, 0x103E);
+const PERFORMING_PHYSICIAN_NAME: Tag = Tag(0x0008, 0x1050);
+const OPERATORS_NAME: Tag = Tag(0x0008, 0x1070);
+const REFERENCED_STUDY_SEQUENCE: Tag = Tag(0x0008, 0x1110);
+const REFERENCED_PATIENT_SEQUENCE: Tag = Tag(0x0008, 0x1120);
+const REFERENCED_IMAGE_SEQUENCE: Tag = Tag(0x0008, 0x1140);
+const REFERENCED_SOP_CLASS_UID: Tag = Tag(0x0008, 0x1150);
+const REFERENCED_SOP_INSTANCE_UID: Tag = Tag(0x0008, 0x1155);
+const PATIENT_NAME: Tag = Tag(0x0010, 0x0010);
+const PATIENT_ID: Tag = Tag(0x0010, 0x0020);
+const PATIENT_BIRTH_DATE: Tag = Tag(0x0010, 0x0030);
+const PATIENT_SEX: Tag = Tag(0x0010, 0x0040);
+const PROTOCOL_NAME: Tag = Tag(0x0018, 0x1030);
+const STUDY_INSTANCE_UID: Tag = Tag(0x0020, 0x000D);
+const SERIES_INSTANCE_UID: Tag = Tag(0x0020, 0x000E);
+const STUDY_ID: Tag = Tag(0x0020, 0x0010);
+const ROWS: Tag = Tag(0x0028, 0x0010);
+const REQUESTED_PROCEDURE_DESCRIPTION: Tag = Tag(0x0032, 0x1060);
+const SCHEDULED_STEP_DESCRIPTION: Tag = Tag(0x0040, 0x0007);
+const SCHEDULED_PROTOCOL_CODE_SEQUENCE: Tag = Tag(0x0040, 0x0008);
+const SCHEDULED_STEP_ID: Tag = Tag(0x0040, 0x0009);
+const NON_IMAGE_SEQUENCE: Tag = Tag(0x0040, 0x0220);
+const PERFORMED_STATION_AE_TITLE: Tag = Tag(0x0040, 0x0241);
+const PERFORMED_STATION_NAME: Tag = Tag(0x0040, 0x0242);
+const PERFORMED_LOCATION: Tag = Tag(0x0040, 0x0243);
+const STEP_START_DATE: Tag = Tag(0x0040, 0x0244);
+const STEP_START_TIME: Tag = Tag(0x0040, 0x0245);
+const STEP_END_DATE: Tag = Tag(0x0040, 0x0250);
+const STEP_END_TIME: Tag = Tag(0x0040, 0x0251);
+const STEP_STATUS: Tag = Tag(0x0040, 0x0252);
+const STEP_ID: Tag = Tag(0x0040, 0x0253);
+const STEP_DESCRIPTION: Tag = Tag(0x0040, 0x0254);
+const STEP_TYPE_DESCRIPTION: Tag = Tag(0x0040, 0x0255);
+const SCHEDULED_STEP_ATTRIBUTES_SEQUENCE: Tag = Tag(0x0040, 0x0270);
+const REQUEST_ATTRIBUTES_SEQUENCE: Tag = Tag(0x0040, 0x0275);
+const PERFORMED_PROTOCOL_CODE_SEQUENCE: Tag = Tag(0x0040, 0x0260);
+const PERFORMED_SERIES_SEQUENCE: Tag = Tag(0x0040, 0x0340);
+const REQUESTED_PROCEDURE_ID: Tag = Tag(0x0040, 0x1001);
+const PIXEL_DATA: Tag = Tag(0x7FE0, 0x0010);
+
+/// Performed Procedure Step Status.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepStatus {
+    InProgress,
+    Completed,
+    Discontinued,
+}
+
+impl StepStatus {
+    pub fn code(self) -> &'static str {
+        match self {
+            StepStatus::InProgress => "IN PROGRESS",
+            StepStatus::Completed => "COMPLETED",
+            StepStatus::Discontinued => "DISCONTINUED",
+        }
+    }
+
+    pub fn parse(code: &str) -> Option<Self> {
+        match code.trim_matches(['\0', ' ']) {
+            "IN PROGRESS" => Some(StepStatus::InProgress),
+            "COMPLETED" => Some(StepStatus::Completed),
+            "DISCONTINUED" => Some(StepStatus::Discontinued),
+            _ => None,
+        }
+    }
+
+    /// Status of a step's attributes; `None` when absent or not a defined term.
+    pub fn of(attributes: &InMemDicomObject) -> Option<Self> {
+        attributes
+            .element_str(STEP_STATUS)
+            .and_then(|code| Self::parse(&code))
+    }
+}
+
+impl fmt::Display for StepStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.code())
+    }
+}
+
+/// One instance the modality produced.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct PerformedInstance {
+    sop_class_uid: String,
+    sop_instance_uid: String,
+    image: bool,
+}
+
+#[derive(Debug, Clone, Default)]
+struct PerformedSeries {
+    description: Option<String>,
+    protocol_name: Option<String>,
+    performing_physician: Option<String>,
+    operators: Option<String>,
+    instances: Vec<PerformedInstance>,
+}
+
+/// The request a study was acquired for.
+#[derive(Debug, Clone, Default)]
+struct PerformedStudy {
+    accession_number: Option<String>,
+    description: Option<String>,
+    requested_procedure_id: Option<String>,
+    requested_procedure_description: Option<String>,
+    scheduled_step_id: Option<String>,
+    scheduled_step_description: Option<String>,
+}
+
+/// What a modality performed: the patient, the studies and series it acquired and their
+/// instances, read from a directory of the files it sent.
+#[derive(Debug, Clone, Default)]
+pub struct Procedure {
+    patient_name: Option<String>,
+    patient_id: Option<String>,
+    patient_birth_date: Option<String>,
+    patient_sex: Option<String>,
+    modality: Option<String>,
+    studies: BTreeMap<String, PerformedStudy>,
+    series: BTreeMap<String, PerformedSeries>,
+}
+
+impl Procedure {
+    /// Read every DICOM file under `dir`; the patient and modality come from the first one.
+    pub fn scan(dir: &Path) -> Result<Self> {
+        if !dir.is_dir() {
+            bail!("{:?} is not a directory", dir);
+        }
+        let mut files: Vec<PathBuf> = WalkDir::new(dir)
+            .into_iter()
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_type().is_file())
+            .map(|e| e.into_path())
+            .collect();
+        files.sort();
+        let mut procedure = Procedure::default();
+        for path in files {
+            let Ok(obj) = OpenFileOptions::new()
+                .read_until(PIXEL_DATA)
+                .open_file(&path)
+            else {
+                continue;
+            };
+            procedure.add(&obj);
+        }
+        if procedure.series.is_empty() {
+            bail!("No DICOM instances found under {:?}", dir);
+        }
+        Ok(procedure)
+    }
+
+    fn add(&mut self, obj: &InMemDicomObject) {
+        let (Some(study_uid), Some(series_uid), Some(sop_class_uid), Some(sop_instance_uid)) = (
+            text(obj, STUDY_INSTANCE_UID),
+            text(obj, SERIES_INSTANCE_UID),
+            text(obj, SOP_CLASS_UID),
+            text(obj, SOP_INSTANCE_UID),
+        ) else {
+            return;
+        };
+        if self.series.is_empty() {
+            self.patient_name = text(obj, PATIENT_NAME);
+            self.patient_id = text(obj, PATIENT_ID);
+            self.patient_birth_date = text(obj, PATIENT_BIRTH_DATE);
+            self.patient_sex = text(obj, PATIENT_SEX);
+            self.modality = text(obj, MODALITY);
+        }
+        self.studies.entry(study_uid).or_insert_with(|| {
+            // Images acquired from a worklist entry carry it in the Request Attributes Sequence.
+            let request = obj.sequence_items(REQUEST_ATTRIBUTES_SEQUENCE).first();
+            let requested = |tag| request.and_then(|r| text(r, tag));
+            PerformedStudy {
+                accession_number: text(obj, ACCESSION_NUMBER),
+                description: text(obj, STUDY_DESCRIPTION),
+                requested_procedure_id: requested(REQUESTED_PROCEDURE_ID),
+                requested_procedure_description: requested(REQUESTED_PROCEDURE_DESCRIPTION),
+                scheduled_step_id: requested(SCHEDULED_STEP_ID),
+                scheduled_step_description: requested(SCHEDULED_STEP_DESCRIPTION),
+            }
+        });
+        let series = self
+            .series
+            .entry(series_uid)
+            .or_insert_with(|| PerformedSeries {
+                description: text(obj, SERIES_DESCRIPTION),
+                protocol_name: text(obj, PROTOCOL_NAME),
+                performing_physician: text(obj, PERFORMING_PHYSICIAN_NAME),
+                operators: text(obj, OPERATORS_NAME),
+                instances: Vec::new(),
+            });
+        series.instances.push(PerformedInstance {
+            sop_class_uid,
+            sop_instance_uid,
+            image: obj.has_element(ROWS),
+        });
+    }
+
+    pub fn instance_count(&self) -> usize {
+        self.series.values().map(|s| s.instances.len()).sum()
+    }
+
+    pub fn series_count(&self) -> usize {
+        self.series.len()
+    }
+
+    /// N-CREATE attributes of a step started at `started` on `station_ae_title`. Every type 2
+    /// attribute the SCP expects is present, empty when unknown; the performed series are only
+    /// listed when the step is finished.
+    pub fn in_progress(
+        &self,
+        step_id: &str,
+        station_ae_title: &str,
+        started: NaiveDateTime,
+    ) -> InMemDicomObject {
+        let mut obj = InMemDicomObject::new_empty();
+        obj.put(str_element(
+            SPECIFIC_CHARACTER_SET,
+            VR::CS,
+            Some("ISO_IR 192"),
+        ));
+        obj.put(str_element(
+            PATIENT_NAME,
+            VR::PN,
+            self.patient_name.as_deref(),
+        ));
+        obj.put(str_element(PATIENT_ID, VR::LO, self.patient_id.as_deref()));
+        obj.put(str_element(
+            PATIENT_BIRTH_DATE,
+            VR::DA,
+            self.patient_birth_date.as_deref(),
+        ));
+        obj.put(str_element(
+            PATIENT_SEX,
+            VR::CS,
+            self.patient_sex.as_deref(),
+        ));
+        put_sequence(&mut obj, REFERENCED_PATIENT_SEQUENCE, Vec::new());
+        put_sequence(
+            &mut obj,
+            SCHEDULED_STEP_ATTRIBUTES_SEQUENCE,
+            self.studies
+                .iter()
+                .map(|(uid, study)| study.scheduled_step(uid))
+                .collect(),
+        );
+        obj.put(str_element(STEP_ID, VR::SH, Some(step_id)));
+        obj.put(str_element(
+            PERFORMED_STATION_AE_TITLE,
+            VR::AE,
+            Some(station_ae_title),
+        ));
+        obj.put(str_element(PERFORMED_STATION_NAME, VR::SH, None));
+        obj.put(str_element(PERFORMED_LOCATION, VR::SH, None));
+        obj.put(str_element(
+            STEP_START_DATE,
+            VR::DA,
+            Some(&started.format("%Y%m%d").to_string()),
+        ));
+        obj.put(str_element(
+            STEP_START_TIME,
+            VR::TM,
+            Some(&started.format("%H%M%S").to_string()),
+        ));
+        obj.put(str_element(
+            STEP_STATUS,
+            VR::CS,
+            Some(StepStatus::InProgress.code()),
+        ));
+        let description = self.studies.values().find_map(|s| s.description.as_deref());
+        obj.put(str_element(STEP_DESCRIPTION, VR::LO, description));
+        obj.put(str_element(STEP_TYPE_DESCRIPTION, VR::LO, None));
+        put_sequence(&mut obj, PROCEDURE_CODE_SEQUENCE, Vec::new());
+        obj.put(str_element(STEP_END_DATE, VR::DA, None));
+        obj.put(str_element(STEP_END_TIME, VR::TM, None));
+        obj.put(str_element(MODALITY, VR::CS, self.modality.as_deref()));
+        obj.put(str_element(STUDY_ID, VR::SH, None));
+        put_sequence(&mut obj, PERFORMED_PROTOCOL_CODE_SEQUENCE, Vec::new());
+        put_sequence(&mut obj, PERFORMED_SERIES_SEQUENCE, Vec::new());
+        obj
+    }
+
+    /// N-SET attributes finishing the step as `status` at `ended`, listing every instance
+    /// acquired; `retrieve_ae_title` is where they can be retrieved from, when known.
+    pub fn completion(
+        &self,
+        status: StepStatus,
+        ended: NaiveDateTime,
+        retrieve_ae_title: Option<&str>,
+    ) -> InMemDicomObject {
+        let mut obj = InMemDicomObject::new_empty();
+        obj.put(str_element(STEP_STATUS, VR::CS, Some(status.code())));
+        obj.put(str_element(
+            STEP_END_DATE,
+            VR::DA,
+            Some(&ended.format("%Y%m%d").to_string()),
+        ));
+        obj.put(str_element(
+            STEP_END_TIME,
+            VR::TM,
+            Some(&ended.format("%H%M%S").to_string()),
+        ));
+        put_sequence(
+            &mut obj,
+            PERFORMED_SERIES_SEQUENCE,
+            self.series
+                .iter()
+                .map(|(uid, series)| series.item(uid, retrieve_ae_title))
+                .collect(),
+        );
+        obj
+    }
+}
+
+impl PerformedStudy {
+    fn scheduled_step(&self, study_uid: &str) -> InMemDicomObject {
+        let mut item = InMemDicomObject::new_empty();
+        item.put(str_element(STUDY_INSTANCE_UID, VR::UI, Some(study_uid)));
+        put_sequence(&mut item, REFERENCED_STUDY_SEQUENCE, Vec::new());
+        item.put(str_element(
+            ACCESSION_NUMBER,
+            VR::SH,
+            self.accession_number.as_deref(),
+        ));
+        item.put(str_element(
+            REQUESTED_PROCEDURE_ID,
+            VR::SH,
+            self.requested_procedure_id.as_deref(),
+        ));
+        item.put(str_element(
+            REQUESTED_PROCEDURE_DESCRIPTION,
+            VR::LO,
+            self.requested_procedure_description.as_deref(),
+        ));
+        item.put(str_element(
+            SCHEDULED_STEP_ID,
+            VR::SH,
+            self.scheduled_step_id.as_deref(),
+        ));
+        item.put(str_element(
+            SCHEDULED_STEP_DESCRIPTION,
+            VR::LO,
+            self.scheduled_step_description.as_deref(),
+        ));
+        put_sequence(&mut item, SCHEDULED_PROTOCOL_CODE_SEQUENCE, Vec::new());
+        item
+    }
+}
+
+impl PerformedSeries {
+    /// Performed Series Sequence item; images and other instances are listed separately.
+    fn item(&self, series_uid: &str, retrieve_ae_title: Option<&str>) -> InMemDicomObject {
+        let mut item = InMemDicomObject::new_empty();
+        item.put(str_element(
+            PERFORMING_PHYSICIAN_NAME,
+            VR::PN,
+            self.performing_physician.as_deref(),
+        ));
+        item.put(str_element(
+            PROTOCOL_NAME,
+            VR::LO,
+            Some(self.protocol_name.as_deref().unwrap_or("UNKNOWN")),
+        ));
+        item.put(str_element(
+            OPERATORS_NAME,
+            VR::PN,
+            self.operators.as_deref(),
+        ));
+        item.put(str_element(SERIES_INSTANCE_UID, VR::UI, Some(series_uid)));
+        item.put(str_element(
+            SERIES_DESCRIPTION,
+            VR::LO,
+            self.description.as_deref(),
+        ));
+        item.put(str_element(RETRIEVE_AE_TITLE, VR::AE, retrieve_ae_title));
+        let reference = |instance: &PerformedInstance| {
+            let mut reference = InMemDicomObject::new_empty();
+            reference.put(str_element(
+                REFERENCED_SOP_CLASS_UID,
+                VR::UI,
+                Some(&instance.sop_class_uid),
+            ));
+            reference.put(str_element(
+                REFERENCED_SOP_INSTANCE_UID,
+                VR::UI,
+                Some(&instance.sop_instance_uid),
+            ));
+            reference
+        };
+        let (images, others): (Vec<_>, Vec<_>) = self.instances.iter().partition(|i| i.image);
+        put_sequence(
+            &mut item,
+            REFERENCED_IMAGE_SEQUENCE,
+            images.into_iter().map(reference).collect(),
+        );
+        put_sequence(
+            &mut item,
+            NON_IMAGE_SEQUENCE,
+            others.into_iter().map(reference).collect(),
+        );
+        item
+    }
+}
+
+/// Instances listed in a step's Performed Series Sequence, as (SOP Class, SOP Instance) UIDs.
+pub fn referenced_instances(attributes: &InMemDicomObject) -> Vec<(String, String)> {
+    attributes
+        .sequence_items(PERFORMED_SERIES_SEQUENCE)
+        .iter()
+        .flat_map(|series| {
+            let images = series.sequence_items(REFERENCED_IMAGE_SEQUENCE);
+            let others = series.sequence_items(NON_IMAGE_SEQUENCE);
+            images.iter().chain(others)
+        })
+        .filter_map(|reference| {
+            Some((
+                text(reference, REFERENCED_SOP_CLASS_UID)?,
+                text(reference, REFERENCED_SOP_INSTANCE_UID)?,
+            ))
+        })
+        .collect()
+}
+
+/// Why the SCP refused an N-CREATE or N-SET.
+#[derive(Debug)]
+pub enum MppsRefusal {
+    /// N-CREATE of an instance that already exists.
+    Duplicate,
+    /// N-SET of an instance that was never created.
+    NoSuchInstance,
+    /// A step must be created IN PROGRESS and finished as COMPLETED or DISCONTINUED.
+    InvalidStatus(String),
+    /// A finished step cannot be modified any more.
+    AlreadyFinished(StepStatus),
+    Failed(anyhow::Error),
+}
+
+impl fmt::Display for MppsRefusal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MppsRefusal::Duplicate => f.write_str("step already exists"),
+            MppsRefusal::NoSuchInstance => f.write_str("no such step"),
+            MppsRefusal::InvalidStatus(status) => write!(f, "invalid step status {:?}", status),
+            MppsRefusal::AlreadyFinished(status) => write!(f, "step is already {}", status),
+            MppsRefusal::Failed(err) => write!(f, "{:#}", err),
+        }
+    }
+}
+
+/// Steps received by the SCP, one `<SOP Instance UID>.dcm` file each.
+#[derive(Debug)]
+pub struct MppsStore {
+    dir: PathBuf,
+    /// Serializes read-modify-write of the step files across associations.
+    lock: Mutex<()>,
+}
+
+impl MppsStore {
+    pub fn new(dir: &Path) -> Result<Self> {
+        fs::create_dir_all(dir).with_context(|| format!("Failed to create {:?}", dir))?;
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            lock: Mutex::new(()),
+        })
+    }
+
+    pub fn path(&self, sop_instance_uid: &str) -> PathBuf {
+        self.dir.join(format!("{}.dcm", sop_instance_uid))
+    }
+
+    /// Attributes of a stored step.
+    pub fn load(&self, sop_instance_uid: &str) -> Result<Option<InMemDicomObject>> {
+        let path = self.path(sop_instance_uid);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let obj = OpenFileOptions::new()
+            .open_file(&path)
+            .with_context(|| format!("Failed to read {:?}", path))?;
+        Ok(Some(obj.into_inner()))
+    }
+
+    /// Record a new step; it must start IN PROGRESS.
+    pub fn create(
+        &self,
+        sop_instance_uid: &str,
+        mut attributes: InMemDicomObject,
+    ) -> Result<(), MppsRefusal> {
+        let _guard = self.lock.lock().unwrap_or_else(|e| e.into_inner());
+        if self.path(sop_instance_uid).exists() {
+            return Err(MppsRefusal::Duplicate);
+        }
+        match StepStatus::of(&attributes) {
+            Some(StepStatus::InProgress) => {}
+            _ => return Err(invalid_status(&attributes)),
+        }
+        attributes.put(str_element(
+            SOP_CLASS_UID,
+            VR::UI,
+            Some(MODALITY_PERFORMED_PROCEDURE_STEP),
+        ));
+        attributes.put(str_element(
+            SOP_INSTANCE_UID,
+            VR::UI,
+            Some(sop_instance_uid),
+        ));
+        self.save(sop_instance_uid, attributes)
+            .map_err(MppsRefusal::Failed)
+    }
+
+    /// Apply an N-SET to an IN PROGRESS step and return its resulting status. Once COMPLETED
+    /// or DISCONTINUED the step is final.
+    pub fn set(
+        &self,
+        sop_instance_uid: &str,
+        modifications: InMemDicomObject,
+    ) -> Result<StepStatus, MppsRefusal> {
+        let _guard = self.lock.lock().unwrap_or_else(|e| e.into_inner());
+        let mut attributes = self
+            .load(sop_instance_uid)
+            .map_err(MppsRefusal::Failed)?
+            .ok_or(MppsRefusal::NoSuchInstance)?;
+        match StepStatus::of(&attributes) {
+            Some(StepStatus::InProgress) => {}
+            Some(finished) => return Err(MppsRefusal::AlreadyFinished(finished)),
+            None => return Err(invalid_status(&attributes)),
+        }
+        if modifications.element(STEP_STATUS).is_ok() && StepStatus::of(&modifications).is_none() {
+            return Err(invalid_status(&modifications));
+        }
+        for element in modifications {
+            attributes.put(element);
+        }
+        let status = StepStatus::of(&attributes).unwrap_or(StepStatus::InProgress);
+        self.save(sop_instance_uid, attributes)
+            .map_err(MppsRefusal::Failed)?;
+        Ok(status)
+    }
+
+    fn save(&self, sop_instance_uid: &str, attributes: InMemDicomObject) -> Result<()> {
+        let obj = attributes
+            .with_meta(
+                dicom::object::FileMetaTableBuilder::new()
+                    .transfer_syntax(uids::EXPLICIT_VR_LITTLE_ENDIAN)
+                    .media_storage_sop_class_uid(MODALITY_PERFORMED_PROCEDURE_STEP)
+                    .media_storage_sop_instance_uid(sop_instance_uid),
+            )
+            .context("Failed to build file meta information")?;
+        atomic_file::write_dicom(&self.path(sop_instance_uid), &obj)
+    }
+}
+
+fn invalid_status(attributes: &InMemDicomObject) -> MppsRefusal {
+    MppsRefusal::InvalidStatus(attributes.element_str(STEP_STATUS).unwrap_or_default())
+}
+
+fn text(obj: &InMemDicomObject, tag: Tag) -> Option<String> {
+    obj.element_str(tag)
+        .map(|v| v.trim_matches(['\0', ' ']).to_string())
+        .filter(|v| !v.is_empty())
+}
+
+/// A string element, or an empty (type 2) one when there is no value.
+fn str_element(tag: Tag, vr: VR, value: Option<&str>) -> DataElement<InMemDicomObject> {
+    let value = match value {
+        Some(value) => PrimitiveValue::from(value),
+        None => PrimitiveValue::Empty,
+    };
+    DataElement::new(tag, vr, value)
+}
+
+fn put_sequence(obj: &mut InMemDicomObject, tag: Tag, items: Vec<InMemDicomObject>) {
+    obj.put(DataElement::new(tag, VR::SQ, DataSetSequence::from(items)));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn instance(series: &str, sop_instance: &str, image: bool) -> InMemDicomObject {
+        let mut obj = InMemDicomObject::new_empty();
+        obj.put(str_element(PATIENT_ID, VR::LO, Some("P1")));
+        obj.put(str_element(MODALITY, VR::CS, Some("CT")));
+        obj.put(str_element(STUDY_INSTANCE_UID, VR::UI, Some("1.2.3")));
+        obj.put(str_element(SERIES_INSTANCE_UID, VR::UI, Some(series)));
+        obj.put(str_element(
+            SOP_CLASS_UID,
+            VR::UI,
+            Some("1.2.840.10008.5.1.4.1.1.2"),
+        ));
+        obj.put(str_element(SOP_INSTANCE_UID, VR::UI, Some(sop_instance)));
+        if image {
+            obj.put(DataElement::new(ROWS, VR::US, PrimitiveValue::from(2_u16)));
+        }
+        obj
+    }
+
+    #[test]
+    fn completion_lists_images_and_other_instances_per_series() {
+        let mut procedure = Procedure::default();
+        procedure.add(&instance("1.2.3.1", "1.2.3.1.1", true));
+        procedure.add(&instance("1.2.3.1", "1.2.3.1.2", true));
+        procedure.add(&instance("1.2.3.2", "1.2.3.2.1", false));
+        let ended = NaiveDateTime::parse_from_str("20250102 101500", "%Y%m%d %H%M%S").unwrap();
+        let set = procedure.completion(StepStatus::Completed, ended, Some("PACS"));
+
+        assert_eq!(StepStatus::of(&set), Some(StepStatus::Completed));
+        assert_eq!(set.element_str(STEP_END_TIME).as_deref(), Some("101500"));
+        let series = set.sequence_items(PERFORMED_SERIES_SEQUENCE);
+        assert_eq!(series.len(), 2);
+        assert_eq!(series[0].sequence_items(REFERENCED_IMAGE_SEQUENCE).len(), 2);
+        assert_eq!(series[1].sequence_items(NON_IMAGE_SEQUENCE).len(), 1);
+        assert_eq!(referenced_instances(&set).len(), 3);
+    }
+
+    #[test]
+    fn finished_steps_cannot_be_modified() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = MppsStore::new(dir.path()).unwrap();
+        let mut procedure = Procedure::default();
+        procedure.add(&instance("1.2.3.1", "1.2.3.1.1", true));
+        let now = chrono::Local::now().naive_local();
+
+        let finished = procedure.completion(StepStatus::Completed, now, None);
+        assert!(matches!(
+            store.create("1.9", finished.clone()),
+            Err(MppsRefusal::InvalidStatus(_))
+        ));
+        store
+            .create("1.9", procedure.in_progress("PPS1", "CT01", now))
+            .unwrap();
+        assert!(matches!(
+            store.create("1.9", procedure.in_progress("PPS1", "CT01", now)),
+            Err(MppsRefusal::Duplicate)
+        ));
+        assert_eq!(
+            store.set("1.9", finished.clone()).unwrap(),
+            StepStatus::Completed
+        );
+        assert!(matches!(
+            store.set("1.9", finished),
+            Err(MppsRefusal::AlreadyFinished(StepStatus::Completed))
+        ));
+        assert!(matches!(
+            store.set("1.8", InMemDicomObject::new_empty()),
+            Err(MppsRefusal::NoSuchInstance)
+        ));
+    }
+}
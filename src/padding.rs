@@ -0,0 +1,302 @@
+//
+// padding.rs
+// Dicom-Tools-rs
+//
+// Pixels that carry no image: those equal to Pixel Padding Value (or within the padding range,
+// PS3.3 C.7.5.1.1.2), e.g. outside the circular reconstruction field of a CT, and those hidden
+// by a rectangular, circular or polygonal display shutter (PS3.3 C.7.6.11).
+//
+// Thales Matheus Mendonça Santos - November 2025
+
+use anyhow::{Context, Result};
+use dicom::core::{Tag, VR};
+use dicom::object::InMemDicomObject;
+use dicom_pixeldata::{ConvertOptions, DecodedPixelData, ModalityLutOption, PixelRepresentation};
+
+use crate::dicom_access::ElementAccess;
+
+const SHUTTER_SHAPE: Tag = Tag(0x0018, 0x1600);
+const SHUTTER_LEFT_VERTICAL_EDGE: Tag = Tag(0x0018, 0x1602);
+const SHUTTER_RIGHT_VERTICAL_EDGE: Tag = Tag(0x0018, 0x1604);
+const SHUTTER_UPPER_HORIZONTAL_EDGE: Tag = Tag(0x0018, 0x1606);
+const SHUTTER_LOWER_HORIZONTAL_EDGE: Tag = Tag(0x0018, 0x1608);
+const CENTER_OF_CIRCULAR_SHUTTER: Tag = Tag(0x0018, 0x1610);
+const RADIUS_OF_CIRCULAR_SHUTTER: Tag = Tag(0x0018, 0x1612);
+const VERTICES_OF_POLYGONAL_SHUTTER: Tag = Tag(0x0018, 0x1620);
+const PIXEL_PADDING_VALUE: Tag = Tag(0x0028, 0x0120);
+const PIXEL_PADDING_RANGE_LIMIT: Tag = Tag(0x0028, 0x0121);
+
+/// Which pixels carrying no image to leave out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Exclusions {
+    pub padding: bool,
+    pub shutters: bool,
+}
+
+impl Default for Exclusions {
+    fn default() -> Self {
+        Self {
+            padding: true,
+            shutters: true,
+        }
+    }
+}
+
+impl Exclusions {
+    pub const NONE: Exclusions = Exclusions {
+        padding: false,
+        shutters: false,
+    };
+}
+
+/// Stored values marking padding, inclusive at both ends (a single value when no range limit
+/// is given).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PaddingRange {
+    pub low: f64,
+    pub high: f64,
+}
+
+impl PaddingRange {
+    /// Pixel Padding Value, and Pixel Padding Range Limit when present, of `obj`. Both are
+    /// US or SS like the pixels; a value read as US from a signed image is reinterpreted.
+    pub fn of(obj: &InMemDicomObject, representation: PixelRepresentation) -> Result<Option<Self>> {
+        let value = |tag: Tag| -> Result<Option<f64>> {
+            let Ok(element) = obj.element(tag) else {
+                return Ok(None);
+            };
+            let value = element
+                .to_int::<i64>()
+                .with_context(|| format!("Invalid {}", tag_name(tag)))?;
+            let signed = representation == PixelRepresentation::Signed;
+            Ok(Some(if signed && element.vr() == VR::US {
+                value as u16 as i16 as f64
+            } else {
+                value as f64
+            }))
+        };
+        let Some(padding) = value(PIXEL_PADDING_VALUE)? else {
+            return Ok(None);
+        };
+        let limit = value(PIXEL_PADDING_RANGE_LIMIT)?.unwrap_or(padding);
+        Ok(Some(Self {
+            low: padding.min(limit),
+            high: padding.max(limit),
+        }))
+    }
+
+    pub fn contains(&self, stored: f64) -> bool {
+        (self.low..=self.high).contains(&stored)
+    }
+}
+
+fn tag_name(tag: Tag) -> &'static str {
+    match tag {
+        PIXEL_PADDING_RANGE_LIMIT => "Pixel Padding Range Limit",
+        _ => "Pixel Padding Value",
+    }
+}
+
+/// One display shutter, in 1-based pixel coordinates.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Shutter {
+    Rectangular {
+        left: f64,
+        right: f64,
+        upper: f64,
+        lower: f64,
+    },
+    Circular {
+        row: f64,
+        column: f64,
+        radius: f64,
+    },
+    /// Vertices as (row, column).
+    Polygonal(Vec<(f64, f64)>),
+}
+
+impl Shutter {
+    /// Shutters of the Display Shutter module; shapes with missing or malformed attributes,
+    /// and bitmap shutters, are ignored.
+    pub fn of(obj: &InMemDicomObject) -> Vec<Shutter> {
+        let Some(shapes) = obj.element_str(SHUTTER_SHAPE) else {
+            return Vec::new();
+        };
+        let numbers = |tag| -> Vec<f64> {
+            obj.element_str(tag)
+                .map(|text| {
+                    text.split('\\')
+                        .filter_map(|v| v.trim_matches(['\0', ' ']).parse().ok())
+                        .collect()
+                })
+                .unwrap_or_default()
+        };
+        let single = |tag| numbers(tag).first().copied();
+        shapes
+            .split('\\')
+            .filter_map(|shape| match shape.trim_matches(['\0', ' ']) {
+                "RECTANGULAR" => Some(Shutter::Rectangular {
+                    left: single(SHUTTER_LEFT_VERTICAL_EDGE)?,
+                    right: single(SHUTTER_RIGHT_VERTICAL_EDGE)?,
+                    upper: single(SHUTTER_UPPER_HORIZONTAL_EDGE)?,
+                    lower: single(SHUTTER_LOWER_HORIZONTAL_EDGE)?,
+                }),
+                "CIRCULAR" => match numbers(CENTER_OF_CIRCULAR_SHUTTER)[..] {
+                    [row, column] => Some(Shutter::Circular {
+                        row,
+                        column,
+                        radius: single(RADIUS_OF_CIRCULAR_SHUTTER)?,
+                    }),
+                    _ => None,
+                },
+                "POLYGONAL" => {
+                    let vertices: Vec<_> = numbers(VERTICES_OF_POLYGONAL_SHUTTER)
+                        .chunks_exact(2)
+                        .map(|pair| (pair[0], pair[1]))
+                        .collect();
+                    (vertices.len() >= 3).then_some(Shutter::Polygonal(vertices))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Whether the pixel at 1-based (`row`, `column`) is left visible by this shutter.
+    pub fn shows(&self, row: f64, column: f64) -> bool {
+        match self {
+            Shutter::Rectangular {
+                left,
+                right,
+                upper,
+                lower,
+            } => (*left..=*right).contains(&column) && (*upper..=*lower).contains(&row),
+            Shutter::Circular {
+                row: center_row,
+                column: center_column,
+                radius,
+            } => (row - center_row).hypot(column - center_column) <= *radius,
+            Shutter::Polygonal(vertices) => {
+                // Even-odd ray casting along the row.
+                let mut inside = false;
+                let mut previous = vertices[vertices.len() - 1];
+                for &vertex in vertices {
+                    let ((r1, c1), (r2, c2)) = (previous, vertex);
+                    if (r1 > row) != (r2 > row) && column < c1 + (row - r1) * (c2 - c1) / (r2 - r1)
+                    {
+                        inside = !inside;
+                    }
+                    previous = vertex;
+                }
+                inside
+            }
+        }
+    }
+}
+
+/// One flag per pixel over every frame, `true` for pixels to leave out; `None` when the image
+/// has neither padding nor shutters (or `exclusions` ignores them). Padding only applies to
+/// single-sample images.
+pub fn excluded_pixels(
+    obj: &InMemDicomObject,
+    decoded: &DecodedPixelData,
+    exclusions: Exclusions,
+) -> Result<Option<Vec<bool>>> {
+    let padding = if exclusions.padding && decoded.samples_per_pixel() == 1 {
+        PaddingRange::of(obj, decoded.pixel_representation())?
+    } else {
+        None
+    };
+    let shutters = if exclusions.shutters {
+        Shutter::of(obj)
+    } else {
+        Vec::new()
+    };
+    if padding.is_none() && shutters.is_empty() {
+        return Ok(None);
+    }
+
+    let (rows, columns) = (decoded.rows() as usize, decoded.columns() as usize);
+    let hidden: Vec<bool> = (0..rows * columns)
+        .map(|i| {
+            let (row, column) = ((i / columns + 1) as f64, (i % columns + 1) as f64);
+            !shutters.iter().all(|shutter| shutter.shows(row, column))
+        })
+        .collect();
+    let frames = decoded.number_of_frames() as usize;
+    let mut excluded: Vec<bool> = hidden
+        .iter()
+        .copied()
+        .cycle()
+        .take(frames * rows * columns)
+        .collect();
+    if let Some(padding) = padding {
+        // Padding is defined on stored values, before any rescale.
+        let stored = decoded
+            .to_vec_with_options::<f32>(
+                &ConvertOptions::new().with_modality_lut(ModalityLutOption::None),
+            )
+            .context("Failed to convert pixel data")?;
+        for (flag, value) in excluded.iter_mut().zip(stored) {
+            *flag |= padding.contains(value as f64);
+        }
+    }
+    Ok(Some(excluded))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dicom::core::{DataElement, PrimitiveValue};
+
+    #[test]
+    fn signed_padding_read_as_us_is_reinterpreted() {
+        let mut obj = InMemDicomObject::new_empty();
+        obj.put(DataElement::new(
+            PIXEL_PADDING_VALUE,
+            VR::US,
+            PrimitiveValue::from(0x8000_u16),
+        ));
+        let range = PaddingRange::of(&obj, PixelRepresentation::Signed)
+            .unwrap()
+            .unwrap();
+        assert_eq!(range.low, -32768.0);
+        assert!(range.contains(-32768.0) && !range.contains(-1000.0));
+
+        obj.put(DataElement::new(
+            PIXEL_PADDING_RANGE_LIMIT,
+            VR::SS,
+            PrimitiveValue::from(-2000_i16),
+        ));
+        let range = PaddingRange::of(&obj, PixelRepresentation::Signed)
+            .unwrap()
+            .unwrap();
+        assert!(range.contains(-2000.0) && range.contains(-30000.0));
+        assert!(!range.contains(-1999.0));
+    }
+
+    #[test]
+    fn shutters_combine_by_intersection() {
+        let mut obj = InMemDicomObject::new_empty();
+        let put = |obj: &mut InMemDicomObject, tag, vr, value: &str| {
+            obj.put(DataElement::new(tag, vr, PrimitiveValue::from(value)));
+        };
+        put(&mut obj, SHUTTER_SHAPE, VR::CS, "RECTANGULAR\\CIRCULAR");
+        put(&mut obj, SHUTTER_LEFT_VERTICAL_EDGE, VR::IS, "2");
+        put(&mut obj, SHUTTER_RIGHT_VERTICAL_EDGE, VR::IS, "9");
+        put(&mut obj, SHUTTER_UPPER_HORIZONTAL_EDGE, VR::IS, "2");
+        put(&mut obj, SHUTTER_LOWER_HORIZONTAL_EDGE, VR::IS, "9");
+        put(&mut obj, CENTER_OF_CIRCULAR_SHUTTER, VR::IS, "5\\5");
+        put(&mut obj, RADIUS_OF_CIRCULAR_SHUTTER, VR::IS, "3");
+        let shutters = Shutter::of(&obj);
+        assert_eq!(shutters.len(), 2);
+        let shows = |row, column| shutters.iter().all(|s| s.shows(row, column));
+        assert!(shows(5.0, 5.0));
+        assert!(shows(2.0, 5.0));
+        assert!(!shows(2.0, 2.0), "inside the rectangle, outside the circle");
+        assert!(!shows(5.0, 1.0), "outside the rectangle");
+
+        let triangle = Shutter::Polygonal(vec![(1.0, 1.0), (1.0, 9.0), (9.0, 1.0)]);
+        assert!(triangle.shows(2.0, 2.0));
+        assert!(!triangle.shows(8.0, 8.0));
+    }
+}
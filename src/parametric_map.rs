@@ -0,0 +1,789 @@
+//
+// parametric_map.rs
+// Dicom-Tools-rs
+//
+// Writes Parametric Map objects from float arrays computed elsewhere (ADC, SUV, AI heatmaps):
+// the values go to Float Pixel Data with a Real World Value Mapping carrying their units,
+// and every frame takes its geometry and provenance from a reference series.
+//
+// Thales Matheus Mendonça Santos - November 2025
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use chrono::Local;
+use dicom::core::value::DataSetSequence;
+use dicom::core::{DataElement, PrimitiveValue, Tag, VR};
+use dicom::dictionary_std::uids;
+use dicom::object::{DefaultDicomObject, InMemDicomObject, OpenFileOptions};
+use ndarray::{Array3, ArrayView3};
+use walkdir::WalkDir;
+
+use crate::atomic_file;
+use crate::codes::{Code, CODE_MEANING, CODE_VALUE, CODING_SCHEME_DESIGNATOR};
+use crate::derivation::new_instance_uid;
+use crate::dicom_access::ElementAccess;
+use crate::float_pixels::FLOAT_PIXEL_DATA;
+use crate::measure::{frame_multi_f64, FrameGeometry};
+
+pub const PARAMETRIC_MAP_STORAGE: &str = "1.2.840.10008.5.1.4.1.1.30";
+
+const IMAGE_TYPE: Tag = Tag(0x0008, 0x0008);
+const SOP_CLASS_UID: Tag = Tag(0x0008, 0x0016);
+const SOP_INSTANCE_UID: Tag = Tag(0x0008, 0x0018);
+const STUDY_DATE: Tag = Tag(0x0008, 0x0020);
+const CONTENT_DATE: Tag = Tag(0x0008, 0x0023);
+const STUDY_TIME: Tag = Tag(0x0008, 0x0030);
+const CONTENT_TIME: Tag = Tag(0x0008, 0x0033);
+const ACCESSION_NUMBER: Tag = Tag(0x0008, 0x0050);
+const MODALITY: Tag = Tag(0x0008, 0x0060);
+const REFERRING_PHYSICIAN_NAME: Tag = Tag(0x0008, 0x0090);
+const SERIES_DESCRIPTION: Tag = Tag(0x0008, 0x103E);
+const REFERENCED_SERIES_SEQUENCE: Tag = Tag(0x0008, 0x1115);
+const REFERENCED_IMAGE_SEQUENCE: Tag = Tag(0x0008, 0x1140);
+const REFERENCED_INSTANCE_SEQUENCE: Tag = Tag(0x0008, 0x114A);
+const REFERENCED_SOP_CLASS_UID: Tag = Tag(0x0008, 0x1150);
+const REFERENCED_SOP_INSTANCE_UID: Tag = Tag(0x0008, 0x1155);
+const REFERENCED_FRAME_NUMBER: Tag = Tag(0x0008, 0x1160);
+const BURNED_IN_ANNOTATION: Tag = Tag(0x0028, 0x0301);
+const RECOGNIZABLE_VISUAL_FEATURES: Tag = Tag(0x0028, 0x0302);
+const PATIENT_NAME: Tag = Tag(0x0010, 0x0010);
+const PATIENT_ID: Tag = Tag(0x0010, 0x0020);
+const PATIENT_BIRTH_DATE: Tag = Tag(0x0010, 0x0030);
+const PATIENT_SEX: Tag = Tag(0x0010, 0x0040);
+const SLICE_THICKNESS: Tag = Tag(0x0018, 0x0050);
+const STUDY_INSTANCE_UID: Tag = Tag(0x0020, 0x000D);
+const SERIES_INSTANCE_UID: Tag = Tag(0x0020, 0x000E);
+const STUDY_ID: Tag = Tag(0x0020, 0x0010);
+const SERIES_NUMBER: Tag = Tag(0x0020, 0x0011);
+const INSTANCE_NUMBER: Tag = Tag(0x0020, 0x0013);
+const IMAGE_POSITION: Tag = Tag(0x0020, 0x0032);
+const IMAGE_ORIENTATION: Tag = Tag(0x0020, 0x0037);
+const FRAME_OF_REFERENCE_UID: Tag = Tag(0x0020, 0x0052);
+const DIMENSION_ORGANIZATION_UID: Tag = Tag(0x0020, 0x9164);
+const DIMENSION_ORGANIZATION_SEQUENCE: Tag = Tag(0x0020, 0x9221);
+const DIMENSION_INDEX_SEQUENCE: Tag = Tag(0x0020, 0x9222);
+const DIMENSION_INDEX_POINTER: Tag = Tag(0x0020, 0x9165);
+const FUNCTIONAL_GROUP_POINTER: Tag = Tag(0x0020, 0x9167);
+const FRAME_CONTENT_SEQUENCE: Tag = Tag(0x0020, 0x9111);
+const PLANE_POSITION_SEQUENCE: Tag = Tag(0x0020, 0x9113);
+const PLANE_ORIENTATION_SEQUENCE: Tag = Tag(0x0020, 0x9116);
+const DIMENSION_INDEX_VALUES: Tag = Tag(0x0020, 0x9157);
+const SAMPLES_PER_PIXEL: Tag = Tag(0x0028, 0x0002);
+const PHOTOMETRIC_INTERPRETATION: Tag = Tag(0x0028, 0x0004);
+const NUMBER_OF_FRAMES: Tag = Tag(0x0028, 0x0008);
+const ROWS: Tag = Tag(0x0028, 0x0010);
+const COLUMNS: Tag = Tag(0x0028, 0x0011);
+const PIXEL_SPACING: Tag = Tag(0x0028, 0x0030);
+const BITS_ALLOCATED: Tag = Tag(0x0028, 0x0100);
+const RESCALE_INTERCEPT: Tag = Tag(0x0028, 0x1052);
+const RESCALE_SLOPE: Tag = Tag(0x0028, 0x1053);
+const RESCALE_TYPE: Tag = Tag(0x0028, 0x1054);
+const PRESENTATION_LUT_SHAPE: Tag = Tag(0x2050, 0x0020);
+const LOSSY_IMAGE_COMPRESSION: Tag = Tag(0x0028, 0x2110);
+const LUT_EXPLANATION: Tag = Tag(0x0028, 0x3003);
+const PIXEL_MEASURES_SEQUENCE: Tag = Tag(0x0028, 0x9110);
+const PIXEL_VALUE_TRANSFORMATION_SEQUENCE: Tag = Tag(0x0028, 0x9145);
+const MEASUREMENT_UNITS_CODE_SEQUENCE: Tag = Tag(0x0040, 0x08EA);
+const REAL_WORLD_VALUE_MAPPING_SEQUENCE: Tag = Tag(0x0040, 0x9096);
+const DOUBLE_FLOAT_FIRST_VALUE_MAPPED: Tag = Tag(0x0040, 0x9213);
+const DOUBLE_FLOAT_LAST_VALUE_MAPPED: Tag = Tag(0x0040, 0x9214);
+const LUT_LABEL: Tag = Tag(0x0040, 0x9210);
+const QUANTITY_DEFINITION_SEQUENCE: Tag = Tag(0x0040, 0x9220);
+const REAL_WORLD_VALUE_INTERCEPT: Tag = Tag(0x0040, 0x9224);
+const REAL_WORLD_VALUE_SLOPE: Tag = Tag(0x0040, 0x9225);
+const VALUE_TYPE: Tag = Tag(0x0040, 0xA040);
+const CONCEPT_NAME_CODE_SEQUENCE: Tag = Tag(0x0040, 0xA043);
+const CONCEPT_CODE_SEQUENCE: Tag = Tag(0x0040, 0xA168);
+const PURPOSE_OF_REFERENCE_CODE_SEQUENCE: Tag = Tag(0x0040, 0xA170);
+const CONTENT_LABEL: Tag = Tag(0x0070, 0x0080);
+const CONTENT_DESCRIPTION: Tag = Tag(0x0070, 0x0081);
+const CONTENT_CREATOR_NAME: Tag = Tag(0x0070, 0x0084);
+const SHARED_FUNCTIONAL_GROUPS: Tag = Tag(0x5200, 0x9229);
+const PER_FRAME_FUNCTIONAL_GROUPS: Tag = Tag(0x5200, 0x9230);
+const PIXEL_DATA: Tag = Tag(0x7FE0, 0x0010);
+
+/// Series Number of written maps, next to the imported annotations (9901).
+const PARAMETRIC_MAP_SERIES_NUMBER: &str = "9902";
+
+/// What the values of a map are and how they map to real-world units.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParametricMapOptions {
+    /// Short name of the quantity (LUT Label and Content Label), e.g. `ADC` or `SUVbw`.
+    pub label: String,
+    /// Free-text description, also used as the Series Description.
+    pub description: String,
+    /// Measurement units, normally a UCUM code such as `um2/s` or `g/ml`.
+    pub units: Code,
+    /// The measured quantity, when it has a code (Quantity Definition Sequence).
+    pub quantity: Option<Code>,
+    /// Real-world value = slope × stored value + intercept.
+    pub slope: f64,
+    pub intercept: f64,
+}
+
+impl Default for ParametricMapOptions {
+    fn default() -> Self {
+        Self {
+            label: "MAP".to_string(),
+            description: "Parametric map".to_string(),
+            units: ucum("1", "no units"),
+            quantity: None,
+            slope: 1.0,
+            intercept: 0.0,
+        }
+    }
+}
+
+/// A UCUM units code.
+pub fn ucum(value: &str, meaning: &str) -> Code {
+    Code {
+        value: value.to_string(),
+        scheme: Some("UCUM".to_string()),
+        meaning: Some(meaning.to_string()),
+    }
+}
+
+/// One frame of the reference series, whose geometry a map frame takes.
+#[derive(Debug, Clone)]
+struct ReferenceFrame {
+    obj: InMemDicomObject,
+    sop_class_uid: String,
+    sop_instance_uid: String,
+    /// 0-based frame of a multi-frame reference instance.
+    frame: u32,
+    frames: u32,
+}
+
+impl ReferenceFrame {
+    fn text(&self, tag: Tag) -> String {
+        text(&self.obj, tag)
+    }
+}
+
+fn text(obj: &InMemDicomObject, tag: Tag) -> String {
+    obj.element_str(tag)
+        .map(|s| s.trim_end_matches(['\0', ' ']).to_string())
+        .unwrap_or_default()
+}
+
+/// Frames of the reference series in viewer order (Instance Number, then frame).
+fn reference_frames(dir: &Path) -> Result<Vec<ReferenceFrame>> {
+    if !dir.is_dir() {
+        bail!("{:?} is not a directory", dir);
+    }
+    let mut instances = Vec::new();
+    for entry in WalkDir::new(dir).into_iter().filter_map(|e| e.ok()) {
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let Ok(file) = OpenFileOptions::new()
+            .read_until(PIXEL_DATA)
+            .open_file(entry.path())
+        else {
+            continue;
+        };
+        let obj: InMemDicomObject = file.into_inner();
+        let sop_instance_uid = text(&obj, SOP_INSTANCE_UID);
+        if sop_instance_uid.is_empty() || obj.element_u32(ROWS).is_none() {
+            continue;
+        }
+        instances.push((obj.element_u32(INSTANCE_NUMBER), sop_instance_uid, obj));
+    }
+    if instances.is_empty() {
+        bail!("No DICOM image found under {:?}", dir);
+    }
+    instances.sort_by(|a, b| (a.0, &a.1).cmp(&(b.0, &b.1)));
+    let series: Vec<String> = instances
+        .iter()
+        .map(|(_, _, obj)| text(obj, SERIES_INSTANCE_UID))
+        .fold(Vec::new(), |mut uids, uid| {
+            if !uids.contains(&uid) {
+                uids.push(uid);
+            }
+            uids
+        });
+    if series.len() > 1 {
+        bail!(
+            "{:?} holds {} series; the reference must be a single series",
+            dir,
+            series.len()
+        );
+    }
+    let mut frames = Vec::new();
+    for (_, sop_instance_uid, obj) in instances {
+        let count = obj.element_u32(NUMBER_OF_FRAMES).unwrap_or(1).max(1);
+        for frame in 0..count {
+            frames.push(ReferenceFrame {
+                sop_class_uid: text(&obj, SOP_CLASS_UID),
+                sop_instance_uid: sop_instance_uid.clone(),
+                frame,
+                frames: count,
+                obj: obj.clone(),
+            });
+        }
+    }
+    Ok(frames)
+}
+
+/// Read a raw little-endian `f32` file holding `frames` × `rows` × `columns` values, frame
+/// after frame in row-major order (what `numpy.ndarray.tofile` writes for `float32`).
+pub fn read_raw_f32(path: &Path, shape: (usize, usize, usize)) -> Result<Array3<f32>> {
+    let bytes = fs::read(path).with_context(|| format!("Failed to read {:?}", path))?;
+    let expected = shape.0 * shape.1 * shape.2;
+    if bytes.len() != expected * 4 {
+        bail!(
+            "{:?} holds {} bytes; {} frame(s) of {}x{} float32 values need {}",
+            path,
+            bytes.len(),
+            shape.0,
+            shape.2,
+            shape.1,
+            expected * 4
+        );
+    }
+    let values = bytes
+        .chunks_exact(4)
+        .map(|chunk| f32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
+        .collect();
+    Array3::from_shape_vec(shape, values).context("Invalid array shape")
+}
+
+/// Build a Parametric Map from `values` (frames, rows, columns), one frame per frame of the
+/// reference series under `reference`, in viewer order.
+pub fn build(
+    values: ArrayView3<f32>,
+    reference: &Path,
+    options: &ParametricMapOptions,
+) -> Result<DefaultDicomObject> {
+    let frames = reference_frames(reference)?;
+    build_from_frames(values, &frames, options)
+}
+
+fn build_from_frames(
+    values: ArrayView3<f32>,
+    frames: &[ReferenceFrame],
+    options: &ParametricMapOptions,
+) -> Result<DefaultDicomObject> {
+    let first = frames.first().context("No reference frame")?;
+    let (count, rows, columns) = values.dim();
+    if count != frames.len() {
+        bail!(
+            "The array has {} frame(s) but the reference series has {}",
+            count,
+            frames.len()
+        );
+    }
+    if let Some(other) = frames.iter().find(|f| {
+        f.obj.element_u32(ROWS) != Some(rows as u32)
+            || f.obj.element_u32(COLUMNS) != Some(columns as u32)
+    }) {
+        bail!(
+            "The array frames are {}x{} but {} is {}x{}",
+            columns,
+            rows,
+            other.sop_instance_uid,
+            other.obj.element_u32(COLUMNS).unwrap_or(0),
+            other.obj.element_u32(ROWS).unwrap_or(0)
+        );
+    }
+    if rows > u16::MAX as usize || columns > u16::MAX as usize {
+        bail!("Frames of {}x{} are too large", columns, rows);
+    }
+    let (low, high) = finite_range(values).context("The array holds no finite value")?;
+
+    let mut obj = InMemDicomObject::new_empty();
+    for (tag, vr) in [
+        (PATIENT_NAME, VR::PN),
+        (PATIENT_ID, VR::LO),
+        (PATIENT_BIRTH_DATE, VR::DA),
+        (PATIENT_SEX, VR::CS),
+        (STUDY_INSTANCE_UID, VR::UI),
+        (STUDY_DATE, VR::DA),
+        (STUDY_TIME, VR::TM),
+        (STUDY_ID, VR::SH),
+        (ACCESSION_NUMBER, VR::SH),
+        (REFERRING_PHYSICIAN_NAME, VR::PN),
+        (FRAME_OF_REFERENCE_UID, VR::UI),
+    ] {
+        put_str(&mut obj, tag, vr, &first.text(tag));
+    }
+    let seed = format!("{}|{}", options.label, first.text(SERIES_INSTANCE_UID));
+    let now = Local::now();
+    put_str(&mut obj, SOP_CLASS_UID, VR::UI, PARAMETRIC_MAP_STORAGE);
+    put_str(
+        &mut obj,
+        SOP_INSTANCE_UID,
+        VR::UI,
+        &new_instance_uid(&format!("parametric-map|{}", seed)),
+    );
+    put_str(
+        &mut obj,
+        SERIES_INSTANCE_UID,
+        VR::UI,
+        &new_instance_uid(&format!("parametric-map-series|{}", seed)),
+    );
+    // The map keeps the modality of the images it was computed from.
+    let modality = first.text(MODALITY);
+    put_str(
+        &mut obj,
+        MODALITY,
+        VR::CS,
+        if modality.is_empty() { "OT" } else { &modality },
+    );
+    put_str(
+        &mut obj,
+        SERIES_NUMBER,
+        VR::IS,
+        PARAMETRIC_MAP_SERIES_NUMBER,
+    );
+    put_str(&mut obj, SERIES_DESCRIPTION, VR::LO, &options.description);
+    put_str(&mut obj, INSTANCE_NUMBER, VR::IS, "1");
+    put_str(
+        &mut obj,
+        CONTENT_DATE,
+        VR::DA,
+        &now.format("%Y%m%d").to_string(),
+    );
+    put_str(
+        &mut obj,
+        CONTENT_TIME,
+        VR::TM,
+        &now.format("%H%M%S").to_string(),
+    );
+    obj.put(DataElement::new(
+        IMAGE_TYPE,
+        VR::CS,
+        PrimitiveValue::Strs(["DERIVED".to_string(), "PRIMARY".to_string()][..].into()),
+    ));
+    put_str(
+        &mut obj,
+        CONTENT_LABEL,
+        VR::CS,
+        &content_label(&options.label),
+    );
+    put_str(&mut obj, CONTENT_DESCRIPTION, VR::LO, &options.description);
+    put_str(&mut obj, CONTENT_CREATOR_NAME, VR::PN, "");
+    put_sequence(
+        &mut obj,
+        REFERENCED_SERIES_SEQUENCE,
+        vec![referenced_series(frames)],
+    );
+
+    put_us(&mut obj, SAMPLES_PER_PIXEL, 1);
+    put_str(&mut obj, PHOTOMETRIC_INTERPRETATION, VR::CS, "MONOCHROME2");
+    put_us(&mut obj, ROWS, rows as u16);
+    put_us(&mut obj, COLUMNS, columns as u16);
+    put_us(&mut obj, BITS_ALLOCATED, 32);
+    put_str(&mut obj, NUMBER_OF_FRAMES, VR::IS, &count.to_string());
+    put_str(&mut obj, PRESENTATION_LUT_SHAPE, VR::CS, "IDENTITY");
+    put_str(&mut obj, LOSSY_IMAGE_COMPRESSION, VR::CS, "00");
+    put_str(&mut obj, BURNED_IN_ANNOTATION, VR::CS, "NO");
+    put_str(&mut obj, RECOGNIZABLE_VISUAL_FEATURES, VR::CS, "NO");
+
+    // Frames are indexed by their position in patient space.
+    let dimension_organization = new_instance_uid(&format!("parametric-map-dimensions|{}", seed));
+    let mut organization = InMemDicomObject::new_empty();
+    put_str(
+        &mut organization,
+        DIMENSION_ORGANIZATION_UID,
+        VR::UI,
+        &dimension_organization,
+    );
+    put_sequence(
+        &mut obj,
+        DIMENSION_ORGANIZATION_SEQUENCE,
+        vec![organization],
+    );
+    let mut index = InMemDicomObject::new_empty();
+    put_str(
+        &mut index,
+        DIMENSION_ORGANIZATION_UID,
+        VR::UI,
+        &dimension_organization,
+    );
+    put_tag(&mut index, DIMENSION_INDEX_POINTER, IMAGE_POSITION);
+    put_tag(
+        &mut index,
+        FUNCTIONAL_GROUP_POINTER,
+        PLANE_POSITION_SEQUENCE,
+    );
+    put_sequence(&mut obj, DIMENSION_INDEX_SEQUENCE, vec![index]);
+
+    put_sequence(
+        &mut obj,
+        SHARED_FUNCTIONAL_GROUPS,
+        vec![shared_group(first, options, (low, high))],
+    );
+    let per_frame = frames
+        .iter()
+        .enumerate()
+        .map(|(index, frame)| frame_group(frame, index as u32 + 1))
+        .collect();
+    put_sequence(&mut obj, PER_FRAME_FUNCTIONAL_GROUPS, per_frame);
+
+    let pixels: Vec<f32> = values.iter().copied().collect();
+    obj.put(DataElement::new(
+        FLOAT_PIXEL_DATA,
+        VR::OF,
+        PrimitiveValue::F32(pixels.into()),
+    ));
+
+    let sop_instance = text(&obj, SOP_INSTANCE_UID);
+    obj.with_meta(
+        dicom::object::FileMetaTableBuilder::new()
+            .transfer_syntax(uids::EXPLICIT_VR_LITTLE_ENDIAN)
+            .media_storage_sop_class_uid(PARAMETRIC_MAP_STORAGE)
+            .media_storage_sop_instance_uid(&sop_instance),
+    )
+    .context("Failed to build file meta information")
+}
+
+/// Smallest and largest finite value; NaN marks voxels without a fit and is left out.
+fn finite_range(values: ArrayView3<f32>) -> Option<(f32, f32)> {
+    values
+        .iter()
+        .filter(|v| v.is_finite())
+        .fold(None, |range, &v| match range {
+            Some((low, high)) => Some((v.min(low), v.max(high))),
+            None => Some((v, v)),
+        })
+}
+
+/// Content Label is a CS: upper case, digits, space and underscore, 16 characters at most.
+fn content_label(label: &str) -> String {
+    let label: String = label
+        .chars()
+        .map(|c| match c.to_ascii_uppercase() {
+            c @ ('A'..='Z' | '0'..='9' | ' ' | '_') => c,
+            _ => '_',
+        })
+        .take(16)
+        .collect();
+    if label.trim().is_empty() {
+        "MAP".to_string()
+    } else {
+        label
+    }
+}
+
+/// Geometry and value mapping shared by every frame.
+fn shared_group(
+    first: &ReferenceFrame,
+    options: &ParametricMapOptions,
+    (low, high): (f32, f32),
+) -> InMemDicomObject {
+    let mut shared = InMemDicomObject::new_empty();
+    if let Ok(geometry) = FrameGeometry::for_frame(&first.obj, first.frame) {
+        let mut item = InMemDicomObject::new_empty();
+        put_str(
+            &mut item,
+            PIXEL_SPACING,
+            VR::DS,
+            &ds_values(&[geometry.row_spacing, geometry.column_spacing]),
+        );
+        if let Some(thickness) = first.obj.element_f64(SLICE_THICKNESS) {
+            put_str(&mut item, SLICE_THICKNESS, VR::DS, &ds_values(&[thickness]));
+        }
+        put_sequence(&mut shared, PIXEL_MEASURES_SEQUENCE, vec![item]);
+        if let Some(orientation) = geometry.orientation {
+            let mut item = InMemDicomObject::new_empty();
+            put_str(
+                &mut item,
+                IMAGE_ORIENTATION,
+                VR::DS,
+                &ds_values(&orientation),
+            );
+            put_sequence(&mut shared, PLANE_ORIENTATION_SEQUENCE, vec![item]);
+        }
+    }
+
+    // Stored values are already real-world values up to the mapping below.
+    let mut transformation = InMemDicomObject::new_empty();
+    put_str(&mut transformation, RESCALE_INTERCEPT, VR::DS, "0");
+    put_str(&mut transformation, RESCALE_SLOPE, VR::DS, "1");
+    put_str(&mut transformation, RESCALE_TYPE, VR::LO, "US");
+    put_sequence(
+        &mut shared,
+        PIXEL_VALUE_TRANSFORMATION_SEQUENCE,
+        vec![transformation],
+    );
+
+    let mut mapping = InMemDicomObject::new_empty();
+    put_str(&mut mapping, LUT_LABEL, VR::SH, &options.label);
+    put_str(&mut mapping, LUT_EXPLANATION, VR::LO, &options.description);
+    put_fd(&mut mapping, DOUBLE_FLOAT_FIRST_VALUE_MAPPED, low as f64);
+    put_fd(&mut mapping, DOUBLE_FLOAT_LAST_VALUE_MAPPED, high as f64);
+    put_fd(&mut mapping, REAL_WORLD_VALUE_INTERCEPT, options.intercept);
+    put_fd(&mut mapping, REAL_WORLD_VALUE_SLOPE, options.slope);
+    put_sequence(
+        &mut mapping,
+        MEASUREMENT_UNITS_CODE_SEQUENCE,
+        vec![code_item(&options.units)],
+    );
+    if let Some(quantity) = &options.quantity {
+        let mut item = InMemDicomObject::new_empty();
+        put_str(&mut item, VALUE_TYPE, VR::CS, "CODE");
+        put_sequence(
+            &mut item,
+            CONCEPT_NAME_CODE_SEQUENCE,
+            vec![code_item(&Code {
+                value: "246205007".to_string(),
+                scheme: Some("SCT".to_string()),
+                meaning: Some("Quantity".to_string()),
+            })],
+        );
+        put_sequence(&mut item, CONCEPT_CODE_SEQUENCE, vec![code_item(quantity)]);
+        put_sequence(&mut mapping, QUANTITY_DEFINITION_SEQUENCE, vec![item]);
+    }
+    put_sequence(
+        &mut shared,
+        REAL_WORLD_VALUE_MAPPING_SEQUENCE,
+        vec![mapping],
+    );
+    shared
+}
+
+/// Position, dimension index and source image of the `number`th (1-based) map frame.
+fn frame_group(frame: &ReferenceFrame, number: u32) -> InMemDicomObject {
+    let mut group = InMemDicomObject::new_empty();
+    let mut content = InMemDicomObject::new_empty();
+    content.put(DataElement::new(
+        DIMENSION_INDEX_VALUES,
+        VR::UL,
+        PrimitiveValue::from(number),
+    ));
+    put_sequence(&mut group, FRAME_CONTENT_SEQUENCE, vec![content]);
+    if let Some(position) = frame_multi_f64(
+        &frame.obj,
+        frame.frame,
+        PLANE_POSITION_SEQUENCE,
+        IMAGE_POSITION,
+    ) {
+        let mut item = InMemDicomObject::new_empty();
+        put_str(&mut item, IMAGE_POSITION, VR::DS, &ds_values(&position));
+        put_sequence(&mut group, PLANE_POSITION_SEQUENCE, vec![item]);
+    }
+    let mut source = sop_reference(frame);
+    put_sequence(
+        &mut source,
+        PURPOSE_OF_REFERENCE_CODE_SEQUENCE,
+        vec![code_item(&Code {
+            value: "121322".to_string(),
+            scheme: Some("DCM".to_string()),
+            meaning: Some("Source image for image processing operation".to_string()),
+        })],
+    );
+    put_sequence(&mut group, REFERENCED_IMAGE_SEQUENCE, vec![source]);
+    group
+}
+
+/// Referenced Series Sequence item listing every reference instance once.
+fn referenced_series(frames: &[ReferenceFrame]) -> InMemDicomObject {
+    let mut item = InMemDicomObject::new_empty();
+    put_str(
+        &mut item,
+        SERIES_INSTANCE_UID,
+        VR::UI,
+        &frames[0].text(SERIES_INSTANCE_UID),
+    );
+    let references = frames
+        .iter()
+        .filter(|frame| frame.frame == 0)
+        .map(|frame| {
+            let mut reference = InMemDicomObject::new_empty();
+            put_str(
+                &mut reference,
+                REFERENCED_SOP_CLASS_UID,
+                VR::UI,
+                &frame.sop_class_uid,
+            );
+            put_str(
+                &mut reference,
+                REFERENCED_SOP_INSTANCE_UID,
+                VR::UI,
+                &frame.sop_instance_uid,
+            );
+            reference
+        })
+        .collect();
+    put_sequence(&mut item, REFERENCED_INSTANCE_SEQUENCE, references);
+    item
+}
+
+fn sop_reference(frame: &ReferenceFrame) -> InMemDicomObject {
+    let mut item = InMemDicomObject::new_empty();
+    put_str(
+        &mut item,
+        REFERENCED_SOP_CLASS_UID,
+        VR::UI,
+        &frame.sop_class_uid,
+    );
+    put_str(
+        &mut item,
+        REFERENCED_SOP_INSTANCE_UID,
+        VR::UI,
+        &frame.sop_instance_uid,
+    );
+    if frame.frames > 1 {
+        put_str(
+            &mut item,
+            REFERENCED_FRAME_NUMBER,
+            VR::IS,
+            &(frame.frame + 1).to_string(),
+        );
+    }
+    item
+}
+
+fn put_str(obj: &mut InMemDicomObject, tag: Tag, vr: VR, value: &str) {
+    obj.put(DataElement::new(tag, vr, PrimitiveValue::from(value)));
+}
+
+fn put_us(obj: &mut InMemDicomObject, tag: Tag, value: u16) {
+    obj.put(DataElement::new(tag, VR::US, PrimitiveValue::from(value)));
+}
+
+fn put_fd(obj: &mut InMemDicomObject, tag: Tag, value: f64) {
+    obj.put(DataElement::new(tag, VR::FD, PrimitiveValue::from(value)));
+}
+
+fn put_tag(obj: &mut InMemDicomObject, tag: Tag, value: Tag) {
+    obj.put(DataElement::new(
+        tag,
+        VR::AT,
+        PrimitiveValue::Tags([value][..].into()),
+    ));
+}
+
+fn put_sequence(obj: &mut InMemDicomObject, tag: Tag, items: Vec<InMemDicomObject>) {
+    obj.put(DataElement::new(tag, VR::SQ, DataSetSequence::from(items)));
+}
+
+fn code_item(code: &Code) -> InMemDicomObject {
+    let mut item = InMemDicomObject::new_empty();
+    put_str(&mut item, CODE_VALUE, VR::SH, &code.value);
+    put_str(
+        &mut item,
+        CODING_SCHEME_DESIGNATOR,
+        VR::SH,
+        code.scheme.as_deref().unwrap_or_default(),
+    );
+    put_str(
+        &mut item,
+        CODE_MEANING,
+        VR::LO,
+        code.meaning.as_deref().unwrap_or(&code.value),
+    );
+    item
+}
+
+fn ds_values(values: &[f64]) -> String {
+    values
+        .iter()
+        .map(|v| format!("{}", v))
+        .collect::<Vec<_>>()
+        .join("\\")
+}
+
+/// Summary of a written map.
+#[derive(Debug, Clone)]
+pub struct ParametricMapSummary {
+    pub frames: usize,
+    pub rows: usize,
+    pub columns: usize,
+    /// Smallest and largest finite stored value.
+    pub range: (f64, f64),
+    pub sop_instance_uid: String,
+    pub output: PathBuf,
+}
+
+/// Build a map from `values` against `reference` and write it to `output`.
+pub fn write(
+    values: ArrayView3<f32>,
+    reference: &Path,
+    options: &ParametricMapOptions,
+    output: &Path,
+) -> Result<ParametricMapSummary> {
+    write_frames(values, &reference_frames(reference)?, options, output)
+}
+
+fn write_frames(
+    values: ArrayView3<f32>,
+    frames: &[ReferenceFrame],
+    options: &ParametricMapOptions,
+    output: &Path,
+) -> Result<ParametricMapSummary> {
+    let obj = build_from_frames(values, frames, options)?;
+    atomic_file::write_dicom(output, &obj)
+        .with_context(|| format!("Failed to write {:?}", output))?;
+    let (count, rows, columns) = values.dim();
+    let (low, high) = finite_range(values).unwrap_or_default();
+    Ok(ParametricMapSummary {
+        frames: count,
+        rows,
+        columns,
+        range: (low as f64, high as f64),
+        sop_instance_uid: text(&obj, SOP_INSTANCE_UID),
+        output: output.to_path_buf(),
+    })
+}
+
+/// CLI entry point: `values` is a raw float32 file shaped like the reference series.
+pub fn print_write(
+    values: &Path,
+    reference: &Path,
+    options: &ParametricMapOptions,
+    output: &Path,
+) -> Result<()> {
+    let frames = reference_frames(reference)?;
+    let shape = (
+        frames.len(),
+        frames[0].obj.element_u32(ROWS).unwrap_or(0) as usize,
+        frames[0].obj.element_u32(COLUMNS).unwrap_or(0) as usize,
+    );
+    let array = read_raw_f32(values, shape)?;
+    let summary = write_frames(array.view(), &frames, options, output)?;
+    println!(
+        "{} frame(s) of {}x{} {} values in [{}, {}] {} written to {:?} ({})",
+        summary.frames,
+        summary.columns,
+        summary.rows,
+        options.label,
+        summary.range.0,
+        summary.range.1,
+        options.units.value,
+        summary.output,
+        summary.sop_instance_uid
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ndarray::Array3;
+
+    #[test]
+    fn content_labels_are_coerced_to_code_strings() {
+        assert_eq!(content_label("SUVbw"), "SUVBW");
+        assert_eq!(content_label("adc-map (fit)"), "ADC_MAP _FIT_");
+        assert_eq!(
+            content_label("a very long quantity name"),
+            "A VERY LONG QUAN"
+        );
+        assert_eq!(content_label("  "), "MAP");
+    }
+
+    #[test]
+    fn unfitted_voxels_stay_out_of_the_mapped_range() {
+        let mut values = Array3::<f32>::zeros((1, 2, 2));
+        values[[0, 0, 0]] = f32::NAN;
+        values[[0, 0, 1]] = -1.5;
+        values[[0, 1, 1]] = f32::INFINITY;
+        assert_eq!(finite_range(values.view()), Some((-1.5, 0.0)));
+        values.fill(f32::NAN);
+        assert_eq!(finite_range(values.view()), None);
+    }
+}
@@ -0,0 +1,143 @@
+//
+// person_name.rs
+// Dicom-Tools-rs
+//
+// Splits PN values into their component groups (alphabetic, ideographic, phonetic) and, within
+// each group, family, given, middle, prefix and suffix names.
+//
+// Thales Matheus Mendonça Santos - November 2025
+
+use serde::{Deserialize, Serialize};
+
+/// The five `^`-separated components of one PN component group; empty ones are `None`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NameComponents {
+    pub family: Option<String>,
+    pub given: Option<String>,
+    pub middle: Option<String>,
+    pub prefix: Option<String>,
+    pub suffix: Option<String>,
+}
+
+impl NameComponents {
+    fn parse(group: &str) -> Option<Self> {
+        let mut parts = group.split('^').map(|part| {
+            let part = part.trim();
+            (!part.is_empty()).then(|| part.to_string())
+        });
+        let mut next = || parts.next().flatten();
+        let components = NameComponents {
+            family: next(),
+            given: next(),
+            middle: next(),
+            prefix: next(),
+            suffix: next(),
+        };
+        (components != NameComponents::default()).then_some(components)
+    }
+
+    /// Present components, family name first.
+    pub fn iter(&self) -> impl Iterator<Item = &str> {
+        [
+            &self.family,
+            &self.given,
+            &self.middle,
+            &self.prefix,
+            &self.suffix,
+        ]
+        .into_iter()
+        .filter_map(|part| part.as_deref())
+    }
+
+    /// "Prefix Given Middle Family Suffix", the usual reading order of a western name.
+    pub fn display(&self) -> String {
+        [
+            &self.prefix,
+            &self.given,
+            &self.middle,
+            &self.family,
+            &self.suffix,
+        ]
+        .into_iter()
+        .filter_map(|part| part.as_deref())
+        .collect::<Vec<_>>()
+        .join(" ")
+    }
+}
+
+/// One PN value. Groups are `=`-separated in the order alphabetic, ideographic, phonetic.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PersonName {
+    pub alphabetic: Option<NameComponents>,
+    pub ideographic: Option<NameComponents>,
+    pub phonetic: Option<NameComponents>,
+}
+
+impl PersonName {
+    /// Parse the first value of a possibly multi-valued PN; `None` when it holds no name.
+    pub fn parse(text: &str) -> Option<Self> {
+        Self::parse_all(text).into_iter().next()
+    }
+
+    /// Every non-empty value of a multi-valued (`\`-separated) PN.
+    pub fn parse_all(text: &str) -> Vec<Self> {
+        text.split('\\')
+            .filter_map(|value| {
+                let value = value.trim_matches(|c: char| c == '\0' || c.is_whitespace());
+                let mut groups = value.split('=').map(NameComponents::parse);
+                let name = PersonName {
+                    alphabetic: groups.next().flatten(),
+                    ideographic: groups.next().flatten(),
+                    phonetic: groups.next().flatten(),
+                };
+                (name != PersonName::default()).then_some(name)
+            })
+            .collect()
+    }
+
+    /// Components of every group.
+    pub fn components(&self) -> impl Iterator<Item = &str> {
+        [&self.alphabetic, &self.ideographic, &self.phonetic]
+            .into_iter()
+            .flatten()
+            .flat_map(NameComponents::iter)
+    }
+
+    /// Human-readable form, from the first group present.
+    pub fn display(&self) -> String {
+        [&self.alphabetic, &self.ideographic, &self.phonetic]
+            .into_iter()
+            .flatten()
+            .next()
+            .map(NameComponents::display)
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn components_and_groups_are_split() {
+        let name = PersonName::parse("Adams^John Robert Quincy^^Rev.^B.A. M.Div.").unwrap();
+        let alphabetic = name.alphabetic.as_ref().unwrap();
+        assert_eq!(alphabetic.family.as_deref(), Some("Adams"));
+        assert_eq!(alphabetic.given.as_deref(), Some("John Robert Quincy"));
+        assert_eq!(alphabetic.middle, None);
+        assert_eq!(alphabetic.prefix.as_deref(), Some("Rev."));
+        assert_eq!(alphabetic.suffix.as_deref(), Some("B.A. M.Div."));
+        assert_eq!(name.display(), "Rev. John Robert Quincy Adams B.A. M.Div.");
+
+        let name = PersonName::parse("Yamada^Tarou=山田^太郎=やまだ^たろう").unwrap();
+        assert_eq!(name.ideographic.unwrap().family.as_deref(), Some("山田"));
+        assert_eq!(name.phonetic.unwrap().given.as_deref(), Some("たろう"));
+
+        let name = PersonName::parse("=山田^太郎").unwrap();
+        assert!(name.alphabetic.is_none());
+        assert_eq!(name.display(), "太郎 山田");
+
+        assert!(PersonName::parse("  ").is_none());
+        assert_eq!(PersonName::parse_all("Doe^John\\Roe^Jane").len(), 2);
+    }
+}
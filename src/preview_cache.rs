@@ -0,0 +1,250 @@
+//
+// preview_cache.rs
+// Dicom-Tools-rs
+//
+// Caches rendered preview images by content hash and render parameters, in memory with LRU
+// eviction and optionally on disk, so repeated views skip decoding.
+//
+// Thales Matheus Mendonça Santos - November 2025
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{anyhow, Context, Result};
+use dicom_pixeldata::WindowLevel;
+use sha2::{Digest, Sha256};
+
+use crate::atomic_file;
+use crate::image::PreviewFormat;
+
+/// Everything a preview depends on: equal keys always render identical images.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct PreviewKey {
+    /// SHA-256 of the DICOM file content.
+    pub content_hash: String,
+    pub frame: u32,
+    /// Window center and width as raw `f64` bits.
+    window: Option<(u64, u64)>,
+    /// Longest edge the preview was scaled to fit.
+    pub max_edge: Option<u32>,
+    pub format: PreviewFormat,
+}
+
+impl PreviewKey {
+    pub fn new(
+        content_hash: impl Into<String>,
+        frame: u32,
+        window: Option<WindowLevel>,
+        max_edge: Option<u32>,
+        format: PreviewFormat,
+    ) -> Self {
+        Self {
+            content_hash: content_hash.into(),
+            frame,
+            window: window.map(|w| (w.center.to_bits(), w.width.to_bits())),
+            max_edge,
+            format,
+        }
+    }
+
+    /// File name of the entry in the disk tier.
+    fn file_name(&self) -> String {
+        let digest = Sha256::digest(format!("{:?}", self).as_bytes());
+        format!("{}.{}", hex::encode(digest), self.format.extension())
+    }
+}
+
+/// Whether a lookup was served from the cache.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheOutcome {
+    Memory,
+    Disk,
+    Rendered,
+}
+
+impl CacheOutcome {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            CacheOutcome::Memory => "hit-memory",
+            CacheOutcome::Disk => "hit-disk",
+            CacheOutcome::Rendered => "miss",
+        }
+    }
+}
+
+#[derive(Default)]
+struct MemoryTier {
+    entries: HashMap<PreviewKey, (Arc<Vec<u8>>, u64)>,
+    used_bytes: usize,
+    /// Logical clock stamped on entries when they are read or written.
+    clock: u64,
+}
+
+impl MemoryTier {
+    fn get(&mut self, key: &PreviewKey) -> Option<Arc<Vec<u8>>> {
+        self.clock += 1;
+        let clock = self.clock;
+        self.entries.get_mut(key).map(|(bytes, last_access)| {
+            *last_access = clock;
+            bytes.clone()
+        })
+    }
+
+    /// Insert, evicting least recently used entries to stay within `capacity` bytes.
+    /// Entries larger than the whole capacity are not kept.
+    fn insert(&mut self, key: PreviewKey, bytes: Arc<Vec<u8>>, capacity: usize) {
+        if bytes.len() > capacity {
+            return;
+        }
+        self.clock += 1;
+        if let Some((old, _)) = self.entries.remove(&key) {
+            self.used_bytes -= old.len();
+        }
+        while self.used_bytes + bytes.len() > capacity {
+            let Some(oldest) = self
+                .entries
+                .iter()
+                .min_by_key(|(_, (_, last_access))| *last_access)
+                .map(|(key, _)| key.clone())
+            else {
+                break;
+            };
+            if let Some((evicted, _)) = self.entries.remove(&oldest) {
+                self.used_bytes -= evicted.len();
+            }
+        }
+        self.used_bytes += bytes.len();
+        self.entries.insert(key, (bytes, self.clock));
+    }
+}
+
+/// Two-tier cache of rendered previews: an in-memory LRU bounded in bytes, backed by an
+/// optional directory that survives restarts.
+pub struct PreviewCache {
+    memory: Mutex<MemoryTier>,
+    capacity_bytes: usize,
+    disk: Option<PathBuf>,
+}
+
+impl PreviewCache {
+    /// Memory-only cache holding up to `capacity_bytes` of encoded images (0 disables it).
+    pub fn new(capacity_bytes: usize) -> Self {
+        Self {
+            memory: Mutex::new(MemoryTier::default()),
+            capacity_bytes,
+            disk: None,
+        }
+    }
+
+    /// Also keep every rendered preview as a file in `dir`.
+    pub fn with_disk(mut self, dir: impl Into<PathBuf>) -> Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)
+            .with_context(|| format!("Failed to create preview cache {:?}", dir))?;
+        self.disk = Some(dir);
+        Ok(self)
+    }
+
+    pub fn describe(&self) -> String {
+        let memory = format!("{} MiB in memory", self.capacity_bytes / (1024 * 1024));
+        match &self.disk {
+            Some(dir) => format!("{}, on disk at {}", memory, dir.display()),
+            None => memory,
+        }
+    }
+
+    /// Cached bytes for `key`, else the result of `render`, which is then cached.
+    pub fn get_or_render(
+        &self,
+        key: &PreviewKey,
+        render: impl FnOnce() -> Result<Vec<u8>>,
+    ) -> Result<(Arc<Vec<u8>>, CacheOutcome)> {
+        if let Some(bytes) = self.lock()?.get(key) {
+            return Ok((bytes, CacheOutcome::Memory));
+        }
+        let disk_path = self.disk.as_ref().map(|dir| dir.join(key.file_name()));
+        if let Some(bytes) = disk_path.as_ref().and_then(|path| fs::read(path).ok()) {
+            let bytes = Arc::new(bytes);
+            self.lock()?
+                .insert(key.clone(), bytes.clone(), self.capacity_bytes);
+            return Ok((bytes, CacheOutcome::Disk));
+        }
+
+        let bytes = Arc::new(render()?);
+        if let Some(path) = disk_path {
+            // Written atomically so concurrent readers never see a partial file.
+            atomic_file::write(&path, bytes.as_slice())
+                .with_context(|| format!("Failed to write preview cache entry {:?}", path))?;
+        }
+        self.lock()?
+            .insert(key.clone(), bytes.clone(), self.capacity_bytes);
+        Ok((bytes, CacheOutcome::Rendered))
+    }
+
+    fn lock(&self) -> Result<std::sync::MutexGuard<'_, MemoryTier>> {
+        self.memory
+            .lock()
+            .map_err(|_| anyhow!("Preview cache lock poisoned"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(frame: u32) -> PreviewKey {
+        PreviewKey::new("abc", frame, None, Some(256), PreviewFormat::Png)
+    }
+
+    #[test]
+    fn memory_tier_evicts_least_recently_used() {
+        let cache = PreviewCache::new(10);
+        let render = |n: usize| move || Ok(vec![0u8; n]);
+        assert_eq!(
+            cache.get_or_render(&key(0), render(4)).unwrap().1,
+            CacheOutcome::Rendered
+        );
+        cache.get_or_render(&key(1), render(4)).unwrap();
+        // Touch frame 0 so frame 1 becomes the oldest, then overflow.
+        assert_eq!(
+            cache.get_or_render(&key(0), render(4)).unwrap().1,
+            CacheOutcome::Memory
+        );
+        cache.get_or_render(&key(2), render(4)).unwrap();
+
+        assert_eq!(
+            cache.get_or_render(&key(0), render(4)).unwrap().1,
+            CacheOutcome::Memory
+        );
+        assert_eq!(
+            cache.get_or_render(&key(1), render(4)).unwrap().1,
+            CacheOutcome::Rendered
+        );
+    }
+
+    #[test]
+    fn disk_tier_survives_a_new_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let window = Some(WindowLevel {
+            center: 40.0,
+            width: 400.0,
+        });
+        let key = PreviewKey::new("abc", 0, window, None, PreviewFormat::Png);
+        let first = PreviewCache::new(1024).with_disk(dir.path()).unwrap();
+        first.get_or_render(&key, || Ok(vec![1, 2, 3])).unwrap();
+
+        let second = PreviewCache::new(1024).with_disk(dir.path()).unwrap();
+        let (bytes, outcome) = second
+            .get_or_render(&key, || panic!("should not render"))
+            .unwrap();
+        assert_eq!(outcome, CacheOutcome::Disk);
+        assert_eq!(bytes.as_slice(), [1, 2, 3]);
+
+        let other_window = PreviewKey::new("abc", 0, None, None, PreviewFormat::Png);
+        assert_ne!(key.file_name(), other_window.file_name());
+        let other_format = PreviewKey::new("abc", 0, window, None, PreviewFormat::Webp);
+        assert!(other_format.file_name().ends_with(".webp"));
+    }
+}
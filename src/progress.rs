@@ -0,0 +1,140 @@
+//
+// progress.rs
+// Dicom-Tools-rs
+//
+// Progress reporting hooks for long-running library operations, with a terminal progress bar sink for the CLI.
+//
+// Thales Matheus Mendonça Santos - November 2025
+
+use std::io::IsTerminal;
+
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
+use serde::Serialize;
+
+/// A single progress update: which phase is running, what item is being handled, and how far along we are.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ProgressEvent {
+    pub phase: String,
+    pub item: Option<String>,
+    pub current: u64,
+    pub total: u64,
+    pub percent: f32,
+}
+
+impl ProgressEvent {
+    pub fn new(phase: impl Into<String>, current: u64, total: u64) -> Self {
+        let percent = if total == 0 {
+            100.0
+        } else {
+            (current.min(total) as f32 / total as f32) * 100.0
+        };
+        Self {
+            phase: phase.into(),
+            item: None,
+            current,
+            total,
+            percent,
+        }
+    }
+
+    pub fn with_item(mut self, item: impl Into<String>) -> Self {
+        self.item = Some(item.into());
+        self
+    }
+}
+
+/// Receiver for progress updates. Implementations must be cheap; they are called from hot paths and worker threads.
+pub trait ProgressSink: Send + Sync {
+    fn report(&self, event: ProgressEvent);
+}
+
+/// Sink that discards every event; used by the convenience wrappers without progress.
+pub struct NoProgress;
+
+impl ProgressSink for NoProgress {
+    fn report(&self, _event: ProgressEvent) {}
+}
+
+impl<F> ProgressSink for F
+where
+    F: Fn(ProgressEvent) + Send + Sync,
+{
+    fn report(&self, event: ProgressEvent) {
+        self(event)
+    }
+}
+
+/// Renders progress events as an `indicatif` bar on stderr. Nothing is drawn when stderr is
+/// not a terminal, so redirected output stays clean.
+pub struct ProgressBarSink {
+    bar: ProgressBar,
+}
+
+impl ProgressBarSink {
+    pub fn new() -> Self {
+        Self::with_template("{bar:40.cyan/blue} {pos}/{len} {percent:>3}% {msg}")
+    }
+
+    /// For operations reporting bytes rather than items: shows sizes, throughput and the
+    /// time left.
+    pub fn bytes() -> Self {
+        Self::with_template(
+            "{bar:40.cyan/blue} {bytes}/{total_bytes} {binary_bytes_per_sec} eta {eta} {msg}",
+        )
+    }
+
+    fn with_template(template: &str) -> Self {
+        let bar = ProgressBar::new(0);
+        if !std::io::stderr().is_terminal() {
+            bar.set_draw_target(ProgressDrawTarget::hidden());
+        }
+        let style =
+            ProgressStyle::with_template(template).unwrap_or_else(|_| ProgressStyle::default_bar());
+        bar.set_style(style);
+        Self { bar }
+    }
+
+    pub fn finish(&self) {
+        self.bar.finish_and_clear();
+    }
+}
+
+impl Default for ProgressBarSink {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ProgressSink for ProgressBarSink {
+    fn report(&self, event: ProgressEvent) {
+        self.bar.set_length(event.total);
+        self.bar.set_position(event.current);
+        let message = match event.item {
+            Some(item) => format!("{} {}", event.phase, item),
+            None => event.phase,
+        };
+        self.bar.set_message(message);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[test]
+    fn percent_is_clamped_and_handles_empty_totals() {
+        assert_eq!(ProgressEvent::new("scan", 0, 0).percent, 100.0);
+        assert_eq!(ProgressEvent::new("process", 1, 4).percent, 25.0);
+        assert_eq!(ProgressEvent::new("process", 9, 4).percent, 100.0);
+    }
+
+    #[test]
+    fn closures_act_as_sinks() {
+        let seen = Mutex::new(Vec::new());
+        let sink = |event: ProgressEvent| seen.lock().unwrap().push(event.phase);
+        sink.report(ProgressEvent::new("decode", 0, 2).with_item("a.dcm"));
+        sink.report(ProgressEvent::new("write", 1, 2));
+        assert_eq!(*seen.lock().unwrap(), vec!["decode", "write"]);
+    }
+}
@@ -0,0 +1,314 @@
+//
+// registration.rs
+// Dicom-Tools-rs
+//
+// Compares the spatial frame of two series (Frame of Reference, orientation, slice positions) before fusion or contour reuse.
+//
+// Thales Matheus Mendonça Santos - November 2025
+
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use dicom::core::Tag;
+use dicom::object::{open_file, InMemDicomObject};
+use serde::Serialize;
+use walkdir::WalkDir;
+
+use crate::dicom_access::ElementAccess;
+use crate::measure::{
+    frame_multi_f64, IMAGE_ORIENTATION, IMAGE_POSITION, PLANE_ORIENTATION_SEQUENCE,
+    PLANE_POSITION_SEQUENCE,
+};
+
+const SERIES_INSTANCE_UID: Tag = Tag(0x0020, 0x000E);
+const FRAME_OF_REFERENCE_UID: Tag = Tag(0x0020, 0x0052);
+const MODALITY: Tag = Tag(0x0008, 0x0060);
+const NUMBER_OF_FRAMES: Tag = Tag(0x0028, 0x0008);
+
+/// Spatial description of one series: its reference frame, plane orientation and slice origins.
+#[derive(Debug, Clone, Serialize)]
+pub struct SeriesGeometry {
+    pub series_instance_uid: Option<String>,
+    pub modality: Option<String>,
+    pub frame_of_reference_uid: Option<String>,
+    pub orientation: [f64; 6],
+    /// Slice origins sorted along the plane normal.
+    pub positions: Vec<[f64; 3]>,
+}
+
+impl SeriesGeometry {
+    /// Collect geometry from every DICOM file under `dir` (single-frame or enhanced multi-frame).
+    pub fn from_directory(dir: &Path) -> Result<Self> {
+        let mut series = None;
+        let mut positions = Vec::new();
+
+        for entry in WalkDir::new(dir).into_iter().filter_map(|e| e.ok()) {
+            if !entry.file_type().is_file() {
+                continue;
+            }
+            // Non-DICOM files (DICOMDIR siblings, notes) are skipped rather than failing the check.
+            let Ok(obj) = open_file(entry.path()) else {
+                continue;
+            };
+            let frames = obj.element_u32(NUMBER_OF_FRAMES).unwrap_or(1).max(1);
+            for frame in 0..frames {
+                if let Some(p) =
+                    frame_vec::<3>(&obj, frame, PLANE_POSITION_SEQUENCE, IMAGE_POSITION)
+                {
+                    positions.push(p);
+                }
+            }
+            if series.is_none() {
+                if let Some(orientation) =
+                    frame_vec::<6>(&obj, 0, PLANE_ORIENTATION_SEQUENCE, IMAGE_ORIENTATION)
+                {
+                    series = Some(SeriesGeometry {
+                        series_instance_uid: obj.element_str(SERIES_INSTANCE_UID),
+                        modality: obj.element_str(MODALITY),
+                        frame_of_reference_uid: obj.element_str(FRAME_OF_REFERENCE_UID),
+                        orientation,
+                        positions: Vec::new(),
+                    });
+                }
+            }
+        }
+
+        let mut series = series
+            .with_context(|| format!("No DICOM instance with plane geometry found in {:?}", dir))?;
+        if positions.is_empty() {
+            bail!("No Image Position (Patient) values found in {:?}", dir);
+        }
+        let normal = series.normal();
+        positions.sort_by(|a, b| dot(normal, *a).total_cmp(&dot(normal, *b)));
+        series.positions = positions;
+        Ok(series)
+    }
+
+    pub fn row_direction(&self) -> [f64; 3] {
+        [
+            self.orientation[0],
+            self.orientation[1],
+            self.orientation[2],
+        ]
+    }
+
+    pub fn column_direction(&self) -> [f64; 3] {
+        [
+            self.orientation[3],
+            self.orientation[4],
+            self.orientation[5],
+        ]
+    }
+
+    pub fn normal(&self) -> [f64; 3] {
+        normalize(cross(self.row_direction(), self.column_direction()))
+    }
+
+    /// Mean slice origin, used as the series reference point.
+    pub fn centroid(&self) -> [f64; 3] {
+        let n = self.positions.len().max(1) as f64;
+        let sum = self.positions.iter().fold([0.0; 3], |acc, p| add(acc, *p));
+        [sum[0] / n, sum[1] / n, sum[2] / n]
+    }
+
+    /// Extent of the slice stack along `normal`.
+    fn extent_along(&self, normal: [f64; 3]) -> (f64, f64) {
+        self.positions
+            .iter()
+            .map(|p| dot(normal, *p))
+            .fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), d| {
+                (lo.min(d), hi.max(d))
+            })
+    }
+}
+
+/// Rigid difference between two series: translation of the centre and rotation between planes.
+#[derive(Debug, Clone, Serialize)]
+pub struct RigidOffset {
+    pub translation_mm: [f64; 3],
+    pub rotation_deg: f64,
+}
+
+/// Outcome of comparing two series.
+#[derive(Debug, Clone, Serialize)]
+pub struct RegistrationReport {
+    pub same_frame_of_reference: bool,
+    /// Angle between the two slice normals.
+    pub plane_angle_deg: f64,
+    /// Angle between the row directions (in-plane rotation).
+    pub in_plane_rotation_deg: f64,
+    /// Overlap of both stacks along the first series' normal, in millimetres.
+    pub overlap_mm: f64,
+    /// True when both series share a Frame of Reference and parallel planes.
+    pub aligned: bool,
+    /// Present when the series are not aligned.
+    pub rigid_offset: Option<RigidOffset>,
+}
+
+pub fn compare(
+    a: &SeriesGeometry,
+    b: &SeriesGeometry,
+    angle_tolerance_deg: f64,
+) -> RegistrationReport {
+    let same_frame_of_reference = match (&a.frame_of_reference_uid, &b.frame_of_reference_uid) {
+        (Some(fa), Some(fb)) => fa.trim() == fb.trim(),
+        _ => false,
+    };
+
+    let normal = a.normal();
+    let plane_angle_deg = angle_deg(normal, b.normal());
+    // Parallel planes may be stacked in opposite directions; treat 180° as parallel.
+    let plane_angle_deg = plane_angle_deg.min(180.0 - plane_angle_deg);
+    let in_plane_rotation_deg = angle_deg(a.row_direction(), b.row_direction());
+
+    let (a_lo, a_hi) = a.extent_along(normal);
+    let (b_lo, b_hi) = b.extent_along(normal);
+    let overlap_mm = (a_hi.min(b_hi) - a_lo.max(b_lo)).max(0.0);
+
+    let aligned = same_frame_of_reference
+        && plane_angle_deg <= angle_tolerance_deg
+        && in_plane_rotation_deg <= angle_tolerance_deg;
+    let rigid_offset = (!aligned).then(|| RigidOffset {
+        translation_mm: sub(b.centroid(), a.centroid()),
+        rotation_deg: plane_angle_deg.max(in_plane_rotation_deg),
+    });
+
+    RegistrationReport {
+        same_frame_of_reference,
+        plane_angle_deg,
+        in_plane_rotation_deg,
+        overlap_mm,
+        aligned,
+        rigid_offset,
+    }
+}
+
+/// CLI entry point: load both series, compare them and print the verdict.
+pub fn check_directories(a: &Path, b: &Path, angle_tolerance_deg: f64) -> Result<()> {
+    let series_a = SeriesGeometry::from_directory(a)?;
+    let series_b = SeriesGeometry::from_directory(b)?;
+    let report = compare(&series_a, &series_b, angle_tolerance_deg);
+
+    for (label, series, dir) in [("A", &series_a, a), ("B", &series_b, b)] {
+        println!(
+            "Series {}: {:?} | {} | FoR {} | {} slice(s)",
+            label,
+            dir,
+            series.modality.as_deref().unwrap_or("?"),
+            series.frame_of_reference_uid.as_deref().unwrap_or("N/A"),
+            series.positions.len()
+        );
+    }
+    println!(
+        "[{}] Frame of Reference UID",
+        if report.same_frame_of_reference {
+            "OK"
+        } else {
+            "DIFF"
+        }
+    );
+    println!("  Plane angle: {:.3} deg", report.plane_angle_deg);
+    println!(
+        "  In-plane rotation: {:.3} deg",
+        report.in_plane_rotation_deg
+    );
+    println!("  Overlap along normal: {:.2} mm", report.overlap_mm);
+
+    if report.aligned {
+        println!("\nResult: ALIGNED (shared patient coordinate system)");
+    } else {
+        println!("\nResult: NOT ALIGNED");
+        if let Some(offset) = &report.rigid_offset {
+            println!(
+                "  Centre offset: [{:.2}, {:.2}, {:.2}] mm, rotation {:.3} deg",
+                offset.translation_mm[0],
+                offset.translation_mm[1],
+                offset.translation_mm[2],
+                offset.rotation_deg
+            );
+        }
+    }
+    Ok(())
+}
+
+fn frame_vec<const N: usize>(
+    obj: &InMemDicomObject,
+    frame: u32,
+    macro_tag: Tag,
+    tag: Tag,
+) -> Option<[f64; N]> {
+    let values = frame_multi_f64(obj, frame, macro_tag, tag)?;
+    <[f64; N]>::try_from(values.get(..N)?).ok()
+}
+
+fn dot(a: [f64; 3], b: [f64; 3]) -> f64 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn cross(a: [f64; 3], b: [f64; 3]) -> [f64; 3] {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+fn add(a: [f64; 3], b: [f64; 3]) -> [f64; 3] {
+    [a[0] + b[0], a[1] + b[1], a[2] + b[2]]
+}
+
+fn sub(a: [f64; 3], b: [f64; 3]) -> [f64; 3] {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+fn normalize(v: [f64; 3]) -> [f64; 3] {
+    let len = dot(v, v).sqrt();
+    if len == 0.0 {
+        v
+    } else {
+        [v[0] / len, v[1] / len, v[2] / len]
+    }
+}
+
+fn angle_deg(a: [f64; 3], b: [f64; 3]) -> f64 {
+    let cos = dot(normalize(a), normalize(b)).clamp(-1.0, 1.0);
+    cos.acos().to_degrees()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn axial(for_uid: &str, z_start: f64, slices: usize, shift: [f64; 3]) -> SeriesGeometry {
+        SeriesGeometry {
+            series_instance_uid: None,
+            modality: None,
+            frame_of_reference_uid: Some(for_uid.to_string()),
+            orientation: [1.0, 0.0, 0.0, 0.0, 1.0, 0.0],
+            positions: (0..slices)
+                .map(|i| add([-250.0, -250.0, z_start + i as f64 * 2.0], shift))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn shared_frame_and_parallel_planes_are_aligned() {
+        let ct = axial("1.2.3", 0.0, 50, [0.0; 3]);
+        let pet = axial("1.2.3", 20.0, 50, [0.0; 3]);
+        let report = compare(&ct, &pet, 0.5);
+        assert!(report.aligned);
+        assert!(report.rigid_offset.is_none());
+        assert!((report.overlap_mm - 78.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn different_frame_reports_rigid_offset() {
+        let a = axial("1.2.3", 0.0, 10, [0.0; 3]);
+        let b = axial("9.8.7", 0.0, 10, [5.0, -3.0, 10.0]);
+        let report = compare(&a, &b, 0.5);
+        assert!(!report.aligned);
+        let offset = report.rigid_offset.expect("offset");
+        assert_eq!(offset.translation_mm, [5.0, -3.0, 10.0]);
+        assert_eq!(offset.rotation_deg, 0.0);
+    }
+}
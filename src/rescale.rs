@@ -0,0 +1,442 @@
+//
+// rescale.rs
+// Dicom-Tools-rs
+//
+// Rewrites Rescale Slope/Intercept, re-encoding stored values so modality values are preserved exactly.
+//
+// Thales Matheus Mendonça Santos - November 2025
+
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use dicom::core::value::Value;
+use dicom::core::{DataElement, PrimitiveValue, Tag, VR};
+use dicom::object::InMemDicomObject;
+
+use crate::atomic_file;
+use crate::dicom_access::{open_dicom, ElementAccess};
+
+const SAMPLES_PER_PIXEL: Tag = Tag(0x0028, 0x0002);
+const BITS_ALLOCATED: Tag = Tag(0x0028, 0x0100);
+const BITS_STORED: Tag = Tag(0x0028, 0x0101);
+const HIGH_BIT: Tag = Tag(0x0028, 0x0102);
+const PIXEL_REPRESENTATION: Tag = Tag(0x0028, 0x0103);
+const SMALLEST_IMAGE_PIXEL_VALUE: Tag = Tag(0x0028, 0x0106);
+const LARGEST_IMAGE_PIXEL_VALUE: Tag = Tag(0x0028, 0x0107);
+const PIXEL_PADDING_VALUE: Tag = Tag(0x0028, 0x0120);
+const RESCALE_INTERCEPT: Tag = Tag(0x0028, 0x1052);
+const RESCALE_SLOPE: Tag = Tag(0x0028, 0x1053);
+const SHARED_FUNCTIONAL_GROUPS_SEQUENCE: Tag = Tag(0x5200, 0x9229);
+const PER_FRAME_FUNCTIONAL_GROUPS_SEQUENCE: Tag = Tag(0x5200, 0x9230);
+const PIXEL_DATA: Tag = Tag(0x7FE0, 0x0010);
+
+/// Stored values further than this from an integer are not representable.
+const INTEGER_TOLERANCE: f64 = 1e-6;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rescale {
+    pub slope: f64,
+    pub intercept: f64,
+}
+
+impl Rescale {
+    pub const IDENTITY: Rescale = Rescale {
+        slope: 1.0,
+        intercept: 0.0,
+    };
+
+    fn apply(self, stored: f64) -> f64 {
+        stored * self.slope + self.intercept
+    }
+
+    fn invert(self, modality: f64) -> f64 {
+        (modality - self.intercept) / self.slope
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RescaleMode {
+    /// Re-encode stored values for the new rescale; modality values do not change.
+    Reencode(Rescale),
+    /// Only rewrite the attributes, changing what the stored values mean (e.g. adding the
+    /// -1024 offset a vendor left out).
+    Relabel(Rescale),
+}
+
+/// Integer sample layout: bits stored and signedness.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SampleFormat {
+    pub bits_stored: u16,
+    pub signed: bool,
+}
+
+impl SampleFormat {
+    fn range(self) -> (i64, i64) {
+        let bits = self.bits_stored as u32;
+        if self.signed {
+            (-(1i64 << (bits - 1)), (1i64 << (bits - 1)) - 1)
+        } else {
+            (0, (1i64 << bits) - 1)
+        }
+    }
+
+    fn holds(self, min: i64, max: i64) -> bool {
+        let (low, high) = self.range();
+        low <= min && max <= high
+    }
+
+    fn decode(self, raw: u32) -> i64 {
+        let bits = self.bits_stored as u32;
+        let value = (raw & ((1u32 << bits) - 1)) as i64;
+        if self.signed && value >= 1 << (bits - 1) {
+            value - (1 << bits)
+        } else {
+            value
+        }
+    }
+
+    /// Smallest format, starting from `self` and never wider than `bits_allocated`, that
+    /// holds every value in `min..=max`.
+    fn fit(self, min: i64, max: i64, bits_allocated: u16) -> Option<SampleFormat> {
+        let signed_first = [self.signed, !self.signed];
+        (self.bits_stored..=bits_allocated)
+            .flat_map(|bits_stored| {
+                signed_first.map(|signed| SampleFormat {
+                    bits_stored,
+                    signed,
+                })
+            })
+            .find(|format| format.holds(min, max))
+    }
+}
+
+/// What a rewrite changed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RescaleSummary {
+    pub before: Rescale,
+    pub after: Rescale,
+    pub format_before: SampleFormat,
+    pub format_after: SampleFormat,
+    /// Range of stored values written.
+    pub stored_range: (i64, i64),
+    pub samples: usize,
+}
+
+fn read_rescale(obj: &InMemDicomObject) -> Rescale {
+    Rescale {
+        slope: obj.element_f64(RESCALE_SLOPE).unwrap_or(1.0),
+        intercept: obj.element_f64(RESCALE_INTERCEPT).unwrap_or(0.0),
+    }
+}
+
+fn write_rescale(obj: &mut InMemDicomObject, rescale: Rescale) {
+    let ds = |value: f64| PrimitiveValue::from(format!("{}", value));
+    obj.put(DataElement::new(RESCALE_SLOPE, VR::DS, ds(rescale.slope)));
+    obj.put(DataElement::new(
+        RESCALE_INTERCEPT,
+        VR::DS,
+        ds(rescale.intercept),
+    ));
+}
+
+fn to_integer(value: f64) -> Option<i64> {
+    let rounded = value.round();
+    ((value - rounded).abs() < INTEGER_TOLERANCE).then_some(rounded as i64)
+}
+
+/// Raw sample words of native pixel data.
+fn raw_samples(obj: &InMemDicomObject, bits_allocated: u16) -> Result<Vec<u32>> {
+    let element = obj.element(PIXEL_DATA).context("No Pixel Data")?;
+    let Value::Primitive(value) = element.value() else {
+        bail!("Pixel Data is encapsulated; transcode to an uncompressed syntax first");
+    };
+    Ok(match (bits_allocated, value) {
+        (16, PrimitiveValue::U16(words)) => words.iter().map(|&w| w as u32).collect(),
+        (16, other) => other
+            .to_bytes()
+            .chunks_exact(2)
+            .map(|pair| u16::from_le_bytes([pair[0], pair[1]]) as u32)
+            .collect(),
+        (8, other) => other.to_bytes().iter().map(|&b| b as u32).collect(),
+        (bits, _) => bail!("Bits Allocated {} is not supported", bits),
+    })
+}
+
+/// Rewrite the rescale of a monochrome object held in memory.
+pub fn rewrite_rescale(obj: &mut InMemDicomObject, mode: RescaleMode) -> Result<RescaleSummary> {
+    if obj.element_u32(SAMPLES_PER_PIXEL).unwrap_or(1) != 1 {
+        bail!("Rescale only applies to single-sample (monochrome) images");
+    }
+    if obj.has_element(SHARED_FUNCTIONAL_GROUPS_SEQUENCE)
+        || obj.has_element(PER_FRAME_FUNCTIONAL_GROUPS_SEQUENCE)
+    {
+        bail!("Enhanced objects carry rescale in functional groups, which is not supported");
+    }
+    let bits_allocated = obj
+        .element_u32(BITS_ALLOCATED)
+        .context("No Bits Allocated")? as u16;
+    let format_before = SampleFormat {
+        bits_stored: obj
+            .element_u32(BITS_STORED)
+            .map(|b| b as u16)
+            .unwrap_or(bits_allocated),
+        signed: obj.element_u32(PIXEL_REPRESENTATION) == Some(1),
+    };
+    if format_before.bits_stored == 0 || format_before.bits_stored > bits_allocated {
+        bail!(
+            "Bits Stored {} does not fit Bits Allocated {}",
+            format_before.bits_stored,
+            bits_allocated
+        );
+    }
+    let before = read_rescale(obj);
+
+    let (RescaleMode::Reencode(after) | RescaleMode::Relabel(after)) = mode;
+    if after.slope == 0.0 || !after.slope.is_finite() || !after.intercept.is_finite() {
+        bail!("Rescale slope must be a non-zero finite number");
+    }
+    let raw = raw_samples(obj, bits_allocated)?;
+    let stored: Vec<i64> = raw.iter().map(|&r| format_before.decode(r)).collect();
+    let RescaleMode::Reencode(_) = mode else {
+        let min = stored.iter().copied().min().unwrap_or(0);
+        let max = stored.iter().copied().max().unwrap_or(0);
+        write_rescale(obj, after);
+        return Ok(RescaleSummary {
+            before,
+            after,
+            format_before,
+            format_after: format_before,
+            stored_range: (min, max),
+            samples: stored.len(),
+        });
+    };
+
+    // Every sample (and the padding value) must land on an integer under the new rescale.
+    let convert = |value: i64| to_integer(after.invert(before.apply(value as f64)));
+    let mut converted = Vec::with_capacity(stored.len());
+    let mut inexact = 0usize;
+    for &value in &stored {
+        match convert(value) {
+            Some(v) => converted.push(v),
+            None => inexact += 1,
+        }
+    }
+    if inexact > 0 {
+        bail!(
+            "{} of {} samples are not integers under slope {} / intercept {}; the rewrite would be lossy",
+            inexact,
+            stored.len(),
+            after.slope,
+            after.intercept
+        );
+    }
+    let padding = match obj.element_str(PIXEL_PADDING_VALUE) {
+        Some(text) => {
+            let value = text
+                .trim()
+                .parse::<i64>()
+                .context("Invalid Pixel Padding Value")?;
+            Some(convert(value).context("Pixel Padding Value is not representable")?)
+        }
+        None => None,
+    };
+
+    let min = converted.iter().chain(&padding).copied().min().unwrap_or(0);
+    let max = converted.iter().chain(&padding).copied().max().unwrap_or(0);
+    let format_after = format_before
+        .fit(min, max, bits_allocated)
+        .with_context(|| {
+            format!(
+                "Stored values {}..={} do not fit in {} allocated bits",
+                min, max, bits_allocated
+            )
+        })?;
+
+    let pixel_vr = obj.element(PIXEL_DATA).map(|e| e.vr()).unwrap_or(VR::OW);
+    let pixels = if bits_allocated == 16 {
+        PrimitiveValue::U16(converted.iter().map(|&v| v as u16).collect())
+    } else {
+        PrimitiveValue::U8(converted.iter().map(|&v| v as u8).collect())
+    };
+    obj.put(DataElement::new(PIXEL_DATA, pixel_vr, pixels));
+
+    let bits_stored = format_after.bits_stored;
+    obj.put(DataElement::new(
+        BITS_STORED,
+        VR::US,
+        PrimitiveValue::from(bits_stored),
+    ));
+    obj.put(DataElement::new(
+        HIGH_BIT,
+        VR::US,
+        PrimitiveValue::from(bits_stored - 1),
+    ));
+    obj.put(DataElement::new(
+        PIXEL_REPRESENTATION,
+        VR::US,
+        PrimitiveValue::from(format_after.signed as u16),
+    ));
+    if let Some(padding) = padding {
+        obj.put(if format_after.signed {
+            DataElement::new(
+                PIXEL_PADDING_VALUE,
+                VR::SS,
+                PrimitiveValue::from(padding as i16),
+            )
+        } else {
+            DataElement::new(
+                PIXEL_PADDING_VALUE,
+                VR::US,
+                PrimitiveValue::from(padding as u16),
+            )
+        });
+    }
+    write_rescale(obj, after);
+    // These are in stored units and would now be stale.
+    obj.remove_element(SMALLEST_IMAGE_PIXEL_VALUE);
+    obj.remove_element(LARGEST_IMAGE_PIXEL_VALUE);
+
+    Ok(RescaleSummary {
+        before,
+        after,
+        format_before,
+        format_after,
+        stored_range: (min, max),
+        samples: converted.len(),
+    })
+}
+
+/// CLI entry point.
+pub fn rescale_file(input: &Path, output: &Path, mode: RescaleMode) -> Result<()> {
+    let mut obj = open_dicom(input).context("Failed to open DICOM file")?;
+    let summary = rewrite_rescale(&mut obj, mode)?;
+    atomic_file::write_dicom(output, &obj)?;
+
+    println!(
+        "Rescale {} x + {} -> {} x + {} ({} samples, stored {}..={})",
+        summary.before.slope,
+        summary.before.intercept,
+        summary.after.slope,
+        summary.after.intercept,
+        summary.samples,
+        summary.stored_range.0,
+        summary.stored_range.1
+    );
+    if summary.format_after != summary.format_before {
+        println!(
+            "  Sample format changed: {} bits {} -> {} bits {}",
+            summary.format_before.bits_stored,
+            if summary.format_before.signed {
+                "signed"
+            } else {
+                "unsigned"
+            },
+            summary.format_after.bits_stored,
+            if summary.format_after.signed {
+                "signed"
+            } else {
+                "unsigned"
+            },
+        );
+    }
+    if let RescaleMode::Relabel(_) = mode {
+        println!("  Stored values unchanged: modality values now follow the new rescale");
+    }
+    println!("Saved to {:?}", output);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ct(stored: Vec<u16>, slope: &str, intercept: &str) -> InMemDicomObject {
+        let mut obj = InMemDicomObject::new_empty();
+        obj.put(DataElement::new(
+            BITS_ALLOCATED,
+            VR::US,
+            PrimitiveValue::from(16u16),
+        ));
+        obj.put(DataElement::new(
+            BITS_STORED,
+            VR::US,
+            PrimitiveValue::from(12u16),
+        ));
+        obj.put(DataElement::new(
+            HIGH_BIT,
+            VR::US,
+            PrimitiveValue::from(11u16),
+        ));
+        obj.put(DataElement::new(
+            PIXEL_REPRESENTATION,
+            VR::US,
+            PrimitiveValue::from(0u16),
+        ));
+        obj.put(DataElement::new(
+            RESCALE_SLOPE,
+            VR::DS,
+            PrimitiveValue::from(slope),
+        ));
+        obj.put(DataElement::new(
+            RESCALE_INTERCEPT,
+            VR::DS,
+            PrimitiveValue::from(intercept),
+        ));
+        obj.put(DataElement::new(
+            PIXEL_DATA,
+            VR::OW,
+            PrimitiveValue::U16(stored.into()),
+        ));
+        obj
+    }
+
+    fn words(obj: &InMemDicomObject) -> Vec<u16> {
+        match obj.element(PIXEL_DATA).unwrap().value() {
+            Value::Primitive(PrimitiveValue::U16(words)) => words.to_vec(),
+            other => panic!("unexpected pixel value {:?}", other),
+        }
+    }
+
+    #[test]
+    fn baking_offset_ct_into_hu_switches_to_signed() {
+        // Air, water and bone in the usual unsigned encoding with a -1024 intercept.
+        let mut obj = ct(vec![24, 1024, 2024], "1", "-1024");
+        let summary =
+            rewrite_rescale(&mut obj, RescaleMode::Reencode(Rescale::IDENTITY)).expect("bake");
+        assert_eq!(summary.stored_range, (-1000, 1000));
+        assert_eq!(
+            summary.format_after,
+            SampleFormat {
+                bits_stored: 12,
+                signed: true
+            }
+        );
+        assert_eq!(words(&obj), [(-1000i16) as u16, 0, 1000]);
+        assert_eq!(obj.element_u32(PIXEL_REPRESENTATION), Some(1));
+        assert_eq!(read_rescale(&obj), Rescale::IDENTITY);
+    }
+
+    #[test]
+    fn lossy_and_overflowing_rewrites_are_refused() {
+        let mut obj = ct(vec![1, 2, 3], "1", "0");
+        let half = Rescale {
+            slope: 2.0,
+            intercept: 0.0,
+        };
+        assert!(rewrite_rescale(&mut obj, RescaleMode::Reencode(half)).is_err());
+        assert_eq!(read_rescale(&obj), Rescale::IDENTITY);
+
+        let mut obj = ct(vec![4095], "32", "0");
+        assert!(rewrite_rescale(&mut obj, RescaleMode::Reencode(Rescale::IDENTITY)).is_err());
+
+        // Relabelling never touches pixels.
+        let mut obj = ct(vec![24, 1024], "1", "0");
+        let offset = Rescale {
+            slope: 1.0,
+            intercept: -1024.0,
+        };
+        rewrite_rescale(&mut obj, RescaleMode::Relabel(offset)).expect("relabel");
+        assert_eq!(words(&obj), [24, 1024]);
+        assert_eq!(read_rescale(&obj), offset);
+    }
+}
@@ -0,0 +1,514 @@
+//
+// roi_mask.rs
+// Dicom-Tools-rs
+//
+// Resolves a segment of a Segmentation (SEG) or an ROI of an RT Structure Set into a
+// per-pixel mask over the frames of a referenced image, for statistics within a region.
+//
+// Thales Matheus Mendonça Santos - November 2025
+
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use dicom::core::Tag;
+use dicom::object::InMemDicomObject;
+
+use crate::dicom_access::{open_dicom, ElementAccess};
+use crate::measure::{frame_multi_f64, FrameGeometry, IMAGE_POSITION, PLANE_POSITION_SEQUENCE};
+
+const SOP_CLASS_UID: Tag = Tag(0x0008, 0x0016);
+const SOP_INSTANCE_UID: Tag = Tag(0x0008, 0x0018);
+const REFERENCED_SOP_INSTANCE_UID: Tag = Tag(0x0008, 0x1155);
+const REFERENCED_FRAME_NUMBER: Tag = Tag(0x0008, 0x1160);
+const SOURCE_IMAGE_SEQUENCE: Tag = Tag(0x0008, 0x2112);
+const DERIVATION_IMAGE_SEQUENCE: Tag = Tag(0x0008, 0x9124);
+const SLICE_THICKNESS: Tag = Tag(0x0018, 0x0050);
+const NUMBER_OF_FRAMES: Tag = Tag(0x0028, 0x0008);
+const ROWS: Tag = Tag(0x0028, 0x0010);
+const COLUMNS: Tag = Tag(0x0028, 0x0011);
+const BITS_ALLOCATED: Tag = Tag(0x0028, 0x0100);
+const PER_FRAME_FUNCTIONAL_GROUPS: Tag = Tag(0x5200, 0x9230);
+const SEGMENTATION_TYPE: Tag = Tag(0x0062, 0x0001);
+const SEGMENT_SEQUENCE: Tag = Tag(0x0062, 0x0002);
+const SEGMENT_NUMBER: Tag = Tag(0x0062, 0x0004);
+const SEGMENT_LABEL: Tag = Tag(0x0062, 0x0005);
+const SEGMENT_IDENTIFICATION_SEQUENCE: Tag = Tag(0x0062, 0x000A);
+const REFERENCED_SEGMENT_NUMBER: Tag = Tag(0x0062, 0x000B);
+const CONTOUR_IMAGE_SEQUENCE: Tag = Tag(0x3006, 0x0016);
+const STRUCTURE_SET_ROI_SEQUENCE: Tag = Tag(0x3006, 0x0020);
+const ROI_NUMBER: Tag = Tag(0x3006, 0x0022);
+const ROI_NAME: Tag = Tag(0x3006, 0x0026);
+const ROI_CONTOUR_SEQUENCE: Tag = Tag(0x3006, 0x0039);
+const CONTOUR_SEQUENCE: Tag = Tag(0x3006, 0x0040);
+const CONTOUR_GEOMETRIC_TYPE: Tag = Tag(0x3006, 0x0042);
+const CONTOUR_DATA: Tag = Tag(0x3006, 0x0050);
+const REFERENCED_ROI_NUMBER: Tag = Tag(0x3006, 0x0084);
+const PIXEL_DATA: Tag = Tag(0x7FE0, 0x0010);
+
+pub const SEGMENTATION_STORAGE: &str = "1.2.840.10008.5.1.4.1.1.66.4";
+pub const RT_STRUCTURE_SET_STORAGE: &str = "1.2.840.10008.5.1.4.1.1.481.3";
+
+/// Segmentation frames are matched to image frames by position when they carry no source
+/// image reference; DS values are rounded by writers, so exact equality is too strict.
+pub const POSITION_TOLERANCE_MM: f64 = 0.05;
+/// Contours are assigned to the slice they lie in, within half this thickness when the
+/// image does not state its own Slice Thickness.
+const DEFAULT_SLICE_THICKNESS_MM: f64 = 1.0;
+
+/// Pixels of the image that belong to one segment or ROI, frame by frame.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RoiMask {
+    /// Segment Label or ROI Name, when the source gives one.
+    pub label: Option<String>,
+    pub rows: u32,
+    pub columns: u32,
+    /// One row-major `rows * columns` mask per image frame.
+    pub frames: Vec<Vec<bool>>,
+}
+
+impl RoiMask {
+    fn empty(label: Option<String>, rows: u32, columns: u32, frames: u32) -> Self {
+        RoiMask {
+            label,
+            rows,
+            columns,
+            frames: vec![vec![false; (rows * columns) as usize]; frames as usize],
+        }
+    }
+
+    /// Number of pixels inside the region, over all frames.
+    pub fn count(&self) -> usize {
+        self.frames
+            .iter()
+            .map(|frame| frame.iter().filter(|&&inside| inside).count())
+            .sum()
+    }
+}
+
+/// Open `path` (a SEG or an RTSTRUCT) and resolve segment / ROI number `segment` against
+/// `image`, which has `frames` frames.
+pub fn load(path: &Path, segment: u32, image: &InMemDicomObject, frames: u32) -> Result<RoiMask> {
+    let source = open_dicom(path).with_context(|| format!("Failed to open mask {:?}", path))?;
+    resolve(&source, segment, image, frames).with_context(|| format!("Mask {:?}", path))
+}
+
+/// [`load`] for a mask object already in memory, to resolve it against many images.
+pub fn resolve(
+    source: &InMemDicomObject,
+    segment: u32,
+    image: &InMemDicomObject,
+    frames: u32,
+) -> Result<RoiMask> {
+    let sop_class = source.element_str(SOP_CLASS_UID).unwrap_or_default();
+    match sop_class.trim_end_matches('\0') {
+        SEGMENTATION_STORAGE => from_segmentation(source, segment, image, frames),
+        RT_STRUCTURE_SET_STORAGE => from_structure_set(source, segment, image, frames),
+        other => bail!(
+            "Neither a Segmentation nor an RT Structure Set (SOP Class {})",
+            other
+        ),
+    }
+}
+
+/// Image frame size, required to lay out a mask.
+fn image_size(image: &InMemDicomObject) -> Result<(u32, u32)> {
+    match (image.element_u32(ROWS), image.element_u32(COLUMNS)) {
+        (Some(rows), Some(columns)) => Ok((rows, columns)),
+        _ => bail!("Image has no Rows/Columns"),
+    }
+}
+
+fn instance_uid(obj: &InMemDicomObject) -> Option<String> {
+    obj.element_str(SOP_INSTANCE_UID)
+        .map(|uid| uid.trim_end_matches('\0').trim().to_string())
+}
+
+/// Whether a reference item points at frame `frame` (0-based) of the image `uid`;
+/// a reference without Referenced Frame Number covers every frame.
+fn references(item: &InMemDicomObject, uid: &str, frame: u32) -> bool {
+    instance_uid_of(item).as_deref() == Some(uid)
+        && item
+            .element_str(REFERENCED_FRAME_NUMBER)
+            .map(|numbers| {
+                numbers
+                    .split('\\')
+                    .filter_map(|n| n.trim().trim_end_matches('\0').parse::<u32>().ok())
+                    .any(|n| n == frame + 1)
+            })
+            .unwrap_or(true)
+}
+
+fn instance_uid_of(item: &InMemDicomObject) -> Option<String> {
+    item.element_str(REFERENCED_SOP_INSTANCE_UID)
+        .map(|uid| uid.trim_end_matches('\0').trim().to_string())
+}
+
+fn from_segmentation(
+    seg: &InMemDicomObject,
+    segment: u32,
+    image: &InMemDicomObject,
+    frames: u32,
+) -> Result<RoiMask> {
+    let label = seg
+        .sequence_items(SEGMENT_SEQUENCE)
+        .iter()
+        .find(|item| item.element_u32(SEGMENT_NUMBER) == Some(segment))
+        .with_context(|| format!("Segmentation has no segment {}", segment))?
+        .element_str(SEGMENT_LABEL)
+        .map(|label| label.trim_end_matches(['\0', ' ']).to_string());
+
+    let (rows, columns) = image_size(image)?;
+    if image_size(seg)? != (rows, columns) {
+        bail!(
+            "Segmentation frames are {}x{}, the image is {}x{}",
+            seg.element_u32(COLUMNS).unwrap_or(0),
+            seg.element_u32(ROWS).unwrap_or(0),
+            columns,
+            rows
+        );
+    }
+    let fractional = seg
+        .element_str(SEGMENTATION_TYPE)
+        .is_some_and(|t| t.trim_end_matches(['\0', ' ']) == "FRACTIONAL");
+    let bits = if fractional { 8 } else { 1 };
+    if seg.element_u32(BITS_ALLOCATED) != Some(bits) {
+        bail!("Segmentation pixel data must be {} bit(s) per pixel", bits);
+    }
+    let pixels = seg
+        .element(PIXEL_DATA)
+        .context("Segmentation has no Pixel Data")?
+        .to_bytes()
+        .context("Segmentation Pixel Data is encapsulated; only native encoding is read")?;
+
+    let image_uid = instance_uid(image).unwrap_or_default();
+    let image_positions: Vec<Option<Vec<f64>>> = (0..frames)
+        .map(|frame| frame_multi_f64(image, frame, PLANE_POSITION_SEQUENCE, IMAGE_POSITION))
+        .collect();
+    let frame_pixels = (rows * columns) as usize;
+    let per_frame = seg.sequence_items(PER_FRAME_FUNCTIONAL_GROUPS);
+    let seg_frames = seg.element_u32(NUMBER_OF_FRAMES).unwrap_or(1);
+
+    let mut mask = RoiMask::empty(label, rows, columns, frames);
+    for seg_frame in 0..seg_frames {
+        let group = per_frame.get(seg_frame as usize);
+        let number = group
+            .and_then(|g| g.sequence_items(SEGMENT_IDENTIFICATION_SEQUENCE).first())
+            .and_then(|item| item.element_u32(REFERENCED_SEGMENT_NUMBER));
+        if number != Some(segment) {
+            continue;
+        }
+        let sources: Vec<&InMemDicomObject> = group
+            .map(|g| g.sequence_items(DERIVATION_IMAGE_SEQUENCE))
+            .unwrap_or_default()
+            .iter()
+            .flat_map(|derivation| derivation.sequence_items(SOURCE_IMAGE_SEQUENCE))
+            .collect();
+        let position = frame_multi_f64(seg, seg_frame, PLANE_POSITION_SEQUENCE, IMAGE_POSITION);
+
+        for frame in 0..frames {
+            let matches = if sources.is_empty() {
+                match (&position, &image_positions[frame as usize]) {
+                    (Some(a), Some(b)) => distance(a, b) <= POSITION_TOLERANCE_MM,
+                    // Without references or positions only a frame-for-frame layout is safe.
+                    _ => seg_frames == frames && seg_frame == frame,
+                }
+            } else {
+                sources
+                    .iter()
+                    .any(|item| references(item, &image_uid, frame))
+            };
+            if !matches {
+                continue;
+            }
+            let target = &mut mask.frames[frame as usize];
+            let start = seg_frame as usize * frame_pixels;
+            for (index, inside) in target.iter_mut().enumerate() {
+                let pixel = start + index;
+                let set = if fractional {
+                    pixels.get(pixel).is_some_and(|&v| v > 0)
+                } else {
+                    pixels
+                        .get(pixel / 8)
+                        .is_some_and(|&byte| byte >> (pixel % 8) & 1 == 1)
+                };
+                *inside |= set;
+            }
+        }
+    }
+    Ok(mask)
+}
+
+fn distance(a: &[f64], b: &[f64]) -> f64 {
+    a.iter()
+        .zip(b)
+        .map(|(x, y)| (x - y) * (x - y))
+        .sum::<f64>()
+        .sqrt()
+}
+
+fn from_structure_set(
+    rtstruct: &InMemDicomObject,
+    roi: u32,
+    image: &InMemDicomObject,
+    frames: u32,
+) -> Result<RoiMask> {
+    let label = rtstruct
+        .sequence_items(STRUCTURE_SET_ROI_SEQUENCE)
+        .iter()
+        .find(|item| item.element_u32(ROI_NUMBER) == Some(roi))
+        .with_context(|| format!("Structure set has no ROI {}", roi))?
+        .element_str(ROI_NAME)
+        .map(|name| name.trim_end_matches(['\0', ' ']).to_string());
+    let contours = rtstruct
+        .sequence_items(ROI_CONTOUR_SEQUENCE)
+        .iter()
+        .find(|item| item.element_u32(REFERENCED_ROI_NUMBER) == Some(roi))
+        .map(|item| item.sequence_items(CONTOUR_SEQUENCE))
+        .unwrap_or_default();
+
+    let (rows, columns) = image_size(image)?;
+    let image_uid = instance_uid(image).unwrap_or_default();
+    let half_thickness = image
+        .element_f64(SLICE_THICKNESS)
+        .filter(|&t| t > 0.0)
+        .unwrap_or(DEFAULT_SLICE_THICKNESS_MM)
+        / 2.0;
+
+    let mut mask = RoiMask::empty(label, rows, columns, frames);
+    for frame in 0..frames {
+        let geometry = FrameGeometry::for_frame(image, frame)?;
+        let (Some(orientation), Some(position)) = (geometry.orientation, geometry.position) else {
+            bail!("Image frame {} is not placed in patient space", frame);
+        };
+        let row_dir = [orientation[0], orientation[1], orientation[2]];
+        let column_dir = [orientation[3], orientation[4], orientation[5]];
+        let normal = cross(row_dir, column_dir);
+
+        for contour in contours {
+            let closed = contour
+                .element_str(CONTOUR_GEOMETRIC_TYPE)
+                .is_some_and(|t| t.trim_end_matches(['\0', ' ']) == "CLOSED_PLANAR");
+            let points = contour_points(contour);
+            if !closed || points.len() < 3 {
+                continue;
+            }
+            // An explicit frame reference decides; otherwise the contour belongs to the
+            // slice whose plane it lies in.
+            let referenced: Vec<&InMemDicomObject> = contour
+                .sequence_items(CONTOUR_IMAGE_SEQUENCE)
+                .iter()
+                .filter(|item| {
+                    instance_uid_of(item).as_deref() == Some(image_uid.as_str())
+                        && item.has_element(REFERENCED_FRAME_NUMBER)
+                })
+                .collect();
+            let on_frame = if referenced.is_empty() {
+                dot(sub(points[0], position), normal).abs() <= half_thickness
+            } else {
+                referenced
+                    .iter()
+                    .any(|item| references(item, &image_uid, frame))
+            };
+            if !on_frame {
+                continue;
+            }
+
+            // Contour points in fractional pixel indices of this frame.
+            let polygon: Vec<(f64, f64)> = points
+                .iter()
+                .map(|&p| {
+                    let offset = sub(p, position);
+                    (
+                        dot(offset, row_dir) / geometry.column_spacing,
+                        dot(offset, column_dir) / geometry.row_spacing,
+                    )
+                })
+                .collect();
+            fill_polygon(&polygon, rows, columns, &mut mask.frames[frame as usize]);
+        }
+    }
+    Ok(mask)
+}
+
+fn contour_points(contour: &InMemDicomObject) -> Vec<[f64; 3]> {
+    let values: Vec<f64> = contour
+        .element_str(CONTOUR_DATA)
+        .map(|text| {
+            text.trim_end_matches(['\0', ' '])
+                .split('\\')
+                .filter_map(|v| v.trim().parse().ok())
+                .collect()
+        })
+        .unwrap_or_default();
+    values.chunks_exact(3).map(|p| [p[0], p[1], p[2]]).collect()
+}
+
+/// Toggle the pixels whose centres fall inside `polygon` (even-odd rule), so contours
+/// nested on the same slice cut holes. Edges are half-open (top and left inclusive), which
+/// keeps the pixel count in line with the enclosed area.
+pub(crate) fn fill_polygon(polygon: &[(f64, f64)], rows: u32, columns: u32, mask: &mut [bool]) {
+    for row in 0..rows {
+        let y = row as f64;
+        let mut crossings: Vec<f64> = polygon
+            .iter()
+            .zip(polygon.iter().cycle().skip(1))
+            .filter(|((_, y0), (_, y1))| (*y0 <= y && y < *y1) || (*y1 <= y && y < *y0))
+            .map(|((x0, y0), (x1, y1))| x0 + (y - y0) * (x1 - x0) / (y1 - y0))
+            .collect();
+        crossings.sort_by(|a, b| a.total_cmp(b));
+        for span in crossings.chunks_exact(2) {
+            let first = span[0].ceil().max(0.0) as u32;
+            let end = span[1].ceil().clamp(0.0, columns as f64) as u32;
+            for column in first..end {
+                let index = (row * columns + column) as usize;
+                mask[index] = !mask[index];
+            }
+        }
+    }
+}
+
+fn sub(a: [f64; 3], b: [f64; 3]) -> [f64; 3] {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+fn dot(a: [f64; 3], b: [f64; 3]) -> f64 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn cross(a: [f64; 3], b: [f64; 3]) -> [f64; 3] {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dicom::core::value::DataSetSequence;
+    use dicom::core::{DataElement, PrimitiveValue, VR};
+
+    fn put(obj: &mut InMemDicomObject, tag: Tag, vr: VR, value: &str) {
+        obj.put(DataElement::new(tag, vr, PrimitiveValue::from(value)));
+    }
+
+    fn sequence(obj: &mut InMemDicomObject, tag: Tag, items: Vec<InMemDicomObject>) {
+        obj.put(DataElement::new(tag, VR::SQ, DataSetSequence::from(items)));
+    }
+
+    /// 4x4 axial slice at z = 10 with 1 mm pixels, first pixel centred at the origin.
+    fn image() -> InMemDicomObject {
+        let mut obj = InMemDicomObject::new_empty();
+        put(&mut obj, SOP_INSTANCE_UID, VR::UI, "1.2.3.4");
+        obj.put(DataElement::new(ROWS, VR::US, PrimitiveValue::from(4_u16)));
+        obj.put(DataElement::new(
+            COLUMNS,
+            VR::US,
+            PrimitiveValue::from(4_u16),
+        ));
+        put(&mut obj, Tag(0x0028, 0x0030), VR::DS, "1\\1");
+        put(&mut obj, Tag(0x0020, 0x0037), VR::DS, "1\\0\\0\\0\\1\\0");
+        put(&mut obj, IMAGE_POSITION, VR::DS, "0\\0\\10");
+        obj
+    }
+
+    #[test]
+    fn structure_set_contours_are_filled_on_their_slice() {
+        let mut contour = InMemDicomObject::new_empty();
+        put(
+            &mut contour,
+            CONTOUR_GEOMETRIC_TYPE,
+            VR::CS,
+            "CLOSED_PLANAR",
+        );
+        // 2x2 mm square around the centres of pixels (1,1)..(2,2).
+        put(
+            &mut contour,
+            CONTOUR_DATA,
+            VR::DS,
+            "0.5\\0.5\\10\\2.5\\0.5\\10\\2.5\\2.5\\10\\0.5\\2.5\\10",
+        );
+        let mut elsewhere = contour.clone();
+        put(
+            &mut elsewhere,
+            CONTOUR_DATA,
+            VR::DS,
+            "0\\0\\20\\3\\0\\20\\3\\3\\20",
+        );
+
+        let mut roi_contour = InMemDicomObject::new_empty();
+        put(&mut roi_contour, REFERENCED_ROI_NUMBER, VR::IS, "2");
+        sequence(&mut roi_contour, CONTOUR_SEQUENCE, vec![contour, elsewhere]);
+        let mut roi = InMemDicomObject::new_empty();
+        put(&mut roi, ROI_NUMBER, VR::IS, "2");
+        put(&mut roi, ROI_NAME, VR::LO, "Lesion");
+        let mut rtstruct = InMemDicomObject::new_empty();
+        sequence(&mut rtstruct, STRUCTURE_SET_ROI_SEQUENCE, vec![roi]);
+        sequence(&mut rtstruct, ROI_CONTOUR_SEQUENCE, vec![roi_contour]);
+
+        let mask = from_structure_set(&rtstruct, 2, &image(), 1).unwrap();
+        assert_eq!(mask.label.as_deref(), Some("Lesion"));
+        assert_eq!(mask.count(), 4);
+        assert!(mask.frames[0][4 + 1] && mask.frames[0][2 * 4 + 2]);
+        assert!(from_structure_set(&rtstruct, 3, &image(), 1).is_err());
+    }
+
+    #[test]
+    fn segmentation_frames_follow_their_source_image() {
+        let mut source = InMemDicomObject::new_empty();
+        put(&mut source, REFERENCED_SOP_INSTANCE_UID, VR::UI, "1.2.3.4");
+        let mut derivation = InMemDicomObject::new_empty();
+        sequence(&mut derivation, SOURCE_IMAGE_SEQUENCE, vec![source]);
+        let group = |segment: &str| {
+            let mut identification = InMemDicomObject::new_empty();
+            put(
+                &mut identification,
+                REFERENCED_SEGMENT_NUMBER,
+                VR::US,
+                segment,
+            );
+            let mut group = InMemDicomObject::new_empty();
+            sequence(
+                &mut group,
+                SEGMENT_IDENTIFICATION_SEQUENCE,
+                vec![identification],
+            );
+            sequence(
+                &mut group,
+                DERIVATION_IMAGE_SEQUENCE,
+                vec![derivation.clone()],
+            );
+            group
+        };
+
+        let mut seg = image();
+        put(&mut seg, SOP_INSTANCE_UID, VR::UI, "1.2.3.5");
+        put(&mut seg, SEGMENTATION_TYPE, VR::CS, "BINARY");
+        put(&mut seg, NUMBER_OF_FRAMES, VR::IS, "2");
+        seg.put(DataElement::new(
+            BITS_ALLOCATED,
+            VR::US,
+            PrimitiveValue::from(1_u16),
+        ));
+        let mut segment = InMemDicomObject::new_empty();
+        put(&mut segment, SEGMENT_NUMBER, VR::US, "2");
+        sequence(&mut seg, SEGMENT_SEQUENCE, vec![segment]);
+        sequence(
+            &mut seg,
+            PER_FRAME_FUNCTIONAL_GROUPS,
+            vec![group("1"), group("2")],
+        );
+        // Frame 1 sets pixel 0, frame 2 sets pixels 0 and 5 (bits continue across frames).
+        seg.put(DataElement::new(
+            PIXEL_DATA,
+            VR::OB,
+            PrimitiveValue::from(vec![0b0000_0001, 0, 0b0010_0001, 0]),
+        ));
+
+        let mask = from_segmentation(&seg, 2, &image(), 1).unwrap();
+        assert_eq!(mask.count(), 2);
+        assert!(mask.frames[0][0] && mask.frames[0][5]);
+        assert!(from_segmentation(&seg, 3, &image(), 1).is_err());
+    }
+}
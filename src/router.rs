@@ -0,0 +1,732 @@
+//
+// router.rs
+// Dicom-Tools-rs
+//
+// Instance-level routing: TOML rules match instances on header tags and run action chains (validate, anonymize, transcode, index, push, store),
+// per instance or per study once it has stopped growing.
+//
+// Thales Matheus Mendonça Santos - November 2025
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Context, Result};
+use chrono::Local;
+use dicom::core::Tag;
+use dicom::object::{open_file, DefaultDicomObject, OpenFileOptions};
+use serde::{Deserialize, Serialize};
+use tempfile::TempDir;
+use walkdir::WalkDir;
+
+use crate::cli::TransferSyntax;
+use crate::dicom_access::ElementAccess;
+use crate::progress::NoProgress;
+use crate::{anonymize, atomic_file, scu, transcode, validate};
+
+const MODALITY: Tag = Tag(0x0008, 0x0060);
+const SOP_CLASS_UID: Tag = Tag(0x0008, 0x0016);
+const SOP_INSTANCE_UID: Tag = Tag(0x0008, 0x0018);
+const PATIENT_ID: Tag = Tag(0x0010, 0x0020);
+const STUDY_INSTANCE_UID: Tag = Tag(0x0020, 0x000D);
+const SERIES_INSTANCE_UID: Tag = Tag(0x0020, 0x000E);
+const PIXEL_DATA: Tag = Tag(0x7FE0, 0x0010);
+/// How often [`spawn_flusher`] looks for studies that have gone quiet.
+const FLUSH_INTERVAL: Duration = Duration::from_millis(250);
+
+/// A rules file:
+///
+/// ```toml
+/// [nodes]
+/// RESEARCH = "10.0.0.5:104"
+///
+/// [[rules]]
+/// name = "ct-to-research"
+/// match = { modality = ["CT"], station_ae = ["CT01"] }
+/// on_failure = "ignore"
+/// actions = [
+///     { do = "validate" },
+///     { do = "anonymize" },
+///     { do = "index", file = "research.jsonl" },
+///     { do = "push", node = "RESEARCH" },
+/// ]
+///
+/// [[rules]]
+/// name = "complete-studies"
+/// coalesce_secs = 60
+/// actions = [{ do = "push", node = "RESEARCH" }]
+/// ```
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RouterConfig {
+    /// Push destinations by name, as `host:port`.
+    #[serde(default)]
+    pub nodes: HashMap<String, String>,
+    #[serde(default)]
+    pub rules: Vec<Rule>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Rule {
+    pub name: String,
+    #[serde(default, rename = "match")]
+    pub criteria: Criteria,
+    /// What a C-STORE answers when this rule fails for the received instance.
+    #[serde(default)]
+    pub on_failure: FailurePolicy,
+    /// Hold matching instances per study until none has arrived for this many seconds, then
+    /// run the actions on the whole study, pushing it over one association. Held instances
+    /// are accepted as they arrive, so `on_failure` does not apply.
+    #[serde(default)]
+    pub coalesce_secs: Option<u64>,
+    pub actions: Vec<Action>,
+}
+
+/// How the SCP answers a C-STORE whose instance a rule failed to process.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FailurePolicy {
+    /// Refuse the instance with 0xC000 (unable to process), leaving it unstored.
+    #[default]
+    Reject,
+    /// Refuse the instance with 0xA700 (out of resources), which senders retry later.
+    OutOfResources,
+    /// Log the failure and accept the instance anyway.
+    Ignore,
+}
+
+/// Conditions an instance must all meet; each list accepts any of its values and an empty
+/// list accepts everything.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Criteria {
+    #[serde(default)]
+    pub modality: Vec<String>,
+    /// AE title of the sender, or Source AE Title (0002,0016) for files read from disk.
+    #[serde(default)]
+    pub station_ae: Vec<String>,
+    #[serde(default)]
+    pub sop_class: Vec<String>,
+    /// Any other attribute, keyed by `"GGGG,EEEE"`.
+    #[serde(default)]
+    pub tags: BTreeMap<String, String>,
+}
+
+/// One step of a rule; each step works on the output of the previous one.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "do", rename_all = "kebab-case", deny_unknown_fields)]
+pub enum Action {
+    /// Fail the chain when the instance misses required attributes.
+    Validate,
+    Anonymize,
+    Transcode {
+        syntax: TransferSyntax,
+    },
+    /// Append one JSON line describing the instance to `file`.
+    Index {
+        file: PathBuf,
+    },
+    Push {
+        node: String,
+    },
+    Store {
+        dir: PathBuf,
+    },
+}
+
+/// Header values rules are evaluated against.
+#[derive(Debug, Clone, Default)]
+pub struct InstanceFacts {
+    pub modality: String,
+    pub station_ae: String,
+    pub sop_class: String,
+    pub sop_instance: String,
+    pub study_instance: String,
+    tags: HashMap<Tag, String>,
+}
+
+impl InstanceFacts {
+    fn read(obj: &DefaultDicomObject, station_ae: Option<&str>, wanted: &[Tag]) -> Self {
+        let text = |tag| clean(&obj.element_str(tag).unwrap_or_default());
+        Self {
+            modality: text(MODALITY),
+            station_ae: clean(
+                station_ae
+                    .or(obj.meta().source_application_entity_title.as_deref())
+                    .unwrap_or_default(),
+            ),
+            sop_class: text(SOP_CLASS_UID),
+            sop_instance: text(SOP_INSTANCE_UID),
+            study_instance: text(STUDY_INSTANCE_UID),
+            tags: wanted.iter().map(|&tag| (tag, text(tag))).collect(),
+        }
+    }
+}
+
+fn clean(value: &str) -> String {
+    value.trim_end_matches(['\0', ' ']).trim_start().to_string()
+}
+
+fn parse_tag(key: &str) -> Result<Tag> {
+    let digits: String = key.chars().filter(char::is_ascii_hexdigit).collect();
+    if digits.len() != 8 {
+        bail!("Tag {:?} must be written as GGGG,EEEE", key);
+    }
+    let group = u16::from_str_radix(&digits[..4], 16)?;
+    let element = u16::from_str_radix(&digits[4..], 16)?;
+    Ok(Tag(group, element))
+}
+
+impl Criteria {
+    fn matches(&self, facts: &InstanceFacts) -> bool {
+        let any = |values: &[String], actual: &str| {
+            values.is_empty() || values.iter().any(|v| v.eq_ignore_ascii_case(actual))
+        };
+        any(&self.modality, &facts.modality)
+            && any(&self.station_ae, &facts.station_ae)
+            && any(&self.sop_class, &facts.sop_class)
+            && self.tags.iter().all(|(key, expected)| {
+                parse_tag(key)
+                    .ok()
+                    .and_then(|tag| facts.tags.get(&tag))
+                    .is_some_and(|actual| actual == expected)
+            })
+    }
+}
+
+/// What happened to one rule for one instance.
+#[derive(Debug)]
+pub struct RouteOutcome {
+    pub rule: String,
+    pub on_failure: FailurePolicy,
+    /// The instance was held until its study stops growing rather than processed now.
+    pub held: bool,
+    pub result: Result<()>,
+}
+
+/// What happened to one coalescing rule for one study.
+#[derive(Debug)]
+pub struct StudyOutcome {
+    pub rule: String,
+    pub study_instance_uid: String,
+    pub instances: usize,
+    pub result: Result<()>,
+}
+
+/// Instances a coalescing rule holds for one study, copied to a directory of their own.
+#[derive(Debug)]
+struct PendingStudy {
+    rule: usize,
+    study_instance_uid: String,
+    dir: TempDir,
+    instances: Vec<(PathBuf, InstanceFacts)>,
+    last_arrival: Instant,
+}
+
+/// Evaluates every rule against each instance and runs the actions of all that match.
+#[derive(Debug)]
+pub struct Router {
+    config: RouterConfig,
+    extra_tags: Vec<Tag>,
+    pending: Mutex<Vec<PendingStudy>>,
+}
+
+impl Router {
+    /// Validate `config`: tag keys must parse and push actions must name a known node.
+    pub fn new(config: RouterConfig) -> Result<Self> {
+        let mut extra_tags = Vec::new();
+        for rule in &config.rules {
+            for key in rule.criteria.tags.keys() {
+                let tag = parse_tag(key).with_context(|| format!("In rule {}", rule.name))?;
+                if !extra_tags.contains(&tag) {
+                    extra_tags.push(tag);
+                }
+            }
+            for action in &rule.actions {
+                if let Action::Push { node } = action {
+                    if !config.nodes.contains_key(node) {
+                        bail!("Rule {} pushes to unknown node {}", rule.name, node);
+                    }
+                }
+            }
+        }
+        Ok(Self {
+            config,
+            extra_tags,
+            pending: Mutex::new(Vec::new()),
+        })
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read rules file {:?}", path))?;
+        let config: RouterConfig =
+            toml::from_str(&text).with_context(|| format!("Invalid rules file {:?}", path))?;
+        Self::new(config)
+    }
+
+    pub fn rules(&self) -> &[Rule] {
+        &self.config.rules
+    }
+
+    /// Whether any rule holds instances per study, needing [`Router::flush_idle`] calls.
+    pub fn coalesces(&self) -> bool {
+        self.config
+            .rules
+            .iter()
+            .any(|rule| rule.coalesce_secs.is_some())
+    }
+
+    /// Route one file. `station_ae` is the sender's AE title when the file came off the
+    /// network. Failing rules do not stop the others; each outcome is returned.
+    pub fn route_file(&self, path: &Path, station_ae: Option<&str>) -> Result<Vec<RouteOutcome>> {
+        let obj = OpenFileOptions::new()
+            .read_until(PIXEL_DATA)
+            .open_file(path)
+            .with_context(|| format!("Failed to open {:?}", path))?;
+        let facts = InstanceFacts::read(&obj, station_ae, &self.extra_tags);
+        Ok(self
+            .config
+            .rules
+            .iter()
+            .enumerate()
+            .filter(|(_, rule)| rule.criteria.matches(&facts))
+            .map(|(index, rule)| {
+                let held = rule.coalesce_secs.is_some();
+                let result = if held {
+                    self.hold(index, path, &facts)
+                } else {
+                    self.run_actions(rule, &[(path.to_path_buf(), facts.clone())], None)
+                };
+                RouteOutcome {
+                    rule: rule.name.clone(),
+                    on_failure: rule.on_failure,
+                    held,
+                    result: result
+                        .with_context(|| format!("Rule {} failed for {:?}", rule.name, path)),
+                }
+            })
+            .collect())
+    }
+
+    /// Copy an instance into the pending study of a coalescing rule, restarting its wait.
+    fn hold(&self, rule: usize, path: &Path, facts: &InstanceFacts) -> Result<()> {
+        if facts.study_instance.is_empty() {
+            bail!("Instance has no Study Instance UID to coalesce on");
+        }
+        let mut pending = self.pending.lock().expect("pending studies lock");
+        let position = pending.iter().position(|study| {
+            study.rule == rule && study.study_instance_uid == facts.study_instance
+        });
+        let study = match position {
+            Some(position) => &mut pending[position],
+            None => {
+                pending.push(PendingStudy {
+                    rule,
+                    study_instance_uid: facts.study_instance.clone(),
+                    dir: scratch_dir()?,
+                    instances: Vec::new(),
+                    last_arrival: Instant::now(),
+                });
+                pending.last_mut().expect("just pushed")
+            }
+        };
+        let name = if facts.sop_instance.is_empty() {
+            format!("held-{}.dcm", study.instances.len())
+        } else {
+            format!("{}.dcm", facts.sop_instance)
+        };
+        let held = study.dir.path().join(name);
+        fs::copy(path, &held).with_context(|| format!("Failed to hold {:?}", path))?;
+        // A resent instance replaces the held copy.
+        study.instances.retain(|(known, _)| *known != held);
+        study.instances.push((held, facts.clone()));
+        study.last_arrival = Instant::now();
+        Ok(())
+    }
+
+    /// Run the actions of every held study that has received nothing for its rule's
+    /// `coalesce_secs`. Instances of a study arriving while it runs start a new one.
+    pub fn flush_idle(&self) -> Vec<StudyOutcome> {
+        self.flush(|study| {
+            let quiet = Duration::from_secs(
+                self.config.rules[study.rule]
+                    .coalesce_secs
+                    .unwrap_or_default(),
+            );
+            study.last_arrival.elapsed() >= quiet
+        })
+    }
+
+    /// Run the actions of every held study now, e.g. once all input files were routed.
+    pub fn flush_all(&self) -> Vec<StudyOutcome> {
+        self.flush(|_| true)
+    }
+
+    fn flush(&self, due: impl Fn(&PendingStudy) -> bool) -> Vec<StudyOutcome> {
+        let ready: Vec<PendingStudy> = {
+            let mut pending = self.pending.lock().expect("pending studies lock");
+            let (ready, waiting) = pending.drain(..).partition(|study| due(study));
+            *pending = waiting;
+            ready
+        };
+        ready
+            .into_iter()
+            .map(|study| {
+                let rule = &self.config.rules[study.rule];
+                StudyOutcome {
+                    rule: rule.name.clone(),
+                    study_instance_uid: study.study_instance_uid.clone(),
+                    instances: study.instances.len(),
+                    result: self
+                        .run_actions(rule, &study.instances, Some(study.dir.path()))
+                        .with_context(|| {
+                            format!(
+                                "Rule {} failed for study {}",
+                                rule.name, study.study_instance_uid
+                            )
+                        }),
+                }
+            })
+            .collect()
+    }
+
+    /// Route an instance held in memory, such as one received by the SCP.
+    pub fn route_object(
+        &self,
+        obj: &DefaultDicomObject,
+        station_ae: Option<&str>,
+    ) -> Result<Vec<RouteOutcome>> {
+        let scratch = scratch_dir()?;
+        let path = scratch.path().join("received.dcm");
+        obj.write_to_file(&path)
+            .context("Failed to write received instance")?;
+        self.route_file(&path, station_ae)
+    }
+
+    /// Route every file under `inputs` (files or directories, walked recursively), printing
+    /// each outcome. Files that are not DICOM are skipped. Returns how many rules failed.
+    pub fn route_paths(&self, inputs: &[PathBuf]) -> usize {
+        let mut failures = 0;
+        let files = inputs.iter().flat_map(|input| {
+            WalkDir::new(input)
+                .into_iter()
+                .filter_map(|entry| entry.ok())
+                .filter(|entry| entry.file_type().is_file())
+                .map(|entry| entry.into_path())
+        });
+        for file in files {
+            let outcomes = match self.route_file(&file, None) {
+                Ok(outcomes) => outcomes,
+                Err(_) => continue,
+            };
+            if outcomes.is_empty() {
+                println!("{:?}: no rule matched", file);
+            }
+            for outcome in outcomes {
+                match outcome.result {
+                    Ok(()) if outcome.held => {
+                        println!("{:?}: held for its study by {}", file, outcome.rule)
+                    }
+                    Ok(()) => println!("{:?}: routed via {}", file, outcome.rule),
+                    Err(err) => {
+                        eprintln!("{:#}", err);
+                        failures += 1;
+                    }
+                }
+            }
+        }
+        // Every input has been seen, so the held studies are complete.
+        for outcome in self.flush_all() {
+            if !report_study(outcome) {
+                failures += 1;
+            }
+        }
+        failures
+    }
+
+    /// Run the actions of `rule` on a batch of instances, one step at a time for all of
+    /// them, so that a failure stops the whole batch. `dir`, when given, holds exactly the
+    /// batch, letting a push send it over one association.
+    fn run_actions(
+        &self,
+        rule: &Rule,
+        batch: &[(PathBuf, InstanceFacts)],
+        dir: Option<&Path>,
+    ) -> Result<()> {
+        let scratch = scratch_dir()?;
+        let mut current: Vec<PathBuf> = batch.iter().map(|(path, _)| path.clone()).collect();
+        let mut current_dir = dir.map(Path::to_path_buf);
+        for (step, action) in rule.actions.iter().enumerate() {
+            let step_dir = scratch.path().join(format!("step-{}", step));
+            let next = |index: usize| step_dir.join(format!("{}.dcm", index));
+            match action {
+                Action::Validate => {
+                    for path in &current {
+                        let obj = open_file(path)
+                            .with_context(|| format!("Failed to open {:?}", path))?;
+                        let report = validate::validate_obj(&obj);
+                        if !report.valid {
+                            bail!(
+                                "Validation failed: {}",
+                                report
+                                    .missing_tags
+                                    .iter()
+                                    .map(|tag| format!("missing {}", tag))
+                                    .chain(report.conditional_violations.iter().cloned())
+                                    .collect::<Vec<_>>()
+                                    .join("; ")
+                            );
+                        }
+                    }
+                }
+                Action::Anonymize => {
+                    fs::create_dir_all(&step_dir)?;
+                    for (index, path) in current.iter_mut().enumerate() {
+                        anonymize::anonymize_file(path, &next(index))?;
+                        *path = next(index);
+                    }
+                    current_dir = Some(step_dir.clone());
+                }
+                Action::Transcode { syntax } => {
+                    fs::create_dir_all(&step_dir)?;
+                    for (index, path) in current.iter_mut().enumerate() {
+                        transcode::transcode(path, &next(index), (*syntax).into())?;
+                        *path = next(index);
+                    }
+                    current_dir = Some(step_dir.clone());
+                }
+                Action::Index { file } => {
+                    for (path, (_, facts)) in current.iter().zip(batch) {
+                        append_index_entry(file, path, facts)?;
+                    }
+                }
+                Action::Push { node } => {
+                    let addr = &self.config.nodes[node];
+                    match &current_dir {
+                        Some(dir) if current.len() > 1 => {
+                            let summary = scu::push_dir(
+                                addr,
+                                dir,
+                                &scu::PushDirOptions {
+                                    association: Default::default(),
+                                    rate_limit: None,
+                                    window: None,
+                                    audit: None,
+                                    queue: None,
+                                },
+                                &NoProgress,
+                                None,
+                            )?;
+                            if !summary.failed.is_empty() {
+                                bail!(
+                                    "{} of {} instance(s) were not accepted by {}",
+                                    summary.failed.len(),
+                                    current.len(),
+                                    node
+                                );
+                            }
+                        }
+                        _ => {
+                            for path in &current {
+                                scu::push(addr, path)?;
+                            }
+                        }
+                    }
+                }
+                Action::Store { dir } => {
+                    fs::create_dir_all(dir)
+                        .with_context(|| format!("Failed to create {:?}", dir))?;
+                    for (path, (_, facts)) in current.iter().zip(batch) {
+                        let name = if facts.sop_instance.is_empty() {
+                            path.file_name().map(PathBuf::from).unwrap_or_default()
+                        } else {
+                            PathBuf::from(format!("{}.dcm", facts.sop_instance))
+                        };
+                        fs::copy(path, dir.join(&name))
+                            .with_context(|| format!("Failed to store {:?} in {:?}", name, dir))?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Print the outcome of a coalesced study; returns whether it succeeded.
+pub fn report_study(outcome: StudyOutcome) -> bool {
+    match outcome.result {
+        Ok(()) => {
+            println!(
+                "Study {}: {} instance(s) routed via {}",
+                outcome.study_instance_uid, outcome.instances, outcome.rule
+            );
+            true
+        }
+        Err(err) => {
+            eprintln!("{:#}", err);
+            false
+        }
+    }
+}
+
+/// Run held studies once they go quiet, on a background thread living as long as the process.
+pub fn spawn_flusher(router: Arc<Router>) {
+    thread::spawn(move || loop {
+        thread::sleep(FLUSH_INTERVAL);
+        for outcome in router.flush_idle() {
+            report_study(outcome);
+        }
+    });
+}
+
+/// One line of an `index` action's file.
+#[derive(Debug, Serialize)]
+struct IndexEntry<'a> {
+    indexed_at: String,
+    station_ae: &'a str,
+    modality: &'a str,
+    patient_id: String,
+    study_instance_uid: String,
+    series_instance_uid: String,
+    sop_class_uid: &'a str,
+    sop_instance_uid: String,
+}
+
+fn append_index_entry(index: &Path, current: &Path, facts: &InstanceFacts) -> Result<()> {
+    // Earlier steps may have anonymized the instance, so read the keys from it again.
+    let obj = OpenFileOptions::new()
+        .read_until(PIXEL_DATA)
+        .open_file(current)
+        .with_context(|| format!("Failed to open {:?}", current))?;
+    let text = |tag| clean(&obj.element_str(tag).unwrap_or_default());
+    let entry = IndexEntry {
+        indexed_at: Local::now().to_rfc3339(),
+        station_ae: &facts.station_ae,
+        modality: &facts.modality,
+        patient_id: text(PATIENT_ID),
+        study_instance_uid: text(STUDY_INSTANCE_UID),
+        series_instance_uid: text(SERIES_INSTANCE_UID),
+        sop_class_uid: &facts.sop_class,
+        sop_instance_uid: text(SOP_INSTANCE_UID),
+    };
+    let mut line = serde_json::to_string(&entry).context("Failed to serialize index entry")?;
+    line.push('\n');
+    if let Some(parent) = index.parent().filter(|p| !p.as_os_str().is_empty()) {
+        fs::create_dir_all(parent).with_context(|| format!("Failed to create {:?}", parent))?;
+    }
+    // One write per line, so entries from concurrent associations do not interleave.
+    fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(index)
+        .and_then(|mut file| file.write_all(line.as_bytes()))
+        .with_context(|| format!("Failed to append to index {:?}", index))
+}
+
+/// Per-rule working directory for intermediate outputs: owner-only, removed when dropped.
+fn scratch_dir() -> Result<TempDir> {
+    atomic_file::private_temp_dir("dicom-tools-route-")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RULES: &str = r#"
+        [nodes]
+        RESEARCH = "127.0.0.1:11112"
+
+        [[rules]]
+        name = "ct-research"
+        match = { modality = ["CT"], station_ae = ["CT01"], tags = { "0008,0070" = "ACME" } }
+        on_failure = "out-of-resources"
+        actions = [{ do = "validate" }, { do = "anonymize" }, { do = "push", node = "RESEARCH" }]
+
+        [[rules]]
+        name = "archive"
+        actions = [{ do = "transcode", syntax = "implicit-vr-little-endian" }, { do = "store", dir = "archive" }]
+    "#;
+
+    #[test]
+    fn rules_parse_and_match_on_tags() {
+        let router = Router::new(toml::from_str(RULES).expect("parse")).expect("valid");
+        assert_eq!(router.rules().len(), 2);
+        assert_eq!(router.rules()[0].on_failure, FailurePolicy::OutOfResources);
+        assert_eq!(router.rules()[1].on_failure, FailurePolicy::Reject);
+        let mut facts = InstanceFacts {
+            modality: "CT".into(),
+            station_ae: "CT01".into(),
+            sop_class: "1.2.840.10008.5.1.4.1.1.2".into(),
+            sop_instance: "1.2.3".into(),
+            study_instance: "1.2".into(),
+            tags: HashMap::from([(Tag(0x0008, 0x0070), "ACME".to_string())]),
+        };
+        let matching = |facts: &InstanceFacts| {
+            router
+                .rules()
+                .iter()
+                .filter(|rule| rule.criteria.matches(facts))
+                .map(|rule| rule.name.as_str())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(matching(&facts), ["ct-research", "archive"]);
+        facts.station_ae = "MR02".into();
+        assert_eq!(matching(&facts), ["archive"]);
+    }
+
+    #[test]
+    fn coalescing_rules_hold_instances_until_the_study_is_flushed() {
+        let dir = scratch_dir().unwrap();
+        let spec = crate::synth::SynthSpec {
+            instances: 3,
+            ..crate::synth::SynthSpec::default()
+        };
+        let files = crate::synth::write_series(&spec, &dir.path().join("in")).unwrap();
+        let archive = dir.path().join("archive");
+        let config = toml::from_str(&format!(
+            "[[rules]]\nname = 'study'\ncoalesce_secs = 3600\nactions = [{{ do = 'store', dir = {:?} }}]",
+            archive
+        ))
+        .unwrap();
+        let router = Router::new(config).unwrap();
+        assert!(router.coalesces());
+        for file in &files {
+            let outcomes = router.route_file(file, Some("CT01")).unwrap();
+            assert!(outcomes[0].held && outcomes[0].result.is_ok());
+        }
+        // Resending an instance does not duplicate it.
+        router.route_file(&files[0], Some("CT01")).unwrap();
+        assert!(!archive.exists());
+        assert!(router.flush_idle().is_empty());
+
+        let outcomes = router.flush_all();
+        assert_eq!(outcomes.len(), 1);
+        assert_eq!(outcomes[0].instances, 3);
+        assert!(outcomes[0].result.is_ok());
+        assert_eq!(fs::read_dir(&archive).unwrap().count(), 3);
+        assert!(router.flush_all().is_empty());
+    }
+
+    #[test]
+    fn unknown_push_nodes_are_rejected() {
+        let config: RouterConfig = toml::from_str(
+            r#"
+            [[rules]]
+            name = "lost"
+            actions = [{ do = "push", node = "NOWHERE" }]
+            "#,
+        )
+        .expect("parse");
+        assert!(Router::new(config).is_err());
+        assert!(toml::from_str::<RouterConfig>(
+            "[[rules]]\nname = 'x'\nactions = [{ do = 'burn' }]"
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,1180 @@
+//
+// scp.rs
+// Dicom-Tools-rs
+//
+// Query/retrieve and storage service class provider: serves a directory of DICOM files over C-ECHO, Study Root C-FIND, C-MOVE and C-GET, saves and routes incoming C-STOREs when a store or rules are configured, answers modality worklist C-FINDs when a worklist is given, and keeps performed procedure steps received with N-CREATE/N-SET when an MPPS store is set.
+//
+// Thales Matheus Mendonça Santos - November 2025
+
+use std::collections::HashMap;
+use std::fs;
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Context, Result};
+use dicom::core::{DataElement, PrimitiveValue, Tag, VR};
+use dicom::object::{open_file, FileMetaTableBuilder, InMemDicomObject, OpenFileOptions};
+use dicom_ul::pdu::reader::MAXIMUM_PDU_SIZE;
+use dicom_ul::pdu::{AssociationRQ, Pdu, PresentationContextResultReason};
+use dicom_ul::{read_pdu, ClientAssociationOptions, ServerAssociation, ServerAssociationOptions};
+use rayon::iter::Either;
+use rayon::prelude::*;
+use walkdir::WalkDir;
+
+use crate::audit::{AuditEvent, AuditLog, AuditOutcome, AuditedStudies};
+use crate::derivation::new_instance_uid;
+use crate::dicom_access::ElementAccess;
+use crate::dimse::{
+    self, command, status, DimseChannel, DimseMessage, MoveOriginator, AFFECTED_SOP_CLASS_UID,
+    AFFECTED_SOP_INSTANCE_UID, COMPLETED_SUBOPERATIONS, FAILED_SUBOPERATIONS, MOVE_DESTINATION,
+    REMAINING_SUBOPERATIONS, REQUESTED_SOP_CLASS_UID, REQUESTED_SOP_INSTANCE_UID,
+    WARNING_SUBOPERATIONS,
+};
+use crate::dimse_trace::{DimseTracer, TracedChannel};
+use crate::mpps::{MppsRefusal, MppsStore, StepStatus, MODALITY_PERFORMED_PROCEDURE_STEP};
+use crate::router::{self, FailurePolicy, Router};
+use crate::scu::STUDY_ROOT_FIND;
+use crate::storage::{FileStore, QuotaError};
+use crate::study_query;
+use crate::transcode::{self, LossyOptions};
+use crate::ts_preference::TransferSyntaxPreference;
+use crate::worklist::{WorklistSource, MODALITY_WORKLIST_FIND};
+
+const SOP_CLASS_UID: Tag = Tag(0x0008, 0x0016);
+const SOP_INSTANCE_UID: Tag = Tag(0x0008, 0x0018);
+const FAILED_SOP_INSTANCE_UID_LIST: Tag = Tag(0x0008, 0x0058);
+const PATIENT_ID: Tag = Tag(0x0010, 0x0020);
+const STUDY_INSTANCE_UID: Tag = Tag(0x0020, 0x000D);
+const SERIES_INSTANCE_UID: Tag = Tag(0x0020, 0x000E);
+const PIXEL_DATA: Tag = Tag(0x7FE0, 0x0010);
+
+/// One retrievable instance and the keys retrieve requests can select it by.
+#[derive(Debug, Clone)]
+pub struct IndexedInstance {
+    pub path: PathBuf,
+    pub patient_id: String,
+    pub study_instance_uid: String,
+    pub series_instance_uid: String,
+    pub sop_instance_uid: String,
+    pub sop_class_uid: String,
+    pub transfer_syntax: String,
+}
+
+impl IndexedInstance {
+    /// Header keys of the DICOM file at `path`; `None` when it is unreadable or has no SOP
+    /// Instance UID.
+    pub fn read(path: &Path) -> Option<Self> {
+        let obj = OpenFileOptions::new()
+            .read_until(PIXEL_DATA)
+            .open_file(path)
+            .ok()?;
+        let text = |tag| {
+            obj.element_str(tag)
+                .map(|s| s.trim_end_matches(['\0', ' ']).to_string())
+                .unwrap_or_default()
+        };
+        let instance = IndexedInstance {
+            patient_id: text(PATIENT_ID),
+            study_instance_uid: text(STUDY_INSTANCE_UID),
+            series_instance_uid: text(SERIES_INSTANCE_UID),
+            sop_instance_uid: text(SOP_INSTANCE_UID),
+            sop_class_uid: text(SOP_CLASS_UID),
+            transfer_syntax: obj
+                .meta()
+                .transfer_syntax()
+                .trim_end_matches('\0')
+                .to_string(),
+            path: path.to_path_buf(),
+        };
+        (!instance.sop_instance_uid.is_empty()).then_some(instance)
+    }
+
+    fn key(&self, tag: Tag) -> &str {
+        match tag {
+            PATIENT_ID => &self.patient_id,
+            STUDY_INSTANCE_UID => &self.study_instance_uid,
+            SERIES_INSTANCE_UID => &self.series_instance_uid,
+            SOP_INSTANCE_UID => &self.sop_instance_uid,
+            _ => "",
+        }
+    }
+}
+
+/// Header-level index of the DICOM files under a directory.
+#[derive(Debug, Default)]
+pub struct InstanceIndex {
+    instances: Vec<IndexedInstance>,
+    skipped: Vec<PathBuf>,
+}
+
+impl InstanceIndex {
+    /// Index every readable DICOM file under `dir`; other files are skipped.
+    pub fn scan(dir: &Path) -> Result<Self> {
+        if !dir.is_dir() {
+            bail!("{:?} is not a directory", dir);
+        }
+        let paths: Vec<PathBuf> = WalkDir::new(dir)
+            .into_iter()
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_type().is_file())
+            .map(|entry| entry.into_path())
+            .collect();
+        let (instances, mut skipped): (Vec<_>, Vec<_>) = paths
+            .into_par_iter()
+            .map(|path| IndexedInstance::read(&path).ok_or(path))
+            .partition_map(|read| match read {
+                Ok(instance) => Either::Left(instance),
+                Err(path) => Either::Right(path),
+            });
+        skipped.sort();
+        Ok(Self { instances, skipped })
+    }
+
+    pub fn instances(&self) -> &[IndexedInstance] {
+        &self.instances
+    }
+
+    /// Files under the directory that are not DICOM instances.
+    pub fn skipped(&self) -> &[PathBuf] {
+        &self.skipped
+    }
+
+    pub fn len(&self) -> usize {
+        self.instances.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.instances.is_empty()
+    }
+
+    /// Instances selected by the unique keys of a retrieve identifier. Each key may hold a
+    /// backslash-separated list of values; an identifier without any key selects nothing.
+    pub fn matching(&self, identifier: &InMemDicomObject) -> Vec<IndexedInstance> {
+        let keys: Vec<(Tag, Vec<String>)> = [
+            PATIENT_ID,
+            STUDY_INSTANCE_UID,
+            SERIES_INSTANCE_UID,
+            SOP_INSTANCE_UID,
+        ]
+        .into_iter()
+        .filter_map(|tag| {
+            let values: Vec<String> = identifier
+                .element_str(tag)?
+                .split('\\')
+                .map(|v| v.trim_end_matches(['\0', ' ']).trim().to_string())
+                .filter(|v| !v.is_empty())
+                .collect();
+            (!values.is_empty()).then_some((tag, values))
+        })
+        .collect();
+        if keys.is_empty() {
+            return Vec::new();
+        }
+        self.instances
+            .iter()
+            .filter(|instance| {
+                keys.iter()
+                    .all(|(tag, values)| values.iter().any(|v| v == instance.key(*tag)))
+            })
+            .cloned()
+            .collect()
+    }
+}
+
+/// Move destinations: AE title to `host:port`.
+#[derive(Debug, Clone, Default)]
+pub struct AeMap {
+    entries: HashMap<String, String>,
+}
+
+impl AeMap {
+    /// Parse one `AE=host:port` entry.
+    pub fn insert_entry(&mut self, entry: &str) -> Result<()> {
+        let (ae, address) = entry
+            .split_once('=')
+            .with_context(|| format!("Expected AE=host:port, got '{}'", entry))?;
+        let (ae, address) = (ae.trim(), address.trim());
+        if ae.is_empty() || ae.len() > 16 || !address.contains(':') {
+            bail!("Invalid AE map entry '{}'", entry);
+        }
+        self.entries.insert(ae.to_string(), address.to_string());
+        Ok(())
+    }
+
+    /// Load `AE=host:port` lines from a file; blank lines and `#` comments are ignored.
+    pub fn load(&mut self, path: &Path) -> Result<()> {
+        let text =
+            fs::read_to_string(path).with_context(|| format!("Failed to read {:?}", path))?;
+        text.lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .try_for_each(|line| self.insert_entry(line))
+    }
+
+    pub fn address(&self, ae_title: &str) -> Option<&str> {
+        self.entries.get(ae_title.trim()).map(String::as_str)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+/// Settings of a [`RetrieveScp`].
+#[derive(Debug, Clone)]
+pub struct ScpConfig {
+    pub ae_title: String,
+    /// Directory whose files are served (e.g. the web upload store).
+    pub root: PathBuf,
+    pub destinations: AeMap,
+    pub trace: Option<Arc<DimseTracer>>,
+    /// When set, incoming C-STOREs are accepted and saved to this store.
+    pub store: Option<FileStore>,
+    /// When set, incoming C-STOREs are accepted and handed to the router.
+    pub router: Option<Arc<Router>>,
+    /// When set, modality worklist C-FINDs are answered from this source.
+    pub worklist: Option<WorklistSource>,
+    /// When set, Modality Performed Procedure Step N-CREATEs and N-SETs are kept in this store.
+    pub mpps: Option<Arc<MppsStore>>,
+    /// When set, received instances and C-MOVE/C-GET deliveries are recorded as audit events.
+    pub audit: Option<Arc<AuditLog>>,
+}
+
+/// A listening retrieve SCP; each association is served on its own thread.
+pub struct RetrieveScp {
+    listener: TcpListener,
+    config: Arc<ScpConfig>,
+}
+
+impl RetrieveScp {
+    pub fn bind(addr: &str, config: ScpConfig) -> Result<Self> {
+        let listener =
+            TcpListener::bind(addr).with_context(|| format!("Failed to listen on {}", addr))?;
+        Ok(Self {
+            listener,
+            config: Arc::new(config),
+        })
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    /// Accept associations forever.
+    pub fn serve(self) -> Result<()> {
+        if let Some(router) = self.config.router.as_ref().filter(|r| r.coalesces()) {
+            router::spawn_flusher(Arc::clone(router));
+        }
+        for stream in self.listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(err) => {
+                    eprintln!("Failed to accept connection: {}", err);
+                    continue;
+                }
+            };
+            let config = Arc::clone(&self.config);
+            thread::spawn(move || {
+                let peer = stream.peer_addr().ok();
+                if let Err(err) = handle_association(stream, &config) {
+                    eprintln!("Association with {:?} failed: {:#}", peer, err);
+                }
+            });
+        }
+        Ok(())
+    }
+}
+
+/// CLI helper: serve `config.root` on `addr` until interrupted.
+pub fn run(addr: &str, config: ScpConfig) -> Result<()> {
+    let indexed = InstanceIndex::scan(&config.root)?.len();
+    let scp = RetrieveScp::bind(addr, config)?;
+    println!(
+        "Retrieve SCP {} listening on {} ({} instances under {:?}, {} move destinations)",
+        scp.config.ae_title,
+        scp.local_addr()?,
+        indexed,
+        scp.config.root,
+        scp.config.destinations.len()
+    );
+    if let Some(store) = &scp.config.store {
+        println!("Saving received instances to {}", store.describe());
+    }
+    scp.serve()
+}
+
+fn handle_association(stream: TcpStream, config: &ScpConfig) -> Result<()> {
+    // dicom-ul does not report which abstract syntax each accepted context carries, which
+    // C-GET needs to pick a storage context; read it from the request before accepting.
+    let request = peek_association_request(&stream)?;
+    if let (Some(tracer), Some(rq)) = (&config.trace, &request) {
+        tracer.note(&format!("-- connection from {:?}", stream.peer_addr().ok()));
+        tracer.association_request(rq);
+    }
+    let proposed: HashMap<u8, String> = request
+        .into_iter()
+        .flat_map(|rq| rq.presentation_contexts)
+        .map(|pc| (pc.id, pc.abstract_syntax.trim_end_matches('\0').to_string()))
+        .collect();
+    let association = ServerAssociationOptions::new()
+        .accept_any()
+        .ae_title(config.ae_title.as_str())
+        .promiscuous(true)
+        .establish(stream)
+        .context("Failed to negotiate association")?;
+    let mut association = TracedChannel::new(association, config.trace.clone());
+    let peer = association.get_ref().client_ae_title().trim().to_string();
+
+    while let Some(request) = dimse::read_message(&mut association)? {
+        match request.command_field() {
+            command::C_ECHO_RQ => {
+                let response = dimse::response_to(&request, status::SUCCESS, false);
+                dimse::send_message(&mut association, request.pc_id, response, None)?;
+            }
+            command::C_MOVE_RQ => handle_move(&mut association, &request, config)?,
+            command::C_GET_RQ => handle_get(&mut association, &request, config, &proposed)?,
+            command::C_STORE_RQ if config.store.is_some() || config.router.is_some() => {
+                handle_store(&mut association, &request, config, &peer)?
+            }
+            command::C_FIND_RQ => handle_find(&mut association, &request, config)?,
+            command::N_CREATE_RQ | command::N_SET_RQ if config.mpps.is_some() => {
+                handle_mpps(&mut association, &request, config)?
+            }
+            // A cancel for an operation that already finished needs no answer.
+            command::C_CANCEL_RQ => {}
+            _ => {
+                let response = dimse::response_to(&request, status::UNRECOGNIZED_OPERATION, false);
+                dimse::send_message(&mut association, request.pc_id, response, None)?;
+            }
+        }
+    }
+    association.into_inner();
+    println!("Association with {} released", peer);
+    Ok(())
+}
+
+/// Accept an instance, run it through the router and save it to the store. A failed save, or
+/// a failed rule whose policy rejects the instance, is reported to the sender so that it can
+/// retry; a rejected instance is not stored.
+fn handle_store(
+    association: &mut TracedChannel<ServerAssociation>,
+    request: &DimseMessage,
+    config: &ScpConfig,
+    peer: &str,
+) -> Result<()> {
+    let sop_instance = request
+        .command
+        .element_str(AFFECTED_SOP_INSTANCE_UID)
+        .unwrap_or_default();
+    let sop_instance = sop_instance.trim_end_matches('\0');
+
+    let mut received = AuditedStudies::default();
+    let outcome = receive_instance(association, request, config, peer, &mut received);
+    let code = match outcome {
+        Ok(None) => status::SUCCESS,
+        Ok(Some(policy)) => {
+            eprintln!("Rejected {} from {}", sop_instance, peer);
+            rejection_status(policy)
+        }
+        Err(err) => {
+            eprintln!("Failed to accept {} from {}: {:#}", sop_instance, peer, err);
+            if err.downcast_ref::<QuotaError>().is_some() {
+                status::OUT_OF_RESOURCES
+            } else {
+                status::UNABLE_TO_PROCESS
+            }
+        }
+    };
+    if let Some(audit) = &config.audit {
+        let outcome = if code == status::SUCCESS {
+            AuditOutcome::Success
+        } else {
+            AuditOutcome::SeriousFailure
+        };
+        audit.record(&AuditEvent::import(
+            peer,
+            &peer_host(association),
+            &config.ae_title,
+            received,
+            outcome,
+        ));
+    }
+    let mut response = dimse::response_to(request, code, false);
+    response.put(DataElement::new(
+        AFFECTED_SOP_INSTANCE_UID,
+        VR::UI,
+        PrimitiveValue::from(sop_instance),
+    ));
+    dimse::send_message(association, request.pc_id, response, None)
+}
+
+/// IP address of the node at the other end of the association.
+fn peer_host(association: &mut TracedChannel<ServerAssociation>) -> String {
+    association
+        .get_mut()
+        .inner_stream()
+        .peer_addr()
+        .map(|addr| addr.ip().to_string())
+        .unwrap_or_default()
+}
+
+/// C-STORE status for an instance rejected under `policy`.
+fn rejection_status(policy: FailurePolicy) -> u16 {
+    match policy {
+        FailurePolicy::OutOfResources => status::OUT_OF_RESOURCES,
+        FailurePolicy::Reject | FailurePolicy::Ignore => status::UNABLE_TO_PROCESS,
+    }
+}
+
+/// Returns the policy of the first failed rule that rejects the instance, if any. The
+/// instance is added to `received` once decoded, for the audit trail.
+fn receive_instance(
+    association: &TracedChannel<ServerAssociation>,
+    request: &DimseMessage,
+    config: &ScpConfig,
+    peer: &str,
+    received: &mut AuditedStudies,
+) -> Result<Option<FailurePolicy>> {
+    let dataset = request
+        .dataset(association)?
+        .context("C-STORE-RQ carried no data set")?;
+    let ts = dimse::negotiated_ts(association, request.pc_id)?;
+    let sop_class = request
+        .command
+        .element_str(AFFECTED_SOP_CLASS_UID)
+        .unwrap_or_default();
+    let obj = dataset
+        .with_meta(
+            FileMetaTableBuilder::new()
+                .transfer_syntax(ts.uid())
+                .media_storage_sop_class_uid(sop_class.trim_end_matches('\0'))
+                .source_application_entity_title(peer),
+        )
+        .context("Failed to build file meta for received instance")?;
+    received.add_object(&obj);
+
+    if let Some(router) = &config.router {
+        let outcomes = router.route_object(&obj, Some(peer))?;
+        if outcomes.is_empty() {
+            println!("No routing rule matched the instance from {}", peer);
+        }
+        let mut rejected = None;
+        for outcome in outcomes {
+            match outcome.result {
+                Ok(()) if outcome.held => {
+                    println!("Held for its study by rule {}", outcome.rule)
+                }
+                Ok(()) => println!("Routed via rule {}", outcome.rule),
+                Err(err) => {
+                    eprintln!("{:#}", err);
+                    if outcome.on_failure != FailurePolicy::Ignore {
+                        rejected = rejected.or(Some(outcome.on_failure));
+                    }
+                }
+            }
+        }
+        if rejected.is_some() {
+            return Ok(rejected);
+        }
+    }
+    if let Some(store) = &config.store {
+        let mut bytes = Vec::new();
+        obj.write_all(&mut bytes)
+            .context("Failed to encode received instance")?;
+        // The store keeps no dots in names; keep the UID readable with underscores.
+        let sop_instance = obj
+            .meta()
+            .media_storage_sop_instance_uid
+            .trim_end_matches('\0');
+        let original_name = format!("{}.dcm", sop_instance.replace('.', "_"));
+        let name = store.save(Some(&original_name), &bytes)?;
+        println!("Stored {} from {}", name, peer);
+    }
+    Ok(None)
+}
+
+/// Read the A-ASSOCIATE-RQ without consuming it, so that dicom-ul can still negotiate.
+fn peek_association_request(stream: &TcpStream) -> Result<Option<AssociationRQ>> {
+    let mut header = [0u8; 6];
+    peek_exact(stream, &mut header)?;
+    let length = u32::from_be_bytes([header[2], header[3], header[4], header[5]]) as usize;
+    if length > MAXIMUM_PDU_SIZE as usize {
+        bail!("Association request of {} bytes is too large", length);
+    }
+    let mut pdu = vec![0u8; length + header.len()];
+    peek_exact(stream, &mut pdu)?;
+    Ok(
+        match read_pdu(&mut pdu.as_slice(), MAXIMUM_PDU_SIZE, false)? {
+            Pdu::AssociationRQ(rq) => Some(rq),
+            _ => None,
+        },
+    )
+}
+
+fn peek_exact(stream: &TcpStream, buf: &mut [u8]) -> Result<()> {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    loop {
+        let n = stream.peek(buf)?;
+        if n == buf.len() {
+            return Ok(());
+        }
+        if n == 0 {
+            bail!("Connection closed before association request");
+        }
+        if Instant::now() > deadline {
+            bail!("Timed out waiting for association request");
+        }
+        thread::sleep(Duration::from_millis(5));
+    }
+}
+
+/// Sub-operation bookkeeping shared by C-MOVE and C-GET.
+#[derive(Default)]
+struct SubOperations {
+    remaining: u16,
+    completed: u16,
+    failed: u16,
+    warning: u16,
+    failed_uids: Vec<String>,
+    /// Every instance a sub-operation was attempted for.
+    audited: AuditedStudies,
+}
+
+impl SubOperations {
+    fn new(total: usize) -> Self {
+        Self {
+            remaining: total.min(u16::MAX as usize) as u16,
+            ..Self::default()
+        }
+    }
+
+    fn record(&mut self, instance: &IndexedInstance, outcome: Result<u16>) {
+        self.remaining = self.remaining.saturating_sub(1);
+        self.audited.add_instance(instance);
+        match outcome {
+            Ok(status::SUCCESS) => self.completed = self.completed.saturating_add(1),
+            // 0xB000, 0xB006, 0xB007: stored with coercion or element discards.
+            Ok(code) if code & 0xF000 == 0xB000 => self.warning = self.warning.saturating_add(1),
+            outcome => {
+                if let Err(err) = outcome {
+                    eprintln!(
+                        "Sub-operation for {} failed: {:#}",
+                        instance.sop_instance_uid, err
+                    );
+                }
+                self.failed = self.failed.saturating_add(1);
+                self.failed_uids.push(instance.sop_instance_uid.clone());
+            }
+        }
+    }
+
+    fn audit_outcome(&self) -> AuditOutcome {
+        if self.failed == 0 {
+            AuditOutcome::Success
+        } else if self.completed == 0 && self.warning == 0 {
+            AuditOutcome::SeriousFailure
+        } else {
+            AuditOutcome::MinorFailure
+        }
+    }
+
+    /// Record the delivery of the attempted instances to `destination_ae` at `addr`.
+    fn audit_export(&self, config: &ScpConfig, destination_ae: &str, addr: &str) {
+        if let Some(audit) = &config.audit {
+            audit.record(&AuditEvent::export(
+                &config.ae_title,
+                destination_ae,
+                addr,
+                self.audited.clone(),
+                self.audit_outcome(),
+            ));
+        }
+    }
+
+    fn response(&self, request: &DimseMessage, code: u16, has_data: bool) -> InMemDicomObject {
+        let mut cmd = dimse::response_to(request, code, has_data);
+        if code == status::PENDING || code == status::CANCEL {
+            cmd.put(dimse::us(REMAINING_SUBOPERATIONS, self.remaining));
+        }
+        cmd.put(dimse::us(COMPLETED_SUBOPERATIONS, self.completed));
+        cmd.put(dimse::us(FAILED_SUBOPERATIONS, self.failed));
+        cmd.put(dimse::us(WARNING_SUBOPERATIONS, self.warning));
+        cmd
+    }
+
+    /// Send the final response, listing failed instances when there are any.
+    fn finish(
+        &self,
+        channel: &mut dyn DimseChannel,
+        request: &DimseMessage,
+        cancelled: bool,
+    ) -> Result<()> {
+        let code = if cancelled {
+            status::CANCEL
+        } else if self.failed > 0 || self.warning > 0 {
+            status::SUBOPERATIONS_FAILED
+        } else {
+            status::SUCCESS
+        };
+        let identifier = if self.failed_uids.is_empty() {
+            None
+        } else {
+            let mut obj = InMemDicomObject::new_empty();
+            obj.put(DataElement::new(
+                FAILED_SOP_INSTANCE_UID_LIST,
+                VR::UI,
+                PrimitiveValue::Strs(self.failed_uids.iter().cloned().collect()),
+            ));
+            let mut bytes = Vec::new();
+            obj.write_dataset_with_ts(&mut bytes, dimse::negotiated_ts(channel, request.pc_id)?)
+                .context("Failed to encode failed instance list")?;
+            Some(bytes)
+        };
+        let response = self.response(request, code, identifier.is_some());
+        dimse::send_message(channel, request.pc_id, response, identifier.as_deref())
+    }
+}
+
+/// Reply to a retrieve request that cannot start at all.
+fn refuse(channel: &mut dyn DimseChannel, request: &DimseMessage, code: u16) -> Result<()> {
+    let response = dimse::response_to(request, code, false);
+    dimse::send_message(channel, request.pc_id, response, None)
+}
+
+/// Identifier of a retrieve request, or `None` after refusing a malformed one.
+fn retrieve_matches(
+    channel: &mut dyn DimseChannel,
+    request: &DimseMessage,
+    config: &ScpConfig,
+) -> Result<Option<Vec<IndexedInstance>>> {
+    let identifier = match request.dataset(channel) {
+        Ok(Some(identifier)) => identifier,
+        _ => {
+            refuse(channel, request, status::UNABLE_TO_PROCESS)?;
+            return Ok(None);
+        }
+    };
+    // Rescan per request so files added since startup (e.g. new uploads) are served.
+    let index = InstanceIndex::scan(&config.root)?;
+    Ok(Some(index.matching(&identifier)))
+}
+
+/// Storage contexts for sending a set of instances: one per distinct SOP class and stored
+/// transfer syntax, with the native encodings offered as fallbacks. With a transfer syntax
+/// preference, each encoding that can be produced gets a context of its own instead, so the
+/// peer accepts or rejects them independently and the best accepted one is used.
+pub(crate) struct StorageProposal {
+    proposals: Vec<(String, String)>,
+    preference: TransferSyntaxPreference,
+}
+
+impl StorageProposal {
+    pub(crate) fn for_instances(instances: &[IndexedInstance]) -> Self {
+        let mut proposals: Vec<(String, String)> = Vec::new();
+        for instance in instances {
+            let key = (
+                instance.sop_class_uid.clone(),
+                instance.transfer_syntax.clone(),
+            );
+            if !proposals.contains(&key) {
+                proposals.push(key);
+            }
+        }
+        Self {
+            proposals,
+            preference: TransferSyntaxPreference::default(),
+        }
+    }
+
+    pub(crate) fn preferring(mut self, preference: &TransferSyntaxPreference) -> Self {
+        self.preference = preference.clone();
+        self
+    }
+
+    pub(crate) fn apply<'a>(
+        &'a self,
+        mut options: ClientAssociationOptions<'a>,
+    ) -> ClientAssociationOptions<'a> {
+        for (sop_class, syntaxes) in self.contexts() {
+            options = options.with_presentation_context(sop_class, syntaxes);
+        }
+        options
+    }
+
+    /// Proposed contexts, in the order dicom-ul numbers them 1, 3, 5...
+    pub(crate) fn contexts(&self) -> Vec<(String, Vec<String>)> {
+        let mut contexts: Vec<(String, Vec<String>)> = Vec::new();
+        for (sop_class, ts) in &self.proposals {
+            let candidates = self.preference.candidates(ts);
+            if self.preference.is_empty() {
+                contexts.push((sop_class.clone(), candidates));
+                continue;
+            }
+            for candidate in candidates {
+                let context = (sop_class.clone(), vec![candidate]);
+                if !contexts.contains(&context) {
+                    contexts.push(context);
+                }
+            }
+        }
+        contexts
+    }
+
+    /// Proposed contexts as the tracer lists them.
+    pub(crate) fn listed(&self) -> Vec<(String, Vec<String>)> {
+        self.contexts()
+            .into_iter()
+            .map(|(sop_class, mut syntaxes)| {
+                syntaxes.truncate(1);
+                (sop_class, syntaxes)
+            })
+            .collect()
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.contexts().len()
+    }
+
+    /// Encode `instance` for the best accepted context: its stored transfer syntax unless a
+    /// preference ranks others first. An encoding that fails (e.g. a lossy image refused
+    /// another lossy step) falls through to the next one. `None` when no context for its SOP
+    /// class was accepted.
+    pub(crate) fn encode(
+        &self,
+        channel: &dyn DimseChannel,
+        instance: &IndexedInstance,
+    ) -> Option<Result<(u8, Vec<u8>)>> {
+        let ranking = self.preference.candidates(&instance.transfer_syntax);
+        let mut accepted: Vec<(usize, u8, String)> = self
+            .contexts()
+            .iter()
+            .enumerate()
+            .filter(|(_, (abstract_syntax, _))| *abstract_syntax == instance.sop_class_uid)
+            .filter_map(|(i, _)| {
+                let id = (2 * i + 1) as u8;
+                let pc = channel.contexts().iter().find(|pc| {
+                    pc.id == id && pc.reason == PresentationContextResultReason::Acceptance
+                })?;
+                let ts = pc.transfer_syntax.trim_end_matches('\0').to_string();
+                let rank = ranking.iter().position(|uid| *uid == ts)?;
+                Some((rank, id, ts))
+            })
+            .collect();
+        accepted.sort();
+        let mut last_error = None;
+        for (_, id, ts) in accepted {
+            match encode_for_context(instance, &ts) {
+                Ok(data) => return Some(Ok((id, data))),
+                Err(err) => last_error = Some(err),
+            }
+        }
+        last_error.map(Err)
+    }
+}
+
+/// Encode an instance for a presentation context, converting between native encodings when
+/// the negotiated transfer syntax differs from the stored one, and transcoding the pixel data
+/// otherwise.
+pub(crate) fn encode_for_context(instance: &IndexedInstance, ts_uid: &str) -> Result<Vec<u8>> {
+    let source = dimse::transfer_syntax(&instance.transfer_syntax)?;
+    let target = dimse::transfer_syntax(ts_uid)?;
+    let mut obj =
+        open_file(&instance.path).with_context(|| format!("Failed to open {:?}", instance.path))?;
+    if source.uid() != target.uid() && !(source.is_codec_free() && target.is_codec_free()) {
+        transcode::transcode_object(&mut obj, target.uid(), &LossyOptions::default())
+            .with_context(|| {
+                format!(
+                    "Cannot convert {} from {} to {}",
+                    instance.sop_instance_uid,
+                    source.uid(),
+                    target.uid()
+                )
+            })?;
+    }
+    let mut bytes = Vec::new();
+    obj.write_dataset_with_ts(&mut bytes, target)
+        .context("Failed to encode data set")?;
+    Ok(bytes)
+}
+
+/// Accepted context for `instance` among `candidates` (id, abstract syntax), preferring its
+/// stored transfer syntax.
+fn context_for(
+    channel: &dyn DimseChannel,
+    candidates: impl Iterator<Item = (u8, String)>,
+    instance: &IndexedInstance,
+) -> Option<(u8, String)> {
+    let accepted: Vec<(u8, String)> = candidates
+        .filter(|(_, abstract_syntax)| *abstract_syntax == instance.sop_class_uid)
+        .filter_map(|(id, _)| {
+            channel
+                .contexts()
+                .iter()
+                .find(|pc| pc.id == id && pc.reason == PresentationContextResultReason::Acceptance)
+                .map(|pc| (id, pc.transfer_syntax.trim_end_matches('\0').to_string()))
+        })
+        .collect();
+    accepted
+        .iter()
+        .find(|(_, ts)| *ts == instance.transfer_syntax)
+        .or_else(|| accepted.first())
+        .cloned()
+}
+
+fn handle_move(
+    association: &mut TracedChannel<ServerAssociation>,
+    request: &DimseMessage,
+    config: &ScpConfig,
+) -> Result<()> {
+    let destination = request
+        .command
+        .element_str(MOVE_DESTINATION)
+        .unwrap_or_default()
+        .trim_end_matches('\0')
+        .trim()
+        .to_string();
+    let Some(address) = config.destinations.address(&destination) else {
+        eprintln!("C-MOVE to unknown destination '{}'", destination);
+        return refuse(association, request, status::MOVE_DESTINATION_UNKNOWN);
+    };
+    let Some(matches) = retrieve_matches(association, request, config)? else {
+        return Ok(());
+    };
+    let mut ops = SubOperations::new(matches.len());
+    if matches.is_empty() {
+        return ops.finish(association, request, false);
+    }
+
+    let proposal = StorageProposal::for_instances(&matches);
+    let options = proposal.apply(
+        ClientAssociationOptions::new()
+            .calling_ae_title(config.ae_title.as_str())
+            .called_ae_title(destination.as_str()),
+    );
+    if let Some(tracer) = &config.trace {
+        tracer.proposed_contexts(
+            &format!("{} at {}", destination, address),
+            &proposal.listed(),
+        );
+    }
+    let mut sub = match options.establish(address) {
+        Ok(sub) => TracedChannel::new(sub, config.trace.clone()),
+        Err(err) => {
+            eprintln!(
+                "Failed to associate with {} at {}: {}",
+                destination, address, err
+            );
+            for instance in &matches {
+                ops.record(instance, Err(anyhow::anyhow!("destination unreachable")));
+            }
+            ops.audit_export(config, &destination, address);
+            return ops.finish(association, request, false);
+        }
+    };
+
+    let originator = association.get_ref().client_ae_title().trim().to_string();
+    for (n, instance) in matches.iter().enumerate() {
+        let outcome = match proposal.encode(&sub, instance) {
+            Some(encoded) => encoded.and_then(|(pc_id, data)| {
+                dimse::store_instance(
+                    &mut sub,
+                    pc_id,
+                    (n + 1) as u16,
+                    &instance.sop_class_uid,
+                    &instance.sop_instance_uid,
+                    &data,
+                    Some(MoveOriginator {
+                        ae_title: &originator,
+                        message_id: request.message_id(),
+                    }),
+                    &mut |_| {},
+                )
+            }),
+            None => Err(anyhow::anyhow!(
+                "{} rejected SOP class {}",
+                destination,
+                instance.sop_class_uid
+            )),
+        };
+        ops.record(instance, outcome);
+        if ops.remaining > 0 {
+            let pending = ops.response(request, status::PENDING, false);
+            dimse::send_message(association, request.pc_id, pending, None)?;
+        }
+    }
+    let _ = sub.into_inner().release();
+    ops.audit_export(config, &destination, address);
+    ops.finish(association, request, false)
+}
+
+/// Answer a Study Root query from the served directory, or a worklist query when a worklist
+/// is configured.
+fn handle_find(
+    association: &mut TracedChannel<ServerAssociation>,
+    request: &DimseMessage,
+    config: &ScpConfig,
+) -> Result<()> {
+    let sop_class = request
+        .command
+        .element_str(AFFECTED_SOP_CLASS_UID)
+        .unwrap_or_default();
+    match sop_class.trim_end_matches('\0') {
+        STUDY_ROOT_FIND => handle_study_find(association, request, config),
+        MODALITY_WORKLIST_FIND if config.worklist.is_some() => {
+            handle_worklist_find(association, request, config)
+        }
+        _ => refuse(association, request, status::SOP_CLASS_NOT_SUPPORTED),
+    }
+}
+
+/// Answer a Study Root query with one pending response per matching study, series or
+/// instance under `config.root`.
+fn handle_study_find(
+    association: &mut TracedChannel<ServerAssociation>,
+    request: &DimseMessage,
+    config: &ScpConfig,
+) -> Result<()> {
+    let identifier = match request.dataset(association) {
+        Ok(Some(identifier)) => identifier,
+        _ => return refuse(association, request, status::UNABLE_TO_PROCESS),
+    };
+    // Rescan per query, like retrieves, so new uploads can be found.
+    let index = InstanceIndex::scan(&config.root)?;
+    let answers = match study_query::answer(&index, &identifier, &config.ae_title) {
+        Ok(answers) => answers,
+        Err(err) => {
+            eprintln!("Refused study query: {:#}", err);
+            return refuse(
+                association,
+                request,
+                status::IDENTIFIER_DOES_NOT_MATCH_SOP_CLASS,
+            );
+        }
+    };
+    println!(
+        "Study query matched {} entries over {} instances",
+        answers.len(),
+        index.len()
+    );
+    send_find_answers(association, request, answers)
+}
+
+/// One pending response per answer, then the final success.
+fn send_find_answers(
+    association: &mut TracedChannel<ServerAssociation>,
+    request: &DimseMessage,
+    answers: Vec<InMemDicomObject>,
+) -> Result<()> {
+    let ts = dimse::negotiated_ts(association, request.pc_id)?;
+    for answer in answers {
+        let mut bytes = Vec::new();
+        answer
+            .write_dataset_with_ts(&mut bytes, ts)
+            .context("Failed to encode C-FIND response")?;
+        let pending = dimse::response_to(request, status::PENDING, true);
+        dimse::send_message(association, request.pc_id, pending, Some(&bytes))?;
+    }
+    let done = dimse::response_to(request, status::SUCCESS, false);
+    dimse::send_message(association, request.pc_id, done, None)
+}
+
+/// Answer a worklist query with one pending response per matching scheduled step.
+fn handle_worklist_find(
+    association: &mut TracedChannel<ServerAssociation>,
+    request: &DimseMessage,
+    config: &ScpConfig,
+) -> Result<()> {
+    let identifier = match request.dataset(association) {
+        Ok(Some(identifier)) => identifier,
+        _ => return refuse(association, request, status::UNABLE_TO_PROCESS),
+    };
+    // Reload per query so edits to the worklist are picked up without a restart.
+    let source = config.worklist.as_ref().expect("checked by caller");
+    let worklist = match source.load() {
+        Ok(worklist) => worklist,
+        Err(err) => {
+            eprintln!("Failed to load worklist: {:#}", err);
+            return refuse(association, request, status::UNABLE_TO_PROCESS);
+        }
+    };
+    let answers = worklist.query(&identifier);
+    println!(
+        "Worklist query matched {} of {} items",
+        answers.len(),
+        worklist.len()
+    );
+    send_find_answers(association, request, answers)
+}
+
+/// Create or update a performed procedure step. The response names the affected instance,
+/// which for an N-CREATE without one is a UID assigned here.
+fn handle_mpps(
+    association: &mut TracedChannel<ServerAssociation>,
+    request: &DimseMessage,
+    config: &ScpConfig,
+) -> Result<()> {
+    let create = request.command_field() == command::N_CREATE_RQ;
+    let (class_tag, instance_tag) = if create {
+        (AFFECTED_SOP_CLASS_UID, AFFECTED_SOP_INSTANCE_UID)
+    } else {
+        (REQUESTED_SOP_CLASS_UID, REQUESTED_SOP_INSTANCE_UID)
+    };
+    let uid = |tag| {
+        request
+            .command
+            .element_str(tag)
+            .map(|v| v.trim_end_matches(['\0', ' ']).to_string())
+            .filter(|v| !v.is_empty())
+    };
+    if uid(class_tag).as_deref() != Some(MODALITY_PERFORMED_PROCEDURE_STEP) {
+        return refuse(association, request, status::SOP_CLASS_NOT_SUPPORTED);
+    }
+    let sop_instance = match uid(instance_tag) {
+        Some(sop_instance) => sop_instance,
+        None if create => new_instance_uid("mpps"),
+        None => return refuse(association, request, status::NO_SUCH_SOP_INSTANCE),
+    };
+    let attributes = match request.dataset(association) {
+        Ok(Some(attributes)) => attributes,
+        _ => return refuse(association, request, status::PROCESSING_FAILURE),
+    };
+    let store = config.mpps.as_ref().expect("checked by caller");
+    let outcome = if create {
+        store
+            .create(&sop_instance, attributes)
+            .map(|()| StepStatus::InProgress)
+    } else {
+        store.set(&sop_instance, attributes)
+    };
+    let code = match outcome {
+        Ok(step) => {
+            println!("Performed procedure step {} is {}", sop_instance, step);
+            status::SUCCESS
+        }
+        Err(refusal) => {
+            eprintln!("Refused MPPS update of {}: {}", sop_instance, refusal);
+            match refusal {
+                MppsRefusal::Duplicate => status::DUPLICATE_SOP_INSTANCE,
+                MppsRefusal::NoSuchInstance => status::NO_SUCH_SOP_INSTANCE,
+                MppsRefusal::InvalidStatus(_) => status::INVALID_ATTRIBUTE_VALUE,
+                MppsRefusal::AlreadyFinished(_) | MppsRefusal::Failed(_) => {
+                    status::PROCESSING_FAILURE
+                }
+            }
+        }
+    };
+    let mut response = dimse::response_to(request, code, false);
+    response.put(DataElement::new(
+        AFFECTED_SOP_INSTANCE_UID,
+        VR::UI,
+        PrimitiveValue::from(sop_instance),
+    ));
+    dimse::send_message(association, request.pc_id, response, None)
+}
+
+fn handle_get(
+    association: &mut TracedChannel<ServerAssociation>,
+    request: &DimseMessage,
+    config: &ScpConfig,
+    proposed: &HashMap<u8, String>,
+) -> Result<()> {
+    let Some(matches) = retrieve_matches(association, request, config)? else {
+        return Ok(());
+    };
+    let mut ops = SubOperations::new(matches.len());
+    let mut cancelled = false;
+    let mut message_id = request.message_id();
+
+    for instance in &matches {
+        let candidates = proposed.iter().map(|(id, syntax)| (*id, syntax.clone()));
+        let outcome = match context_for(association, candidates, instance) {
+            Some((pc_id, ts)) => encode_for_context(instance, &ts).and_then(|data| {
+                message_id = message_id.wrapping_add(1);
+                dimse::store_instance(
+                    association,
+                    pc_id,
+                    message_id,
+                    &instance.sop_class_uid,
+                    &instance.sop_instance_uid,
+                    &data,
+                    None,
+                    &mut |other| {
+                        if other.command_field() == command::C_CANCEL_RQ {
+                            cancelled = true;
+                        }
+                    },
+                )
+            }),
+            None => Err(anyhow::anyhow!(
+                "No storage context negotiated for SOP class {}",
+                instance.sop_class_uid
+            )),
+        };
+        ops.record(instance, outcome);
+        if cancelled {
+            break;
+        }
+        if ops.remaining > 0 {
+            let pending = ops.response(request, status::PENDING, false);
+            dimse::send_message(association, request.pc_id, pending, None)?;
+        }
+    }
+    let requestor = association.get_ref().client_ae_title().trim().to_string();
+    ops.audit_export(config, &requestor, &peer_host(association));
+    ops.finish(association, request, cancelled)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ae_map_parses_entries_and_files() {
+        let mut map = AeMap::default();
+        map.insert_entry("STORESCP = 10.0.0.5:104").expect("entry");
+        assert_eq!(map.address("STORESCP"), Some("10.0.0.5:104"));
+        assert!(map.insert_entry("NO_ADDRESS").is_err());
+        assert!(map.insert_entry("WAY_TOO_LONG_AE_TITLE=h:1").is_err());
+
+        let dir = tempfile::tempdir().expect("tmpdir");
+        let path = dir.path().join("aes.conf");
+        fs::write(&path, "# archive\nARCHIVE=pacs:11112\n\n").expect("write");
+        map.load(&path).expect("load");
+        assert_eq!(map.address("ARCHIVE"), Some("pacs:11112"));
+        assert_eq!(map.len(), 2);
+    }
+
+    #[test]
+    fn sub_operation_counts_saturate_past_u16() {
+        let instance = IndexedInstance {
+            path: PathBuf::from("ct.dcm"),
+            patient_id: "P1".into(),
+            study_instance_uid: "1.2.3".into(),
+            series_instance_uid: "1.2.3.4".into(),
+            sop_instance_uid: "1.2.3.4.5".into(),
+            sop_class_uid: "1.2.840.10008.5.1.4.1.1.2".into(),
+            transfer_syntax: "1.2.840.10008.1.2.1".into(),
+        };
+        let matches = u16::MAX as usize + 10;
+        let mut ops = SubOperations::new(matches);
+        assert_eq!(ops.remaining, u16::MAX);
+        for _ in 0..matches {
+            ops.record(&instance, Ok(status::SUCCESS));
+            ops.record(&instance, Ok(0xB000));
+        }
+        assert_eq!(
+            (ops.completed, ops.warning, ops.remaining),
+            (u16::MAX, u16::MAX, 0)
+        );
+        ops.record(&instance, Ok(0xA700));
+        assert_eq!(ops.failed, 1);
+        assert!(matches!(ops.audit_outcome(), AuditOutcome::MinorFailure));
+    }
+}
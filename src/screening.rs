@@ -0,0 +1,238 @@
+//
+// screening.rs
+// Dicom-Tools-rs
+//
+// Pluggable checks run on uploads before they are persisted: DICOM sanity rules and external scanners.
+//
+// Thales Matheus Mendonça Santos - November 2025
+
+use std::collections::HashSet;
+use std::io::Write;
+use std::process::Command;
+
+use anyhow::{bail, Context, Result};
+use dicom::core::Tag;
+use dicom::object::file::ReadPreamble;
+use dicom::object::OpenFileOptions;
+
+use crate::dicom_access::ElementAccess;
+
+const SOP_CLASS_UID: Tag = Tag(0x0008, 0x0016);
+const NUMBER_OF_FRAMES: Tag = Tag(0x0028, 0x0008);
+const ROWS: Tag = Tag(0x0028, 0x0010);
+const COLUMNS: Tag = Tag(0x0028, 0x0011);
+const PIXEL_DATA: Tag = Tag(0x7FE0, 0x0010);
+
+/// An upload refused by a screen; the web server answers it with 422.
+#[derive(Debug, thiserror::Error)]
+#[error("Upload rejected: {0}")]
+pub struct Rejection(pub String);
+
+/// A check run on every upload before it reaches the store.
+///
+/// Returning a [`Rejection`] refuses the upload; any other error also refuses it, since a
+/// screen that cannot run must not let content through.
+pub trait UploadScreen: Send + Sync {
+    fn screen(&self, original_name: Option<&str>, bytes: &[u8]) -> Result<()>;
+}
+
+/// Runs screens in order, stopping at the first refusal.
+#[derive(Default)]
+pub struct ScreeningPipeline {
+    screens: Vec<Box<dyn UploadScreen>>,
+}
+
+impl ScreeningPipeline {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with(mut self, screen: impl UploadScreen + 'static) -> Self {
+        self.screens.push(Box::new(screen));
+        self
+    }
+}
+
+impl UploadScreen for ScreeningPipeline {
+    fn screen(&self, original_name: Option<&str>, bytes: &[u8]) -> Result<()> {
+        self.screens
+            .iter()
+            .try_for_each(|screen| screen.screen(original_name, bytes))
+    }
+}
+
+/// Header-level DICOM checks: the payload must parse as a DICOM file, use an allowed SOP
+/// class, and declare plausible image dimensions.
+#[derive(Debug, Clone)]
+pub struct DicomSanityScreen {
+    /// SOP Class UIDs accepted; empty accepts every class.
+    pub allowed_sop_classes: HashSet<String>,
+    pub max_dimension: u32,
+    pub max_frames: u32,
+}
+
+impl Default for DicomSanityScreen {
+    fn default() -> Self {
+        Self {
+            allowed_sop_classes: HashSet::new(),
+            max_dimension: 16384,
+            max_frames: 10_000,
+        }
+    }
+}
+
+impl UploadScreen for DicomSanityScreen {
+    fn screen(&self, _original_name: Option<&str>, bytes: &[u8]) -> Result<()> {
+        let obj = match OpenFileOptions::new()
+            .read_preamble(ReadPreamble::Auto)
+            .read_until(PIXEL_DATA)
+            .from_reader(bytes)
+        {
+            Ok(obj) => obj,
+            Err(_) => bail!(Rejection("content is not a DICOM file".into())),
+        };
+
+        let sop_class = obj
+            .element_str(SOP_CLASS_UID)
+            .unwrap_or_else(|| obj.meta().media_storage_sop_class_uid().to_string());
+        let sop_class = sop_class.trim_end_matches(['\0', ' ']);
+        if !self.allowed_sop_classes.is_empty() && !self.allowed_sop_classes.contains(sop_class) {
+            bail!(Rejection(format!("SOP class {} is not allowed", sop_class)));
+        }
+
+        for (tag, label) in [(ROWS, "Rows"), (COLUMNS, "Columns")] {
+            if let Some(value) = obj.element_u32(tag) {
+                if value > self.max_dimension {
+                    bail!(Rejection(format!(
+                        "{} {} exceeds the maximum of {}",
+                        label, value, self.max_dimension
+                    )));
+                }
+            }
+        }
+        if let Some(frames) = obj.element_u32(NUMBER_OF_FRAMES) {
+            if frames > self.max_frames {
+                bail!(Rejection(format!(
+                    "{} frames exceed the maximum of {}",
+                    frames, self.max_frames
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Runs an external scanner (e.g. `clamscan --no-summary`) with the upload written to a
+/// temporary file appended as the last argument. A non-zero exit status rejects the upload.
+#[derive(Debug, Clone)]
+pub struct CommandScreen {
+    program: String,
+    args: Vec<String>,
+}
+
+impl CommandScreen {
+    pub fn new(program: impl Into<String>, args: Vec<String>) -> Self {
+        Self {
+            program: program.into(),
+            args,
+        }
+    }
+
+    /// Split a command line on whitespace; no shell is involved.
+    pub fn parse(command_line: &str) -> Result<Self> {
+        let mut parts = command_line.split_whitespace().map(str::to_string);
+        let program = parts.next().context("Screening command is empty")?;
+        Ok(Self::new(program, parts.collect()))
+    }
+}
+
+impl UploadScreen for CommandScreen {
+    fn screen(&self, _original_name: Option<&str>, bytes: &[u8]) -> Result<()> {
+        // Private (0600, created new) and removed when dropped, after the command has run.
+        let mut scratch = tempfile::Builder::new()
+            .prefix("dicom-tools-screen-")
+            .suffix(".dcm")
+            .tempfile()
+            .context("Failed to stage upload for screening")?;
+        scratch
+            .write_all(bytes)
+            .and_then(|_| scratch.flush())
+            .context("Failed to stage upload for screening")?;
+        let output = Command::new(&self.program)
+            .args(&self.args)
+            .arg(scratch.path())
+            .output();
+        drop(scratch);
+        let output =
+            output.with_context(|| format!("Failed to run screening command {}", self.program))?;
+
+        if !output.status.success() {
+            // Scanners usually explain themselves on stdout (clamscan) or stderr.
+            let text = [output.stdout, output.stderr]
+                .iter()
+                .map(|s| String::from_utf8_lossy(s).trim().to_string())
+                .find(|s| !s.is_empty())
+                .unwrap_or_else(|| output.status.to_string());
+            bail!(Rejection(format!("{} reported: {}", self.program, text)));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dicom::core::{DataElement, PrimitiveValue, VR};
+    use dicom::object::{FileDicomObject, FileMetaTableBuilder};
+
+    fn dicom_bytes(rows: u16) -> Vec<u8> {
+        let meta = FileMetaTableBuilder::new()
+            .transfer_syntax("1.2.840.10008.1.2.1")
+            .media_storage_sop_class_uid("1.2.840.10008.5.1.4.1.1.7")
+            .media_storage_sop_instance_uid("1.2.3.4")
+            .build()
+            .expect("meta");
+        let mut obj = FileDicomObject::new_empty_with_meta(meta);
+        obj.put(DataElement::new(
+            SOP_CLASS_UID,
+            VR::UI,
+            PrimitiveValue::from("1.2.840.10008.5.1.4.1.1.7"),
+        ));
+        obj.put(DataElement::new(ROWS, VR::US, PrimitiveValue::from(rows)));
+        let mut bytes = Vec::new();
+        obj.write_all(&mut bytes).expect("serialize");
+        bytes
+    }
+
+    fn rejected(result: Result<()>) -> bool {
+        result.is_err_and(|e| e.downcast_ref::<Rejection>().is_some())
+    }
+
+    #[test]
+    fn sanity_screen_rejects_junk_classes_and_dimensions() {
+        let screen = DicomSanityScreen::default();
+        assert!(screen.screen(None, &dicom_bytes(512)).is_ok());
+        assert!(rejected(screen.screen(None, b"MZ\x90\0 not dicom")));
+        assert!(rejected(screen.screen(None, &dicom_bytes(60000))));
+
+        let ct_only = DicomSanityScreen {
+            allowed_sop_classes: HashSet::from(["1.2.840.10008.5.1.4.1.1.2".to_string()]),
+            ..DicomSanityScreen::default()
+        };
+        assert!(rejected(ct_only.screen(None, &dicom_bytes(512))));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn command_screen_uses_exit_status() {
+        let bytes = dicom_bytes(8);
+        assert!(CommandScreen::parse("true")
+            .unwrap()
+            .screen(None, &bytes)
+            .is_ok());
+        assert!(rejected(
+            CommandScreen::parse("false").unwrap().screen(None, &bytes)
+        ));
+        assert!(CommandScreen::parse("  ").is_err());
+    }
+}
@@ -2,203 +2,1131 @@
 // scu.rs
 // Dicom-Tools-rs
 //
-// Implements minimal C-ECHO and C-STORE service class user operations for testing network connectivity.
+// Implements minimal C-ECHO and C-STORE service class user operations for testing network connectivity,
+// paced bulk pushes of a directory, C-FIND queries, N-CREATE/N-SET of normalized instances, and C-MOVE or C-GET retrieval
+// of a study or series into a local directory.
 //
 // Thales Matheus Mendonça Santos - November 2025
 
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
+use chrono::Local;
 use dicom::core::{DataElement, PrimitiveValue, Tag, VR};
-use dicom::object::{open_file, InMemDicomObject};
-use dicom_ul::association::client::ClientAssociationOptions;
-use dicom_ul::pdu::{PDataValue, PDataValueType, Pdu, PresentationContextResultReason};
-use std::path::Path;
+use dicom::object::{FileMetaTableBuilder, InMemDicomObject};
+use dicom_ul::association::client::ClientAssociation;
+use dicom_ul::pdu::PresentationContextResultReason;
+use dicom_ul::ServerAssociationOptions;
+use std::fs;
+use std::io::ErrorKind;
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
 
-// Import Registry
-use dicom::transfer_syntax::TransferSyntaxRegistry;
-// Import Index trait to enable .get().
-// Using generic encoding path which usually works for dicom 0.7
-use dicom::encoding::TransferSyntaxIndex;
+use crate::archive::{ArchiveKind, ExtractedArchive};
+use crate::atomic_file;
+use crate::audit::{AuditEvent, AuditLog, AuditOutcome, AuditedStudies};
+use crate::dicom_access::ElementAccess;
+use crate::dimse::{
+    self, command, status, AssociationSettings, DimseChannel, DimseMessage,
+    RoleSelectingAssociation, AFFECTED_SOP_CLASS_UID, AFFECTED_SOP_INSTANCE_UID,
+    COMPLETED_SUBOPERATIONS, EXPLICIT_VR_LITTLE_ENDIAN, FAILED_SUBOPERATIONS,
+    IMPLICIT_VR_LITTLE_ENDIAN, MESSAGE_ID, MOVE_DESTINATION, PRIORITY, REMAINING_SUBOPERATIONS,
+    REQUESTED_SOP_CLASS_UID, REQUESTED_SOP_INSTANCE_UID, WARNING_SUBOPERATIONS,
+};
+use crate::dimse_trace::{DimseTracer, TracedChannel};
+use crate::progress::{NoProgress, ProgressEvent, ProgressSink};
+use crate::scp::{IndexedInstance, InstanceIndex, StorageProposal};
+use crate::send_queue::{self, Destination, SendQueue};
+use crate::transfer_limits::{Throttle, TransferWindow};
+
+pub(crate) const VERIFICATION: &str = "1.2.840.10008.1.1";
+pub const STUDY_ROOT_FIND: &str = "1.2.840.10008.5.1.4.1.2.2.1";
+const STUDY_ROOT_MOVE: &str = "1.2.840.10008.5.1.4.1.2.2.2";
+const STUDY_ROOT_GET: &str = "1.2.840.10008.5.1.4.1.2.2.3";
+const QUERY_RETRIEVE_LEVEL: Tag = Tag(0x0008, 0x0052);
+const STUDY_INSTANCE_UID: Tag = Tag(0x0020, 0x000D);
+const SERIES_INSTANCE_UID: Tag = Tag(0x0020, 0x000E);
+
+/// Storage SOP classes a C-GET accepts back: image, SR, RT and document classes commonly
+/// found in archives.
+const STORAGE_SOP_CLASSES: [&str; 29] = [
+    "1.2.840.10008.5.1.4.1.1.1",     // CR Image
+    "1.2.840.10008.5.1.4.1.1.1.1",   // Digital X-Ray Image - For Presentation
+    "1.2.840.10008.5.1.4.1.1.1.1.1", // Digital X-Ray Image - For Processing
+    "1.2.840.10008.5.1.4.1.1.1.2",   // Digital Mammography X-Ray Image - For Presentation
+    "1.2.840.10008.5.1.4.1.1.2",     // CT Image
+    "1.2.840.10008.5.1.4.1.1.2.1",   // Enhanced CT Image
+    "1.2.840.10008.5.1.4.1.1.3.1",   // Ultrasound Multi-frame Image
+    "1.2.840.10008.5.1.4.1.1.4",     // MR Image
+    "1.2.840.10008.5.1.4.1.1.4.1",   // Enhanced MR Image
+    "1.2.840.10008.5.1.4.1.1.6.1",   // Ultrasound Image
+    "1.2.840.10008.5.1.4.1.1.7",     // Secondary Capture Image
+    "1.2.840.10008.5.1.4.1.1.7.2",   // Multi-frame Grayscale Byte Secondary Capture
+    "1.2.840.10008.5.1.4.1.1.7.3",   // Multi-frame Grayscale Word Secondary Capture
+    "1.2.840.10008.5.1.4.1.1.7.4",   // Multi-frame True Color Secondary Capture
+    "1.2.840.10008.5.1.4.1.1.11.1",  // Grayscale Softcopy Presentation State
+    "1.2.840.10008.5.1.4.1.1.12.1",  // X-Ray Angiographic Image
+    "1.2.840.10008.5.1.4.1.1.12.2",  // X-Ray Radiofluoroscopic Image
+    "1.2.840.10008.5.1.4.1.1.20",    // Nuclear Medicine Image
+    "1.2.840.10008.5.1.4.1.1.30",    // Parametric Map
+    "1.2.840.10008.5.1.4.1.1.66.4",  // Segmentation
+    "1.2.840.10008.5.1.4.1.1.88.11", // Basic Text SR
+    "1.2.840.10008.5.1.4.1.1.88.22", // Enhanced SR
+    "1.2.840.10008.5.1.4.1.1.88.33", // Comprehensive SR
+    "1.2.840.10008.5.1.4.1.1.88.59", // Key Object Selection Document
+    "1.2.840.10008.5.1.4.1.1.104.1", // Encapsulated PDF
+    "1.2.840.10008.5.1.4.1.1.128",   // Positron Emission Tomography Image
+    "1.2.840.10008.5.1.4.1.1.481.2", // RT Dose
+    "1.2.840.10008.5.1.4.1.1.481.3", // RT Structure Set
+    "1.2.840.10008.5.1.4.1.1.481.5", // RT Plan
+];
+
+/// Transfer syntaxes offered for each storage class, native first so archives holding
+/// compressed instances can still send them as they are.
+const STORAGE_TRANSFER_SYNTAXES: [&str; 8] = [
+    EXPLICIT_VR_LITTLE_ENDIAN,
+    IMPLICIT_VR_LITTLE_ENDIAN,
+    "1.2.840.10008.1.2.4.50", // JPEG Baseline
+    "1.2.840.10008.1.2.4.70", // JPEG Lossless, SV1
+    "1.2.840.10008.1.2.4.80", // JPEG-LS Lossless
+    "1.2.840.10008.1.2.4.90", // JPEG 2000 Lossless
+    "1.2.840.10008.1.2.4.91", // JPEG 2000
+    "1.2.840.10008.1.2.5",    // RLE Lossless
+];
 
 /// Perform a DICOM C-ECHO request against the given AE.
 pub fn echo(addr: &str) -> Result<()> {
+    echo_traced(addr, &AssociationSettings::default(), None)
+}
+
+/// Same as [`echo`] with the given association `settings`, mirroring the exchange to
+/// `tracer` when given.
+pub fn echo_traced(
+    addr: &str,
+    settings: &AssociationSettings,
+    tracer: Option<Arc<DimseTracer>>,
+) -> Result<()> {
     println!("Sending C-ECHO to {}", addr);
 
-    let abstract_syntax = "1.2.840.10008.1.1";
+    let contexts = default_proposal(VERIFICATION);
+    if let Some(tracer) = &tracer {
+        tracer.proposed_contexts(addr, &contexts);
+    }
+    let association = settings.establish(addr, &contexts)?;
+    let mut association = TracedChannel::new(association, tracer);
+    let pc_id = accepted_context(&association)
+        .context("No accepted presentation context for Verification")?;
 
-    let mut association = ClientAssociationOptions::new()
-        .with_abstract_syntax(abstract_syntax)
-        .establish(addr)
-        .context("Failed to establish association")?;
+    // The command set is a tiny data set, always encoded in Implicit VR Little Endian.
+    let mut cmd = dimse::command_set(VERIFICATION, command::C_ECHO_RQ, false);
+    cmd.put(dimse::us(MESSAGE_ID, 1));
+    dimse::send_message(&mut association, pc_id, cmd, None).context("Failed to send C-ECHO-RQ")?;
 
-    let pc_id = association
-        .presentation_contexts()
-        .iter()
-        .find(|pc| pc.reason == PresentationContextResultReason::Acceptance)
-        .map(|pc| pc.id)
-        .context("No accepted presentation context for Verification")?;
+    let msg = dimse::read_message(&mut association)
+        .context("Failed to receive C-ECHO-RSP")?
+        .context("Association released before C-ECHO-RSP")?;
+    println!(
+        "Received response: status 0x{:04X}",
+        msg.status().unwrap_or(0)
+    );
 
-    // Construct C-ECHO-RQ
-    // Command set is a tiny DICOM dataset encoded with the negotiated transfer syntax.
-    let mut cmd = InMemDicomObject::new_empty();
+    let _ = association.into_inner().release();
+    Ok(())
+}
+
+/// Phases reported by [`push_with_progress`], in order.
+const PUSH_PHASES: [&str; 5] = ["open", "associate", "encode", "send", "await-response"];
+
+/// Perform a minimal C-STORE to push a single object to a remote AE. The file's own transfer
+/// syntax is proposed with the native ones as fallbacks; when the peer only accepts the
+/// latter, the object is transcoded on the fly.
+pub fn push(addr: &str, file: &Path) -> Result<()> {
+    push_with_progress(addr, file, &NoProgress)
+}
+
+/// Same as [`push`], reporting each phase of the exchange to `progress`.
+pub fn push_with_progress(addr: &str, file: &Path, progress: &dyn ProgressSink) -> Result<()> {
+    push_traced(addr, file, &AssociationSettings::default(), progress, None)
+}
+
+/// Same as [`push_with_progress`] with the given association `settings`, mirroring the
+/// exchange to `tracer` when given.
+pub fn push_traced(
+    addr: &str,
+    file: &Path,
+    settings: &AssociationSettings,
+    progress: &dyn ProgressSink,
+    tracer: Option<Arc<DimseTracer>>,
+) -> Result<()> {
+    push_status(addr, file, settings, progress, tracer).map(|_| ())
+}
+
+/// Same as [`push_traced`], returning the status of the C-STORE-RSP so callers can tell a
+/// refused instance from a stored one.
+pub fn push_status(
+    addr: &str,
+    file: &Path,
+    settings: &AssociationSettings,
+    progress: &dyn ProgressSink,
+    tracer: Option<Arc<DimseTracer>>,
+) -> Result<u16> {
+    println!("Sending C-STORE for {:?} to {}", file, addr);
+    let started = Instant::now();
+    let total = PUSH_PHASES.len() as u64;
+    let item = file.display().to_string();
+    let report = |step: usize| {
+        progress.report(
+            ProgressEvent::new(PUSH_PHASES[step], step as u64, total).with_item(item.clone()),
+        )
+    };
+
+    report(0);
+
+    let instance = IndexedInstance::read(file)
+        .with_context(|| format!("{:?} is not a DICOM file with a SOP Instance UID", file))?;
+
+    report(1);
+    let proposal = StorageProposal::for_instances(std::slice::from_ref(&instance))
+        .preferring(&settings.transfer_syntaxes);
+    let contexts = proposal.contexts();
+    if let Some(tracer) = &tracer {
+        tracer.proposed_contexts(addr, &contexts);
+    }
+    let association = settings.establish(addr, &contexts)?;
+    let mut association = TracedChannel::new(association, tracer);
+
+    report(2);
+    let (pc_id, data_bytes) = proposal
+        .encode(&association, &instance)
+        .context("No accepted presentation context for file SOP Class")??;
+    let negotiated = dimse::negotiated_ts(&association, pc_id)?;
+    if negotiated.uid() != instance.transfer_syntax {
+        println!(
+            "Transcoded from {} to {} accepted by the peer",
+            dimse::transfer_syntax(&instance.transfer_syntax)
+                .map(|ts| ts.name())
+                .unwrap_or(instance.transfer_syntax.as_str()),
+            negotiated.name()
+        );
+    }
+    // Only the required command elements are included; the data set follows as data PDVs.
+    let mut cmd = dimse::command_set(&instance.sop_class_uid, command::C_STORE_RQ, true);
+    cmd.put(dimse::us(MESSAGE_ID, 2));
     cmd.put(DataElement::new(
-        Tag(0x0000, 0x0002),
+        AFFECTED_SOP_INSTANCE_UID,
         VR::UI,
-        PrimitiveValue::from(abstract_syntax),
-    ));
-    cmd.put(DataElement::new(
-        Tag(0x0000, 0x0100),
-        VR::US,
-        PrimitiveValue::from(0x0030_u16),
-    ));
-    cmd.put(DataElement::new(
-        Tag(0x0000, 0x0110),
-        VR::US,
-        PrimitiveValue::from(1_u16),
-    ));
-    cmd.put(DataElement::new(
-        Tag(0x0000, 0x0800),
-        VR::US,
-        PrimitiveValue::from(0x0101_u16),
+        PrimitiveValue::from(instance.sop_instance_uid.as_str()),
     ));
 
-    // Get IVRLE Transfer Syntax
-    let ts = TransferSyntaxRegistry
-        .get("1.2.840.10008.1.2")
-        .context("Implicit VR Little Endian transfer syntax not found")?;
-
-    let mut command_bytes = Vec::new();
-    cmd.write_dataset_with_ts(&mut command_bytes, ts)
-        .context("Failed to encode command set")?;
-
-    let pdu = Pdu::PData {
-        data: vec![PDataValue {
-            presentation_context_id: pc_id,
-            value_type: PDataValueType::Command,
-            is_last: true,
-            data: command_bytes,
-        }],
-    };
-
-    association.send(&pdu).context("Failed to send C-ECHO-RQ")?;
+    report(3);
+    dimse::send_message(&mut association, pc_id, cmd, Some(&data_bytes))
+        .context("Failed to send C-STORE-RQ")?;
 
-    let msg = association
-        .receive()
-        .context("Failed to receive C-ECHO-RSP")?;
-    println!("Received response: {:?}", msg);
+    report(4);
+    let msg = dimse::read_message(&mut association)
+        .context("Failed to receive C-STORE-RSP")?
+        .context("Association released before C-STORE-RSP")?;
+    progress.report(ProgressEvent::new("done", total, total).with_item(item.clone()));
+    let code = msg.status().unwrap_or(0);
+    println!("Received response: status 0x{:04X}", code);
+    println!(
+        "Sent {}",
+        transfer_rate(data_bytes.len() as u64, started.elapsed())
+    );
 
-    let _ = association.release();
-    Ok(())
+    let _ = association.into_inner().release();
+    Ok(code)
 }
 
-/// Perform a minimal C-STORE to push a single object to a remote AE.
-pub fn push(addr: &str, file: &Path) -> Result<()> {
-    println!("Sending C-STORE for {:?} to {}", file, addr);
+/// Settings of [`push_dir`].
+#[derive(Debug, Clone)]
+pub struct PushDirOptions {
+    pub association: AssociationSettings,
+    /// Average rate cap in MB/s, enforced between instances.
+    pub rate_limit: Option<f64>,
+    /// Daily window outside which sending pauses, with the association released.
+    pub window: Option<TransferWindow>,
+    /// When set, the run is recorded as an export audit event.
+    pub audit: Option<Arc<AuditLog>>,
+    /// When set, instances that fail for a transient reason are queued for a later retry.
+    pub queue: Option<SendQueue>,
+}
 
-    let obj = open_file(file).context("Failed to open DICOM file")?;
+/// Outcome of [`push_dir`].
+#[derive(Debug, Clone, Default)]
+pub struct PushDirSummary {
+    pub sent: usize,
+    pub bytes: u64,
+    /// Archive inputs list failed instances as `archive/entry`.
+    pub failed: Vec<PathBuf>,
+    /// How many of the failed instances went to the send queue.
+    pub queued: usize,
+    /// Files under the directory that are not DICOM instances, so were not sent.
+    pub skipped: Vec<PathBuf>,
+    /// Every instance tried, in sending order.
+    pub transfers: Vec<InstanceTransfer>,
+    pub elapsed: Duration,
+}
 
-    let sop_class = obj
-        .element(Tag(0x0008, 0x0016))
-        .context("Missing SOP Class UID")?
-        .to_str()?;
-    let sop_instance = obj
-        .element(Tag(0x0008, 0x0018))
-        .context("Missing SOP Instance UID")?
-        .to_str()?;
+/// One instance sent by [`push_dir`].
+#[derive(Debug, Clone)]
+pub struct InstanceTransfer {
+    pub path: PathBuf,
+    /// Encoded data set bytes written to the association.
+    pub bytes: u64,
+    /// From encoding the data set to the C-STORE-RSP.
+    pub elapsed: Duration,
+    /// Status of the C-STORE-RSP; `None` when the instance could not be sent.
+    pub status: Option<u16>,
+}
 
-    let mut association = ClientAssociationOptions::new()
-        .with_abstract_syntax(&*sop_class)
-        .establish(addr)
-        .context("Failed to establish association")?;
+/// `1.23 MB in 0.45 s (2.73 MB/s)`
+fn transfer_rate(bytes: u64, elapsed: Duration) -> String {
+    let megabytes = bytes as f64 / (1024.0 * 1024.0);
+    let seconds = elapsed.as_secs_f64();
+    let rate = if seconds > 0.0 {
+        megabytes / seconds
+    } else {
+        0.0
+    };
+    format!("{:.2} MB in {:.2} s ({:.2} MB/s)", megabytes, seconds, rate)
+}
 
-    let pc_id = association
-        .presentation_contexts()
+/// C-STORE every DICOM file under `dir` over one association, paced by the rate limit and
+/// transfer window of `options`. `dir` may also be a `.zip`, `.tar` or `.tar.gz` export, whose
+/// DICOM entries are unpacked to a temporary directory first. Failed instances are listed
+/// rather than aborting the run. Progress is reported in bytes of the files sent so far. With a
+/// send queue, transient failures are queued, and when the peer cannot be reached at all the
+/// remaining instances are queued without trying them.
+pub fn push_dir(
+    addr: &str,
+    dir: &Path,
+    options: &PushDirOptions,
+    progress: &dyn ProgressSink,
+    tracer: Option<Arc<DimseTracer>>,
+) -> Result<PushDirSummary> {
+    let extracted = match ArchiveKind::of(dir) {
+        Some(_) if dir.is_file() => Some(ExtractedArchive::extract(dir)?),
+        _ => None,
+    };
+    let shown = |path: &Path| match &extracted {
+        Some(archive) => archive.origin(dir, path),
+        None => path.to_path_buf(),
+    };
+    let index = InstanceIndex::scan(extracted.as_ref().map_or(dir, |archive| archive.path()))?;
+    let instances = index.instances();
+    let proposal = StorageProposal::for_instances(instances)
+        .preferring(&options.association.transfer_syntaxes);
+    // Context ids are odd numbers up to 255.
+    if proposal.len() > 128 {
+        bail!(
+            "{} SOP class/transfer syntax combinations exceed the 128 presentation contexts of one association",
+            proposal.len()
+        );
+    }
+    let mut throttle = options.rate_limit.map(Throttle::new).transpose()?;
+    let mut association: Option<TracedChannel<ClientAssociation>> = None;
+    let mut summary = PushDirSummary {
+        skipped: index.skipped().iter().map(|path| shown(path)).collect(),
+        ..Default::default()
+    };
+    let total = instances.len() as u64;
+    let total_bytes: u64 = instances
         .iter()
-        .find(|pc| pc.reason == PresentationContextResultReason::Acceptance)
-        .map(|pc| pc.id)
-        .context("No accepted presentation context for file SOP Class")?;
+        .map(|instance| fs::metadata(&instance.path).map_or(0, |meta| meta.len()))
+        .sum();
+    let mut done_bytes = 0;
+    let started = Instant::now();
+    println!("Sending {} instance(s) from {:?} to {}", total, dir, addr);
+    if !summary.skipped.is_empty() {
+        println!(
+            "Skipping {} file(s) that are not DICOM instances",
+            summary.skipped.len()
+        );
+    }
+
+    let destination = Destination::dimse(addr, &options.association);
+    let queue = |instance: &IndexedInstance, error: &str, summary: &mut PushDirSummary| {
+        summary.failed.push(shown(&instance.path));
+        let Some(queue) = &options.queue else {
+            return;
+        };
+        match queue.enqueue(
+            &instance.path,
+            &shown(&instance.path),
+            &instance.sop_instance_uid,
+            destination.clone(),
+            error,
+        ) {
+            Ok(_) => summary.queued += 1,
+            Err(err) => eprintln!("Failed to queue {:?}: {:#}", shown(&instance.path), err),
+        }
+    };
 
-    // Construct C-STORE-RQ
-    // Only the required command elements are included here; dataset follows later as PDV.
-    let mut cmd = InMemDicomObject::new_empty();
+    for (n, instance) in instances.iter().enumerate() {
+        if let Some(window) = &options.window {
+            if window.until_open(Local::now().time()).is_some() {
+                if let Some(open) = association.take() {
+                    let _ = open.into_inner().release();
+                }
+                window.wait_until_open();
+                if let Some(throttle) = &mut throttle {
+                    throttle.restart();
+                }
+            }
+        }
+        progress.report(
+            ProgressEvent::new("push", done_bytes, total_bytes)
+                .with_item(shown(&instance.path).display().to_string()),
+        );
+        done_bytes += fs::metadata(&instance.path).map_or(0, |meta| meta.len());
+        let channel = match &mut association {
+            Some(channel) => channel,
+            None => {
+                let contexts = proposal.contexts();
+                if let Some(tracer) = &tracer {
+                    tracer.proposed_contexts(addr, &contexts);
+                }
+                let opened = match options.association.establish(addr, &contexts) {
+                    Ok(opened) => opened,
+                    Err(err) if options.queue.is_some() => {
+                        let error = format!("{:#}", err);
+                        eprintln!("Cannot reach {}: {}", addr, error);
+                        for rest in &instances[n..] {
+                            queue(rest, &error, &mut summary);
+                        }
+                        break;
+                    }
+                    Err(err) => return Err(err),
+                };
+                association.insert(TracedChannel::new(opened, tracer.clone()))
+            }
+        };
+        let sending = Instant::now();
+        let mut bytes = 0;
+        let outcome = match proposal.encode(channel, instance) {
+            Some(encoded) => encoded.and_then(|(pc_id, data)| {
+                let code = dimse::store_instance(
+                    channel,
+                    pc_id,
+                    (n % usize::from(u16::MAX)) as u16 + 1,
+                    &instance.sop_class_uid,
+                    &instance.sop_instance_uid,
+                    &data,
+                    None,
+                    &mut |_| {},
+                )?;
+                bytes = data.len() as u64;
+                if let Some(throttle) = &mut throttle {
+                    throttle.pace(data.len());
+                }
+                Ok(code)
+            }),
+            None => Err(anyhow::anyhow!(
+                "{} rejected SOP class {}",
+                options.association.called_ae_title,
+                instance.sop_class_uid
+            )),
+        };
+        let transfer = InstanceTransfer {
+            path: shown(&instance.path),
+            bytes,
+            elapsed: sending.elapsed(),
+            status: outcome.as_ref().ok().copied(),
+        };
+        summary.bytes += bytes;
+        match outcome {
+            // 0xB000, 0xB006, 0xB007: stored with coercion or element discards.
+            Ok(code) if code == status::SUCCESS || code & 0xF000 == 0xB000 => {
+                println!(
+                    "  {:?}: {}",
+                    transfer.path,
+                    transfer_rate(transfer.bytes, transfer.elapsed)
+                );
+                summary.sent += 1
+            }
+            Ok(code) => {
+                eprintln!(
+                    "{:?} refused with status 0x{:04X}",
+                    shown(&instance.path),
+                    code
+                );
+                if send_queue::is_transient_status(code) {
+                    queue(instance, &format!("status 0x{:04X}", code), &mut summary);
+                } else {
+                    summary.failed.push(shown(&instance.path));
+                }
+            }
+            Err(err) => {
+                eprintln!("Failed to send {:?}: {:#}", shown(&instance.path), err);
+                queue(instance, &format!("{:#}", err), &mut summary);
+                // The association may be broken; reconnect for the next instance.
+                if let Some(broken) = association.take() {
+                    let _ = broken.into_inner().abort();
+                }
+            }
+        }
+        summary.transfers.push(transfer);
+    }
+    if let Some(open) = association {
+        let _ = open.into_inner().release();
+    }
+    progress.report(ProgressEvent::new("done", total_bytes, total_bytes));
+    summary.elapsed = started.elapsed();
+    if let Some(audit) = &options.audit {
+        let mut studies = AuditedStudies::default();
+        for instance in instances {
+            studies.add_instance(instance);
+        }
+        let outcome = if summary.failed.is_empty() {
+            AuditOutcome::Success
+        } else if summary.sent == 0 {
+            AuditOutcome::SeriousFailure
+        } else {
+            AuditOutcome::MinorFailure
+        };
+        audit.record(&AuditEvent::export(
+            &options.association.calling_ae_title,
+            &options.association.called_ae_title,
+            addr,
+            studies,
+            outcome,
+        ));
+    }
+    println!(
+        "Sent {} of {} instance(s), {}; {} failed, {} skipped",
+        summary.sent,
+        total,
+        transfer_rate(summary.bytes, summary.elapsed),
+        summary.failed.len(),
+        summary.skipped.len()
+    );
+    if let Some(queue) = options.queue.as_ref().filter(|_| summary.queued > 0) {
+        println!(
+            "{} instance(s) queued for retry in {:?}",
+            summary.queued,
+            queue.root()
+        );
+    }
+    Ok(summary)
+}
+
+/// Send a C-FIND-RQ for `identifier` on `sop_class` and collect the identifiers of the
+/// pending responses. Fails unless the final response is a success.
+pub fn find(
+    addr: &str,
+    settings: &AssociationSettings,
+    sop_class: &str,
+    identifier: &InMemDicomObject,
+    tracer: Option<Arc<DimseTracer>>,
+) -> Result<Vec<InMemDicomObject>> {
+    let contexts = default_proposal(sop_class);
+    if let Some(tracer) = &tracer {
+        tracer.proposed_contexts(addr, &contexts);
+    }
+    let association = settings.establish(addr, &contexts)?;
+    let mut association = TracedChannel::new(association, tracer);
+    let pc_id = accepted_context(&association).with_context(|| {
+        format!(
+            "No accepted presentation context for C-FIND on {}",
+            sop_class
+        )
+    })?;
+
+    let mut cmd = dimse::command_set(sop_class, command::C_FIND_RQ, true);
+    cmd.put(dimse::us(MESSAGE_ID, 1));
+    cmd.put(dimse::us(PRIORITY, 0));
+    let mut bytes = Vec::new();
+    identifier
+        .write_dataset_with_ts(&mut bytes, dimse::negotiated_ts(&association, pc_id)?)
+        .context("Failed to encode identifier")?;
+    dimse::send_message(&mut association, pc_id, cmd, Some(&bytes))
+        .context("Failed to send C-FIND-RQ")?;
+
+    let mut matches = Vec::new();
+    let outcome = loop {
+        let msg = dimse::read_message(&mut association)
+            .context("Failed to receive C-FIND-RSP")?
+            .context("Association released before the final C-FIND-RSP")?;
+        match msg.status() {
+            // 0xFF01: pending, but some optional keys were not supported.
+            Some(status::PENDING) | Some(0xFF01) => {
+                if let Some(found) = msg.dataset(&association)? {
+                    matches.push(found);
+                }
+            }
+            Some(status::SUCCESS) => break Ok(matches),
+            other => {
+                break Err(anyhow::anyhow!(
+                    "C-FIND failed with status 0x{:04X}",
+                    other.unwrap_or(0xFFFF)
+                ))
+            }
+        }
+    };
+    let _ = association.into_inner().release();
+    outcome
+}
+
+/// Create a normalized SOP instance (e.g. a performed procedure step) with N-CREATE.
+pub fn n_create(
+    addr: &str,
+    settings: &AssociationSettings,
+    sop_class: &str,
+    sop_instance: &str,
+    attributes: &InMemDicomObject,
+    tracer: Option<Arc<DimseTracer>>,
+) -> Result<()> {
+    let mut cmd = dimse::command_set(sop_class, command::N_CREATE_RQ, true);
     cmd.put(DataElement::new(
-        Tag(0x0000, 0x0002),
+        AFFECTED_SOP_INSTANCE_UID,
         VR::UI,
-        PrimitiveValue::from(sop_class.to_string()),
-    ));
-    cmd.put(DataElement::new(
-        Tag(0x0000, 0x0100),
-        VR::US,
-        PrimitiveValue::from(0x0001_u16),
-    ));
-    cmd.put(DataElement::new(
-        Tag(0x0000, 0x0110),
-        VR::US,
-        PrimitiveValue::from(2_u16),
+        PrimitiveValue::from(sop_instance),
     ));
+    normalized_request(
+        addr, settings, sop_class, cmd, attributes, "N-CREATE", tracer,
+    )
+}
+
+/// Modify attributes of a normalized SOP instance with N-SET.
+pub fn n_set(
+    addr: &str,
+    settings: &AssociationSettings,
+    sop_class: &str,
+    sop_instance: &str,
+    modifications: &InMemDicomObject,
+    tracer: Option<Arc<DimseTracer>>,
+) -> Result<()> {
+    // N-SET names its target as the Requested rather than the Affected SOP Class and Instance.
+    let mut cmd = dimse::command_set(sop_class, command::N_SET_RQ, true);
+    cmd.remove_element(AFFECTED_SOP_CLASS_UID);
     cmd.put(DataElement::new(
-        Tag(0x0000, 0x0800),
-        VR::US,
-        PrimitiveValue::from(0x0000_u16),
+        REQUESTED_SOP_CLASS_UID,
+        VR::UI,
+        PrimitiveValue::from(sop_class),
     ));
     cmd.put(DataElement::new(
-        Tag(0x0000, 1000),
+        REQUESTED_SOP_INSTANCE_UID,
         VR::UI,
-        PrimitiveValue::from(sop_instance.to_string()),
+        PrimitiveValue::from(sop_instance),
     ));
+    normalized_request(
+        addr,
+        settings,
+        sop_class,
+        cmd,
+        modifications,
+        "N-SET",
+        tracer,
+    )
+}
 
-    // Get IVRLE Transfer Syntax for Command Set
-    let ts_ivrle = TransferSyntaxRegistry
-        .get("1.2.840.10008.1.2")
-        .context("Implicit VR Little Endian transfer syntax not found")?;
-
-    let mut command_bytes = Vec::new();
-    // Encode command as a command PDV (even though this code path uses the same TS as dataset).
-    cmd.write_dataset_with_ts(&mut command_bytes, ts_ivrle)
-        .context("Failed to encode command set")?;
+/// Send one N-service request with a data set on its own association and check the response.
+fn normalized_request(
+    addr: &str,
+    settings: &AssociationSettings,
+    sop_class: &str,
+    mut cmd: InMemDicomObject,
+    dataset: &InMemDicomObject,
+    operation: &str,
+    tracer: Option<Arc<DimseTracer>>,
+) -> Result<()> {
+    let contexts = default_proposal(sop_class);
+    if let Some(tracer) = &tracer {
+        tracer.proposed_contexts(addr, &contexts);
+    }
+    let association = settings.establish(addr, &contexts)?;
+    let mut association = TracedChannel::new(association, tracer);
+    let pc_id = accepted_context(&association).with_context(|| {
+        format!(
+            "No accepted presentation context for {} on {}",
+            operation, sop_class
+        )
+    })?;
 
-    // Encode File Dataset
-    let pc = association
-        .presentation_contexts()
-        .iter()
-        .find(|pc| pc.id == pc_id)
-        .unwrap();
-    let negotiated_ts_uid = &pc.transfer_syntax;
-    let ts_negotiated = TransferSyntaxRegistry
-        .get(negotiated_ts_uid)
-        .context(format!(
-            "Negotiated transfer syntax {} not found",
-            negotiated_ts_uid
-        ))?;
-
-    let mut data_bytes = Vec::new();
-    obj.write_dataset_with_ts(&mut data_bytes, ts_negotiated)
+    cmd.put(dimse::us(MESSAGE_ID, 1));
+    let mut bytes = Vec::new();
+    dataset
+        .write_dataset_with_ts(&mut bytes, dimse::negotiated_ts(&association, pc_id)?)
         .context("Failed to encode data set")?;
+    dimse::send_message(&mut association, pc_id, cmd, Some(&bytes))
+        .with_context(|| format!("Failed to send {}-RQ", operation))?;
+    let outcome = dimse::read_message(&mut association)
+        .with_context(|| format!("Failed to receive {}-RSP", operation))
+        .and_then(|msg| {
+            let msg =
+                msg.with_context(|| format!("Association released before {}-RSP", operation))?;
+            match msg.status() {
+                Some(status::SUCCESS) => Ok(()),
+                other => bail!(
+                    "{} failed with status 0x{:04X}",
+                    operation,
+                    other.unwrap_or(0xFFFF)
+                ),
+            }
+        });
+    let _ = association.into_inner().release();
+    outcome
+}
 
-    // Send Command
-    association.send(&Pdu::PData {
-        data: vec![PDataValue {
-            presentation_context_id: pc_id,
-            value_type: PDataValueType::Command,
-            is_last: true,
-            data: command_bytes,
-        }],
-    })?;
+/// What a C-MOVE retrieve asks for.
+#[derive(Debug, Clone)]
+pub enum RetrieveTarget {
+    Study(String),
+    Series { study: String, series: String },
+}
 
-    // Send Data
-    association.send(&Pdu::PData {
-        data: vec![PDataValue {
-            presentation_context_id: pc_id,
-            value_type: PDataValueType::Data,
-            is_last: true,
-            data: data_bytes,
-        }],
-    })?;
+impl RetrieveTarget {
+    /// Study Root identifier selecting the target.
+    fn identifier(&self) -> InMemDicomObject {
+        let mut identifier = InMemDicomObject::new_empty();
+        let (level, study, series) = match self {
+            RetrieveTarget::Study(study) => ("STUDY", study, None),
+            RetrieveTarget::Series { study, series } => ("SERIES", study, Some(series)),
+        };
+        identifier.put(DataElement::new(
+            QUERY_RETRIEVE_LEVEL,
+            VR::CS,
+            PrimitiveValue::from(level),
+        ));
+        identifier.put(DataElement::new(
+            STUDY_INSTANCE_UID,
+            VR::UI,
+            PrimitiveValue::from(study.as_str()),
+        ));
+        if let Some(series) = series {
+            identifier.put(DataElement::new(
+                SERIES_INSTANCE_UID,
+                VR::UI,
+                PrimitiveValue::from(series.as_str()),
+            ));
+        }
+        identifier
+    }
+}
+
+/// Settings of a [`MoveRetriever`].
+#[derive(Debug, Clone)]
+pub struct RetrieveOptions {
+    /// Association with the PACS. The calling AE title is also the move destination, so the
+    /// PACS must know it.
+    pub association: AssociationSettings,
+    /// Directory received instances are written to, as `<SOP Instance UID>.dcm`.
+    pub output: PathBuf,
+}
+
+/// Outcome of a C-MOVE retrieve.
+#[derive(Debug, Clone, Default)]
+pub struct RetrieveSummary {
+    /// Status of the final C-MOVE response.
+    pub status: u16,
+    pub completed: u16,
+    pub failed: u16,
+    pub warning: u16,
+    /// Files written from the C-STORE sub-operations, in arrival order.
+    pub stored: Vec<PathBuf>,
+}
+
+/// C-MOVE SCU with its own storage SCP: the listener is bound first so that the PACS can
+/// reach it as soon as the move starts.
+pub struct MoveRetriever {
+    listener: TcpListener,
+    options: RetrieveOptions,
+}
+
+impl MoveRetriever {
+    /// Listen for the incoming C-STORE sub-operations on `listen` (`host:port`).
+    pub fn bind(listen: &str, options: RetrieveOptions) -> Result<Self> {
+        let listener =
+            TcpListener::bind(listen).with_context(|| format!("Failed to listen on {}", listen))?;
+        Ok(Self { listener, options })
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    /// Ask the PACS at `addr` to move `target` to us and store what it sends until the
+    /// final C-MOVE response. Pending responses are reported to `progress`.
+    pub fn retrieve(
+        self,
+        addr: &str,
+        target: &RetrieveTarget,
+        progress: &dyn ProgressSink,
+        tracer: Option<Arc<DimseTracer>>,
+    ) -> Result<RetrieveSummary> {
+        fs::create_dir_all(&self.options.output)
+            .with_context(|| format!("Failed to create {:?}", self.options.output))?;
+        let done = Arc::new(AtomicBool::new(false));
+        let receiver = {
+            let listener = self.listener.try_clone()?;
+            let options = self.options.clone();
+            let done = Arc::clone(&done);
+            let tracer = tracer.clone();
+            thread::spawn(move || receive_stores(listener, &options, &done, tracer))
+        };
+        let outcome = self.send_move(addr, target, progress, tracer);
+        // Sub-operations are complete once the final response arrives; stop accepting and
+        // wait for the stores in flight.
+        done.store(true, Ordering::Relaxed);
+        let stored = receiver
+            .join()
+            .map_err(|_| anyhow::anyhow!("Storage listener panicked"))?;
+        let mut summary = outcome?;
+        summary.stored = stored;
+        Ok(summary)
+    }
+
+    fn send_move(
+        &self,
+        addr: &str,
+        target: &RetrieveTarget,
+        progress: &dyn ProgressSink,
+        tracer: Option<Arc<DimseTracer>>,
+    ) -> Result<RetrieveSummary> {
+        let contexts = default_proposal(STUDY_ROOT_MOVE);
+        if let Some(tracer) = &tracer {
+            tracer.proposed_contexts(addr, &contexts);
+        }
+        let association = self.options.association.establish(addr, &contexts)?;
+        let mut association = TracedChannel::new(association, tracer);
+        let pc_id = accepted_context(&association)
+            .context("No accepted presentation context for Study Root C-MOVE")?;
+
+        let mut cmd = dimse::command_set(STUDY_ROOT_MOVE, command::C_MOVE_RQ, true);
+        cmd.put(dimse::us(MESSAGE_ID, 1));
+        cmd.put(dimse::us(PRIORITY, 0));
+        cmd.put(DataElement::new(
+            MOVE_DESTINATION,
+            VR::AE,
+            PrimitiveValue::from(self.options.association.calling_ae_title.as_str()),
+        ));
+        let mut identifier = Vec::new();
+        target
+            .identifier()
+            .write_dataset_with_ts(&mut identifier, dimse::negotiated_ts(&association, pc_id)?)
+            .context("Failed to encode identifier")?;
+        dimse::send_message(&mut association, pc_id, cmd, Some(&identifier))
+            .context("Failed to send C-MOVE-RQ")?;
 
-    let msg = association
-        .receive()
-        .context("Failed to receive C-STORE-RSP")?;
-    println!("Received response: {:?}", msg);
+        let summary = await_retrieve(
+            &mut association,
+            &self.options,
+            &self.options.association.called_ae_title,
+            progress,
+        )?;
+        let _ = association.into_inner().release();
+        Ok(summary)
+    }
+}
+
+/// CLI helper: retrieve `target` from `addr` into `options.output`, listening on `listen`.
+pub fn retrieve(
+    addr: &str,
+    listen: &str,
+    options: RetrieveOptions,
+    target: &RetrieveTarget,
+    progress: &dyn ProgressSink,
+    tracer: Option<Arc<DimseTracer>>,
+) -> Result<RetrieveSummary> {
+    let output = options.output.clone();
+    let retriever = MoveRetriever::bind(listen, options)?;
+    println!(
+        "Sending C-MOVE to {}, receiving on {}",
+        addr,
+        retriever.local_addr()?
+    );
+    let summary = retriever.retrieve(addr, target, progress, tracer)?;
+    report_summary(&summary, &output)?;
+    Ok(summary)
+}
+
+/// Retrieve `target` with C-GET: the instances come back as C-STORE sub-operations on the
+/// same association, for archives that cannot open connections to us. The commonly
+/// archived storage SOP classes are proposed with the SCP role.
+pub fn get(
+    addr: &str,
+    options: &RetrieveOptions,
+    target: &RetrieveTarget,
+    progress: &dyn ProgressSink,
+    tracer: Option<Arc<DimseTracer>>,
+) -> Result<RetrieveSummary> {
+    println!("Sending C-GET to {}", addr);
+    fs::create_dir_all(&options.output)
+        .with_context(|| format!("Failed to create {:?}", options.output))?;
+    let native = vec![
+        EXPLICIT_VR_LITTLE_ENDIAN.to_string(),
+        IMPLICIT_VR_LITTLE_ENDIAN.to_string(),
+    ];
+    let storage_syntaxes: Vec<String> = STORAGE_TRANSFER_SYNTAXES
+        .iter()
+        .map(|uid| uid.to_string())
+        .collect();
+    let storage_classes: Vec<String> = STORAGE_SOP_CLASSES
+        .iter()
+        .map(|uid| uid.to_string())
+        .collect();
+    let mut contexts = vec![(STUDY_ROOT_GET.to_string(), native)];
+    contexts.extend(
+        storage_classes
+            .iter()
+            .map(|class| (class.clone(), storage_syntaxes.clone())),
+    );
+    if let Some(tracer) = &tracer {
+        tracer.proposed_contexts(addr, &contexts);
+    }
+    let association = RoleSelectingAssociation::establish(
+        addr,
+        &options.association,
+        &contexts,
+        &storage_classes,
+    )?;
+    let mut association = TracedChannel::new(association, tracer);
+    // The C-GET context is the first one proposed.
+    let pc_id = 1;
+    if !association
+        .contexts()
+        .iter()
+        .any(|pc| pc.id == pc_id && pc.reason == PresentationContextResultReason::Acceptance)
+    {
+        bail!("No accepted presentation context for Study Root C-GET");
+    }
 
-    let _ = association.release();
+    let mut cmd = dimse::command_set(STUDY_ROOT_GET, command::C_GET_RQ, true);
+    cmd.put(dimse::us(MESSAGE_ID, 1));
+    cmd.put(dimse::us(PRIORITY, 0));
+    let mut identifier = Vec::new();
+    target
+        .identifier()
+        .write_dataset_with_ts(&mut identifier, dimse::negotiated_ts(&association, pc_id)?)
+        .context("Failed to encode identifier")?;
+    dimse::send_message(&mut association, pc_id, cmd, Some(&identifier))
+        .context("Failed to send C-GET-RQ")?;
+
+    let summary = await_retrieve(
+        &mut association,
+        options,
+        &options.association.called_ae_title,
+        progress,
+    )?;
+    let _ = association.into_inner().release();
+    report_summary(&summary, &options.output)?;
+    Ok(summary)
+}
+
+/// Print the outcome; fails unless the retrieve succeeded, fully or partly.
+fn report_summary(summary: &RetrieveSummary, output: &Path) -> Result<()> {
+    println!(
+        "Final status 0x{:04X}: {} completed, {} failed, {} warning; {} file(s) in {:?}",
+        summary.status,
+        summary.completed,
+        summary.failed,
+        summary.warning,
+        summary.stored.len(),
+        output
+    );
+    // 0xB000: some sub-operations failed, but what arrived is kept.
+    if summary.status != status::SUCCESS && summary.status != status::SUBOPERATIONS_FAILED {
+        bail!("Retrieve failed with status 0x{:04X}", summary.status);
+    }
     Ok(())
 }
+
+/// Accept storage associations until `done` is set, then wait for the open ones to end.
+fn receive_stores(
+    listener: TcpListener,
+    options: &RetrieveOptions,
+    done: &AtomicBool,
+    tracer: Option<Arc<DimseTracer>>,
+) -> Vec<PathBuf> {
+    let _ = listener.set_nonblocking(true);
+    let mut associations = Vec::new();
+    while !done.load(Ordering::Relaxed) {
+        match listener.accept() {
+            Ok((stream, _)) => {
+                let _ = stream.set_nonblocking(false);
+                let options = options.clone();
+                let tracer = tracer.clone();
+                associations.push(thread::spawn(move || {
+                    store_association(stream, &options, tracer).unwrap_or_else(|err| {
+                        eprintln!("Storage association failed: {:#}", err);
+                        Vec::new()
+                    })
+                }));
+            }
+            Err(err) if err.kind() == ErrorKind::WouldBlock => {
+                thread::sleep(Duration::from_millis(20))
+            }
+            Err(err) => eprintln!("Failed to accept connection: {}", err),
+        }
+    }
+    associations
+        .into_iter()
+        .flat_map(|handle| handle.join().unwrap_or_default())
+        .collect()
+}
+
+/// Serve one storage association of a C-MOVE, answering each C-STORE-RQ after writing its
+/// instance.
+fn store_association(
+    stream: TcpStream,
+    options: &RetrieveOptions,
+    tracer: Option<Arc<DimseTracer>>,
+) -> Result<Vec<PathBuf>> {
+    let association = ServerAssociationOptions::new()
+        .accept_any()
+        .ae_title(options.association.calling_ae_title.as_str())
+        .promiscuous(true)
+        .establish(stream)
+        .context("Failed to negotiate storage association")?;
+    let mut association = TracedChannel::new(association, tracer);
+    let peer = association.get_ref().client_ae_title().trim().to_string();
+    let mut stored = Vec::new();
+    while let Some(request) = dimse::read_message(&mut association)? {
+        match request.command_field() {
+            command::C_STORE_RQ => {
+                stored.extend(answer_store(&mut association, &request, options, &peer)?)
+            }
+            field => {
+                let code = if field == command::C_ECHO_RQ {
+                    status::SUCCESS
+                } else {
+                    status::UNRECOGNIZED_OPERATION
+                };
+                let response = dimse::response_to(&request, code, false);
+                dimse::send_message(&mut association, request.pc_id, response, None)?;
+            }
+        }
+    }
+    association.into_inner();
+    Ok(stored)
+}
+
+/// Read retrieve responses until the final one, storing the C-STORE sub-operations that
+/// arrive on the same association meanwhile (C-GET). Pending responses go to `progress`.
+fn await_retrieve(
+    channel: &mut dyn DimseChannel,
+    options: &RetrieveOptions,
+    peer: &str,
+    progress: &dyn ProgressSink,
+) -> Result<RetrieveSummary> {
+    let count = |msg: &DimseMessage, tag: Tag| msg.command.element_u32(tag).unwrap_or(0) as u16;
+    let mut stored = Vec::new();
+    loop {
+        let msg = dimse::read_message(channel)
+            .context("Failed to receive retrieve response")?
+            .context("Association released before the final retrieve response")?;
+        if msg.command_field() == command::C_STORE_RQ {
+            stored.extend(answer_store(channel, &msg, options, peer)?);
+            continue;
+        }
+        let code = msg.status().context("Retrieve response without status")?;
+        let (completed, failed, warning) = (
+            count(&msg, COMPLETED_SUBOPERATIONS),
+            count(&msg, FAILED_SUBOPERATIONS),
+            count(&msg, WARNING_SUBOPERATIONS),
+        );
+        let finished = completed as u64 + failed as u64 + warning as u64;
+        let remaining = count(&msg, REMAINING_SUBOPERATIONS) as u64;
+        progress.report(ProgressEvent::new(
+            "retrieve",
+            finished,
+            finished + remaining,
+        ));
+        if code != status::PENDING {
+            return Ok(RetrieveSummary {
+                status: code,
+                completed,
+                failed,
+                warning,
+                stored,
+            });
+        }
+    }
+}
+
+/// Write the instance of a C-STORE-RQ and answer it; the path is returned when it was stored.
+fn answer_store(
+    channel: &mut dyn DimseChannel,
+    request: &DimseMessage,
+    options: &RetrieveOptions,
+    peer: &str,
+) -> Result<Option<PathBuf>> {
+    let stored = store_received(channel, request, options, peer)
+        .map_err(|err| eprintln!("Failed to store instance from {}: {:#}", peer, err))
+        .ok();
+    let code = if stored.is_some() {
+        status::SUCCESS
+    } else {
+        status::UNABLE_TO_PROCESS
+    };
+    let mut response = dimse::response_to(request, code, false);
+    if let Some(uid) = request.command.element_str(AFFECTED_SOP_INSTANCE_UID) {
+        response.put(DataElement::new(
+            AFFECTED_SOP_INSTANCE_UID,
+            VR::UI,
+            PrimitiveValue::from(uid.trim_end_matches('\0')),
+        ));
+    }
+    dimse::send_message(channel, request.pc_id, response, None)?;
+    Ok(stored)
+}
+
+/// Write a received instance as `<SOP Instance UID>.dcm` in the output directory.
+fn store_received(
+    channel: &dyn DimseChannel,
+    request: &DimseMessage,
+    options: &RetrieveOptions,
+    peer: &str,
+) -> Result<PathBuf> {
+    let sop_instance = request
+        .command
+        .element_str(AFFECTED_SOP_INSTANCE_UID)
+        .unwrap_or_default();
+    let sop_instance = sop_instance.trim_end_matches(['\0', ' ']);
+    // The UID becomes a file name; anything but a well-formed UID could escape the directory.
+    if sop_instance.is_empty() || !sop_instance.chars().all(|c| c.is_ascii_digit() || c == '.') {
+        bail!("Invalid SOP Instance UID '{}'", sop_instance);
+    }
+    let sop_class = request
+        .command
+        .element_str(AFFECTED_SOP_CLASS_UID)
+        .unwrap_or_default();
+    let dataset = request
+        .dataset(channel)?
+        .context("C-STORE-RQ carried no data set")?;
+    let ts = dimse::negotiated_ts(channel, request.pc_id)?;
+    let obj = dataset
+        .with_meta(
+            FileMetaTableBuilder::new()
+                .transfer_syntax(ts.uid())
+                .media_storage_sop_class_uid(sop_class.trim_end_matches('\0'))
+                .media_storage_sop_instance_uid(sop_instance)
+                .source_application_entity_title(peer),
+        )
+        .context("Failed to build file meta for received instance")?;
+    let path = options.output.join(format!("{}.dcm", sop_instance));
+    atomic_file::write_dicom(&path, &obj)?;
+    Ok(path)
+}
+
+/// The transfer syntaxes dicom-ul proposes by default, for the trace.
+pub(crate) fn default_proposal(abstract_syntax: &str) -> Vec<(String, Vec<String>)> {
+    vec![(
+        abstract_syntax.to_string(),
+        vec![
+            EXPLICIT_VR_LITTLE_ENDIAN.to_string(),
+            IMPLICIT_VR_LITTLE_ENDIAN.to_string(),
+        ],
+    )]
+}
+
+pub(crate) fn accepted_context(channel: &dyn DimseChannel) -> Option<u8> {
+    channel
+        .contexts()
+        .iter()
+        .find(|pc| pc.reason == PresentationContextResultReason::Acceptance)
+        .map(|pc| pc.id)
+}
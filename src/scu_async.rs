@@ -0,0 +1,155 @@
+//
+// scu_async.rs
+// Dicom-Tools-rs
+//
+// Tokio front end to the SCU operations: each exchange runs on the blocking thread pool so
+// the runtime keeps serving other tasks, which lets web handlers and concurrent pushes share it.
+//
+// Thales Matheus Mendonça Santos - November 2025
+
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use dicom::object::InMemDicomObject;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+
+use crate::dimse::AssociationSettings;
+use crate::dimse_trace::DimseTracer;
+use crate::progress::{NoProgress, ProgressSink};
+use crate::scu::{self, PushDirOptions, PushDirSummary};
+
+/// Run a blocking SCU exchange off the async executor. Dropping the returned future does not
+/// stop an exchange already under way; it runs to completion on its thread.
+async fn off_runtime<T, F>(job: F) -> Result<T>
+where
+    T: Send + 'static,
+    F: FnOnce() -> Result<T> + Send + 'static,
+{
+    tokio::task::spawn_blocking(job)
+        .await
+        .context("SCU task panicked")?
+}
+
+/// [`scu::echo_traced`] without blocking the runtime.
+pub async fn echo(
+    addr: String,
+    settings: AssociationSettings,
+    tracer: Option<Arc<DimseTracer>>,
+) -> Result<()> {
+    off_runtime(move || scu::echo_traced(&addr, &settings, tracer)).await
+}
+
+/// [`scu::push_status`] without blocking the runtime; returns the C-STORE-RSP status.
+pub async fn push(
+    addr: String,
+    file: PathBuf,
+    settings: AssociationSettings,
+    progress: Arc<dyn ProgressSink>,
+    tracer: Option<Arc<DimseTracer>>,
+) -> Result<u16> {
+    off_runtime(move || scu::push_status(&addr, &file, &settings, progress.as_ref(), tracer)).await
+}
+
+/// [`scu::push_dir`] without blocking the runtime.
+pub async fn push_dir(
+    addr: String,
+    dir: PathBuf,
+    options: PushDirOptions,
+    progress: Arc<dyn ProgressSink>,
+    tracer: Option<Arc<DimseTracer>>,
+) -> Result<PushDirSummary> {
+    off_runtime(move || scu::push_dir(&addr, &dir, &options, progress.as_ref(), tracer)).await
+}
+
+/// [`scu::find`] without blocking the runtime.
+pub async fn find(
+    addr: String,
+    settings: AssociationSettings,
+    sop_class: String,
+    identifier: InMemDicomObject,
+    tracer: Option<Arc<DimseTracer>>,
+) -> Result<Vec<InMemDicomObject>> {
+    off_runtime(move || scu::find(&addr, &settings, &sop_class, &identifier, tracer)).await
+}
+
+/// [`scu::n_create`] without blocking the runtime.
+pub async fn n_create(
+    addr: String,
+    settings: AssociationSettings,
+    sop_class: String,
+    sop_instance: String,
+    attributes: InMemDicomObject,
+    tracer: Option<Arc<DimseTracer>>,
+) -> Result<()> {
+    off_runtime(move || {
+        scu::n_create(
+            &addr,
+            &settings,
+            &sop_class,
+            &sop_instance,
+            &attributes,
+            tracer,
+        )
+    })
+    .await
+}
+
+/// [`scu::n_set`] without blocking the runtime.
+pub async fn n_set(
+    addr: String,
+    settings: AssociationSettings,
+    sop_class: String,
+    sop_instance: String,
+    modifications: InMemDicomObject,
+    tracer: Option<Arc<DimseTracer>>,
+) -> Result<()> {
+    off_runtime(move || {
+        scu::n_set(
+            &addr,
+            &settings,
+            &sop_class,
+            &sop_instance,
+            &modifications,
+            tracer,
+        )
+    })
+    .await
+}
+
+/// Push each file on its own association, at most `concurrency` at a time. Outcomes come back
+/// in the order of `files`.
+pub async fn push_files(
+    addr: &str,
+    files: Vec<PathBuf>,
+    settings: &AssociationSettings,
+    concurrency: usize,
+) -> Vec<(PathBuf, Result<u16>)> {
+    let permits = Arc::new(Semaphore::new(concurrency.max(1)));
+    let mut tasks = JoinSet::new();
+    for (index, file) in files.iter().cloned().enumerate() {
+        let permits = Arc::clone(&permits);
+        let (addr, settings) = (addr.to_string(), settings.clone());
+        tasks.spawn(async move {
+            let _permit = permits.acquire_owned().await;
+            let outcome = push(addr, file, settings, Arc::new(NoProgress), None).await;
+            (index, outcome)
+        });
+    }
+    let mut outcomes: Vec<Option<Result<u16>>> = files.iter().map(|_| None).collect();
+    while let Some(joined) = tasks.join_next().await {
+        if let Ok((index, outcome)) = joined {
+            outcomes[index] = Some(outcome);
+        }
+    }
+    files
+        .into_iter()
+        .zip(outcomes)
+        .map(|(file, outcome)| {
+            let outcome =
+                outcome.unwrap_or_else(|| Err(anyhow::anyhow!("Push of {:?} panicked", file)));
+            (file, outcome)
+        })
+        .collect()
+}
@@ -0,0 +1,507 @@
+//
+// send_queue.rs
+// Dicom-Tools-rs
+//
+// Persistent send queue: instances a C-STORE or STOW-RS push could not deliver for a
+// transient reason (network failure, out of resources) are copied to a queue directory and
+// retried later with exponential backoff, so a large push survives a PACS outage.
+//
+// Thales Matheus Mendonça Santos - November 2025
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::atomic_file;
+use crate::dimse::AssociationSettings;
+use crate::progress::NoProgress;
+use crate::{scu, stow};
+
+/// Where a queued instance goes.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum Destination {
+    /// C-STORE to a DICOM node.
+    Dimse {
+        addr: String,
+        calling_ae_title: String,
+        called_ae_title: String,
+        max_pdu_length: u32,
+        /// Seconds.
+        connect_timeout: Option<u64>,
+        read_timeout: Option<u64>,
+    },
+    /// STOW-RS to a DICOMweb service root. The token is not persisted; it is supplied again
+    /// when the queue is drained.
+    Stow { url: String },
+}
+
+impl Destination {
+    pub fn dimse(addr: &str, settings: &AssociationSettings) -> Self {
+        Destination::Dimse {
+            addr: addr.to_string(),
+            calling_ae_title: settings.calling_ae_title.clone(),
+            called_ae_title: settings.called_ae_title.clone(),
+            max_pdu_length: settings.max_pdu_length,
+            connect_timeout: settings.connect_timeout.map(|t| t.as_secs()),
+            read_timeout: settings.read_timeout.map(|t| t.as_secs()),
+        }
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            Destination::Dimse {
+                addr,
+                called_ae_title,
+                ..
+            } => format!("{}@{}", called_ae_title, addr),
+            Destination::Stow { url } => url.clone(),
+        }
+    }
+}
+
+/// Backoff between attempts: `initial_delay` after the first failure, doubling up to
+/// `max_delay`; after `max_attempts` failures the instance is moved to the failed list.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+    pub max_attempts: u32,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            initial_delay: Duration::from_secs(30),
+            max_delay: Duration::from_secs(3600),
+            max_attempts: 10,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Delay before the next attempt after `attempts` failed ones.
+    pub fn delay(&self, attempts: u32) -> Duration {
+        let doublings = attempts.saturating_sub(1).min(31);
+        self.initial_delay
+            .saturating_mul(1 << doublings)
+            .min(self.max_delay)
+    }
+}
+
+/// A queued instance, stored as `<id>.json` next to its copy `<id>.dcm`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QueuedSend {
+    pub id: String,
+    pub sop_instance_uid: String,
+    /// Where the instance was originally read from, for reports.
+    pub source: PathBuf,
+    pub destination: Destination,
+    /// Failed attempts so far, the original push included.
+    pub attempts: u32,
+    /// Seconds since the Unix epoch.
+    pub queued_at: u64,
+    pub next_attempt: u64,
+    pub last_error: String,
+}
+
+/// Result of one delivery attempt.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SendOutcome {
+    Sent,
+    /// Worth retrying later.
+    Transient(String),
+    /// The destination refused the instance; retrying would not help.
+    Permanent(String),
+}
+
+/// Outcome of [`SendQueue::drain`].
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct DrainSummary {
+    pub sent: usize,
+    /// Still queued for a later attempt.
+    pub retrying: usize,
+    /// Moved to the failed list during this drain.
+    pub failed: usize,
+}
+
+/// C-STORE statuses (0xA7xx, out of resources) that may succeed later.
+pub fn is_transient_status(code: u16) -> bool {
+    code & 0xFF00 == 0xA700
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// Queue directory: `pending/` holds instances awaiting a retry, `failed/` those that ran
+/// out of attempts or were refused for good.
+#[derive(Debug, Clone)]
+pub struct SendQueue {
+    root: PathBuf,
+    policy: RetryPolicy,
+}
+
+impl SendQueue {
+    pub fn open(root: impl Into<PathBuf>) -> Result<Self> {
+        let root = root.into();
+        for dir in ["pending", "failed"] {
+            fs::create_dir_all(root.join(dir))
+                .with_context(|| format!("Failed to create the send queue in {:?}", root))?;
+        }
+        Ok(Self {
+            root,
+            policy: RetryPolicy::default(),
+        })
+    }
+
+    pub fn with_policy(mut self, policy: RetryPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    fn path(&self, list: &str, id: &str, extension: &str) -> PathBuf {
+        self.root.join(list).join(format!("{}.{}", id, extension))
+    }
+
+    fn save(&self, list: &str, entry: &QueuedSend) -> Result<()> {
+        atomic_file::write(
+            &self.path(list, &entry.id, "json"),
+            &serde_json::to_vec_pretty(entry)?,
+        )
+    }
+
+    /// Copy `file` into the queue for a later attempt at `destination`. Queuing the same
+    /// instance for the same destination again replaces the previous entry.
+    pub fn enqueue(
+        &self,
+        file: &Path,
+        source: &Path,
+        sop_instance_uid: &str,
+        destination: Destination,
+        error: &str,
+    ) -> Result<QueuedSend> {
+        let mut hasher = Sha256::new();
+        hasher.update(serde_json::to_vec(&destination)?);
+        hasher.update(sop_instance_uid.as_bytes());
+        let id = hex::encode(&hasher.finalize()[..16]);
+        let bytes = fs::read(file).with_context(|| format!("Failed to read {:?}", file))?;
+        atomic_file::write(&self.path("pending", &id, "dcm"), &bytes)?;
+        let queued_at = now();
+        let entry = QueuedSend {
+            id,
+            sop_instance_uid: sop_instance_uid.to_string(),
+            source: source.to_path_buf(),
+            destination,
+            attempts: 1,
+            queued_at,
+            next_attempt: queued_at + self.policy.delay(1).as_secs(),
+            last_error: error.to_string(),
+        };
+        self.save("pending", &entry)?;
+        Ok(entry)
+    }
+
+    fn list(&self, list: &str) -> Result<Vec<QueuedSend>> {
+        let mut entries = Vec::new();
+        for item in fs::read_dir(self.root.join(list))? {
+            let path = item?.path();
+            if path.extension().is_some_and(|ext| ext == "json") {
+                let text = fs::read(&path).with_context(|| format!("Failed to read {:?}", path))?;
+                entries.push(
+                    serde_json::from_slice(&text)
+                        .with_context(|| format!("Corrupt queue entry {:?}", path))?,
+                );
+            }
+        }
+        entries.sort_by(|a: &QueuedSend, b| (a.next_attempt, &a.id).cmp(&(b.next_attempt, &b.id)));
+        Ok(entries)
+    }
+
+    /// Instances awaiting a retry, soonest first.
+    pub fn pending(&self) -> Result<Vec<QueuedSend>> {
+        self.list("pending")
+    }
+
+    /// Instances given up on.
+    pub fn failed(&self) -> Result<Vec<QueuedSend>> {
+        self.list("failed")
+    }
+
+    fn move_to_failed(&self, entry: &QueuedSend) -> Result<()> {
+        fs::rename(
+            self.path("pending", &entry.id, "dcm"),
+            self.path("failed", &entry.id, "dcm"),
+        )?;
+        self.save("failed", entry)?;
+        fs::remove_file(self.path("pending", &entry.id, "json"))?;
+        Ok(())
+    }
+
+    /// Attempt every due instance with `send`, rescheduling transient failures and moving
+    /// permanent ones (or those out of attempts) to the failed list. With `wait`, sleeps
+    /// until the next instance is due and keeps going until the queue is empty.
+    pub fn drain(
+        &self,
+        send: &mut dyn FnMut(&QueuedSend, &Path) -> SendOutcome,
+        wait: bool,
+    ) -> Result<DrainSummary> {
+        let mut summary = DrainSummary::default();
+        loop {
+            let pending = self.pending()?;
+            let current = now();
+            for entry in pending.iter().filter(|e| e.next_attempt <= current) {
+                let mut entry = entry.clone();
+                match send(&entry, &self.path("pending", &entry.id, "dcm")) {
+                    SendOutcome::Sent => {
+                        fs::remove_file(self.path("pending", &entry.id, "dcm"))?;
+                        fs::remove_file(self.path("pending", &entry.id, "json"))?;
+                        summary.sent += 1;
+                    }
+                    SendOutcome::Transient(error) => {
+                        entry.attempts += 1;
+                        entry.last_error = error;
+                        if entry.attempts >= self.policy.max_attempts {
+                            self.move_to_failed(&entry)?;
+                            summary.failed += 1;
+                        } else {
+                            entry.next_attempt =
+                                now() + self.policy.delay(entry.attempts).as_secs();
+                            self.save("pending", &entry)?;
+                        }
+                    }
+                    SendOutcome::Permanent(error) => {
+                        entry.attempts += 1;
+                        entry.last_error = error;
+                        self.move_to_failed(&entry)?;
+                        summary.failed += 1;
+                    }
+                }
+            }
+            let pending = self.pending()?;
+            summary.retrying = pending.len();
+            let Some(next) = pending.first().filter(|_| wait) else {
+                return Ok(summary);
+            };
+            let current = now();
+            if next.next_attempt > current {
+                thread::sleep(Duration::from_secs(next.next_attempt - current));
+            }
+        }
+    }
+}
+
+/// Credentials and limits for retried deliveries.
+#[derive(Debug, Clone, Default)]
+pub struct DeliveryOptions {
+    /// Bearer token for STOW-RS destinations.
+    pub token: Option<String>,
+    pub timeout: Option<Duration>,
+}
+
+/// Send the queued copy `file` of `entry` to its destination.
+pub fn deliver(entry: &QueuedSend, file: &Path, options: &DeliveryOptions) -> SendOutcome {
+    match &entry.destination {
+        Destination::Dimse {
+            addr,
+            calling_ae_title,
+            called_ae_title,
+            max_pdu_length,
+            connect_timeout,
+            read_timeout,
+        } => {
+            let settings = AssociationSettings {
+                calling_ae_title: calling_ae_title.clone(),
+                called_ae_title: called_ae_title.clone(),
+                max_pdu_length: *max_pdu_length,
+                connect_timeout: connect_timeout.map(Duration::from_secs),
+                read_timeout: read_timeout.map(Duration::from_secs),
+                ..AssociationSettings::default()
+            };
+            match scu::push_status(addr, file, &settings, &NoProgress, None) {
+                // 0xB000, 0xB006, 0xB007: stored with coercion or element discards.
+                Ok(code) if code == 0 || code & 0xF000 == 0xB000 => SendOutcome::Sent,
+                Ok(code) if is_transient_status(code) => {
+                    SendOutcome::Transient(format!("status 0x{:04X}", code))
+                }
+                Ok(code) => SendOutcome::Permanent(format!("status 0x{:04X}", code)),
+                Err(err) => SendOutcome::Transient(format!("{:#}", err)),
+            }
+        }
+        Destination::Stow { url } => {
+            let stow_options = stow::StowOptions {
+                url: url.clone(),
+                token: options.token.clone(),
+                batch_size: 1,
+                timeout: options.timeout,
+                rate_limit: None,
+                window: None,
+            };
+            match stow::store(&[file.to_path_buf()], &stow_options) {
+                Ok(summary) if !summary.accepted.is_empty() => SendOutcome::Sent,
+                Ok(summary) => match summary.rejected.first() {
+                    Some(rejected) if rejected.is_transient() => {
+                        SendOutcome::Transient(rejected.reason())
+                    }
+                    Some(rejected) => SendOutcome::Permanent(rejected.reason()),
+                    None => SendOutcome::Permanent("not a DICOM file".to_string()),
+                },
+                Err(err) => SendOutcome::Permanent(format!("{:#}", err)),
+            }
+        }
+    }
+}
+
+/// CLI helper: list the pending and failed instances.
+pub fn print_queue(queue: &SendQueue) -> Result<()> {
+    let pending = queue.pending()?;
+    let failed = queue.failed()?;
+    println!(
+        "Send queue {:?}: {} pending, {} failed",
+        queue.root(),
+        pending.len(),
+        failed.len()
+    );
+    let current = now();
+    for entry in &pending {
+        println!(
+            "  pending  {} -> {} (attempt {}, due in {} s): {}",
+            entry.source.display(),
+            entry.destination.describe(),
+            entry.attempts + 1,
+            entry.next_attempt.saturating_sub(current),
+            entry.last_error
+        );
+    }
+    for entry in &failed {
+        println!(
+            "  failed   {} -> {} after {} attempt(s): {}",
+            entry.source.display(),
+            entry.destination.describe(),
+            entry.attempts,
+            entry.last_error
+        );
+    }
+    Ok(())
+}
+
+/// CLI helper: drain the queue and report the outcome.
+pub fn print_drain(
+    queue: &SendQueue,
+    options: &DeliveryOptions,
+    wait: bool,
+) -> Result<DrainSummary> {
+    let summary = queue.drain(
+        &mut |entry, file| {
+            let outcome = deliver(entry, file, options);
+            match &outcome {
+                SendOutcome::Sent => println!("Sent {}", entry.source.display()),
+                SendOutcome::Transient(error) => {
+                    eprintln!("Retrying {} later: {}", entry.source.display(), error)
+                }
+                SendOutcome::Permanent(error) => {
+                    eprintln!("Giving up on {}: {}", entry.source.display(), error)
+                }
+            }
+            outcome
+        },
+        wait,
+    )?;
+    println!(
+        "{} sent, {} still queued, {} failed",
+        summary.sent, summary.retrying, summary.failed
+    );
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_doubles_up_to_the_cap() {
+        let policy = RetryPolicy {
+            initial_delay: Duration::from_secs(10),
+            max_delay: Duration::from_secs(60),
+            max_attempts: 5,
+        };
+        let delays: Vec<u64> = (1..=5).map(|n| policy.delay(n).as_secs()).collect();
+        assert_eq!(delays, [10, 20, 40, 60, 60]);
+    }
+
+    #[test]
+    fn drained_entries_are_sent_rescheduled_or_given_up() {
+        let dir = tempfile::tempdir().unwrap();
+        let queue = SendQueue::open(dir.path().join("queue"))
+            .unwrap()
+            .with_policy(RetryPolicy {
+                initial_delay: Duration::ZERO,
+                max_delay: Duration::ZERO,
+                max_attempts: 3,
+            });
+        let file = dir.path().join("a.dcm");
+        fs::write(&file, b"dicom").unwrap();
+        let stow = Destination::Stow {
+            url: "http://pacs/dicomweb".to_string(),
+        };
+        queue
+            .enqueue(&file, &file, "1.2.1", stow.clone(), "down")
+            .unwrap();
+        queue
+            .enqueue(&file, &file, "1.2.2", stow.clone(), "down")
+            .unwrap();
+        // Queuing again replaces the entry.
+        queue.enqueue(&file, &file, "1.2.2", stow, "down").unwrap();
+        assert_eq!(queue.pending().unwrap().len(), 2);
+
+        let first = queue
+            .drain(
+                &mut |entry, file| {
+                    assert_eq!(fs::read(file).unwrap(), b"dicom");
+                    if entry.sop_instance_uid == "1.2.1" {
+                        SendOutcome::Sent
+                    } else {
+                        SendOutcome::Transient("busy".to_string())
+                    }
+                },
+                false,
+            )
+            .unwrap();
+        assert_eq!(
+            first,
+            DrainSummary {
+                sent: 1,
+                retrying: 1,
+                failed: 0
+            }
+        );
+        let retried = queue.pending().unwrap();
+        assert_eq!(retried[0].attempts, 2);
+        assert_eq!(retried[0].last_error, "busy");
+
+        let second = queue
+            .drain(&mut |_, _| SendOutcome::Transient("busy".to_string()), true)
+            .unwrap();
+        assert_eq!(second.failed, 1);
+        assert!(queue.pending().unwrap().is_empty());
+        let failed = queue.failed().unwrap();
+        assert_eq!(failed[0].attempts, 3);
+        assert!(dir
+            .path()
+            .join("queue/failed")
+            .join(format!("{}.dcm", failed[0].id))
+            .exists());
+    }
+}
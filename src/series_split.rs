@@ -0,0 +1,381 @@
+//
+// series_split.rs
+// Dicom-Tools-rs
+//
+// Splits mixed series (multi-echo MR, multi-phase CT, diffusion b-values dumped into one
+// series) into one logical series per Echo Time, Temporal Position Identifier or Diffusion
+// b-value, giving each part its own Series Instance UID, Series Number and description.
+//
+// Thales Matheus Mendonça Santos - November 2025
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use dicom::core::{DataElement, PrimitiveValue, Tag, VR};
+use dicom::object::{open_file, InMemDicomObject, OpenFileOptions};
+use rayon::prelude::*;
+use serde::Serialize;
+use walkdir::WalkDir;
+
+use crate::atomic_file;
+use crate::dicom_access::{hashed_uid, ElementAccess};
+
+const ECHO_TIME: Tag = Tag(0x0018, 0x0081);
+const DIFFUSION_B_VALUE: Tag = Tag(0x0018, 0x9087);
+const SERIES_DESCRIPTION: Tag = Tag(0x0008, 0x103E);
+const SERIES_INSTANCE_UID: Tag = Tag(0x0020, 0x000E);
+const SERIES_NUMBER: Tag = Tag(0x0020, 0x0011);
+const TEMPORAL_POSITION_IDENTIFIER: Tag = Tag(0x0020, 0x0100);
+const PIXEL_DATA: Tag = Tag(0x7FE0, 0x0010);
+
+/// Attribute telling the logical series of a mixed series apart.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum SplitKey {
+    EchoTime,
+    TemporalPosition,
+    BValue,
+}
+
+impl SplitKey {
+    pub const ALL: [SplitKey; 3] = [
+        SplitKey::EchoTime,
+        SplitKey::TemporalPosition,
+        SplitKey::BValue,
+    ];
+
+    pub fn tag(self) -> Tag {
+        match self {
+            SplitKey::EchoTime => ECHO_TIME,
+            SplitKey::TemporalPosition => TEMPORAL_POSITION_IDENTIFIER,
+            SplitKey::BValue => DIFFUSION_B_VALUE,
+        }
+    }
+
+    pub fn keyword(self) -> &'static str {
+        match self {
+            SplitKey::EchoTime => "EchoTime",
+            SplitKey::TemporalPosition => "TemporalPositionIdentifier",
+            SplitKey::BValue => "DiffusionBValue",
+        }
+    }
+
+    /// Suffix appended to the Series Description of the part holding `value`.
+    fn label(self, value: Option<f64>) -> String {
+        match (self, value) {
+            (_, None) => format!("(no {})", self.keyword()),
+            (SplitKey::EchoTime, Some(v)) => format!("(TE {})", v),
+            (SplitKey::TemporalPosition, Some(v)) => format!("(phase {})", v),
+            (SplitKey::BValue, Some(v)) => format!("(b={})", v),
+        }
+    }
+
+    /// The key value of `obj`, `None` when absent or not numeric.
+    fn value(self, obj: &InMemDicomObject) -> Option<f64> {
+        obj.element_str(self.tag())?
+            .split('\\')
+            .next()?
+            .trim_matches(['\0', ' '])
+            .parse()
+            .ok()
+    }
+}
+
+/// One logical series carved out of a mixed one.
+#[derive(Debug, Clone, Serialize)]
+pub struct SplitPart {
+    /// Key value; `None` for the instances that lack it.
+    pub value: Option<f64>,
+    pub series_instance_uid: String,
+    pub series_number: u32,
+    pub series_description: String,
+    pub files: Vec<PathBuf>,
+}
+
+/// How one input series was split.
+#[derive(Debug, Clone, Serialize)]
+pub struct SeriesSplit {
+    pub series_instance_uid: String,
+    pub key: SplitKey,
+    pub parts: Vec<SplitPart>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SplitSummary {
+    pub split: Vec<SeriesSplit>,
+    /// Files of series with a single value of every candidate key, copied as they are.
+    pub unchanged_files: usize,
+    /// Non-DICOM files, left out.
+    pub skipped: usize,
+}
+
+/// Header facts of one file.
+struct Member {
+    relative: PathBuf,
+    number: Option<u32>,
+    description: String,
+    values: [Option<f64>; 3],
+}
+
+fn distinct(values: impl Iterator<Item = Option<f64>>) -> usize {
+    let mut seen: Vec<Option<f64>> = Vec::new();
+    for value in values {
+        if !seen.contains(&value) {
+            seen.push(value);
+        }
+    }
+    seen.len()
+}
+
+/// The key splitting `members`: `key` when given and varying, otherwise the first of
+/// [`SplitKey::ALL`] taking more than one value. `None` leaves the series whole.
+fn choose_key(members: &[Member], key: Option<SplitKey>) -> Option<SplitKey> {
+    let varies = |key: SplitKey| {
+        let index = SplitKey::ALL.iter().position(|k| *k == key).unwrap_or(0);
+        distinct(members.iter().map(|m| m.values[index])) > 1
+    };
+    match key {
+        Some(key) => varies(key).then_some(key),
+        None => SplitKey::ALL.into_iter().find(|key| varies(*key)),
+    }
+}
+
+/// Plan the parts of one series: ascending key values, instances without one last. Each
+/// part gets a UID derived from the original and its value (so reruns agree) and Series
+/// Number `original × 100 + n`.
+fn plan(series_uid: &str, members: &[Member], key: SplitKey) -> SeriesSplit {
+    let index = SplitKey::ALL.iter().position(|k| *k == key).unwrap_or(0);
+    let mut groups: Vec<(Option<f64>, Vec<&Member>)> = Vec::new();
+    for member in members {
+        let value = member.values[index];
+        match groups.iter_mut().find(|(v, _)| *v == value) {
+            Some((_, group)) => group.push(member),
+            None => groups.push((value, vec![member])),
+        }
+    }
+    groups.sort_by(|(a, _), (b, _)| match (a, b) {
+        (Some(a), Some(b)) => a.total_cmp(b),
+        (a, b) => b.is_some().cmp(&a.is_some()),
+    });
+    let base = members.iter().find_map(|m| m.number).unwrap_or(0);
+    let parts = groups
+        .into_iter()
+        .enumerate()
+        .map(|(n, (value, group))| {
+            let label = key.label(value);
+            let original = group[0].description.trim();
+            let mut description = if original.is_empty() {
+                label.clone()
+            } else {
+                format!("{} {}", original, label)
+            };
+            // Series Description is LO: 64 characters at most, keep the label.
+            if description.chars().count() > 64 {
+                let keep = 64usize.saturating_sub(label.chars().count() + 1);
+                let head: String = original.chars().take(keep).collect();
+                description = format!("{} {}", head.trim_end(), label);
+            }
+            SplitPart {
+                value,
+                series_instance_uid: hashed_uid(&format!(
+                    "split|{}|{}|{:?}",
+                    series_uid,
+                    key.keyword(),
+                    value
+                )),
+                series_number: base * 100 + n as u32 + 1,
+                series_description: description,
+                files: group.iter().map(|m| m.relative.clone()).collect(),
+            }
+        })
+        .collect();
+    SeriesSplit {
+        series_instance_uid: series_uid.to_string(),
+        key,
+        parts,
+    }
+}
+
+/// Split the mixed series under `dir` into `output`, at the same relative paths. Without
+/// `output` (a dry run) only the plan is computed. `key: None` picks, per series, the first
+/// candidate key that varies.
+pub fn split_directory(
+    dir: &Path,
+    key: Option<SplitKey>,
+    output: Option<&Path>,
+) -> Result<SplitSummary> {
+    if !dir.is_dir() {
+        bail!("{:?} is not a directory", dir);
+    }
+    if let Some(output) = output.filter(|o| o.starts_with(dir) || dir.starts_with(o)) {
+        bail!(
+            "The output {:?} must not overlap the input {:?}",
+            output,
+            dir
+        );
+    }
+    let mut paths: Vec<PathBuf> = WalkDir::new(dir)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file())
+        .map(|e| e.into_path())
+        .collect();
+    paths.sort();
+
+    let mut summary = SplitSummary::default();
+    let mut series: BTreeMap<String, Vec<Member>> = BTreeMap::new();
+    for path in &paths {
+        let Ok(header) = OpenFileOptions::new()
+            .read_until(PIXEL_DATA)
+            .open_file(path)
+        else {
+            summary.skipped += 1;
+            continue;
+        };
+        let text = |tag| {
+            header
+                .element_str(tag)
+                .map(|v| v.trim_matches(['\0', ' ']).to_string())
+                .unwrap_or_default()
+        };
+        series
+            .entry(text(SERIES_INSTANCE_UID))
+            .or_default()
+            .push(Member {
+                relative: path.strip_prefix(dir).unwrap_or(path).to_path_buf(),
+                number: text(SERIES_NUMBER).parse().ok(),
+                description: text(SERIES_DESCRIPTION),
+                values: SplitKey::ALL.map(|key| key.value(&header)),
+            });
+    }
+
+    let mut unchanged = Vec::new();
+    for (uid, members) in &series {
+        match choose_key(members, key) {
+            Some(key) => summary.split.push(plan(uid, members, key)),
+            None => unchanged.extend(members.iter().map(|m| m.relative.clone())),
+        }
+    }
+    summary.unchanged_files = unchanged.len();
+
+    let Some(output) = output else {
+        return Ok(summary);
+    };
+    let rewrites: Vec<(&PathBuf, &SplitPart)> = summary
+        .split
+        .iter()
+        .flat_map(|split| split.parts.iter())
+        .flat_map(|part| part.files.iter().map(move |file| (file, part)))
+        .collect();
+    rewrites
+        .par_iter()
+        .map(|(relative, part)| rewrite(&dir.join(relative), &output.join(relative), part))
+        .collect::<Result<Vec<_>>>()?;
+    unchanged
+        .par_iter()
+        .map(|relative| {
+            let target = output.join(relative);
+            if let Some(parent) = target.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::copy(dir.join(relative), &target)
+                .with_context(|| format!("Failed to copy {:?}", relative))?;
+            Ok(())
+        })
+        .collect::<Result<Vec<_>>>()?;
+    Ok(summary)
+}
+
+fn rewrite(path: &Path, target: &Path, part: &SplitPart) -> Result<()> {
+    let mut obj = open_file(path).with_context(|| format!("Failed to open {:?}", path))?;
+    obj.put(DataElement::new(
+        SERIES_INSTANCE_UID,
+        VR::UI,
+        PrimitiveValue::from(part.series_instance_uid.as_str()),
+    ));
+    obj.put(DataElement::new(
+        SERIES_NUMBER,
+        VR::IS,
+        PrimitiveValue::from(part.series_number.to_string()),
+    ));
+    obj.put(DataElement::new(
+        SERIES_DESCRIPTION,
+        VR::LO,
+        PrimitiveValue::from(part.series_description.as_str()),
+    ));
+    if let Some(parent) = target.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    atomic_file::write_dicom(target, &obj)
+}
+
+/// CLI entry point; `output: None` is a dry run.
+pub fn print_split(dir: &Path, key: Option<SplitKey>, output: Option<&Path>) -> Result<()> {
+    let summary = split_directory(dir, key, output)?;
+    for split in &summary.split {
+        println!(
+            "Series {} split by {} into {} series:",
+            split.series_instance_uid,
+            split.key.keyword(),
+            split.parts.len()
+        );
+        for part in &split.parts {
+            println!(
+                "  #{:<6} {:<40} {} file(s)  {}",
+                part.series_number,
+                part.series_description,
+                part.files.len(),
+                part.series_instance_uid
+            );
+        }
+    }
+    println!(
+        "{} series split, {} file(s) of other series unchanged, {} non-DICOM file(s) left out",
+        summary.split.len(),
+        summary.unchanged_files,
+        summary.skipped
+    );
+    match output {
+        Some(output) => println!("Split copies in {:?}", output),
+        None => println!("Dry run: nothing written"),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn member(name: &str, echo: Option<f64>, phase: Option<f64>) -> Member {
+        Member {
+            relative: PathBuf::from(name),
+            number: Some(7),
+            description: "T2 MAP".to_string(),
+            values: [echo, phase, None],
+        }
+    }
+
+    #[test]
+    fn the_first_varying_key_splits_and_missing_values_go_last() {
+        let members = vec![
+            member("a", Some(80.0), Some(1.0)),
+            member("b", None, Some(1.0)),
+            member("c", Some(10.0), Some(1.0)),
+            member("d", Some(80.0), Some(1.0)),
+        ];
+        assert_eq!(choose_key(&members, None), Some(SplitKey::EchoTime));
+        assert_eq!(choose_key(&members, Some(SplitKey::TemporalPosition)), None);
+
+        let split = plan("1.2.3", &members, SplitKey::EchoTime);
+        let numbers: Vec<u32> = split.parts.iter().map(|p| p.series_number).collect();
+        assert_eq!(numbers, [701, 702, 703]);
+        assert_eq!(split.parts[0].series_description, "T2 MAP (TE 10)");
+        assert_eq!(split.parts[1].files.len(), 2);
+        assert_eq!(split.parts[2].value, None);
+        assert_eq!(
+            split.parts[0].series_instance_uid,
+            plan("1.2.3", &members, SplitKey::EchoTime).parts[0].series_instance_uid
+        );
+    }
+}
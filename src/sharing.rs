@@ -0,0 +1,173 @@
+//
+// sharing.rs
+// Dicom-Tools-rs
+//
+// Signed, expiring share tokens granting access to one stored file (download or preview)
+// without an account.
+//
+// Thales Matheus Mendonça Santos - November 2025
+
+use std::path::Path;
+
+use aes_gcm::aead::rand_core::RngCore;
+use aes_gcm::aead::OsRng;
+use anyhow::Result;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use crate::encryption::read_key_file;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// What a share token lets its holder do with the file.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ShareKind {
+    #[default]
+    Download,
+    Preview,
+}
+
+impl ShareKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            ShareKind::Download => "download",
+            ShareKind::Preview => "preview",
+        }
+    }
+}
+
+/// Access carried by a token.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct ShareGrant {
+    pub filename: String,
+    pub kind: ShareKind,
+    /// Seconds since the Unix epoch after which the token is refused.
+    pub expires_at: u64,
+}
+
+/// Why a token was refused.
+#[derive(Debug, PartialEq, Eq, thiserror::Error)]
+pub enum ShareError {
+    #[error("Malformed share token")]
+    Malformed,
+    #[error("Invalid share token signature")]
+    BadSignature,
+    #[error("Share link expired")]
+    Expired,
+}
+
+/// Signs and verifies share tokens with HMAC-SHA256.
+#[derive(Clone)]
+pub struct ShareSigner {
+    key: [u8; 32],
+}
+
+impl ShareSigner {
+    /// Key drawn from the OS; tokens stop working when the process restarts.
+    pub fn random() -> Self {
+        let mut key = [0u8; 32];
+        OsRng.fill_bytes(&mut key);
+        Self { key }
+    }
+
+    /// Key file holding 32 raw bytes or 64 hex characters, so tokens survive restarts and
+    /// are honored by every server sharing the file.
+    pub fn from_file(path: &Path) -> Result<Self> {
+        Ok(Self {
+            key: read_key_file(path)?,
+        })
+    }
+
+    /// Token of the form `<hex payload>.<hex signature>`, safe to put in a URL path.
+    pub fn sign(&self, grant: &ShareGrant) -> String {
+        let payload = format!(
+            "{}:{}:{}",
+            grant.kind.as_str(),
+            grant.expires_at,
+            grant.filename
+        );
+        let signature = self.mac(payload.as_bytes()).finalize().into_bytes();
+        format!("{}.{}", hex::encode(payload), hex::encode(signature))
+    }
+
+    /// The grant a token carries, if its signature holds and it has not expired at `now`.
+    pub fn verify(&self, token: &str, now: u64) -> Result<ShareGrant, ShareError> {
+        let (payload, signature) = token.split_once('.').ok_or(ShareError::Malformed)?;
+        let payload = hex::decode(payload).map_err(|_| ShareError::Malformed)?;
+        let signature = hex::decode(signature).map_err(|_| ShareError::Malformed)?;
+        self.mac(&payload)
+            .verify_slice(&signature)
+            .map_err(|_| ShareError::BadSignature)?;
+
+        let payload = String::from_utf8(payload).map_err(|_| ShareError::Malformed)?;
+        let mut parts = payload.splitn(3, ':');
+        let kind = match parts.next() {
+            Some("download") => ShareKind::Download,
+            Some("preview") => ShareKind::Preview,
+            _ => return Err(ShareError::Malformed),
+        };
+        let expires_at = parts
+            .next()
+            .and_then(|value| value.parse().ok())
+            .ok_or(ShareError::Malformed)?;
+        let filename = parts.next().ok_or(ShareError::Malformed)?.to_string();
+        if now >= expires_at {
+            return Err(ShareError::Expired);
+        }
+        Ok(ShareGrant {
+            filename,
+            kind,
+            expires_at,
+        })
+    }
+
+    fn mac(&self, payload: &[u8]) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(&self.key).expect("HMAC accepts any key length");
+        mac.update(payload);
+        mac
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grant() -> ShareGrant {
+        ShareGrant {
+            filename: "scan-1a2b.dcm".to_string(),
+            kind: ShareKind::Preview,
+            expires_at: 1_000,
+        }
+    }
+
+    #[test]
+    fn tokens_round_trip_until_they_expire() {
+        let signer = ShareSigner::random();
+        let token = signer.sign(&grant());
+        assert_eq!(signer.verify(&token, 999), Ok(grant()));
+        assert_eq!(signer.verify(&token, 1_000), Err(ShareError::Expired));
+    }
+
+    #[test]
+    fn tampered_or_foreign_tokens_are_refused() {
+        let signer = ShareSigner::random();
+        let token = signer.sign(&grant());
+
+        // Re-sign the payload with a later expiry but keep the original signature.
+        let (_, signature) = token.split_once('.').unwrap();
+        let forged = format!(
+            "{}.{}",
+            hex::encode("preview:9999:scan-1a2b.dcm"),
+            signature
+        );
+        assert_eq!(signer.verify(&forged, 0), Err(ShareError::BadSignature));
+
+        assert_eq!(
+            ShareSigner::random().verify(&token, 0),
+            Err(ShareError::BadSignature)
+        );
+        assert_eq!(signer.verify("not-a-token", 0), Err(ShareError::Malformed));
+    }
+}
@@ -0,0 +1,491 @@
+//
+// size_report.rs
+// Dicom-Tools-rs
+//
+// Estimates, per series, current archive size against JPEG-LS and JPEG 2000 lossless, from pixel entropy.
+//
+// Thales Matheus Mendonça Santos - November 2025
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt::Write as _;
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use dicom::core::value::Value;
+use dicom::core::Tag;
+use dicom::pixeldata::PixelDecoder;
+use dicom_pixeldata::{ConvertOptions, DecodedPixelData, ModalityLutOption, VoiLutOption};
+use rayon::prelude::*;
+use serde::Serialize;
+use walkdir::WalkDir;
+
+use crate::atomic_file;
+use crate::dicom_access::{open_dicom, ElementAccess};
+
+const MODALITY: Tag = Tag(0x0008, 0x0060);
+const SERIES_INSTANCE_UID: Tag = Tag(0x0020, 0x000E);
+const PIXEL_DATA: Tag = Tag(0x7FE0, 0x0010);
+/// Decomposition levels of the JPEG 2000 estimate (the codec's usual default).
+const DWT_LEVELS: usize = 5;
+
+/// Size figures for one series. Estimates only cover files whose pixels could be decoded.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SeriesSize {
+    pub series_instance_uid: String,
+    pub modality: String,
+    pub files: usize,
+    /// Files whose pixel data could not be decoded; they count at their current size.
+    pub not_estimated: usize,
+    pub transfer_syntaxes: BTreeSet<String>,
+    pub current_bytes: u64,
+    /// Native (uncompressed) pixel data size.
+    pub uncompressed_pixel_bytes: u64,
+    /// Zeroth-order entropy of the stored sample values, in bits per sample.
+    pub entropy_bits_per_sample: f64,
+    pub jpeg_ls_bytes: u64,
+    pub j2k_bytes: u64,
+}
+
+impl SeriesSize {
+    pub fn jpeg_ls_ratio(&self) -> f64 {
+        ratio(self.current_bytes, self.jpeg_ls_bytes)
+    }
+
+    pub fn j2k_ratio(&self) -> f64 {
+        ratio(self.current_bytes, self.j2k_bytes)
+    }
+}
+
+fn ratio(current: u64, estimate: u64) -> f64 {
+    if estimate == 0 {
+        0.0
+    } else {
+        current as f64 / estimate as f64
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SizeReport {
+    pub files: usize,
+    pub skipped: usize,
+    pub series: Vec<SeriesSize>,
+    pub total: SeriesSize,
+}
+
+/// Per-file measurements before they are folded into a series.
+#[derive(Debug, Clone, Default)]
+struct FileEstimate {
+    series_instance_uid: String,
+    modality: String,
+    transfer_syntax: String,
+    current_bytes: u64,
+    uncompressed_pixel_bytes: u64,
+    histogram: HashMap<i32, u64>,
+    /// Estimated file sizes; `None` when the pixel data could not be decoded.
+    jpeg_ls_bytes: Option<u64>,
+    j2k_bytes: Option<u64>,
+}
+
+#[derive(Debug, Default)]
+struct SeriesAccumulator {
+    size: SeriesSize,
+    histogram: HashMap<i32, u64>,
+}
+
+impl SeriesAccumulator {
+    fn add(&mut self, file: FileEstimate) {
+        let size = &mut self.size;
+        size.files += 1;
+        size.transfer_syntaxes.insert(file.transfer_syntax);
+        size.current_bytes += file.current_bytes;
+        size.uncompressed_pixel_bytes += file.uncompressed_pixel_bytes;
+        match (file.jpeg_ls_bytes, file.j2k_bytes) {
+            (Some(jpeg_ls), Some(j2k)) => {
+                size.jpeg_ls_bytes += jpeg_ls;
+                size.j2k_bytes += j2k;
+            }
+            _ => {
+                size.not_estimated += 1;
+                size.jpeg_ls_bytes += file.current_bytes;
+                size.j2k_bytes += file.current_bytes;
+            }
+        }
+        for (value, count) in file.histogram {
+            *self.histogram.entry(value).or_default() += count;
+        }
+    }
+
+    fn finish(mut self) -> SeriesSize {
+        let samples: u64 = self.histogram.values().sum();
+        if samples > 0 {
+            self.size.entropy_bits_per_sample = entropy_bits(&self.histogram) / samples as f64;
+        }
+        self.size
+    }
+}
+
+/// Total information content, in bits, of the values counted by `histogram`.
+fn entropy_bits<K>(histogram: &HashMap<K, u64>) -> f64 {
+    let total: u64 = histogram.values().sum();
+    if total == 0 {
+        return 0.0;
+    }
+    let total = total as f64;
+    histogram
+        .values()
+        .filter(|&&count| count > 0)
+        .map(|&count| {
+            let count = count as f64;
+            -count * (count / total).log2()
+        })
+        .sum()
+}
+
+fn histogram_of(values: impl IntoIterator<Item = i32>) -> HashMap<i32, u64> {
+    let mut histogram = HashMap::new();
+    for value in values {
+        *histogram.entry(value).or_default() += 1;
+    }
+    histogram
+}
+
+/// JPEG-LS lossless estimate: entropy of the median edge detector (LOCO-I) residuals.
+fn jpeg_ls_bits(plane: &[i32], width: usize, height: usize) -> f64 {
+    let at = |x: usize, y: usize| plane[y * width + x];
+    let residuals = (0..height).flat_map(|y| {
+        (0..width).map(move |x| {
+            let a = if x > 0 {
+                at(x - 1, y)
+            } else if y > 0 {
+                at(x, y - 1)
+            } else {
+                0
+            };
+            let b = if y > 0 { at(x, y - 1) } else { a };
+            let c = if x > 0 && y > 0 { at(x - 1, y - 1) } else { b };
+            let prediction = if c >= a.max(b) {
+                a.min(b)
+            } else if c <= a.min(b) {
+                a.max(b)
+            } else {
+                a + b - c
+            };
+            at(x, y) - prediction
+        })
+    });
+    entropy_bits(&histogram_of(residuals))
+}
+
+/// One level of the reversible 5/3 lifting transform in place: low-pass then high-pass half.
+fn lift53(line: &mut [i32]) {
+    let n = line.len();
+    if n < 2 {
+        return;
+    }
+    let mut low: Vec<i32> = line.iter().step_by(2).copied().collect();
+    let mut high: Vec<i32> = line.iter().skip(1).step_by(2).copied().collect();
+    for i in 0..high.len() {
+        let right = low.get(i + 1).copied().unwrap_or(low[i]);
+        high[i] -= (low[i] + right) >> 1;
+    }
+    for i in 0..low.len() {
+        let left = high[i.saturating_sub(1)];
+        let right = high.get(i).copied().unwrap_or(high[high.len() - 1]);
+        low[i] += (left + right + 2) >> 2;
+    }
+    line[..low.len()].copy_from_slice(&low);
+    line[low.len()..].copy_from_slice(&high);
+}
+
+/// JPEG 2000 lossless estimate: entropy of each subband of a 5-level 5/3 wavelet transform.
+fn j2k_bits(plane: &[i32], width: usize, height: usize) -> f64 {
+    let mut coefficients = plane.to_vec();
+    let (mut w, mut h) = (width, height);
+    let mut bits = 0.0;
+    for _ in 0..DWT_LEVELS {
+        if w < 2 && h < 2 {
+            break;
+        }
+        for y in 0..h {
+            lift53(&mut coefficients[y * width..y * width + w]);
+        }
+        let mut column = vec![0; h];
+        for x in 0..w {
+            for y in 0..h {
+                column[y] = coefficients[y * width + x];
+            }
+            lift53(&mut column);
+            for y in 0..h {
+                coefficients[y * width + x] = column[y];
+            }
+        }
+        let (low_w, low_h) = (w.div_ceil(2), h.div_ceil(2));
+        for (xs, ys) in [
+            (low_w..w, 0..low_h),
+            (0..low_w, low_h..h),
+            (low_w..w, low_h..h),
+        ] {
+            let band = ys.flat_map(|y| xs.clone().map(move |x| (x, y)));
+            bits += entropy_bits(&histogram_of(
+                band.map(|(x, y)| coefficients[y * width + x]),
+            ));
+        }
+        (w, h) = (low_w, low_h);
+    }
+    let approximation = (0..h).flat_map(|y| (0..w).map(move |x| (x, y)));
+    bits + entropy_bits(&histogram_of(
+        approximation.map(|(x, y)| coefficients[y * width + x]),
+    ))
+}
+
+/// Stored values of each frame, split into one plane per sample.
+fn planes(decoded: &DecodedPixelData) -> Result<Vec<Vec<i32>>> {
+    let options = ConvertOptions::new()
+        .with_modality_lut(ModalityLutOption::None)
+        .with_voi_lut(VoiLutOption::Identity);
+    let samples = decoded.samples_per_pixel() as usize;
+    let mut planes = Vec::new();
+    for frame in 0..decoded.number_of_frames() {
+        let values: Vec<i32> = decoded
+            .to_vec_frame_with_options(frame, &options)
+            .context("Failed to convert pixel data")?;
+        for sample in 0..samples {
+            planes.push(
+                values
+                    .iter()
+                    .skip(sample)
+                    .step_by(samples)
+                    .copied()
+                    .collect(),
+            );
+        }
+    }
+    Ok(planes)
+}
+
+/// Bytes taken by the Pixel Data element's value as currently stored.
+fn stored_pixel_bytes<I>(value: &Value<I>) -> u64 {
+    match value {
+        Value::PixelSequence(seq) => seq.fragments().iter().map(|f| f.len() as u64).sum(),
+        Value::Primitive(p) => p.calculate_byte_len() as u64,
+        Value::Sequence(_) => 0,
+    }
+}
+
+fn estimate_file(path: &Path) -> Result<FileEstimate> {
+    let current_bytes = std::fs::metadata(path)?.len();
+    let obj = open_dicom(path)?;
+    let text = |tag| {
+        obj.element_str(tag)
+            .map(|s| s.trim_end_matches(['\0', ' ']).to_string())
+            .unwrap_or_default()
+    };
+    let mut estimate = FileEstimate {
+        series_instance_uid: text(SERIES_INSTANCE_UID),
+        modality: text(MODALITY),
+        transfer_syntax: obj
+            .meta()
+            .transfer_syntax()
+            .trim_end_matches('\0')
+            .to_string(),
+        current_bytes,
+        ..FileEstimate::default()
+    };
+    let Ok(element) = obj.element(PIXEL_DATA) else {
+        // Nothing to compress: the file would be stored as is.
+        estimate.jpeg_ls_bytes = Some(current_bytes);
+        estimate.j2k_bytes = Some(current_bytes);
+        return Ok(estimate);
+    };
+    let header_bytes = current_bytes.saturating_sub(stored_pixel_bytes(element.value()));
+
+    let Ok(decoded) = obj.decode_pixel_data() else {
+        return Ok(estimate);
+    };
+    let Ok(planes) = planes(&decoded) else {
+        return Ok(estimate);
+    };
+    let (width, height) = (decoded.columns() as usize, decoded.rows() as usize);
+    let samples: u64 = planes.iter().map(|p| p.len() as u64).sum();
+    estimate.uncompressed_pixel_bytes = samples * decoded.bits_allocated().div_ceil(8) as u64;
+
+    let (jpeg_ls, j2k) = planes
+        .par_iter()
+        .filter(|plane| plane.len() == width * height)
+        .map(|plane| {
+            (
+                jpeg_ls_bits(plane, width, height),
+                j2k_bits(plane, width, height),
+            )
+        })
+        .reduce(|| (0.0, 0.0), |a, b| (a.0 + b.0, a.1 + b.1));
+    estimate.jpeg_ls_bytes = Some(header_bytes + (jpeg_ls / 8.0).ceil() as u64);
+    estimate.j2k_bytes = Some(header_bytes + (j2k / 8.0).ceil() as u64);
+    estimate.histogram = histogram_of(planes.into_iter().flatten());
+    Ok(estimate)
+}
+
+/// Decode every file under `dir` and aggregate size estimates per series.
+pub fn scan_directory(dir: &Path) -> Result<SizeReport> {
+    if !dir.is_dir() {
+        bail!("{:?} is not a directory", dir);
+    }
+    let paths: Vec<_> = WalkDir::new(dir)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file())
+        .map(|e| e.into_path())
+        .collect();
+
+    // Files are decoded one at a time per worker so memory stays bounded by the thread count.
+    let estimates: Vec<Option<FileEstimate>> = paths
+        .par_iter()
+        .map(|path| estimate_file(path).ok())
+        .collect();
+
+    let mut series: BTreeMap<String, SeriesAccumulator> = BTreeMap::new();
+    let mut total = SeriesAccumulator::default();
+    let mut skipped = 0;
+    for estimate in estimates {
+        let Some(estimate) = estimate else {
+            skipped += 1;
+            continue;
+        };
+        let acc = series
+            .entry(estimate.series_instance_uid.clone())
+            .or_default();
+        acc.size.series_instance_uid = estimate.series_instance_uid.clone();
+        acc.size.modality = estimate.modality.clone();
+        total.add(estimate.clone());
+        acc.add(estimate);
+    }
+    let series: Vec<SeriesSize> = series.into_values().map(|acc| acc.finish()).collect();
+    let mut total = total.finish();
+    total.series_instance_uid = "TOTAL".to_string();
+    Ok(SizeReport {
+        files: total.files,
+        skipped,
+        series,
+        total,
+    })
+}
+
+/// Render the report as CSV, one line per series followed by the total.
+pub fn to_csv(report: &SizeReport) -> String {
+    let mut out = String::from(
+        "series_instance_uid,modality,files,not_estimated,transfer_syntaxes,current_bytes,uncompressed_pixel_bytes,entropy_bits_per_sample,jpeg_ls_bytes,jpeg_ls_ratio,j2k_bytes,j2k_ratio\n",
+    );
+    for size in report.series.iter().chain([&report.total]) {
+        let syntaxes: Vec<&str> = size.transfer_syntaxes.iter().map(String::as_str).collect();
+        let _ = writeln!(
+            out,
+            "{},{},{},{},{},{},{},{:.3},{},{:.2},{},{:.2}",
+            size.series_instance_uid,
+            size.modality,
+            size.files,
+            size.not_estimated,
+            syntaxes.join("|"),
+            size.current_bytes,
+            size.uncompressed_pixel_bytes,
+            size.entropy_bits_per_sample,
+            size.jpeg_ls_bytes,
+            size.jpeg_ls_ratio(),
+            size.j2k_bytes,
+            size.j2k_ratio()
+        );
+    }
+    out
+}
+
+fn megabytes(bytes: u64) -> f64 {
+    bytes as f64 / (1024.0 * 1024.0)
+}
+
+/// CLI entry point: print a table, or write CSV when `csv` is given.
+pub fn print_size_report(dir: &Path, csv: Option<&Path>) -> Result<()> {
+    let report = scan_directory(dir)?;
+    println!(
+        "Size report for {:?}: {} file(s) in {} series, {} skipped (not DICOM)",
+        dir,
+        report.files,
+        report.series.len(),
+        report.skipped
+    );
+
+    if let Some(path) = csv {
+        atomic_file::write(path, to_csv(&report))
+            .with_context(|| format!("Failed to write CSV to {:?}", path))?;
+        println!("CSV saved to {:?}", path);
+        return Ok(());
+    }
+
+    println!(
+        "{:<40} {:<4} {:>6} {:>11} {:>8} {:>11} {:>6} {:>11} {:>6}",
+        "Series", "Mod", "Files", "Current MB", "Entropy", "JPEG-LS MB", "Ratio", "J2K MB", "Ratio"
+    );
+    for size in report.series.iter().chain([&report.total]) {
+        println!(
+            "{:<40} {:<4} {:>6} {:>11.2} {:>8.2} {:>11.2} {:>5.2}x {:>11.2} {:>5.2}x{}",
+            size.series_instance_uid,
+            size.modality,
+            size.files,
+            megabytes(size.current_bytes),
+            size.entropy_bits_per_sample,
+            megabytes(size.jpeg_ls_bytes),
+            size.jpeg_ls_ratio(),
+            megabytes(size.j2k_bytes),
+            size.j2k_ratio(),
+            if size.not_estimated > 0 {
+                format!("  [{} not decodable, counted as is]", size.not_estimated)
+            } else {
+                String::new()
+            }
+        );
+    }
+    println!(
+        "Estimates are entropy bounds of each codec's predictor/transform, not encoder output."
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entropy_of_uniform_and_constant_values() {
+        let uniform = histogram_of(0..256);
+        assert!((entropy_bits(&uniform) - 256.0 * 8.0).abs() < 1e-6);
+        assert_eq!(entropy_bits(&histogram_of([7; 100])), 0.0);
+    }
+
+    #[test]
+    fn smooth_images_estimate_far_below_noise() {
+        let (width, height) = (64, 64);
+        let ramp: Vec<i32> = (0..width * height)
+            .map(|i| ((i % width) * 16 + (i / width) * 8) as i32)
+            .collect();
+        // Deterministic pseudo-random 12-bit noise.
+        let mut state = 12345u32;
+        let noise: Vec<i32> = (0..width * height)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                ((state >> 16) & 0x0FFF) as i32
+            })
+            .collect();
+
+        let pixels = (width * height) as f64;
+        assert!(jpeg_ls_bits(&ramp, width, height) / pixels < 0.5);
+        assert!(j2k_bits(&ramp, width, height) / pixels < 2.0);
+        // Small subbands bias the empirical entropy low, hence the margin under 12 bits.
+        assert!(jpeg_ls_bits(&noise, width, height) / pixels > 8.0);
+        assert!(j2k_bits(&noise, width, height) / pixels > 8.0);
+    }
+
+    #[test]
+    fn lifting_keeps_constant_lines_in_the_low_band() {
+        let mut line = vec![5; 7];
+        lift53(&mut line);
+        assert_eq!(line, [5, 5, 5, 5, 0, 0, 0]);
+    }
+}
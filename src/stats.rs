@@ -3,24 +3,97 @@
 // Dicom-Tools-rs
 //
 // Computes pixel statistics, histograms, and format summaries from decoded DICOM pixel data.
+// Statistics leave out padding and shuttered pixels unless asked to count them.
 //
 // Thales Matheus Mendonça Santos - November 2025
 
 use std::path::Path;
 
-use anyhow::{Context, Result};
-use dicom::object::open_file;
+use anyhow::{bail, Context, Result};
+use dicom::object::DefaultDicomObject;
 use dicom::pixeldata::PixelDecoder;
-use dicom_pixeldata::{ConvertOptions, DecodedPixelData, ModalityLutOption};
+use dicom_pixeldata::{
+    ConvertOptions, DecodedPixelData, ModalityLutOption, PhotometricInterpretation, VoiLutFunction,
+};
 
-use crate::models::{PixelFormatSummary, PixelHistogram, PixelStatistics};
+use crate::concatenation::open_logical;
+use crate::dicom_access::open_dicom;
+use crate::float_pixels::FloatPixels;
+use crate::kernels::{self, MinMaxSum};
+use crate::lut::{self, PaletteLut, PresentationLutShape};
+use crate::models::{PixelFormatSummary, PixelHistogram, PixelStatistics, VoiWindow};
+use crate::padding::{self, Exclusions};
+use crate::roi_mask::{self, RoiMask};
+
+/// Which values statistics are computed on for images with Palette Color LUTs.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PaletteSpace {
+    /// The stored palette indices.
+    #[default]
+    Index,
+    /// The red, green and blue components the indices map to.
+    Rgb,
+}
 
 /// Calculate and print basic statistics of the pixel data.
-pub fn stats(input: &Path) -> Result<()> {
-    let stats = pixel_statistics_for_file(input)?;
+pub fn stats(input: &Path, space: PaletteSpace, exclusions: Exclusions) -> Result<()> {
+    let obj = open_logical(input).context("Failed to open DICOM file")?;
+    if let Some(float) = FloatPixels::from_object(&obj)? {
+        let stats = pixel_statistics_from_float(&float)?;
+        println!("Statistics for {:?}", input);
+        print_statistics(&stats);
+        let ignored = float.values.len() - stats.total_pixels;
+        if ignored > 0 {
+            println!("  Note: {} non-finite float value(s) ignored", ignored);
+        }
+        return Ok(());
+    }
+    let decoded = obj
+        .decode_pixel_data()
+        .context("Failed to decode pixel data")?;
+    let stats = pixel_statistics_for_object(&obj, &decoded, space, exclusions)?;
 
-    // Present data in a CLI-friendly block.
     println!("Statistics for {:?}", input);
+    print_statistics(&stats);
+    if stats.excluded_pixels > 0 {
+        println!(
+            "  Note: {} padding or shuttered pixel(s) excluded",
+            stats.excluded_pixels
+        );
+    }
+    if decoded.photometric_interpretation() == &PhotometricInterpretation::Monochrome1 {
+        println!("  Note: MONOCHROME1 - values are not display-inverted (higher = darker)");
+    }
+    if decoded.photometric_interpretation() == &PhotometricInterpretation::PaletteColor {
+        match space {
+            PaletteSpace::Index => println!("  Note: computed on palette indices"),
+            PaletteSpace::Rgb => println!("  Note: computed on palette RGB components"),
+        }
+    }
+
+    Ok(())
+}
+
+/// Calculate and print statistics of the pixels inside segment (SEG) or ROI (RTSTRUCT)
+/// number `segment` of `mask`.
+pub fn stats_within(input: &Path, mask: &Path, segment: u32) -> Result<()> {
+    let (stats, roi) = pixel_statistics_within_file(input, mask, segment)?;
+    println!(
+        "Statistics for {:?} within {} {} of {:?}",
+        input,
+        segment,
+        roi.label
+            .as_deref()
+            .map(|l| format!("({})", l))
+            .unwrap_or_default(),
+        mask
+    );
+    print_statistics(&stats);
+    Ok(())
+}
+
+fn print_statistics(stats: &PixelStatistics) {
+    // Present data in a CLI-friendly block.
     println!("  Shape: {:?}", stats.shape);
     println!("  Min:   {:.2}", stats.min);
     println!("  Max:   {:.2}", stats.max);
@@ -30,22 +103,159 @@ pub fn stats(input: &Path) -> Result<()> {
     }
     println!("  StdDv: {:.2}", stats.std_dev);
     println!("  Total Pixels: {}", stats.total_pixels);
+}
 
-    Ok(())
+/// Statistics restricted to a segmentation segment or a contoured ROI, resolved against the
+/// geometry of `input`; also returns the resolved mask.
+pub fn pixel_statistics_within_file(
+    input: &Path,
+    mask: &Path,
+    segment: u32,
+) -> Result<(PixelStatistics, RoiMask)> {
+    let obj = open_logical(input).context("Failed to open DICOM file")?;
+    let decoded = obj
+        .decode_pixel_data()
+        .context("Failed to decode pixel data")?;
+    if decoded.samples_per_pixel() != 1 {
+        bail!("Masked statistics need a single-sample (grayscale) image");
+    }
+    let roi = roi_mask::load(mask, segment, &obj, decoded.number_of_frames())?;
+    if roi.count() == 0 {
+        bail!(
+            "Segment/ROI {} of {:?} covers no pixel of {:?}",
+            segment,
+            mask,
+            input
+        );
+    }
+    let stats = pixel_statistics_within(&decoded, &roi)?;
+    Ok((stats, roi))
+}
+
+/// Statistics of the modality values whose pixels are set in `roi`; the shape is the
+/// number of pixels selected.
+pub fn pixel_statistics_within(
+    decoded: &DecodedPixelData,
+    roi: &RoiMask,
+) -> Result<PixelStatistics> {
+    let options = ConvertOptions::new().with_modality_lut(ModalityLutOption::Default);
+    let mut values = Vec::with_capacity(roi.count());
+    for (frame, inside) in roi.frames.iter().enumerate() {
+        if !inside.contains(&true) {
+            continue;
+        }
+        let frame_values: Vec<f32> = decoded
+            .to_vec_frame_with_options(frame as u32, &options)
+            .context("Failed to convert pixel data")?;
+        values.extend(
+            frame_values
+                .into_iter()
+                .zip(inside)
+                .filter(|(_, &inside)| inside)
+                .map(|(v, _)| v),
+        );
+    }
+    let count = values.len();
+    statistics_of(values, vec![count])
 }
 
 pub fn pixel_statistics_for_file(input: &Path) -> Result<PixelStatistics> {
-    let obj = open_file(input).context("Failed to open DICOM file")?;
+    pixel_statistics_in_space(input, PaletteSpace::Index)
+}
+
+pub fn pixel_statistics_in_space(input: &Path, space: PaletteSpace) -> Result<PixelStatistics> {
+    pixel_statistics_with(input, space, Exclusions::default())
+}
+
+pub fn pixel_statistics_with(
+    input: &Path,
+    space: PaletteSpace,
+    exclusions: Exclusions,
+) -> Result<PixelStatistics> {
+    let obj = open_dicom(input).context("Failed to open DICOM file")?;
+    if let Some(float) = FloatPixels::from_object(&obj)? {
+        return pixel_statistics_from_float(&float);
+    }
     let decoded = obj
         .decode_pixel_data()
         .context("Failed to decode pixel data")?;
 
-    pixel_statistics_from_decoded(&decoded)
+    pixel_statistics_for_object(&obj, &decoded, space, exclusions)
+}
+
+/// Statistics of Float or Double Float Pixel Data, stored values as-is. Non-finite values
+/// are left out, so `total_pixels` can be smaller than the shape implies.
+pub fn pixel_statistics_from_float(float: &FloatPixels) -> Result<PixelStatistics> {
+    statistics_of(float.finite_values(), float.shape())
+}
+
+/// Like [`pixel_statistics_from_decoded`], but for an image with Palette Color LUTs the
+/// values are mapped into RGB first when `space` asks for it (shape gains a channel axis).
+/// Other images, including those with a supplemental palette, ignore `space`.
+///
+/// Pixels `exclusions` selects are left out, so `total_pixels` can be smaller than the shape
+/// implies; `excluded_pixels` says how many.
+pub fn pixel_statistics_for_object(
+    obj: &DefaultDicomObject,
+    decoded: &DecodedPixelData,
+    space: PaletteSpace,
+    exclusions: Exclusions,
+) -> Result<PixelStatistics> {
+    let excluded = padding::excluded_pixels(obj, decoded, exclusions)?;
+    let samples = decoded.samples_per_pixel() as usize;
+    let mut stats = match (space, PaletteLut::from_object(obj)) {
+        (PaletteSpace::Rgb, Some(palette)) if !palette.supplemental => {
+            let raw_options = ConvertOptions::new().with_modality_lut(ModalityLutOption::None);
+            let array = decoded.to_ndarray_with_options::<f32>(&raw_options)?;
+            let mut shape = array.shape().to_vec();
+            let indices = without_excluded(array.into_raw_vec(), excluded.as_deref(), samples);
+            // Components are reported in the palette's own output range.
+            let values = indices
+                .iter()
+                .flat_map(|v| {
+                    [&palette.red, &palette.green, &palette.blue]
+                        .map(|lut| lut.apply(*v as f64) as f32)
+                })
+                .collect();
+            if shape.last() == Some(&1) {
+                shape.pop();
+            }
+            shape.push(3);
+            statistics_of(values, shape)?
+        }
+        _ => {
+            let (values, shape) = pixel_values(decoded)?;
+            statistics_of(
+                without_excluded(values, excluded.as_deref(), samples),
+                shape,
+            )?
+        }
+    };
+    stats.excluded_pixels = excluded.map_or(0, |flags| flags.iter().filter(|&&x| x).count());
+    Ok(stats)
+}
+
+/// Values of the pixels not flagged in `excluded` (one flag per pixel of `samples` values).
+fn without_excluded(values: Vec<f32>, excluded: Option<&[bool]>, samples: usize) -> Vec<f32> {
+    let Some(excluded) = excluded else {
+        return values;
+    };
+    values
+        .chunks(samples.max(1))
+        .zip(excluded)
+        .filter(|(_, &excluded)| !excluded)
+        .flat_map(|(pixel, _)| pixel.iter().copied())
+        .collect()
 }
 
+/// Statistics are computed on modality values (stored values after rescale/Modality LUT), never
+/// on display values, so MONOCHROME1 data is not inverted here.
 pub fn pixel_statistics_from_decoded(decoded: &DecodedPixelData) -> Result<PixelStatistics> {
     let (values, shape) = pixel_values(decoded)?;
+    statistics_of(values, shape)
+}
 
+fn statistics_of(values: Vec<f32>, shape: Vec<usize>) -> Result<PixelStatistics> {
     if values.is_empty() {
         return Ok(PixelStatistics {
             min: 0.0,
@@ -54,35 +264,24 @@ pub fn pixel_statistics_from_decoded(decoded: &DecodedPixelData) -> Result<Pixel
             median: None,
             std_dev: 0.0,
             total_pixels: 0,
+            excluded_pixels: 0,
             shape,
         });
     }
 
-    let mut min = f32::INFINITY;
-    let mut max = f32::NEG_INFINITY;
-    let mut sum = 0f64;
-
-    for &v in &values {
-        min = min.min(v);
-        max = max.max(v);
-        sum += v as f64;
-    }
+    let MinMaxSum { min, max, sum } = kernels::min_max_sum(&values);
 
     let total_pixels = values.len();
     let mean = (sum / total_pixels as f64) as f32;
 
-    let mut variance_sum = 0f64;
-    for v in &values {
-        let diff = *v as f64 - mean as f64;
-        variance_sum += diff * diff;
-    }
+    let variance_sum = kernels::sum_squared_diff(&values, mean as f64);
     let std_dev = (variance_sum / total_pixels as f64).sqrt() as f32;
 
     let median = {
         let mut sorted = values.clone();
         sorted.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
         let mid = sorted.len() / 2;
-        if sorted.len() % 2 == 0 {
+        if sorted.len().is_multiple_of(2) {
             Some((sorted[mid - 1] + sorted[mid]) / 2.0)
         } else {
             Some(sorted[mid])
@@ -96,41 +295,129 @@ pub fn pixel_statistics_from_decoded(decoded: &DecodedPixelData) -> Result<Pixel
         median,
         std_dev,
         total_pixels,
+        excluded_pixels: 0,
         shape,
     })
 }
 
+/// Upper limit on the number of fixed-width bins, so a tiny width cannot exhaust memory.
+pub const MAX_FIXED_BINS: usize = 1 << 20;
+
+/// Explicit histogram bin edges: bins `width` wide starting at `min`, the last one holding
+/// `max`. Histograms of different images binned the same way can be compared bin by bin.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FixedBins {
+    pub min: f32,
+    pub max: f32,
+    pub width: f32,
+}
+
+impl FixedBins {
+    /// CT in Hounsfield units, -1024..3071 in 1 HU bins (the 12-bit CT range).
+    pub const CT_HU: FixedBins = FixedBins {
+        min: -1024.0,
+        max: 3071.0,
+        width: 1.0,
+    };
+    /// Rescaled PET activity concentration, 0..100 kBq/ml in 100 Bq/ml bins; that covers
+    /// SUV 0..20 for a typical 370 MBq injection in a 70 kg patient.
+    pub const PET_BQML: FixedBins = FixedBins {
+        min: 0.0,
+        max: 100_000.0,
+        width: 100.0,
+    };
+
+    pub fn new(min: f32, max: f32, width: f32) -> Result<Self> {
+        let bins = FixedBins { min, max, width };
+        if !(min.is_finite() && max.is_finite() && width.is_finite()) {
+            bail!("Histogram bounds and bin width must be finite numbers");
+        }
+        if max < min {
+            bail!("Histogram maximum {} is below the minimum {}", max, min);
+        }
+        if width <= 0.0 {
+            bail!("Histogram bin width must be greater than zero");
+        }
+        if bins.bin_count() > MAX_FIXED_BINS {
+            bail!(
+                "{}..{} in bins of {} needs more than {} bins",
+                min,
+                max,
+                width,
+                MAX_FIXED_BINS
+            );
+        }
+        Ok(bins)
+    }
+
+    pub fn bin_count(&self) -> usize {
+        ((self.max - self.min) / self.width).floor() as usize + 1
+    }
+}
+
+/// How [`histogram_for_file_with`] lays out its bins.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Binning {
+    /// This many equal bins between the observed minimum and maximum.
+    Auto(usize),
+    Fixed(FixedBins),
+}
+
 /// Generate an intensity histogram for the pixel data.
 pub fn histogram_for_file(input: &Path, bins: usize) -> Result<PixelHistogram> {
-    let obj = open_file(input).context("Failed to open DICOM file")?;
+    histogram_for_file_with(input, Binning::Auto(bins))
+}
+
+/// Like [`histogram_for_file`], with auto-ranged or fixed bin edges.
+pub fn histogram_for_file_with(input: &Path, binning: Binning) -> Result<PixelHistogram> {
+    let obj = open_logical(input).context("Failed to open DICOM file")?;
+    if let Some(float) = FloatPixels::from_object(&obj)? {
+        return Ok(histogram_of(&float.finite_values(), binning));
+    }
     let decoded = obj
         .decode_pixel_data()
         .context("Failed to decode pixel data")?;
-    histogram_from_decoded(&decoded, bins)
+    histogram_from_decoded_with(&decoded, binning)
 }
 
 pub fn histogram_from_decoded(decoded: &DecodedPixelData, bins: usize) -> Result<PixelHistogram> {
+    histogram_from_decoded_with(decoded, Binning::Auto(bins))
+}
+
+/// Histogram of the modality values of `decoded`.
+pub fn histogram_from_decoded_with(
+    decoded: &DecodedPixelData,
+    binning: Binning,
+) -> Result<PixelHistogram> {
     let (values, _shape) = pixel_values(decoded)?;
+    Ok(histogram_of(&values, binning))
+}
+
+fn histogram_of(values: &[f32], binning: Binning) -> PixelHistogram {
+    match binning {
+        Binning::Auto(bins) => auto_histogram(values, bins),
+        Binning::Fixed(fixed) => fixed_histogram(values, fixed),
+    }
+}
 
+fn auto_histogram(values: &[f32], bins: usize) -> PixelHistogram {
     if values.is_empty() {
-        return Ok(PixelHistogram {
+        return PixelHistogram {
             bins: vec![],
             min: 0.0,
             max: 0.0,
-        });
+            bin_width: 0.0,
+            below: 0,
+            above: 0,
+        };
     }
 
-    let mut min = f32::INFINITY;
-    let mut max = f32::NEG_INFINITY;
-    for &v in &values {
-        min = min.min(v);
-        max = max.max(v);
-    }
+    let MinMaxSum { min, max, .. } = kernels::min_max_sum(values);
 
     let bin_count = bins.max(1);
     let mut counts = vec![0u64; bin_count];
     let range = max - min;
-    for &v in &values {
+    for &v in values {
         let idx = if range == 0.0 {
             0
         } else {
@@ -140,25 +427,85 @@ pub fn histogram_from_decoded(decoded: &DecodedPixelData, bins: usize) -> Result
         counts[clamped] += 1;
     }
 
-    Ok(PixelHistogram {
+    PixelHistogram {
         bins: counts,
         min,
         max,
-    })
+        bin_width: range / bin_count as f32,
+        below: 0,
+        above: 0,
+    }
+}
+
+/// Out-of-range values are tallied apart rather than clamped into the edge bins, which
+/// would make air or metal look like the first or last HU of the window.
+fn fixed_histogram(values: &[f32], fixed: FixedBins) -> PixelHistogram {
+    let mut histogram = PixelHistogram {
+        bins: vec![0u64; fixed.bin_count()],
+        min: fixed.min,
+        max: fixed.max,
+        bin_width: fixed.width,
+        below: 0,
+        above: 0,
+    };
+    let last = histogram.bins.len() - 1;
+    for &v in values {
+        if v < fixed.min {
+            histogram.below += 1;
+        } else if v > fixed.max {
+            histogram.above += 1;
+        } else {
+            // f32 rounding must not push `max` itself past the last bin.
+            let idx = ((v - fixed.min) / fixed.width).floor() as usize;
+            histogram.bins[idx.min(last)] += 1;
+        }
+    }
+    histogram
 }
 
 /// Summarize pixel format information (bits, samples, VOI/LUT).
 pub fn pixel_format_for_file(input: &Path) -> Result<PixelFormatSummary> {
-    let obj = open_file(input).context("Failed to open DICOM file")?;
+    let obj = open_dicom(input).context("Failed to open DICOM file")?;
     let decoded = obj
         .decode_pixel_data()
         .context("Failed to decode pixel data")?;
-    pixel_format_from_decoded(&decoded)
+    pixel_format_for_object(&obj, &decoded)
+}
+
+/// Like [`pixel_format_from_decoded`], with the windows and Presentation LUT Shape read
+/// from `obj` itself: the decoder drops windows whose count differs from the frame count.
+pub fn pixel_format_for_object(
+    obj: &DefaultDicomObject,
+    decoded: &DecodedPixelData,
+) -> Result<PixelFormatSummary> {
+    let mut summary = pixel_format_from_decoded(decoded)?;
+    summary.windows = lut::voi_windows(obj);
+    summary.presentation_lut_shape =
+        PresentationLutShape::from_object(obj).map(|shape| shape.as_str().to_string());
+    if let Some(first) = summary.windows.first() {
+        summary.window_center = Some(first.center);
+        summary.window_width = Some(first.width);
+    }
+    Ok(summary)
 }
 
 pub fn pixel_format_from_decoded(decoded: &DecodedPixelData) -> Result<PixelFormatSummary> {
     let rescale = decoded.rescale()?.first().cloned();
     let window = decoded.window()?.and_then(|w| w.first()).cloned();
+    let functions = decoded.voi_lut_function()?.unwrap_or_default();
+    let windows = decoded
+        .window()?
+        .unwrap_or_default()
+        .iter()
+        .enumerate()
+        .map(|(i, w)| VoiWindow {
+            center: w.center,
+            width: w.width,
+            function: voi_function_name(functions.get(i).or(functions.first()).copied())
+                .to_string(),
+            explanation: None,
+        })
+        .collect();
     let pi = decoded.photometric_interpretation();
     let planar_config = if decoded.samples_per_pixel() > 1 {
         Some(decoded.planar_configuration())
@@ -181,9 +528,20 @@ pub fn pixel_format_from_decoded(decoded: &DecodedPixelData) -> Result<PixelForm
         rescale_intercept: rescale.map(|r| r.intercept),
         window_center: window.map(|w| w.center),
         window_width: window.map(|w| w.width),
+        windows,
+        presentation_lut_shape: None,
     })
 }
 
+/// The defined term of a VOI LUT Function (LINEAR when absent).
+pub fn voi_function_name(function: Option<VoiLutFunction>) -> &'static str {
+    match function.unwrap_or_default() {
+        VoiLutFunction::Linear => "LINEAR",
+        VoiLutFunction::LinearExact => "LINEAR_EXACT",
+        VoiLutFunction::Sigmoid => "SIGMOID",
+    }
+}
+
 fn pixel_values(decoded: &DecodedPixelData) -> Result<(Vec<f32>, Vec<usize>)> {
     // Apply modality LUT by default to reflect clinician-facing values.
     let options = ConvertOptions::new().with_modality_lut(ModalityLutOption::Default);
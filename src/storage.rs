@@ -2,30 +2,469 @@
 // storage.rs
 // Dicom-Tools-rs
 //
-// Provides a safe file store for uploaded/derived DICOM files with path sanitization and hashing.
+// Provides a safe file store for uploaded/derived DICOM files with path sanitization and hashing,
+// backed by a local directory or (with the `s3` feature) S3-compatible object storage.
 //
 // Thales Matheus Mendonça Santos - November 2025
 
+use std::collections::HashMap;
+use std::fmt;
 use std::fs;
+use std::io;
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
 
-use anyhow::{bail, Context, Result};
+use anyhow::{anyhow, bail, Context, Result};
+use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
 
+use crate::atomic_file;
+use crate::encryption::{EncryptedBackend, EncryptionKey};
+
+/// Index file kept next to the stored files; it is hidden from `resolve`.
+const INDEX_FILE: &str = ".store-index.json";
+
+/// Where stored blobs are kept durably.
+///
+/// DICOM code opens files by path, so [`FileStore`] always works on a local directory;
+/// a backend mirrors that directory's files and fetches them back when they are missing.
+pub trait StorageBackend: Send + Sync {
+    /// Persist the local file `local` under `name`.
+    fn upload(&self, name: &str, local: &Path) -> Result<()>;
+    /// Fetch `name` into `local`. Returns `false` when the backend does not hold it.
+    fn download(&self, name: &str, local: &Path) -> Result<bool>;
+    /// Whether the backend currently holds `name`.
+    fn contains(&self, name: &str) -> Result<bool>;
+    /// Delete `name`; deleting a missing blob is not an error.
+    fn remove(&self, name: &str) -> Result<()>;
+    /// Remote backends are trusted as the source of truth and never scanned eagerly.
+    fn is_remote(&self) -> bool {
+        false
+    }
+    fn describe(&self) -> String;
+}
+
+/// Blobs kept as plain files under a directory. When that directory is the store's own
+/// working directory, uploads and downloads are no-ops.
+pub struct DirectoryBackend {
+    root: PathBuf,
+}
+
+impl DirectoryBackend {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+}
+
+impl StorageBackend for DirectoryBackend {
+    fn upload(&self, name: &str, local: &Path) -> Result<()> {
+        let target = self.root.join(name);
+        if target != local {
+            if let Some(parent) = target.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            atomic_file::write_with(&target, |writer| {
+                io::copy(&mut fs::File::open(local)?, writer)?;
+                Ok(())
+            })?;
+        }
+        Ok(())
+    }
+
+    fn download(&self, name: &str, local: &Path) -> Result<bool> {
+        let source = self.root.join(name);
+        if !source.is_file() {
+            return Ok(false);
+        }
+        if source != local {
+            atomic_file::write_with(local, |writer| {
+                io::copy(&mut fs::File::open(&source)?, writer)?;
+                Ok(())
+            })?;
+        }
+        Ok(true)
+    }
+
+    fn contains(&self, name: &str) -> Result<bool> {
+        Ok(self.root.join(name).is_file())
+    }
+
+    fn remove(&self, name: &str) -> Result<()> {
+        match fs::remove_file(self.root.join(name)) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                Err(e).with_context(|| format!("Failed to remove {}", name))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn describe(&self) -> String {
+        format!("directory {:?}", self.root)
+    }
+}
+
+/// Storage target selected on the command line: a directory or `s3://bucket/prefix`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StorageLocation {
+    Local(PathBuf),
+    S3 { bucket: String, prefix: String },
+}
+
+impl FromStr for StorageLocation {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.strip_prefix("s3://") {
+            Some(rest) => {
+                let (bucket, prefix) = rest.split_once('/').unwrap_or((rest, ""));
+                if bucket.is_empty() {
+                    bail!("S3 location '{}' is missing a bucket name", s);
+                }
+                Ok(StorageLocation::S3 {
+                    bucket: bucket.to_string(),
+                    prefix: prefix.trim_matches('/').to_string(),
+                })
+            }
+            None => Ok(StorageLocation::Local(PathBuf::from(s))),
+        }
+    }
+}
+
+impl StorageLocation {
+    /// Build the backend for this location. Remote backends read credentials from the
+    /// environment (see `s3::S3Backend::from_env`).
+    pub fn backend(&self) -> Result<Arc<dyn StorageBackend>> {
+        match self {
+            StorageLocation::Local(root) => Ok(Arc::new(DirectoryBackend::new(root))),
+            #[cfg(feature = "s3")]
+            StorageLocation::S3 { bucket, prefix } => {
+                Ok(Arc::new(s3::S3Backend::from_env(bucket, prefix)?))
+            }
+            #[cfg(not(feature = "s3"))]
+            StorageLocation::S3 { .. } => {
+                bail!("S3 storage requires building with `--features s3`")
+            }
+        }
+    }
+}
+
+/// What to do when a new file would push the store past [`StoreLimits::max_total_bytes`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum EvictionPolicy {
+    /// Delete least recently accessed uploads (with their derived artifacts) until it fits.
+    #[default]
+    Lru,
+    /// Refuse the new file.
+    Reject,
+}
+
+/// Disk-space guardrails; `None` means unlimited.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StoreLimits {
+    pub max_upload_bytes: Option<u64>,
+    /// Budget for uploads plus their derived artifacts.
+    pub max_total_bytes: Option<u64>,
+    pub eviction: EvictionPolicy,
+}
+
+impl StoreLimits {
+    /// Fail early when a single upload is over the per-upload limit.
+    pub fn check_upload(&self, size: u64) -> Result<(), QuotaError> {
+        match self.max_upload_bytes {
+            Some(limit) if size > limit => Err(QuotaError::UploadTooLarge { size, limit }),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Refusals caused by [`StoreLimits`]; the web server answers them with 507.
+#[derive(Debug, thiserror::Error)]
+pub enum QuotaError {
+    #[error("Upload of {size} bytes exceeds the per-upload limit of {limit} bytes")]
+    UploadTooLarge { size: u64, limit: u64 },
+    #[error("Storage quota exceeded: {requested} bytes requested, {used} of {limit} bytes in use")]
+    StoreFull {
+        requested: u64,
+        used: u64,
+        limit: u64,
+    },
+}
+
 #[derive(Clone)]
 pub struct FileStore {
     root: PathBuf,
+    backend: Arc<dyn StorageBackend>,
+    index: Arc<Mutex<StoreIndex>>,
+    limits: StoreLimits,
+    /// Private plaintext working copy of an encrypted store, removed when the last clone drops
+    /// (or, after a crash, by the next store opened on the machine).
+    private_cache: Option<Arc<PrivateCache>>,
+}
+
+struct PrivateCache(PathBuf);
+
+const PRIVATE_CACHE_PREFIX: &str = "dicom-tools-store-";
+
+impl PrivateCache {
+    /// Owner-only directory under the system temp dir, so plaintext never lands next to the
+    /// (possibly shared) durable store.
+    fn create() -> Result<Self> {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        Self::sweep_stale(&std::env::temp_dir());
+        let dir = std::env::temp_dir().join(format!(
+            "{}{}-{}",
+            PRIVATE_CACHE_PREFIX,
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::create_dir_all(&dir).context("Failed to create private store cache")?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&dir, fs::Permissions::from_mode(0o700))?;
+        }
+        Ok(Self(dir))
+    }
+
+    /// Remove the working copies that processes killed before `Drop` ran left under `temp`.
+    /// Only directories of processes known to be gone are touched; other users' directories
+    /// fail to delete and are skipped.
+    fn sweep_stale(temp: &Path) {
+        let Ok(entries) = fs::read_dir(temp) else {
+            return;
+        };
+        for entry in entries.flatten() {
+            let name = entry.file_name();
+            let pid = name
+                .to_str()
+                .and_then(|n| n.strip_prefix(PRIVATE_CACHE_PREFIX))
+                .and_then(|rest| rest.split_once('-'))
+                .and_then(|(pid, _)| pid.parse::<u32>().ok());
+            if pid.is_some_and(process_gone) {
+                let _ = fs::remove_dir_all(entry.path());
+            }
+        }
+    }
+}
+
+/// Whether no process `pid` is running. Only Linux can tell (through /proc); elsewhere
+/// every process is assumed alive and stale working copies stay until removed by hand.
+fn process_gone(pid: u32) -> bool {
+    if pid == std::process::id() {
+        return false;
+    }
+    #[cfg(target_os = "linux")]
+    {
+        !Path::new("/proc").join(pid.to_string()).exists()
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        false
+    }
+}
+
+impl Drop for PrivateCache {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Content-addressed bookkeeping: one entry per distinct upload payload.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct StoreIndex {
+    entries: HashMap<String, StoredEntry>,
+    /// Logical clock stamped on entries when they are accessed.
+    #[serde(default)]
+    clock: u64,
+    /// Bumped whenever an upload is added or removed; see [`FileStore::generation`].
+    #[serde(skip)]
+    generation: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct StoredEntry {
+    filename: String,
+    /// Number of uploads currently referencing this content.
+    refs: u32,
+    /// Artifacts derived from this file (anonymized, transcoded...), removed with it.
+    derived: Vec<String>,
+    /// Size in bytes of the upload and of each published derived artifact.
+    #[serde(default)]
+    sizes: HashMap<String, u64>,
+    /// [`StoreIndex::clock`] value at the last save or resolve, for LRU eviction.
+    #[serde(default)]
+    last_access: u64,
+    /// Manifest of `derived`, keyed by operation and parameters, for reuse and listing.
+    #[serde(default)]
+    artifacts: Vec<DerivedArtifact>,
+}
+
+/// A file derived from a stored upload by one operation with one set of parameters.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DerivedArtifact {
+    pub filename: String,
+    /// What produced the file, e.g. `anon` or `transcoded`.
+    pub operation: String,
+    /// Canonical parameters of the operation, e.g. the target transfer syntax UID.
+    pub parameters: String,
+    /// Whether the artifact was written completely and published; only those are reused.
+    pub published: bool,
+}
+
+impl StoredEntry {
+    fn files(&self) -> impl Iterator<Item = &String> {
+        self.derived.iter().chain(std::iter::once(&self.filename))
+    }
+}
+
+impl StoreIndex {
+    fn hash_of(&self, filename: &str) -> Option<String> {
+        self.entries
+            .iter()
+            .find(|(_, e)| e.filename == filename)
+            .map(|(hash, _)| hash.clone())
+    }
+
+    /// Hash of the entry owning `name`, either as its upload or as a derived artifact.
+    fn owner_of(&self, name: &str) -> Option<String> {
+        self.entries
+            .iter()
+            .find(|(_, e)| e.files().any(|f| f == name))
+            .map(|(hash, _)| hash.clone())
+    }
+
+    fn used_bytes(&self) -> u64 {
+        self.entries.values().flat_map(|e| e.sizes.values()).sum()
+    }
+
+    fn tick(&mut self) -> u64 {
+        self.clock += 1;
+        self.clock
+    }
+}
+
+impl fmt::Debug for FileStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FileStore")
+            .field("root", &self.root)
+            .field("backend", &self.backend.describe())
+            .field("limits", &self.limits)
+            .finish_non_exhaustive()
+    }
 }
 
 impl FileStore {
     pub fn new(root: impl AsRef<Path>) -> Result<Self> {
+        let backend = Arc::new(DirectoryBackend::new(root.as_ref()));
+        Self::with_backend(root, backend)
+    }
+
+    /// Open a store at `location`; remote locations use `cache_dir` as the local working copy.
+    ///
+    /// With a `key`, blobs (and the index) are encrypted before reaching `location` and the
+    /// decrypted working copy lives in a private temporary directory instead of `cache_dir`.
+    pub fn open(
+        location: &StorageLocation,
+        cache_dir: impl AsRef<Path>,
+        key: Option<EncryptionKey>,
+    ) -> Result<Self> {
+        match (location, key) {
+            (StorageLocation::Local(root), None) => Self::new(root),
+            (remote, None) => Self::with_backend(cache_dir, remote.backend()?),
+            (location, Some(key)) => {
+                let cache = PrivateCache::create()?;
+                let backend = Arc::new(EncryptedBackend::new(location.backend()?, key));
+                let mut store = Self::with_backend(&cache.0, backend)?;
+                store.private_cache = Some(Arc::new(cache));
+                Ok(store)
+            }
+        }
+    }
+
+    /// Use `root` as the local working directory, mirrored to `backend`.
+    pub fn with_backend(root: impl AsRef<Path>, backend: Arc<dyn StorageBackend>) -> Result<Self> {
         let root = root.as_ref().to_path_buf();
         // Create the upload directory eagerly so subsequent saves do not fail at runtime.
         fs::create_dir_all(&root).context("Failed to create upload directory")?;
-        Ok(Self { root })
+
+        let index_path = root.join(INDEX_FILE);
+        backend.download(INDEX_FILE, &index_path)?;
+        let mut index: StoreIndex = match fs::read(&index_path) {
+            Ok(bytes) => serde_json::from_slice(&bytes).unwrap_or_default(),
+            Err(_) => StoreIndex::default(),
+        };
+        // Forget entries whose files were removed behind our back. Remote backends are
+        // trusted instead, to avoid one request per entry at startup.
+        if !backend.is_remote() {
+            let mut missing = Vec::new();
+            for (hash, entry) in &index.entries {
+                if !backend.contains(&entry.filename)? {
+                    missing.push(hash.clone());
+                }
+            }
+            for hash in missing {
+                index.entries.remove(&hash);
+            }
+        }
+        // Indexes written before sizes were tracked: account for uploads still on disk.
+        for entry in index.entries.values_mut() {
+            if entry.sizes.is_empty() {
+                if let Ok(meta) = fs::metadata(root.join(&entry.filename)) {
+                    entry.sizes.insert(entry.filename.clone(), meta.len());
+                }
+            }
+        }
+
+        Ok(Self {
+            root,
+            backend,
+            index: Arc::new(Mutex::new(index)),
+            limits: StoreLimits::default(),
+            private_cache: None,
+        })
     }
 
+    pub fn with_limits(mut self, limits: StoreLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    pub fn limits(&self) -> StoreLimits {
+        self.limits
+    }
+
+    /// Bytes currently accounted against [`StoreLimits::max_total_bytes`].
+    pub fn used_bytes(&self) -> Result<u64> {
+        Ok(self.lock_index()?.used_bytes())
+    }
+
+    pub fn describe(&self) -> String {
+        format!("{} (working copy {:?})", self.backend.describe(), self.root)
+    }
+
+    /// Store `bytes`, or return the existing name when identical content is already stored.
+    /// Each call takes one reference that is given back with [`FileStore::release`].
+    ///
+    /// Fails with a [`QuotaError`] when the upload does not fit within the store limits.
     pub fn save(&self, original_name: Option<&str>, bytes: &[u8]) -> Result<String> {
+        let size = bytes.len() as u64;
+        self.limits.check_upload(size)?;
+        let hash = hex::encode(Sha256::digest(bytes));
+        let mut index = self.lock_index()?;
+        let now = index.tick();
+
+        if let Some(entry) = index.entries.get_mut(&hash) {
+            if self.fetch(&entry.filename)? {
+                entry.refs += 1;
+                entry.last_access = now;
+                let filename = entry.filename.clone();
+                self.persist(&index)?;
+                return Ok(filename);
+            }
+        }
+
         // Use a sanitized stem plus a content hash to avoid collisions and unsafe paths.
         let stem = original_name
             .and_then(|n| Path::new(n).file_stem().and_then(|s| s.to_str()))
@@ -33,15 +472,103 @@ impl FileStore {
             .filter(|s| !s.is_empty())
             .unwrap_or_else(|| "dicom".to_string());
 
-        let hash = hex::encode(Sha256::digest(bytes));
+        // A stale entry whose file vanished from the backend is replaced below.
+        index.entries.remove(&hash);
+        self.make_room(&mut index, size, None)?;
+
         let filename = format!("{}-{}.dcm", stem, &hash[..12]);
         let path = self.root.join(&filename);
-        fs::write(&path, bytes).context("Failed to persist uploaded file")?;
+        atomic_file::write(&path, bytes).context("Failed to persist uploaded file")?;
+        self.backend.upload(&filename, &path)?;
+        index.entries.insert(
+            hash,
+            StoredEntry {
+                filename: filename.clone(),
+                refs: 1,
+                derived: Vec::new(),
+                sizes: HashMap::from([(filename.clone(), size)]),
+                last_access: now,
+                artifacts: Vec::new(),
+            },
+        );
+        index.generation += 1;
+        self.persist(&index)?;
         Ok(filename)
     }
 
+    /// Drop one reference to a stored upload. When the last reference goes, the file and
+    /// every artifact derived from it are deleted. Returns whether anything was removed.
+    pub fn release(&self, name: &str) -> Result<bool> {
+        let mut index = self.lock_index()?;
+        let hash = index
+            .hash_of(name)
+            .with_context(|| format!("{} is not a stored upload", name))?;
+        let entry = index.entries.get_mut(&hash).expect("hash found above");
+        entry.refs = entry.refs.saturating_sub(1);
+        if entry.refs > 0 {
+            self.persist(&index)?;
+            return Ok(false);
+        }
+
+        let entry = index.entries.remove(&hash).expect("hash found above");
+        index.generation += 1;
+        self.delete_files(entry.files())?;
+        self.persist(&index)?;
+        Ok(true)
+    }
+
+    /// Number of live references to a stored upload (0 when unknown).
+    pub fn ref_count(&self, name: &str) -> Result<u32> {
+        let index = self.lock_index()?;
+        Ok(index
+            .hash_of(name)
+            .and_then(|hash| index.entries.get(&hash))
+            .map_or(0, |entry| entry.refs))
+    }
+
+    /// SHA-256 (hex) of the content of a stored upload; `None` for derived artifacts.
+    pub fn content_hash(&self, name: &str) -> Result<Option<String>> {
+        Ok(self.lock_index()?.hash_of(name))
+    }
+
+    /// Size in bytes of a stored upload or published derived artifact.
+    pub fn size_of(&self, name: &str) -> Result<Option<u64>> {
+        let index = self.lock_index()?;
+        Ok(index
+            .owner_of(name)
+            .and_then(|hash| index.entries.get(&hash))
+            .and_then(|entry| entry.sizes.get(name).copied()))
+    }
+
+    /// Changes whenever an upload is added or removed, so callers can cache what they derive
+    /// from the set of uploads. Counted in memory, per store and its clones.
+    pub fn generation(&self) -> Result<u64> {
+        Ok(self.lock_index()?.generation)
+    }
+
+    /// Names of every stored upload (derived artifacts excluded), sorted.
+    pub fn uploads(&self) -> Result<Vec<String>> {
+        let index = self.lock_index()?;
+        let mut names: Vec<String> = index
+            .entries
+            .values()
+            .map(|entry| entry.filename.clone())
+            .collect();
+        names.sort();
+        Ok(names)
+    }
+
     pub fn resolve(&self, name: &str) -> Result<PathBuf> {
+        if name.starts_with('.') {
+            bail!("Requested file not found");
+        }
         let candidate = self.root.join(name);
+        // Only plain file names are fetched from the backend; anything else is left to the
+        // traversal guard below.
+        if !candidate.exists() && Path::new(name).file_name() == Some(name.as_ref()) {
+            self.fetch(name)?;
+        }
+        self.touch(name)?;
         let canonical_root = self
             .root
             .canonicalize()
@@ -56,10 +583,16 @@ impl FileStore {
         Ok(canonical)
     }
 
+    /// Path for an artifact derived from `source_name` by `operation` with `parameters`.
+    /// The name is addressed by the source content, operation and parameters, so different
+    /// derivations never overwrite each other while repeating one lands on the same file.
+    /// Because identical uploads share one source name, their derived artifacts are shared
+    /// too; they are tracked so that [`FileStore::release`] cleans them up with the source.
     pub fn derived_path(
         &self,
         source_name: &str,
-        suffix: &str,
+        operation: &str,
+        parameters: &str,
         extension: &str,
     ) -> Result<(String, PathBuf)> {
         let base = Path::new(source_name)
@@ -69,17 +602,375 @@ impl FileStore {
             .filter(|s| !s.is_empty())
             .unwrap_or_else(|| "dicom".to_string());
 
-        let filename = format!("{}-{}.{}", base, suffix, extension);
+        let mut index = self.lock_index()?;
+        let hash = index.hash_of(source_name);
+        let key = Sha256::digest(format!(
+            "{}\0{}\0{}",
+            hash.as_deref().unwrap_or(source_name),
+            operation,
+            parameters
+        ));
+        let filename = format!(
+            "{}-{}-{}.{}",
+            base,
+            sanitize_filename(operation),
+            &hex::encode(key)[..8],
+            extension
+        );
+
+        if let Some(hash) = hash {
+            let entry = index.entries.get_mut(&hash).expect("hash found above");
+            if !entry.derived.contains(&filename) {
+                entry.derived.push(filename.clone());
+            }
+            match entry.artifacts.iter_mut().find(|a| a.filename == filename) {
+                // Rewritten from scratch, so not reusable until published again.
+                Some(artifact) => artifact.published = false,
+                None => entry.artifacts.push(DerivedArtifact {
+                    filename: filename.clone(),
+                    operation: operation.to_string(),
+                    parameters: parameters.to_string(),
+                    published: false,
+                }),
+            }
+            self.persist(&index)?;
+        }
         Ok((filename.clone(), self.root.join(filename)))
     }
+
+    /// Name of a published artifact derived from `source_name` by `operation` with
+    /// `parameters`, when it is still available and can be served instead of redoing the work.
+    pub fn find_derived(
+        &self,
+        source_name: &str,
+        operation: &str,
+        parameters: &str,
+    ) -> Result<Option<String>> {
+        let found = {
+            let index = self.lock_index()?;
+            index
+                .hash_of(source_name)
+                .and_then(|hash| index.entries.get(&hash))
+                .and_then(|entry| {
+                    entry.artifacts.iter().find(|a| {
+                        a.published && a.operation == operation && a.parameters == parameters
+                    })
+                })
+                .map(|a| a.filename.clone())
+        };
+        match found {
+            Some(name) if self.fetch(&name)? => Ok(Some(name)),
+            _ => Ok(None),
+        }
+    }
+
+    /// Artifacts derived from `source_name`, published or not, in creation order.
+    pub fn derivatives(&self, source_name: &str) -> Result<Vec<DerivedArtifact>> {
+        let index = self.lock_index()?;
+        Ok(index
+            .hash_of(source_name)
+            .and_then(|hash| index.entries.get(&hash))
+            .map(|entry| entry.artifacts.clone())
+            .unwrap_or_default())
+    }
+
+    /// Push a derived artifact written under [`FileStore::derived_path`] to the backend.
+    ///
+    /// The artifact counts against the store quota; when it cannot fit it is deleted again
+    /// and a [`QuotaError`] is returned.
+    pub fn publish(&self, name: &str) -> Result<()> {
+        let path = self.root.join(name);
+        let size = fs::metadata(&path)
+            .with_context(|| format!("Failed to stat {:?}", path))?
+            .len();
+        let mut index = self.lock_index()?;
+        if let Some(hash) = index.owner_of(name) {
+            let now = index.tick();
+            let entry = index.entries.get_mut(&hash).expect("hash found above");
+            entry.sizes.remove(name);
+            entry.last_access = now;
+            if let Err(err) = self.make_room(&mut index, size, Some(&hash)) {
+                self.delete_files([name])?;
+                self.persist(&index)?;
+                return Err(err);
+            }
+            let entry = index
+                .entries
+                .get_mut(&hash)
+                .expect("owner is never evicted");
+            entry.sizes.insert(name.to_string(), size);
+            if let Some(artifact) = entry.artifacts.iter_mut().find(|a| a.filename == name) {
+                artifact.published = true;
+            }
+            self.persist(&index)?;
+        }
+        self.backend.upload(name, &path)
+    }
+
+    /// Evict (never from `keep`) until `incoming` more bytes fit within the total quota. The
+    /// published derived artifacts of the least recently used entry go first, as they can be
+    /// made again; then uploads with a single reference; payloads shared by several uploads
+    /// only when nothing else is left.
+    fn make_room(&self, index: &mut StoreIndex, incoming: u64, keep: Option<&str>) -> Result<()> {
+        let Some(limit) = self.limits.max_total_bytes else {
+            return Ok(());
+        };
+        let full = |index: &StoreIndex| QuotaError::StoreFull {
+            requested: incoming,
+            used: index.used_bytes(),
+            limit,
+        };
+        if incoming > limit {
+            return Err(full(index).into());
+        }
+        while index.used_bytes() + incoming > limit {
+            if self.limits.eviction == EvictionPolicy::Reject {
+                return Err(full(index).into());
+            }
+            let candidates = || {
+                index
+                    .entries
+                    .iter()
+                    .filter(|(hash, _)| Some(hash.as_str()) != keep)
+            };
+            let with_derived = candidates()
+                .filter(|(_, entry)| entry.sizes.len() > 1)
+                .min_by_key(|(_, entry)| entry.last_access)
+                .map(|(hash, _)| hash.clone());
+            if let Some(hash) = with_derived {
+                let entry = index.entries.get_mut(&hash).expect("hash found above");
+                let derived: Vec<String> = entry
+                    .sizes
+                    .keys()
+                    .filter(|name| **name != entry.filename)
+                    .cloned()
+                    .collect();
+                entry.sizes.retain(|name, _| !derived.contains(name));
+                entry.derived.retain(|name| !derived.contains(name));
+                entry.artifacts.retain(|a| !derived.contains(&a.filename));
+                self.delete_files(&derived)?;
+                continue;
+            }
+            let victim = candidates()
+                .min_by_key(|(_, entry)| (entry.refs > 1, entry.last_access))
+                .map(|(hash, _)| hash.clone());
+            let Some(victim) = victim else {
+                return Err(full(index).into());
+            };
+            let entry = index
+                .entries
+                .remove(&victim)
+                .expect("victim taken from index");
+            index.generation += 1;
+            self.delete_files(entry.files())?;
+        }
+        Ok(())
+    }
+
+    /// Record an access to `name` for LRU eviction. Only kept in memory until the next write.
+    fn touch(&self, name: &str) -> Result<()> {
+        let mut index = self.lock_index()?;
+        if let Some(hash) = index.owner_of(name) {
+            let now = index.tick();
+            index
+                .entries
+                .get_mut(&hash)
+                .expect("hash found above")
+                .last_access = now;
+        }
+        Ok(())
+    }
+
+    fn delete_files(&self, files: impl IntoIterator<Item = impl AsRef<str>>) -> Result<()> {
+        for file in files {
+            let file = file.as_ref();
+            match fs::remove_file(self.root.join(file)) {
+                Ok(()) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e).with_context(|| format!("Failed to remove {}", file)),
+            }
+            self.backend.remove(file)?;
+        }
+        Ok(())
+    }
+
+    /// Make sure `name` is present locally, fetching it from the backend when needed.
+    fn fetch(&self, name: &str) -> Result<bool> {
+        let local = self.root.join(name);
+        if local.is_file() {
+            return Ok(true);
+        }
+        self.backend.download(name, &local)
+    }
+
+    fn lock_index(&self) -> Result<MutexGuard<'_, StoreIndex>> {
+        self.index
+            .lock()
+            .map_err(|_| anyhow!("File store index lock poisoned"))
+    }
+
+    fn persist(&self, index: &StoreIndex) -> Result<()> {
+        let json = serde_json::to_vec(index).context("Failed to serialize store index")?;
+        let path = self.root.join(INDEX_FILE);
+        atomic_file::write(&path, json).context("Failed to write store index")?;
+        self.backend.upload(INDEX_FILE, &path)
+    }
 }
 
+#[cfg(feature = "s3")]
+pub mod s3 {
+    use std::fs;
+    use std::path::Path;
+
+    use anyhow::{bail, Context, Result};
+    use s3::creds::Credentials;
+    use s3::{Bucket, Region};
+
+    use super::StorageBackend;
+
+    /// S3-compatible object storage (AWS, MinIO, GCS interoperability endpoints).
+    pub struct S3Backend {
+        bucket: Box<Bucket>,
+        prefix: String,
+    }
+
+    impl S3Backend {
+        /// Configure from the environment: credentials via the usual `AWS_ACCESS_KEY_ID` /
+        /// `AWS_SECRET_ACCESS_KEY` (or profile), region from `AWS_REGION` (default
+        /// `us-east-1`), and an optional `AWS_ENDPOINT_URL` for non-AWS services, which
+        /// also switches to path-style addressing.
+        pub fn from_env(bucket: &str, prefix: &str) -> Result<Self> {
+            let region_name = std::env::var("AWS_REGION")
+                .or_else(|_| std::env::var("AWS_DEFAULT_REGION"))
+                .unwrap_or_else(|_| "us-east-1".to_string());
+            let endpoint = std::env::var("AWS_ENDPOINT_URL").ok();
+            let region = match &endpoint {
+                Some(endpoint) => Region::Custom {
+                    region: region_name,
+                    endpoint: endpoint.clone(),
+                },
+                None => region_name.parse().context("Invalid AWS region")?,
+            };
+            let credentials = Credentials::default().context("No S3 credentials found")?;
+            let mut bucket = Bucket::new(bucket, region, credentials)?;
+            if endpoint.is_some() {
+                bucket = bucket.with_path_style();
+            }
+            Ok(Self {
+                bucket,
+                prefix: prefix.to_string(),
+            })
+        }
+
+        fn key(&self, name: &str) -> String {
+            if self.prefix.is_empty() {
+                name.to_string()
+            } else {
+                format!("{}/{}", self.prefix, name)
+            }
+        }
+    }
+
+    impl StorageBackend for S3Backend {
+        fn upload(&self, name: &str, local: &Path) -> Result<()> {
+            let bytes = fs::read(local).with_context(|| format!("Failed to read {:?}", local))?;
+            let response = self.bucket.put_object(self.key(name), &bytes)?;
+            if !(200..300).contains(&response.status_code()) {
+                bail!(
+                    "S3 upload of {} failed with HTTP {}",
+                    name,
+                    response.status_code()
+                );
+            }
+            Ok(())
+        }
+
+        fn download(&self, name: &str, local: &Path) -> Result<bool> {
+            let response = self.bucket.get_object(self.key(name))?;
+            match response.status_code() {
+                200 => {
+                    crate::atomic_file::write(local, response.bytes())?;
+                    Ok(true)
+                }
+                404 => Ok(false),
+                code => bail!("S3 download of {} failed with HTTP {}", name, code),
+            }
+        }
+
+        fn contains(&self, name: &str) -> Result<bool> {
+            let (_, status) = self.bucket.head_object(self.key(name))?;
+            match status {
+                200 => Ok(true),
+                404 => Ok(false),
+                code => bail!("S3 lookup of {} failed with HTTP {}", name, code),
+            }
+        }
+
+        fn remove(&self, name: &str) -> Result<()> {
+            let response = self.bucket.delete_object(self.key(name))?;
+            if !(200..300).contains(&response.status_code()) && response.status_code() != 404 {
+                bail!(
+                    "S3 delete of {} failed with HTTP {}",
+                    name,
+                    response.status_code()
+                );
+            }
+            Ok(())
+        }
+
+        fn is_remote(&self) -> bool {
+            true
+        }
+
+        fn describe(&self) -> String {
+            format!("s3://{}/{}", self.bucket.name(), self.prefix)
+        }
+    }
+}
+
+/// Longest name [`sanitize_filename`] returns, in bytes, leaving room for the hash suffix
+/// and extension within the 255-byte file name limit of common filesystems.
+const MAX_SANITIZED_BYTES: usize = 120;
+
 fn sanitize_filename(input: &str) -> String {
-    // Keep only ASCII word characters and a few safe separators to avoid filesystem surprises.
-    input
-        .chars()
-        .filter(|c| c.is_ascii_alphanumeric() || *c == '-' || *c == '_')
-        .collect()
+    // Keep letters and digits of any script plus a few safe separators, so non-ASCII patient
+    // names stay readable. Other symbols are transliterated and kept only for the letters
+    // and digits that yields ("№" becomes "No"); dots, separators and control characters
+    // never survive.
+    let mut cleaned = String::new();
+    for c in input.chars() {
+        if c.is_alphanumeric() || c == '-' || c == '_' {
+            cleaned.push(c);
+        } else if let Some(ascii) = deunicode::deunicode_char(c) {
+            cleaned.extend(ascii.chars().filter(|c| c.is_ascii_alphanumeric()));
+        }
+        if cleaned.len() > MAX_SANITIZED_BYTES {
+            break;
+        }
+    }
+    while cleaned.len() > MAX_SANITIZED_BYTES {
+        cleaned.pop();
+    }
+    if is_reserved_on_windows(&cleaned) {
+        cleaned.push('_');
+    }
+    cleaned
+}
+
+/// Device names Windows refuses as file names whatever their extension, e.g. `CON` or
+/// `com1`; it also counts superscript digits for the `COM`/`LPT` ports.
+fn is_reserved_on_windows(name: &str) -> bool {
+    let upper = name.to_uppercase();
+    if matches!(upper.as_str(), "CON" | "PRN" | "AUX" | "NUL") {
+        return true;
+    }
+    let port = upper
+        .strip_prefix("COM")
+        .or_else(|| upper.strip_prefix("LPT"));
+    matches!(
+        port,
+        Some("1" | "2" | "3" | "4" | "5" | "6" | "7" | "8" | "9" | "¹" | "²" | "³")
+    )
 }
 
 #[cfg(test)]
@@ -93,6 +984,20 @@ mod tests {
         assert_eq!(cleaned, "weirdname123dcm");
     }
 
+    #[test]
+    fn sanitize_keeps_unicode_letters_and_avoids_windows_pitfalls() {
+        assert_eq!(sanitize_filename("Müller^José"), "MüllerJosé");
+        assert_eq!(sanitize_filename("山田^太郎"), "山田太郎");
+        assert_eq!(sanitize_filename("Case №7"), "CaseNo7");
+        assert_eq!(sanitize_filename("CON"), "CON_");
+        assert_eq!(sanitize_filename("lpt1"), "lpt1_");
+        assert_eq!(sanitize_filename("console"), "console");
+
+        let long = sanitize_filename(&"é".repeat(200));
+        assert!(long.len() <= MAX_SANITIZED_BYTES);
+        assert_eq!(long.chars().count(), MAX_SANITIZED_BYTES / 2);
+    }
+
     #[test]
     fn resolve_rejects_paths_outside_root() {
         let root = tempdir().expect("tmpdir");
@@ -110,4 +1015,280 @@ mod tests {
         let canonical_root = store_root.canonicalize().expect("canonical root");
         assert!(resolved.starts_with(&canonical_root));
     }
+
+    #[test]
+    fn identical_uploads_share_one_file_until_released() {
+        let root = tempdir().expect("tmpdir");
+        let store = FileStore::new(root.path()).expect("store");
+
+        let first = store.save(Some("study.dcm"), b"same bytes").expect("save");
+        let second = store
+            .save(Some("renamed.dcm"), b"same bytes")
+            .expect("save");
+        assert_eq!(first, second);
+        assert_eq!(store.ref_count(&first).unwrap(), 2);
+
+        let (_, derived) = store
+            .derived_path(&first, "anon", "", "dcm")
+            .expect("derived");
+        fs::write(&derived, b"anon").expect("write derived");
+
+        // Index survives a restart.
+        let store = FileStore::new(root.path()).expect("reopen");
+        assert_eq!(store.ref_count(&first).unwrap(), 2);
+        assert!(store.resolve(INDEX_FILE).is_err());
+
+        assert!(!store.release(&first).expect("release"));
+        assert!(store.resolve(&first).is_ok());
+        assert!(store.release(&second).expect("release"));
+        assert!(store.resolve(&first).is_err());
+        assert!(!derived.exists());
+    }
+
+    #[test]
+    fn derived_artifacts_are_keyed_by_operation_and_parameters() {
+        let root = tempdir().expect("tmpdir");
+        let store = FileStore::new(root.path()).expect("store");
+        let source = store.save(Some("ct.dcm"), b"source").expect("save");
+
+        let (explicit, path) = store
+            .derived_path(&source, "transcoded", "1.2.840.10008.1.2.1", "dcm")
+            .expect("derived");
+        let (implicit, _) = store
+            .derived_path(&source, "transcoded", "1.2.840.10008.1.2", "dcm")
+            .expect("derived");
+        assert_ne!(explicit, implicit);
+        assert!(explicit.contains("-transcoded-"));
+
+        // Only a published artifact is offered for reuse.
+        assert_eq!(
+            store
+                .find_derived(&source, "transcoded", "1.2.840.10008.1.2.1")
+                .unwrap(),
+            None
+        );
+        fs::write(&path, b"explicit").expect("write derived");
+        store.publish(&explicit).expect("publish");
+
+        // A re-upload of the same content finds it too, and the manifest survives a restart.
+        let store = FileStore::new(root.path()).expect("reopen");
+        let again = store.save(Some("copy.dcm"), b"source").expect("save");
+        assert_eq!(
+            store
+                .find_derived(&again, "transcoded", "1.2.840.10008.1.2.1")
+                .unwrap(),
+            Some(explicit.clone())
+        );
+        let listed = store.derivatives(&source).expect("list");
+        assert_eq!(listed.len(), 2);
+        assert!(listed[0].published && !listed[1].published);
+        assert_eq!(listed[1].parameters, "1.2.840.10008.1.2");
+    }
+
+    #[test]
+    fn quota_evicts_least_recently_used_uploads() {
+        let root = tempdir().expect("tmpdir");
+        let limits = StoreLimits {
+            max_upload_bytes: Some(8),
+            max_total_bytes: Some(20),
+            eviction: EvictionPolicy::Lru,
+        };
+        let store = FileStore::new(root.path())
+            .expect("store")
+            .with_limits(limits);
+
+        let err = store.save(Some("big.dcm"), &[0; 9]).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<QuotaError>(),
+            Some(QuotaError::UploadTooLarge { size: 9, limit: 8 })
+        ));
+
+        let a = store.save(Some("a.dcm"), &[1; 8]).expect("save a");
+        let b = store.save(Some("b.dcm"), &[2; 8]).expect("save b");
+        store.resolve(&a).expect("touch a");
+        let c = store.save(Some("c.dcm"), &[3; 8]).expect("save c");
+        assert_eq!(store.used_bytes().unwrap(), 16);
+        assert!(store.resolve(&a).is_ok());
+        assert!(store.resolve(&b).is_err());
+        assert!(store.resolve(&c).is_ok());
+
+        // With eviction disabled a full store refuses new content.
+        let store = FileStore::new(root.path())
+            .expect("reopen")
+            .with_limits(StoreLimits {
+                eviction: EvictionPolicy::Reject,
+                ..limits
+            });
+        let err = store.save(Some("d.dcm"), &[4; 8]).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<QuotaError>(),
+            Some(QuotaError::StoreFull { used: 16, .. })
+        ));
+    }
+
+    #[test]
+    fn eviction_drops_derived_artifacts_and_spares_shared_payloads() {
+        let root = tempdir().expect("tmpdir");
+        let store = FileStore::new(root.path())
+            .expect("store")
+            .with_limits(StoreLimits {
+                max_upload_bytes: Some(8),
+                max_total_bytes: Some(20),
+                eviction: EvictionPolicy::Lru,
+            });
+
+        // The oldest payload is shared by two uploads; the newer one has a single reference.
+        let shared = store.save(Some("a.dcm"), &[1; 8]).expect("save a");
+        assert_eq!(store.save(Some("a2.dcm"), &[1; 8]).unwrap(), shared);
+        let single = store.save(Some("b.dcm"), &[2; 8]).expect("save b");
+        let c = store.save(Some("c.dcm"), &[3; 8]).expect("save c");
+        assert!(store.resolve(&single).is_err());
+        assert_eq!(store.ref_count(&shared).unwrap(), 2);
+        assert!(store.resolve(&shared).is_ok());
+
+        // A derived artifact goes before any upload, however recently it was used.
+        let (anon, path) = store.derived_path(&c, "anon", "", "dcm").expect("derived");
+        fs::write(&path, [9; 2]).expect("write derived");
+        store.publish(&anon).expect("publish");
+        store.resolve(&anon).expect("touch derived");
+        let d = store.save(Some("d.dcm"), &[4; 3]).expect("save d");
+        assert!(!path.exists());
+        assert_eq!(store.find_derived(&c, "anon", "").unwrap(), None);
+        assert!(store.derivatives(&c).unwrap().is_empty());
+        for name in [&shared, &c, &d] {
+            assert!(store.resolve(name).is_ok(), "{} evicted", name);
+        }
+        assert_eq!(store.used_bytes().unwrap(), 19);
+    }
+
+    /// In-memory stand-in for an object store.
+    #[derive(Default)]
+    struct MemoryBackend {
+        blobs: Mutex<HashMap<String, Vec<u8>>>,
+    }
+
+    impl StorageBackend for MemoryBackend {
+        fn upload(&self, name: &str, local: &Path) -> Result<()> {
+            let bytes = fs::read(local)?;
+            self.blobs.lock().unwrap().insert(name.to_string(), bytes);
+            Ok(())
+        }
+
+        fn download(&self, name: &str, local: &Path) -> Result<bool> {
+            match self.blobs.lock().unwrap().get(name) {
+                Some(bytes) => {
+                    fs::write(local, bytes)?;
+                    Ok(true)
+                }
+                None => Ok(false),
+            }
+        }
+
+        fn contains(&self, name: &str) -> Result<bool> {
+            Ok(self.blobs.lock().unwrap().contains_key(name))
+        }
+
+        fn remove(&self, name: &str) -> Result<()> {
+            self.blobs.lock().unwrap().remove(name);
+            Ok(())
+        }
+
+        fn is_remote(&self) -> bool {
+            true
+        }
+
+        fn describe(&self) -> String {
+            "memory".to_string()
+        }
+    }
+
+    #[test]
+    fn remote_backend_restores_files_into_an_empty_cache() {
+        let backend = Arc::new(MemoryBackend::default());
+        let first_cache = tempdir().expect("tmpdir");
+        let store = FileStore::with_backend(first_cache.path(), backend.clone()).expect("store");
+        let name = store.save(Some("ct.dcm"), b"pixels").expect("save");
+
+        // A fresh instance with an empty cache sees the same index and content.
+        let second_cache = tempdir().expect("tmpdir");
+        let store = FileStore::with_backend(second_cache.path(), backend.clone()).expect("store");
+        assert_eq!(store.ref_count(&name).unwrap(), 1);
+        let path = store.resolve(&name).expect("resolve");
+        assert_eq!(fs::read(path).unwrap(), b"pixels");
+
+        assert!(store.release(&name).expect("release"));
+        assert!(!backend.blobs.lock().unwrap().contains_key(&name));
+    }
+
+    #[test]
+    fn encrypted_store_keeps_only_ciphertext_at_rest() {
+        let durable = tempdir().expect("tmpdir");
+        let location = StorageLocation::Local(durable.path().to_path_buf());
+        let secrets = tempdir().expect("tmpdir");
+        let key_file = secrets.path().join("store.key");
+        fs::write(&key_file, hex::encode([3u8; 32])).expect("key");
+        let key = || EncryptionKey::from_file(&key_file).expect("load key");
+
+        let store = FileStore::open(&location, "unused", Some(key())).expect("store");
+        let name = store.save(Some("ct.dcm"), b"DOE^JANE").expect("save");
+        let at_rest = fs::read(durable.path().join(&name)).expect("stored blob");
+        assert!(!at_rest.windows(8).any(|w| w == b"DOE^JANE"));
+        let index = fs::read(durable.path().join(INDEX_FILE)).expect("index");
+        assert!(!String::from_utf8_lossy(&index).contains("ct-"));
+        drop(store);
+
+        // Restart: the index is decrypted, entries survive pruning, content is readable.
+        let store = FileStore::open(&location, "unused", Some(key())).expect("reopen");
+        assert_eq!(store.ref_count(&name).unwrap(), 1);
+        let path = store.resolve(&name).expect("resolve");
+        assert!(!path.starts_with(durable.path().canonicalize().unwrap()));
+        assert_eq!(fs::read(path).unwrap(), b"DOE^JANE");
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn plaintext_left_by_a_killed_process_is_swept_on_open() {
+        // A pid that has certainly exited: a child that has been waited for.
+        let mut child = std::process::Command::new("true").spawn().expect("spawn");
+        child.wait().expect("wait");
+        let stale = std::env::temp_dir().join(format!("{}{}-0", PRIVATE_CACHE_PREFIX, child.id()));
+        fs::create_dir_all(&stale).expect("stale cache");
+        fs::write(stale.join("ct.dcm"), b"DOE^JANE").expect("plaintext");
+        let live = std::env::temp_dir().join(format!(
+            "{}{}-999999",
+            PRIVATE_CACHE_PREFIX,
+            std::process::id()
+        ));
+        fs::create_dir_all(&live).expect("live cache");
+
+        let durable = tempdir().expect("tmpdir");
+        let location = StorageLocation::Local(durable.path().to_path_buf());
+        let secrets = tempdir().expect("tmpdir");
+        let key_file = secrets.path().join("store.key");
+        fs::write(&key_file, hex::encode([5u8; 32])).expect("key");
+        let key = EncryptionKey::from_file(&key_file).expect("load key");
+        let store = FileStore::open(&location, "unused", Some(key)).expect("store");
+        assert!(!stale.exists());
+        assert!(live.exists());
+        drop(store);
+        fs::remove_dir_all(&live).unwrap();
+    }
+
+    #[test]
+    fn storage_location_parses_s3_urls() {
+        assert_eq!(
+            "s3://bucket/studies/incoming/"
+                .parse::<StorageLocation>()
+                .unwrap(),
+            StorageLocation::S3 {
+                bucket: "bucket".into(),
+                prefix: "studies/incoming".into()
+            }
+        );
+        assert_eq!(
+            "target/uploads".parse::<StorageLocation>().unwrap(),
+            StorageLocation::Local(PathBuf::from("target/uploads"))
+        );
+        assert!("s3://".parse::<StorageLocation>().is_err());
+    }
 }
@@ -0,0 +1,469 @@
+//
+// stow.rs
+// Dicom-Tools-rs
+//
+// DICOMweb STOW-RS client (PS3.18 10.5): uploads DICOM files as multipart/related
+// application/dicom requests, optionally with a bearer token, and reads the store
+// instances response back into accepted and rejected instances.
+//
+// Thales Matheus Mendonça Santos - November 2025
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{bail, Context, Result};
+use dicom::core::Tag;
+use dicom::object::OpenFileOptions;
+use serde::Serialize;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use walkdir::WalkDir;
+
+use crate::dicom_access::ElementAccess;
+use crate::transfer_limits::{Throttle, TransferWindow};
+
+const SOP_CLASS_UID: Tag = Tag(0x0008, 0x0016);
+const SOP_INSTANCE_UID: Tag = Tag(0x0008, 0x0018);
+const PIXEL_DATA: Tag = Tag(0x7FE0, 0x0010);
+
+// Attributes of the store instances response, as DICOM JSON keys.
+const FAILURE_REASON: &str = "00081197";
+const FAILED_SOP_SEQUENCE: &str = "00081198";
+const REFERENCED_SOP_SEQUENCE: &str = "00081199";
+const REFERENCED_SOP_CLASS_UID: &str = "00081150";
+const REFERENCED_SOP_INSTANCE_UID: &str = "00081155";
+const RETRIEVE_URL: &str = "00081190";
+const WARNING_REASON: &str = "00081196";
+
+/// Where and how to upload.
+#[derive(Debug, Clone)]
+pub struct StowOptions {
+    /// DICOMweb service root (`.../dicomweb`) or a `.../studies` endpoint.
+    pub url: String,
+    pub token: Option<String>,
+    /// Files per request; large uploads are split so one failure does not lose them all.
+    pub batch_size: usize,
+    pub timeout: Option<Duration>,
+    /// Average rate cap in MB/s, enforced between requests.
+    pub rate_limit: Option<f64>,
+    /// Daily window outside which uploading pauses.
+    pub window: Option<TransferWindow>,
+}
+
+impl StowOptions {
+    /// The STOW-RS endpoint: `studies` appended to a service root.
+    pub fn endpoint(&self) -> String {
+        let url = self.url.trim_end_matches('/');
+        if url.ends_with("/studies") || url.contains("/studies/") {
+            url.to_string()
+        } else {
+            format!("{}/studies", url)
+        }
+    }
+}
+
+/// An instance the server stored.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct StoredInstance {
+    pub sop_class_uid: String,
+    pub sop_instance_uid: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retrieve_url: Option<String>,
+    /// Warning Reason, when the server stored it with coercion or discrepancies.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub warning: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file: Option<PathBuf>,
+}
+
+/// An instance the server did not store.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RejectedInstance {
+    pub sop_instance_uid: String,
+    /// Failure Reason from the response (e.g. 0xA700 out of resources, 0x0122 SOP class not
+    /// supported); absent when the whole request failed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub failure_reason: Option<u16>,
+    /// Why the request failed, when the server did not answer per instance.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file: Option<PathBuf>,
+}
+
+impl RejectedInstance {
+    /// Whether the upload may succeed later: out of resources (0xA7xx), a server-side HTTP
+    /// error, throttling, or a request that never got an answer.
+    pub fn is_transient(&self) -> bool {
+        match (self.failure_reason, &self.error) {
+            (Some(reason), _) => reason & 0xFF00 == 0xA700,
+            (None, Some(error)) => match error.strip_prefix("HTTP ") {
+                Some(status) => status
+                    .parse::<u16>()
+                    .is_ok_and(|status| status >= 500 || status == 408 || status == 429),
+                None => true,
+            },
+            (None, None) => false,
+        }
+    }
+
+    /// Failure Reason or error, for reports.
+    pub fn reason(&self) -> String {
+        match (self.failure_reason, &self.error) {
+            (Some(reason), _) => format!("failure reason 0x{:04X}", reason),
+            (None, Some(error)) => error.clone(),
+            (None, None) => "rejected".to_string(),
+        }
+    }
+}
+
+/// Outcome of an upload.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct StowSummary {
+    pub endpoint: String,
+    pub requests: usize,
+    pub accepted: Vec<StoredInstance>,
+    pub rejected: Vec<RejectedInstance>,
+    /// Inputs that are not readable DICOM files and were not sent.
+    pub skipped: Vec<PathBuf>,
+}
+
+/// One file to upload.
+struct Upload {
+    path: PathBuf,
+    sop_class_uid: String,
+    sop_instance_uid: String,
+}
+
+/// DICOM files among `inputs`, directories walked recursively, in path order.
+fn collect_uploads(inputs: &[PathBuf], skipped: &mut Vec<PathBuf>) -> Result<Vec<Upload>> {
+    let mut paths = Vec::new();
+    for input in inputs {
+        if input.is_dir() {
+            let mut found: Vec<PathBuf> = WalkDir::new(input)
+                .into_iter()
+                .filter_map(|e| e.ok())
+                .filter(|e| e.file_type().is_file())
+                .map(|e| e.into_path())
+                .collect();
+            found.sort();
+            paths.extend(found);
+        } else if input.is_file() {
+            paths.push(input.clone());
+        } else {
+            bail!("{:?} does not exist", input);
+        }
+    }
+    let mut uploads = Vec::new();
+    for path in paths {
+        let header = OpenFileOptions::new()
+            .read_until(PIXEL_DATA)
+            .open_file(&path);
+        let uid = |tag| {
+            header
+                .as_ref()
+                .ok()
+                .and_then(|obj| obj.element_str(tag))
+                .map(|v| v.trim_end_matches(['\0', ' ']).to_string())
+                .filter(|v| !v.is_empty())
+        };
+        match (uid(SOP_CLASS_UID), uid(SOP_INSTANCE_UID)) {
+            (Some(sop_class_uid), Some(sop_instance_uid)) => uploads.push(Upload {
+                path,
+                sop_class_uid,
+                sop_instance_uid,
+            }),
+            _ => skipped.push(path),
+        }
+    }
+    Ok(uploads)
+}
+
+/// A boundary that cannot occur in the parts: derived from the time and the first file.
+fn boundary(batch: &[Upload]) -> String {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or_default();
+    let seed = format!(
+        "{}|{}",
+        nanos,
+        batch.first().map_or("", |u| u.sop_instance_uid.as_str())
+    );
+    format!("DICOMweb-{}", &hex::encode(Sha256::digest(seed))[..32])
+}
+
+/// The multipart/related body of one request, one application/dicom part per file.
+fn multipart_body(batch: &[Upload], boundary: &str) -> Result<Vec<u8>> {
+    let mut body = Vec::new();
+    for upload in batch {
+        let bytes =
+            fs::read(&upload.path).with_context(|| format!("Failed to read {:?}", upload.path))?;
+        body.extend_from_slice(
+            format!("--{}\r\nContent-Type: application/dicom\r\n\r\n", boundary).as_bytes(),
+        );
+        body.extend_from_slice(&bytes);
+        body.extend_from_slice(b"\r\n");
+    }
+    body.extend_from_slice(format!("--{}--\r\n", boundary).as_bytes());
+    Ok(body)
+}
+
+/// First string value of a DICOM JSON attribute.
+fn json_str(item: &Value, key: &str) -> Option<String> {
+    item.get(key)?
+        .get("Value")?
+        .get(0)?
+        .as_str()
+        .map(str::to_string)
+}
+
+fn json_u16(item: &Value, key: &str) -> Option<u16> {
+    item.get(key)?
+        .get("Value")?
+        .get(0)?
+        .as_u64()
+        .and_then(|v| u16::try_from(v).ok())
+}
+
+fn json_items<'a>(response: &'a Value, key: &str) -> &'a [Value] {
+    response
+        .get(key)
+        .and_then(|attribute| attribute.get("Value"))
+        .and_then(Value::as_array)
+        .map_or(&[], Vec::as_slice)
+}
+
+/// Sort the instances of one request into the summary from the server's response. Instances
+/// the response does not mention are accepted when the request succeeded outright and
+/// rejected otherwise.
+fn record_response(summary: &mut StowSummary, batch: &[Upload], status: u16, body: &[u8]) {
+    let response: Option<Value> = serde_json::from_slice(body).ok();
+    // Some servers answer with a one-element array, as for other DICOM JSON payloads.
+    let response = match response {
+        Some(Value::Array(mut items)) if items.len() == 1 => Some(items.remove(0)),
+        other => other,
+    };
+    let mut files: HashMap<&str, &Upload> = batch
+        .iter()
+        .map(|upload| (upload.sop_instance_uid.as_str(), upload))
+        .collect();
+    if let Some(response) = &response {
+        for item in json_items(response, REFERENCED_SOP_SEQUENCE) {
+            let Some(sop_instance_uid) = json_str(item, REFERENCED_SOP_INSTANCE_UID) else {
+                continue;
+            };
+            let upload = files.remove(sop_instance_uid.as_str());
+            summary.accepted.push(StoredInstance {
+                sop_class_uid: json_str(item, REFERENCED_SOP_CLASS_UID)
+                    .or_else(|| upload.map(|u| u.sop_class_uid.clone()))
+                    .unwrap_or_default(),
+                retrieve_url: json_str(item, RETRIEVE_URL),
+                warning: json_u16(item, WARNING_REASON),
+                file: upload.map(|u| u.path.clone()),
+                sop_instance_uid,
+            });
+        }
+        for item in json_items(response, FAILED_SOP_SEQUENCE) {
+            let Some(sop_instance_uid) = json_str(item, REFERENCED_SOP_INSTANCE_UID) else {
+                continue;
+            };
+            let upload = files.remove(sop_instance_uid.as_str());
+            summary.rejected.push(RejectedInstance {
+                failure_reason: json_u16(item, FAILURE_REASON),
+                error: None,
+                file: upload.map(|u| u.path.clone()),
+                sop_instance_uid,
+            });
+        }
+    }
+    // Keep the input order for instances the response leaves out.
+    for upload in batch {
+        if files.remove(upload.sop_instance_uid.as_str()).is_none() {
+            continue;
+        }
+        if status == 200 {
+            summary.accepted.push(StoredInstance {
+                sop_class_uid: upload.sop_class_uid.clone(),
+                sop_instance_uid: upload.sop_instance_uid.clone(),
+                retrieve_url: None,
+                warning: None,
+                file: Some(upload.path.clone()),
+            });
+        } else {
+            summary.rejected.push(RejectedInstance {
+                sop_instance_uid: upload.sop_instance_uid.clone(),
+                failure_reason: None,
+                error: Some(format!("HTTP {}", status)),
+                file: Some(upload.path.clone()),
+            });
+        }
+    }
+}
+
+/// Upload the DICOM files among `inputs` (directories are walked) with STOW-RS. A request
+/// that fails, at the HTTP level or because the server cannot be reached, rejects its
+/// instances rather than aborting the upload.
+pub fn store(inputs: &[PathBuf], options: &StowOptions) -> Result<StowSummary> {
+    let mut summary = StowSummary {
+        endpoint: options.endpoint(),
+        ..StowSummary::default()
+    };
+    let uploads = collect_uploads(inputs, &mut summary.skipped)?;
+    if uploads.is_empty() {
+        bail!("No DICOM files to upload");
+    }
+    let mut throttle = options.rate_limit.map(Throttle::new).transpose()?;
+    for batch in uploads.chunks(options.batch_size.max(1)) {
+        if options
+            .window
+            .is_some_and(|window| window.wait_until_open())
+        {
+            if let Some(throttle) = &mut throttle {
+                throttle.restart();
+            }
+        }
+        let boundary = boundary(batch);
+        let body = multipart_body(batch, &boundary)?;
+        let size = body.len();
+        let mut request = attohttpc::post(&summary.endpoint)
+            .header(
+                "Content-Type",
+                format!(
+                    "multipart/related; type=\"application/dicom\"; boundary={}",
+                    boundary
+                ),
+            )
+            .header("Accept", "application/dicom+json");
+        if let Some(token) = &options.token {
+            request = request.bearer_auth(token.as_str());
+        }
+        if let Some(timeout) = options.timeout {
+            request = request.timeout(timeout);
+        }
+        summary.requests += 1;
+        let sent = request.bytes(body).send().and_then(|response| {
+            let status = response.status().as_u16();
+            response.bytes().map(|body| (status, body))
+        });
+        if let Some(throttle) = &mut throttle {
+            throttle.pace(size);
+        }
+        match sent {
+            Ok((status, body)) => record_response(&mut summary, batch, status, &body),
+            Err(err) => {
+                for upload in batch {
+                    summary.rejected.push(RejectedInstance {
+                        sop_instance_uid: upload.sop_instance_uid.clone(),
+                        failure_reason: None,
+                        error: Some(format!("request failed: {}", err)),
+                        file: Some(upload.path.clone()),
+                    });
+                }
+            }
+        }
+    }
+    Ok(summary)
+}
+
+/// CLI helper: upload, then print the summary as a table or as JSON.
+pub fn print_store(inputs: &[PathBuf], options: &StowOptions, json: bool) -> Result<StowSummary> {
+    let summary = store(inputs, options)?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&summary)?);
+        return Ok(summary);
+    }
+    println!(
+        "STOW-RS to {}: {} accepted, {} rejected in {} request(s)",
+        summary.endpoint,
+        summary.accepted.len(),
+        summary.rejected.len(),
+        summary.requests
+    );
+    for instance in summary.accepted.iter().filter(|i| i.warning.is_some()) {
+        println!(
+            "  warning 0x{:04X}: {}",
+            instance.warning.unwrap_or_default(),
+            instance.sop_instance_uid
+        );
+    }
+    for instance in &summary.rejected {
+        let reason = match (&instance.failure_reason, &instance.error) {
+            (Some(code), _) => format!("0x{:04X}", code),
+            (None, Some(error)) => error.clone(),
+            (None, None) => "unknown".to_string(),
+        };
+        println!(
+            "  rejected ({}): {}{}",
+            reason,
+            instance.sop_instance_uid,
+            instance
+                .file
+                .as_ref()
+                .map(|f| format!(" [{}]", f.display()))
+                .unwrap_or_default()
+        );
+    }
+    if !summary.skipped.is_empty() {
+        println!("  {} non-DICOM file(s) skipped", summary.skipped.len());
+    }
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn upload(uid: &str) -> Upload {
+        Upload {
+            path: PathBuf::from(format!("{}.dcm", uid)),
+            sop_class_uid: "1.2.840.10008.5.1.4.1.1.7".to_string(),
+            sop_instance_uid: uid.to_string(),
+        }
+    }
+
+    #[test]
+    fn endpoint_appends_studies_to_a_service_root() {
+        let mut options = StowOptions {
+            url: "https://pacs.example/dicomweb/".to_string(),
+            token: None,
+            batch_size: 10,
+            timeout: None,
+            rate_limit: None,
+            window: None,
+        };
+        assert_eq!(options.endpoint(), "https://pacs.example/dicomweb/studies");
+        options.url = "https://pacs.example/dicomweb/studies/1.2.3".to_string();
+        assert_eq!(options.endpoint(), options.url);
+    }
+
+    #[test]
+    fn partial_responses_sort_instances_and_fill_in_the_rest() {
+        let batch = [upload("1.1"), upload("1.2"), upload("1.3")];
+        let response = br#"{
+            "00081199": {"vr": "SQ", "Value": [
+                {"00081150": {"vr": "UI", "Value": ["1.2.840.10008.5.1.4.1.1.7"]},
+                 "00081155": {"vr": "UI", "Value": ["1.1"]},
+                 "00081190": {"vr": "UR", "Value": ["https://pacs/studies/9/series/8/instances/1.1"]}}
+            ]},
+            "00081198": {"vr": "SQ", "Value": [
+                {"00081155": {"vr": "UI", "Value": ["1.2"]},
+                 "00081197": {"vr": "US", "Value": [290]}}
+            ]}
+        }"#;
+        let mut summary = StowSummary::default();
+        record_response(&mut summary, &batch, 202, response);
+        assert_eq!(summary.accepted.len(), 1);
+        assert!(summary.accepted[0].retrieve_url.is_some());
+        assert_eq!(summary.rejected.len(), 2);
+        assert_eq!(summary.rejected[0].failure_reason, Some(0x0122));
+        assert_eq!(summary.rejected[1].sop_instance_uid, "1.3");
+        assert_eq!(summary.rejected[1].error.as_deref(), Some("HTTP 202"));
+
+        let mut summary = StowSummary::default();
+        record_response(&mut summary, &batch, 401, b"");
+        assert_eq!(summary.rejected.len(), 3);
+    }
+}
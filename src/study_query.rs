@@ -0,0 +1,255 @@
+//
+// study_query.rs
+// Dicom-Tools-rs
+//
+// Study Root C-FIND for the SCP: matches an identifier at the STUDY, SERIES or IMAGE level
+// against an index of the served files (PS3.4 C.4.1), with the matching rules of the worklist,
+// and builds one response identifier per matching study, series or instance.
+//
+// Thales Matheus Mendonça Santos - November 2025
+
+use std::collections::BTreeSet;
+
+use anyhow::{bail, Result};
+use dicom::core::header::Header;
+use dicom::core::value::DataSetSequence;
+use dicom::core::{DataElement, PrimitiveValue, Tag, VR};
+use dicom::object::{InMemDicomObject, OpenFileOptions};
+
+use crate::dicom_access::ElementAccess;
+use crate::scp::{IndexedInstance, InstanceIndex};
+use crate::worklist::{query_value, value_matches};
+
+const SPECIFIC_CHARACTER_SET: Tag = Tag(0x0008, 0x0005);
+const SOP_INSTANCE_UID: Tag = Tag(0x0008, 0x0018);
+const QUERY_RETRIEVE_LEVEL: Tag = Tag(0x0008, 0x0052);
+const RETRIEVE_AE_TITLE: Tag = Tag(0x0008, 0x0054);
+const MODALITY: Tag = Tag(0x0008, 0x0060);
+const MODALITIES_IN_STUDY: Tag = Tag(0x0008, 0x0061);
+const PATIENT_ID: Tag = Tag(0x0010, 0x0020);
+const STUDY_INSTANCE_UID: Tag = Tag(0x0020, 0x000D);
+const SERIES_INSTANCE_UID: Tag = Tag(0x0020, 0x000E);
+const NUMBER_OF_STUDY_RELATED_SERIES: Tag = Tag(0x0020, 0x1206);
+const NUMBER_OF_STUDY_RELATED_INSTANCES: Tag = Tag(0x0020, 0x1208);
+const NUMBER_OF_SERIES_RELATED_INSTANCES: Tag = Tag(0x0020, 0x1209);
+const PIXEL_DATA: Tag = Tag(0x7FE0, 0x0010);
+
+/// Query/Retrieve Level of a Study Root query.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QueryLevel {
+    Study,
+    Series,
+    Image,
+}
+
+impl QueryLevel {
+    pub fn parse(code: &str) -> Option<Self> {
+        match code.trim_matches(['\0', ' ']) {
+            "STUDY" => Some(QueryLevel::Study),
+            "SERIES" => Some(QueryLevel::Series),
+            "IMAGE" => Some(QueryLevel::Image),
+            _ => None,
+        }
+    }
+
+    pub fn code(self) -> &'static str {
+        match self {
+            QueryLevel::Study => "STUDY",
+            QueryLevel::Series => "SERIES",
+            QueryLevel::Image => "IMAGE",
+        }
+    }
+
+    /// Unique keys of the levels above this one, which a hierarchical query must give.
+    fn required_keys(self) -> &'static [Tag] {
+        match self {
+            QueryLevel::Study => &[],
+            QueryLevel::Series => &[STUDY_INSTANCE_UID],
+            QueryLevel::Image => &[STUDY_INSTANCE_UID, SERIES_INSTANCE_UID],
+        }
+    }
+
+    /// The study, series or instance `instance` belongs to at this level.
+    fn entity(self, instance: &IndexedInstance) -> &str {
+        match self {
+            QueryLevel::Study => &instance.study_instance_uid,
+            QueryLevel::Series => &instance.series_instance_uid,
+            QueryLevel::Image => &instance.sop_instance_uid,
+        }
+    }
+}
+
+/// One study, series or instance and what its attributes are read from: the header of the
+/// first instance of each of its series.
+struct Entity<'a> {
+    instances: Vec<&'a IndexedInstance>,
+    headers: Vec<InMemDicomObject>,
+}
+
+impl Entity<'_> {
+    fn value(&self, tag: Tag) -> Option<String> {
+        let value = match tag {
+            MODALITIES_IN_STUDY => self.modalities().into_iter().collect::<Vec<_>>().join("\\"),
+            NUMBER_OF_STUDY_RELATED_SERIES => self.headers.len().to_string(),
+            NUMBER_OF_STUDY_RELATED_INSTANCES | NUMBER_OF_SERIES_RELATED_INSTANCES => {
+                self.instances.len().to_string()
+            }
+            _ => self.headers.first()?.element_str(tag)?,
+        };
+        let value = value.trim_matches(['\0', ' ']).to_string();
+        (!value.is_empty()).then_some(value)
+    }
+
+    fn modalities(&self) -> BTreeSet<String> {
+        self.headers
+            .iter()
+            .filter_map(|header| header.element_str(MODALITY))
+            .map(|modality| modality.trim_matches(['\0', ' ']).to_string())
+            .filter(|modality| !modality.is_empty())
+            .collect()
+    }
+
+    /// Whether every matching key of `identifier` matches. Modalities in Study matches when
+    /// any of the study's modalities is among the requested ones.
+    fn matches(&self, identifier: &InMemDicomObject) -> bool {
+        identifier.iter().all(|element| {
+            let tag = element.tag();
+            if matches!(tag, QUERY_RETRIEVE_LEVEL | SPECIFIC_CHARACTER_SET)
+                || element.vr() == VR::SQ
+            {
+                return true;
+            }
+            let Some(pattern) = query_value(identifier, tag) else {
+                return true;
+            };
+            if tag == MODALITIES_IN_STUDY {
+                let modalities = self.modalities();
+                return pattern
+                    .split('\\')
+                    .any(|wanted| modalities.iter().any(|m| value_matches(VR::CS, wanted, m)));
+            }
+            self.value(tag)
+                .is_some_and(|value| value_matches(element.vr(), &pattern, &value))
+        })
+    }
+
+    /// Response identifier: the requested keys filled from this entity.
+    fn response(
+        &self,
+        identifier: &InMemDicomObject,
+        level: QueryLevel,
+        retrieve_ae_title: &str,
+    ) -> InMemDicomObject {
+        let mut out = InMemDicomObject::new_empty();
+        for element in identifier.iter() {
+            let (tag, vr) = (element.tag(), element.vr());
+            if vr == VR::SQ {
+                out.put(DataElement::new(
+                    tag,
+                    VR::SQ,
+                    DataSetSequence::<InMemDicomObject>::from(Vec::new()),
+                ));
+                continue;
+            }
+            let value = match self.value(tag) {
+                Some(value) => PrimitiveValue::from(value),
+                None => PrimitiveValue::Empty,
+            };
+            out.put(DataElement::new(tag, vr, value));
+        }
+        out.put(DataElement::new(
+            QUERY_RETRIEVE_LEVEL,
+            VR::CS,
+            PrimitiveValue::from(level.code()),
+        ));
+        out.put(DataElement::new(
+            RETRIEVE_AE_TITLE,
+            VR::AE,
+            PrimitiveValue::from(retrieve_ae_title),
+        ));
+        if let Some(charset) = self.value(SPECIFIC_CHARACTER_SET) {
+            out.put(DataElement::new(
+                SPECIFIC_CHARACTER_SET,
+                VR::CS,
+                PrimitiveValue::from(charset),
+            ));
+        }
+        out
+    }
+}
+
+/// Answer a Study Root C-FIND identifier from `index`, one response per matching study,
+/// series or instance in index order. Fails when the level is missing or unknown, or when a
+/// unique key of a higher level is missing.
+pub fn answer(
+    index: &InstanceIndex,
+    identifier: &InMemDicomObject,
+    retrieve_ae_title: &str,
+) -> Result<Vec<InMemDicomObject>> {
+    let level_code = identifier
+        .element_str(QUERY_RETRIEVE_LEVEL)
+        .unwrap_or_default();
+    let Some(level) = QueryLevel::parse(&level_code) else {
+        bail!("Unsupported Query/Retrieve Level {:?}", level_code.trim());
+    };
+    for &tag in level.required_keys() {
+        if query_value(identifier, tag).is_none() {
+            bail!(
+                "{}-level query without the unique key {}",
+                level.code(),
+                tag
+            );
+        }
+    }
+
+    // The keys the index holds narrow the candidates before any header is read.
+    let indexed_key = |instance: &IndexedInstance, tag: Tag| -> Option<bool> {
+        let pattern = query_value(identifier, tag)?;
+        Some(match tag {
+            PATIENT_ID => value_matches(VR::LO, &pattern, &instance.patient_id),
+            STUDY_INSTANCE_UID => value_matches(VR::UI, &pattern, &instance.study_instance_uid),
+            SERIES_INSTANCE_UID => value_matches(VR::UI, &pattern, &instance.series_instance_uid),
+            _ => value_matches(VR::UI, &pattern, &instance.sop_instance_uid),
+        })
+    };
+    let mut entities: Vec<(&str, Vec<&IndexedInstance>)> = Vec::new();
+    for instance in index.instances() {
+        let selected = [
+            PATIENT_ID,
+            STUDY_INSTANCE_UID,
+            SERIES_INSTANCE_UID,
+            SOP_INSTANCE_UID,
+        ]
+        .into_iter()
+        .all(|tag| indexed_key(instance, tag).unwrap_or(true));
+        if !selected {
+            continue;
+        }
+        let entity = level.entity(instance);
+        match entities.iter_mut().find(|(key, _)| *key == entity) {
+            Some((_, instances)) => instances.push(instance),
+            None => entities.push((entity, vec![instance])),
+        }
+    }
+
+    let mut answers = Vec::new();
+    for (_, instances) in entities {
+        let mut series_seen = BTreeSet::new();
+        let headers = instances
+            .iter()
+            .filter(|instance| series_seen.insert(instance.series_instance_uid.as_str()))
+            .filter_map(|instance| {
+                OpenFileOptions::new()
+                    .read_until(PIXEL_DATA)
+                    .open_file(&instance.path)
+                    .ok()
+                    .map(|obj| obj.into_inner())
+            })
+            .collect();
+        let entity = Entity { instances, headers };
+        if entity.matches(identifier) {
+            answers.push(entity.response(identifier, level, retrieve_ae_title));
+        }
+    }
+    Ok(answers)
+}
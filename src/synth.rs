@@ -0,0 +1,423 @@
+//
+// synth.rs
+// Dicom-Tools-rs
+//
+// Generates synthetic DICOM instances and series (gradients, noise, phantoms) for tests and CI.
+//
+// Thales Matheus Mendonça Santos - November 2025
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use dicom::core::{DataElement, PrimitiveValue, Tag, VR};
+use dicom::dictionary_std::uids;
+use dicom::object::{DefaultDicomObject, FileMetaTableBuilder, InMemDicomObject};
+
+use crate::atomic_file;
+use crate::dicom_access::hashed_uid;
+
+const IMAGE_TYPE: Tag = Tag(0x0008, 0x0008);
+const SOP_CLASS_UID: Tag = Tag(0x0008, 0x0016);
+const SOP_INSTANCE_UID: Tag = Tag(0x0008, 0x0018);
+const STUDY_DATE: Tag = Tag(0x0008, 0x0020);
+const MODALITY: Tag = Tag(0x0008, 0x0060);
+const SERIES_DESCRIPTION: Tag = Tag(0x0008, 0x103E);
+const PATIENT_NAME: Tag = Tag(0x0010, 0x0010);
+const PATIENT_ID: Tag = Tag(0x0010, 0x0020);
+const SLICE_THICKNESS: Tag = Tag(0x0018, 0x0050);
+const STUDY_INSTANCE_UID: Tag = Tag(0x0020, 0x000D);
+const SERIES_INSTANCE_UID: Tag = Tag(0x0020, 0x000E);
+const SERIES_NUMBER: Tag = Tag(0x0020, 0x0011);
+const INSTANCE_NUMBER: Tag = Tag(0x0020, 0x0013);
+const IMAGE_POSITION_PATIENT: Tag = Tag(0x0020, 0x0032);
+const IMAGE_ORIENTATION_PATIENT: Tag = Tag(0x0020, 0x0037);
+const FRAME_OF_REFERENCE_UID: Tag = Tag(0x0020, 0x0052);
+const SAMPLES_PER_PIXEL: Tag = Tag(0x0028, 0x0002);
+const PHOTOMETRIC_INTERPRETATION: Tag = Tag(0x0028, 0x0004);
+const NUMBER_OF_FRAMES: Tag = Tag(0x0028, 0x0008);
+const ROWS: Tag = Tag(0x0028, 0x0010);
+const COLUMNS: Tag = Tag(0x0028, 0x0011);
+const PIXEL_SPACING: Tag = Tag(0x0028, 0x0030);
+const BITS_ALLOCATED: Tag = Tag(0x0028, 0x0100);
+const BITS_STORED: Tag = Tag(0x0028, 0x0101);
+const HIGH_BIT: Tag = Tag(0x0028, 0x0102);
+const PIXEL_REPRESENTATION: Tag = Tag(0x0028, 0x0103);
+const WINDOW_CENTER: Tag = Tag(0x0028, 0x1050);
+const WINDOW_WIDTH: Tag = Tag(0x0028, 0x1051);
+const RESCALE_INTERCEPT: Tag = Tag(0x0028, 0x1052);
+const RESCALE_SLOPE: Tag = Tag(0x0028, 0x1053);
+const PIXEL_DATA: Tag = Tag(0x7FE0, 0x0010);
+
+/// What the generated pixels look like.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Pattern {
+    /// Diagonal ramp over the full stored range, shifted a little on every frame.
+    Gradient,
+    /// Uniform pseudo-random values, reproducible from the seed.
+    Noise,
+    /// Shepp-Logan head phantom.
+    Phantom,
+}
+
+/// Shape of the synthetic data. Instances of one spec share study, series and frame of
+/// reference, and are stacked along z as a contiguous volume.
+#[derive(Clone, Debug)]
+pub struct SynthSpec {
+    pub modality: String,
+    pub rows: u16,
+    pub columns: u16,
+    /// Bits Stored (1..=16); 8 or fewer are allocated as bytes, more as 16-bit words.
+    pub bits_stored: u16,
+    pub frames: u32,
+    pub instances: u32,
+    pub pattern: Pattern,
+    /// Seeds both the noise and the UIDs, so the same spec always produces the same files.
+    pub seed: u64,
+}
+
+impl Default for SynthSpec {
+    fn default() -> Self {
+        Self {
+            modality: "OT".to_string(),
+            rows: 64,
+            columns: 64,
+            bits_stored: 16,
+            frames: 1,
+            instances: 1,
+            pattern: Pattern::Gradient,
+            seed: 0,
+        }
+    }
+}
+
+impl SynthSpec {
+    fn validate(&self) -> Result<()> {
+        if self.rows == 0 || self.columns == 0 {
+            bail!("Rows and columns must be positive");
+        }
+        if !(1..=16).contains(&self.bits_stored) {
+            bail!("Bits stored must be between 1 and 16");
+        }
+        if self.frames == 0 || self.instances == 0 {
+            bail!("Frames and instances must be positive");
+        }
+        Ok(())
+    }
+
+    fn bits_allocated(&self) -> u16 {
+        if self.bits_stored <= 8 {
+            8
+        } else {
+            16
+        }
+    }
+
+    fn max_value(&self) -> u32 {
+        (1u32 << self.bits_stored) - 1
+    }
+
+    fn uid(&self, role: &str) -> String {
+        hashed_uid(&format!("synth|{}|{}", self.seed, role))
+    }
+
+    /// Single-frame CT and MR get their own storage classes; everything else is
+    /// (multi-frame) Secondary Capture.
+    fn sop_class(&self) -> &'static str {
+        match (self.modality.as_str(), self.frames) {
+            ("CT", 1) => uids::CT_IMAGE_STORAGE,
+            ("MR", 1) => uids::MR_IMAGE_STORAGE,
+            (_, 1) => uids::SECONDARY_CAPTURE_IMAGE_STORAGE,
+            _ if self.bits_allocated() == 8 => {
+                uids::MULTI_FRAME_GRAYSCALE_BYTE_SECONDARY_CAPTURE_IMAGE_STORAGE
+            }
+            _ => uids::MULTI_FRAME_GRAYSCALE_WORD_SECONDARY_CAPTURE_IMAGE_STORAGE,
+        }
+    }
+}
+
+/// xorshift64*: tiny, deterministic, good enough for test noise.
+struct NoiseSource(u64);
+
+impl NoiseSource {
+    fn new(seed: u64) -> Self {
+        Self((seed ^ 0x9E37_79B9_7F4A_7C15) | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+}
+
+/// Shepp-Logan ellipses: intensity, semi-axes, centre, rotation (degrees).
+const SHEPP_LOGAN: [(f64, f64, f64, f64, f64, f64); 10] = [
+    (1.0, 0.69, 0.92, 0.0, 0.0, 0.0),
+    (-0.8, 0.6624, 0.874, 0.0, -0.0184, 0.0),
+    (-0.2, 0.11, 0.31, 0.22, 0.0, -18.0),
+    (-0.2, 0.16, 0.41, -0.22, 0.0, 18.0),
+    (0.1, 0.21, 0.25, 0.0, 0.35, 0.0),
+    (0.1, 0.046, 0.046, 0.0, 0.1, 0.0),
+    (0.1, 0.046, 0.046, 0.0, -0.1, 0.0),
+    (0.1, 0.046, 0.023, -0.08, -0.605, 0.0),
+    (0.1, 0.023, 0.023, 0.0, -0.606, 0.0),
+    (0.1, 0.023, 0.046, 0.06, -0.605, 0.0),
+];
+
+/// Phantom intensity in 0..=1 at normalized coordinates (-1..=1, y pointing up).
+fn shepp_logan(x: f64, y: f64) -> f64 {
+    let mut value = 0.0;
+    for (intensity, a, b, cx, cy, degrees) in SHEPP_LOGAN {
+        let (sin, cos) = degrees.to_radians().sin_cos();
+        let (dx, dy) = (x - cx, y - cy);
+        let u = dx * cos + dy * sin;
+        let v = -dx * sin + dy * cos;
+        if (u / a).powi(2) + (v / b).powi(2) <= 1.0 {
+            value += intensity;
+        }
+    }
+    value.clamp(0.0, 1.0)
+}
+
+/// Stored values of one frame, row-major.
+fn frame_values(spec: &SynthSpec, frame: u32, noise: &mut NoiseSource) -> Vec<u32> {
+    let (rows, columns) = (spec.rows as usize, spec.columns as usize);
+    let max = spec.max_value();
+    let mut values = Vec::with_capacity(rows * columns);
+    for y in 0..rows {
+        for x in 0..columns {
+            let value = match spec.pattern {
+                Pattern::Gradient => {
+                    let span = (rows + columns).saturating_sub(2).max(1) as u64;
+                    let position = (x + y) as u64 + frame as u64 * span / 16;
+                    ((position % (span + 1)) * max as u64 / span) as u32
+                }
+                Pattern::Noise => (noise.next() % (max as u64 + 1)) as u32,
+                Pattern::Phantom => {
+                    let nx = (2 * x + 1) as f64 / columns as f64 - 1.0;
+                    let ny = 1.0 - (2 * y + 1) as f64 / rows as f64;
+                    (shepp_logan(nx, ny) * max as f64).round() as u32
+                }
+            };
+            values.push(value);
+        }
+    }
+    values
+}
+
+fn put_str(obj: &mut InMemDicomObject, tag: Tag, vr: VR, value: &str) {
+    obj.put(DataElement::new(tag, vr, PrimitiveValue::from(value)));
+}
+
+fn put_us(obj: &mut InMemDicomObject, tag: Tag, value: u16) {
+    obj.put(DataElement::new(tag, VR::US, PrimitiveValue::from(value)));
+}
+
+/// Build instance `index` (0-based) of the series described by `spec`.
+pub fn synthesize(spec: &SynthSpec, index: u32) -> Result<DefaultDicomObject> {
+    spec.validate()?;
+    let sop_class = spec.sop_class();
+    let instance_uid = spec.uid(&format!("instance|{}", index));
+
+    let mut obj = InMemDicomObject::new_empty();
+    obj.put(DataElement::new(
+        IMAGE_TYPE,
+        VR::CS,
+        PrimitiveValue::Strs(["DERIVED".to_string(), "SECONDARY".to_string()][..].into()),
+    ));
+    put_str(&mut obj, SOP_CLASS_UID, VR::UI, sop_class);
+    put_str(&mut obj, SOP_INSTANCE_UID, VR::UI, &instance_uid);
+    put_str(&mut obj, STUDY_DATE, VR::DA, "20000101");
+    put_str(&mut obj, MODALITY, VR::CS, &spec.modality);
+    put_str(
+        &mut obj,
+        SERIES_DESCRIPTION,
+        VR::LO,
+        &format!("Synthetic {:?}", spec.pattern),
+    );
+    put_str(&mut obj, PATIENT_NAME, VR::PN, "SYNTHETIC^PATIENT");
+    put_str(
+        &mut obj,
+        PATIENT_ID,
+        VR::LO,
+        &format!("SYNTH{:04}", spec.seed),
+    );
+    put_str(&mut obj, SLICE_THICKNESS, VR::DS, "1");
+    put_str(&mut obj, STUDY_INSTANCE_UID, VR::UI, &spec.uid("study"));
+    put_str(&mut obj, SERIES_INSTANCE_UID, VR::UI, &spec.uid("series"));
+    put_str(&mut obj, SERIES_NUMBER, VR::IS, "1");
+    put_str(&mut obj, INSTANCE_NUMBER, VR::IS, &(index + 1).to_string());
+    put_str(
+        &mut obj,
+        IMAGE_POSITION_PATIENT,
+        VR::DS,
+        &format!("0\\0\\{}", index),
+    );
+    put_str(
+        &mut obj,
+        IMAGE_ORIENTATION_PATIENT,
+        VR::DS,
+        "1\\0\\0\\0\\1\\0",
+    );
+    put_str(
+        &mut obj,
+        FRAME_OF_REFERENCE_UID,
+        VR::UI,
+        &spec.uid("frame-of-reference"),
+    );
+
+    put_us(&mut obj, SAMPLES_PER_PIXEL, 1);
+    put_str(&mut obj, PHOTOMETRIC_INTERPRETATION, VR::CS, "MONOCHROME2");
+    if spec.frames > 1 {
+        put_str(&mut obj, NUMBER_OF_FRAMES, VR::IS, &spec.frames.to_string());
+    }
+    put_us(&mut obj, ROWS, spec.rows);
+    put_us(&mut obj, COLUMNS, spec.columns);
+    put_str(&mut obj, PIXEL_SPACING, VR::DS, "1\\1");
+    put_us(&mut obj, BITS_ALLOCATED, spec.bits_allocated());
+    put_us(&mut obj, BITS_STORED, spec.bits_stored);
+    put_us(&mut obj, HIGH_BIT, spec.bits_stored - 1);
+    put_us(&mut obj, PIXEL_REPRESENTATION, 0);
+    let max = spec.max_value() as f64;
+    put_str(
+        &mut obj,
+        WINDOW_CENTER,
+        VR::DS,
+        &format!("{}", (max / 2.0).round()),
+    );
+    put_str(&mut obj, WINDOW_WIDTH, VR::DS, &format!("{}", max + 1.0));
+    if sop_class == uids::CT_IMAGE_STORAGE {
+        put_str(&mut obj, RESCALE_INTERCEPT, VR::DS, "0");
+        put_str(&mut obj, RESCALE_SLOPE, VR::DS, "1");
+    }
+
+    let mut noise = NoiseSource::new(spec.seed.wrapping_add(index as u64));
+    let values: Vec<u32> = (0..spec.frames)
+        .flat_map(|frame| frame_values(spec, frame, &mut noise))
+        .collect();
+    if spec.bits_allocated() == 8 {
+        let mut bytes: Vec<u8> = values.into_iter().map(|v| v as u8).collect();
+        // Odd-length byte data is padded to an even length.
+        if bytes.len() % 2 == 1 {
+            bytes.push(0);
+        }
+        obj.put(DataElement::new(
+            PIXEL_DATA,
+            VR::OB,
+            PrimitiveValue::U8(bytes.into()),
+        ));
+    } else {
+        let words: Vec<u16> = values.into_iter().map(|v| v as u16).collect();
+        obj.put(DataElement::new(
+            PIXEL_DATA,
+            VR::OW,
+            PrimitiveValue::U16(words.into()),
+        ));
+    }
+
+    let meta = FileMetaTableBuilder::new()
+        .transfer_syntax(uids::EXPLICIT_VR_LITTLE_ENDIAN)
+        .media_storage_sop_class_uid(sop_class)
+        .media_storage_sop_instance_uid(&instance_uid);
+    obj.with_meta(meta)
+        .context("Failed to build file meta information")
+}
+
+/// CLI entry point: write the series as `IMG0001.dcm`, `IMG0002.dcm`, ... under `output`.
+pub fn write_series(spec: &SynthSpec, output: &Path) -> Result<Vec<PathBuf>> {
+    spec.validate()?;
+    std::fs::create_dir_all(output).with_context(|| format!("Failed to create {:?}", output))?;
+    let mut written = Vec::new();
+    for index in 0..spec.instances {
+        let path = output.join(format!("IMG{:04}.dcm", index + 1));
+        atomic_file::write_dicom(&path, &synthesize(spec, index)?)?;
+        written.push(path);
+    }
+    println!(
+        "Wrote {} synthetic {} instance(s) to {:?}",
+        written.len(),
+        spec.modality,
+        output
+    );
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dicom_access::ElementAccess;
+
+    #[test]
+    fn series_is_reproducible_and_consistent() {
+        let spec = SynthSpec {
+            modality: "CT".to_string(),
+            rows: 16,
+            columns: 8,
+            bits_stored: 12,
+            instances: 3,
+            pattern: Pattern::Noise,
+            seed: 7,
+            ..SynthSpec::default()
+        };
+        let first = synthesize(&spec, 0).unwrap();
+        let again = synthesize(&spec, 0).unwrap();
+        let second = synthesize(&spec, 1).unwrap();
+
+        assert_eq!(
+            first.element_str(SOP_CLASS_UID).as_deref(),
+            Some(uids::CT_IMAGE_STORAGE)
+        );
+        assert_eq!(
+            first.element_str(SOP_INSTANCE_UID),
+            again.element_str(SOP_INSTANCE_UID)
+        );
+        assert_ne!(
+            first.element_str(SOP_INSTANCE_UID),
+            second.element_str(SOP_INSTANCE_UID)
+        );
+        assert_eq!(
+            first.element_str(SERIES_INSTANCE_UID),
+            second.element_str(SERIES_INSTANCE_UID)
+        );
+
+        let words = first
+            .element(PIXEL_DATA)
+            .unwrap()
+            .to_multi_int::<u16>()
+            .unwrap();
+        assert_eq!(words.len(), 16 * 8);
+        assert!(words.iter().all(|&w| w <= 4095));
+        assert_eq!(
+            words,
+            again
+                .element(PIXEL_DATA)
+                .unwrap()
+                .to_multi_int::<u16>()
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn multiframe_byte_phantom_uses_secondary_capture() {
+        let spec = SynthSpec {
+            rows: 32,
+            columns: 32,
+            bits_stored: 8,
+            frames: 4,
+            pattern: Pattern::Phantom,
+            ..SynthSpec::default()
+        };
+        let obj = synthesize(&spec, 0).unwrap();
+        assert_eq!(
+            obj.element_str(SOP_CLASS_UID).as_deref(),
+            Some(uids::MULTI_FRAME_GRAYSCALE_BYTE_SECONDARY_CAPTURE_IMAGE_STORAGE)
+        );
+        assert_eq!(obj.element_u32(NUMBER_OF_FRAMES), Some(4));
+        let bytes = obj.element(PIXEL_DATA).unwrap().to_bytes().unwrap();
+        assert_eq!(bytes.len(), 32 * 32 * 4);
+        // Skull rim is bright, the background outside the head is empty.
+        assert_eq!(bytes[0], 0);
+        assert!(bytes.contains(&255));
+    }
+}
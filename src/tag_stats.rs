@@ -0,0 +1,393 @@
+//
+// tag_stats.rs
+// Dicom-Tools-rs
+//
+// Summarizes, per tag across a directory of DICOM files, presence, distinct values, lengths, multiplicity and VR drift.
+//
+// Thales Matheus Mendonça Santos - November 2025
+
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::fmt::Write as _;
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use dicom::core::dictionary::DataDictionary;
+use dicom::core::value::Value;
+use dicom::core::{Tag, VR};
+use dicom::dictionary_std::StandardDataDictionary;
+use dicom::object::{InMemDicomObject, OpenFileOptions};
+use rayon::prelude::*;
+use serde::Serialize;
+use walkdir::WalkDir;
+
+use crate::atomic_file;
+
+const PIXEL_DATA: Tag = Tag(0x7FE0, 0x0010);
+/// Distinct values tracked per tag; beyond this the count is reported as a lower bound.
+const MAX_DISTINCT: usize = 1000;
+
+/// Aggregated statistics for one tag across the scanned files.
+#[derive(Debug, Clone, Serialize)]
+pub struct TagStats {
+    pub tag: String,
+    pub keyword: String,
+    /// VRs seen for this tag and how many files used each.
+    pub vrs: BTreeMap<String, usize>,
+    pub present: usize,
+    pub presence_rate: f64,
+    pub distinct_values: usize,
+    /// True when `distinct_values` hit the tracking cap.
+    pub distinct_capped: bool,
+    pub min_length: usize,
+    pub max_length: usize,
+    pub min_vm: usize,
+    pub max_vm: usize,
+    pub example: Option<String>,
+}
+
+impl TagStats {
+    pub fn vr_inconsistent(&self) -> bool {
+        self.vrs.len() > 1
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TagStatsReport {
+    pub files: usize,
+    pub skipped: usize,
+    pub tags: Vec<TagStats>,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Accumulator {
+    present: usize,
+    vrs: BTreeMap<VR, usize>,
+    values: HashSet<String>,
+    capped: bool,
+    min_length: usize,
+    max_length: usize,
+    min_vm: usize,
+    max_vm: usize,
+    example: Option<String>,
+}
+
+impl Accumulator {
+    fn observe(&mut self, vr: VR, value: Option<String>, length: usize, vm: usize) {
+        if self.present == 0 {
+            self.min_length = length;
+            self.min_vm = vm;
+        }
+        self.present += 1;
+        *self.vrs.entry(vr).or_default() += 1;
+        self.min_length = self.min_length.min(length);
+        self.max_length = self.max_length.max(length);
+        self.min_vm = self.min_vm.min(vm);
+        self.max_vm = self.max_vm.max(vm);
+        if let Some(value) = value {
+            if self.example.is_none() {
+                self.example = Some(value.clone());
+            }
+            self.insert_value(value);
+        }
+    }
+
+    fn insert_value(&mut self, value: String) {
+        if self.values.len() < MAX_DISTINCT {
+            self.values.insert(value);
+        } else if !self.values.contains(&value) {
+            self.capped = true;
+        }
+    }
+
+    fn merge(mut self, other: Accumulator) -> Accumulator {
+        if other.present == 0 {
+            return self;
+        }
+        if self.present == 0 {
+            return other;
+        }
+        self.present += other.present;
+        for (vr, count) in other.vrs {
+            *self.vrs.entry(vr).or_default() += count;
+        }
+        self.min_length = self.min_length.min(other.min_length);
+        self.max_length = self.max_length.max(other.max_length);
+        self.min_vm = self.min_vm.min(other.min_vm);
+        self.max_vm = self.max_vm.max(other.max_vm);
+        self.capped |= other.capped;
+        self.example = self.example.or(other.example);
+        for value in other.values {
+            self.insert_value(value);
+        }
+        self
+    }
+}
+
+type Accumulators = BTreeMap<Tag, Accumulator>;
+
+/// Record every top-level element of one dataset.
+fn observe_object(acc: &mut Accumulators, obj: &InMemDicomObject) {
+    for elem in obj.iter() {
+        let header = elem.header();
+        let (value, length, vm) = match elem.value() {
+            Value::Primitive(p) => {
+                if is_binary(header.vr) {
+                    (None, p.to_bytes().len(), p.multiplicity() as usize)
+                } else {
+                    let text = p.to_str().trim_end_matches(['\0', ' ']).to_string();
+                    // Count VM from the backslash-joined form so raw and parsed values agree.
+                    let vm = if text.is_empty() {
+                        0
+                    } else {
+                        text.split('\\').count()
+                    };
+                    let length = text.len();
+                    (Some(text), length, vm)
+                }
+            }
+            Value::Sequence(seq) => (None, 0, seq.items().len()),
+            Value::PixelSequence(p) => (None, 0, p.fragments().len()),
+        };
+        acc.entry(header.tag)
+            .or_default()
+            .observe(header.vr, value, length, vm);
+    }
+}
+
+fn is_binary(vr: VR) -> bool {
+    matches!(
+        vr,
+        VR::OB | VR::OW | VR::OF | VR::OD | VR::OL | VR::OV | VR::UN
+    )
+}
+
+fn merge_accumulators(mut a: Accumulators, b: Accumulators) -> Accumulators {
+    for (tag, acc) in b {
+        let merged = a.remove(&tag).unwrap_or_default().merge(acc);
+        a.insert(tag, merged);
+    }
+    a
+}
+
+/// Build the report from datasets that have already been loaded.
+pub fn summarize<'a>(objects: impl IntoIterator<Item = &'a InMemDicomObject>) -> TagStatsReport {
+    let mut acc = Accumulators::new();
+    let mut files = 0;
+    for obj in objects {
+        observe_object(&mut acc, obj);
+        files += 1;
+    }
+    finish(acc, files, 0)
+}
+
+/// Scan every file under `dir` (headers only) and aggregate per-tag statistics.
+pub fn scan_directory(dir: &Path) -> Result<TagStatsReport> {
+    if !dir.is_dir() {
+        bail!("{:?} is not a directory", dir);
+    }
+    let paths: Vec<_> = WalkDir::new(dir)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file())
+        .map(|e| e.into_path())
+        .collect();
+
+    let (acc, files, skipped) = paths
+        .par_iter()
+        .fold(
+            || (Accumulators::new(), 0usize, 0usize),
+            |(mut acc, files, skipped), path| {
+                // Pixel data is never inspected, so stop parsing before it.
+                match OpenFileOptions::new()
+                    .read_until(PIXEL_DATA)
+                    .open_file(path)
+                {
+                    Ok(obj) => {
+                        observe_object(&mut acc, &obj);
+                        (acc, files + 1, skipped)
+                    }
+                    Err(_) => (acc, files, skipped + 1),
+                }
+            },
+        )
+        .reduce(
+            || (Accumulators::new(), 0, 0),
+            |(a, fa, sa), (b, fb, sb)| (merge_accumulators(a, b), fa + fb, sa + sb),
+        );
+
+    Ok(finish(acc, files, skipped))
+}
+
+fn finish(acc: Accumulators, files: usize, skipped: usize) -> TagStatsReport {
+    let tags = acc
+        .into_iter()
+        .map(|(tag, acc)| TagStats {
+            tag: format!("({:04X},{:04X})", tag.group(), tag.element()),
+            keyword: keyword(tag),
+            vrs: acc
+                .vrs
+                .into_iter()
+                .map(|(vr, count)| (vr.to_string().to_string(), count))
+                .collect(),
+            present: acc.present,
+            presence_rate: if files > 0 {
+                acc.present as f64 / files as f64
+            } else {
+                0.0
+            },
+            distinct_values: acc.values.len(),
+            distinct_capped: acc.capped,
+            min_length: acc.min_length,
+            max_length: acc.max_length,
+            min_vm: acc.min_vm,
+            max_vm: acc.max_vm,
+            example: acc.example,
+        })
+        .collect();
+    TagStatsReport {
+        files,
+        skipped,
+        tags,
+    }
+}
+
+fn keyword(tag: Tag) -> String {
+    if tag.group() % 2 == 1 {
+        return "Private".to_string();
+    }
+    StandardDataDictionary
+        .by_tag(tag)
+        .map(|e| e.alias.to_string())
+        .unwrap_or_else(|| "UnknownTag".to_string())
+}
+
+/// Render the report as CSV. Values are quoted whenever they contain a delimiter, quote or
+/// line break; multi-valued examples keep DICOM's backslash separator inside a single field.
+pub fn to_csv(report: &TagStatsReport) -> String {
+    let mut out = String::from(
+        "tag,keyword,vrs,present,presence_rate,distinct_values,min_length,max_length,min_vm,max_vm,vr_inconsistent,example\n",
+    );
+    for stats in &report.tags {
+        let vrs: BTreeSet<&str> = stats.vrs.keys().map(String::as_str).collect();
+        let distinct = if stats.distinct_capped {
+            format!(">={}", stats.distinct_values)
+        } else {
+            stats.distinct_values.to_string()
+        };
+        let fields = [
+            stats.tag.clone(),
+            stats.keyword.clone(),
+            vrs.into_iter().collect::<Vec<_>>().join("|"),
+            stats.present.to_string(),
+            format!("{:.4}", stats.presence_rate),
+            distinct,
+            stats.min_length.to_string(),
+            stats.max_length.to_string(),
+            stats.min_vm.to_string(),
+            stats.max_vm.to_string(),
+            stats.vr_inconsistent().to_string(),
+            stats.example.clone().unwrap_or_default(),
+        ];
+        let line: Vec<String> = fields.iter().map(|f| csv_field(f)).collect();
+        let _ = writeln!(out, "{}", line.join(","));
+    }
+    out
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// CLI entry point: print a table, or write CSV when `csv` is given.
+pub fn print_tag_stats(dir: &Path, csv: Option<&Path>) -> Result<()> {
+    let report = scan_directory(dir)?;
+    println!(
+        "Tag statistics for {:?}: {} file(s), {} skipped (not DICOM)",
+        dir, report.files, report.skipped
+    );
+
+    if let Some(path) = csv {
+        atomic_file::write(path, to_csv(&report))
+            .with_context(|| format!("Failed to write CSV to {:?}", path))?;
+        println!("CSV saved to {:?}", path);
+        return Ok(());
+    }
+
+    println!(
+        "{:<12} {:<36} {:>7} {:>9} {:>9} {:>7}  VR",
+        "Tag", "Keyword", "Present", "Distinct", "Length", "VM"
+    );
+    for stats in &report.tags {
+        let vrs: Vec<&str> = stats.vrs.keys().map(String::as_str).collect();
+        println!(
+            "{:<12} {:<36} {:>6.1}% {:>8}{} {:>9} {:>7}  {}{}",
+            stats.tag,
+            stats.keyword,
+            stats.presence_rate * 100.0,
+            stats.distinct_values,
+            if stats.distinct_capped { "+" } else { " " },
+            format!("{}-{}", stats.min_length, stats.max_length),
+            format!("{}-{}", stats.min_vm, stats.max_vm),
+            vrs.join("|"),
+            if stats.vr_inconsistent() {
+                "  [WARN] inconsistent VR"
+            } else {
+                ""
+            }
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dicom::core::{DataElement, PrimitiveValue};
+
+    fn object(vr: VR, name: &str, spacing: Option<&str>) -> InMemDicomObject {
+        let mut obj = InMemDicomObject::new_empty();
+        obj.put(DataElement::new(
+            Tag(0x0010, 0x0010),
+            vr,
+            PrimitiveValue::from(name),
+        ));
+        if let Some(spacing) = spacing {
+            obj.put(DataElement::new(
+                Tag(0x0028, 0x0030),
+                VR::DS,
+                PrimitiveValue::from(spacing),
+            ));
+        }
+        obj
+    }
+
+    #[test]
+    fn aggregates_presence_lengths_and_vr_drift() {
+        let objects = [
+            object(VR::PN, "Doe^John", Some("0.5\\0.5")),
+            object(VR::PN, "Doe^John", None),
+            object(VR::LO, "Roe, Jane \"JR\"", None),
+        ];
+        let report = summarize(objects.iter());
+        assert_eq!(report.files, 3);
+
+        let name = &report.tags[0];
+        assert_eq!(name.keyword, "PatientName");
+        assert_eq!(name.present, 3);
+        assert_eq!(name.distinct_values, 2);
+        assert_eq!((name.min_length, name.max_length), (8, 14));
+        assert!(name.vr_inconsistent());
+
+        let spacing = &report.tags[1];
+        assert!((spacing.presence_rate - 1.0 / 3.0).abs() < 1e-9);
+        assert_eq!((spacing.min_vm, spacing.max_vm), (2, 2));
+
+        let csv = to_csv(&report);
+        assert!(csv.contains("\"(0028,0030)\",PixelSpacing,DS,1,0.3333,1,7,7,2,2,false,0.5\\0.5"));
+        assert_eq!(csv_field("Roe, Jane \"JR\""), "\"Roe, Jane \"\"JR\"\"\"");
+    }
+}
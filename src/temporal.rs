@@ -0,0 +1,257 @@
+//
+// temporal.rs
+// Dicom-Tools-rs
+//
+// Parses DA, TM and DT values into chrono types, keeping how much of the value was given
+// and any UTC offset, so consumers do not need their own DICOM date parsing.
+//
+// Thales Matheus Mendonça Santos - November 2025
+
+use chrono::{DateTime, FixedOffset, NaiveDate, NaiveDateTime, NaiveTime, TimeZone};
+use serde::{Deserialize, Serialize};
+
+/// Finest component present in a parsed value; missing components default to their first
+/// value (January, the 1st, midnight...).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Precision {
+    Year,
+    Month,
+    Day,
+    Hour,
+    Minute,
+    Second,
+    /// Seconds with a fractional part.
+    Fraction,
+}
+
+/// A DA value (`YYYYMMDD`, or the ACR-NEMA `YYYY.MM.DD`; `YYYY` and `YYYYMM` are accepted).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DicomDate {
+    pub value: NaiveDate,
+    pub precision: Precision,
+}
+
+/// A TM value (`HH[MM[SS[.FFFFFF]]]`, or the ACR-NEMA `HH:MM:SS`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DicomTime {
+    pub value: NaiveTime,
+    pub precision: Precision,
+}
+
+/// A DT value (`YYYY[MM[DD[HH[MM[SS[.FFFFFF]]]]]][&ZZXX]`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DicomDateTime {
+    /// Local date and time as written.
+    pub value: NaiveDateTime,
+    /// Offset from UTC in minutes, when the value carries one.
+    pub utc_offset_minutes: Option<i32>,
+    pub precision: Precision,
+}
+
+impl DicomDateTime {
+    /// The instant, when the value states its UTC offset.
+    pub fn with_offset(&self) -> Option<DateTime<FixedOffset>> {
+        let offset = FixedOffset::east_opt(self.utc_offset_minutes? * 60)?;
+        offset.from_local_datetime(&self.value).single()
+    }
+}
+
+/// A parsed DA, TM or DT value, tagged with its VR when serialized.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "vr")]
+pub enum Temporal {
+    DA(DicomDate),
+    TM(DicomTime),
+    DT(DicomDateTime),
+}
+
+/// First value of a possibly multi-valued string, without padding.
+fn first_value(text: &str) -> &str {
+    text.split('\\')
+        .next()
+        .unwrap_or_default()
+        .trim_matches(|c: char| c == '\0' || c.is_whitespace())
+}
+
+/// Leading ASCII digits of `text` as a number, when there are exactly `len` of them.
+fn number(text: &str, len: usize) -> Option<u32> {
+    let part = text.get(..len)?;
+    part.bytes()
+        .all(|b| b.is_ascii_digit())
+        .then(|| part.parse().ok())?
+}
+
+pub fn parse_date(text: &str) -> Option<DicomDate> {
+    let text = first_value(text);
+    // ACR-NEMA wrote dates with dots; the fields are the same.
+    let compact: String = if text.len() == 10 && text.as_bytes()[4] == b'.' {
+        text.replace('.', "")
+    } else {
+        text.to_string()
+    };
+    let (date, precision, rest) = date_prefix(&compact)?;
+    rest.is_empty().then_some(DicomDate {
+        value: date,
+        precision,
+    })
+}
+
+/// Year, month and day at the start of `text`, and what follows them.
+fn date_prefix(text: &str) -> Option<(NaiveDate, Precision, &str)> {
+    let year = number(text, 4)? as i32;
+    let (month, precision) = match number(&text[4..], 2) {
+        Some(month) => (month, Precision::Month),
+        None => (1, Precision::Year),
+    };
+    let (day, precision) = match (precision, text.get(6..).and_then(|t| number(t, 2))) {
+        (Precision::Month, Some(day)) => (day, Precision::Day),
+        _ => (1, precision),
+    };
+    let consumed = match precision {
+        Precision::Year => 4,
+        Precision::Month => 6,
+        _ => 8,
+    };
+    let date = NaiveDate::from_ymd_opt(year, month, day)?;
+    Some((date, precision, &text[consumed..]))
+}
+
+pub fn parse_time(text: &str) -> Option<DicomTime> {
+    let text = first_value(text);
+    // ACR-NEMA wrote times with colons; the fields are the same.
+    let compact = text.replace(':', "");
+    let (time, precision, rest) = time_prefix(&compact)?;
+    rest.is_empty().then_some(DicomTime {
+        value: time,
+        precision,
+    })
+}
+
+/// Hours, minutes, seconds and fraction at the start of `text`, and what follows them.
+fn time_prefix(text: &str) -> Option<(NaiveTime, Precision, &str)> {
+    let hour = number(text, 2)?;
+    let mut precision = Precision::Hour;
+    let mut consumed = 2;
+    let (mut minute, mut second, mut micros) = (0, 0, 0);
+    if let Some(value) = number(&text[2..], 2) {
+        minute = value;
+        precision = Precision::Minute;
+        consumed = 4;
+        if let Some(value) = text.get(4..).and_then(|t| number(t, 2)) {
+            second = value;
+            precision = Precision::Second;
+            consumed = 6;
+            if let Some(fraction) = text.get(6..).and_then(|t| t.strip_prefix('.')) {
+                let digits = fraction.bytes().take_while(u8::is_ascii_digit).count();
+                if !(1..=6).contains(&digits) {
+                    return None;
+                }
+                micros = fraction[..digits].parse::<u32>().ok()? * 10u32.pow(6 - digits as u32);
+                precision = Precision::Fraction;
+                consumed = 7 + digits;
+            }
+        }
+    }
+    // A leap second (60) is allowed by DICOM; chrono expresses it in the fraction.
+    let time = if second == 60 {
+        NaiveTime::from_hms_micro_opt(hour, minute, 59, 1_000_000 + micros)?
+    } else {
+        NaiveTime::from_hms_micro_opt(hour, minute, second, micros)?
+    };
+    Some((time, precision, &text[consumed..]))
+}
+
+pub fn parse_datetime(text: &str) -> Option<DicomDateTime> {
+    let text = first_value(text);
+    // The offset suffix starts at the first sign; dates and times have none.
+    let (local, offset) = match text.find(['+', '-']) {
+        Some(at) => (&text[..at], Some(parse_offset(&text[at..])?)),
+        None => (text, None),
+    };
+    let (date, date_precision, rest) = date_prefix(local)?;
+    let (time, precision) = if date_precision == Precision::Day && !rest.is_empty() {
+        let (time, precision, rest) = time_prefix(rest)?;
+        if !rest.is_empty() {
+            return None;
+        }
+        (time, precision)
+    } else if rest.is_empty() {
+        (NaiveTime::MIN, date_precision)
+    } else {
+        return None;
+    };
+    Some(DicomDateTime {
+        value: date.and_time(time),
+        utc_offset_minutes: offset,
+        precision,
+    })
+}
+
+/// `&ZZXX` as minutes east of UTC.
+fn parse_offset(text: &str) -> Option<i32> {
+    let sign = match text.as_bytes().first()? {
+        b'+' => 1,
+        b'-' => -1,
+        _ => return None,
+    };
+    let digits = &text[1..];
+    if digits.len() != 4 {
+        return None;
+    }
+    let hours = number(digits, 2)? as i32;
+    let minutes = number(&digits[2..], 2)? as i32;
+    (hours <= 14 && minutes < 60).then_some(sign * (hours * 60 + minutes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dates_and_times_keep_their_precision() {
+        let date = parse_date("20240229 ").unwrap();
+        assert_eq!(date.value, NaiveDate::from_ymd_opt(2024, 2, 29).unwrap());
+        assert_eq!(date.precision, Precision::Day);
+        assert_eq!(parse_date("1998.07.03").unwrap().precision, Precision::Day);
+        assert_eq!(parse_date("199807").unwrap().precision, Precision::Month);
+        assert!(parse_date("20230229").is_none());
+        assert!(parse_date("2023-01-01").is_none());
+
+        let time = parse_time("070907.0705").unwrap();
+        assert_eq!(
+            time.value,
+            NaiveTime::from_hms_micro_opt(7, 9, 7, 70_500).unwrap()
+        );
+        assert_eq!(time.precision, Precision::Fraction);
+        assert_eq!(parse_time("1010").unwrap().precision, Precision::Minute);
+        assert_eq!(
+            parse_time("07:09:07").unwrap().value,
+            NaiveTime::from_hms_opt(7, 9, 7).unwrap()
+        );
+        assert!(parse_time("25").is_none());
+        assert!(parse_time("101010.1234567").is_none());
+    }
+
+    #[test]
+    fn datetimes_carry_their_utc_offset() {
+        let dt = parse_datetime("20240102101530.25-0330").unwrap();
+        assert_eq!(dt.precision, Precision::Fraction);
+        assert_eq!(dt.utc_offset_minutes, Some(-210));
+        assert_eq!(
+            dt.with_offset().unwrap().to_rfc3339(),
+            "2024-01-02T10:15:30.250-03:30"
+        );
+
+        let partial = parse_datetime("202401").unwrap();
+        assert_eq!(partial.precision, Precision::Month);
+        assert_eq!(partial.utc_offset_minutes, None);
+        assert!(partial.with_offset().is_none());
+        assert!(parse_datetime("20240102+01").is_none());
+
+        let json = serde_json::to_value(Temporal::DA(parse_date("20240102").unwrap())).unwrap();
+        assert_eq!(json["vr"], "DA");
+        assert_eq!(json["value"], "2024-01-02");
+        assert_eq!(json["precision"], "day");
+    }
+}
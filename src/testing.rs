@@ -0,0 +1,255 @@
+//
+// testing.rs
+// Dicom-Tools-rs
+//
+// Fixture objects for tests: a builder over InMemDicomObject with patient, study, series,
+// image-pixel and multi-frame helpers, so a test states the attributes it cares about instead of
+// spelling out every put(). `tags` re-exports the standard dictionary's keyword constants.
+//
+// Thales Matheus Mendonça Santos - November 2025
+
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use dicom::core::{DataElement, PrimitiveValue, Tag, VR};
+use dicom::dictionary_std::StandardDataDictionary;
+use dicom::object::mem::InMemElement;
+use dicom::object::{DefaultDicomObject, FileMetaTableBuilder, InMemDicomObject};
+use dicom::transfer_syntax::entries::EXPLICIT_VR_LITTLE_ENDIAN;
+
+pub use dicom::dictionary_std::tags;
+
+/// Secondary Capture Image Storage, the SOP class of `sample_image`.
+pub const SECONDARY_CAPTURE: &str = "1.2.840.10008.5.1.4.1.1.7";
+
+/// Builds a data set attribute by attribute; every setter replaces what was there.
+#[derive(Debug, Clone)]
+pub struct ObjectBuilder {
+    obj: InMemDicomObject,
+}
+
+impl Default for ObjectBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ObjectBuilder {
+    pub fn new() -> Self {
+        Self {
+            obj: InMemDicomObject::new_empty_with_dict(StandardDataDictionary),
+        }
+    }
+
+    /// Any element, e.g. a sequence or a value of a non-string VR.
+    pub fn element(mut self, element: InMemElement) -> Self {
+        self.obj.put(element);
+        self
+    }
+
+    /// An attribute with a textual value (numbers included for IS and DS).
+    pub fn text(self, tag: Tag, vr: VR, value: &str) -> Self {
+        self.element(DataElement::new(tag, vr, PrimitiveValue::from(value)))
+    }
+
+    fn us(self, tag: Tag, value: u16) -> Self {
+        self.element(DataElement::new(tag, VR::US, PrimitiveValue::from(value)))
+    }
+
+    /// SOP Class and SOP Instance UID; the file meta group is built from them.
+    pub fn sop(self, class_uid: &str, instance_uid: &str) -> Self {
+        self.text(tags::SOP_CLASS_UID, VR::UI, class_uid).text(
+            tags::SOP_INSTANCE_UID,
+            VR::UI,
+            instance_uid,
+        )
+    }
+
+    pub fn patient(self, name: &str, id: &str) -> Self {
+        self.text(tags::PATIENT_NAME, VR::PN, name)
+            .text(tags::PATIENT_ID, VR::LO, id)
+    }
+
+    /// Study Instance UID and Study Date (`YYYYMMDD`).
+    pub fn study(self, instance_uid: &str, date: &str) -> Self {
+        self.text(tags::STUDY_INSTANCE_UID, VR::UI, instance_uid)
+            .text(tags::STUDY_DATE, VR::DA, date)
+    }
+
+    pub fn series(self, instance_uid: &str, modality: &str) -> Self {
+        self.text(tags::SERIES_INSTANCE_UID, VR::UI, instance_uid)
+            .text(tags::MODALITY, VR::CS, modality)
+    }
+
+    /// Image Pixel module for unsigned samples using every allocated bit; RGB and YBR
+    /// interpretations get three interleaved samples per pixel.
+    pub fn image_pixel(
+        self,
+        rows: u16,
+        columns: u16,
+        bits_allocated: u16,
+        photometric: &str,
+    ) -> Self {
+        let color = photometric == "RGB" || photometric.starts_with("YBR");
+        let builder = self
+            .us(tags::ROWS, rows)
+            .us(tags::COLUMNS, columns)
+            .us(tags::SAMPLES_PER_PIXEL, if color { 3 } else { 1 })
+            .us(tags::BITS_ALLOCATED, bits_allocated)
+            .us(tags::BITS_STORED, bits_allocated)
+            .us(tags::HIGH_BIT, bits_allocated.saturating_sub(1))
+            .us(tags::PIXEL_REPRESENTATION, 0)
+            .text(tags::PHOTOMETRIC_INTERPRETATION, VR::CS, photometric);
+        if color {
+            builder.us(tags::PLANAR_CONFIGURATION, 0)
+        } else {
+            builder
+        }
+    }
+
+    /// Two's complement samples (Pixel Representation 1).
+    pub fn signed(self) -> Self {
+        self.us(tags::PIXEL_REPRESENTATION, 1)
+    }
+
+    pub fn rescale(self, slope: f64, intercept: f64) -> Self {
+        self.text(tags::RESCALE_SLOPE, VR::DS, &slope.to_string())
+            .text(tags::RESCALE_INTERCEPT, VR::DS, &intercept.to_string())
+    }
+
+    pub fn window(self, center: f64, width: f64) -> Self {
+        self.text(tags::WINDOW_CENTER, VR::DS, &center.to_string())
+            .text(tags::WINDOW_WIDTH, VR::DS, &width.to_string())
+    }
+
+    /// Number of Frames; the pixel data holds the frames back to back.
+    pub fn multiframe(self, frames: u32) -> Self {
+        self.text(tags::NUMBER_OF_FRAMES, VR::IS, &frames.to_string())
+    }
+
+    /// Native 8-bit Pixel Data (OB).
+    pub fn pixel_data(self, bytes: Vec<u8>) -> Self {
+        self.element(DataElement::new(
+            tags::PIXEL_DATA,
+            VR::OB,
+            PrimitiveValue::from(bytes),
+        ))
+    }
+
+    /// Native 16-bit Pixel Data (OW); signed samples are passed as their bit patterns.
+    pub fn pixel_data_u16(self, samples: &[u16]) -> Self {
+        self.element(DataElement::new(
+            tags::PIXEL_DATA,
+            VR::OW,
+            PrimitiveValue::U16(samples.iter().copied().collect()),
+        ))
+    }
+
+    pub fn build(self) -> InMemDicomObject {
+        self.obj
+    }
+
+    /// The data set with a file meta group for `transfer_syntax`; needs `sop` first.
+    pub fn into_file(self, transfer_syntax: &str) -> Result<DefaultDicomObject> {
+        let uid = |tag| {
+            self.obj
+                .element(tag)
+                .ok()
+                .and_then(|e| e.to_str().ok())
+                .map(|s| s.trim_end_matches(['\0', ' ']).to_string())
+                .context("A file needs the SOP Class and SOP Instance UID; call sop() first")
+        };
+        let meta = FileMetaTableBuilder::new()
+            .transfer_syntax(transfer_syntax)
+            .media_storage_sop_class_uid(uid(tags::SOP_CLASS_UID)?)
+            .media_storage_sop_instance_uid(uid(tags::SOP_INSTANCE_UID)?);
+        self.obj
+            .with_meta(meta)
+            .context("Failed to build the file meta group")
+    }
+
+    /// Write the object to `path` in Explicit VR Little Endian.
+    pub fn write(self, path: &Path) -> Result<()> {
+        self.into_file(EXPLICIT_VR_LITTLE_ENDIAN.uid())?
+            .write_to_file(path)
+            .with_context(|| format!("Failed to write {:?}", path))
+    }
+}
+
+/// A 2x2 8-bit MONOCHROME2 Secondary Capture image with pixels 0, 64, 128, 255, rescale
+/// 2 / -1024 and a 50/150 window; extend or override it with the builder.
+pub fn sample_image() -> ObjectBuilder {
+    ObjectBuilder::new()
+        .sop(SECONDARY_CAPTURE, "1.2.826.0.1.3680043.2.1125.1")
+        .patient("Test^Patient", "PAT123")
+        .text(tags::STUDY_DATE, VR::DA, "20240101")
+        .text(tags::MODALITY, VR::CS, "OT")
+        .image_pixel(2, 2, 8, "MONOCHROME2")
+        .multiframe(1)
+        .rescale(2.0, -1024.0)
+        .window(50.0, 150.0)
+        .pixel_data(vec![0, 64, 128, 255])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dicom::object::open_file;
+
+    #[test]
+    fn a_multiframe_fixture_round_trips_through_a_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("ct.dcm");
+        let samples: Vec<u16> = (0..8).map(|v| (v as i16 - 4) as u16).collect();
+        ObjectBuilder::new()
+            .sop("1.2.840.10008.5.1.4.1.1.2", "1.2.3.4.5")
+            .patient("Doe^Jane", "P1")
+            .study("1.2.3.4", "20250101")
+            .series("1.2.3.4.1", "CT")
+            .image_pixel(2, 2, 16, "MONOCHROME2")
+            .signed()
+            .multiframe(2)
+            .pixel_data_u16(&samples)
+            .write(&path)
+            .unwrap();
+
+        let obj = open_file(&path).unwrap();
+        assert_eq!(obj.meta().media_storage_sop_instance_uid(), "1.2.3.4.5");
+        assert_eq!(
+            obj.element(tags::PIXEL_REPRESENTATION)
+                .unwrap()
+                .to_int::<u16>()
+                .unwrap(),
+            1
+        );
+        let stats = crate::stats::pixel_statistics_for_file(&path).unwrap();
+        assert_eq!(stats.shape, vec![2, 2, 2, 1]);
+        assert_eq!(stats.total_pixels, 8);
+
+        assert!(ObjectBuilder::new()
+            .patient("A", "B")
+            .into_file("1.2.840.10008.1.2.1")
+            .is_err());
+        let sample = sample_image().build();
+        assert_eq!(
+            sample
+                .element(tags::SAMPLES_PER_PIXEL)
+                .unwrap()
+                .to_int::<u16>()
+                .unwrap(),
+            1
+        );
+        // A zero Bits Allocated is the caller's mistake to make, not a panic.
+        let empty = ObjectBuilder::new()
+            .image_pixel(1, 1, 0, "MONOCHROME2")
+            .build();
+        assert_eq!(
+            empty
+                .element(tags::HIGH_BIT)
+                .unwrap()
+                .to_int::<u16>()
+                .unwrap(),
+            0
+        );
+    }
+}
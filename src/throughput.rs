@@ -0,0 +1,195 @@
+//
+// throughput.rs
+// Dicom-Tools-rs
+//
+// Wall-clock throughput of the anonymization and transcode pipelines over a directory of
+// real files, reported as files/sec and MB/sec so IO and pixel path regressions show up.
+//
+// Thales Matheus Mendonça Santos - November 2025
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Context, Result};
+use walkdir::WalkDir;
+
+use crate::anonymize::{self, AnonymizeOptions};
+use crate::derivation::DerivationPolicy;
+use crate::transcode::{self, UncompressedTransferSyntax};
+
+/// Pipeline timed by [`run`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Pipeline {
+    /// Header anonymization with the default options (pixel data spliced when possible).
+    Anonymize,
+    /// Decode and rewrite as explicit VR little endian.
+    Transcode,
+}
+
+impl fmt::Display for Pipeline {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Pipeline::Anonymize => "anonymize",
+            Pipeline::Transcode => "transcode",
+        })
+    }
+}
+
+/// Outcome of timing one pipeline over a set of files.
+#[derive(Debug, Clone)]
+pub struct ThroughputReport {
+    pub pipeline: Pipeline,
+    /// Files processed successfully, summed over all iterations.
+    pub files: usize,
+    pub failed: usize,
+    /// Input bytes of the successfully processed files.
+    pub bytes: u64,
+    pub elapsed: Duration,
+}
+
+impl ThroughputReport {
+    pub fn files_per_sec(&self) -> f64 {
+        per_sec(self.files as f64, self.elapsed)
+    }
+
+    pub fn mb_per_sec(&self) -> f64 {
+        per_sec(self.bytes as f64 / 1_000_000.0, self.elapsed)
+    }
+}
+
+impl fmt::Display for ThroughputReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} file(s), {:.1} MB in {:.3}s -> {:.1} files/sec, {:.1} MB/sec",
+            self.pipeline,
+            self.files,
+            self.bytes as f64 / 1_000_000.0,
+            self.elapsed.as_secs_f64(),
+            self.files_per_sec(),
+            self.mb_per_sec()
+        )?;
+        if self.failed > 0 {
+            write!(f, " ({} failed)", self.failed)?;
+        }
+        Ok(())
+    }
+}
+
+fn per_sec(amount: f64, elapsed: Duration) -> f64 {
+    let secs = elapsed.as_secs_f64();
+    if secs > 0.0 {
+        amount / secs
+    } else {
+        0.0
+    }
+}
+
+/// `.dcm` files under `dir`, in a stable order.
+pub fn collect_files(dir: &Path) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = WalkDir::new(dir)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file())
+        .map(|e| e.into_path())
+        .filter(|p| {
+            p.extension()
+                .is_some_and(|ext| ext.eq_ignore_ascii_case("dcm"))
+        })
+        .collect();
+    files.sort();
+    files
+}
+
+/// Run `pipeline` over `files` `iterations` times, one file after another, writing outputs
+/// into `scratch`. Only the pipeline calls are timed; a file that fails is counted and
+/// skipped so one bad input does not hide the figures for the rest.
+pub fn run(
+    pipeline: Pipeline,
+    files: &[PathBuf],
+    scratch: &Path,
+    iterations: usize,
+) -> Result<ThroughputReport> {
+    let mut report = ThroughputReport {
+        pipeline,
+        files: 0,
+        failed: 0,
+        bytes: 0,
+        elapsed: Duration::ZERO,
+    };
+    for _ in 0..iterations {
+        for (index, input) in files.iter().enumerate() {
+            let size = std::fs::metadata(input)
+                .with_context(|| format!("Failed to stat {:?}", input))?
+                .len();
+            let output = scratch.join(format!("{:06}.dcm", index));
+            let started = Instant::now();
+            let result = match pipeline {
+                Pipeline::Anonymize => anonymize::anonymize_file_with(
+                    input,
+                    &output,
+                    AnonymizeOptions::default(),
+                    DerivationPolicy::Preserve,
+                ),
+                Pipeline::Transcode => transcode::transcode(
+                    input,
+                    &output,
+                    UncompressedTransferSyntax::ExplicitVRLittleEndian,
+                ),
+            };
+            report.elapsed += started.elapsed();
+            match result {
+                Ok(()) => {
+                    report.files += 1;
+                    report.bytes += size;
+                }
+                Err(_) => report.failed += 1,
+            }
+        }
+    }
+    Ok(report)
+}
+
+/// CLI entry point: time `pipeline` over every `.dcm` file under `dir` and print the rates.
+pub fn bench_directory(pipeline: Pipeline, dir: &Path, iterations: usize) -> Result<()> {
+    let files = collect_files(dir);
+    if files.is_empty() {
+        bail!("No .dcm files found under {:?}", dir);
+    }
+    let scratch = std::env::temp_dir().join(format!("dicom-tools-bench-{}", std::process::id()));
+    std::fs::create_dir_all(&scratch).context("Failed to create scratch directory")?;
+    let report = run(pipeline, &files, &scratch, iterations.max(1));
+    let _ = std::fs::remove_dir_all(&scratch);
+    println!("{}", report?);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::synth::{self, SynthSpec};
+
+    #[test]
+    fn both_pipelines_report_rates_over_synthetic_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let spec = SynthSpec {
+            instances: 3,
+            ..SynthSpec::default()
+        };
+        synth::write_series(&spec, dir.path()).unwrap();
+        std::fs::write(dir.path().join("broken.dcm"), b"not dicom").unwrap();
+        let files = collect_files(dir.path());
+        assert_eq!(files.len(), 4);
+
+        let scratch = tempfile::tempdir().unwrap();
+        for pipeline in [Pipeline::Anonymize, Pipeline::Transcode] {
+            let report = run(pipeline, &files, scratch.path(), 2).unwrap();
+            assert_eq!(report.files, 6, "{}", pipeline);
+            assert_eq!(report.failed, 2);
+            assert!(report.bytes > 6 * 64 * 64 * 2);
+            assert!(report.files_per_sec() > 0.0);
+            assert!(report.to_string().contains("(2 failed)"));
+        }
+    }
+}
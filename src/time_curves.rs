@@ -0,0 +1,700 @@
+//
+// time_curves.rs
+// Dicom-Tools-rs
+//
+// Time-intensity curves of dynamic acquisitions (perfusion, DCE-MRI): the series is sorted
+// into timepoints, each stacked into a volume, and the mean (with spread) of an ROI, of
+// single voxels or of the whole volume is followed across time and exported as CSV or a
+// PNG plot.
+//
+// Thales Matheus Mendonça Santos - November 2025
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use chrono::Timelike;
+use dicom::core::Tag;
+use dicom::object::{InMemDicomObject, OpenFileOptions};
+use image::{Rgb, RgbImage};
+use serde::Serialize;
+
+use crate::atomic_file;
+use crate::dicom_access::{open_dicom, ElementAccess};
+use crate::measure::FrameGeometry;
+use crate::roi_mask;
+use crate::temporal::parse_time;
+use crate::volume::{self, Resampling, SliceStack, Volume};
+
+const ACQUISITION_TIME: Tag = Tag(0x0008, 0x0032);
+const TRIGGER_TIME: Tag = Tag(0x0018, 0x1060);
+const INSTANCE_NUMBER: Tag = Tag(0x0020, 0x0013);
+const TEMPORAL_POSITION_IDENTIFIER: Tag = Tag(0x0020, 0x0100);
+const NUMBER_OF_FRAMES: Tag = Tag(0x0028, 0x0008);
+const PIXEL_DATA: Tag = Tag(0x7FE0, 0x0010);
+
+/// A voxel as column, row and slice indices (0-based, slices in stacking order).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct VoxelIndex {
+    pub x: usize,
+    pub y: usize,
+    pub z: usize,
+}
+
+impl std::str::FromStr for VoxelIndex {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let parts: Vec<&str> = s.split(',').map(str::trim).collect();
+        let [x, y, z] = parts[..] else {
+            bail!("Voxel '{}' must be formatted as x,y,z", s);
+        };
+        Ok(Self {
+            x: x.parse().context("Invalid x index")?,
+            y: y.parse().context("Invalid y index")?,
+            z: z.parse().context("Invalid slice index")?,
+        })
+    }
+}
+
+/// Which curves to compute. Without a mask or voxels, the whole volume gives one curve.
+#[derive(Debug, Clone, Default)]
+pub struct CurveRequest {
+    pub series: Option<String>,
+    /// SEG or RTSTRUCT, and the segment / ROI number within it.
+    pub mask: Option<(PathBuf, u32)>,
+    pub voxels: Vec<VoxelIndex>,
+}
+
+/// One timepoint of the dynamic series.
+#[derive(Debug, Clone, Serialize)]
+pub struct TimePoint {
+    /// Seconds since the first timepoint.
+    pub time_s: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temporal_position: Option<u32>,
+    pub slices: usize,
+}
+
+/// Statistics of the curve's voxels at one timepoint.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct CurveSample {
+    pub time_s: f64,
+    pub mean: f64,
+    pub std_dev: f64,
+    pub min: f64,
+    pub max: f64,
+}
+
+/// Usual descriptors of an enhancement curve, from the means. The first timepoint is taken
+/// as the pre-contrast baseline.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct CurveSummary {
+    pub baseline: f64,
+    pub peak: f64,
+    pub time_to_peak_s: f64,
+    pub peak_enhancement: f64,
+    /// Peak enhancement relative to the baseline, when the baseline is not zero.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub relative_enhancement_percent: Option<f64>,
+    /// Area under (mean − baseline) over time, trapezoidal.
+    pub enhancement_auc: f64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TimeCurve {
+    pub label: String,
+    pub voxels: usize,
+    pub samples: Vec<CurveSample>,
+    pub summary: CurveSummary,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TimeCurves {
+    pub columns: usize,
+    pub rows: usize,
+    pub slices: usize,
+    pub timepoints: Vec<TimePoint>,
+    pub curves: Vec<TimeCurve>,
+    pub warnings: Vec<String>,
+}
+
+/// Header facts deciding which timepoint a file belongs to.
+struct Member {
+    path: PathBuf,
+    temporal_position: Option<u32>,
+    acquisition_s: Option<f64>,
+    trigger_s: Option<f64>,
+    instance_number: Option<u32>,
+    /// Position along the slice normal, rounded to 0.01 mm.
+    location: Option<i64>,
+}
+
+impl Member {
+    fn read(path: &Path) -> Result<Self> {
+        let header = OpenFileOptions::new()
+            .read_until(PIXEL_DATA)
+            .open_file(path)
+            .with_context(|| format!("Failed to open {:?}", path))?;
+        let location = FrameGeometry::for_frame(&header, 0)
+            .ok()
+            .and_then(|g| Some((g.orientation?, g.position?)))
+            .map(|(o, p)| {
+                let normal = [
+                    o[1] * o[5] - o[2] * o[4],
+                    o[2] * o[3] - o[0] * o[5],
+                    o[0] * o[4] - o[1] * o[3],
+                ];
+                ((p[0] * normal[0] + p[1] * normal[1] + p[2] * normal[2]) * 100.0).round() as i64
+            });
+        Ok(Self {
+            path: path.to_path_buf(),
+            temporal_position: header.element_u32(TEMPORAL_POSITION_IDENTIFIER),
+            acquisition_s: header
+                .element_str(ACQUISITION_TIME)
+                .and_then(|t| parse_time(&t))
+                .map(|t| {
+                    t.value.num_seconds_from_midnight() as f64 + t.value.nanosecond() as f64 * 1e-9
+                }),
+            trigger_s: header.element_f64(TRIGGER_TIME).map(|ms| ms / 1000.0),
+            instance_number: header.element_u32(INSTANCE_NUMBER),
+            location,
+        })
+    }
+
+    fn order(&self) -> (f64, u32) {
+        (
+            self.acquisition_s.or(self.trigger_s).unwrap_or(0.0),
+            self.instance_number.unwrap_or(0),
+        )
+    }
+}
+
+/// Files grouped into timepoints: by Temporal Position Identifier when every file has one,
+/// otherwise the n-th acquisition of every slice location is timepoint n.
+fn group_timepoints(members: Vec<Member>) -> Result<Vec<Vec<Member>>> {
+    if members.iter().all(|m| m.temporal_position.is_some()) {
+        let mut groups: BTreeMap<u32, Vec<Member>> = BTreeMap::new();
+        for member in members {
+            groups
+                .entry(member.temporal_position.unwrap_or_default())
+                .or_default()
+                .push(member);
+        }
+        return Ok(groups.into_values().collect());
+    }
+    let mut locations: BTreeMap<i64, Vec<Member>> = BTreeMap::new();
+    for member in members {
+        let Some(location) = member.location else {
+            bail!(
+                "{:?} has neither a Temporal Position Identifier nor a slice position",
+                member.path
+            );
+        };
+        locations.entry(location).or_default().push(member);
+    }
+    let mut groups: Vec<Vec<Member>> = Vec::new();
+    for (_, mut acquisitions) in locations {
+        acquisitions.sort_by(|a, b| {
+            let ((ta, na), (tb, nb)) = (a.order(), b.order());
+            ta.total_cmp(&tb).then(na.cmp(&nb))
+        });
+        for (n, member) in acquisitions.into_iter().enumerate() {
+            if groups.len() <= n {
+                groups.push(Vec::new());
+            }
+            groups[n].push(member);
+        }
+    }
+    Ok(groups)
+}
+
+/// Stack every timepoint of the dynamic series in `dir` and follow the requested regions
+/// through time. Every timepoint must have the same slices; gaps are not resampled.
+pub fn compute(dir: &Path, request: &CurveRequest) -> Result<TimeCurves> {
+    let files = volume::series_files(dir, request.series.as_deref())?;
+    let members = files
+        .iter()
+        .map(|path| Member::read(path))
+        .collect::<Result<Vec<_>>>()?;
+    let groups = group_timepoints(members)?;
+    if groups.len() < 2 {
+        bail!(
+            "{:?} holds a single timepoint; nothing to follow over time",
+            dir
+        );
+    }
+
+    let mut warnings = Vec::new();
+    let start = |group: &[Member], time: fn(&Member) -> Option<f64>| {
+        group
+            .iter()
+            .map(time)
+            .collect::<Option<Vec<f64>>>()
+            .and_then(|times| times.into_iter().reduce(f64::min))
+    };
+    let starts: Option<Vec<f64>> = groups
+        .iter()
+        .map(|g| start(g, |m| m.acquisition_s))
+        .collect::<Option<_>>()
+        .or_else(|| {
+            groups
+                .iter()
+                .map(|g| start(g, |m| m.trigger_s))
+                .collect::<Option<_>>()
+        });
+    let times: Vec<f64> = match starts {
+        Some(starts) => starts.iter().map(|t| t - starts[0]).collect(),
+        None => {
+            warnings.push(
+                "No Acquisition Time or Trigger Time on every file; time is the timepoint index"
+                    .to_string(),
+            );
+            (0..groups.len()).map(|n| n as f64).collect()
+        }
+    };
+
+    let mut volumes: Vec<Volume> = Vec::new();
+    let mut slice_sources: Vec<Vec<(PathBuf, u32)>> = Vec::new();
+    let mut timepoints = Vec::new();
+    for (n, group) in groups.iter().enumerate() {
+        let paths: Vec<PathBuf> = group.iter().map(|m| m.path.clone()).collect();
+        let mut stack = SliceStack::read(&paths)
+            .with_context(|| format!("Cannot stack timepoint {}", n + 1))?;
+        // Sort here so the order matches the volume's (the stacking sort is stable).
+        let normal = stack.normal();
+        let distance = |slice: &volume::Slice| {
+            slice.position[0] * normal[0]
+                + slice.position[1] * normal[1]
+                + slice.position[2] * normal[2]
+        };
+        stack
+            .slices
+            .sort_by(|a, b| distance(a).total_cmp(&distance(b)));
+        let sources: Vec<(PathBuf, u32)> = stack
+            .slices
+            .iter()
+            .map(|s| (s.path.clone(), s.frame))
+            .collect();
+        let stacked = stack
+            .assemble(Resampling::None)
+            .with_context(|| format!("Cannot stack timepoint {}", n + 1))?;
+        if stacked.slices != sources.len() {
+            bail!(
+                "Timepoint {} has slices at the same position: {}",
+                n + 1,
+                stacked.warnings.join("; ")
+            );
+        }
+        if let Some(first) = volumes.first() {
+            if (stacked.columns, stacked.rows, stacked.slices)
+                != (first.columns, first.rows, first.slices)
+            {
+                bail!(
+                    "Timepoint {} is {}x{}x{}, timepoint 1 is {}x{}x{}",
+                    n + 1,
+                    stacked.columns,
+                    stacked.rows,
+                    stacked.slices,
+                    first.columns,
+                    first.rows,
+                    first.slices
+                );
+            }
+            let shift = (0..3)
+                .map(|i| (stacked.origin[i] - first.origin[i]).powi(2))
+                .sum::<f64>()
+                .sqrt();
+            if shift > 0.01 {
+                warnings.push(format!(
+                    "Timepoint {} starts {:.2} mm away from timepoint 1; voxels are compared by index",
+                    n + 1,
+                    shift
+                ));
+            }
+        }
+        timepoints.push(TimePoint {
+            time_s: times[n],
+            temporal_position: group[0].temporal_position,
+            slices: stacked.slices,
+        });
+        volumes.push(stacked);
+        slice_sources.push(sources);
+    }
+
+    let (columns, rows, slices) = (volumes[0].columns, volumes[0].rows, volumes[0].slices);
+    let plane = columns * rows;
+    let mut regions: Vec<(String, Vec<usize>)> = Vec::new();
+    if let Some((path, segment)) = &request.mask {
+        let (label, voxels) = mask_voxels(path, *segment, &slice_sources, plane)?;
+        if voxels.is_empty() {
+            bail!(
+                "Mask {:?} segment {} covers no voxel of the series",
+                path,
+                segment
+            );
+        }
+        regions.push((label, voxels));
+    }
+    for voxel in &request.voxels {
+        if voxel.x >= columns || voxel.y >= rows || voxel.z >= slices {
+            bail!(
+                "Voxel {},{},{} is outside the {}x{}x{} volume",
+                voxel.x,
+                voxel.y,
+                voxel.z,
+                columns,
+                rows,
+                slices
+            );
+        }
+        regions.push((
+            format!("voxel {},{},{}", voxel.x, voxel.y, voxel.z),
+            vec![voxel.z * plane + voxel.y * columns + voxel.x],
+        ));
+    }
+    if regions.is_empty() {
+        regions.push(("volume".to_string(), (0..plane * slices).collect()));
+    }
+
+    let curves = regions
+        .into_iter()
+        .map(|(label, voxels)| {
+            let samples: Vec<CurveSample> = volumes
+                .iter()
+                .zip(&timepoints)
+                .map(|(volume, timepoint)| sample(&volume.values, &voxels, timepoint.time_s))
+                .collect();
+            TimeCurve {
+                label,
+                voxels: voxels.len(),
+                summary: summarize(&samples),
+                samples,
+            }
+        })
+        .collect();
+    Ok(TimeCurves {
+        columns,
+        rows,
+        slices,
+        timepoints,
+        curves,
+        warnings,
+    })
+}
+
+/// Voxel indices of the mask, resolved slice by slice. A slice belongs to the region when
+/// the mask covers it at any timepoint, since a SEG may only reference the images of one.
+fn mask_voxels(
+    path: &Path,
+    segment: u32,
+    slice_sources: &[Vec<(PathBuf, u32)>],
+    plane: usize,
+) -> Result<(String, Vec<usize>)> {
+    let source = open_dicom(path).with_context(|| format!("Failed to open mask {:?}", path))?;
+    let mut resolved: HashMap<&Path, roi_mask::RoiMask> = HashMap::new();
+    let mut label = None;
+    let mut inside = vec![false; plane * slice_sources[0].len()];
+    for sources in slice_sources {
+        for (z, (file, frame)) in sources.iter().enumerate() {
+            if !resolved.contains_key(file.as_path()) {
+                let header: InMemDicomObject = OpenFileOptions::new()
+                    .read_until(PIXEL_DATA)
+                    .open_file(file)
+                    .with_context(|| format!("Failed to open {:?}", file))?
+                    .into_inner();
+                let frames = header.element_u32(NUMBER_OF_FRAMES).unwrap_or(1).max(1);
+                let mask = roi_mask::resolve(&source, segment, &header, frames)
+                    .with_context(|| format!("Mask {:?}", path))?;
+                resolved.insert(file.as_path(), mask);
+            }
+            let mask = &resolved[file.as_path()];
+            label = label.or_else(|| mask.label.clone());
+            if let Some(pixels) = mask.frames.get(*frame as usize) {
+                for (i, &covered) in pixels.iter().enumerate().take(plane) {
+                    inside[z * plane + i] |= covered;
+                }
+            }
+        }
+    }
+    let voxels = inside
+        .iter()
+        .enumerate()
+        .filter(|(_, &inside)| inside)
+        .map(|(i, _)| i)
+        .collect();
+    Ok((
+        label.unwrap_or_else(|| format!("segment {}", segment)),
+        voxels,
+    ))
+}
+
+fn sample(values: &[f32], voxels: &[usize], time_s: f64) -> CurveSample {
+    let n = voxels.len() as f64;
+    let (mut sum, mut min, mut max) = (0.0, f64::INFINITY, f64::NEG_INFINITY);
+    for &i in voxels {
+        let value = values[i] as f64;
+        sum += value;
+        min = min.min(value);
+        max = max.max(value);
+    }
+    let mean = sum / n;
+    let variance = voxels
+        .iter()
+        .map(|&i| (values[i] as f64 - mean).powi(2))
+        .sum::<f64>()
+        / n;
+    CurveSample {
+        time_s,
+        mean,
+        std_dev: variance.sqrt(),
+        min,
+        max,
+    }
+}
+
+fn summarize(samples: &[CurveSample]) -> CurveSummary {
+    let baseline = samples[0].mean;
+    let peak = samples
+        .iter()
+        .copied()
+        .reduce(|best, s| if s.mean > best.mean { s } else { best })
+        .unwrap_or(samples[0]);
+    let enhancement_auc = samples
+        .windows(2)
+        .map(|w| (w[1].time_s - w[0].time_s) * ((w[0].mean + w[1].mean) / 2.0 - baseline))
+        .sum();
+    CurveSummary {
+        baseline,
+        peak: peak.mean,
+        time_to_peak_s: peak.time_s - samples[0].time_s,
+        peak_enhancement: peak.mean - baseline,
+        relative_enhancement_percent: (baseline != 0.0)
+            .then(|| (peak.mean - baseline) / baseline.abs() * 100.0),
+        enhancement_auc,
+    }
+}
+
+impl TimeCurves {
+    /// One row per curve and timepoint.
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("curve,timepoint,time_s,mean,std_dev,min,max,voxels\n");
+        for curve in &self.curves {
+            let label = if curve.label.contains([',', '"']) {
+                format!("\"{}\"", curve.label.replace('"', "\"\""))
+            } else {
+                curve.label.clone()
+            };
+            for (n, s) in curve.samples.iter().enumerate() {
+                let _ = writeln!(
+                    csv,
+                    "{},{},{},{},{},{},{},{}",
+                    label,
+                    n + 1,
+                    s.time_s,
+                    s.mean,
+                    s.std_dev,
+                    s.min,
+                    s.max,
+                    curve.voxels
+                );
+            }
+        }
+        csv
+    }
+
+    /// Means over time, one coloured polyline per curve (in [`PLOT_COLORS`] order) on
+    /// axes spanning the time range and the range of the means.
+    pub fn plot(&self) -> RgbImage {
+        const WIDTH: u32 = 800;
+        const HEIGHT: u32 = 480;
+        const MARGIN: f64 = 40.0;
+        let mut image = RgbImage::from_pixel(WIDTH, HEIGHT, Rgb([255, 255, 255]));
+        let means = self
+            .curves
+            .iter()
+            .flat_map(|c| c.samples.iter().map(|s| s.mean));
+        let (low, high) = means.fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), v| {
+            (lo.min(v), hi.max(v))
+        });
+        let span = if high > low { high - low } else { 1.0 };
+        let end = self.timepoints.last().map_or(1.0, |t| t.time_s).max(1e-9);
+        let to_pixel = |time: f64, value: f64| {
+            (
+                MARGIN + time / end * (WIDTH as f64 - 2.0 * MARGIN),
+                HEIGHT as f64 - MARGIN - (value - low) / span * (HEIGHT as f64 - 2.0 * MARGIN),
+            )
+        };
+        let axis = Rgb([0, 0, 0]);
+        let (x0, y0) = (MARGIN, HEIGHT as f64 - MARGIN);
+        draw_line(&mut image, (x0, y0), (WIDTH as f64 - MARGIN, y0), axis);
+        draw_line(&mut image, (x0, y0), (x0, MARGIN), axis);
+        for (curve, color) in self.curves.iter().zip(PLOT_COLORS.iter().cycle()) {
+            let points: Vec<(f64, f64)> = curve
+                .samples
+                .iter()
+                .map(|s| to_pixel(s.time_s, s.mean))
+                .collect();
+            for pair in points.windows(2) {
+                draw_line(&mut image, pair[0], pair[1], *color);
+            }
+            for &(x, y) in &points {
+                for dx in -2..=2 {
+                    for dy in -2..=2 {
+                        put_pixel(&mut image, x + dx as f64, y + dy as f64, *color);
+                    }
+                }
+            }
+        }
+        image
+    }
+}
+
+/// Curve colours of [`TimeCurves::plot`], reused in order.
+pub const PLOT_COLORS: [Rgb<u8>; 6] = [
+    Rgb([215, 48, 39]),
+    Rgb([69, 117, 180]),
+    Rgb([26, 152, 80]),
+    Rgb([152, 78, 163]),
+    Rgb([255, 127, 0]),
+    Rgb([77, 77, 77]),
+];
+
+fn put_pixel(image: &mut RgbImage, x: f64, y: f64, color: Rgb<u8>) {
+    let (x, y) = (x.round(), y.round());
+    if x >= 0.0 && y >= 0.0 && (x as u32) < image.width() && (y as u32) < image.height() {
+        image.put_pixel(x as u32, y as u32, color);
+    }
+}
+
+fn draw_line(image: &mut RgbImage, from: (f64, f64), to: (f64, f64), color: Rgb<u8>) {
+    let steps = (to.0 - from.0)
+        .abs()
+        .max((to.1 - from.1).abs())
+        .ceil()
+        .max(1.0);
+    for step in 0..=steps as u32 {
+        let t = step as f64 / steps;
+        put_pixel(
+            image,
+            from.0 + t * (to.0 - from.0),
+            from.1 + t * (to.1 - from.1),
+            color,
+        );
+    }
+}
+
+const COLOR_NAMES: [&str; 6] = ["red", "blue", "green", "purple", "orange", "grey"];
+
+/// CLI entry point: compute the curves, print their descriptors and write the exports.
+pub fn print_curves(
+    dir: &Path,
+    request: &CurveRequest,
+    csv: Option<&Path>,
+    png: Option<&Path>,
+    json: bool,
+) -> Result<()> {
+    let curves = compute(dir, request)?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&curves)?);
+    } else {
+        println!(
+            "{} timepoint(s) of {}x{}x{} over {:.1} s",
+            curves.timepoints.len(),
+            curves.columns,
+            curves.rows,
+            curves.slices,
+            curves.timepoints.last().map_or(0.0, |t| t.time_s)
+        );
+        for warning in &curves.warnings {
+            println!("  [WARN] {}", warning);
+        }
+        for (curve, color) in curves.curves.iter().zip(COLOR_NAMES.iter().cycle()) {
+            let s = &curve.summary;
+            println!(
+                "  {} ({} voxel(s), {}): baseline {:.2}, peak {:.2} at {:.1} s, enhancement {:.2}{}, AUC {:.2}",
+                curve.label,
+                curve.voxels,
+                color,
+                s.baseline,
+                s.peak,
+                s.time_to_peak_s,
+                s.peak_enhancement,
+                s.relative_enhancement_percent
+                    .map(|p| format!(" ({:.1}%)", p))
+                    .unwrap_or_default(),
+                s.enhancement_auc
+            );
+        }
+    }
+    if let Some(path) = csv {
+        atomic_file::write(path, curves.to_csv())
+            .with_context(|| format!("Failed to write CSV to {:?}", path))?;
+        eprintln!("CSV saved to {:?}", path);
+    }
+    if let Some(path) = png {
+        let plot = image::DynamicImage::ImageRgb8(curves.plot());
+        atomic_file::write_with(path, |writer| {
+            Ok(plot.write_to(writer, image::ImageFormat::Png)?)
+        })
+        .with_context(|| format!("Failed to write plot to {:?}", path))?;
+        eprintln!("Plot saved to {:?}", path);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_at(time_s: f64, mean: f64) -> CurveSample {
+        CurveSample {
+            time_s,
+            mean,
+            std_dev: 0.0,
+            min: mean,
+            max: mean,
+        }
+    }
+
+    #[test]
+    fn summary_describes_an_enhancement_curve() {
+        let samples = [
+            sample_at(0.0, 100.0),
+            sample_at(10.0, 300.0),
+            sample_at(20.0, 200.0),
+        ];
+        let summary = summarize(&samples);
+        assert_eq!(summary.peak, 300.0);
+        assert_eq!(summary.time_to_peak_s, 10.0);
+        assert_eq!(summary.relative_enhancement_percent, Some(200.0));
+        // (0 + 200) / 2 * 10 + (200 + 100) / 2 * 10
+        assert_eq!(summary.enhancement_auc, 2500.0);
+    }
+
+    #[test]
+    fn acquisitions_of_each_location_are_numbered_in_time() {
+        let member = |location, acquisition_s| Member {
+            path: PathBuf::from(format!("{}-{}", location, acquisition_s)),
+            temporal_position: None,
+            acquisition_s: Some(acquisition_s),
+            trigger_s: None,
+            instance_number: None,
+            location: Some(location),
+        };
+        let groups = group_timepoints(vec![
+            member(0, 20.0),
+            member(100, 1.0),
+            member(0, 0.0),
+            member(100, 21.0),
+        ])
+        .unwrap();
+        let names: Vec<Vec<String>> = groups
+            .iter()
+            .map(|g| g.iter().map(|m| m.path.display().to_string()).collect())
+            .collect();
+        assert_eq!(names, [["0-0", "100-1"], ["0-20", "100-21"]]);
+        assert!("1,2".parse::<VoxelIndex>().is_err());
+    }
+}
@@ -0,0 +1,752 @@
+//
+// toolkit.rs
+// Dicom-Tools-rs
+//
+// Library facade: one `DicomToolkit` holding the configuration the CLI spreads over flags
+// (anonymization profile, derivation policy, association settings, DICOMweb credentials,
+// upload store and preview cache), with a method per CLI verb. Methods return the structured
+// result of the underlying module instead of printing it, where the module offers one.
+//
+// Thales Matheus Mendonça Santos - November 2025
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use dicom::core::dictionary::DataDictionary;
+use dicom::core::Tag;
+use dicom::dictionary_std::StandardDataDictionary;
+use dicom::object::open_file;
+use dicom_pixeldata::WindowLevel;
+use ndarray::ArrayView3;
+use sha2::{Digest, Sha256};
+
+use crate::anonymize::{self, AnonymizeOptions};
+use crate::availability::{self, AvailabilityReport, RemoteAe};
+use crate::cli::BatchOperation;
+use crate::derivation::{self, DerivationPolicy};
+use crate::dimse::AssociationSettings;
+use crate::echo_scan::{self, EchoResult};
+use crate::harmonize::{self, HarmonizeSummary, Harmonizer};
+use crate::image::{self, ImageExportOptions, PreviewFormat};
+use crate::joint_histogram::{self, JointHistogram};
+use crate::measure::{self, Measurement, PixelPoint};
+use crate::models::{DetailedMetadata, PixelHistogram, PixelStatistics};
+use crate::preview_cache::{CacheOutcome, PreviewCache, PreviewKey};
+use crate::progress::NoProgress;
+use crate::registration::{self, RegistrationReport, SeriesGeometry};
+use crate::router::Router;
+use crate::scp::{AeMap, ScpConfig};
+use crate::screening::DicomSanityScreen;
+use crate::scu::{
+    self, PushDirOptions, PushDirSummary, RetrieveOptions, RetrieveSummary, RetrieveTarget,
+};
+use crate::send_queue::{self, DeliveryOptions, DrainSummary, SendQueue};
+use crate::series_split::{self, SplitKey, SplitSummary};
+use crate::sharing::ShareSigner;
+use crate::stats::{self, Binning, PaletteSpace};
+use crate::storage::FileStore;
+use crate::stow::{self, StowOptions, StowSummary};
+use crate::transcode::{self, LossyOptions, LossyTransferSyntax, UncompressedTransferSyntax};
+use crate::upload_pipeline::UploadPipeline;
+use crate::validate::{self, FileFinding};
+use crate::volume::{self, Volume, VolumeOptions};
+use crate::wado::{self, WadoOptions, WadoResource, WadoSummary, WadoTarget};
+use crate::web::{self, WorkerLimits};
+use crate::worklist::{self, WorklistItem, WorklistQuery};
+use crate::{
+    annotations, batch, codes, concatenation, fhir, frame_extract, icon, json, measurement_report,
+    mpps, padding, parametric_map, rescale, size_report, synth, tag_stats, time_curves,
+};
+
+/// Memory given to the decode cache unless [`DicomToolkit::with_preview_cache`] replaces it,
+/// the same as `web --preview-cache-mb`.
+const DEFAULT_PREVIEW_CACHE_BYTES: usize = 64 * 1024 * 1024;
+
+/// Files per STOW-RS request, as `stow --batch-size` defaults to.
+const DEFAULT_STOW_BATCH: usize = 50;
+
+/// Entry point for applications embedding the crate.
+///
+/// ```no_run
+/// use dicom_tools::DicomToolkit;
+///
+/// let toolkit = DicomToolkit::new();
+/// toolkit.anonymize("ct.dcm".as_ref(), "ct_anon.dcm".as_ref())?;
+/// let info = toolkit.info("ct_anon.dcm".as_ref())?;
+/// println!("{:?}", info.patient.get("Name"));
+/// # anyhow::Ok(())
+/// ```
+///
+/// Tag keywords and code meanings come from the standard data dictionary and the bundled
+/// code table; they are not configurable.
+pub struct DicomToolkit {
+    anonymization: AnonymizeOptions,
+    derivation: DerivationPolicy,
+    association: AssociationSettings,
+    dicomweb_token: Option<String>,
+    http_timeout: Option<Duration>,
+    store: Option<FileStore>,
+    previews: PreviewCache,
+}
+
+impl Default for DicomToolkit {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DicomToolkit {
+    /// Basic-profile anonymization, sources' identity preserved, dicom-ul's association
+    /// defaults and no upload store.
+    pub fn new() -> Self {
+        Self {
+            anonymization: AnonymizeOptions::default(),
+            derivation: DerivationPolicy::Preserve,
+            association: AssociationSettings::default(),
+            dicomweb_token: None,
+            http_timeout: None,
+            store: None,
+            previews: PreviewCache::new(DEFAULT_PREVIEW_CACHE_BYTES),
+        }
+    }
+
+    /// Profile and options used by [`anonymize`](Self::anonymize).
+    pub fn with_anonymization(mut self, options: AnonymizeOptions) -> Self {
+        self.anonymization = options;
+        self
+    }
+
+    /// Provenance recorded by the verbs writing a new object from an existing one.
+    pub fn with_derivation(mut self, policy: DerivationPolicy) -> Self {
+        self.derivation = policy;
+        self
+    }
+
+    /// AE titles, PDU size, timeouts and transfer syntaxes of every DIMSE verb.
+    pub fn with_association(mut self, settings: AssociationSettings) -> Self {
+        self.association = settings;
+        self
+    }
+
+    /// Bearer token sent to DICOMweb services (STOW-RS, WADO-RS, queued STOW deliveries).
+    pub fn with_dicomweb_token(mut self, token: impl Into<String>) -> Self {
+        self.dicomweb_token = Some(token.into());
+        self
+    }
+
+    pub fn with_http_timeout(mut self, timeout: Duration) -> Self {
+        self.http_timeout = Some(timeout);
+        self
+    }
+
+    /// Storage backend served by [`serve_web`](Self::serve_web) and filled by
+    /// [`serve_scp`](Self::serve_scp).
+    pub fn with_store(mut self, store: FileStore) -> Self {
+        self.store = Some(store);
+        self
+    }
+
+    /// Decode cache behind [`preview`](Self::preview) and the web viewer.
+    pub fn with_preview_cache(mut self, previews: PreviewCache) -> Self {
+        self.previews = previews;
+        self
+    }
+
+    pub fn association(&self) -> &AssociationSettings {
+        &self.association
+    }
+
+    pub fn store(&self) -> Option<&FileStore> {
+        self.store.as_ref()
+    }
+
+    pub fn previews(&self) -> &PreviewCache {
+        &self.previews
+    }
+
+    // Dictionaries
+
+    /// Keyword of a standard attribute, e.g. `PatientName` for (0010,0010).
+    pub fn tag_keyword(&self, tag: Tag) -> Option<&'static str> {
+        StandardDataDictionary.by_tag(tag).map(|entry| entry.alias)
+    }
+
+    /// Meaning of a coded concept in the bundled code table.
+    pub fn code_meaning(&self, scheme: &str, value: &str) -> Option<&'static str> {
+        codes::lookup(scheme, value)
+    }
+
+    // File verbs
+
+    /// `info`
+    pub fn info(&self, path: &Path) -> Result<DetailedMetadata> {
+        crate::metadata::read_detailed_metadata(path)
+    }
+
+    /// `anonymize`, with the configured profile and derivation policy.
+    pub fn anonymize(&self, input: &Path, output: &Path) -> Result<()> {
+        anonymize::anonymize_file_with(input, output, self.anonymization, self.derivation)
+    }
+
+    /// `to-image`
+    pub fn to_image(
+        &self,
+        input: &Path,
+        output: &Path,
+        format: &str,
+        options: &ImageExportOptions,
+    ) -> Result<()> {
+        image::convert(input, Some(output.to_path_buf()), format, options)
+    }
+
+    /// PNG preview of `frame`, through the decode cache; the cache is keyed by content, so
+    /// renaming or copying a file still hits it.
+    pub fn preview(
+        &self,
+        path: &Path,
+        frame: u32,
+        window: Option<WindowLevel>,
+        max_edge: Option<u32>,
+    ) -> Result<(Arc<Vec<u8>>, CacheOutcome)> {
+        let bytes = fs::read(path).with_context(|| format!("Failed to read {:?}", path))?;
+        let key = PreviewKey::new(
+            hex::encode(Sha256::digest(&bytes)),
+            frame,
+            window,
+            max_edge,
+            PreviewFormat::Png,
+        );
+        self.previews.get_or_render(&key, || {
+            image::preview_png_bytes(path, frame, window, max_edge)
+        })
+    }
+
+    /// `validate` for one file.
+    pub fn validate(&self, path: &Path) -> FileFinding {
+        validate::inspect_file(path)
+    }
+
+    /// `batch`
+    pub fn batch(&self, directory: &Path, operation: BatchOperation) -> Result<()> {
+        batch::process_directory_with_progress(directory, operation, None, &NoProgress)
+    }
+
+    /// `route`; returns the number of rules that failed.
+    pub fn route(&self, rules: &Path, inputs: &[PathBuf]) -> Result<usize> {
+        Ok(Router::load(rules)?.route_paths(inputs))
+    }
+
+    /// `to-json`, in the DICOM JSON model.
+    pub fn to_json(&self, path: &Path) -> Result<String> {
+        json::to_json_string(path)
+    }
+
+    /// `from-json`
+    pub fn from_json(&self, input: &Path, output: &Path) -> Result<()> {
+        json::from_json(input, output)
+    }
+
+    /// `transcode` to an uncompressed transfer syntax.
+    pub fn transcode(
+        &self,
+        input: &Path,
+        output: &Path,
+        target: UncompressedTransferSyntax,
+    ) -> Result<()> {
+        transcode::transcode_with_derivation(input, output, target, self.derivation, &NoProgress)
+    }
+
+    /// `transcode --lossy`
+    pub fn transcode_lossy(
+        &self,
+        input: &Path,
+        output: &Path,
+        codec: LossyTransferSyntax,
+        quality: u8,
+        force: bool,
+    ) -> Result<()> {
+        let options = LossyOptions {
+            quality,
+            force,
+            derivation: self.derivation,
+        };
+        transcode::transcode_lossy(input, output, codec, &options)
+    }
+
+    /// `stats`, leaving padding and shuttered pixels out.
+    pub fn stats(&self, path: &Path) -> Result<PixelStatistics> {
+        stats::pixel_statistics_with(path, PaletteSpace::Index, padding::Exclusions::default())
+    }
+
+    /// `stats --mask`
+    pub fn stats_within(&self, path: &Path, mask: &Path, segment: u32) -> Result<PixelStatistics> {
+        Ok(stats::pixel_statistics_within_file(path, mask, segment)?.0)
+    }
+
+    /// `histogram`
+    pub fn histogram(&self, path: &Path, binning: Binning) -> Result<PixelHistogram> {
+        stats::histogram_for_file_with(path, binning)
+    }
+
+    /// `joint-histogram`
+    pub fn joint_histogram(
+        &self,
+        a: (&Path, u32),
+        b: (&Path, u32),
+        bins: usize,
+    ) -> Result<JointHistogram> {
+        let (values_a, rows_a, columns_a) = joint_histogram::frame_values(a.0, a.1)?;
+        let (values_b, rows_b, columns_b) = joint_histogram::frame_values(b.0, b.1)?;
+        if (rows_a, columns_a) != (rows_b, columns_b) {
+            bail!(
+                "Frames differ in size: {}x{} against {}x{}",
+                columns_a,
+                rows_a,
+                columns_b,
+                rows_b
+            );
+        }
+        JointHistogram::compute(&values_a, &values_b, bins)
+    }
+
+    /// `dump`
+    pub fn dump(&self, path: &Path, max_depth: usize, max_value_len: usize) -> Result<String> {
+        crate::dump::dump_to_string(path, max_depth, max_value_len)
+    }
+
+    /// `measure`
+    pub fn measure(&self, path: &Path, frame: u32, points: &[PixelPoint]) -> Result<Measurement> {
+        let obj = open_file(path).context("Failed to open DICOM file")?;
+        measure::measure(&obj, frame, points)
+    }
+
+    /// `export-nifti`
+    pub fn export_nifti(
+        &self,
+        directory: &Path,
+        output: &Path,
+        options: &VolumeOptions,
+    ) -> Result<Volume> {
+        volume::export_nifti(directory, output, options)
+    }
+
+    /// `time-curves`
+    pub fn time_curves(
+        &self,
+        directory: &Path,
+        request: &time_curves::CurveRequest,
+    ) -> Result<time_curves::TimeCurves> {
+        time_curves::compute(directory, request)
+    }
+
+    /// `registration-check`
+    pub fn registration_check(
+        &self,
+        series_a: &Path,
+        series_b: &Path,
+        angle_tolerance_deg: f64,
+    ) -> Result<RegistrationReport> {
+        let a = SeriesGeometry::from_directory(series_a)?;
+        let b = SeriesGeometry::from_directory(series_b)?;
+        Ok(registration::compare(&a, &b, angle_tolerance_deg))
+    }
+
+    /// `tag-stats`
+    pub fn tag_stats(&self, directory: &Path) -> Result<tag_stats::TagStatsReport> {
+        tag_stats::scan_directory(directory)
+    }
+
+    /// `harmonize`; without `output` nothing is written (dry run).
+    pub fn harmonize(
+        &self,
+        directory: &Path,
+        map: &Path,
+        output: Option<&Path>,
+    ) -> Result<HarmonizeSummary> {
+        harmonize::harmonize_directory(directory, &Harmonizer::load(map)?, output)
+    }
+
+    /// `split-series`; without `output` nothing is written (dry run).
+    pub fn split_series(
+        &self,
+        directory: &Path,
+        by: Option<SplitKey>,
+        output: Option<&Path>,
+    ) -> Result<SplitSummary> {
+        series_split::split_directory(directory, by, output)
+    }
+
+    /// `concat`; reports on stdout like the CLI.
+    pub fn concat(&self, directory: &Path, output: Option<&Path>) -> Result<()> {
+        concatenation::concat_directory(directory, output)
+    }
+
+    /// `extract-frames`, with the configured derivation policy.
+    pub fn extract_frames(
+        &self,
+        input: &Path,
+        frames: &[u32],
+        output: &Path,
+    ) -> Result<Vec<PathBuf>> {
+        frame_extract::extract_frames_file(input, frames, output, self.derivation)
+    }
+
+    /// `synth`
+    pub fn synth(&self, spec: &synth::SynthSpec, output: &Path) -> Result<Vec<PathBuf>> {
+        synth::write_series(spec, output)
+    }
+
+    /// `size-report`
+    pub fn size_report(&self, directory: &Path) -> Result<size_report::SizeReport> {
+        size_report::scan_directory(directory)
+    }
+
+    /// `import-annotations`
+    pub fn import_annotations(
+        &self,
+        annotations: &Path,
+        series: &Path,
+        target: annotations::ImportTarget,
+        output: &Path,
+    ) -> Result<annotations::ImportSummary> {
+        annotations::import(annotations, series, target, output)
+    }
+
+    /// `measurement-report`
+    pub fn measurement_report(
+        &self,
+        findings: &Path,
+        series: &Path,
+        output: &Path,
+    ) -> Result<measurement_report::ReportSummary> {
+        measurement_report::write(findings, series, output)
+    }
+
+    /// `to-fhir`; the bundle is also written to `output` when given.
+    pub fn to_fhir(&self, directory: &Path, output: Option<&Path>) -> Result<fhir::FhirExport> {
+        match output {
+            Some(_) => fhir::write_bundle(directory, output),
+            None => fhir::export_directory(directory),
+        }
+    }
+
+    /// `parametric-map`, from values already in memory (frames × rows × columns).
+    pub fn parametric_map(
+        &self,
+        values: ArrayView3<f32>,
+        reference: &Path,
+        options: &parametric_map::ParametricMapOptions,
+        output: &Path,
+    ) -> Result<parametric_map::ParametricMapSummary> {
+        parametric_map::write(values, reference, options, output)
+    }
+
+    /// `rescale`
+    pub fn rescale(&self, input: &Path, output: &Path, mode: rescale::RescaleMode) -> Result<()> {
+        rescale::rescale_file(input, output, mode)
+    }
+
+    /// `extract-icon`
+    pub fn extract_icon(&self, path: &Path, output: &Path) -> Result<()> {
+        icon::extract_icon_file(path, output)
+    }
+
+    /// `add-icon`
+    pub fn add_icon(&self, input: &Path, output: &Path, size: icon::IconSize) -> Result<()> {
+        icon::add_icon_file(input, output, size)
+    }
+
+    /// `export-pixels`
+    #[cfg(feature = "parquet")]
+    pub fn export_pixels(
+        &self,
+        input: &Path,
+        output: &Path,
+        granularity: crate::pixel_export::Granularity,
+        format: crate::pixel_export::ExportFormat,
+    ) -> Result<()> {
+        crate::pixel_export::export_pixels(input, output, granularity, format)
+    }
+
+    /// `bench`; reports on stdout like the CLI.
+    #[cfg(feature = "bench")]
+    pub fn bench(
+        &self,
+        pipeline: crate::throughput::Pipeline,
+        directory: &Path,
+        iterations: usize,
+    ) -> Result<()> {
+        crate::throughput::bench_directory(pipeline, directory, iterations)
+    }
+
+    // Network verbs. These block; async applications run them with `spawn_blocking`.
+
+    /// `echo`
+    pub fn echo(&self, addr: &str) -> Result<()> {
+        scu::echo_traced(addr, &self.association, None)
+    }
+
+    /// `echo-scan`; `targets` and `ports` take the same lists as the command line.
+    pub fn echo_scan(
+        &self,
+        targets: &str,
+        ports: &str,
+        concurrency: usize,
+    ) -> Result<Vec<EchoResult>> {
+        let hosts = echo_scan::parse_targets(targets)?;
+        let ports = echo_scan::parse_ports(ports)?;
+        Ok(echo_scan::scan(
+            &hosts,
+            &ports,
+            &self.association,
+            concurrency,
+        ))
+    }
+
+    /// `push`; returns the C-STORE-RSP status.
+    pub fn push(&self, addr: &str, file: &Path) -> Result<u16> {
+        scu::push_status(addr, file, &self.association, &NoProgress, None)
+    }
+
+    /// `push-dir`; instances failing for a transient reason go to `queue` when given.
+    pub fn push_dir(
+        &self,
+        addr: &str,
+        directory: &Path,
+        queue: Option<SendQueue>,
+    ) -> Result<PushDirSummary> {
+        let options = PushDirOptions {
+            association: self.association.clone(),
+            rate_limit: None,
+            window: None,
+            audit: None,
+            queue,
+        };
+        scu::push_dir(addr, directory, &options, &NoProgress, None)
+    }
+
+    /// `mwl`
+    pub fn worklist(&self, addr: &str, query: &WorklistQuery) -> Result<Vec<WorklistItem>> {
+        let answers = scu::find(
+            addr,
+            &self.association,
+            worklist::MODALITY_WORKLIST_FIND,
+            &query.identifier(),
+            None,
+        )?;
+        Ok(answers.iter().map(WorklistItem::from_identifier).collect())
+    }
+
+    /// `mpps` without `--uid`: reports the procedure acquired in `directory` as started now
+    /// and returns the SOP Instance UID of the new step.
+    pub fn mpps_start(&self, addr: &str, directory: &Path, step_id: &str) -> Result<String> {
+        let procedure = mpps::Procedure::scan(directory)?;
+        let uid = derivation::new_instance_uid(&directory.to_string_lossy());
+        let attributes = procedure.in_progress(
+            step_id,
+            &self.association.calling_ae_title,
+            chrono::Local::now().naive_local(),
+        );
+        scu::n_create(
+            addr,
+            &self.association,
+            mpps::MODALITY_PERFORMED_PROCEDURE_STEP,
+            &uid,
+            &attributes,
+            None,
+        )?;
+        Ok(uid)
+    }
+
+    /// `mpps --uid`: finishes the step, listing every instance in `directory`.
+    pub fn mpps_finish(
+        &self,
+        addr: &str,
+        directory: &Path,
+        uid: &str,
+        status: mpps::StepStatus,
+        retrieve_ae_title: Option<&str>,
+    ) -> Result<()> {
+        if status == mpps::StepStatus::InProgress {
+            bail!("Step {} already exists; the status must finish it", uid);
+        }
+        let procedure = mpps::Procedure::scan(directory)?;
+        let modifications = procedure.completion(
+            status,
+            chrono::Local::now().naive_local(),
+            retrieve_ae_title,
+        );
+        scu::n_set(
+            addr,
+            &self.association,
+            mpps::MODALITY_PERFORMED_PROCEDURE_STEP,
+            uid,
+            &modifications,
+            None,
+        )
+    }
+
+    /// `stow`
+    pub fn stow(&self, url: &str, inputs: &[PathBuf]) -> Result<StowSummary> {
+        let options = StowOptions {
+            url: url.to_string(),
+            token: self.dicomweb_token.clone(),
+            batch_size: DEFAULT_STOW_BATCH,
+            timeout: self.http_timeout,
+            rate_limit: None,
+            window: None,
+        };
+        stow::store(inputs, &options)
+    }
+
+    /// `wado`
+    pub fn wado(
+        &self,
+        url: &str,
+        target: &WadoTarget,
+        resource: &WadoResource,
+        output: &Path,
+    ) -> Result<WadoSummary> {
+        let options = WadoOptions {
+            url: url.to_string(),
+            token: self.dicomweb_token.clone(),
+            timeout: self.http_timeout,
+            transfer_syntax: None,
+        };
+        wado::retrieve(&options, target, resource, output)
+    }
+
+    /// `send-queue`: one pass over the due entries, or until the queue is empty with `wait`.
+    pub fn drain_send_queue(&self, queue: &SendQueue, wait: bool) -> Result<DrainSummary> {
+        let options = DeliveryOptions {
+            token: self.dicomweb_token.clone(),
+            timeout: self.http_timeout,
+        };
+        queue.drain(
+            &mut |entry, file| send_queue::deliver(entry, file, &options),
+            wait,
+        )
+    }
+
+    /// `verify-remote`
+    pub fn verify_remote(&self, addr: &str, directory: &Path) -> Result<AvailabilityReport> {
+        let remote = RemoteAe {
+            addr: addr.to_string(),
+            association: self.association.clone(),
+        };
+        availability::verify(directory, &remote, None)
+    }
+
+    /// `retrieve --method move`; `listen` is where the C-STORE sub-operations arrive, under
+    /// the calling AE title.
+    pub fn retrieve(
+        &self,
+        addr: &str,
+        listen: &str,
+        target: &RetrieveTarget,
+        output: &Path,
+    ) -> Result<RetrieveSummary> {
+        let options = self.retrieve_options(output);
+        scu::retrieve(addr, listen, options, target, &NoProgress, None)
+    }
+
+    /// `retrieve --method get`
+    pub fn get(
+        &self,
+        addr: &str,
+        target: &RetrieveTarget,
+        output: &Path,
+    ) -> Result<RetrieveSummary> {
+        scu::get(
+            addr,
+            &self.retrieve_options(output),
+            target,
+            &NoProgress,
+            None,
+        )
+    }
+
+    fn retrieve_options(&self, output: &Path) -> RetrieveOptions {
+        RetrieveOptions {
+            association: self.association.clone(),
+            output: output.to_path_buf(),
+        }
+    }
+
+    /// `scp`: serves `directory` to C-FIND, C-MOVE and C-GET; with a store configured,
+    /// incoming C-STOREs are saved to it. Runs until the process exits.
+    pub fn serve_scp(&self, addr: &str, ae_title: &str, directory: &Path) -> Result<()> {
+        let config = ScpConfig {
+            ae_title: ae_title.to_string(),
+            root: directory.to_path_buf(),
+            destinations: AeMap::default(),
+            trace: None,
+            store: self.store.clone(),
+            router: None,
+            worklist: None,
+            mpps: None,
+            audit: None,
+        };
+        crate::scp::run(addr, config)
+    }
+
+    /// `web`: serves the configured store with the header sanity screen, default worker
+    /// limits, no upload pipeline and no push destinations. The decode cache moves into the
+    /// server.
+    pub async fn serve_web(self, host: &str, port: u16) -> Result<()> {
+        let Some(store) = self.store else {
+            bail!("The web server needs a store; configure one with with_store");
+        };
+        web::start_server(
+            host,
+            port,
+            store,
+            Arc::new(DicomSanityScreen::default()),
+            WorkerLimits::default(),
+            self.previews,
+            ShareSigner::random(),
+            UploadPipeline::default(),
+            AeMap::default(),
+        )
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verbs_share_the_configured_profile_and_decode_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let toolkit = DicomToolkit::new();
+        let files = toolkit
+            .synth(&synth::SynthSpec::default(), &dir.path().join("series"))
+            .unwrap();
+
+        let anon = dir.path().join("anon.dcm");
+        toolkit.anonymize(&files[0], &anon).unwrap();
+        let anonymized = toolkit.info(&anon).unwrap();
+        assert_eq!(
+            anonymized.patient.get("Name").map(String::as_str),
+            Some("ANONYMOUS^PATIENT")
+        );
+
+        let (png, outcome) = toolkit.preview(&anon, 0, None, Some(32)).unwrap();
+        assert_eq!(outcome, CacheOutcome::Rendered);
+        let copy = dir.path().join("copy.dcm");
+        fs::copy(&anon, &copy).unwrap();
+        let (cached, outcome) = toolkit.preview(&copy, 0, None, Some(32)).unwrap();
+        assert_eq!(outcome, CacheOutcome::Memory);
+        assert_eq!(png, cached);
+
+        assert_eq!(
+            toolkit.tag_keyword(Tag(0x0010, 0x0010)),
+            Some("PatientName")
+        );
+        assert!(toolkit.validate(&anon).parsed);
+    }
+}
@@ -2,19 +2,56 @@
 // transcode.rs
 // Dicom-Tools-rs
 //
-// Transcodes DICOM files to uncompressed transfer syntaxes while preserving raw pixel meaning.
+// Transcodes DICOM files to uncompressed transfer syntaxes while preserving raw pixel meaning,
+// or to JPEG Baseline while recording the lossy compression history.
 //
 // Thales Matheus Mendonça Santos - November 2025
 
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use dicom::core::{DataElement, PrimitiveValue, Tag, VR};
-use dicom::object::open_file;
+use dicom::encoding::adapters::EncodeOptions;
+use dicom::encoding::{TransferSyntax, TransferSyntaxIndex};
+use dicom::object::DefaultDicomObject;
 use dicom::pixeldata::PixelDecoder;
-use dicom::transfer_syntax::entries::{EXPLICIT_VR_LITTLE_ENDIAN, IMPLICIT_VR_LITTLE_ENDIAN};
-use dicom_pixeldata::{ConvertOptions, ModalityLutOption, VoiLutOption};
+use dicom::transfer_syntax::entries::{
+    EXPLICIT_VR_LITTLE_ENDIAN, IMPLICIT_VR_LITTLE_ENDIAN, JPEG_BASELINE,
+};
+use dicom::transfer_syntax::TransferSyntaxRegistry;
+use dicom_pixeldata::{ConvertOptions, ModalityLutOption, Transcode, VoiLutOption};
 use std::borrow::Cow;
 use std::path::Path;
 
+use crate::atomic_file;
+use crate::derivation::{self, Derivation, DerivationPolicy, SourceImage};
+use crate::dicom_access::{open_dicom, ElementAccess};
+use crate::progress::{NoProgress, ProgressEvent, ProgressSink};
+
+const PIXEL_DATA: Tag = Tag(0x7FE0, 0x0010);
+const LOSSY_IMAGE_COMPRESSION: Tag = Tag(0x0028, 0x2110);
+const LOSSY_IMAGE_COMPRESSION_RATIO: Tag = Tag(0x0028, 0x2112);
+const LOSSY_IMAGE_COMPRESSION_METHOD: Tag = Tag(0x0028, 0x2114);
+
+/// Transfer syntaxes whose encoding discards information, so an image stored in one of
+/// them is lossy even without Lossy Image Compression set.
+const LOSSY_TRANSFER_SYNTAXES: [&str; 16] = [
+    "1.2.840.10008.1.2.4.50",
+    "1.2.840.10008.1.2.4.51",
+    "1.2.840.10008.1.2.4.81",
+    "1.2.840.10008.1.2.4.91",
+    "1.2.840.10008.1.2.4.93",
+    "1.2.840.10008.1.2.4.100",
+    "1.2.840.10008.1.2.4.101",
+    "1.2.840.10008.1.2.4.102",
+    "1.2.840.10008.1.2.4.103",
+    "1.2.840.10008.1.2.4.104",
+    "1.2.840.10008.1.2.4.105",
+    "1.2.840.10008.1.2.4.106",
+    "1.2.840.10008.1.2.4.107",
+    "1.2.840.10008.1.2.4.108",
+    "1.2.840.10008.1.2.4.112",
+    "1.2.840.10008.1.2.4.203",
+];
+
 /// Supported uncompressed transfer syntaxes for transcoding.
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub enum UncompressedTransferSyntax {
@@ -23,7 +60,7 @@ pub enum UncompressedTransferSyntax {
 }
 
 impl UncompressedTransferSyntax {
-    fn uid(self) -> &'static str {
+    pub fn uid(self) -> &'static str {
         match self {
             UncompressedTransferSyntax::ExplicitVRLittleEndian => EXPLICIT_VR_LITTLE_ENDIAN.uid(),
             UncompressedTransferSyntax::ImplicitVRLittleEndian => IMPLICIT_VR_LITTLE_ENDIAN.uid(),
@@ -31,9 +68,245 @@ impl UncompressedTransferSyntax {
     }
 }
 
+/// Lossy transfer syntaxes the encoder can produce.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum LossyTransferSyntax {
+    JpegBaseline,
+}
+
+impl LossyTransferSyntax {
+    fn transfer_syntax(self) -> TransferSyntax {
+        match self {
+            LossyTransferSyntax::JpegBaseline => JPEG_BASELINE.erased(),
+        }
+    }
+
+    /// Defined term for Lossy Image Compression Method (PS3.3 C.7.6.1.1.5.1).
+    fn method(self) -> &'static str {
+        match self {
+            LossyTransferSyntax::JpegBaseline => "ISO_10918_1",
+        }
+    }
+}
+
+/// Options for [`transcode_lossy`].
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct LossyOptions {
+    /// Encoder quality from 1 to 100.
+    pub quality: u8,
+    /// Compress again even if the image has already been lossy compressed.
+    pub force: bool,
+    /// Provenance recorded on the compressed copy.
+    pub derivation: DerivationPolicy,
+}
+
+impl Default for LossyOptions {
+    fn default() -> Self {
+        Self {
+            quality: 85,
+            force: false,
+            derivation: DerivationPolicy::Preserve,
+        }
+    }
+}
+
+/// Lossy compression an image has been through, one ratio/method per step, oldest first.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LossyHistory {
+    pub lossy: bool,
+    pub ratios: Vec<String>,
+    pub methods: Vec<String>,
+}
+
+/// Read the lossy compression history from the attributes and the transfer syntax.
+pub fn lossy_history(obj: &DefaultDicomObject) -> LossyHistory {
+    let values = |tag: Tag| -> Vec<String> {
+        obj.element_str(tag)
+            .map(|s| {
+                s.split('\\')
+                    .map(|v| v.trim_matches(['\0', ' ']).to_string())
+                    .filter(|v| !v.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default()
+    };
+    let flagged = obj
+        .element_str(LOSSY_IMAGE_COMPRESSION)
+        .is_some_and(|v| v.trim_matches(['\0', ' ']) == "01");
+    let ts = obj.meta().transfer_syntax().trim_end_matches('\0');
+    LossyHistory {
+        lossy: flagged || LOSSY_TRANSFER_SYNTAXES.contains(&ts),
+        ratios: values(LOSSY_IMAGE_COMPRESSION_RATIO),
+        methods: values(LOSSY_IMAGE_COMPRESSION_METHOD),
+    }
+}
+
+/// Lossy compress `obj` in place and append this step to its compression history.
+/// Refuses an image that is already lossy unless `options.force` is set, since every
+/// further lossy step compounds the loss.
+pub fn compress_lossy(
+    obj: &mut DefaultDicomObject,
+    target: LossyTransferSyntax,
+    options: &LossyOptions,
+) -> Result<LossyHistory> {
+    let mut history = lossy_history(obj);
+    if history.lossy && !options.force {
+        bail!(
+            "Image is already lossy compressed{}; use --force to compress it again",
+            if history.methods.is_empty() {
+                String::new()
+            } else {
+                format!(" ({})", history.methods.join(", "))
+            }
+        );
+    }
+
+    let source = SourceImage::of(obj);
+    let decoded = obj
+        .decode_pixel_data()
+        .context("Failed to decode pixel data")?;
+    let original_bytes = decoded.rows() as u64
+        * decoded.columns() as u64
+        * decoded.samples_per_pixel() as u64
+        * decoded.number_of_frames() as u64
+        * decoded.bits_allocated().div_ceil(8) as u64;
+    drop(decoded);
+
+    let mut encode_options = EncodeOptions::new();
+    encode_options.quality = Some(options.quality.clamp(1, 100));
+    obj.transcode_with_options(&target.transfer_syntax(), encode_options)
+        .context("Failed to encode pixel data")?;
+
+    let compressed_bytes: u64 = obj
+        .element(PIXEL_DATA)?
+        .fragments()
+        .map(|fragments| fragments.iter().map(|f| f.len() as u64).sum())
+        .unwrap_or_default();
+    let ratio = original_bytes as f64 / compressed_bytes.max(1) as f64;
+
+    // The encoder records its own per-frame ratios; replace them with one entry per step.
+    history.lossy = true;
+    history.ratios.push(format!("{:.2}", ratio));
+    history.methods.push(target.method().to_string());
+    obj.put(DataElement::new(
+        LOSSY_IMAGE_COMPRESSION,
+        VR::CS,
+        PrimitiveValue::from("01"),
+    ));
+    obj.put(DataElement::new(
+        LOSSY_IMAGE_COMPRESSION_RATIO,
+        VR::DS,
+        PrimitiveValue::Strs(history.ratios.clone().into()),
+    ));
+    obj.put(DataElement::new(
+        LOSSY_IMAGE_COMPRESSION_METHOD,
+        VR::CS,
+        PrimitiveValue::Strs(history.methods.clone().into()),
+    ));
+    derivation::apply(
+        obj,
+        &source,
+        Derivation::LossyCompression,
+        options.derivation,
+    );
+    Ok(history)
+}
+
+/// Re-encode `obj` in memory as `target_uid`, e.g. for a transfer syntax negotiated on an
+/// association. JPEG Baseline goes through [`compress_lossy`] so the step is recorded;
+/// decoding a lossy image keeps it flagged as lossy.
+pub fn transcode_object(
+    obj: &mut DefaultDicomObject,
+    target_uid: &str,
+    lossy: &LossyOptions,
+) -> Result<()> {
+    if obj.meta().transfer_syntax().trim_end_matches('\0') == target_uid {
+        return Ok(());
+    }
+    if target_uid == JPEG_BASELINE.uid() {
+        compress_lossy(obj, LossyTransferSyntax::JpegBaseline, lossy)?;
+        return Ok(());
+    }
+    let was_lossy = lossy_history(obj).lossy;
+    let target = TransferSyntaxRegistry
+        .get(target_uid)
+        .with_context(|| format!("Transfer syntax {} not supported", target_uid))?;
+    obj.transcode(target)
+        .with_context(|| format!("Failed to transcode to {}", target.name()))?;
+    if was_lossy {
+        obj.put(DataElement::new(
+            LOSSY_IMAGE_COMPRESSION,
+            VR::CS,
+            PrimitiveValue::from("01"),
+        ));
+    }
+    Ok(())
+}
+
+/// Transcode a DICOM file to a lossy transfer syntax, recording the compression history.
+pub fn transcode_lossy(
+    input: &Path,
+    output: &Path,
+    target: LossyTransferSyntax,
+    options: &LossyOptions,
+) -> Result<()> {
+    let mut obj = open_dicom(input).context("Failed to open DICOM file")?;
+    let history = compress_lossy(&mut obj, target, options)?;
+    atomic_file::write_dicom(output, &obj).context("Failed to write output file")?;
+    println!(
+        "Transcoded to {} (ratio {}:1): {:?}",
+        target.transfer_syntax().uid(),
+        history.ratios.last().map(String::as_str).unwrap_or("?"),
+        output
+    );
+    Ok(())
+}
+
+/// Phases reported by [`transcode_with_progress`], in order.
+const PHASES: [&str; 4] = ["decode", "convert", "encode", "write"];
+
 /// Transcode a DICOM file to an uncompressed transfer syntax (explicit or implicit VR LE).
 pub fn transcode(input: &Path, output: &Path, target_ts: UncompressedTransferSyntax) -> Result<()> {
-    let obj = open_file(input).context("Failed to open DICOM file")?;
+    transcode_with_progress(input, output, target_ts, &NoProgress)
+}
+
+/// Same as [`transcode`], reporting each phase to `progress`.
+pub fn transcode_with_progress(
+    input: &Path,
+    output: &Path,
+    target_ts: UncompressedTransferSyntax,
+    progress: &dyn ProgressSink,
+) -> Result<()> {
+    transcode_with_derivation(
+        input,
+        output,
+        target_ts,
+        DerivationPolicy::Preserve,
+        progress,
+    )
+}
+
+/// Same as [`transcode_with_progress`], recording provenance on the output as
+/// `derivation` asks for. A lossless transcode keeps the pixel values, so the default
+/// of preserving the SOP Instance UID is what the standard expects.
+pub fn transcode_with_derivation(
+    input: &Path,
+    output: &Path,
+    target_ts: UncompressedTransferSyntax,
+    derivation: DerivationPolicy,
+    progress: &dyn ProgressSink,
+) -> Result<()> {
+    let total = PHASES.len() as u64;
+    let item = input.display().to_string();
+    let report = |step: usize| {
+        progress
+            .report(ProgressEvent::new(PHASES[step], step as u64, total).with_item(item.clone()))
+    };
+
+    report(0);
+    let obj = open_dicom(input).context("Failed to open DICOM file")?;
+    let was_lossy = lossy_history(&obj).lossy;
+    let source = SourceImage::of(&obj);
 
     // 1. Decode Pixel Data.
     //    We rely on dicom-pixeldata to decompress any encapsulated streams for us.
@@ -41,6 +314,7 @@ pub fn transcode(input: &Path, output: &Path, target_ts: UncompressedTransferSyn
         .decode_pixel_data()
         .context("Failed to decode pixel data")?;
 
+    report(1);
     // 2. Get raw bytes (native) without applying LUTs.
     //    This avoids altering pixel meaning while changing transfer syntax.
     let convert_options = ConvertOptions::new()
@@ -64,6 +338,7 @@ pub fn transcode(input: &Path, output: &Path, target_ts: UncompressedTransferSyn
     // Release borrow on obj so we can consume it.
     drop(decoded);
 
+    report(2);
     // 3. Reconstruct object.
     let mut new_obj = obj.into_inner(); // Unwrap the FileDicomObject to get InMemDicomObject
 
@@ -78,6 +353,15 @@ pub fn transcode(input: &Path, output: &Path, target_ts: UncompressedTransferSyn
         PrimitiveValue::from(pixel_bytes),
     ));
 
+    // Decompressing does not undo earlier loss; keep it on record (PS3.3 C.7.6.1.1.5).
+    if was_lossy {
+        new_obj.put(DataElement::new(
+            LOSSY_IMAGE_COMPRESSION,
+            VR::CS,
+            PrimitiveValue::from("01"),
+        ));
+    }
+
     // 4. Save with new Transfer Syntax and regenerated file meta.
 
     use dicom::object::FileDicomObject;
@@ -109,10 +393,11 @@ pub fn transcode(input: &Path, output: &Path, target_ts: UncompressedTransferSyn
     for elem in new_obj {
         file_obj.put(elem);
     }
+    derivation::apply(&mut file_obj, &source, Derivation::Transcode, derivation);
 
-    file_obj
-        .write_to_file(output)
-        .context("Failed to write output file")?;
+    report(3);
+    atomic_file::write_dicom(output, &file_obj).context("Failed to write output file")?;
+    progress.report(ProgressEvent::new("done", total, total).with_item(item));
     println!("Transcoded to {}: {:?}", target_ts.uid(), output);
 
     Ok(())
@@ -0,0 +1,187 @@
+//
+// transfer_limits.rs
+// Dicom-Tools-rs
+//
+// Bandwidth cap and daily time window for bulk transfers, so migrations can run unattended
+// without saturating clinical network links during working hours.
+//
+// Thales Matheus Mendonça Santos - November 2025
+
+use std::fmt;
+use std::str::FromStr;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Context, Result};
+use chrono::{Local, NaiveTime, Timelike};
+
+/// Paces a transfer to an average number of bytes per second.
+#[derive(Debug)]
+pub struct Throttle {
+    bytes_per_sec: f64,
+    started: Instant,
+    sent: u64,
+}
+
+impl Throttle {
+    pub fn new(megabytes_per_sec: f64) -> Result<Self> {
+        if !(megabytes_per_sec.is_finite() && megabytes_per_sec > 0.0) {
+            bail!("Rate limit must be a positive number of MB/s");
+        }
+        Ok(Self {
+            bytes_per_sec: megabytes_per_sec * 1024.0 * 1024.0,
+            started: Instant::now(),
+            sent: 0,
+        })
+    }
+
+    /// Account for `bytes` just sent, sleeping until the average rate is back under the cap.
+    pub fn pace(&mut self, bytes: usize) {
+        self.sent += bytes as u64;
+        if let Some(delay) = delay_for(self.sent, self.bytes_per_sec, self.started.elapsed()) {
+            thread::sleep(delay);
+        }
+    }
+
+    /// Start averaging afresh, e.g. after waiting for a transfer window, so the idle time is
+    /// not spent as a burst.
+    pub fn restart(&mut self) {
+        self.started = Instant::now();
+        self.sent = 0;
+    }
+}
+
+/// How long to wait so that `sent` bytes over `elapsed` stay within `bytes_per_sec`.
+fn delay_for(sent: u64, bytes_per_sec: f64, elapsed: Duration) -> Option<Duration> {
+    let due = Duration::from_secs_f64(sent as f64 / bytes_per_sec);
+    due.checked_sub(elapsed).filter(|delay| !delay.is_zero())
+}
+
+/// Daily window (`HH:MM-HH:MM`, local time) in which transfers may run. A window whose end is
+/// before its start spans midnight; equal ends mean all day.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TransferWindow {
+    start: NaiveTime,
+    end: NaiveTime,
+}
+
+impl FromStr for TransferWindow {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (start, end) = s
+            .split_once('-')
+            .with_context(|| format!("Expected HH:MM-HH:MM, got '{}'", s))?;
+        let time = |text: &str| {
+            NaiveTime::parse_from_str(text.trim(), "%H:%M")
+                .with_context(|| format!("Invalid time '{}' in window '{}'", text.trim(), s))
+        };
+        Ok(Self {
+            start: time(start)?,
+            end: time(end)?,
+        })
+    }
+}
+
+impl fmt::Display for TransferWindow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}-{}",
+            self.start.format("%H:%M"),
+            self.end.format("%H:%M")
+        )
+    }
+}
+
+impl TransferWindow {
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        match self.start.cmp(&self.end) {
+            std::cmp::Ordering::Equal => true,
+            std::cmp::Ordering::Less => self.start <= time && time < self.end,
+            std::cmp::Ordering::Greater => time >= self.start || time < self.end,
+        }
+    }
+
+    /// Time left until the window opens, or `None` when `now` is inside it.
+    pub fn until_open(&self, now: NaiveTime) -> Option<Duration> {
+        if self.contains(now) {
+            return None;
+        }
+        let seconds = |t: NaiveTime| i64::from(t.num_seconds_from_midnight());
+        let wait = (seconds(self.start) - seconds(now)).rem_euclid(24 * 3600);
+        Some(Duration::from_secs(wait as u64))
+    }
+
+    /// Block until the window is open in local time. Returns whether it had to wait.
+    pub fn wait_until_open(&self) -> bool {
+        let Some(wait) = self.until_open(Local::now().time()) else {
+            return false;
+        };
+        println!(
+            "Outside transfer window {}, waiting {} min",
+            self,
+            wait.as_secs().div_ceil(60)
+        );
+        // Sleep in slices so clock changes (e.g. DST) are noticed.
+        while let Some(wait) = self.until_open(Local::now().time()) {
+            thread::sleep(
+                wait.min(Duration::from_secs(60))
+                    .max(Duration::from_secs(1)),
+            );
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(hour: u32, minute: u32) -> NaiveTime {
+        NaiveTime::from_hms_opt(hour, minute, 0).unwrap()
+    }
+
+    #[test]
+    fn windows_may_span_midnight() {
+        let night: TransferWindow = "22:00-06:00".parse().unwrap();
+        assert_eq!(night.to_string(), "22:00-06:00");
+        assert!(night.contains(at(23, 30)));
+        assert!(night.contains(at(5, 59)));
+        assert!(!night.contains(at(6, 0)));
+        assert_eq!(night.until_open(at(1, 0)), None);
+        assert_eq!(
+            night.until_open(at(21, 30)),
+            Some(Duration::from_secs(30 * 60))
+        );
+        assert_eq!(
+            night.until_open(at(6, 0)),
+            Some(Duration::from_secs(16 * 3600))
+        );
+
+        let lunch: TransferWindow = "12:00-13:30".parse().unwrap();
+        assert!(lunch.contains(at(12, 45)));
+        assert_eq!(
+            lunch.until_open(at(14, 0)),
+            Some(Duration::from_secs(22 * 3600))
+        );
+        assert!("00:00-00:00"
+            .parse::<TransferWindow>()
+            .unwrap()
+            .contains(at(9, 0)));
+        assert!("22:00".parse::<TransferWindow>().is_err());
+        assert!("25:00-06:00".parse::<TransferWindow>().is_err());
+    }
+
+    #[test]
+    fn throttle_delays_until_the_average_rate_fits() {
+        let mb = 1024 * 1024;
+        assert_eq!(
+            delay_for(2 * mb, mb as f64, Duration::from_millis(500)),
+            Some(Duration::from_millis(1500))
+        );
+        assert_eq!(delay_for(mb, mb as f64, Duration::from_secs(2)), None);
+        assert!(Throttle::new(0.0).is_err());
+        assert!(Throttle::new(f64::NAN).is_err());
+    }
+}
@@ -0,0 +1,161 @@
+//
+// ts_preference.rs
+// Dicom-Tools-rs
+//
+// Ordered transfer syntax preference for outgoing C-STOREs: which encodings to propose, most
+// wanted first, and which of them can be produced from an instance's stored encoding.
+//
+// Thales Matheus Mendonça Santos - November 2025
+
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::{bail, Result};
+use dicom::encoding::transfer_syntax::Codec;
+use dicom::encoding::TransferSyntaxIndex;
+use dicom::transfer_syntax::TransferSyntaxRegistry;
+
+use crate::dimse::{EXPLICIT_VR_LITTLE_ENDIAN, IMPLICIT_VR_LITTLE_ENDIAN};
+
+/// Short names accepted next to UIDs.
+const ALIASES: [(&str, &str); 12] = [
+    ("implicit", "1.2.840.10008.1.2"),
+    ("explicit", "1.2.840.10008.1.2.1"),
+    ("evrle", "1.2.840.10008.1.2.1"),
+    ("deflate", "1.2.840.10008.1.2.1.99"),
+    ("jpeg-baseline", "1.2.840.10008.1.2.4.50"),
+    ("jpeg-lossless", "1.2.840.10008.1.2.4.70"),
+    ("jpeg-ls", "1.2.840.10008.1.2.4.80"),
+    ("jpeg-ls-near", "1.2.840.10008.1.2.4.81"),
+    ("j2k-lossless", "1.2.840.10008.1.2.4.90"),
+    ("j2k", "1.2.840.10008.1.2.4.91"),
+    ("rle", "1.2.840.10008.1.2.5"),
+    ("htj2k-lossless", "1.2.840.10008.1.2.4.201"),
+];
+
+/// Transfer syntaxes to propose for C-STORE, most wanted first. Empty means each instance is
+/// offered in its stored encoding with the native ones as fallbacks.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TransferSyntaxPreference {
+    uids: Vec<String>,
+}
+
+impl FromStr for TransferSyntaxPreference {
+    type Err = anyhow::Error;
+
+    /// Comma-separated UIDs or aliases, e.g. `jpeg-ls,j2k,explicit`.
+    fn from_str(s: &str) -> Result<Self> {
+        let mut uids: Vec<String> = Vec::new();
+        for entry in s.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let uid = match ALIASES
+                .iter()
+                .find(|(alias, _)| alias.eq_ignore_ascii_case(entry))
+            {
+                Some((_, uid)) => *uid,
+                None if TransferSyntaxRegistry.get(entry).is_some() => entry,
+                None => bail!(
+                    "Unknown transfer syntax '{}' (use a UID or one of {})",
+                    entry,
+                    ALIASES.map(|(alias, _)| alias).join(", ")
+                ),
+            };
+            if !uids.iter().any(|known| known == uid) {
+                uids.push(uid.to_string());
+            }
+        }
+        if uids.is_empty() {
+            bail!("Empty transfer syntax preference");
+        }
+        Ok(Self { uids })
+    }
+}
+
+impl fmt::Display for TransferSyntaxPreference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.uids.join(","))
+    }
+}
+
+impl TransferSyntaxPreference {
+    pub fn is_empty(&self) -> bool {
+        self.uids.is_empty()
+    }
+
+    pub fn uids(&self) -> &[String] {
+        &self.uids
+    }
+
+    /// Encodings to offer for an instance stored as `stored`, best first: the preferred ones
+    /// that can be produced from it, then the stored one and the native ones.
+    pub fn candidates(&self, stored: &str) -> Vec<String> {
+        let mut candidates: Vec<String> = Vec::new();
+        let fallbacks = [stored, EXPLICIT_VR_LITTLE_ENDIAN, IMPLICIT_VR_LITTLE_ENDIAN];
+        for uid in self
+            .uids
+            .iter()
+            .map(String::as_str)
+            .chain(fallbacks)
+            .filter(|uid| can_produce(stored, uid))
+        {
+            if !candidates.iter().any(|known| known == uid) {
+                candidates.push(uid.to_string());
+            }
+        }
+        candidates
+    }
+}
+
+/// Whether an instance stored as `stored` can be sent as `target` with the codecs built in:
+/// unchanged, between native encodings, decoded to a native one, or re-encoded by a pixel
+/// data encoder.
+pub fn can_produce(stored: &str, target: &str) -> bool {
+    if stored == target {
+        return true;
+    }
+    let (Some(source), Some(target)) = (
+        TransferSyntaxRegistry.get(stored),
+        TransferSyntaxRegistry.get(target),
+    ) else {
+        return false;
+    };
+    let decodable = source.is_codec_free()
+        || matches!(source.codec(), Codec::EncapsulatedPixelData(Some(_), _));
+    let encodable = target.is_codec_free()
+        || matches!(target.codec(), Codec::EncapsulatedPixelData(_, Some(_)));
+    decodable && encodable
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const JPEG_BASELINE: &str = "1.2.840.10008.1.2.4.50";
+    const JPEG_LS: &str = "1.2.840.10008.1.2.4.80";
+
+    #[test]
+    fn candidates_keep_the_order_and_skip_what_cannot_be_produced() {
+        let preference: TransferSyntaxPreference =
+            "jpeg-ls, jpeg-baseline,explicit".parse().unwrap();
+        assert_eq!(
+            preference.to_string(),
+            format!(
+                "{},{},{}",
+                JPEG_LS, JPEG_BASELINE, EXPLICIT_VR_LITTLE_ENDIAN
+            )
+        );
+        // No JPEG-LS encoder is built in, so a native instance is offered as JPEG Baseline
+        // first, while a JPEG-LS one can go out as stored.
+        assert_eq!(
+            preference.candidates(IMPLICIT_VR_LITTLE_ENDIAN),
+            vec![
+                JPEG_BASELINE,
+                EXPLICIT_VR_LITTLE_ENDIAN,
+                IMPLICIT_VR_LITTLE_ENDIAN
+            ]
+        );
+        assert_eq!(preference.candidates(JPEG_LS)[0], JPEG_LS);
+        assert!(can_produce(JPEG_BASELINE, EXPLICIT_VR_LITTLE_ENDIAN));
+        assert!("jpeg-xl".parse::<TransferSyntaxPreference>().is_err());
+        assert!(" , ".parse::<TransferSyntaxPreference>().is_err());
+    }
+}
@@ -0,0 +1,245 @@
+//
+// upload_pipeline.rs
+// Dicom-Tools-rs
+//
+// Steps the web server runs on every upload (`web --on-upload anonymize,validate,index,thumbnail`).
+// Each step works on the current file: once `anonymize` has run, the later steps see the
+// anonymized copy. A failing step is reported and the next ones still run.
+//
+// Thales Matheus Mendonça Santos - November 2025
+
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use chrono::Local;
+use dicom::core::Tag;
+use dicom::object::OpenFileOptions;
+use serde::Serialize;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+
+use crate::dicom_access::{open_dicom, ElementAccess};
+use crate::image::{self, PreviewFormat};
+use crate::preview_cache::{PreviewCache, PreviewKey};
+use crate::storage::FileStore;
+use crate::{anonymize, validate};
+
+const SOP_CLASS_UID: Tag = Tag(0x0008, 0x0016);
+const SOP_INSTANCE_UID: Tag = Tag(0x0008, 0x0018);
+const MODALITY: Tag = Tag(0x0008, 0x0060);
+const PATIENT_ID: Tag = Tag(0x0010, 0x0020);
+const STUDY_INSTANCE_UID: Tag = Tag(0x0020, 0x000D);
+const SERIES_INSTANCE_UID: Tag = Tag(0x0020, 0x000E);
+const PIXEL_DATA: Tag = Tag(0x7FE0, 0x0010);
+
+/// Edge of the thumbnails rendered on upload; the series strip of the web page asks for it.
+pub const THUMBNAIL_SIZE: u32 = 96;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UploadStep {
+    /// Store an anonymized copy (basic profile) that the following steps work on.
+    Anonymize,
+    /// Validate the current file.
+    Validate,
+    /// Append the identifiers of the current file to the JSON Lines index.
+    Index,
+    /// Render the thumbnail into the preview cache.
+    Thumbnail,
+}
+
+/// Steps to run, in order, and where `index` writes.
+#[derive(Debug, Clone, Default)]
+pub struct UploadPipeline {
+    pub steps: Vec<UploadStep>,
+    pub index: PathBuf,
+}
+
+/// Outcome of one step, as reported in the upload response.
+#[derive(Debug, Clone, Serialize)]
+pub struct StepResult {
+    pub step: UploadStep,
+    pub ok: bool,
+    /// Stored file the step ran on.
+    pub filename: String,
+    #[serde(skip_serializing_if = "Value::is_null")]
+    pub result: Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Serialize)]
+struct IndexEntry {
+    indexed_at: String,
+    filename: String,
+    modality: String,
+    patient_id: String,
+    study_instance_uid: String,
+    series_instance_uid: String,
+    sop_class_uid: String,
+    sop_instance_uid: String,
+}
+
+impl UploadPipeline {
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+
+    /// Run every step on the stored upload `filename`.
+    pub fn run(
+        &self,
+        store: &FileStore,
+        previews: &PreviewCache,
+        filename: &str,
+    ) -> Vec<StepResult> {
+        let mut current = filename.to_string();
+        let mut results = Vec::with_capacity(self.steps.len());
+        for &step in &self.steps {
+            let outcome = match step {
+                UploadStep::Anonymize => anonymized(store, &current).map(|(name, reused)| {
+                    let result = json!({ "filename": name, "reused": reused });
+                    (result, Some(name))
+                }),
+                UploadStep::Validate => store.resolve(&current).and_then(|path| {
+                    let obj = open_dicom(&path)?;
+                    let summary = validate::as_summary(&validate::validate_obj(&obj));
+                    Ok((serde_json::to_value(summary)?, None))
+                }),
+                UploadStep::Index => self
+                    .append_index_entry(store, &current)
+                    .map(|()| (json!({ "index": self.index }), None)),
+                UploadStep::Thumbnail => thumbnail(store, previews, &current)
+                    .map(|url| (json!({ "url": url, "size": THUMBNAIL_SIZE }), None)),
+            };
+            match outcome {
+                Ok((result, next)) => {
+                    results.push(StepResult {
+                        step,
+                        ok: true,
+                        filename: current.clone(),
+                        result,
+                        error: None,
+                    });
+                    if let Some(next) = next {
+                        current = next;
+                    }
+                }
+                Err(err) => results.push(StepResult {
+                    step,
+                    ok: false,
+                    filename: current.clone(),
+                    result: Value::Null,
+                    error: Some(format!("{:#}", err)),
+                }),
+            }
+        }
+        results
+    }
+
+    fn append_index_entry(&self, store: &FileStore, name: &str) -> Result<()> {
+        let path = store.resolve(name)?;
+        let obj = OpenFileOptions::new()
+            .read_until(PIXEL_DATA)
+            .open_file(&path)
+            .with_context(|| format!("Failed to open {}", name))?;
+        let text = |tag| {
+            obj.element_str(tag)
+                .unwrap_or_default()
+                .trim_end_matches(['\0', ' '])
+                .to_string()
+        };
+        let entry = IndexEntry {
+            indexed_at: Local::now().to_rfc3339(),
+            filename: name.to_string(),
+            modality: text(MODALITY),
+            patient_id: text(PATIENT_ID),
+            study_instance_uid: text(STUDY_INSTANCE_UID),
+            series_instance_uid: text(SERIES_INSTANCE_UID),
+            sop_class_uid: text(SOP_CLASS_UID),
+            sop_instance_uid: text(SOP_INSTANCE_UID),
+        };
+        let mut line = serde_json::to_string(&entry)?;
+        line.push('\n');
+        if let Some(parent) = self.index.parent().filter(|p| !p.as_os_str().is_empty()) {
+            fs::create_dir_all(parent).with_context(|| format!("Failed to create {:?}", parent))?;
+        }
+        // One write per line, so concurrent uploads do not interleave.
+        fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.index)
+            .and_then(|mut file| file.write_all(line.as_bytes()))
+            .with_context(|| format!("Failed to append to index {:?}", self.index))
+    }
+}
+
+/// The anonymized derivative of `name`, made unless an earlier upload of the same content
+/// already has one; `true` when it was reused.
+fn anonymized(store: &FileStore, name: &str) -> Result<(String, bool)> {
+    if let Some(existing) = store.find_derived(name, "anon", "")? {
+        return Ok((existing, true));
+    }
+    let path = store.resolve(name)?;
+    let (anon_name, anon_path) = store.derived_path(name, "anon", "", "dcm")?;
+    anonymize::process_file(&path, Some(anon_path))?;
+    store.publish(&anon_name)?;
+    Ok((anon_name, false))
+}
+
+/// Render the thumbnail under the cache key `/api/image?size=96` and the series strip use.
+fn thumbnail(store: &FileStore, previews: &PreviewCache, name: &str) -> Result<String> {
+    let path = store.resolve(name)?;
+    let hash = match store.content_hash(name)? {
+        Some(hash) => hash,
+        None => hex::encode(Sha256::digest(fs::read(&path)?)),
+    };
+    let key = PreviewKey::new(hash, 0, None, Some(THUMBNAIL_SIZE), PreviewFormat::Png);
+    previews.get_or_render(&key, || {
+        image::preview_png_bytes(&path, 0, None, Some(THUMBNAIL_SIZE))
+    })?;
+    Ok(format!("/api/image/{}?size={}", name, THUMBNAIL_SIZE))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn later_steps_run_on_the_anonymized_copy() {
+        let source = tempfile::tempdir().unwrap();
+        let files =
+            crate::synth::write_series(&crate::synth::SynthSpec::default(), source.path()).unwrap();
+        let root = tempfile::tempdir().unwrap();
+        let store = FileStore::new(root.path().join("store")).unwrap();
+        let name = store
+            .save(Some("ct.dcm"), &fs::read(&files[0]).unwrap())
+            .unwrap();
+        let pipeline = UploadPipeline {
+            steps: vec![
+                UploadStep::Anonymize,
+                UploadStep::Validate,
+                UploadStep::Index,
+                UploadStep::Thumbnail,
+            ],
+            index: root.path().join("index.jsonl"),
+        };
+
+        let results = pipeline.run(&store, &PreviewCache::new(1 << 20), &name);
+        assert!(results.iter().all(|r| r.ok), "{:?}", results);
+        let anon = results[0].result["filename"].as_str().unwrap().to_string();
+        assert_ne!(anon, name);
+        assert!(results[1..].iter().all(|r| r.filename == anon));
+        let line = fs::read_to_string(&pipeline.index).unwrap();
+        assert!(line.contains(&format!("\"filename\":\"{}\"", anon)));
+        assert_eq!(
+            results[3].result["url"],
+            format!("/api/image/{}?size=96", anon)
+        );
+
+        // A second upload of the same content reuses the anonymized copy.
+        let again = pipeline.run(&store, &PreviewCache::new(1 << 20), &name);
+        assert_eq!(again[0].result["reused"], true);
+    }
+}
@@ -3,26 +3,73 @@
 // Dicom-Tools-rs
 //
 // Validates critical DICOM attributes and pixel presence, emitting summaries for CLI and API clients.
+// Directories are walked file by file, optionally as NDJSON findings for log aggregators.
 //
 // Thales Matheus Mendonça Santos - November 2025
 
-use std::path::Path;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
 
 use anyhow::{Context, Result};
+use dicom::core::value::Value;
 use dicom::core::Tag;
-use dicom::object::open_file;
+use dicom::encoding::{Codec, Endianness, TransferSyntaxIndex};
+use dicom::object::DefaultDicomObject;
+use dicom::transfer_syntax::entries::IMPLICIT_VR_LITTLE_ENDIAN;
+use dicom::transfer_syntax::TransferSyntaxRegistry;
 use serde::Serialize;
+use walkdir::WalkDir;
 
-use crate::dicom_access::ElementAccess;
+use crate::codes::{self, Code, CODE_SEQUENCES};
+use crate::dicom_access::{is_big_endian, ElementAccess};
+use crate::lenient::{observe_encoding, parse_lenient_bytes, ObservedEncoding, ParseAnomaly};
 use crate::models::ValidationSummary;
+use crate::{cine, consistency};
 
 #[derive(Debug, Clone, Serialize)]
 pub struct ValidationReport {
     pub valid: bool,
     pub missing_tags: Vec<String>,
+    /// Type 1C/2C requirements whose condition holds but which the object does not meet.
+    pub conditional_violations: Vec<String>,
+    /// Implausible demographics or date ordering; reported without failing validation.
+    pub inconsistencies: Vec<String>,
     pub has_pixel_data: bool,
 }
 
+const SOP_CLASS_UID: Tag = Tag(0x0008, 0x0016);
+const SOP_INSTANCE_UID: Tag = Tag(0x0008, 0x0018);
+const SAMPLES_PER_PIXEL: Tag = Tag(0x0028, 0x0002);
+const PHOTOMETRIC_INTERPRETATION: Tag = Tag(0x0028, 0x0004);
+const PLANAR_CONFIGURATION: Tag = Tag(0x0028, 0x0006);
+const NUMBER_OF_FRAMES: Tag = Tag(0x0028, 0x0008);
+const FRAME_INCREMENT_POINTER: Tag = Tag(0x0028, 0x0009);
+const RESCALE_INTERCEPT: Tag = Tag(0x0028, 0x1052);
+const RESCALE_SLOPE: Tag = Tag(0x0028, 0x1053);
+const ICON_IMAGE_SEQUENCE: Tag = Tag(0x0088, 0x0200);
+const SHARED_FUNCTIONAL_GROUPS_SEQUENCE: Tag = Tag(0x5200, 0x9229);
+const PER_FRAME_FUNCTIONAL_GROUPS_SEQUENCE: Tag = Tag(0x5200, 0x9230);
+const PIXEL_DATA: Tag = Tag(0x7fe0, 0x0010);
+
+/// Image Pixel module attributes that are Type 1 wherever Pixel Data is (PS3.3 C.7.6.3).
+const IMAGE_PIXEL_TYPE1: [(Tag, &str); 8] = [
+    (SAMPLES_PER_PIXEL, "Samples per Pixel"),
+    (PHOTOMETRIC_INTERPRETATION, "Photometric Interpretation"),
+    (Tag(0x0028, 0x0010), "Rows"),
+    (Tag(0x0028, 0x0011), "Columns"),
+    (Tag(0x0028, 0x0100), "Bits Allocated"),
+    (Tag(0x0028, 0x0101), "Bits Stored"),
+    (Tag(0x0028, 0x0102), "High Bit"),
+    (Tag(0x0028, 0x0103), "Pixel Representation"),
+];
+
+/// Palette descriptors that become Type 1 under PALETTE COLOR.
+const PALETTE_DESCRIPTORS: [(Tag, &str); 3] = [
+    (Tag(0x0028, 0x1101), "Red Palette Color LUT Descriptor"),
+    (Tag(0x0028, 0x1102), "Green Palette Color LUT Descriptor"),
+    (Tag(0x0028, 0x1103), "Blue Palette Color LUT Descriptor"),
+];
+
 /// Validates a DICOM object in memory.
 pub fn validate_obj<T: ElementAccess>(obj: &T) -> ValidationReport {
     // Core attributes pulled from PS3.3 C.7.2.1 plus pixel presence.
@@ -43,12 +90,16 @@ pub fn validate_obj<T: ElementAccess>(obj: &T) -> ValidationReport {
         }
     }
 
-    let pixel_data_tag = Tag(0x7fe0, 0x0010);
-    let has_pixel_data = obj.has_element(pixel_data_tag);
+    let has_pixel_data = obj.has_element(PIXEL_DATA);
+
+    let mut conditional_violations = Vec::new();
+    check_conditions(obj, "", &mut conditional_violations);
 
     ValidationReport {
-        valid: missing_tags.is_empty(),
+        valid: missing_tags.is_empty() && conditional_violations.is_empty(),
         missing_tags,
+        conditional_violations,
+        inconsistencies: consistency::check(obj),
         has_pixel_data,
     }
 }
@@ -57,50 +108,420 @@ pub fn as_summary(report: &ValidationReport) -> ValidationSummary {
     ValidationSummary {
         valid: report.valid,
         missing_tags: report.missing_tags.clone(),
+        conditional_violations: report.conditional_violations.clone(),
+        inconsistencies: report.inconsistencies.clone(),
         has_pixel_data: report.has_pixel_data,
     }
 }
 
+/// Evaluates Type 1C/2C requirements on `obj`, then on the nested items that carry
+/// their own pixel modules; `scope` prefixes findings from inside a sequence.
+fn check_conditions<T: ElementAccess>(obj: &T, scope: &str, out: &mut Vec<String>) {
+    let mut violation = |message: String| out.push(format!("{}{}", scope, message));
+
+    // Image Pixel module: only required once there are pixels to describe.
+    if obj.has_element(PIXEL_DATA) {
+        for (tag, name) in IMAGE_PIXEL_TYPE1 {
+            if !has_value(obj, tag) {
+                violation(format!(
+                    "{} {} required when Pixel Data is present",
+                    name, tag
+                ));
+            }
+        }
+        if obj.element_u32(SAMPLES_PER_PIXEL).unwrap_or(1) > 1
+            && !has_value(obj, PLANAR_CONFIGURATION)
+        {
+            violation(format!(
+                "Planar Configuration {} required when Samples per Pixel > 1",
+                PLANAR_CONFIGURATION
+            ));
+        }
+        let photometric = obj.element_str(PHOTOMETRIC_INTERPRETATION);
+        if photometric.as_deref().map(str::trim) == Some("PALETTE COLOR") {
+            for (tag, name) in PALETTE_DESCRIPTORS {
+                if !has_value(obj, tag) {
+                    violation(format!("{} {} required for PALETTE COLOR", name, tag));
+                }
+            }
+        }
+    }
+
+    // Rescale Slope and Intercept are each Type 1C on the other (PS3.3 C.11.1).
+    for (present, present_name, required, required_name) in [
+        (
+            RESCALE_INTERCEPT,
+            "Rescale Intercept",
+            RESCALE_SLOPE,
+            "Rescale Slope",
+        ),
+        (
+            RESCALE_SLOPE,
+            "Rescale Slope",
+            RESCALE_INTERCEPT,
+            "Rescale Intercept",
+        ),
+    ] {
+        if obj.has_element(present) && !has_value(obj, required) {
+            violation(format!(
+                "{} {} required when {} is present",
+                required_name, required, present_name
+            ));
+        }
+    }
+
+    // Multi-frame: legacy objects locate frames through Frame Increment Pointer, while
+    // enhanced objects describe them with one functional group item per frame instead.
+    let frames = obj.element_u32(NUMBER_OF_FRAMES);
+    if obj.has_element(SHARED_FUNCTIONAL_GROUPS_SEQUENCE) {
+        let per_frame = obj
+            .sequence_items(PER_FRAME_FUNCTIONAL_GROUPS_SEQUENCE)
+            .len();
+        match frames {
+            Some(frames) if per_frame != frames as usize => violation(format!(
+                "Per-frame Functional Groups Sequence {} has {} item(s) for {} frame(s)",
+                PER_FRAME_FUNCTIONAL_GROUPS_SEQUENCE, per_frame, frames
+            )),
+            None => violation(format!(
+                "Number of Frames {} required with functional groups",
+                NUMBER_OF_FRAMES
+            )),
+            _ => {}
+        }
+    } else if frames.is_some_and(|frames| frames > 1) && !has_value(obj, FRAME_INCREMENT_POINTER) {
+        violation(format!(
+            "Frame Increment Pointer {} required when Number of Frames > 1",
+            FRAME_INCREMENT_POINTER
+        ));
+    }
+    for problem in cine::check(obj) {
+        violation(problem);
+    }
+
+    // Code Sequence items need a value, a scheme and a meaning (PS3.3 Table 8.8-1); the
+    // concept is named in the finding so the item can be found without a dump.
+    for (tag, name) in CODE_SEQUENCES {
+        for (index, item) in obj.sequence_items(tag).iter().enumerate() {
+            let missing = codes::missing_in_item(item);
+            if missing.is_empty() {
+                continue;
+            }
+            let concept = Code::from_item(item)
+                .map(|code| format!(" {}", code))
+                .unwrap_or_default();
+            violation(format!(
+                "{} {} item {}{}: {} required",
+                name,
+                tag,
+                index + 1,
+                concept,
+                missing.join(", ")
+            ));
+        }
+    }
+
+    // Icon images carry their own Image Pixel module inside the sequence item.
+    for (index, item) in obj.sequence_items(ICON_IMAGE_SEQUENCE).iter().enumerate() {
+        let item_scope = format!("{}Icon Image Sequence item {}: ", scope, index + 1);
+        if !item.has_element(PIXEL_DATA) {
+            out.push(format!("{}Pixel Data {} required", item_scope, PIXEL_DATA));
+        }
+        check_conditions(item, &item_scope, out);
+    }
+}
+
+/// Checks the file meta group (group 0002) against the dataset it describes: the SOP UIDs
+/// must repeat the dataset's, and the transfer syntax must match how the dataset and its
+/// pixel data are actually written. `observed` comes from [`observe_encoding`].
+pub fn check_file_meta(
+    obj: &DefaultDicomObject,
+    observed: Option<ObservedEncoding>,
+) -> Vec<String> {
+    let mut mismatches = Vec::new();
+    let meta = obj.meta();
+    let trim = |s: &str| s.trim_end_matches(['\0', ' ']).to_string();
+
+    for (meta_name, meta_tag, meta_value, name, tag) in [
+        (
+            "Media Storage SOP Class UID",
+            Tag(0x0002, 0x0002),
+            &meta.media_storage_sop_class_uid,
+            "SOP Class UID",
+            SOP_CLASS_UID,
+        ),
+        (
+            "Media Storage SOP Instance UID",
+            Tag(0x0002, 0x0003),
+            &meta.media_storage_sop_instance_uid,
+            "SOP Instance UID",
+            SOP_INSTANCE_UID,
+        ),
+    ] {
+        let meta_value = trim(meta_value);
+        // A dataset without the attribute is already reported as missing.
+        let Some(value) = obj.element_str(tag).map(|v| trim(&v)) else {
+            continue;
+        };
+        if meta_value != value {
+            mismatches.push(format!(
+                "{} {} \"{}\" does not match {} {} \"{}\"",
+                meta_name, meta_tag, meta_value, name, tag, value
+            ));
+        }
+    }
+
+    let uid = trim(meta.transfer_syntax());
+    let Some(ts) = TransferSyntaxRegistry.get(&uid) else {
+        mismatches.push(format!(
+            "Transfer Syntax UID (0002,0010) \"{}\" is not a known transfer syntax",
+            uid
+        ));
+        return mismatches;
+    };
+    // Deflated datasets are compressed as a whole; their bytes say nothing about the encoding.
+    if matches!(ts.codec(), Codec::Dataset(_)) {
+        return mismatches;
+    }
+
+    let declared = ObservedEncoding {
+        explicit_vr: uid != IMPLICIT_VR_LITTLE_ENDIAN.uid(),
+        big_endian: ts.endianness() == Endianness::Big,
+    };
+    if let Some(observed) = observed.filter(|observed| *observed != declared) {
+        mismatches.push(format!(
+            "Transfer Syntax UID (0002,0010) {} declares {} but the dataset is written in {}",
+            ts.name(),
+            declared,
+            observed
+        ));
+    }
+
+    if let Ok(pixel_data) = obj.element(PIXEL_DATA) {
+        let encapsulated = matches!(pixel_data.value(), Value::PixelSequence(_));
+        let expected = matches!(ts.codec(), Codec::EncapsulatedPixelData(..));
+        if encapsulated != expected {
+            mismatches.push(format!(
+                "Transfer Syntax UID (0002,0010) {} expects {} Pixel Data but it is {}",
+                ts.name(),
+                if expected { "encapsulated" } else { "native" },
+                if encapsulated {
+                    "encapsulated"
+                } else {
+                    "native"
+                }
+            ));
+        }
+    }
+
+    mismatches
+}
+
+/// Type 1 semantics: present with a non-empty value.
+fn has_value<T: ElementAccess>(obj: &T, tag: Tag) -> bool {
+    obj.has_element(tag)
+        && obj
+            .element_str(tag)
+            .is_none_or(|value| !value.trim_end_matches(['\0', ' ']).is_empty())
+}
+
 /// Validates if a file can be parsed as DICOM and prints a detailed summary.
 pub fn check_file(path: &Path) -> Result<()> {
     println!("Validating: {:?}", path);
-    let obj = open_file(path).context("Failed to open/parse DICOM file")?;
+    // Parse leniently so recoverable defects are listed instead of aborting the check.
+    let bytes = std::fs::read(path).context("Failed to read DICOM file")?;
+    let parsed = parse_lenient_bytes(&bytes);
+    for anomaly in &parsed.anomalies {
+        println!("[WARN] {}", anomaly);
+    }
+    let obj = parsed
+        .object
+        .as_ref()
+        .context("Failed to open/parse DICOM file")?;
     let meta = obj.meta();
 
     // Echo key meta info before running attribute-level checks.
-    println!("[OK] File Structure Parsed");
+    match parsed.truncated_at {
+        Some(offset) => println!(
+            "[WARN] File Structure Recovered (elements from byte {} on are missing)",
+            offset
+        ),
+        None => println!("[OK] File Structure Parsed"),
+    }
     println!("[OK] Transfer Syntax: {}", meta.transfer_syntax());
+    if is_big_endian(obj) {
+        println!("[WARN] Explicit VR Big Endian is retired; consider transcoding to little endian");
+    }
     println!(
         "[OK] Media Storage SOP Class: {}",
         meta.media_storage_sop_class_uid
     );
 
-    let report = validate_obj(&obj);
+    let meta_mismatches = check_file_meta(obj, observe_encoding(&bytes));
+    if meta_mismatches.is_empty() {
+        println!("[OK] File Meta Group consistent with dataset");
+    }
+    for mismatch in &meta_mismatches {
+        println!("[MISMATCH] {}", mismatch);
+    }
+
+    let report = validate_obj(obj);
 
     if report.has_pixel_data {
         println!("[OK] Pixel Data present");
     } else {
         println!("[WARN] No Pixel Data found");
     }
+    for inconsistency in &report.inconsistencies {
+        println!("[WARN] {}", inconsistency);
+    }
 
-    if report.valid {
+    if report.valid && meta_mismatches.is_empty() {
         println!("\nResult: VALID (All critical attributes found)");
     } else {
         println!(
-            "\nResult: INVALID ({} critical attributes missing)",
-            report.missing_tags.len()
+            "\nResult: INVALID ({} critical attributes missing, {} conditional requirements unmet, {} meta group mismatches)",
+            report.missing_tags.len(),
+            report.conditional_violations.len(),
+            meta_mismatches.len()
         );
         for missing in report.missing_tags {
             println!("[MISSING] {}", missing);
         }
+        for violation in report.conditional_violations {
+            println!("[CONDITION] {}", violation);
+        }
     }
 
     Ok(())
 }
 
+/// How `validate` reports its findings.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum ValidateOutput {
+    /// Human-readable report per file.
+    #[default]
+    Text,
+    /// One JSON object per line and file, flushed as soon as the file is checked.
+    Ndjson,
+}
+
+/// Findings for one file, flat enough to index as a single log document.
+#[derive(Debug, Clone, Serialize)]
+pub struct FileFinding {
+    /// RFC 3339 time the file was checked, under the field name log pipelines expect.
+    #[serde(rename = "@timestamp")]
+    pub timestamp: String,
+    pub path: PathBuf,
+    pub valid: bool,
+    /// Whether any dataset could be recovered from the file.
+    pub parsed: bool,
+    pub truncated_at: Option<u64>,
+    pub transfer_syntax: Option<String>,
+    pub sop_class_uid: Option<String>,
+    pub has_pixel_data: bool,
+    pub missing_tags: Vec<String>,
+    pub conditional_violations: Vec<String>,
+    pub inconsistencies: Vec<String>,
+    /// File meta group entries that contradict the dataset.
+    pub meta_mismatches: Vec<String>,
+    pub anomalies: Vec<ParseAnomaly>,
+    /// Set when the file could not be read at all.
+    pub error: Option<String>,
+}
+
+/// Checks one file without printing, never failing: unreadable files become findings too.
+pub fn inspect_file(path: &Path) -> FileFinding {
+    let mut finding = FileFinding {
+        timestamp: chrono::Utc::now().to_rfc3339(),
+        path: path.to_path_buf(),
+        valid: false,
+        parsed: false,
+        truncated_at: None,
+        transfer_syntax: None,
+        sop_class_uid: None,
+        has_pixel_data: false,
+        missing_tags: Vec::new(),
+        conditional_violations: Vec::new(),
+        inconsistencies: Vec::new(),
+        meta_mismatches: Vec::new(),
+        anomalies: Vec::new(),
+        error: None,
+    };
+    let bytes = match std::fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(err) => {
+            finding.error = Some(err.to_string());
+            return finding;
+        }
+    };
+    let parsed = parse_lenient_bytes(&bytes);
+    finding.truncated_at = parsed.truncated_at;
+    finding.anomalies = parsed.anomalies;
+    if let Some(obj) = &parsed.object {
+        let meta = obj.meta();
+        let report = validate_obj(obj);
+        finding.parsed = true;
+        finding.transfer_syntax = Some(meta.transfer_syntax().to_string());
+        finding.sop_class_uid = Some(
+            meta.media_storage_sop_class_uid
+                .trim_end_matches('\0')
+                .to_string(),
+        );
+        finding.meta_mismatches = check_file_meta(obj, observe_encoding(&bytes));
+        finding.valid = report.valid && finding.meta_mismatches.is_empty();
+        finding.has_pixel_data = report.has_pixel_data;
+        finding.missing_tags = report.missing_tags;
+        finding.conditional_violations = report.conditional_violations;
+        finding.inconsistencies = report.inconsistencies;
+    }
+    finding
+}
+
+/// Validates a file or every `.dcm` file under a directory, in path order.
+pub fn check_path(path: &Path, output: ValidateOutput) -> Result<()> {
+    let files: Vec<PathBuf> = if path.is_dir() {
+        let mut files: Vec<PathBuf> = WalkDir::new(path)
+            .into_iter()
+            .filter_map(|e| e.ok())
+            .filter(|e| e.path().extension().is_some_and(|ext| ext == "dcm"))
+            .map(|e| e.into_path())
+            .collect();
+        files.sort();
+        files
+    } else {
+        vec![path.to_path_buf()]
+    };
+
+    match output {
+        ValidateOutput::Text => {
+            for (index, file) in files.iter().enumerate() {
+                if index > 0 {
+                    println!();
+                }
+                // A bad file is reported and the walk goes on, as in `batch`.
+                if let Err(err) = check_file(file) {
+                    println!("[ERROR] {:#}", err);
+                }
+            }
+        }
+        ValidateOutput::Ndjson => {
+            let stdout = io::stdout();
+            let mut out = stdout.lock();
+            for file in &files {
+                serde_json::to_writer(&mut out, &inspect_file(file))?;
+                out.write_all(b"\n")?;
+                // Flush per file so a downstream shipper sees findings while the walk runs.
+                out.flush()?;
+            }
+        }
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use dicom::core::value::DataSetSequence;
     use dicom::core::{DataElement, PrimitiveValue, VR};
     use dicom::object::InMemDicomObject;
 
@@ -115,6 +536,134 @@ mod tests {
 
     #[test]
     fn test_validate_valid_object() {
+        let obj = valid_object();
+        let report = validate_obj(&obj);
+        assert!(
+            report.valid,
+            "Object should be valid, missing: {:?}",
+            report.missing_tags
+        );
+        assert!(!report.has_pixel_data);
+    }
+
+    #[test]
+    fn test_conditional_requirements_follow_their_conditions() {
+        let mut obj = valid_object();
+        // Pixel Data alone pulls in the Image Pixel module.
+        obj.put(DataElement::new(
+            PIXEL_DATA,
+            VR::OB,
+            PrimitiveValue::from(vec![0u8; 4]),
+        ));
+        let report = validate_obj(&obj);
+        assert!(!report.valid);
+        assert!(report.missing_tags.is_empty());
+        assert_eq!(report.conditional_violations.len(), IMAGE_PIXEL_TYPE1.len());
+
+        put_image_pixel(&mut obj);
+        assert!(validate_obj(&obj).valid);
+
+        // A legacy multi-frame needs Frame Increment Pointer.
+        obj.put(DataElement::new(
+            NUMBER_OF_FRAMES,
+            VR::IS,
+            PrimitiveValue::from("2"),
+        ));
+        let report = validate_obj(&obj);
+        assert_eq!(report.conditional_violations.len(), 1);
+        assert!(report.conditional_violations[0].contains("Frame Increment Pointer"));
+        obj.put(DataElement::new(
+            FRAME_INCREMENT_POINTER,
+            VR::AT,
+            PrimitiveValue::Tags(vec![Tag(0x0018, 0x1063)].into()),
+        ));
+        // ...and the attribute it points at.
+        let report = validate_obj(&obj);
+        assert_eq!(report.conditional_violations.len(), 1);
+        assert!(report.conditional_violations[0].contains("which is absent"));
+        obj.put(DataElement::new(
+            Tag(0x0018, 0x1063),
+            VR::DS,
+            PrimitiveValue::from("40"),
+        ));
+        assert!(validate_obj(&obj).valid);
+    }
+
+    #[test]
+    fn test_code_items_without_meaning_are_named_in_the_finding() {
+        let mut region = InMemDicomObject::new_empty();
+        for (tag, value) in [
+            (codes::CODE_VALUE, "T-D3000"),
+            (codes::CODING_SCHEME_DESIGNATOR, "SRT"),
+        ] {
+            region.put(DataElement::new(tag, VR::SH, PrimitiveValue::from(value)));
+        }
+        let mut obj = valid_object();
+        obj.put(DataElement::new(
+            Tag(0x0008, 0x2218),
+            VR::SQ,
+            DataSetSequence::from(vec![region]),
+        ));
+        let report = validate_obj(&obj);
+        assert_eq!(
+            report.conditional_violations,
+            vec![
+                "Anatomic Region Sequence (0008,2218) item 1 (T-D3000, SRT, \"Chest\"): \
+                 Code Meaning (0008,0104) required"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_icon_image_items_are_checked_in_their_own_scope() {
+        let mut icon = InMemDicomObject::new_empty();
+        icon.put(DataElement::new(
+            PIXEL_DATA,
+            VR::OB,
+            PrimitiveValue::from(vec![0u8; 4]),
+        ));
+        let mut obj = valid_object();
+        obj.put(DataElement::new(
+            ICON_IMAGE_SEQUENCE,
+            VR::SQ,
+            DataSetSequence::from(vec![icon.clone()]),
+        ));
+        let report = validate_obj(&obj);
+        assert!(!report.valid);
+        assert!(report
+            .conditional_violations
+            .iter()
+            .all(|v| v.starts_with("Icon Image Sequence item 1: ")));
+
+        put_image_pixel(&mut icon);
+        obj.put(DataElement::new(
+            ICON_IMAGE_SEQUENCE,
+            VR::SQ,
+            DataSetSequence::from(vec![icon]),
+        ));
+        assert!(validate_obj(&obj).valid);
+    }
+
+    fn put_image_pixel(obj: &mut InMemDicomObject) {
+        for (tag, value) in [
+            (SAMPLES_PER_PIXEL, 1u16),
+            (Tag(0x0028, 0x0010), 2),
+            (Tag(0x0028, 0x0011), 2),
+            (Tag(0x0028, 0x0100), 8),
+            (Tag(0x0028, 0x0101), 8),
+            (Tag(0x0028, 0x0102), 7),
+            (Tag(0x0028, 0x0103), 0),
+        ] {
+            obj.put(DataElement::new(tag, VR::US, PrimitiveValue::from(value)));
+        }
+        obj.put(DataElement::new(
+            PHOTOMETRIC_INTERPRETATION,
+            VR::CS,
+            PrimitiveValue::from("MONOCHROME2"),
+        ));
+    }
+
+    fn valid_object() -> InMemDicomObject {
         let mut obj = InMemDicomObject::new_empty();
 
         obj.put(DataElement::new(
@@ -147,13 +696,6 @@ mod tests {
             VR::CS,
             PrimitiveValue::from("CT"),
         ));
-
-        let report = validate_obj(&obj);
-        assert!(
-            report.valid,
-            "Object should be valid, missing: {:?}",
-            report.missing_tags
-        );
-        assert!(!report.has_pixel_data);
+        obj
     }
 }
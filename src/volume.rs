@@ -0,0 +1,744 @@
+//
+// volume.rs
+// Dicom-Tools-rs
+//
+// Stacks the slices of a series into a regular 3D volume along the slice normal and writes
+// it as NIfTI-1. Missing slices and irregular spacing are refused unless a resampling mode is
+// chosen, in which case the volume is resampled to the nominal spacing and every
+// interpolated slice is reported.
+//
+// Thales Matheus Mendonça Santos - November 2025
+
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use dicom::core::Tag;
+use dicom::object::OpenFileOptions;
+use dicom_pixeldata::{ConvertOptions, ModalityLutOption, PixelDecoder};
+use flate2::write::GzEncoder;
+use walkdir::WalkDir;
+
+use crate::atomic_file;
+use crate::dicom_access::{open_dicom, ElementAccess};
+use crate::measure::FrameGeometry;
+
+const SERIES_INSTANCE_UID: Tag = Tag(0x0020, 0x000E);
+const SLICE_THICKNESS: Tag = Tag(0x0018, 0x0050);
+const SPACING_BETWEEN_SLICES: Tag = Tag(0x0018, 0x0088);
+const PIXEL_DATA: Tag = Tag(0x7FE0, 0x0010);
+
+/// Spacing deviations up to this fraction of the nominal spacing are DS rounding, not gaps.
+const SPACING_TOLERANCE: f64 = 0.01;
+
+/// What to do when the slices are not evenly spaced (missing slices, irregular spacing).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Resampling {
+    /// Refuse to build the volume.
+    #[default]
+    None,
+    /// Fill each missing position with the closest acquired slice.
+    Nearest,
+    /// Interpolate each missing position linearly between its neighbours.
+    Linear,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct VolumeOptions {
+    /// Series to export when the directory holds several.
+    pub series: Option<String>,
+    pub resampling: Resampling,
+}
+
+/// One acquired slice: a frame and where it lies in patient space.
+#[derive(Debug, Clone)]
+pub struct Slice {
+    pub position: [f64; 3],
+    /// Modality values, row-major.
+    pub values: Vec<f32>,
+    /// File (and frame) the slice comes from, for messages.
+    pub source: String,
+    pub path: PathBuf,
+    /// 0-based frame of `path`.
+    pub frame: u32,
+}
+
+/// A regular volume in DICOM patient coordinates (LPS).
+#[derive(Debug, Clone)]
+pub struct Volume {
+    pub columns: usize,
+    pub rows: usize,
+    pub slices: usize,
+    /// Column, row and slice spacing (mm).
+    pub spacing: [f64; 3],
+    /// Patient position of the first voxel.
+    pub origin: [f64; 3],
+    /// Row then column direction cosines.
+    pub orientation: [f64; 6],
+    /// Modality values, slice after slice.
+    pub values: Vec<f32>,
+    /// Slices filled in by resampling rather than acquired.
+    pub interpolated_slices: usize,
+    pub warnings: Vec<String>,
+}
+
+fn cross(a: [f64; 3], b: [f64; 3]) -> [f64; 3] {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+fn dot(a: [f64; 3], b: [f64; 3]) -> f64 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn row_direction(orientation: &[f64; 6]) -> [f64; 3] {
+    [orientation[0], orientation[1], orientation[2]]
+}
+
+fn column_direction(orientation: &[f64; 6]) -> [f64; 3] {
+    [orientation[3], orientation[4], orientation[5]]
+}
+
+impl Volume {
+    /// Slice direction: row direction × column direction.
+    pub fn normal(&self) -> [f64; 3] {
+        cross(
+            row_direction(&self.orientation),
+            column_direction(&self.orientation),
+        )
+    }
+
+    /// Voxel index to RAS millimetres, as NIfTI expects: DICOM LPS with x and y negated.
+    pub fn affine_ras(&self) -> [[f64; 4]; 3] {
+        let axes = [
+            row_direction(&self.orientation),
+            column_direction(&self.orientation),
+            self.normal(),
+        ];
+        let mut affine = [[0.0; 4]; 3];
+        for (r, row) in affine.iter_mut().enumerate() {
+            let sign = if r < 2 { -1.0 } else { 1.0 };
+            for (axis, direction) in axes.iter().enumerate() {
+                row[axis] = sign * direction[r] * self.spacing[axis];
+            }
+            row[3] = sign * self.origin[r];
+        }
+        affine
+    }
+}
+
+/// Stack `slices` (any order) of a `columns` × `rows` plane along the normal of
+/// `orientation`. `fallback_spacing` is the slice spacing of a single-slice volume.
+pub fn assemble(
+    mut slices: Vec<Slice>,
+    columns: usize,
+    rows: usize,
+    pixel_spacing: [f64; 2],
+    orientation: [f64; 6],
+    fallback_spacing: f64,
+    resampling: Resampling,
+) -> Result<Volume> {
+    if slices.is_empty() {
+        bail!("No slices to stack");
+    }
+    let normal = cross(row_direction(&orientation), column_direction(&orientation));
+    let distance = |slice: &Slice| dot(slice.position, normal);
+    slices.sort_by(|a, b| distance(a).total_cmp(&distance(b)));
+
+    // Every slice must lie on the same line through the first: in-plane offsets (gantry tilt,
+    // sheared stacks) would need a different grid altogether.
+    let first = slices[0].position;
+    let in_plane_tolerance = 0.1 * pixel_spacing[0].min(pixel_spacing[1]);
+    for slice in &slices[1..] {
+        let offset = [
+            slice.position[0] - first[0],
+            slice.position[1] - first[1],
+            slice.position[2] - first[2],
+        ];
+        let shift = dot(offset, row_direction(&orientation))
+            .hypot(dot(offset, column_direction(&orientation)));
+        if shift > in_plane_tolerance {
+            bail!(
+                "{} is shifted {:.2} mm in-plane from {} (gantry tilt or sheared stack); cannot export a regular volume",
+                slice.source,
+                shift,
+                slices[0].source
+            );
+        }
+    }
+
+    let mut warnings = Vec::new();
+    let mut kept: Vec<Slice> = Vec::with_capacity(slices.len());
+    for slice in slices {
+        match kept.last() {
+            Some(previous) if (distance(&slice) - distance(previous)).abs() < 1e-3 => {
+                warnings.push(format!(
+                    "{} duplicates the position of {}; left out",
+                    slice.source, previous.source
+                ));
+            }
+            _ => kept.push(slice),
+        }
+    }
+    let slices = kept;
+    let distances: Vec<f64> = slices.iter().map(distance).collect();
+    let steps: Vec<f64> = distances.windows(2).map(|w| w[1] - w[0]).collect();
+
+    let mut volume = Volume {
+        columns,
+        rows,
+        slices: slices.len(),
+        spacing: [pixel_spacing[1], pixel_spacing[0], fallback_spacing],
+        origin: slices[0].position,
+        orientation,
+        values: Vec::new(),
+        interpolated_slices: 0,
+        warnings,
+    };
+    if steps.is_empty() {
+        volume.values = slices.into_iter().flat_map(|s| s.values).collect();
+        return Ok(volume);
+    }
+
+    let mut sorted = steps.clone();
+    sorted.sort_by(f64::total_cmp);
+    let nominal = sorted[sorted.len() / 2];
+    let tolerance = SPACING_TOLERANCE * nominal;
+    let irregular: Vec<usize> = steps
+        .iter()
+        .enumerate()
+        .filter(|(_, step)| (*step - nominal).abs() > tolerance)
+        .map(|(i, _)| i)
+        .collect();
+    if irregular.is_empty() {
+        volume.spacing[2] = (distances[distances.len() - 1] - distances[0]) / steps.len() as f64;
+        volume.values = slices.into_iter().flat_map(|s| s.values).collect();
+        return Ok(volume);
+    }
+
+    let problems: Vec<String> = irregular
+        .iter()
+        .map(|&i| {
+            let missing = (steps[i] / nominal).round() as i64 - 1;
+            let what =
+                if missing >= 1 && (steps[i] - (missing + 1) as f64 * nominal).abs() <= tolerance {
+                    format!("{} missing slice(s)", missing)
+                } else {
+                    "irregular spacing".to_string()
+                };
+            format!(
+                "{:.3} mm between {} and {} where {:.3} mm is expected ({})",
+                steps[i],
+                slices[i].source,
+                slices[i + 1].source,
+                nominal,
+                what
+            )
+        })
+        .collect();
+    if resampling == Resampling::None {
+        bail!(
+            "Slices are not evenly spaced: {}; choose a resampling mode to interpolate",
+            problems.join("; ")
+        );
+    }
+    volume.warnings.extend(problems);
+
+    // Resample onto a grid spanning the acquired extent at (close to) the nominal spacing.
+    let extent = distances[distances.len() - 1] - distances[0];
+    let count = (extent / nominal).round() as usize + 1;
+    let spacing = extent / (count - 1) as f64;
+    let plane = columns * rows;
+    let mut values = Vec::with_capacity(count * plane);
+    let mut interpolated = 0;
+    let mut upper = 1;
+    for k in 0..count {
+        let target = distances[0] + k as f64 * spacing;
+        while upper < distances.len() - 1 && distances[upper] < target {
+            upper += 1;
+        }
+        let (below, above) = (upper - 1, upper);
+        let span = distances[above] - distances[below];
+        let t = ((target - distances[below]) / span).clamp(0.0, 1.0);
+        let acquired = t * span <= tolerance || (1.0 - t) * span <= tolerance;
+        if acquired || resampling == Resampling::Nearest {
+            let nearest = if t <= 0.5 { below } else { above };
+            values.extend_from_slice(&slices[nearest].values);
+        } else {
+            let (a, b) = (&slices[below].values, &slices[above].values);
+            values.extend(
+                a.iter()
+                    .zip(b)
+                    .map(|(a, b)| ((1.0 - t) * *a as f64 + t * *b as f64) as f32),
+            );
+        }
+        if !acquired {
+            interpolated += 1;
+        }
+    }
+    volume.warnings.push(format!(
+        "{} of {} slice(s) filled in by {} resampling to a regular {:.3} mm spacing",
+        interpolated,
+        count,
+        match resampling {
+            Resampling::Nearest => "nearest-neighbour",
+            _ => "linear",
+        },
+        spacing
+    ));
+    volume.slices = count;
+    volume.spacing[2] = spacing;
+    volume.values = values;
+    volume.interpolated_slices = interpolated;
+    Ok(volume)
+}
+
+/// Files of one series of `dir`: `series`, or the only series there is.
+pub(crate) fn series_files(dir: &Path, series: Option<&str>) -> Result<Vec<PathBuf>> {
+    let mut found: BTreeMap<String, Vec<PathBuf>> = BTreeMap::new();
+    let mut paths: Vec<PathBuf> = WalkDir::new(dir)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file())
+        .map(|e| e.into_path())
+        .collect();
+    paths.sort();
+    for path in paths {
+        let Ok(header) = OpenFileOptions::new()
+            .read_until(PIXEL_DATA)
+            .open_file(&path)
+        else {
+            continue;
+        };
+        let uid = header
+            .element_str(SERIES_INSTANCE_UID)
+            .unwrap_or_default()
+            .trim_end_matches(['\0', ' '])
+            .to_string();
+        found.entry(uid).or_default().push(path);
+    }
+    match series {
+        Some(uid) => found
+            .remove(uid)
+            .with_context(|| format!("Series {} not found in {:?}", uid, dir)),
+        None if found.len() > 1 => bail!(
+            "{:?} holds {} series; choose one of {}",
+            dir,
+            found.len(),
+            found.keys().cloned().collect::<Vec<_>>().join(", ")
+        ),
+        None => found
+            .into_values()
+            .next()
+            .with_context(|| format!("No DICOM files in {:?}", dir)),
+    }
+}
+
+/// Slices read from a set of files, with the plane they share.
+pub(crate) struct SliceStack {
+    pub slices: Vec<Slice>,
+    pub columns: usize,
+    pub rows: usize,
+    pub pixel_spacing: [f64; 2],
+    pub orientation: [f64; 6],
+    /// Spacing Between Slices or Slice Thickness, for a single-slice volume.
+    pub fallback_spacing: f64,
+}
+
+impl SliceStack {
+    /// Read every frame of `files` as a slice. Every slice must share the matrix size, pixel
+    /// spacing and orientation.
+    pub fn read(files: &[PathBuf]) -> Result<Self> {
+        let convert = ConvertOptions::new().with_modality_lut(ModalityLutOption::Default);
+        let mut slices = Vec::new();
+        let mut plane: Option<(usize, usize, [f64; 2], [f64; 6])> = None;
+        let mut fallback_spacing = None;
+        for path in files {
+            let obj = open_dicom(path).with_context(|| format!("Failed to open {:?}", path))?;
+            let decoded = obj
+                .decode_pixel_data()
+                .with_context(|| format!("Failed to decode {:?}", path))?;
+            if decoded.samples_per_pixel() != 1 {
+                bail!("{:?} is not a single-sample (grayscale) image", path);
+            }
+            let name = path.file_name().map_or_else(
+                || path.display().to_string(),
+                |n| n.to_string_lossy().into_owned(),
+            );
+            let frames = decoded.number_of_frames();
+            for frame in 0..frames {
+                let geometry = FrameGeometry::for_frame(&obj, frame)
+                    .with_context(|| format!("{:?} has no pixel spacing", path))?;
+                let (Some(orientation), Some(position)) = (geometry.orientation, geometry.position)
+                else {
+                    bail!("{:?} has no Image Position/Orientation (Patient)", path);
+                };
+                let this = (
+                    decoded.columns() as usize,
+                    decoded.rows() as usize,
+                    [geometry.row_spacing, geometry.column_spacing],
+                    orientation,
+                );
+                match &plane {
+                    None => plane = Some(this),
+                    Some(first) => {
+                        if (first.0, first.1) != (this.0, this.1) {
+                            bail!(
+                                "{:?} has a different matrix size from the rest of the series",
+                                path
+                            );
+                        }
+                        let close = |a: &[f64], b: &[f64], tolerance: f64| {
+                            a.iter().zip(b).all(|(a, b)| (a - b).abs() <= tolerance)
+                        };
+                        if !close(&first.2, &this.2, 1e-3) {
+                            bail!(
+                                "{:?} has a different pixel spacing from the rest of the series",
+                                path
+                            );
+                        }
+                        if !close(&first.3, &this.3, 1e-3) {
+                            bail!(
+                                "{:?} has a different orientation from the rest of the series",
+                                path
+                            );
+                        }
+                    }
+                }
+                fallback_spacing = fallback_spacing.or_else(|| {
+                    obj.element_f64(SPACING_BETWEEN_SLICES)
+                        .or_else(|| obj.element_f64(SLICE_THICKNESS))
+                        .filter(|spacing| *spacing > 0.0)
+                });
+                let values = decoded
+                    .to_vec_frame_with_options::<f32>(frame, &convert)
+                    .with_context(|| format!("Failed to convert {:?}", path))?;
+                slices.push(Slice {
+                    position,
+                    values,
+                    source: if frames > 1 {
+                        format!("{} frame {}", name, frame + 1)
+                    } else {
+                        name.clone()
+                    },
+                    path: path.clone(),
+                    frame,
+                });
+            }
+        }
+        let Some((columns, rows, pixel_spacing, orientation)) = plane else {
+            bail!("No slices found");
+        };
+        Ok(Self {
+            slices,
+            columns,
+            rows,
+            pixel_spacing,
+            orientation,
+            fallback_spacing: fallback_spacing.unwrap_or(1.0),
+        })
+    }
+
+    /// Slice direction of the plane: row direction × column direction.
+    pub fn normal(&self) -> [f64; 3] {
+        cross(
+            row_direction(&self.orientation),
+            column_direction(&self.orientation),
+        )
+    }
+
+    pub fn assemble(self, resampling: Resampling) -> Result<Volume> {
+        assemble(
+            self.slices,
+            self.columns,
+            self.rows,
+            self.pixel_spacing,
+            self.orientation,
+            self.fallback_spacing,
+            resampling,
+        )
+    }
+}
+
+/// Stack one series of `dir` into a volume; multi-frame objects contribute one slice per
+/// frame.
+pub fn load_series(dir: &Path, options: &VolumeOptions) -> Result<Volume> {
+    let files = series_files(dir, options.series.as_deref())?;
+    SliceStack::read(&files)
+        .with_context(|| format!("Cannot stack the series in {:?}", dir))?
+        .assemble(options.resampling)
+}
+
+/// Quaternion parameters (b, c, d) and qfac of a rotation given by its columns, as in
+/// `nifti_mat44_to_quatern`: a left-handed matrix gets qfac -1 and its third column negated.
+fn quaternion(mut columns: [[f64; 3]; 3]) -> ([f64; 3], f64) {
+    let determinant = dot(columns[0], cross(columns[1], columns[2]));
+    let qfac = if determinant < 0.0 {
+        columns[2] = columns[2].map(|v| -v);
+        -1.0
+    } else {
+        1.0
+    };
+    let m = |r: usize, c: usize| columns[c][r];
+    let trace = m(0, 0) + m(1, 1) + m(2, 2) + 1.0;
+    let (a, b, c, d) = if trace > 0.5 {
+        let a = 0.5 * trace.sqrt();
+        (
+            a,
+            0.25 * (m(2, 1) - m(1, 2)) / a,
+            0.25 * (m(0, 2) - m(2, 0)) / a,
+            0.25 * (m(1, 0) - m(0, 1)) / a,
+        )
+    } else {
+        let (xd, yd, zd) = (
+            1.0 + m(0, 0) - (m(1, 1) + m(2, 2)),
+            1.0 + m(1, 1) - (m(0, 0) + m(2, 2)),
+            1.0 + m(2, 2) - (m(0, 0) + m(1, 1)),
+        );
+        if xd > 1.0 {
+            let b = 0.5 * xd.sqrt();
+            (
+                0.25 * (m(2, 1) - m(1, 2)) / b,
+                b,
+                0.25 * (m(0, 1) + m(1, 0)) / b,
+                0.25 * (m(0, 2) + m(2, 0)) / b,
+            )
+        } else if yd > 1.0 {
+            let c = 0.5 * yd.sqrt();
+            (
+                0.25 * (m(0, 2) - m(2, 0)) / c,
+                0.25 * (m(0, 1) + m(1, 0)) / c,
+                c,
+                0.25 * (m(1, 2) + m(2, 1)) / c,
+            )
+        } else {
+            let d = 0.5 * zd.sqrt();
+            (
+                0.25 * (m(1, 0) - m(0, 1)) / d,
+                0.25 * (m(0, 2) + m(2, 0)) / d,
+                0.25 * (m(1, 2) + m(2, 1)) / d,
+                d,
+            )
+        }
+    };
+    // The convention keeps a non-negative.
+    let sign = if a < 0.0 { -1.0 } else { 1.0 };
+    ([sign * b, sign * c, sign * d], qfac)
+}
+
+/// NIfTI-1 single-file header (348 bytes) plus the empty extension flag, float32 voxels.
+pub fn nifti_header(volume: &Volume, description: &str) -> Vec<u8> {
+    let mut header = vec![0u8; 352];
+    let mut put = |offset: usize, bytes: &[u8]| {
+        header[offset..offset + bytes.len()].copy_from_slice(bytes);
+    };
+    put(0, &348i32.to_le_bytes());
+    put(38, b"r");
+    let dims = [
+        3,
+        volume.columns as i16,
+        volume.rows as i16,
+        volume.slices as i16,
+        1,
+        1,
+        1,
+        1,
+    ];
+    for (i, dim) in dims.iter().enumerate() {
+        put(40 + 2 * i, &dim.to_le_bytes());
+    }
+    put(70, &16i16.to_le_bytes()); // DT_FLOAT32
+    put(72, &32i16.to_le_bytes());
+
+    let affine = volume.affine_ras();
+    let axes: [[f64; 3]; 3] =
+        std::array::from_fn(|axis| std::array::from_fn(|r| affine[r][axis] / volume.spacing[axis]));
+    let (quatern, qfac) = quaternion(axes);
+    let pixdim = [
+        qfac,
+        volume.spacing[0],
+        volume.spacing[1],
+        volume.spacing[2],
+        0.0,
+        0.0,
+        0.0,
+        0.0,
+    ];
+    for (i, value) in pixdim.iter().enumerate() {
+        put(76 + 4 * i, &(*value as f32).to_le_bytes());
+    }
+    put(108, &352f32.to_le_bytes());
+    put(112, &1f32.to_le_bytes());
+    put(123, &[2]); // NIFTI_UNITS_MM
+    let description = description.as_bytes();
+    put(148, &description[..description.len().min(79)]);
+    // Both transforms are scanner-based (NIFTI_XFORM_SCANNER_ANAT).
+    put(252, &1i16.to_le_bytes());
+    put(254, &1i16.to_le_bytes());
+    let qform = [
+        quatern[0],
+        quatern[1],
+        quatern[2],
+        affine[0][3],
+        affine[1][3],
+        affine[2][3],
+    ];
+    for (i, value) in qform.iter().enumerate() {
+        put(256 + 4 * i, &(*value as f32).to_le_bytes());
+    }
+    for (r, row) in affine.iter().enumerate() {
+        for (c, value) in row.iter().enumerate() {
+            put(280 + 16 * r + 4 * c, &(*value as f32).to_le_bytes());
+        }
+    }
+    put(344, b"n+1\0");
+    header
+}
+
+/// Write `volume` as NIfTI-1 to `path`, gzip-compressed when it ends in `.gz`.
+pub fn write_nifti(volume: &Volume, path: &Path) -> Result<()> {
+    let description = if volume.interpolated_slices > 0 {
+        format!(
+            "dicom-tools; {} slice(s) interpolated",
+            volume.interpolated_slices
+        )
+    } else {
+        "dicom-tools".to_string()
+    };
+    let mut bytes = nifti_header(volume, &description);
+    bytes.reserve(volume.values.len() * 4);
+    for value in &volume.values {
+        bytes.extend_from_slice(&value.to_le_bytes());
+    }
+    let gzip = path
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("gz"));
+    atomic_file::write_with(path, |writer| {
+        if gzip {
+            let mut encoder = GzEncoder::new(writer, flate2::Compression::default());
+            encoder.write_all(&bytes)?;
+            encoder.finish()?;
+        } else {
+            writer.write_all(&bytes)?;
+        }
+        Ok(())
+    })
+}
+
+/// CLI helper: stack a series, write it as NIfTI and report what was resampled.
+pub fn export_nifti(dir: &Path, output: &Path, options: &VolumeOptions) -> Result<Volume> {
+    let volume = load_series(dir, options)?;
+    write_nifti(&volume, output)?;
+    println!(
+        "Wrote {:?}: {}x{}x{} voxels, spacing {:.3} x {:.3} x {:.3} mm",
+        output,
+        volume.columns,
+        volume.rows,
+        volume.slices,
+        volume.spacing[0],
+        volume.spacing[1],
+        volume.spacing[2]
+    );
+    for warning in &volume.warnings {
+        println!("  [WARN] {}", warning);
+    }
+    Ok(volume)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const AXIAL: [f64; 6] = [1.0, 0.0, 0.0, 0.0, 1.0, 0.0];
+
+    fn slice(z: f64, value: f32) -> Slice {
+        Slice {
+            position: [0.0, 0.0, z],
+            values: vec![value; 4],
+            source: format!("z={}", z),
+            path: PathBuf::new(),
+            frame: 0,
+        }
+    }
+
+    #[test]
+    fn gaps_are_refused_unless_resampled() {
+        let slices = vec![
+            slice(0.0, 0.0),
+            slice(2.0, 20.0),
+            slice(6.0, 60.0),
+            slice(4.0, 40.0),
+            slice(10.0, 100.0),
+        ];
+        let stack = |resampling| assemble(slices.clone(), 2, 2, [1.0, 1.0], AXIAL, 1.0, resampling);
+
+        let error = stack(Resampling::None).unwrap_err().to_string();
+        assert!(error.contains("1 missing slice(s)"), "{}", error);
+
+        let volume = stack(Resampling::Linear).unwrap();
+        assert_eq!(volume.slices, 6);
+        assert_eq!(volume.interpolated_slices, 1);
+        assert!((volume.spacing[2] - 2.0).abs() < 1e-9);
+        assert_eq!(volume.values[4 * 4], 80.0, "midway between z=6 and z=10");
+        assert_eq!(volume.values[3 * 4], 60.0);
+
+        let volume = stack(Resampling::Nearest).unwrap();
+        assert!([60.0, 100.0].contains(&volume.values[4 * 4]));
+        assert_eq!(volume.warnings.len(), 2);
+    }
+
+    #[test]
+    fn sheared_stacks_are_refused_and_duplicates_dropped() {
+        let mut slices = vec![slice(0.0, 0.0), slice(1.0, 1.0), slice(1.0, 1.0)];
+        let volume = assemble(
+            slices.clone(),
+            2,
+            2,
+            [1.0, 1.0],
+            AXIAL,
+            1.0,
+            Resampling::None,
+        )
+        .unwrap();
+        assert_eq!(volume.slices, 2);
+        assert_eq!(volume.warnings.len(), 1);
+
+        slices[1].position[0] = 0.5;
+        assert!(assemble(slices, 2, 2, [1.0, 1.0], AXIAL, 1.0, Resampling::Linear).is_err());
+    }
+
+    #[test]
+    fn nifti_header_maps_lps_to_ras() {
+        let volume = Volume {
+            columns: 4,
+            rows: 3,
+            slices: 2,
+            spacing: [0.5, 0.75, 2.0],
+            origin: [10.0, 20.0, 30.0],
+            orientation: AXIAL,
+            values: vec![0.0; 24],
+            interpolated_slices: 0,
+            warnings: Vec::new(),
+        };
+        let header = nifti_header(&volume, "test");
+        let f32_at =
+            |offset: usize| f32::from_le_bytes(header[offset..offset + 4].try_into().unwrap());
+        assert_eq!(&header[344..348], b"n+1\0");
+        assert_eq!(i16::from_le_bytes([header[42], header[43]]), 4);
+        assert_eq!(
+            f32_at(280),
+            -0.5,
+            "srow_x: columns run towards patient left"
+        );
+        assert_eq!(f32_at(292), -10.0);
+        assert_eq!(f32_at(296 + 4), -0.75);
+        assert_eq!(f32_at(312 + 8), 2.0);
+        // A 180° rotation about z: quaternion (0, 0, 1).
+        assert_eq!((f32_at(256), f32_at(260), f32_at(264)), (0.0, 0.0, 1.0));
+        assert_eq!(f32_at(108), 352.0);
+    }
+}
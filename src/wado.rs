@@ -0,0 +1,417 @@
+//
+// wado.rs
+// Dicom-Tools-rs
+//
+// DICOMweb WADO-RS client (PS3.18 10.4): retrieves studies, series, instances, frames or
+// rendered images, negotiating the transfer syntax through the Accept header, splits the
+// multipart/related response and writes each part to disk.
+//
+// Thales Matheus Mendonça Santos - November 2025
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use dicom::object::FileMetaTable;
+use serde::Serialize;
+
+use crate::atomic_file;
+
+/// What to retrieve, down to a single instance.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WadoTarget {
+    pub study: String,
+    pub series: Option<String>,
+    pub instance: Option<String>,
+}
+
+/// Representation of the target to ask for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WadoResource {
+    /// Part 10 files, one part per instance.
+    Instances,
+    /// Pixel data of these 1-based frames of an instance.
+    Frames(Vec<u32>),
+    /// Consumer-format images (`image/jpeg`, `image/png`...) of the target, or of these
+    /// frames of an instance when not empty.
+    Rendered {
+        media_type: String,
+        frames: Vec<u32>,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub struct WadoOptions {
+    /// DICOMweb service root (`.../dicomweb`); a trailing `/studies` is accepted.
+    pub url: String,
+    pub token: Option<String>,
+    pub timeout: Option<Duration>,
+    /// Transfer syntax UID to request for instances and frames; `*` accepts the stored one.
+    /// Without it the server sends its default (Explicit VR Little Endian).
+    pub transfer_syntax: Option<String>,
+}
+
+/// Files written by a retrieval.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct WadoSummary {
+    pub url: String,
+    pub files: Vec<PathBuf>,
+    pub bytes: u64,
+}
+
+/// One body part of a multipart/related response.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Part {
+    pub content_type: Option<String>,
+    pub content_location: Option<String>,
+    pub body: Vec<u8>,
+}
+
+impl WadoTarget {
+    fn path(&self) -> Result<String> {
+        let mut path = format!("studies/{}", self.study);
+        match (&self.series, &self.instance) {
+            (Some(series), Some(instance)) => {
+                path.push_str(&format!("/series/{}/instances/{}", series, instance))
+            }
+            (Some(series), None) => path.push_str(&format!("/series/{}", series)),
+            (None, Some(_)) => bail!("An instance can only be retrieved within its series"),
+            (None, None) => {}
+        }
+        Ok(path)
+    }
+}
+
+/// The resource URL of `resource` of `target` under the service root `url`.
+pub fn resource_url(url: &str, target: &WadoTarget, resource: &WadoResource) -> Result<String> {
+    let root = url.trim_end_matches('/');
+    let root = root.strip_suffix("/studies").unwrap_or(root);
+    let mut path = format!("{}/{}", root, target.path()?);
+    match resource {
+        WadoResource::Instances => {}
+        WadoResource::Frames(frames) => push_frames(&mut path, target, frames)?,
+        WadoResource::Rendered { frames, .. } => {
+            if !frames.is_empty() {
+                push_frames(&mut path, target, frames)?;
+            }
+            path.push_str("/rendered");
+        }
+    }
+    Ok(path)
+}
+
+fn push_frames(path: &mut String, target: &WadoTarget, frames: &[u32]) -> Result<()> {
+    if target.instance.is_none() {
+        bail!("Frames can only be retrieved from an instance");
+    }
+    if frames.is_empty() || frames.contains(&0) {
+        bail!("Frame numbers start at 1");
+    }
+    let list: Vec<String> = frames.iter().map(u32::to_string).collect();
+    path.push_str(&format!("/frames/{}", list.join(",")));
+    Ok(())
+}
+
+/// Accept header for `resource`. Rendered images come one per response part, so they are
+/// accepted both as multipart and as a single image.
+pub fn accept(resource: &WadoResource, transfer_syntax: Option<&str>) -> String {
+    let syntax = transfer_syntax
+        .map(|uid| format!("; transfer-syntax={}", uid))
+        .unwrap_or_default();
+    match resource {
+        WadoResource::Instances => {
+            format!("multipart/related; type=\"application/dicom\"{}", syntax)
+        }
+        WadoResource::Frames(_) => {
+            format!(
+                "multipart/related; type=\"application/octet-stream\"{}",
+                syntax
+            )
+        }
+        WadoResource::Rendered { media_type, .. } => {
+            format!("{}, multipart/related; type=\"{}\"", media_type, media_type)
+        }
+    }
+}
+
+/// Parameter `name` of a Content-Type value, unquoted.
+fn parameter(content_type: &str, name: &str) -> Option<String> {
+    content_type.split(';').skip(1).find_map(|param| {
+        let (key, value) = param.split_once('=')?;
+        key.trim()
+            .eq_ignore_ascii_case(name)
+            .then(|| value.trim().trim_matches('"').to_string())
+    })
+}
+
+fn find(haystack: &[u8], needle: &[u8], from: usize) -> Option<usize> {
+    haystack
+        .get(from..)?
+        .windows(needle.len())
+        .position(|window| window == needle)
+        .map(|position| position + from)
+}
+
+/// Split a multipart/related body (RFC 2046) into its parts, given the response
+/// Content-Type carrying the boundary.
+pub fn parse_multipart(content_type: &str, body: &[u8]) -> Result<Vec<Part>> {
+    let boundary = parameter(content_type, "boundary")
+        .with_context(|| format!("No boundary in Content-Type {:?}", content_type))?;
+    let delimiter = format!("--{}", boundary).into_bytes();
+    // Delimiters other than a leading one follow a CRLF, so a boundary-like sequence inside
+    // binary content that does not start a line is not mistaken for one.
+    let mut start = if body.starts_with(&delimiter) {
+        0
+    } else {
+        let mut line = b"\r\n".to_vec();
+        line.extend_from_slice(&delimiter);
+        find(body, &line, 0).context("Multipart body without any part")? + 2
+    };
+    let mut closing = b"\r\n".to_vec();
+    closing.extend_from_slice(&delimiter);
+    let mut parts = Vec::new();
+    loop {
+        let after = start + delimiter.len();
+        if body[after..].starts_with(b"--") {
+            break;
+        }
+        if after == body.len() {
+            bail!("Multipart body is truncated: no closing boundary");
+        }
+        let content_start = after
+            + if body[after..].starts_with(b"\r\n") {
+                2
+            } else {
+                0
+            };
+        let Some(end) = find(body, &closing, content_start) else {
+            bail!("Multipart body is truncated: no closing boundary");
+        };
+        let part = &body[content_start..end];
+        // A part without headers starts with the blank line right away.
+        let (headers, content) = if part.starts_with(b"\r\n") {
+            (&part[..0], &part[2..])
+        } else {
+            match find(part, b"\r\n\r\n", 0) {
+                Some(split) => (&part[..split], &part[split + 4..]),
+                None => bail!("Multipart part without a header/body separator"),
+            }
+        };
+        let headers = String::from_utf8_lossy(headers);
+        let header = |name: &str| {
+            headers.lines().find_map(|line| {
+                let (key, value) = line.split_once(':')?;
+                key.trim()
+                    .eq_ignore_ascii_case(name)
+                    .then(|| value.trim().to_string())
+            })
+        };
+        parts.push(Part {
+            content_type: header("Content-Type"),
+            content_location: header("Content-Location"),
+            body: content.to_vec(),
+        });
+        start = end + 2;
+    }
+    Ok(parts)
+}
+
+/// File extension for a part's media type.
+fn extension(content_type: Option<&str>) -> &'static str {
+    let media_type = content_type
+        .and_then(|ct| ct.split(';').next())
+        .map(|ct| ct.trim().to_ascii_lowercase())
+        .unwrap_or_default();
+    match media_type.as_str() {
+        "application/dicom" => "dcm",
+        "image/jpeg" => "jpg",
+        "image/png" => "png",
+        "image/gif" => "gif",
+        "image/jp2" => "jp2",
+        "image/jls" => "jls",
+        "image/jxl" => "jxl",
+        "video/mpeg" => "mpg",
+        "video/mp4" => "mp4",
+        _ => "raw",
+    }
+}
+
+/// SOP Instance UID of a Part 10 file, from its file meta group.
+fn sop_instance_uid(bytes: &[u8]) -> Option<String> {
+    let meta = if bytes.get(128..132) == Some(b"DICM") {
+        &bytes[128..]
+    } else if bytes.starts_with(b"DICM") {
+        bytes
+    } else {
+        return None;
+    };
+    let table = FileMetaTable::from_reader(meta).ok()?;
+    let uid = table.media_storage_sop_instance_uid();
+    (!uid.is_empty()).then(|| uid.to_string())
+}
+
+/// File name of part `index` (0-based) of a response for `resource`.
+fn part_name(resource: &WadoResource, part: &Part, index: usize) -> String {
+    let extension = extension(part.content_type.as_deref());
+    match resource {
+        WadoResource::Instances => match sop_instance_uid(&part.body) {
+            Some(uid) => format!("{}.dcm", uid),
+            None => format!("part{:04}.{}", index + 1, extension),
+        },
+        WadoResource::Frames(frames) => match frames.get(index) {
+            Some(frame) => format!("frame{:04}.{}", frame, extension),
+            None => format!("part{:04}.{}", index + 1, extension),
+        },
+        WadoResource::Rendered { frames, .. } => match frames.get(index) {
+            Some(frame) => format!("frame{:04}.{}", frame, extension),
+            None => format!("rendered{:04}.{}", index + 1, extension),
+        },
+    }
+}
+
+/// Retrieve `resource` of `target` and write every returned part under `output`. The
+/// whole response is held in memory while it is split.
+pub fn retrieve(
+    options: &WadoOptions,
+    target: &WadoTarget,
+    resource: &WadoResource,
+    output: &Path,
+) -> Result<WadoSummary> {
+    let url = resource_url(&options.url, target, resource)?;
+    let transfer_syntax = match resource {
+        WadoResource::Rendered { .. } => None,
+        _ => options.transfer_syntax.as_deref(),
+    };
+    let mut request = attohttpc::get(&url).header("Accept", accept(resource, transfer_syntax));
+    if let Some(token) = &options.token {
+        request = request.bearer_auth(token.as_str());
+    }
+    if let Some(timeout) = options.timeout {
+        request = request.timeout(timeout);
+    }
+    let response = request
+        .send()
+        .with_context(|| format!("WADO-RS request to {} failed", url))?;
+    let status = response.status();
+    let content_type = response
+        .headers()
+        .get("Content-Type")
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default()
+        .to_string();
+    let body = response
+        .bytes()
+        .context("Failed to read the WADO-RS response")?;
+    if !status.is_success() {
+        let text = String::from_utf8_lossy(&body[..body.len().min(200)]).into_owned();
+        bail!(
+            "WADO-RS request to {} failed: HTTP {} {}",
+            url,
+            status.as_u16(),
+            text.trim()
+        );
+    }
+    if status.as_u16() == 204 || body.is_empty() {
+        bail!("{} returned no content", url);
+    }
+
+    let parts = if content_type
+        .to_ascii_lowercase()
+        .starts_with("multipart/related")
+    {
+        parse_multipart(&content_type, &body)?
+    } else {
+        vec![Part {
+            content_type: Some(content_type),
+            content_location: None,
+            body: body.to_vec(),
+        }]
+    };
+    fs::create_dir_all(output).with_context(|| format!("Failed to create {:?}", output))?;
+    let mut summary = WadoSummary {
+        url,
+        ..WadoSummary::default()
+    };
+    for (index, part) in parts.iter().enumerate() {
+        let path = output.join(part_name(resource, part, index));
+        atomic_file::write(&path, &part.body)?;
+        summary.bytes += part.body.len() as u64;
+        summary.files.push(path);
+    }
+    Ok(summary)
+}
+
+/// CLI helper: retrieve and list what was written.
+pub fn print_retrieve(
+    options: &WadoOptions,
+    target: &WadoTarget,
+    resource: &WadoResource,
+    output: &Path,
+) -> Result<()> {
+    let summary = retrieve(options, target, resource, output)?;
+    println!(
+        "WADO-RS {}: {} file(s), {} bytes written to {:?}",
+        summary.url,
+        summary.files.len(),
+        summary.bytes,
+        output
+    );
+    for file in &summary.files {
+        println!("  {}", file.display());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn urls_and_accept_headers_follow_the_target() {
+        let target = WadoTarget {
+            study: "1.2".to_string(),
+            series: Some("1.2.3".to_string()),
+            instance: Some("1.2.3.4".to_string()),
+        };
+        assert_eq!(
+            resource_url(
+                "https://pacs/dicomweb/studies/",
+                &target,
+                &WadoResource::Frames(vec![1, 3])
+            )
+            .unwrap(),
+            "https://pacs/dicomweb/studies/1.2/series/1.2.3/instances/1.2.3.4/frames/1,3"
+        );
+        let study = WadoTarget {
+            series: None,
+            instance: None,
+            ..target.clone()
+        };
+        assert!(resource_url("https://pacs", &study, &WadoResource::Frames(vec![1])).is_err());
+        assert_eq!(
+            accept(&WadoResource::Instances, Some("1.2.840.10008.1.2.4.50")),
+            "multipart/related; type=\"application/dicom\"; transfer-syntax=1.2.840.10008.1.2.4.50"
+        );
+    }
+
+    #[test]
+    fn multipart_bodies_split_on_line_delimiters_only() {
+        let body = b"preamble\r\n--b1\r\nContent-Type: application/octet-stream\r\n\r\nAB--b1C\r\n--b1\r\nContent-Type: image/jpeg\r\nContent-Location: /frames/2\r\n\r\n\xff\xd8\r\n--b1--\r\n";
+        let parts = parse_multipart(
+            "multipart/related; type=\"application/octet-stream\"; boundary=\"b1\"",
+            body,
+        )
+        .unwrap();
+        assert_eq!(parts.len(), 2);
+        assert_eq!(parts[0].body, b"AB--b1C");
+        assert_eq!(parts[1].content_type.as_deref(), Some("image/jpeg"));
+        assert_eq!(parts[1].content_location.as_deref(), Some("/frames/2"));
+        assert_eq!(parts[1].body, b"\xff\xd8");
+        assert_eq!(
+            part_name(&WadoResource::Frames(vec![5, 2]), &parts[1], 1),
+            "frame0002.jpg"
+        );
+        assert!(parse_multipart("multipart/related; boundary=b1", b"--b1\r\n\r\nx").is_err());
+    }
+}
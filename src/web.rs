@@ -6,57 +6,262 @@
 //
 // Thales Matheus Mendonça Santos - November 2025
 
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::convert::Infallible;
 use std::fmt::Display;
+use std::io::Seek;
 use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 use axum::{
-    extract::{Multipart, Path, Query, State},
-    http::{header, HeaderValue, StatusCode},
-    response::{Html, IntoResponse},
-    routing::{get, post},
+    body::Body,
+    extract::{DefaultBodyLimit, FromRequestParts, Multipart, Path, Query, State},
+    http::{header, request::Parts, HeaderMap, HeaderName, HeaderValue, StatusCode},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        Html, IntoResponse,
+    },
+    routing::{delete, get, post},
     Json, Router,
 };
-use dicom::object::open_file;
+use dicom::core::Tag;
+use dicom::object::{open_file, OpenFileOptions};
 use dicom::pixeldata::PixelDecoder;
+use dicom_pixeldata::WindowLevel;
+use futures_util::stream::{self, Stream};
 use serde::Deserialize;
 use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
 use tokio::net::TcpListener;
+use tokio::sync::Semaphore;
+use tokio_util::io::ReaderStream;
 use tower_http::cors::CorsLayer;
 
 use crate::{
-    anonymize, image, json, metadata,
+    anonymize, capabilities, cine,
+    cli::{
+        device_retention, group_removal, AnonymizationProfile, DeviceField, HistogramPreset,
+        JsonStyle, ModificationReason, PaletteSpace, RemovableGroup, TransferSyntax,
+    },
+    dicom_access::{open_dicom, ElementAccess},
+    dimse::AssociationSettings,
+    image::{self, PreviewFormat},
+    jobs::{JobInput, JobOperation, JobQueue, JobRecord},
+    json, lenient,
+    listing::{self, ListQuery, Page},
+    lut, measure, metadata,
     models::{DetailedMetadata, PixelStatistics, ValidationSummary},
+    padding::Exclusions,
+    person_name::PersonName,
+    preview_cache::{PreviewCache, PreviewKey},
+    progress::{NoProgress, ProgressEvent},
+    scp::AeMap,
+    screening::{Rejection, UploadScreen},
+    scu_async,
+    sharing::{ShareError, ShareGrant, ShareKind, ShareSigner},
     stats,
-    storage::FileStore,
+    storage::{DerivedArtifact, FileStore, QuotaError},
+    transcode::{self, UncompressedTransferSyntax},
+    upload_pipeline::UploadPipeline,
     validate,
 };
 
+const STUDY_DATE: Tag = Tag(0x0008, 0x0020);
+const SOP_CLASS_UID: Tag = Tag(0x0008, 0x0016);
+const MODALITY: Tag = Tag(0x0008, 0x0060);
+const SERIES_DESCRIPTION: Tag = Tag(0x0008, 0x103E);
+const PATIENT_NAME: Tag = Tag(0x0010, 0x0010);
+const PATIENT_ID: Tag = Tag(0x0010, 0x0020);
+const STUDY_INSTANCE_UID: Tag = Tag(0x0020, 0x000D);
+const SERIES_INSTANCE_UID: Tag = Tag(0x0020, 0x000E);
+const SERIES_NUMBER: Tag = Tag(0x0020, 0x0011);
+const INSTANCE_NUMBER: Tag = Tag(0x0020, 0x0013);
+const SLICE_THICKNESS: Tag = Tag(0x0018, 0x0050);
+const SPACING_BETWEEN_SLICES: Tag = Tag(0x0018, 0x0088);
+const FRAME_TIME: Tag = Tag(0x0018, 0x1063);
+const CINE_RATE: Tag = Tag(0x0018, 0x0040);
+const RECOMMENDED_DISPLAY_FRAME_RATE: Tag = Tag(0x0008, 0x2144);
+const SAMPLES_PER_PIXEL: Tag = Tag(0x0028, 0x0002);
+const NUMBER_OF_FRAMES: Tag = Tag(0x0028, 0x0008);
+const ROWS: Tag = Tag(0x0028, 0x0010);
+const COLUMNS: Tag = Tag(0x0028, 0x0011);
+const PIXEL_DATA: Tag = Tag(0x7FE0, 0x0010);
+
 #[derive(Clone)]
 struct AppState {
     store: FileStore,
+    screen: Arc<dyn UploadScreen>,
+    workers: BlockingPool,
+    previews: Arc<PreviewCache>,
+    jobs: JobQueue,
+    shares: ShareSigner,
+    pipeline: Arc<UploadPipeline>,
+    series: Arc<SeriesCache>,
+    /// Nodes `POST /api/push` may send to; empty leaves the endpoint off.
+    push: Arc<AeMap>,
 }
 
 type ApiResult<T> = Result<T, (StatusCode, String)>;
 
-/// Bootstraps the Axum HTTP server and wires up API routes.
-pub async fn start_server(host: &str, port: u16) -> anyhow::Result<()> {
+/// Limits on the file IO and pixel decoding that requests perform off the async executor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WorkerLimits {
+    /// Jobs running at once; further requests wait for a free slot.
+    pub concurrency: usize,
+    /// Longest a request may wait for and run its job before it is answered with 503.
+    pub timeout: Duration,
+}
+
+impl Default for WorkerLimits {
+    fn default() -> Self {
+        Self {
+            concurrency: std::thread::available_parallelism()
+                .map(|n| n.get())
+                .unwrap_or(4),
+            timeout: Duration::from_secs(120),
+        }
+    }
+}
+
+/// Runs blocking jobs on tokio's blocking threads, at most `concurrency` at a time.
+#[derive(Clone)]
+struct BlockingPool {
+    permits: Arc<Semaphore>,
+    timeout: Duration,
+}
+
+impl BlockingPool {
+    fn new(limits: WorkerLimits) -> Self {
+        Self {
+            permits: Arc::new(Semaphore::new(limits.concurrency.max(1))),
+            timeout: limits.timeout,
+        }
+    }
+
+    /// Wait for a slot and run `job` on it. A job that outlives the timeout keeps its slot
+    /// until it finishes, so the limit holds even for requests that were already answered.
+    async fn run<T, F>(&self, job: F) -> ApiResult<T>
+    where
+        T: Send + 'static,
+        F: FnOnce() -> ApiResult<T> + Send + 'static,
+    {
+        let work = async {
+            let permit = self
+                .permits
+                .clone()
+                .acquire_owned()
+                .await
+                .map_err(internal_error)?;
+            tokio::task::spawn_blocking(move || {
+                let _permit = permit;
+                job()
+            })
+            .await
+            .map_err(internal_error)?
+        };
+        tokio::time::timeout(self.timeout, work)
+            .await
+            .unwrap_or_else(|_| {
+                Err((
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    format!("Request timed out after {:?}", self.timeout),
+                ))
+            })
+    }
+}
+
+/// Bootstraps the Axum HTTP server and wires up API routes. Uploads must pass `screen`
+/// before they are stored and then go through `pipeline`; file IO and decoding run within
+/// `limits`, and rendered previews are kept in `previews`. Stored files can only be pushed
+/// to the nodes of `push`.
+#[allow(clippy::too_many_arguments)]
+pub async fn start_server(
+    host: &str,
+    port: u16,
+    store: FileStore,
+    screen: Arc<dyn UploadScreen>,
+    limits: WorkerLimits,
+    previews: PreviewCache,
+    shares: ShareSigner,
+    pipeline: UploadPipeline,
+    push: AeMap,
+) -> anyhow::Result<()> {
+    println!("Upload store: {}", store.describe());
+    println!("Preview cache: {}", previews.describe());
+    println!(
+        "Workers: {} concurrent job(s), {:?} timeout",
+        limits.concurrency, limits.timeout
+    );
+    if !pipeline.is_empty() {
+        println!("On upload: {:?}", pipeline.steps);
+    }
+    if !push.is_empty() {
+        println!("Push destinations: {} AE title(s)", push.len());
+    }
+    let upload_limit = store.limits().max_upload_bytes;
     let state = AppState {
-        store: FileStore::new("target/uploads")?,
+        jobs: JobQueue::start(store.clone()),
+        shares,
+        store,
+        screen,
+        workers: BlockingPool::new(limits),
+        previews: Arc::new(previews),
+        pipeline: Arc::new(pipeline),
+        series: Arc::default(),
+        push: Arc::new(push),
     };
 
     let app = Router::new()
         .route("/", get(root_handler))
         .route("/api/metadata/:filename", get(get_metadata))
         .route("/api/upload", post(upload_handler))
+        .route("/api/files", get(list_files_handler))
+        .route("/api/studies", get(list_studies_handler))
+        .route("/api/series", get(list_series_handler))
         .route("/api/stats/:filename", get(get_stats))
         .route("/api/image/:filename", get(get_image_preview))
+        .route(
+            "/api/series/:series_uid/thumbnails",
+            get(series_thumbnails_handler),
+        )
+        .route(
+            "/api/series/:series_uid/manifest",
+            get(series_manifest_handler),
+        )
+        .route(
+            "/api/series/:series_uid/frames/:n",
+            get(series_frame_handler),
+        )
+        .route("/api/jobs", post(submit_job_handler).get(list_jobs_handler))
+        .route("/api/jobs/:id", get(job_status_handler))
+        .route("/api/share", post(create_share_handler))
+        .route("/api/share/:token", get(shared_handler))
         .route("/api/anonymize/:filename", post(anonymize_handler))
+        .route(
+            "/api/studies/:study_uid/anonymize",
+            post(study_anonymize_handler),
+        )
         .route("/api/validate/:filename", get(validate_handler))
         .route("/api/json/:filename", get(json_handler))
         .route("/api/download/:filename", get(download_handler))
+        .route("/api/files/:filename", delete(release_handler))
+        .route("/api/files/:filename/derivatives", get(derivatives_handler))
+        .route("/api/push/:filename", post(push_handler))
         .route("/api/histogram/:filename", get(histogram_handler))
+        .route(
+            "/api/transcode/:filename/events",
+            get(transcode_events_handler),
+        )
         .with_state(state)
         .layer(CorsLayer::permissive());
+    // With an explicit per-upload limit the upload handler enforces it (answering 507) while
+    // streaming; otherwise axum's default request body cap stays in place.
+    let app = match upload_limit {
+        Some(_) => app.layer(DefaultBodyLimit::disable()),
+        None => app,
+    };
 
     let addr: SocketAddr = format!("{}:{}", host, port).parse()?;
     println!("Server running at http://{}", addr);
@@ -77,40 +282,324 @@ async fn upload_handler(
     let mut original_name = None;
     let mut data = None;
 
-    // Find the first part named "file" and pull bytes eagerly.
-    while let Some(field) = multipart.next_field().await.map_err(bad_request)? {
+    // Find the first part named "file" and buffer it, stopping as soon as it is over the limit.
+    while let Some(mut field) = multipart.next_field().await.map_err(bad_request)? {
         if field.name() == Some("file") {
             original_name = field.file_name().map(|s| s.to_string());
-            data = Some(field.bytes().await.map_err(internal_error)?);
+            let mut bytes = Vec::new();
+            while let Some(chunk) = field.chunk().await.map_err(bad_request)? {
+                bytes.extend_from_slice(&chunk);
+                state
+                    .store
+                    .limits()
+                    .check_upload(bytes.len() as u64)
+                    .map_err(insufficient_storage)?;
+            }
+            data = Some(bytes);
             break;
         }
     }
 
-    let data = data.ok_or((StatusCode::BAD_REQUEST, "No file uploaded".to_string()))?;
-    let saved_name = state
-        .store
-        .save(original_name.as_deref(), &data)
-        .map_err(internal_error)?;
-    let path = state.store.resolve(&saved_name).map_err(internal_error)?;
-
-    // Parse once so we can return metadata, validation, and pixel information together.
-    let obj = open_file(&path).map_err(internal_error)?;
-    let info = metadata::extract_basic_metadata(&obj);
-    let validation = validate::validate_obj(&obj);
-    let summary = validate::as_summary(&validation);
-    let decoded = obj.decode_pixel_data().ok();
-    let pixel_format = decoded
-        .as_ref()
-        .and_then(|d| stats::pixel_format_from_decoded(d).ok())
-        .or_else(|| stats::pixel_format_for_file(&path).ok());
+    let mut data = data.ok_or((StatusCode::BAD_REQUEST, "No file uploaded".to_string()))?;
+    let AppState {
+        store,
+        screen,
+        previews,
+        pipeline,
+        ..
+    } = state.clone();
+    state
+        .workers
+        .run(move || {
+            // Slightly broken files are accepted in their recovered form; the anomalies are reported.
+            let parsed = lenient::parse_lenient_bytes(&data);
+            let repaired = parsed.repaired_bytes();
+            let anomalies = parsed.anomalies;
+            if let Some(bytes) = repaired {
+                data = bytes;
+            }
+            screen
+                .screen(original_name.as_deref(), &data)
+                .map_err(screening_error)?;
+            let saved_name = store
+                .save(original_name.as_deref(), &data)
+                .map_err(store_error)?;
+            let path = store.resolve(&saved_name).map_err(internal_error)?;
 
-    Ok(Json(json!({
-        "success": true,
-        "filename": saved_name,
-        "info": info,
-        "validation": summary,
-        "pixel_format": pixel_format
-    })))
+            // Parse once so we can return metadata, validation, and pixel information together.
+            let obj = open_dicom(&path).map_err(internal_error)?;
+            let info = metadata::extract_basic_metadata(&obj);
+            let capabilities = capabilities::detect(&obj);
+            let validation = validate::validate_obj(&obj);
+            let summary = validate::as_summary(&validation);
+            let decoded = obj.decode_pixel_data().ok();
+            let pixel_format = decoded
+                .as_ref()
+                .and_then(|d| stats::pixel_format_for_object(&obj, d).ok())
+                .or_else(|| stats::pixel_format_for_file(&path).ok());
+
+            let references = store.ref_count(&saved_name).map_err(internal_error)?;
+            let series_uid = obj
+                .element_str(SERIES_INSTANCE_UID)
+                .map(|uid| uid.trim_end_matches(['\0', ' ']).to_string());
+            let pipeline = pipeline.run(&store, &previews, &saved_name);
+
+            Ok(Json(json!({
+                "success": true,
+                "filename": saved_name,
+                "references": references,
+                "info": info,
+                "capabilities": capabilities,
+                "series_uid": series_uid,
+                "validation": summary,
+                "pixel_format": pixel_format,
+                "anomalies": anomalies,
+                "pipeline": pipeline
+            })))
+        })
+        .await
+}
+
+/// Header attributes of one stored upload, read up to (not including) its pixel data.
+struct StoredInstance {
+    name: String,
+    path: PathBuf,
+    size_bytes: Option<u64>,
+    patient_name: Option<String>,
+    patient_name_parts: Option<PersonName>,
+    patient_id: Option<String>,
+    study_date: Option<String>,
+    modality: Option<String>,
+    sop_class_uid: Option<String>,
+    study_uid: Option<String>,
+    series_uid: Option<String>,
+    series_number: Option<i64>,
+    series_description: Option<String>,
+    instance_number: Option<i64>,
+    number_of_frames: u32,
+}
+
+/// Every stored upload that parses as DICOM, in name order.
+fn scan_store(store: &FileStore) -> ApiResult<Vec<StoredInstance>> {
+    let mut instances = Vec::new();
+    for name in store.uploads().map_err(internal_error)? {
+        let Ok(path) = store.resolve(&name) else {
+            continue;
+        };
+        let Ok(obj) = OpenFileOptions::new()
+            .read_until(PIXEL_DATA)
+            .open_file(&path)
+        else {
+            continue;
+        };
+        let text = |tag: Tag| {
+            obj.element_str(tag)
+                .map(|s| s.trim_end_matches(['\0', ' ']).to_string())
+                .filter(|s| !s.is_empty())
+        };
+        let number = |tag: Tag| text(tag).and_then(|n| n.trim().parse::<i64>().ok());
+        instances.push(StoredInstance {
+            size_bytes: store.size_of(&name).map_err(internal_error)?,
+            patient_name_parts: text(PATIENT_NAME).as_deref().and_then(PersonName::parse),
+            patient_name: text(PATIENT_NAME),
+            patient_id: text(PATIENT_ID),
+            study_date: text(STUDY_DATE),
+            modality: text(MODALITY),
+            sop_class_uid: text(SOP_CLASS_UID),
+            study_uid: text(STUDY_INSTANCE_UID),
+            series_uid: text(SERIES_INSTANCE_UID),
+            series_number: number(SERIES_NUMBER),
+            series_description: text(SERIES_DESCRIPTION),
+            instance_number: number(INSTANCE_NUMBER),
+            number_of_frames: obj.element_u32(NUMBER_OF_FRAMES).unwrap_or(1).max(1),
+            name,
+            path,
+        });
+    }
+    Ok(instances)
+}
+
+/// Listing query parameters, parsed the same way for every listing endpoint.
+struct ListParams(ListQuery);
+
+#[axum::async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for ListParams {
+    type Rejection = (StatusCode, String);
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Query(params) = Query::<Vec<(String, String)>>::from_request_parts(parts, state)
+            .await
+            .map_err(bad_request)?;
+        ListQuery::parse(params)
+            .map(ListParams)
+            .map_err(bad_request)
+    }
+}
+
+const FILE_FIELDS: &[&str] = &[
+    "filename",
+    "size_bytes",
+    "patient_name",
+    "patient_name_parts",
+    "patient_id",
+    "study_date",
+    "modality",
+    "sop_class_uid",
+    "study_uid",
+    "series_uid",
+    "instance_number",
+];
+
+/// Stored uploads, one item per file.
+async fn list_files_handler(
+    State(state): State<AppState>,
+    ListParams(query): ListParams,
+) -> ApiResult<Json<Page>> {
+    let store = state.store.clone();
+    state
+        .workers
+        .run(move || {
+            let items = scan_store(&store)?
+                .into_iter()
+                .map(|instance| {
+                    json!({
+                        "filename": instance.name,
+                        "size_bytes": instance.size_bytes,
+                        "patient_name": instance.patient_name,
+                        "patient_name_parts": instance.patient_name_parts,
+                        "patient_id": instance.patient_id,
+                        "study_date": instance.study_date,
+                        "modality": instance.modality,
+                        "sop_class_uid": instance.sop_class_uid,
+                        "study_uid": instance.study_uid,
+                        "series_uid": instance.series_uid,
+                        "instance_number": instance.instance_number,
+                    })
+                })
+                .collect();
+            listing::page(items, &query, FILE_FIELDS)
+                .map(Json)
+                .map_err(bad_request)
+        })
+        .await
+}
+
+const STUDY_FIELDS: &[&str] = &[
+    "study_uid",
+    "patient_name",
+    "patient_name_parts",
+    "patient_id",
+    "study_date",
+    "modalities",
+    "series_count",
+    "instance_count",
+    "size_bytes",
+];
+
+/// Stored studies, grouping uploads by Study Instance UID.
+async fn list_studies_handler(
+    State(state): State<AppState>,
+    ListParams(query): ListParams,
+) -> ApiResult<Json<Page>> {
+    let store = state.store.clone();
+    state
+        .workers
+        .run(move || {
+            let mut studies: BTreeMap<String, Vec<StoredInstance>> = BTreeMap::new();
+            for instance in scan_store(&store)? {
+                if let Some(uid) = instance.study_uid.clone() {
+                    studies.entry(uid).or_default().push(instance);
+                }
+            }
+            let items = studies
+                .into_iter()
+                .map(|(uid, instances)| {
+                    let first = &instances[0];
+                    let modalities: BTreeSet<&str> = instances
+                        .iter()
+                        .filter_map(|i| i.modality.as_deref())
+                        .collect();
+                    let series: BTreeSet<&str> = instances
+                        .iter()
+                        .filter_map(|i| i.series_uid.as_deref())
+                        .collect();
+                    json!({
+                        "study_uid": uid,
+                        "patient_name": first.patient_name,
+                        "patient_name_parts": first.patient_name_parts,
+                        "patient_id": first.patient_id,
+                        "study_date": first.study_date,
+                        "modalities": modalities,
+                        "series_count": series.len(),
+                        "instance_count": instances.len(),
+                        "size_bytes": total_size(&instances),
+                    })
+                })
+                .collect();
+            listing::page(items, &query, STUDY_FIELDS)
+                .map(Json)
+                .map_err(bad_request)
+        })
+        .await
+}
+
+const SERIES_FIELDS: &[&str] = &[
+    "series_uid",
+    "study_uid",
+    "series_number",
+    "series_description",
+    "modality",
+    "patient_name",
+    "patient_name_parts",
+    "study_date",
+    "instance_count",
+    "size_bytes",
+    "thumbnails_url",
+];
+
+/// Stored series, grouping uploads by Series Instance UID.
+async fn list_series_handler(
+    State(state): State<AppState>,
+    ListParams(query): ListParams,
+) -> ApiResult<Json<Page>> {
+    let store = state.store.clone();
+    state
+        .workers
+        .run(move || {
+            let mut series: BTreeMap<String, Vec<StoredInstance>> = BTreeMap::new();
+            for instance in scan_store(&store)? {
+                if let Some(uid) = instance.series_uid.clone() {
+                    series.entry(uid).or_default().push(instance);
+                }
+            }
+            let items = series
+                .into_iter()
+                .map(|(uid, instances)| {
+                    let first = &instances[0];
+                    json!({
+                        "thumbnails_url": format!("/api/series/{}/thumbnails", uid),
+                        "series_uid": uid,
+                        "study_uid": first.study_uid,
+                        "series_number": first.series_number,
+                        "series_description": first.series_description,
+                        "modality": first.modality,
+                        "patient_name": first.patient_name,
+                        "patient_name_parts": first.patient_name_parts,
+                        "study_date": first.study_date,
+                        "instance_count": instances.len(),
+                        "size_bytes": total_size(&instances),
+                    })
+                })
+                .collect();
+            listing::page(items, &query, SERIES_FIELDS)
+                .map(Json)
+                .map_err(bad_request)
+        })
+        .await
+}
+
+fn total_size(instances: &[StoredInstance]) -> u64 {
+    instances.iter().filter_map(|i| i.size_bytes).sum()
 }
 
 async fn get_metadata(
@@ -118,23 +607,80 @@ async fn get_metadata(
     Path(filename): Path<String>,
 ) -> ApiResult<Json<DetailedMetadata>> {
     // Detailed metadata is read lazily when requested to keep uploads fast.
-    let path = state.store.resolve(&filename).map_err(not_found)?;
-    let detailed = metadata::read_detailed_metadata(&path).map_err(internal_error)?;
-    Ok(Json(detailed))
+    let store = state.store.clone();
+    state
+        .workers
+        .run(move || {
+            let path = store.resolve(&filename).map_err(not_found)?;
+            let detailed = metadata::read_detailed_metadata(&path).map_err(internal_error)?;
+            Ok(Json(detailed))
+        })
+        .await
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct StatsQuery {
+    #[serde(default)]
+    palette_space: PaletteSpace,
+    /// Count padding pixels, which are left out by default.
+    #[serde(default)]
+    include_padding: bool,
+    /// Count pixels hidden by a display shutter, which are left out by default.
+    #[serde(default)]
+    include_shuttered: bool,
 }
 
 async fn get_stats(
     State(state): State<AppState>,
     Path(filename): Path<String>,
+    Query(query): Query<StatsQuery>,
 ) -> ApiResult<Json<PixelStatistics>> {
-    let path = state.store.resolve(&filename).map_err(not_found)?;
-    let stats = stats::pixel_statistics_for_file(&path).map_err(internal_error)?;
-    Ok(Json(stats))
+    let store = state.store.clone();
+    state
+        .workers
+        .run(move || {
+            let path = store.resolve(&filename).map_err(not_found)?;
+            let exclusions = Exclusions {
+                padding: !query.include_padding,
+                shutters: !query.include_shuttered,
+            };
+            let stats = stats::pixel_statistics_with(&path, query.palette_space.into(), exclusions)
+                .map_err(internal_error)?;
+            Ok(Json(stats))
+        })
+        .await
 }
 
 #[derive(Debug, Default, Deserialize)]
 struct HistogramQuery {
     bins: Option<usize>,
+    /// Fixed bin edges; `preset` wins over `min`/`max`/`bin_width`, which go together.
+    preset: Option<HistogramPreset>,
+    min: Option<f32>,
+    max: Option<f32>,
+    bin_width: Option<f32>,
+}
+
+impl HistogramQuery {
+    fn binning(&self) -> ApiResult<stats::Binning> {
+        match (self.preset, self.min, self.max, self.bin_width) {
+            (Some(preset), ..) => Ok(stats::Binning::Fixed(preset.into())),
+            (None, Some(min), Some(max), Some(width)) => stats::FixedBins::new(min, max, width)
+                .map(stats::Binning::Fixed)
+                .map_err(|err| (StatusCode::BAD_REQUEST, err.to_string())),
+            (None, None, None, None) => match self.bins.unwrap_or(256) {
+                0 => Err((
+                    StatusCode::BAD_REQUEST,
+                    "bins must be greater than 0".into(),
+                )),
+                bins => Ok(stats::Binning::Auto(bins)),
+            },
+            _ => Err((
+                StatusCode::BAD_REQUEST,
+                "min, max and bin_width must be given together".into(),
+            )),
+        }
+    }
 }
 
 async fn histogram_handler(
@@ -142,95 +688,1129 @@ async fn histogram_handler(
     Path(filename): Path<String>,
     Query(query): Query<HistogramQuery>,
 ) -> ApiResult<Json<Value>> {
-    let bins = query.bins.unwrap_or(256);
-    if bins == 0 {
-        return Err((
-            StatusCode::BAD_REQUEST,
-            "bins must be greater than 0".into(),
-        ));
+    let binning = query.binning()?;
+    let store = state.store.clone();
+    state
+        .workers
+        .run(move || {
+            let path = store.resolve(&filename).map_err(not_found)?;
+            let histogram =
+                stats::histogram_for_file_with(&path, binning).map_err(internal_error)?;
+            Ok(Json(json!({
+                "bins": histogram.bins,
+                "min": histogram.min,
+                "max": histogram.max,
+                "bin_width": histogram.bin_width,
+                "below": histogram.below,
+                "above": histogram.above
+            })))
+        })
+        .await
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct PreviewQuery {
+    #[serde(default)]
+    frame: u32,
+    window_center: Option<f64>,
+    window_width: Option<f64>,
+    /// Longest edge of the returned image, in pixels.
+    size: Option<u32>,
+    /// Overrides the Accept header.
+    format: Option<PreviewFormat>,
+}
+
+impl PreviewQuery {
+    fn window(&self) -> ApiResult<Option<WindowLevel>> {
+        match (self.window_center, self.window_width) {
+            (Some(center), Some(width)) if width > 0.0 => Ok(Some(WindowLevel { center, width })),
+            (None, None) => Ok(None),
+            _ => Err(bad_request(
+                "window_center and window_width go together (width > 0)",
+            )),
+        }
     }
-    let path = state.store.resolve(&filename).map_err(not_found)?;
-    let histogram = stats::histogram_for_file(&path, bins).map_err(internal_error)?;
-    Ok(Json(json!({
-        "bins": histogram.bins,
-        "min": histogram.min,
-        "max": histogram.max
-    })))
 }
 
+/// Renders a frame so the UI can embed an <img>, as PNG, JPEG or WebP per `format` or the
+/// Accept header. Renders are cached by file content and parameters; `X-Preview-Cache` tells
+/// whether this one was.
 async fn get_image_preview(
     State(state): State<AppState>,
     Path(filename): Path<String>,
+    Query(query): Query<PreviewQuery>,
+    headers: HeaderMap,
+) -> ApiResult<axum::response::Response> {
+    let format = negotiate_format(query.format, &headers)?;
+    let window = query.window()?;
+    preview_response(&state, filename, &query, window, format).await
+}
+
+async fn preview_response(
+    state: &AppState,
+    filename: String,
+    query: &PreviewQuery,
+    window: Option<WindowLevel>,
+    format: PreviewFormat,
+) -> ApiResult<axum::response::Response> {
+    let store = state.store.clone();
+    let previews = state.previews.clone();
+    let (frame, size) = (query.frame, query.size);
+    let (bytes, outcome) = state
+        .workers
+        .run(move || {
+            let path = store.resolve(&filename).map_err(not_found)?;
+            let content_hash = content_hash(&store, &filename, &path)?;
+            let key = PreviewKey::new(content_hash, frame, window, size, format);
+            previews
+                .get_or_render(&key, || {
+                    image::preview_bytes(&path, frame, window, size, format)
+                })
+                .map_err(internal_error)
+        })
+        .await?;
+    Ok((
+        [
+            (
+                header::CONTENT_TYPE,
+                HeaderValue::from_static(format.mime()),
+            ),
+            (header::VARY, HeaderValue::from_static("accept")),
+            (
+                HeaderName::from_static("x-preview-cache"),
+                HeaderValue::from_static(outcome.as_str()),
+            ),
+        ],
+        bytes.as_ref().clone(),
+    )
+        .into_response())
+}
+
+/// Image encoding for a response: an explicit `format` parameter wins, then the best match in
+/// the Accept header (highest q, exact types before wildcards, then listing order). PNG is the
+/// default and what wildcards resolve to; 406 when the header admits none of the formats.
+fn negotiate_format(
+    requested: Option<PreviewFormat>,
+    headers: &HeaderMap,
+) -> ApiResult<PreviewFormat> {
+    if let Some(format) = requested {
+        return Ok(format);
+    }
+    let Some(accept) = headers
+        .get(header::ACCEPT)
+        .and_then(|value| value.to_str().ok())
+    else {
+        return Ok(PreviewFormat::default());
+    };
+
+    let mut best: Option<(f32, bool, PreviewFormat)> = None;
+    for range in accept.split(',') {
+        let mut parts = range.split(';');
+        let mime = parts.next().unwrap_or_default().trim();
+        let quality = parts
+            .filter_map(|param| param.trim().strip_prefix("q="))
+            .find_map(|q| q.trim().parse::<f32>().ok())
+            .unwrap_or(1.0);
+        let (exact, format) = match PreviewFormat::from_mime(mime) {
+            Some(format) => (true, format),
+            None if mime == "image/*" || mime == "*/*" => (false, PreviewFormat::default()),
+            None => continue,
+        };
+        if quality <= 0.0 {
+            continue;
+        }
+        let better = match best {
+            None => true,
+            Some((best_quality, best_exact, _)) => {
+                quality > best_quality || (quality == best_quality && exact && !best_exact)
+            }
+        };
+        if better {
+            best = Some((quality, exact, format));
+        }
+    }
+    best.map(|(_, _, format)| format).ok_or((
+        StatusCode::NOT_ACCEPTABLE,
+        "Available formats: image/png, image/jpeg, image/webp".to_string(),
+    ))
+}
+
+/// Content hash keying cached previews. Derived artifacts are not in the index; their
+/// content is hashed instead.
+fn content_hash(store: &FileStore, name: &str, path: &std::path::Path) -> ApiResult<String> {
+    match store.content_hash(name).map_err(internal_error)? {
+        Some(hash) => Ok(hash),
+        None => Ok(hex::encode(Sha256::digest(
+            std::fs::read(path).map_err(internal_error)?,
+        ))),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct ThumbnailQuery {
+    #[serde(default = "default_thumbnail_size")]
+    size: u32,
+    /// Answer with one image strip instead of a list of URLs.
+    #[serde(default)]
+    sprite: bool,
+    /// Encoding of the sprite and of the listed thumbnails; the sprite otherwise follows the
+    /// Accept header.
+    format: Option<PreviewFormat>,
+}
+
+fn default_thumbnail_size() -> u32 {
+    96
+}
+
+/// Thumbnails of every stored instance of a series, ordered by Instance Number: a JSON list of
+/// preview URLs, or with `sprite=true` a single image with one `size` × `size` cell per instance.
+async fn series_thumbnails_handler(
+    State(state): State<AppState>,
+    Path(series_uid): Path<String>,
+    Query(query): Query<ThumbnailQuery>,
+    headers: HeaderMap,
+) -> ApiResult<axum::response::Response> {
+    if !(16..=512).contains(&query.size) {
+        return Err(bad_request("size must be between 16 and 512"));
+    }
+    let sprite_format = if query.sprite {
+        negotiate_format(query.format, &headers)?
+    } else {
+        PreviewFormat::default()
+    };
+    let store = state.store.clone();
+    let previews = state.previews.clone();
+    let series = series_uid.clone();
+    let size = query.size;
+    let sprite = query.sprite;
+    let (instances, sprite_bytes) = state
+        .workers
+        .run(move || {
+            let instances: Vec<(Option<i64>, String, PathBuf)> = series_instances(&store, &series)?
+                .into_iter()
+                .map(|instance| (instance.instance_number, instance.name, instance.path))
+                .collect();
+
+            let sprite_bytes = if sprite {
+                let mut tiles = Vec::with_capacity(instances.len());
+                for (_, name, path) in &instances {
+                    // Tiles stay lossless PNG so they share cache entries with /api/image.
+                    let key = PreviewKey::new(
+                        content_hash(&store, name, path)?,
+                        0,
+                        None,
+                        Some(size),
+                        PreviewFormat::Png,
+                    );
+                    let (bytes, _) = previews
+                        .get_or_render(&key, || image::preview_png_bytes(path, 0, None, Some(size)))
+                        .map_err(internal_error)?;
+                    tiles.push(bytes.as_ref().clone());
+                }
+                Some(image::sprite_bytes(&tiles, size, sprite_format).map_err(internal_error)?)
+            } else {
+                None
+            };
+            let instances: Vec<(Option<i64>, String)> = instances
+                .into_iter()
+                .map(|(number, name, _)| (number, name))
+                .collect();
+            Ok((instances, sprite_bytes))
+        })
+        .await?;
+
+    if let Some(bytes) = sprite_bytes {
+        return Ok((
+            [
+                (
+                    header::CONTENT_TYPE,
+                    HeaderValue::from_static(sprite_format.mime()),
+                ),
+                (header::VARY, HeaderValue::from_static("accept")),
+            ],
+            bytes,
+        )
+            .into_response());
+    }
+    let format_param = query
+        .format
+        .map(|format| format!("&format={}", format.extension()))
+        .unwrap_or_default();
+    let thumbnails: Vec<Value> = instances
+        .iter()
+        .enumerate()
+        .map(|(index, (number, name))| {
+            json!({
+                "filename": name,
+                "instance_number": number,
+                "url": format!("/api/image/{}?size={}{}", name, size, format_param),
+                "sprite_offset": index as u32 * size,
+            })
+        })
+        .collect();
+    Ok(Json(json!({
+        "series_uid": series_uid,
+        "count": thumbnails.len(),
+        "size": size,
+        "sprite_url": format!(
+            "/api/series/{}/thumbnails?size={}&sprite=true{}",
+            series_uid, size, format_param
+        ),
+        "thumbnails": thumbnails,
+    }))
+    .into_response())
+}
+
+/// Stored instances of a series in viewing order: by Instance Number, unnumbered instances
+/// last in name order. 404 when there are none.
+fn series_instances(store: &FileStore, series_uid: &str) -> ApiResult<Vec<StoredInstance>> {
+    let mut instances: Vec<StoredInstance> = scan_store(store)?
+        .into_iter()
+        .filter(|instance| instance.series_uid.as_deref() == Some(series_uid))
+        .collect();
+    if instances.is_empty() {
+        return Err(not_found(format!(
+            "No stored instances for series {}",
+            series_uid
+        )));
+    }
+    instances.sort_by(|a, b| {
+        (a.instance_number.is_none(), a.instance_number, &a.name).cmp(&(
+            b.instance_number.is_none(),
+            b.instance_number,
+            &b.name,
+        ))
+    });
+    Ok(instances)
+}
+
+/// Series resolved by [`series_instances`], kept until an upload is added or removed, so a
+/// cine viewer fetching one frame per request does not rescan the store every time.
+#[derive(Default)]
+struct SeriesCache(Mutex<HashMap<String, CachedSeries>>);
+
+/// Store generation the series was resolved at, and its instances.
+type CachedSeries = (u64, Arc<Vec<StoredInstance>>);
+
+impl SeriesCache {
+    fn instances(
+        &self,
+        store: &FileStore,
+        series_uid: &str,
+    ) -> ApiResult<Arc<Vec<StoredInstance>>> {
+        // Read before the scan: an upload landing meanwhile makes the entry stale, not wrong.
+        let generation = store.generation().map_err(internal_error)?;
+        if let Some((_, instances)) = self
+            .lock()?
+            .get(series_uid)
+            .filter(|(cached, _)| *cached == generation)
+        {
+            return Ok(instances.clone());
+        }
+        let instances = Arc::new(series_instances(store, series_uid)?);
+        let mut cache = self.lock()?;
+        cache.retain(|_, (cached, _)| *cached == generation);
+        cache.insert(series_uid.to_string(), (generation, instances.clone()));
+        Ok(instances)
+    }
+
+    fn lock(&self) -> ApiResult<std::sync::MutexGuard<'_, HashMap<String, CachedSeries>>> {
+        self.0
+            .lock()
+            .map_err(|_| internal_error("Series cache lock poisoned"))
+    }
+}
+
+/// Every frame of `instances`, instance after instance, as (instance index, frame).
+fn series_frames(instances: &[StoredInstance]) -> Vec<(usize, u32)> {
+    instances
+        .iter()
+        .enumerate()
+        .flat_map(|(index, instance)| (0..instance.number_of_frames).map(move |f| (index, f)))
+        .collect()
+}
+
+/// One window for every frame of a series, so stack scrolling and cine playback keep the
+/// same brightness: the first Window Center/Width stored in the series, or else the full
+/// range of the middle instance's modality values. None for colour images.
+fn series_window(instances: &[StoredInstance]) -> ApiResult<Option<WindowLevel>> {
+    let header = |instance: &StoredInstance| {
+        OpenFileOptions::new()
+            .read_until(PIXEL_DATA)
+            .open_file(&instance.path)
+            .map_err(internal_error)
+    };
+    let middle = &instances[instances.len() / 2];
+    if header(middle)?.element_u32(SAMPLES_PER_PIXEL).unwrap_or(1) > 1 {
+        return Ok(None);
+    }
+    for instance in instances {
+        let obj = header(instance)?;
+        if let Some(window) = lut::voi_windows(&obj).first() {
+            return Ok(Some(WindowLevel {
+                center: window.center,
+                width: window.width,
+            }));
+        }
+    }
+    let range = stats::pixel_statistics_for_file(&middle.path).map_err(internal_error)?;
+    Ok(Some(WindowLevel {
+        center: f64::from(range.min + range.max) / 2.0,
+        width: f64::from(range.max - range.min).max(1.0),
+    }))
+}
+
+/// Everything a viewer needs to scroll through or play a series frame by frame: the frames
+/// in order with their source instance and position, the matrix and spacing, the shared
+/// window, and cine timing when the series carries one.
+async fn series_manifest_handler(
+    State(state): State<AppState>,
+    Path(series_uid): Path<String>,
+) -> ApiResult<Json<Value>> {
+    let store = state.store.clone();
+    let cache = state.series.clone();
+    state
+        .workers
+        .run(move || {
+            let instances = cache.instances(&store, &series_uid)?;
+            let window = series_window(&instances)?;
+            let window_param = window
+                .map(|w| format!("?window_center={}&window_width={}", w.center, w.width))
+                .unwrap_or_default();
+
+            let mut frames = Vec::new();
+            let mut matrix = None;
+            let mut pixel_spacing = None;
+            let mut normal = None;
+            let mut fallback_spacing = None;
+            let mut frame_time_ms = None;
+            let mut frame_rate = None;
+            // One header per instance, however many frames it holds.
+            let headers = instances
+                .iter()
+                .map(|instance| {
+                    OpenFileOptions::new()
+                        .read_until(PIXEL_DATA)
+                        .open_file(&instance.path)
+                        .map_err(internal_error)
+                })
+                .collect::<ApiResult<Vec<_>>>()?;
+            for (index, (instance_index, frame)) in series_frames(&instances).into_iter().enumerate()
+            {
+                let instance = &instances[instance_index];
+                let obj = &headers[instance_index];
+                let geometry = measure::FrameGeometry::for_frame(obj, frame).ok();
+                if frame == 0 {
+                    matrix = matrix.or(obj.element_u32(ROWS).zip(obj.element_u32(COLUMNS)));
+                    fallback_spacing = fallback_spacing.or(obj
+                        .element_f64(SPACING_BETWEEN_SLICES)
+                        .or_else(|| obj.element_f64(SLICE_THICKNESS)));
+                    frame_time_ms = frame_time_ms.or_else(|| {
+                        cine::duration_ms(obj)
+                            .map(|total| total / f64::from(instance.number_of_frames))
+                            .or_else(|| obj.element_f64(FRAME_TIME))
+                            .filter(|&t| t > 0.0)
+                    });
+                    frame_rate = frame_rate.or(obj
+                        .element_f64(RECOMMENDED_DISPLAY_FRAME_RATE)
+                        .or_else(|| obj.element_f64(CINE_RATE))
+                        .filter(|&fps| fps > 0.0));
+                }
+                if let Some(geometry) = &geometry {
+                    pixel_spacing =
+                        pixel_spacing.or(Some([geometry.row_spacing, geometry.column_spacing]));
+                    normal = normal.or(geometry.orientation.map(|o| {
+                        [
+                            o[1] * o[5] - o[2] * o[4],
+                            o[2] * o[3] - o[0] * o[5],
+                            o[0] * o[4] - o[1] * o[3],
+                        ]
+                    }));
+                }
+                frames.push((
+                    index,
+                    instance,
+                    frame,
+                    geometry.and_then(|g| g.position),
+                ));
+            }
+
+            // Median distance between consecutive distinct positions along the normal.
+            let slice_spacing = normal
+                .and_then(|normal: [f64; 3]| {
+                    let mut offsets: Vec<f64> = frames
+                        .iter()
+                        .filter_map(|(_, _, _, position)| {
+                            position.map(|p| p[0] * normal[0] + p[1] * normal[1] + p[2] * normal[2])
+                        })
+                        .collect();
+                    offsets.sort_by(f64::total_cmp);
+                    let mut steps: Vec<f64> = offsets
+                        .windows(2)
+                        .map(|pair| pair[1] - pair[0])
+                        .filter(|step| *step > 1e-3)
+                        .collect();
+                    steps.sort_by(f64::total_cmp);
+                    steps.get(steps.len() / 2).copied()
+                })
+                .or(fallback_spacing);
+
+            let frames: Vec<Value> = frames
+                .into_iter()
+                .map(|(index, instance, frame, position)| {
+                    json!({
+                        "index": index,
+                        "filename": instance.name,
+                        "instance_number": instance.instance_number,
+                        "frame": frame,
+                        "position": position,
+                        "url": format!("/api/series/{}/frames/{}{}", series_uid, index, window_param),
+                    })
+                })
+                .collect();
+            Ok(Json(json!({
+                "series_uid": series_uid,
+                "instance_count": instances.len(),
+                "frame_count": frames.len(),
+                "rows": matrix.map(|(rows, _)| rows),
+                "columns": matrix.map(|(_, columns)| columns),
+                "pixel_spacing": pixel_spacing,
+                "slice_spacing": slice_spacing,
+                "window": window.map(|w| json!({ "center": w.center, "width": w.width })),
+                "cine": {
+                    "frame_time_ms": frame_time_ms,
+                    "frame_rate": frame_rate.or(frame_time_ms.map(|t| 1000.0 / t)),
+                },
+                "frames": frames,
+            })))
+        })
+        .await
+}
+
+/// Frame `n` of a series (counting every frame of every instance, in manifest order),
+/// rendered like `/api/image` but with the series window unless one is given.
+async fn series_frame_handler(
+    State(state): State<AppState>,
+    Path((series_uid, n)): Path<(String, usize)>,
+    Query(query): Query<PreviewQuery>,
+    headers: HeaderMap,
+) -> ApiResult<axum::response::Response> {
+    let format = negotiate_format(query.format, &headers)?;
+    let requested = query.window()?;
+    let store = state.store.clone();
+    let cache = state.series.clone();
+    let (filename, frame, window) = state
+        .workers
+        .run(move || {
+            let instances = cache.instances(&store, &series_uid)?;
+            let frames = series_frames(&instances);
+            let Some(&(index, frame)) = frames.get(n) else {
+                return Err(not_found(format!(
+                    "Series {} has {} frame(s)",
+                    series_uid,
+                    frames.len()
+                )));
+            };
+            let window = match requested {
+                Some(window) => Some(window),
+                None => series_window(&instances)?,
+            };
+            Ok((instances[index].name.clone(), frame, window))
+        })
+        .await?;
+    let query = PreviewQuery { frame, ..query };
+    preview_response(&state, filename, &query, window, format).await
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum JobKind {
+    Anonymize,
+    Transcode,
+    Validate,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct JobParameters {
+    #[serde(default)]
+    profile: AnonymizationProfile,
+    #[serde(default)]
+    retain_device_identity: bool,
+    #[serde(default)]
+    retain_device: Vec<DeviceField>,
+    #[serde(default)]
+    clean_descriptors: bool,
+    #[serde(default)]
+    remove: Vec<RemovableGroup>,
+    record_original: Option<ModificationReason>,
+    transfer_syntax: Option<TransferSyntax>,
+}
+
+/// Stored files a job runs on: exactly one of an explicit list, a study or a series.
+#[derive(Debug, Default, Deserialize)]
+struct JobTarget {
+    files: Option<Vec<String>>,
+    study_uid: Option<String>,
+    series_uid: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct JobRequest {
+    operation: JobKind,
+    #[serde(default)]
+    parameters: JobParameters,
+    target: JobTarget,
+}
+
+/// Queues an operation over a set of stored files and answers 202 with the job's status URL.
+async fn submit_job_handler(
+    State(state): State<AppState>,
+    Json(request): Json<JobRequest>,
 ) -> ApiResult<impl IntoResponse> {
-    let path = state.store.resolve(&filename).map_err(not_found)?;
-    // Render the first frame to PNG bytes so the UI can embed an <img>.
-    let bytes = image::first_frame_png_bytes(&path).map_err(internal_error)?;
-    Ok(([(header::CONTENT_TYPE, "image/png")], bytes))
+    let operation = match request.operation {
+        JobKind::Anonymize => JobOperation::Anonymize {
+            options: anonymize::AnonymizeOptions {
+                profile: request.parameters.profile.into(),
+                retain_device: device_retention(
+                    request.parameters.retain_device_identity,
+                    &request.parameters.retain_device,
+                ),
+                clean_descriptors: request.parameters.clean_descriptors,
+                remove_groups: group_removal(&request.parameters.remove),
+                record_original: request.parameters.record_original.map(Into::into),
+                ..Default::default()
+            },
+        },
+        JobKind::Transcode => JobOperation::Transcode {
+            target: request
+                .parameters
+                .transfer_syntax
+                .unwrap_or(TransferSyntax::ExplicitVrLittleEndian)
+                .into(),
+        },
+        JobKind::Validate => JobOperation::Validate,
+    };
+    let store = state.store.clone();
+    let target = request.target;
+    let inputs = state
+        .workers
+        .run(move || {
+            let inputs: Vec<JobInput> = match (target.files, target.study_uid, target.series_uid) {
+                (Some(files), None, None) => files
+                    .into_iter()
+                    .map(|name| {
+                        let path = store.resolve(&name).map_err(not_found)?;
+                        Ok(JobInput { name, path })
+                    })
+                    .collect::<ApiResult<_>>()?,
+                (None, Some(study), None) => scan_store(&store)?
+                    .into_iter()
+                    .filter(|i| i.study_uid.as_deref() == Some(study.as_str()))
+                    .map(|i| JobInput {
+                        name: i.name,
+                        path: i.path,
+                    })
+                    .collect(),
+                (None, None, Some(series)) => scan_store(&store)?
+                    .into_iter()
+                    .filter(|i| i.series_uid.as_deref() == Some(series.as_str()))
+                    .map(|i| JobInput {
+                        name: i.name,
+                        path: i.path,
+                    })
+                    .collect(),
+                _ => {
+                    return Err(bad_request(
+                        "target takes exactly one of files, study_uid or series_uid",
+                    ))
+                }
+            };
+            if inputs.is_empty() {
+                return Err(not_found("No stored instances match the target"));
+            }
+            Ok(inputs)
+        })
+        .await?;
+
+    let total = inputs.len();
+    let id = state
+        .jobs
+        .submit(operation, inputs)
+        .map_err(internal_error)?;
+    Ok((
+        StatusCode::ACCEPTED,
+        Json(json!({
+            "id": id,
+            "total": total,
+            "status_url": format!("/api/jobs/{}", id),
+        })),
+    ))
+}
+
+/// Status, progress, per-file results and artifacts of a job.
+async fn job_status_handler(
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+) -> ApiResult<Json<JobRecord>> {
+    state
+        .jobs
+        .get(id)
+        .map_err(internal_error)?
+        .map(Json)
+        .ok_or_else(|| not_found(format!("No job {}", id)))
+}
+
+async fn list_jobs_handler(State(state): State<AppState>) -> ApiResult<Json<Vec<JobRecord>>> {
+    state.jobs.list().map(Json).map_err(internal_error)
 }
 
 async fn anonymize_handler(
     State(state): State<AppState>,
     Path(filename): Path<String>,
 ) -> ApiResult<Json<Value>> {
-    let path = state.store.resolve(&filename).map_err(not_found)?;
-    let (anon_name, anon_path) = state
-        .store
-        .derived_path(&filename, "anon", "dcm")
-        .map_err(internal_error)?;
+    let store = state.store.clone();
+    state
+        .workers
+        .run(move || {
+            let path = store.resolve(&filename).map_err(not_found)?;
+            // The same upload anonymized before is served again instead of being redone.
+            if let Some(anon_name) = store
+                .find_derived(&filename, "anon", "")
+                .map_err(internal_error)?
+            {
+                return Ok(Json(
+                    json!({ "success": true, "filename": anon_name, "reused": true }),
+                ));
+            }
+            let (anon_name, anon_path) = store
+                .derived_path(&filename, "anon", "", "dcm")
+                .map_err(internal_error)?;
+
+            // Run anonymization in-place and return the new filename for download.
+            anonymize::process_file(&path, Some(anon_path)).map_err(internal_error)?;
+            store.publish(&anon_name).map_err(store_error)?;
 
-    // Run anonymization in-place and return the new filename for download.
-    anonymize::process_file(&path, Some(anon_path)).map_err(internal_error)?;
+            Ok(Json(
+                json!({ "success": true, "filename": anon_name, "reused": false }),
+            ))
+        })
+        .await
+}
 
-    Ok(Json(json!({ "success": true, "filename": anon_name })))
+#[derive(Debug, Deserialize)]
+struct StudyAnonymizeQuery {
+    #[serde(default)]
+    profile: AnonymizationProfile,
+    #[serde(default)]
+    retain_device_identity: bool,
+    #[serde(default)]
+    clean_descriptors: bool,
+}
+
+/// Anonymizes every stored instance of a study, remapping UIDs consistently across them,
+/// and answers with the results as a ZIP archive. The archive is built in an unnamed
+/// temporary file and streamed from there, so memory does not grow with the study.
+async fn study_anonymize_handler(
+    State(state): State<AppState>,
+    Path(study_uid): Path<String>,
+    Query(query): Query<StudyAnonymizeQuery>,
+) -> ApiResult<impl IntoResponse> {
+    let store = state.store.clone();
+    let options = anonymize::AnonymizeOptions {
+        profile: query.profile.into(),
+        retain_device: device_retention(query.retain_device_identity, &[]),
+        clean_descriptors: query.clean_descriptors,
+        ..Default::default()
+    };
+    let study = study_uid.clone();
+    let archive = state
+        .workers
+        .run(move || {
+            let files: Vec<PathBuf> = scan_store(&store)?
+                .into_iter()
+                .filter(|instance| instance.study_uid.as_deref() == Some(study.as_str()))
+                .map(|instance| instance.path)
+                .collect();
+            if files.is_empty() {
+                return Err(not_found(format!(
+                    "No stored instances for study {}",
+                    study
+                )));
+            }
+            let mut archive = anonymize::anonymize_study_zip(
+                &files,
+                options,
+                tempfile::tempfile().map_err(internal_error)?,
+            )
+            .map_err(internal_error)?;
+            archive.rewind().map_err(internal_error)?;
+            Ok(archive)
+        })
+        .await?;
+    let body = Body::from_stream(ReaderStream::new(tokio::fs::File::from_std(archive)));
+    let disposition = HeaderValue::from_str(&format!(
+        "attachment; filename=\"{}.zip\"",
+        anonymize::remap_uid(&study_uid)
+    ))
+    .map_err(internal_error)?;
+    Ok((
+        [
+            (
+                header::CONTENT_TYPE,
+                HeaderValue::from_static("application/zip"),
+            ),
+            (header::CONTENT_DISPOSITION, disposition),
+        ],
+        body,
+    ))
+}
+
+#[derive(Debug, Deserialize)]
+struct TranscodeQuery {
+    transfer_syntax: Option<TransferSyntax>,
+}
+
+/// Runs a transcode job and streams its progress as Server-Sent Events.
+///
+/// Emits `progress` events carrying a [`ProgressEvent`], then a final `done` (with the derived
+/// filename) or `error` event.
+async fn transcode_events_handler(
+    State(state): State<AppState>,
+    Path(filename): Path<String>,
+    Query(query): Query<TranscodeQuery>,
+) -> ApiResult<Sse<impl Stream<Item = Result<Event, Infallible>>>> {
+    let target: UncompressedTransferSyntax = query
+        .transfer_syntax
+        .unwrap_or(TransferSyntax::ExplicitVrLittleEndian)
+        .into();
+    let store = state.store.clone();
+    let (path, reused, derived) = state
+        .workers
+        .run(move || {
+            let path = store.resolve(&filename).map_err(not_found)?;
+            let reused = store
+                .find_derived(&filename, "transcoded", target.uid())
+                .map_err(internal_error)?;
+            let derived = match reused {
+                Some(_) => None,
+                None => Some(
+                    store
+                        .derived_path(&filename, "transcoded", target.uid(), "dcm")
+                        .map_err(internal_error)?,
+                ),
+            };
+            Ok((path, reused, derived))
+        })
+        .await?;
+    let (tx, rx) = tokio::sync::mpsc::unbounded_channel::<Event>();
+    if let Some((out_name, out_path)) = derived {
+        // The job is bounded by the worker slots but not by the timeout: its progress is
+        // streamed, and the client decides how long to watch.
+        let permit = state
+            .workers
+            .permits
+            .clone()
+            .acquire_owned()
+            .await
+            .map_err(internal_error)?;
+        let store = state.store.clone();
+        tokio::task::spawn_blocking(move || {
+            let _permit = permit;
+            // The sink runs on the blocking thread; events are forwarded to the SSE stream as they happen.
+            let progress_tx = tx.clone();
+            let sink = move |event: ProgressEvent| {
+                if let Ok(sse) = Event::default().event("progress").json_data(&event) {
+                    let _ = progress_tx.send(sse);
+                }
+            };
+            let result = transcode::transcode_with_progress(&path, &out_path, target, &sink)
+                .and_then(|()| store.publish(&out_name));
+            let last = match result {
+                Ok(()) => Event::default()
+                    .event("done")
+                    .json_data(json!({ "success": true, "filename": out_name, "reused": false }))
+                    .unwrap_or_default(),
+                Err(err) => Event::default().event("error").data(err.to_string()),
+            };
+            let _ = tx.send(last);
+        });
+    } else if let Some(name) = reused {
+        // An earlier transcode of the same upload to the same syntax is answered straight away.
+        let done = Event::default()
+            .event("done")
+            .json_data(json!({ "success": true, "filename": name, "reused": true }))
+            .unwrap_or_default();
+        let _ = tx.send(done);
+    }
+
+    let events = stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|event| (Ok(event), rx))
+    });
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()))
 }
 
 async fn validate_handler(
     State(state): State<AppState>,
     Path(filename): Path<String>,
 ) -> ApiResult<Json<Value>> {
-    let path = state.store.resolve(&filename).map_err(not_found)?;
-    let obj = open_file(&path).map_err(internal_error)?;
-    let report = validate::validate_obj(&obj);
-    let summary = validate::as_summary(&report);
-    let (errors, warnings) = validation_messages(&summary);
+    let store = state.store.clone();
+    let (summary, meta_mismatches) = state
+        .workers
+        .run(move || {
+            let path = store.resolve(&filename).map_err(not_found)?;
+            let bytes = std::fs::read(&path).map_err(internal_error)?;
+            let obj = open_file(&path).map_err(internal_error)?;
+            let meta_mismatches =
+                validate::check_file_meta(&obj, lenient::observe_encoding(&bytes));
+            Ok((
+                validate::as_summary(&validate::validate_obj(&obj)),
+                meta_mismatches,
+            ))
+        })
+        .await?;
+    let (mut errors, warnings) = validation_messages(&summary);
+    errors.extend(meta_mismatches.iter().cloned());
 
     Ok(Json(json!({
-        "valid": summary.valid,
+        "valid": summary.valid && meta_mismatches.is_empty(),
         "errors": errors,
         "warnings": warnings,
         "missing_tags": summary.missing_tags,
+        "conditional_violations": summary.conditional_violations,
+        "inconsistencies": summary.inconsistencies,
+        "meta_mismatches": meta_mismatches,
         "has_pixel_data": summary.has_pixel_data
     })))
 }
 
+#[derive(Debug, Default, Deserialize)]
+struct JsonQuery {
+    style: Option<JsonStyle>,
+}
+
 async fn json_handler(
     State(state): State<AppState>,
     Path(filename): Path<String>,
+    Query(query): Query<JsonQuery>,
+) -> ApiResult<Json<Value>> {
+    let store = state.store.clone();
+    state
+        .workers
+        .run(move || {
+            let path = store.resolve(&filename).map_err(not_found)?;
+            let json_string = match query.style.map(Into::into).unwrap_or_default() {
+                json::JsonStyle::Standard => json::to_json_string(&path),
+                json::JsonStyle::Simple => json::to_simple_json_string(&path),
+            }
+            .map_err(internal_error)?;
+            let value: Value = serde_json::from_str(&json_string).map_err(internal_error)?;
+            Ok(Json(value))
+        })
+        .await
+}
+
+/// Drops one upload reference; the file and its derived artifacts go with the last one.
+async fn release_handler(
+    State(state): State<AppState>,
+    Path(filename): Path<String>,
 ) -> ApiResult<Json<Value>> {
-    let path = state.store.resolve(&filename).map_err(not_found)?;
-    let json_string = json::to_json_string(&path).map_err(internal_error)?;
-    let value: Value = serde_json::from_str(&json_string).map_err(internal_error)?;
-    Ok(Json(value))
+    let store = state.store.clone();
+    let removed = state
+        .workers
+        .run(move || store.release(&filename).map_err(not_found))
+        .await?;
+    Ok(Json(json!({ "success": true, "removed": removed })))
+}
+
+/// Artifacts derived from a stored upload, with the operation and parameters behind each.
+async fn derivatives_handler(
+    State(state): State<AppState>,
+    Path(filename): Path<String>,
+) -> ApiResult<Json<Vec<DerivedArtifact>>> {
+    let store = state.store.clone();
+    state
+        .workers
+        .run(move || {
+            store.resolve(&filename).map_err(not_found)?;
+            store
+                .derivatives(&filename)
+                .map(Json)
+                .map_err(internal_error)
+        })
+        .await
 }
 
 async fn download_handler(
     State(state): State<AppState>,
     Path(filename): Path<String>,
-) -> ApiResult<impl IntoResponse> {
-    let path = state.store.resolve(&filename).map_err(not_found)?;
+) -> ApiResult<axum::response::Response> {
+    download_response(&state, filename).await
+}
+
+async fn download_response(
+    state: &AppState,
+    filename: String,
+) -> ApiResult<axum::response::Response> {
+    let store = state.store.clone();
+    let name = filename.clone();
+    let path = state
+        .workers
+        .run(move || store.resolve(&name).map_err(not_found))
+        .await?;
     let bytes = tokio::fs::read(&path).await.map_err(internal_error)?;
     let disposition = HeaderValue::from_str(&format!("attachment; filename=\"{}\"", filename))
         .map_err(internal_error)?;
+    // Job results include ZIP archives next to the DICOM files.
+    let content_type = if filename.ends_with(".zip") {
+        "application/zip"
+    } else {
+        "application/dicom"
+    };
     Ok((
         [
-            (
-                header::CONTENT_TYPE,
-                HeaderValue::from_static("application/dicom"),
-            ),
+            (header::CONTENT_TYPE, HeaderValue::from_static(content_type)),
             (header::CONTENT_DISPOSITION, disposition),
         ],
         bytes,
-    ))
+    )
+        .into_response())
+}
+
+/// Connect and read timeout of the associations opened by [`push_handler`].
+const PUSH_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Calling AE title of the associations opened by [`push_handler`].
+const PUSH_CALLING_AE_TITLE: &str = "DICOM-TOOLS";
+
+#[derive(Debug, Deserialize)]
+struct PushRequest {
+    /// AE title of a node configured with `--push-ae` or `--push-ae-map`.
+    destination: String,
+}
+
+/// Sends a stored file with C-STORE to a node of the server's push AE map, whose AE title
+/// is the called AE. Requests cannot name an address: the endpoint answers 403 for AE
+/// titles outside the map, and for every request when none was configured. The exchange
+/// runs through `scu_async` rather than on a worker slot, so waiting on the network does
+/// not hold up decoding.
+async fn push_handler(
+    State(state): State<AppState>,
+    Path(filename): Path<String>,
+    Json(request): Json<PushRequest>,
+) -> ApiResult<Json<Value>> {
+    if state.push.is_empty() {
+        return Err((
+            StatusCode::FORBIDDEN,
+            "Push is disabled; start the server with --push-ae or --push-ae-map".to_string(),
+        ));
+    }
+    let Some(addr) = state.push.address(&request.destination) else {
+        return Err((
+            StatusCode::FORBIDDEN,
+            format!("{} is not a push destination", request.destination.trim()),
+        ));
+    };
+    let addr = addr.to_string();
+    let store = state.store.clone();
+    let path = state
+        .workers
+        .run(move || store.resolve(&filename).map_err(not_found))
+        .await?;
+    let settings = AssociationSettings {
+        calling_ae_title: PUSH_CALLING_AE_TITLE.to_string(),
+        called_ae_title: request.destination.trim().to_string(),
+        connect_timeout: Some(PUSH_TIMEOUT),
+        read_timeout: Some(PUSH_TIMEOUT),
+        ..Default::default()
+    };
+    let code = scu_async::push(addr, path, settings, Arc::new(NoProgress), None)
+        .await
+        .map_err(|err| (StatusCode::BAD_GATEWAY, format!("{:#}", err)))?;
+    // 0xB000, 0xB006, 0xB007: stored with coercion or element discards.
+    Ok(Json(json!({
+        "success": code == 0 || code & 0xF000 == 0xB000,
+        "status": code,
+    })))
+}
+
+/// Longest lifetime of a share link: one week.
+const MAX_SHARE_TTL_SECS: u64 = 7 * 24 * 3600;
+
+#[derive(Debug, Deserialize)]
+struct ShareRequest {
+    filename: String,
+    #[serde(default)]
+    kind: ShareKind,
+    #[serde(default = "default_share_ttl")]
+    ttl_secs: u64,
+}
+
+fn default_share_ttl() -> u64 {
+    3600
+}
+
+/// Issues a signed link granting download or preview access to one stored file until it
+/// expires; no account is needed to follow it.
+async fn create_share_handler(
+    State(state): State<AppState>,
+    Json(request): Json<ShareRequest>,
+) -> ApiResult<Json<Value>> {
+    if !(1..=MAX_SHARE_TTL_SECS).contains(&request.ttl_secs) {
+        return Err(bad_request(format!(
+            "ttl_secs must be between 1 and {}",
+            MAX_SHARE_TTL_SECS
+        )));
+    }
+    let store = state.store.clone();
+    let name = request.filename.clone();
+    state
+        .workers
+        .run(move || store.resolve(&name).map(|_| ()).map_err(not_found))
+        .await?;
+    let grant = ShareGrant {
+        filename: request.filename,
+        kind: request.kind,
+        expires_at: unix_now() + request.ttl_secs,
+    };
+    let token = state.shares.sign(&grant);
+    Ok(Json(json!({
+        "url": format!("/api/share/{}", token),
+        "token": token,
+        "kind": grant.kind,
+        "filename": grant.filename,
+        "expires_at": grant.expires_at,
+    })))
+}
+
+/// Serves the file a share token grants: 403 for a forged token, 410 once it has expired.
+async fn shared_handler(
+    State(state): State<AppState>,
+    Path(token): Path<String>,
+    Query(query): Query<PreviewQuery>,
+    headers: HeaderMap,
+) -> ApiResult<axum::response::Response> {
+    let grant = state
+        .shares
+        .verify(&token, unix_now())
+        .map_err(|err| match err {
+            ShareError::Expired => (StatusCode::GONE, err.to_string()),
+            _ => (StatusCode::FORBIDDEN, err.to_string()),
+        })?;
+    match grant.kind {
+        ShareKind::Download => download_response(&state, grant.filename).await,
+        ShareKind::Preview => {
+            let format = negotiate_format(query.format, &headers)?;
+            let window = query.window()?;
+            preview_response(&state, grant.filename, &query, window, format).await
+        }
+    }
+}
+
+fn unix_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
 }
 
 fn validation_messages(summary: &ValidationSummary) -> (Vec<String>, Vec<String>) {
@@ -243,11 +1823,13 @@ fn validation_messages(summary: &ValidationSummary) -> (Vec<String>, Vec<String>
             summary.missing_tags.join(", ")
         ));
     }
+    errors.extend(summary.conditional_violations.iter().cloned());
 
     let mut warnings = Vec::new();
     if !summary.has_pixel_data {
         warnings.push("Pixel Data element not present".to_string());
     }
+    warnings.extend(summary.inconsistencies.iter().cloned());
 
     (errors, warnings)
 }
@@ -263,3 +1845,132 @@ fn internal_error<E: Display>(err: E) -> (StatusCode, String) {
 fn not_found<E: Display>(err: E) -> (StatusCode, String) {
     (StatusCode::NOT_FOUND, err.to_string())
 }
+
+fn insufficient_storage<E: Display>(err: E) -> (StatusCode, String) {
+    (StatusCode::INSUFFICIENT_STORAGE, err.to_string())
+}
+
+/// Screen refusals are reported as 422; a screen that failed to run is a server error.
+fn screening_error(err: anyhow::Error) -> (StatusCode, String) {
+    if err.downcast_ref::<Rejection>().is_some() {
+        (StatusCode::UNPROCESSABLE_ENTITY, err.to_string())
+    } else {
+        internal_error(err)
+    }
+}
+
+/// Store failures caused by quotas are reported as 507, everything else as 500.
+fn store_error(err: anyhow::Error) -> (StatusCode, String) {
+    if err.downcast_ref::<QuotaError>().is_some() {
+        insufficient_storage(err)
+    } else {
+        internal_error(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn pool_times_out_and_keeps_slots_bounded() {
+        let pool = BlockingPool::new(WorkerLimits {
+            concurrency: 1,
+            timeout: Duration::from_millis(50),
+        });
+        let slow = pool
+            .run(|| {
+                std::thread::sleep(Duration::from_millis(300));
+                Ok(())
+            })
+            .await;
+        assert_eq!(slow.unwrap_err().0, StatusCode::SERVICE_UNAVAILABLE);
+
+        // The timed-out job still holds the only slot, so the next request waits too.
+        let queued = pool.run(|| Ok(1)).await;
+        assert_eq!(queued.unwrap_err().0, StatusCode::SERVICE_UNAVAILABLE);
+
+        let patient = BlockingPool {
+            timeout: Duration::from_secs(5),
+            ..pool.clone()
+        };
+        assert_eq!(patient.run(|| Ok(2)).await, Ok(2));
+    }
+
+    #[test]
+    fn series_frames_span_instances_with_one_window() {
+        let source = tempfile::tempdir().unwrap();
+        let spec = crate::synth::SynthSpec {
+            frames: 2,
+            instances: 3,
+            ..crate::synth::SynthSpec::default()
+        };
+        let files = crate::synth::write_series(&spec, source.path()).unwrap();
+        let root = tempfile::tempdir().unwrap();
+        let store = FileStore::new(root.path()).unwrap();
+        // Stored out of order: the series is still read by Instance Number.
+        for path in files.iter().rev() {
+            store.save(None, &std::fs::read(path).unwrap()).unwrap();
+        }
+        let series_uid = scan_store(&store).unwrap()[0].series_uid.clone().unwrap();
+
+        let instances = series_instances(&store, &series_uid).unwrap();
+        let numbers: Vec<Option<i64>> = instances.iter().map(|i| i.instance_number).collect();
+        assert_eq!(numbers, [Some(1), Some(2), Some(3)]);
+        assert_eq!(
+            series_frames(&instances),
+            [(0, 0), (0, 1), (1, 0), (1, 1), (2, 0), (2, 1)]
+        );
+        let window = series_window(&instances)
+            .unwrap()
+            .expect("monochrome window");
+        assert!(window.width >= 1.0);
+        assert!(matches!(
+            series_instances(&store, "1.2.3"),
+            Err((StatusCode::NOT_FOUND, _))
+        ));
+
+        // Cached until an upload is added or removed.
+        let cache = SeriesCache::default();
+        let cached = cache.instances(&store, &series_uid).unwrap();
+        assert!(Arc::ptr_eq(
+            &cached,
+            &cache.instances(&store, &series_uid).unwrap()
+        ));
+        assert!(store.release(&instances[2].name).unwrap());
+        assert_eq!(cache.instances(&store, &series_uid).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn format_negotiation_prefers_query_then_accept() {
+        let accept = |value: &'static str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(header::ACCEPT, HeaderValue::from_static(value));
+            headers
+        };
+        let negotiate = |value| negotiate_format(None, &accept(value)).map_err(|e| e.0);
+
+        assert_eq!(
+            negotiate_format(None, &HeaderMap::new()),
+            Ok(PreviewFormat::Png)
+        );
+        assert_eq!(
+            negotiate_format(Some(PreviewFormat::Jpeg), &accept("image/webp")),
+            Ok(PreviewFormat::Jpeg)
+        );
+        // Typical browser <img> Accept: the explicit WebP entry beats the wildcards.
+        assert_eq!(
+            negotiate("image/avif,image/webp,image/apng,image/*,*/*;q=0.8"),
+            Ok(PreviewFormat::Webp)
+        );
+        assert_eq!(
+            negotiate("image/webp;q=0.5, image/jpeg"),
+            Ok(PreviewFormat::Jpeg)
+        );
+        assert_eq!(negotiate("*/*"), Ok(PreviewFormat::Png));
+        assert_eq!(
+            negotiate("image/avif, image/png;q=0"),
+            Err(StatusCode::NOT_ACCEPTABLE)
+        );
+    }
+}
@@ -0,0 +1,541 @@
+//
+// worklist.rs
+// Dicom-Tools-rs
+//
+// Modality worklist for the SCP: scheduled procedure steps loaded from a JSON or CSV file, or
+// derived from the studies in a directory, matched against C-FIND identifiers (PS3.4 K.6).
+// The SCU side builds those identifiers and reads the answers back into items.
+//
+// Thales Matheus Mendonça Santos - November 2025
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use dicom::core::value::{DataSetSequence, PrimitiveValue, Value};
+use dicom::core::{DataElement, Tag, VR};
+use dicom::object::{InMemDicomObject, OpenFileOptions};
+use serde::{Deserialize, Serialize};
+use walkdir::WalkDir;
+
+use crate::dicom_access::ElementAccess;
+
+/// Modality Worklist Information Model - FIND.
+pub const MODALITY_WORKLIST_FIND: &str = "1.2.840.10008.5.1.4.31";
+
+const SPECIFIC_CHARACTER_SET: Tag = Tag(0x0008, 0x0005);
+const STUDY_DATE: Tag = Tag(0x0008, 0x0020);
+const STUDY_TIME: Tag = Tag(0x0008, 0x0030);
+const ACCESSION_NUMBER: Tag = Tag(0x0008, 0x0050);
+const MODALITY: Tag = Tag(0x0008, 0x0060);
+const STUDY_DESCRIPTION: Tag = Tag(0x0008, 0x1030);
+const PATIENT_NAME: Tag = Tag(0x0010, 0x0010);
+const PATIENT_ID: Tag = Tag(0x0010, 0x0020);
+const PATIENT_BIRTH_DATE: Tag = Tag(0x0010, 0x0030);
+const PATIENT_SEX: Tag = Tag(0x0010, 0x0040);
+const STUDY_INSTANCE_UID: Tag = Tag(0x0020, 0x000D);
+const REQUESTED_PROCEDURE_DESCRIPTION: Tag = Tag(0x0032, 0x1060);
+const SCHEDULED_STATION_AE_TITLE: Tag = Tag(0x0040, 0x0001);
+const SCHEDULED_START_DATE: Tag = Tag(0x0040, 0x0002);
+const SCHEDULED_START_TIME: Tag = Tag(0x0040, 0x0003);
+const SCHEDULED_PERFORMING_PHYSICIAN: Tag = Tag(0x0040, 0x0006);
+const SCHEDULED_STEP_DESCRIPTION: Tag = Tag(0x0040, 0x0007);
+const SCHEDULED_STEP_ID: Tag = Tag(0x0040, 0x0009);
+const SCHEDULED_STEP_SEQUENCE: Tag = Tag(0x0040, 0x0100);
+const REQUESTED_PROCEDURE_ID: Tag = Tag(0x0040, 0x1001);
+const PIXEL_DATA: Tag = Tag(0x7FE0, 0x0010);
+
+/// Return keys at the top level of a worklist item.
+const ITEM_KEYS: [(Tag, VR); 8] = [
+    (ACCESSION_NUMBER, VR::SH),
+    (PATIENT_NAME, VR::PN),
+    (PATIENT_ID, VR::LO),
+    (PATIENT_BIRTH_DATE, VR::DA),
+    (PATIENT_SEX, VR::CS),
+    (STUDY_INSTANCE_UID, VR::UI),
+    (REQUESTED_PROCEDURE_DESCRIPTION, VR::LO),
+    (REQUESTED_PROCEDURE_ID, VR::SH),
+];
+
+/// Return keys inside the Scheduled Procedure Step Sequence item.
+const STEP_KEYS: [(Tag, VR); 7] = [
+    (MODALITY, VR::CS),
+    (SCHEDULED_STATION_AE_TITLE, VR::AE),
+    (SCHEDULED_START_DATE, VR::DA),
+    (SCHEDULED_START_TIME, VR::TM),
+    (SCHEDULED_PERFORMING_PHYSICIAN, VR::PN),
+    (SCHEDULED_STEP_DESCRIPTION, VR::LO),
+    (SCHEDULED_STEP_ID, VR::SH),
+];
+
+/// One scheduled procedure step. Field names are the JSON keys and CSV column headers;
+/// dates are `YYYYMMDD` and times `HHMMSS`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WorklistItem {
+    pub patient_name: Option<String>,
+    pub patient_id: Option<String>,
+    pub patient_birth_date: Option<String>,
+    pub patient_sex: Option<String>,
+    pub accession_number: Option<String>,
+    pub study_instance_uid: Option<String>,
+    pub requested_procedure_id: Option<String>,
+    pub requested_procedure_description: Option<String>,
+    pub modality: Option<String>,
+    pub scheduled_station_ae_title: Option<String>,
+    pub scheduled_date: Option<String>,
+    pub scheduled_time: Option<String>,
+    pub scheduled_performing_physician: Option<String>,
+    pub scheduled_procedure_step_description: Option<String>,
+    pub scheduled_procedure_step_id: Option<String>,
+}
+
+impl WorklistItem {
+    fn value(&self, tag: Tag) -> Option<&str> {
+        let value = match tag {
+            ACCESSION_NUMBER => &self.accession_number,
+            PATIENT_NAME => &self.patient_name,
+            PATIENT_ID => &self.patient_id,
+            PATIENT_BIRTH_DATE => &self.patient_birth_date,
+            PATIENT_SEX => &self.patient_sex,
+            STUDY_INSTANCE_UID => &self.study_instance_uid,
+            REQUESTED_PROCEDURE_DESCRIPTION => &self.requested_procedure_description,
+            REQUESTED_PROCEDURE_ID => &self.requested_procedure_id,
+            MODALITY => &self.modality,
+            SCHEDULED_STATION_AE_TITLE => &self.scheduled_station_ae_title,
+            SCHEDULED_START_DATE => &self.scheduled_date,
+            SCHEDULED_START_TIME => &self.scheduled_time,
+            SCHEDULED_PERFORMING_PHYSICIAN => &self.scheduled_performing_physician,
+            SCHEDULED_STEP_DESCRIPTION => &self.scheduled_procedure_step_description,
+            SCHEDULED_STEP_ID => &self.scheduled_procedure_step_id,
+            _ => &None,
+        };
+        value.as_deref().map(str::trim).filter(|v| !v.is_empty())
+    }
+
+    fn value_mut(&mut self, tag: Tag) -> Option<&mut Option<String>> {
+        Some(match tag {
+            ACCESSION_NUMBER => &mut self.accession_number,
+            PATIENT_NAME => &mut self.patient_name,
+            PATIENT_ID => &mut self.patient_id,
+            PATIENT_BIRTH_DATE => &mut self.patient_birth_date,
+            PATIENT_SEX => &mut self.patient_sex,
+            STUDY_INSTANCE_UID => &mut self.study_instance_uid,
+            REQUESTED_PROCEDURE_DESCRIPTION => &mut self.requested_procedure_description,
+            REQUESTED_PROCEDURE_ID => &mut self.requested_procedure_id,
+            MODALITY => &mut self.modality,
+            SCHEDULED_STATION_AE_TITLE => &mut self.scheduled_station_ae_title,
+            SCHEDULED_START_DATE => &mut self.scheduled_date,
+            SCHEDULED_START_TIME => &mut self.scheduled_time,
+            SCHEDULED_PERFORMING_PHYSICIAN => &mut self.scheduled_performing_physician,
+            SCHEDULED_STEP_DESCRIPTION => &mut self.scheduled_procedure_step_description,
+            SCHEDULED_STEP_ID => &mut self.scheduled_procedure_step_id,
+            _ => return None,
+        })
+    }
+
+    /// Read a C-FIND response identifier back into an item; step attributes come from the
+    /// first Scheduled Procedure Step Sequence item.
+    pub fn from_identifier(identifier: &InMemDicomObject) -> Self {
+        let mut item = WorklistItem::default();
+        let mut fill = |source: &InMemDicomObject, keys: &[(Tag, VR)]| {
+            for &(tag, _) in keys {
+                let value = source
+                    .element_str(tag)
+                    .map(|v| v.trim_matches(['\0', ' ']).to_string())
+                    .filter(|v| !v.is_empty());
+                if let Some(slot) = item.value_mut(tag) {
+                    *slot = value;
+                }
+            }
+        };
+        fill(identifier, &ITEM_KEYS);
+        if let Some(step) = identifier.sequence_items(SCHEDULED_STEP_SEQUENCE).first() {
+            fill(step, &STEP_KEYS);
+        }
+        item
+    }
+
+    /// Whether every key of `identifier` (and of its step item) matches this item.
+    fn matches(&self, identifier: &InMemDicomObject) -> bool {
+        let keys_match = |query: &InMemDicomObject, keys: &[(Tag, VR)]| {
+            keys.iter().all(|&(tag, vr)| match query_value(query, tag) {
+                None => true,
+                Some(pattern) => self
+                    .value(tag)
+                    .is_some_and(|value| value_matches(vr, &pattern, value)),
+            })
+        };
+        let step_query = identifier.sequence_items(SCHEDULED_STEP_SEQUENCE).first();
+        keys_match(identifier, &ITEM_KEYS)
+            && step_query.is_none_or(|step| keys_match(step, &STEP_KEYS))
+    }
+
+    /// Response identifier: the requested keys, filled from this item. An empty or itemless
+    /// step sequence asks for every step attribute.
+    fn response(&self, identifier: &InMemDicomObject) -> InMemDicomObject {
+        let mut out = InMemDicomObject::new_empty();
+        for &(tag, vr) in &ITEM_KEYS {
+            if identifier.element(tag).is_ok() {
+                out.put(self.element(tag, vr));
+            }
+        }
+        if identifier.element(SCHEDULED_STEP_SEQUENCE).is_ok() {
+            let requested = identifier.sequence_items(SCHEDULED_STEP_SEQUENCE).first();
+            let mut step = InMemDicomObject::new_empty();
+            for &(tag, vr) in &STEP_KEYS {
+                if requested.is_none_or(|r| r.iter().next().is_none() || r.element(tag).is_ok()) {
+                    step.put(self.element(tag, vr));
+                }
+            }
+            out.put(DataElement::new(
+                SCHEDULED_STEP_SEQUENCE,
+                VR::SQ,
+                DataSetSequence::from(vec![step]),
+            ));
+        }
+        // Values are written as UTF-8; say so when any of them needs it.
+        let non_ascii = out.iter().any(|elem| {
+            elem.to_str().is_ok_and(|text| !text.is_ascii())
+                || elem.items().is_some_and(|items| {
+                    items
+                        .iter()
+                        .flat_map(|item| item.iter())
+                        .any(|e| e.to_str().is_ok_and(|text| !text.is_ascii()))
+                })
+        });
+        if non_ascii || identifier.element(SPECIFIC_CHARACTER_SET).is_ok() {
+            out.put(DataElement::new(
+                SPECIFIC_CHARACTER_SET,
+                VR::CS,
+                PrimitiveValue::from(if non_ascii { "ISO_IR 192" } else { "" }),
+            ));
+        }
+        out
+    }
+
+    fn element(&self, tag: Tag, vr: VR) -> DataElement<InMemDicomObject> {
+        let value = match self.value(tag) {
+            Some(value) => PrimitiveValue::from(value),
+            None => PrimitiveValue::Empty,
+        };
+        DataElement::new(tag, vr, Value::Primitive(value))
+    }
+}
+
+/// Non-empty value of a matching key, or `None` for universal matching.
+pub(crate) fn query_value(query: &InMemDicomObject, tag: Tag) -> Option<String> {
+    query
+        .element_str(tag)
+        .map(|value| value.trim_matches(['\0', ' ']).to_string())
+        .filter(|value| !value.is_empty() && value != "*")
+}
+
+/// PS3.4 C.2.2.2 matching: UID lists, date/time ranges, and single values with `*`/`?`
+/// wildcards elsewhere (person names case-insensitively).
+pub(crate) fn value_matches(vr: VR, pattern: &str, value: &str) -> bool {
+    match vr {
+        VR::UI => pattern.split('\\').any(|uid| uid.trim() == value),
+        VR::DA | VR::TM => match pattern.split_once('-') {
+            Some((from, to)) => {
+                let value = comparable(vr, value);
+                (from.is_empty() || comparable(vr, from) <= value)
+                    && (to.is_empty() || value <= comparable(vr, to))
+            }
+            None => comparable(vr, pattern) == comparable(vr, value),
+        },
+        VR::PN => wildcard_match(&pattern.to_lowercase(), &value.to_lowercase()),
+        _ => wildcard_match(pattern, value),
+    }
+}
+
+/// Dates as `YYYYMMDD` and times as `HHMMSS`, so that ranges compare as strings.
+fn comparable(vr: VR, text: &str) -> String {
+    let digits: String = text
+        .trim()
+        .chars()
+        .take_while(|c| *c != '.')
+        .filter(char::is_ascii_digit)
+        .collect();
+    match vr {
+        VR::TM => format!("{:0<6}", &digits[..digits.len().min(6)]),
+        _ => digits,
+    }
+}
+
+fn wildcard_match(pattern: &str, value: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let value: Vec<char> = value.chars().collect();
+    // Iterative matching with backtracking to the last `*`.
+    let (mut p, mut v) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while v < value.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == value[v]) {
+            p += 1;
+            v += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, v));
+            p += 1;
+        } else if let Some((star_p, star_v)) = star {
+            p = star_p + 1;
+            v = star_v + 1;
+            star = Some((star_p, star_v + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// Scheduled procedure steps answered by the SCP.
+#[derive(Debug, Clone, Default)]
+pub struct Worklist {
+    items: Vec<WorklistItem>,
+}
+
+impl Worklist {
+    pub fn new(items: Vec<WorklistItem>) -> Self {
+        Self { items }
+    }
+
+    /// Load a `.json` array of items or a `.csv` file with one item per row.
+    pub fn load(path: &Path) -> Result<Self> {
+        let extension = path
+            .extension()
+            .map(|e| e.to_string_lossy().to_ascii_lowercase());
+        let items = match extension.as_deref() {
+            Some("json") => {
+                let text = fs::read_to_string(path)
+                    .with_context(|| format!("Failed to read {:?}", path))?;
+                serde_json::from_str(&text)
+                    .with_context(|| format!("Invalid worklist JSON in {:?}", path))?
+            }
+            Some("csv") => csv::Reader::from_path(path)
+                .with_context(|| format!("Failed to read {:?}", path))?
+                .deserialize()
+                .collect::<Result<Vec<WorklistItem>, _>>()
+                .with_context(|| format!("Invalid worklist CSV in {:?}", path))?,
+            _ => bail!("Worklist {:?} must be a .json or .csv file", path),
+        };
+        Ok(Self { items })
+    }
+
+    /// One item per study found under `dir`, scheduled at the study's date and time.
+    pub fn from_directory(dir: &Path) -> Result<Self> {
+        if !dir.is_dir() {
+            bail!("{:?} is not a directory", dir);
+        }
+        let mut studies: BTreeMap<String, WorklistItem> = BTreeMap::new();
+        for entry in WalkDir::new(dir).into_iter().filter_map(|e| e.ok()) {
+            if !entry.file_type().is_file() {
+                continue;
+            }
+            let Ok(obj) = OpenFileOptions::new()
+                .read_until(PIXEL_DATA)
+                .open_file(entry.path())
+            else {
+                continue;
+            };
+            let text = |tag: Tag| {
+                obj.element_str(tag)
+                    .map(|s| s.trim_end_matches(['\0', ' ']).to_string())
+                    .filter(|s| !s.is_empty())
+            };
+            let Some(study_uid) = text(STUDY_INSTANCE_UID) else {
+                continue;
+            };
+            studies
+                .entry(study_uid.clone())
+                .or_insert_with(|| WorklistItem {
+                    patient_name: text(PATIENT_NAME),
+                    patient_id: text(PATIENT_ID),
+                    patient_birth_date: text(PATIENT_BIRTH_DATE),
+                    patient_sex: text(PATIENT_SEX),
+                    accession_number: text(ACCESSION_NUMBER),
+                    study_instance_uid: Some(study_uid),
+                    requested_procedure_id: text(ACCESSION_NUMBER),
+                    requested_procedure_description: text(STUDY_DESCRIPTION),
+                    modality: text(MODALITY),
+                    scheduled_date: text(STUDY_DATE),
+                    scheduled_time: text(STUDY_TIME),
+                    scheduled_procedure_step_description: text(STUDY_DESCRIPTION),
+                    ..WorklistItem::default()
+                });
+        }
+        Ok(Self {
+            items: studies.into_values().collect(),
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    /// Response identifiers of the items matching a C-FIND identifier, in worklist order.
+    pub fn query(&self, identifier: &InMemDicomObject) -> Vec<InMemDicomObject> {
+        self.items
+            .iter()
+            .filter(|item| item.matches(identifier))
+            .map(|item| item.response(identifier))
+            .collect()
+    }
+}
+
+/// Matching keys of a worklist query; `None` matches everything.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WorklistQuery {
+    /// `YYYYMMDD`, or a `YYYYMMDD-YYYYMMDD` range with either end open.
+    pub scheduled_date: Option<String>,
+    pub modality: Option<String>,
+    pub station_ae_title: Option<String>,
+}
+
+impl WorklistQuery {
+    /// C-FIND identifier carrying the matching keys and every other worklist attribute
+    /// as an empty return key.
+    pub fn identifier(&self) -> InMemDicomObject {
+        let keys = WorklistItem {
+            scheduled_date: self.scheduled_date.clone(),
+            modality: self.modality.clone(),
+            scheduled_station_ae_title: self.station_ae_title.clone(),
+            ..WorklistItem::default()
+        };
+        let mut identifier = InMemDicomObject::new_empty();
+        for &(tag, vr) in &ITEM_KEYS {
+            identifier.put(keys.element(tag, vr));
+        }
+        let mut step = InMemDicomObject::new_empty();
+        for &(tag, vr) in &STEP_KEYS {
+            step.put(keys.element(tag, vr));
+        }
+        identifier.put(DataElement::new(
+            SCHEDULED_STEP_SEQUENCE,
+            VR::SQ,
+            DataSetSequence::from(vec![step]),
+        ));
+        identifier
+    }
+}
+
+/// Print scheduled procedure steps as a table, or as a JSON array when `json` is set.
+pub fn print_items(items: &[WorklistItem], json: bool) -> Result<()> {
+    if json {
+        let text = serde_json::to_string_pretty(items).context("Failed to serialize worklist")?;
+        println!("{}", text);
+        return Ok(());
+    }
+    println!(
+        "{:<10} {:<6} {:<4} {:<16} {:<16} {:<24} {:<16} Step",
+        "Date", "Time", "Mod", "Station AE", "Patient ID", "Patient Name", "Accession"
+    );
+    for item in items {
+        let field = |value: &Option<String>| value.clone().unwrap_or_default();
+        println!(
+            "{:<10} {:<6} {:<4} {:<16} {:<16} {:<24} {:<16} {}",
+            field(&item.scheduled_date),
+            field(&item.scheduled_time)
+                .chars()
+                .take(6)
+                .collect::<String>(),
+            field(&item.modality),
+            field(&item.scheduled_station_ae_title),
+            field(&item.patient_id),
+            field(&item.patient_name),
+            field(&item.accession_number),
+            item.scheduled_procedure_step_description
+                .as_deref()
+                .or(item.requested_procedure_description.as_deref())
+                .unwrap_or_default()
+        );
+    }
+    println!("{} scheduled procedure step(s)", items.len());
+    Ok(())
+}
+
+/// Where the SCP reads its worklist from; it is reloaded for every query so edits to the
+/// file or new studies in the directory are picked up.
+#[derive(Debug, Clone)]
+pub enum WorklistSource {
+    File(PathBuf),
+    Directory(PathBuf),
+}
+
+impl WorklistSource {
+    pub fn load(&self) -> Result<Worklist> {
+        match self {
+            WorklistSource::File(path) => Worklist::load(path),
+            WorklistSource::Directory(dir) => Worklist::from_directory(dir),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn put(obj: &mut InMemDicomObject, tag: Tag, vr: VR, value: &str) {
+        obj.put(DataElement::new(tag, vr, PrimitiveValue::from(value)));
+    }
+
+    #[test]
+    fn queries_match_wildcards_ranges_and_step_keys() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("worklist.csv");
+        fs::write(
+            &path,
+            "patient_name,patient_id,accession_number,modality,scheduled_station_ae_title,scheduled_date,scheduled_time\n\
+             Doe^Jane,P1,A1,CT,CT01,20250102,0930\n\
+             Roe^Rich,P2,A2,MR,MR01,20250105,143000\n",
+        )
+        .unwrap();
+        let worklist = Worklist::load(&path).unwrap();
+        assert_eq!(worklist.len(), 2);
+
+        let mut step = InMemDicomObject::new_empty();
+        put(&mut step, MODALITY, VR::CS, "CT");
+        put(&mut step, SCHEDULED_START_DATE, VR::DA, "20250101-20250103");
+        step.put(DataElement::new(
+            SCHEDULED_START_TIME,
+            VR::TM,
+            PrimitiveValue::Empty,
+        ));
+        let mut query = InMemDicomObject::new_empty();
+        put(&mut query, PATIENT_NAME, VR::PN, "doe*");
+        query.put(DataElement::new(PATIENT_ID, VR::LO, PrimitiveValue::Empty));
+        query.put(DataElement::new(
+            SCHEDULED_STEP_SEQUENCE,
+            VR::SQ,
+            DataSetSequence::from(vec![step]),
+        ));
+
+        let answers = worklist.query(&query);
+        assert_eq!(answers.len(), 1);
+        assert_eq!(answers[0].element_str(PATIENT_ID).as_deref(), Some("P1"));
+        // Only requested keys come back.
+        assert!(answers[0].element(ACCESSION_NUMBER).is_err());
+        let step = &answers[0].sequence_items(SCHEDULED_STEP_SEQUENCE)[0];
+        assert_eq!(
+            step.element_str(SCHEDULED_START_TIME).as_deref(),
+            Some("0930")
+        );
+        assert!(step.element(SCHEDULED_STATION_AE_TITLE).is_err());
+
+        assert!(value_matches(VR::TM, "-1000", "0930"));
+        assert!(!value_matches(VR::TM, "1000-", "0930"));
+        assert!(wildcard_match("R?e^*", "Roe^Rich"));
+        assert!(!wildcard_match("R?e", "Roe^Rich"));
+
+        let mut everyone = InMemDicomObject::new_empty();
+        everyone.put(DataElement::new(
+            PATIENT_NAME,
+            VR::PN,
+            PrimitiveValue::Empty,
+        ));
+        assert_eq!(worklist.query(&everyone).len(), 2);
+    }
+}
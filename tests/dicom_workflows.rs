@@ -12,131 +12,22 @@ use dicom::core::{DataElement, PrimitiveValue, Tag, VR};
 use dicom::dictionary_std::StandardDataDictionary;
 use dicom::object::{FileDicomObject, FileMetaTableBuilder, InMemDicomObject};
 use dicom::transfer_syntax::entries::EXPLICIT_VR_LITTLE_ENDIAN;
-use dicom_tools::{anonymize, image, json, metadata, stats, transcode, validate};
+use dicom_tools::{
+    annotations, anonymize, audit, availability, batch, capabilities, derivation, dimse,
+    dimse_trace, echo_scan, fhir, float_pixels, harmonize, image, jobs, joint_histogram, json,
+    kernels, lenient, measurement_report, metadata, mpps, parametric_map, progress, roi_mask,
+    router, scp, scu, scu_async, send_queue, series_split, size_report, stats, storage, stow,
+    synth, testing, time_curves, transcode, validate, volume, wado, web, worklist,
+};
 use tempfile::{tempdir, TempDir};
 
 fn build_test_dicom() -> (TempDir, PathBuf) {
-    // Construct a tiny Secondary Capture instance with predictable pixel values.
+    // A tiny Secondary Capture instance with predictable pixel values.
     let dir = tempdir().expect("tempdir");
     let path = dir.path().join("sample.dcm");
-
-    let mut obj = InMemDicomObject::new_empty_with_dict(StandardDataDictionary);
-    obj.put(DataElement::new(
-        Tag(0x0010, 0x0010),
-        VR::PN,
-        PrimitiveValue::from("Test^Patient"),
-    ));
-    obj.put(DataElement::new(
-        Tag(0x0010, 0x0020),
-        VR::LO,
-        PrimitiveValue::from("PAT123"),
-    ));
-    obj.put(DataElement::new(
-        Tag(0x0008, 0x0060),
-        VR::CS,
-        PrimitiveValue::from("OT"),
-    ));
-    obj.put(DataElement::new(
-        Tag(0x0008, 0x0020),
-        VR::DA,
-        PrimitiveValue::from("20240101"),
-    ));
-    obj.put(DataElement::new(
-        Tag(0x0008, 0x0016),
-        VR::UI,
-        PrimitiveValue::from("1.2.840.10008.5.1.4.1.1.7"),
-    ));
-    obj.put(DataElement::new(
-        Tag(0x0008, 0x0018),
-        VR::UI,
-        PrimitiveValue::from("1.2.826.0.1.3680043.2.1125.1"),
-    ));
-
-    obj.put(DataElement::new(
-        Tag(0x0028, 0x0010),
-        VR::US,
-        PrimitiveValue::from(2_u16),
-    )); // Rows
-    obj.put(DataElement::new(
-        Tag(0x0028, 0x0011),
-        VR::US,
-        PrimitiveValue::from(2_u16),
-    )); // Columns
-    obj.put(DataElement::new(
-        Tag(0x0028, 0x0002),
-        VR::US,
-        PrimitiveValue::from(1_u16),
-    )); // Samples per pixel
-    obj.put(DataElement::new(
-        Tag(0x0028, 0x0100),
-        VR::US,
-        PrimitiveValue::from(8_u16),
-    )); // Bits Allocated
-    obj.put(DataElement::new(
-        Tag(0x0028, 0x0101),
-        VR::US,
-        PrimitiveValue::from(8_u16),
-    )); // Bits Stored
-    obj.put(DataElement::new(
-        Tag(0x0028, 0x0102),
-        VR::US,
-        PrimitiveValue::from(7_u16),
-    )); // High Bit
-    obj.put(DataElement::new(
-        Tag(0x0028, 0x0103),
-        VR::US,
-        PrimitiveValue::from(0_u16),
-    )); // Pixel Representation
-    obj.put(DataElement::new(
-        Tag(0x0028, 0x0004),
-        VR::CS,
-        PrimitiveValue::from("MONOCHROME2"),
-    ));
-    obj.put(DataElement::new(
-        Tag(0x0028, 0x0008),
-        VR::IS,
-        PrimitiveValue::from("1"),
-    )); // Number of Frames
-    obj.put(DataElement::new(
-        Tag(0x0028, 0x1052),
-        VR::DS,
-        PrimitiveValue::from("-1024"),
-    )); // Rescale Intercept
-    obj.put(DataElement::new(
-        Tag(0x0028, 0x1053),
-        VR::DS,
-        PrimitiveValue::from("2"),
-    )); // Rescale Slope
-    obj.put(DataElement::new(
-        Tag(0x0028, 0x1050),
-        VR::DS,
-        PrimitiveValue::from("50"),
-    )); // Window Center
-    obj.put(DataElement::new(
-        Tag(0x0028, 0x1051),
-        VR::DS,
-        PrimitiveValue::from("150"),
-    )); // Window Width
-
-    obj.put(DataElement::new(
-        Tag(0x7fe0, 0x0010),
-        VR::OB,
-        PrimitiveValue::from(vec![0, 64, 128, 255]),
-    ));
-
-    let meta = FileMetaTableBuilder::new()
-        .transfer_syntax(EXPLICIT_VR_LITTLE_ENDIAN.uid())
-        .media_storage_sop_class_uid("1.2.840.10008.5.1.4.1.1.7")
-        .media_storage_sop_instance_uid("1.2.826.0.1.3680043.2.1125.1")
-        .build()
-        .expect("meta");
-
-    let mut file_obj = FileDicomObject::new_empty_with_dict_and_meta(StandardDataDictionary, meta);
-    for elem in obj {
-        file_obj.put(elem);
-    }
-    file_obj.write_to_file(&path).expect("write test dicom");
-
+    testing::sample_image()
+        .write(&path)
+        .expect("write test dicom");
     (dir, path)
 }
 
@@ -156,6 +47,60 @@ fn metadata_and_validation_cover_required_tags() {
     assert!(report.has_pixel_data);
 }
 
+#[test]
+fn file_findings_cover_valid_and_unreadable_files() {
+    let (dir, path) = build_test_dicom();
+    let junk = dir.path().join("junk.dcm");
+    std::fs::write(&junk, b"not a dicom file").expect("write junk");
+
+    let finding = validate::inspect_file(&path);
+    assert!(finding.valid && finding.parsed && finding.has_pixel_data);
+    assert_eq!(
+        finding.transfer_syntax.as_deref(),
+        Some("1.2.840.10008.1.2.1")
+    );
+
+    let broken = validate::inspect_file(&junk);
+    assert!(!broken.valid && !broken.parsed);
+    assert!(broken.error.is_some() || !broken.anomalies.is_empty());
+
+    // Each finding serializes to a single line with the timestamp field indexers look for.
+    let line = serde_json::to_string(&broken).expect("serialize finding");
+    assert!(!line.contains('\n'));
+    let value: serde_json::Value = serde_json::from_str(&line).expect("parse finding");
+    assert!(value["@timestamp"].is_string());
+    assert_eq!(value["valid"], false);
+}
+
+#[test]
+fn file_meta_group_is_checked_against_the_dataset() {
+    let (_dir, path) = build_test_dicom();
+    let bytes = std::fs::read(&path).expect("read file");
+    let observed = lenient::observe_encoding(&bytes).expect("observed encoding");
+    assert!(observed.explicit_vr && !observed.big_endian);
+
+    let mut obj = dicom::object::open_file(&path).expect("open file");
+    assert!(validate::check_file_meta(&obj, Some(observed)).is_empty());
+
+    // An implicit VR dataset behind a meta group declaring explicit VR.
+    let implicit = lenient::ObservedEncoding {
+        explicit_vr: false,
+        big_endian: false,
+    };
+    let mismatches = validate::check_file_meta(&obj, Some(implicit));
+    assert_eq!(mismatches.len(), 1);
+    assert!(mismatches[0].contains("written in implicit VR little endian"));
+
+    obj.put(DataElement::new(
+        Tag(0x0008, 0x0018),
+        VR::UI,
+        PrimitiveValue::from("1.2.3.4"),
+    ));
+    let mismatches = validate::check_file_meta(&obj, Some(observed));
+    assert_eq!(mismatches.len(), 1);
+    assert!(mismatches[0].starts_with("Media Storage SOP Instance UID"));
+}
+
 #[test]
 fn pixel_stats_and_image_preview_work() {
     let (_dir, path) = build_test_dicom();
@@ -173,6 +118,98 @@ fn pixel_stats_and_image_preview_work() {
     assert!(png.starts_with(&[0x89, b'P', b'N', b'G']));
 }
 
+#[test]
+fn stats_leave_out_padding_and_shuttered_pixels() {
+    let (_dir, path) = build_test_dicom();
+    let mut obj = dicom::object::open_file(&path).unwrap();
+    // Stored 0 (-1024 after rescale) marks the area outside the reconstruction field.
+    obj.put(DataElement::new(
+        Tag(0x0028, 0x0120),
+        VR::US,
+        PrimitiveValue::from(0_u16),
+    ));
+    obj.write_to_file(&path).unwrap();
+
+    let stats = stats::pixel_statistics_for_file(&path).expect("stats");
+    assert_eq!(stats.total_pixels, 3);
+    assert_eq!(stats.excluded_pixels, 1);
+    assert!((stats.min - -896.0).abs() < f32::EPSILON);
+    let all = stats::pixel_statistics_with(
+        &path,
+        stats::PaletteSpace::Index,
+        dicom_tools::padding::Exclusions::NONE,
+    )
+    .expect("stats with padding");
+    assert_eq!(all.total_pixels, 4);
+    assert!((all.min - -1024.0).abs() < f32::EPSILON);
+
+    // A rectangular shutter leaving only the first column visible.
+    for (element, value) in [(0x1602, "1"), (0x1604, "1"), (0x1606, "1"), (0x1608, "2")] {
+        obj.put(DataElement::new(
+            Tag(0x0018, element),
+            VR::IS,
+            PrimitiveValue::from(value),
+        ));
+    }
+    obj.put(DataElement::new(
+        Tag(0x0018, 0x1600),
+        VR::CS,
+        PrimitiveValue::from("RECTANGULAR"),
+    ));
+    obj.write_to_file(&path).unwrap();
+    let stats = stats::pixel_statistics_for_file(&path).expect("shuttered stats");
+    assert_eq!(stats.total_pixels, 1);
+    assert_eq!(stats.excluded_pixels, 3);
+    assert!((stats.mean - -768.0).abs() < f32::EPSILON);
+}
+
+#[test]
+fn capabilities_describe_operations_and_warn_on_unsupported_syntax() {
+    let (_dir, path) = build_test_dicom();
+    let obj = dicom::object::open_file(&path).expect("open");
+    let caps = capabilities::detect(&obj);
+    assert_eq!(
+        caps.sop_class_name.as_deref(),
+        Some("Secondary Capture Image Storage")
+    );
+    assert_eq!(caps.modality.as_deref(), Some("OT"));
+    assert_eq!(caps.frames, 1);
+    assert_eq!(caps.estimated_decoded_bytes, Some(4));
+    assert!(caps.can_render && caps.can_compute_stats && caps.can_transcode);
+    assert!(caps.warnings.is_empty(), "{:?}", caps.warnings);
+
+    // No JPEG 2000 decoder is built in: the dataset is readable but not previewable.
+    let mut j2k = obj.clone();
+    j2k.meta_mut().transfer_syntax = "1.2.840.10008.1.2.4.90".to_string();
+    let caps = capabilities::detect(&j2k);
+    assert!(!caps.can_render && !caps.can_transcode);
+    assert!(caps.warnings[0].starts_with("Unsupported transfer syntax for preview"));
+}
+
+#[test]
+fn series_sprite_places_one_cell_per_instance() {
+    let (_dir, path) = build_test_dicom();
+    let tile = image::preview_png_bytes(&path, 0, None, Some(32)).expect("thumbnail");
+    let sprite =
+        image::sprite_bytes(&[tile.clone(), tile], 32, image::PreviewFormat::Png).expect("sprite");
+    let sprite = ::image::load_from_memory(&sprite).expect("decode sprite");
+    assert_eq!((sprite.width(), sprite.height()), (64, 32));
+}
+
+#[test]
+fn previews_encode_as_jpeg_and_webp() {
+    let (_dir, path) = build_test_dicom();
+    let jpeg =
+        image::preview_bytes(&path, 0, None, None, image::PreviewFormat::Jpeg).expect("jpeg");
+    assert!(jpeg.starts_with(&[0xFF, 0xD8, 0xFF]));
+    let webp =
+        image::preview_bytes(&path, 0, None, None, image::PreviewFormat::Webp).expect("webp");
+    assert_eq!(&webp[..4], b"RIFF");
+    assert_eq!(&webp[8..12], b"WEBP");
+    let decoded = ::image::load_from_memory(&webp).expect("decode webp");
+    assert_eq!((decoded.width(), decoded.height()), (2, 2));
+}
+
 #[test]
 fn anonymization_creates_clean_copy() {
     let (_dir, path) = build_test_dicom();
@@ -189,6 +226,103 @@ fn anonymization_creates_clean_copy() {
     assert_eq!(patient_name, "ANONYMOUS^PATIENT");
 }
 
+#[test]
+fn study_zip_remaps_uids_consistently() {
+    let (_dir, path) = build_test_dicom();
+    let original_uid = "1.2.826.0.1.3680043.2.1125.1";
+
+    let written = anonymize::anonymize_study_zip(
+        &[path],
+        anonymize::AnonymizeOptions {
+            profile: anonymize::Profile::RetainDates,
+            ..Default::default()
+        },
+        std::io::Cursor::new(Vec::new()),
+    )
+    .expect("study zip");
+    let mut archive = zip::ZipArchive::new(written).expect("zip");
+    assert_eq!(archive.len(), 1);
+
+    let remapped = anonymize::remap_uid(original_uid);
+    let mut entry = archive.by_index(0).expect("entry");
+    assert_eq!(entry.name(), format!("unknown/{}.dcm", remapped));
+    let mut content = Vec::new();
+    std::io::Read::read_to_end(&mut entry, &mut content).expect("read entry");
+    let anon = dicom::object::from_reader(&content[128..]).expect("parse entry");
+
+    let text = |tag| anon.element(tag).unwrap().to_str().unwrap().into_owned();
+    assert_eq!(text(Tag(0x0008, 0x0018)), remapped);
+    assert_eq!(anon.meta().media_storage_sop_instance_uid(), remapped);
+    assert_eq!(text(Tag(0x0010, 0x0010)), "ANONYMOUS^PATIENT");
+    // The retain-dates profile keeps the Study Date.
+    assert_eq!(text(Tag(0x0008, 0x0020)), "20240101");
+}
+
+#[test]
+fn job_queue_runs_operations_and_lists_artifacts() {
+    let (dir, path) = build_test_dicom();
+    let store = dicom_tools::storage::FileStore::new(dir.path().join("store")).expect("store");
+    let name = store
+        .save(Some("sample.dcm"), &std::fs::read(&path).expect("read"))
+        .expect("save");
+    let input = jobs::JobInput {
+        path: store.resolve(&name).expect("resolve"),
+        name: name.clone(),
+    };
+    let queue = jobs::JobQueue::start(store.clone());
+
+    let wait = |id: u64| loop {
+        let record = queue.get(id).expect("get").expect("known job");
+        if record.finished_at.is_some() {
+            return record;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    };
+
+    let transcode = queue
+        .submit(
+            jobs::JobOperation::Transcode {
+                target: transcode::UncompressedTransferSyntax::ImplicitVRLittleEndian,
+            },
+            vec![input.clone()],
+        )
+        .expect("submit");
+    let anonymize = queue
+        .submit(
+            jobs::JobOperation::Anonymize {
+                options: anonymize::AnonymizeOptions::default(),
+            },
+            vec![input],
+        )
+        .expect("submit");
+
+    let record = wait(transcode);
+    assert_eq!(record.status, jobs::JobStatus::Completed);
+    assert_eq!((record.done, record.total), (1, 1));
+    assert!(record.results[0].ok);
+    let artifact = &record.artifacts[0];
+    assert_eq!(artifact.source.as_deref(), Some(name.as_str()));
+    let transcoded = dicom::object::open_file(store.resolve(&artifact.filename).expect("artifact"))
+        .expect("open artifact");
+    assert_eq!(
+        transcoded.meta().transfer_syntax().trim_end_matches('\0'),
+        "1.2.840.10008.1.2"
+    );
+
+    let record = wait(anonymize);
+    assert_eq!(record.status, jobs::JobStatus::Completed);
+    assert!(record.artifacts[0].filename.ends_with(".zip"));
+    assert_eq!(
+        queue
+            .list()
+            .expect("list")
+            .iter()
+            .map(|r| r.id)
+            .collect::<Vec<_>>(),
+        [anonymize, transcode]
+    );
+}
+
 #[test]
 fn transcode_keeps_pixel_data_intact() {
     let (_dir, path) = build_test_dicom();
@@ -209,6 +343,71 @@ fn transcode_keeps_pixel_data_intact() {
     assert!((baseline.max - transcoded.max).abs() < f32::EPSILON);
 }
 
+#[test]
+fn parallel_frame_export_matches_sequential_export() {
+    let dir = tempdir().expect("tempdir");
+    let spec = synth::SynthSpec {
+        rows: 16,
+        columns: 16,
+        frames: 6,
+        ..synth::SynthSpec::default()
+    };
+    let input = synth::write_series(&spec, dir.path())
+        .expect("synth")
+        .remove(0);
+
+    let export = |jobs: usize, stem: &str| {
+        let options = image::ImageExportOptions {
+            jobs: Some(jobs),
+            ..Default::default()
+        };
+        let output = dir.path().join(format!("{}.png", stem));
+        image::convert(&input, Some(output), "png", &options).expect("export");
+        (0..6)
+            .map(|frame| {
+                let path = dir.path().join(format!("{}_frame{:03}.png", stem, frame));
+                ::image::open(path)
+                    .expect("frame png")
+                    .to_luma16()
+                    .into_raw()
+            })
+            .collect::<Vec<_>>()
+    };
+    let sequential = export(1, "sequential");
+    assert_eq!(sequential, export(4, "parallel"));
+    // The gradient shifts every frame, so frames were not mixed up.
+    assert_ne!(sequential[0], sequential[1]);
+}
+
+#[test]
+fn joint_histogram_of_a_transcode_is_diagonal() {
+    let (dir, path) = build_test_dicom();
+    let output = path.with_file_name("sample_transcoded.dcm");
+    transcode::transcode(
+        &path,
+        &output,
+        transcode::UncompressedTransferSyntax::ImplicitVRLittleEndian,
+    )
+    .expect("transcode");
+
+    let (before, _, _) = joint_histogram::frame_values(&path, 0).expect("before");
+    let (after, _, _) = joint_histogram::frame_values(&output, 0).expect("after");
+    let histogram = joint_histogram::JointHistogram::compute(&before, &after, 4).expect("joint");
+    assert!((histogram.normalized_mutual_information() - 2.0).abs() < 1e-9);
+    assert!((histogram.correlation - 1.0).abs() < 1e-9);
+    assert!(joint_histogram::frame_values(&path, 1).is_err());
+
+    let csv = dir.path().join("joint.csv");
+    let png = dir.path().join("joint.png");
+    joint_histogram::compare_files(&path, 0, &output, 0, 4, Some(&csv), Some(&png))
+        .expect("compare");
+    let rows = std::fs::read_to_string(&csv).expect("csv");
+    assert!(rows.starts_with("a_bin,a_start,b_bin,b_start,count\n"));
+    assert_eq!(rows.lines().count(), 5);
+    let heatmap = ::image::open(&png).expect("png");
+    assert_eq!((heatmap.width(), heatmap.height()), (4, 4));
+}
+
 #[test]
 fn transcode_to_implicit_vr_le_changes_meta() {
     let (_dir, path) = build_test_dicom();
@@ -227,6 +426,150 @@ fn transcode_to_implicit_vr_le_changes_meta() {
         ts_uid,
         dicom::transfer_syntax::entries::IMPLICIT_VR_LITTLE_ENDIAN.uid()
     );
+    // The rewritten dataset really is implicit VR, so the meta group agrees with it.
+    let observed = lenient::observe_encoding(&std::fs::read(&output).expect("read transcoded"));
+    assert!(observed.is_some_and(|o| !o.explicit_vr && !o.big_endian));
+    assert!(validate::check_file_meta(&transcoded, observed).is_empty());
+}
+
+#[test]
+fn lossy_transcode_records_history_and_refuses_recompression() {
+    let (_dir, path) = build_test_dicom();
+    let lossy = path.with_file_name("sample_jpeg.dcm");
+    let options = transcode::LossyOptions::default();
+
+    transcode::transcode_lossy(
+        &path,
+        &lossy,
+        transcode::LossyTransferSyntax::JpegBaseline,
+        &options,
+    )
+    .expect("lossy transcode");
+    let obj = dicom::object::open_file(&lossy).expect("open lossy");
+    assert_eq!(
+        obj.meta().transfer_syntax().trim_end_matches('\0'),
+        dicom::transfer_syntax::entries::JPEG_BASELINE.uid()
+    );
+    let history = transcode::lossy_history(&obj);
+    assert!(history.lossy);
+    assert_eq!(history.methods, vec!["ISO_10918_1"]);
+    assert_eq!(history.ratios.len(), 1);
+
+    // A second lossy step is refused unless forced, then appended to the history.
+    let again = path.with_file_name("sample_jpeg_again.dcm");
+    let refused = transcode::transcode_lossy(
+        &lossy,
+        &again,
+        transcode::LossyTransferSyntax::JpegBaseline,
+        &options,
+    );
+    assert!(refused.is_err());
+    let forced = transcode::LossyOptions {
+        force: true,
+        ..options
+    };
+    transcode::transcode_lossy(
+        &lossy,
+        &again,
+        transcode::LossyTransferSyntax::JpegBaseline,
+        &forced,
+    )
+    .expect("forced lossy transcode");
+    let obj = dicom::object::open_file(&again).expect("open recompressed");
+    let history = transcode::lossy_history(&obj);
+    assert_eq!(history.methods, vec!["ISO_10918_1", "ISO_10918_1"]);
+    assert_eq!(history.ratios.len(), 2);
+
+    // Decompressing keeps the image marked as lossy.
+    let decompressed = path.with_file_name("sample_decompressed.dcm");
+    transcode::transcode(
+        &again,
+        &decompressed,
+        transcode::UncompressedTransferSyntax::ExplicitVRLittleEndian,
+    )
+    .expect("decompress");
+    let obj = dicom::object::open_file(&decompressed).expect("open decompressed");
+    assert!(transcode::lossy_history(&obj).lossy);
+}
+
+#[test]
+fn derivation_policy_records_provenance_on_written_copies() {
+    let (_dir, path) = build_test_dicom();
+    let source = dicom::object::open_file(&path).expect("open source");
+    let source_uid = source
+        .element(Tag(0x0008, 0x0018))
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .trim_end_matches('\0')
+        .to_string();
+    let uid_of = |obj: &dicom::object::DefaultDicomObject| {
+        obj.element(Tag(0x0008, 0x0018))
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .trim_end_matches('\0')
+            .to_string()
+    };
+
+    let lossy = path.with_file_name("derived_jpeg.dcm");
+    let options = transcode::LossyOptions {
+        derivation: derivation::DerivationPolicy::Full,
+        ..Default::default()
+    };
+    transcode::transcode_lossy(
+        &path,
+        &lossy,
+        transcode::LossyTransferSyntax::JpegBaseline,
+        &options,
+    )
+    .expect("lossy transcode");
+    let obj = dicom::object::open_file(&lossy).expect("open lossy");
+    assert_ne!(uid_of(&obj), source_uid);
+    assert_eq!(obj.meta().media_storage_sop_instance_uid(), uid_of(&obj));
+    let reference = &obj
+        .element(Tag(0x0008, 0x2112))
+        .expect("source image sequence")
+        .items()
+        .unwrap()[0];
+    assert_eq!(
+        reference
+            .element(Tag(0x0008, 0x1155))
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .trim_end_matches('\0'),
+        source_uid
+    );
+    let code = &obj.element(Tag(0x0008, 0x9215)).unwrap().items().unwrap()[0];
+    assert_eq!(
+        code.element(Tag(0x0008, 0x0100)).unwrap().to_str().unwrap(),
+        "113040"
+    );
+
+    // Anonymized copies get a new identity but never point back at the original.
+    let anon = path.with_file_name("derived_anon.dcm");
+    anonymize::anonymize_file_with(
+        &path,
+        &anon,
+        anonymize::AnonymizeOptions::default(),
+        derivation::DerivationPolicy::Full,
+    )
+    .expect("anonymize");
+    let obj = dicom::object::open_file(&anon).expect("open anonymized");
+    assert_ne!(uid_of(&obj), source_uid);
+    assert!(obj.element(Tag(0x0008, 0x2112)).is_err());
+
+    // The default keeps the identity of a lossless transcode.
+    let plain = path.with_file_name("derived_plain.dcm");
+    transcode::transcode(
+        &path,
+        &plain,
+        transcode::UncompressedTransferSyntax::ExplicitVRLittleEndian,
+    )
+    .expect("transcode");
+    let obj = dicom::object::open_file(&plain).expect("open transcoded");
+    assert_eq!(uid_of(&obj), source_uid);
 }
 
 #[test]
@@ -235,7 +578,7 @@ fn json_roundtrip_preserves_pixels_and_attributes() {
     let json_path = path.with_file_name("sample.json");
     let roundtrip = path.with_file_name("sample_roundtrip.dcm");
 
-    json::to_json(&path, Some(&json_path)).expect("to json");
+    json::to_json(&path, Some(&json_path), json::JsonStyle::Standard).expect("to json");
     json::from_json(&json_path, &roundtrip).expect("from json");
 
     let original = dicom::object::open_file(&path).expect("open original");
@@ -288,6 +631,106 @@ fn histogram_counts_align_with_pixels() {
     assert!(histogram.max >= histogram.min);
 }
 
+#[test]
+fn statistics_within_a_segmentation_segment() {
+    let (dir, path) = build_test_dicom();
+    let mut seg = InMemDicomObject::new_empty_with_dict(StandardDataDictionary);
+    for (tag, vr, value) in [
+        (Tag(0x0008, 0x0016), VR::UI, "1.2.840.10008.5.1.4.1.1.66.4"),
+        (Tag(0x0008, 0x0018), VR::UI, "1.2.826.0.1.3680043.2.1125.2"),
+        (Tag(0x0062, 0x0001), VR::CS, "BINARY"),
+        (Tag(0x0028, 0x0008), VR::IS, "1"),
+    ] {
+        seg.put(DataElement::new(tag, vr, PrimitiveValue::from(value)));
+    }
+    for (tag, value) in [
+        (Tag(0x0028, 0x0010), 2_u16),
+        (Tag(0x0028, 0x0011), 2),
+        (Tag(0x0028, 0x0100), 1),
+    ] {
+        seg.put(DataElement::new(tag, VR::US, PrimitiveValue::from(value)));
+    }
+    let mut segment = InMemDicomObject::new_empty_with_dict(StandardDataDictionary);
+    segment.put(DataElement::new(
+        Tag(0x0062, 0x0004),
+        VR::US,
+        PrimitiveValue::from(1_u16),
+    ));
+    segment.put(DataElement::new(
+        Tag(0x0062, 0x0005),
+        VR::LO,
+        PrimitiveValue::from("Lesion"),
+    ));
+    seg.put(DataElement::new(
+        Tag(0x0062, 0x0002),
+        VR::SQ,
+        dicom::core::value::DataSetSequence::from(vec![segment]),
+    ));
+    let mut identification = InMemDicomObject::new_empty_with_dict(StandardDataDictionary);
+    identification.put(DataElement::new(
+        Tag(0x0062, 0x000B),
+        VR::US,
+        PrimitiveValue::from(1_u16),
+    ));
+    let mut group = InMemDicomObject::new_empty_with_dict(StandardDataDictionary);
+    group.put(DataElement::new(
+        Tag(0x0062, 0x000A),
+        VR::SQ,
+        dicom::core::value::DataSetSequence::from(vec![identification]),
+    ));
+    seg.put(DataElement::new(
+        Tag(0x5200, 0x9230),
+        VR::SQ,
+        dicom::core::value::DataSetSequence::from(vec![group]),
+    ));
+    // Pixels 1 and 3 of the 2x2 image: -896 and -514 HU.
+    seg.put(DataElement::new(
+        Tag(0x7FE0, 0x0010),
+        VR::OB,
+        PrimitiveValue::from(vec![0b0000_1010_u8, 0]),
+    ));
+    let meta = FileMetaTableBuilder::new()
+        .transfer_syntax(EXPLICIT_VR_LITTLE_ENDIAN.uid())
+        .media_storage_sop_class_uid("1.2.840.10008.5.1.4.1.1.66.4")
+        .media_storage_sop_instance_uid("1.2.826.0.1.3680043.2.1125.2");
+    let seg_path = dir.path().join("seg.dcm");
+    seg.with_meta(meta)
+        .expect("seg file")
+        .write_to_file(&seg_path)
+        .expect("write seg");
+
+    let (stats, roi) = stats::pixel_statistics_within_file(&path, &seg_path, 1).expect("masked");
+    assert_eq!(roi.label.as_deref(), Some("Lesion"));
+    assert_eq!(stats.total_pixels, 2);
+    assert_eq!((stats.min, stats.max), (-896.0, -514.0));
+    assert_eq!(stats.mean, -705.0);
+    assert!(stats::pixel_statistics_within_file(&path, &seg_path, 2).is_err());
+    assert!(stats::pixel_statistics_within_file(&path, &path, 1).is_err());
+}
+
+#[test]
+fn fixed_bin_edges_are_comparable_across_images() {
+    let (_dir, path) = build_test_dicom();
+    // Modality values are -1024, -896, -768 and -514 HU.
+    let ct = stats::histogram_for_file_with(&path, stats::Binning::Fixed(stats::FixedBins::CT_HU))
+        .expect("ct histogram");
+    assert_eq!(ct.bins.len(), 4096);
+    assert_eq!(ct.bin_width, 1.0);
+    for bin in [0, 128, 256, 510] {
+        assert_eq!(ct.bins[bin], 1, "bin {}", bin);
+    }
+
+    let narrow = stats::FixedBins::new(-900.0, -600.0, 100.0).expect("bins");
+    let histogram = stats::histogram_for_file_with(&path, stats::Binning::Fixed(narrow))
+        .expect("narrow histogram");
+    assert_eq!(histogram.bins, vec![1, 1, 0, 0]);
+    assert_eq!((histogram.below, histogram.above), (1, 1));
+
+    assert!(stats::FixedBins::new(0.0, 10.0, 0.0).is_err());
+    assert!(stats::FixedBins::new(10.0, 0.0, 1.0).is_err());
+    assert!(stats::FixedBins::new(0.0, 1e9, 1.0).is_err());
+}
+
 #[test]
 fn pixel_format_summary_includes_window_and_rescale() {
     let (_dir, path) = build_test_dicom();
@@ -302,3 +745,3413 @@ fn pixel_format_summary_includes_window_and_rescale() {
     assert_eq!(details.window_center, Some(50.0));
     assert_eq!(details.window_width, Some(150.0));
 }
+
+#[test]
+fn transcode_reports_progress_phases() {
+    let (_dir, path) = build_test_dicom();
+    let output = path.with_file_name("sample_progress.dcm");
+    let events = std::sync::Mutex::new(Vec::new());
+    let sink = |event: progress::ProgressEvent| events.lock().unwrap().push(event);
+
+    transcode::transcode_with_progress(
+        &path,
+        &output,
+        transcode::UncompressedTransferSyntax::ExplicitVRLittleEndian,
+        &sink,
+    )
+    .expect("transcode with progress");
+
+    let events = events.into_inner().unwrap();
+    let phases: Vec<_> = events.iter().map(|e| e.phase.as_str()).collect();
+    assert_eq!(phases, ["decode", "convert", "encode", "write", "done"]);
+    assert_eq!(events.last().map(|e| e.percent), Some(100.0));
+}
+
+#[test]
+fn explicit_voi_lut_sequence_drives_preview() {
+    let (_dir, path) = build_test_dicom();
+
+    // Replace the linear window with a VOI LUT that maps everything to mid-grey
+    // except the top of the modality range.
+    let mut obj = dicom::object::open_file(&path).expect("open");
+    let mut item = InMemDicomObject::new_empty_with_dict(StandardDataDictionary);
+    item.put(DataElement::new(
+        Tag(0x0028, 0x3002),
+        VR::US,
+        PrimitiveValue::U16(vec![4, 0, 8].into()),
+    ));
+    item.put(DataElement::new(
+        Tag(0x0028, 0x3006),
+        VR::OW,
+        PrimitiveValue::U16(vec![128, 128, 128, 255].into()),
+    ));
+    obj.put(DataElement::new(
+        Tag(0x0028, 0x3010),
+        VR::SQ,
+        dicom::core::value::DataSetSequence::from(vec![item]),
+    ));
+    obj.write_to_file(&path).expect("rewrite");
+
+    let png = image::first_frame_png_bytes(&path).expect("render png");
+    let rendered = ::image::load_from_memory(&png)
+        .expect("decode png")
+        .to_luma8();
+    // Modality values are -1024..-514, all below the LUT range, so the first entry applies.
+    assert!(rendered.pixels().all(|p| p.0[0] == 128));
+}
+
+#[test]
+fn eight_bit_windowed_export_runs_the_window_kernel() {
+    use dicom::pixeldata::PixelDecoder;
+
+    // A signed 16-bit CT ramp, rescaled by 2 / -1024, exported to 8 bits under a 40/400 window.
+    let samples: Vec<u16> = (0..64 * 64).map(|i| (i as i16 - 2048) as u16).collect();
+    let obj = testing::ObjectBuilder::new()
+        .sop("1.2.840.10008.5.1.4.1.1.2", "1.2.826.0.1.3680043.2.1125.9")
+        .image_pixel(64, 64, 16, "MONOCHROME2")
+        .signed()
+        .rescale(2.0, -1024.0)
+        .window(40.0, 400.0)
+        .pixel_data_u16(&samples)
+        .into_file(EXPLICIT_VR_LITTLE_ENDIAN.uid())
+        .expect("ct");
+    let options = image::ImageExportOptions {
+        force_8bit: true,
+        ..Default::default()
+    };
+    let rendered = image::render_object_frame(&obj, 0, &options)
+        .expect("render")
+        .to_luma8();
+
+    let hounsfield: Vec<f32> = samples
+        .iter()
+        .map(|v| *v as i16 as f32 * 2.0 - 1024.0)
+        .collect();
+    assert_eq!(
+        rendered.as_raw(),
+        &kernels::scalar::window_to_u8(&hounsfield, 40.0, 400.0)
+    );
+    // Same image as dicom-pixeldata's own windowing, give or take rounding.
+    let reference = obj
+        .decode_pixel_data()
+        .unwrap()
+        .to_dynamic_image_with_options(0, &dicom_pixeldata::ConvertOptions::new().force_8bit())
+        .unwrap()
+        .to_luma8();
+    assert!(rendered
+        .as_raw()
+        .iter()
+        .zip(reference.as_raw())
+        .all(|(a, b)| a.abs_diff(*b) <= 1));
+}
+
+#[test]
+fn window_index_selects_among_multiple_windows() {
+    let (_dir, path) = build_test_dicom();
+    let mut obj = dicom::object::open_file(&path).expect("open");
+    for (tag, vr, value) in [
+        (Tag(0x0028, 0x1050), VR::DS, "-800\\-700"),
+        (Tag(0x0028, 0x1051), VR::DS, "100\\1000"),
+        (Tag(0x0028, 0x1055), VR::LO, "NARROW\\WIDE"),
+        (Tag(0x0028, 0x1056), VR::CS, "LINEAR\\SIGMOID"),
+    ] {
+        obj.put(DataElement::new(tag, vr, PrimitiveValue::from(value)));
+    }
+    obj.write_to_file(&path).expect("rewrite");
+
+    let format = stats::pixel_format_for_file(&path).expect("pixel format");
+    assert_eq!(format.windows.len(), 2);
+    assert_eq!(format.windows[1].center, -700.0);
+    assert_eq!(format.windows[1].function, "SIGMOID");
+    assert_eq!(format.windows[1].explanation.as_deref(), Some("WIDE"));
+
+    let render = |window_index: Option<usize>, name: &str| {
+        let out = path.with_file_name(name);
+        let options = image::ImageExportOptions {
+            window_index,
+            ..Default::default()
+        };
+        image::convert(&path, Some(out.clone()), "png", &options)
+            .map(|()| ::image::open(&out).expect("open png").to_luma8().into_raw())
+    };
+
+    // The narrow window saturates; the wide sigmoid keeps every pixel off the extremes.
+    let narrow = render(None, "narrow.png").expect("first window");
+    assert_eq!(narrow, render(Some(0), "first.png").expect("window 0"));
+    let wide = render(Some(1), "wide.png").expect("window 1");
+    assert_eq!(narrow.first(), Some(&0));
+    assert_eq!(narrow.last(), Some(&255));
+    assert!(wide.iter().all(|&v| v > 0 && v < 255));
+    assert!(render(Some(2), "missing.png").is_err());
+}
+
+#[test]
+fn presentation_lut_shape_and_sigmoid_drive_rendering() {
+    let (_dir, path) = build_test_dicom();
+    let mut obj = dicom::object::open_file(&path).expect("open");
+    for (tag, vr, value) in [
+        (Tag(0x0028, 0x1050), VR::DS, "-770"),
+        (Tag(0x0028, 0x1051), VR::DS, "600"),
+        (Tag(0x0028, 0x1056), VR::CS, "SIGMOID"),
+        (Tag(0x2050, 0x0020), VR::CS, "INVERSE"),
+    ] {
+        obj.put(DataElement::new(tag, vr, PrimitiveValue::from(value)));
+    }
+    obj.write_to_file(&path).expect("rewrite");
+
+    let format = stats::pixel_format_for_file(&path).expect("pixel format");
+    assert_eq!(format.presentation_lut_shape.as_deref(), Some("INVERSE"));
+    assert_eq!(format.windows[0].function, "SIGMOID");
+
+    let png = image::first_frame_png_bytes(&path).expect("render png");
+    let rendered = ::image::load_from_memory(&png)
+        .expect("decode png")
+        .to_luma8()
+        .into_raw();
+    // Sigmoid keeps both ends off 0/255, and INVERSE makes the lowest value the brightest.
+    assert!(rendered.iter().all(|&v| v > 0 && v < 255));
+    assert!(rendered.windows(2).all(|pair| pair[0] > pair[1]));
+}
+
+#[test]
+fn palette_color_exports_rgb_and_stats_choose_space() {
+    let (_dir, path) = build_test_dicom();
+    let mut obj = dicom::object::open_file(&path).expect("open");
+    obj.put(DataElement::new(
+        Tag(0x0028, 0x0004),
+        VR::CS,
+        PrimitiveValue::from("PALETTE COLOR"),
+    ));
+    // Red ramps up, green stays off and blue ramps down over the 256 indices.
+    let ramp: Vec<u16> = (0..256u32).map(|i| (i * 257) as u16).collect();
+    let tables = [
+        ramp.clone(),
+        vec![0; 256],
+        ramp.iter().rev().copied().collect(),
+    ];
+    for (channel, data) in tables.into_iter().enumerate() {
+        let element = 0x1101 + channel as u16;
+        obj.put(DataElement::new(
+            Tag(0x0028, element),
+            VR::US,
+            PrimitiveValue::U16([256, 0, 16][..].into()),
+        ));
+        obj.put(DataElement::new(
+            Tag(0x0028, element + 0x100),
+            VR::OW,
+            PrimitiveValue::U16(data.into()),
+        ));
+    }
+    obj.write_to_file(&path).expect("rewrite");
+
+    let png = image::first_frame_png_bytes(&path).expect("render png");
+    let rendered = ::image::load_from_memory(&png)
+        .expect("decode png")
+        .to_rgb8()
+        .into_raw();
+    assert_eq!(&rendered[..3], &[0, 0, 255]);
+    assert_eq!(&rendered[3..6], &[64, 0, 191]);
+    assert_eq!(&rendered[9..], &[255, 0, 0]);
+
+    let index =
+        stats::pixel_statistics_in_space(&path, stats::PaletteSpace::Index).expect("index stats");
+    assert_eq!(index.max, 255.0);
+    assert_eq!(index.total_pixels, 4);
+
+    let rgb = stats::pixel_statistics_in_space(&path, stats::PaletteSpace::Rgb).expect("rgb stats");
+    assert_eq!(rgb.max, 65535.0);
+    assert_eq!(rgb.total_pixels, 12);
+    assert_eq!(rgb.shape.last(), Some(&3));
+}
+
+#[test]
+fn monochrome1_inverts_for_display_only() {
+    let (_dir, path) = build_test_dicom();
+    let mut obj = dicom::object::open_file(&path).expect("open");
+    obj.put(DataElement::new(
+        Tag(0x0028, 0x0004),
+        VR::CS,
+        PrimitiveValue::from("MONOCHROME1"),
+    ));
+    obj.write_to_file(&path).expect("rewrite");
+
+    let render = |inversion: image::Inversion, name: &str| {
+        let out = path.with_file_name(name);
+        let options = image::ImageExportOptions {
+            normalize: true,
+            inversion,
+            ..Default::default()
+        };
+        image::convert(&path, Some(out.clone()), "png", &options).expect("convert");
+        ::image::open(&out).expect("open png").to_luma8().into_raw()
+    };
+
+    // Stored values increase left to right; MONOCHROME1 shows the lowest value as white.
+    let auto = render(image::Inversion::Auto, "auto.png");
+    let never = render(image::Inversion::Never, "never.png");
+    assert_eq!(auto, vec![255, 191, 127, 0]);
+    assert_eq!(never, vec![0, 64, 128, 255]);
+
+    // Statistics stay in modality value space regardless of polarity.
+    let stats = stats::pixel_statistics_for_file(&path).expect("stats");
+    assert!((stats.min - -1024.0).abs() < f32::EPSILON);
+}
+
+#[test]
+fn explicit_vr_big_endian_reads_and_transcodes_to_le() {
+    let dir = tempdir().expect("tempdir");
+    let path = dir.path().join("legacy_be.dcm");
+
+    let mut obj = InMemDicomObject::new_empty_with_dict(StandardDataDictionary);
+    for (tag, vr, value) in [
+        (Tag(0x0008, 0x0016), VR::UI, "1.2.840.10008.5.1.4.1.1.7"),
+        (Tag(0x0008, 0x0018), VR::UI, "1.2.826.0.1.3680043.2.1125.2"),
+        (Tag(0x0008, 0x0060), VR::CS, "OT"),
+        (Tag(0x0010, 0x0010), VR::PN, "Legacy^Patient"),
+        (Tag(0x0028, 0x0004), VR::CS, "MONOCHROME2"),
+    ] {
+        obj.put(DataElement::new(tag, vr, PrimitiveValue::from(value)));
+    }
+    for (tag, value) in [
+        (Tag(0x0028, 0x0002), 1_u16),
+        (Tag(0x0028, 0x0010), 2),
+        (Tag(0x0028, 0x0011), 2),
+        (Tag(0x0028, 0x0100), 16),
+        (Tag(0x0028, 0x0101), 12),
+        (Tag(0x0028, 0x0102), 11),
+        (Tag(0x0028, 0x0103), 0),
+    ] {
+        obj.put(DataElement::new(tag, VR::US, PrimitiveValue::from(value)));
+    }
+    // Legacy writers sometimes store 16-bit big endian samples as OB bytes.
+    let samples: [u16; 4] = [1, 256, 1000, 4095];
+    let be_bytes: Vec<u8> = samples.iter().flat_map(|v| v.to_be_bytes()).collect();
+    obj.put(DataElement::new(
+        Tag(0x7fe0, 0x0010),
+        VR::OB,
+        PrimitiveValue::from(be_bytes),
+    ));
+
+    let meta = FileMetaTableBuilder::new()
+        .transfer_syntax(dicom_tools::dicom_access::EXPLICIT_VR_BIG_ENDIAN)
+        .media_storage_sop_class_uid("1.2.840.10008.5.1.4.1.1.7")
+        .media_storage_sop_instance_uid("1.2.826.0.1.3680043.2.1125.2")
+        .build()
+        .expect("meta");
+    let mut file_obj = FileDicomObject::new_empty_with_dict_and_meta(StandardDataDictionary, meta);
+    for elem in obj {
+        file_obj.put(elem);
+    }
+    file_obj.write_to_file(&path).expect("write BE dicom");
+
+    let basic = metadata::read_basic_metadata(&path).expect("metadata");
+    assert_eq!(basic.patient_name.as_deref(), Some("Legacy^Patient"));
+    assert_eq!(basic.rows, Some(2));
+
+    let stats = stats::pixel_statistics_for_file(&path).expect("stats");
+    assert_eq!(stats.min, 1.0);
+    assert_eq!(stats.max, 4095.0);
+
+    let png = image::first_frame_png_bytes(&path).expect("render png");
+    assert!(png.starts_with(&[0x89, b'P', b'N', b'G']));
+
+    let output = dir.path().join("legacy_le.dcm");
+    transcode::transcode(
+        &path,
+        &output,
+        transcode::UncompressedTransferSyntax::ExplicitVRLittleEndian,
+    )
+    .expect("transcode to LE");
+    let transcoded = stats::pixel_statistics_for_file(&output).expect("transcoded stats");
+    assert_eq!(transcoded.min, 1.0);
+    assert_eq!(transcoded.max, 4095.0);
+}
+
+#[test]
+fn anonymization_splices_pixel_data_and_scrubs_sequences() {
+    let (dir, path) = build_test_dicom();
+    let mut obj = dicom::object::open_file(&path).expect("open");
+    let mut item = InMemDicomObject::new_empty_with_dict(StandardDataDictionary);
+    item.put(DataElement::new(
+        Tag(0x0032, 0x1032),
+        VR::PN,
+        PrimitiveValue::from("Requesting^Doctor"),
+    ));
+    // Undefined-length sequence ahead of Pixel Data exercises the header scanner.
+    obj.put(DataElement::new(
+        Tag(0x0040, 0x0275),
+        VR::SQ,
+        dicom::core::value::DataSetSequence::from(vec![item]),
+    ));
+    let explicit = dir.path().join("explicit.dcm");
+    obj.write_to_file(&explicit).expect("write explicit");
+    let implicit = dir.path().join("implicit.dcm");
+    transcode::transcode(
+        &explicit,
+        &implicit,
+        transcode::UncompressedTransferSyntax::ImplicitVRLittleEndian,
+    )
+    .expect("transcode to implicit");
+
+    for input in [explicit, implicit] {
+        let output = input.with_extension("anon.dcm");
+        anonymize::anonymize_file(&input, &output).expect("anonymize");
+        let anon = dicom::object::open_file(&output).expect("open anon");
+
+        let pixels = anon
+            .element(Tag(0x7fe0, 0x0010))
+            .expect("pixel data")
+            .to_bytes()
+            .expect("bytes");
+        assert_eq!(&pixels[..], &[0, 64, 128, 255]);
+        let requesting = anon
+            .element(Tag(0x0040, 0x0275))
+            .expect("sequence")
+            .items()
+            .expect("items")[0]
+            .element(Tag(0x0032, 0x1032))
+            .expect("physician")
+            .to_str()
+            .unwrap()
+            .into_owned();
+        assert_eq!(requesting, "ANONYMIZED");
+        let patient_id = anon.element(Tag(0x0010, 0x0020)).unwrap().to_str().unwrap();
+        assert!(patient_id.starts_with("ANON_"));
+    }
+}
+
+fn retrieve_request(field: u16, sop_class: &str, destination: Option<&str>) -> InMemDicomObject {
+    let mut cmd = dimse::command_set(sop_class, field, true);
+    cmd.put(dimse::us(dimse::MESSAGE_ID, 7));
+    cmd.put(dimse::us(dimse::PRIORITY, 0));
+    if let Some(destination) = destination {
+        cmd.put(DataElement::new(
+            dimse::MOVE_DESTINATION,
+            VR::AE,
+            PrimitiveValue::from(destination),
+        ));
+    }
+    cmd
+}
+
+fn patient_identifier() -> Vec<u8> {
+    let mut identifier = InMemDicomObject::new_empty();
+    identifier.put(DataElement::new(
+        Tag(0x0008, 0x0052),
+        VR::CS,
+        PrimitiveValue::from("PATIENT"),
+    ));
+    identifier.put(DataElement::new(
+        Tag(0x0010, 0x0020),
+        VR::LO,
+        PrimitiveValue::from("PAT123"),
+    ));
+    let mut bytes = Vec::new();
+    identifier
+        .write_dataset_with_ts(&mut bytes, &EXPLICIT_VR_LITTLE_ENDIAN.erased())
+        .expect("encode identifier");
+    bytes
+}
+
+/// Answer every C-STORE-RQ until a final response to the retrieve arrives; returns the
+/// received SOP Instance UIDs and the final response.
+fn collect_retrieve(channel: &mut dyn dimse::DimseChannel) -> (Vec<String>, dimse::DimseMessage) {
+    let mut received = Vec::new();
+    loop {
+        let message = dimse::read_message(channel)
+            .expect("read")
+            .expect("message");
+        if message.command_field() == dimse::command::C_STORE_RQ {
+            let uid = message
+                .command
+                .element(dimse::AFFECTED_SOP_INSTANCE_UID)
+                .unwrap()
+                .to_str()
+                .unwrap()
+                .trim_end_matches('\0')
+                .to_string();
+            received.push(uid);
+            let response = dimse::response_to(&message, dimse::status::SUCCESS, false);
+            dimse::send_message(channel, message.pc_id, response, None).expect("store rsp");
+        } else if message.status() != Some(dimse::status::PENDING) {
+            return (received, message);
+        }
+    }
+}
+
+#[test]
+fn retrieve_scp_serves_c_get_and_c_move() {
+    const PATIENT_ROOT_MOVE: &str = "1.2.840.10008.5.1.4.1.2.1.2";
+    const PATIENT_ROOT_GET: &str = "1.2.840.10008.5.1.4.1.2.1.3";
+    const SECONDARY_CAPTURE: &str = "1.2.840.10008.5.1.4.1.1.7";
+    let (dir, _path) = build_test_dicom();
+
+    // Move destination: a storage SCP that accepts a single association.
+    let destination = std::net::TcpListener::bind("127.0.0.1:0").expect("bind destination");
+    let destination_addr = destination.local_addr().unwrap();
+    let store_thread = std::thread::spawn(move || {
+        let (stream, _) = destination.accept().expect("accept");
+        let mut association = dicom_ul::ServerAssociationOptions::new()
+            .with_abstract_syntax(SECONDARY_CAPTURE)
+            .establish(stream)
+            .expect("sub-association");
+        let mut received = Vec::new();
+        while let Some(message) = dimse::read_message(&mut association).expect("read") {
+            received.push(message.data.is_some());
+            let response = dimse::response_to(&message, dimse::status::SUCCESS, false);
+            dimse::send_message(&mut association, message.pc_id, response, None)
+                .expect("store rsp");
+        }
+        received
+    });
+
+    let mut destinations = scp::AeMap::default();
+    destinations
+        .insert_entry(&format!("DEST={}", destination_addr))
+        .unwrap();
+    let server = scp::RetrieveScp::bind(
+        "127.0.0.1:0",
+        scp::ScpConfig {
+            ae_title: "DICOM-TOOLS".into(),
+            root: dir.path().to_path_buf(),
+            destinations,
+            trace: None,
+            store: None,
+            router: None,
+            worklist: None,
+            mpps: None,
+            audit: None,
+        },
+    )
+    .expect("bind scp");
+    let addr = server.local_addr().unwrap().to_string();
+    std::thread::spawn(move || server.serve());
+
+    // C-GET: instances come back as C-STORE sub-operations on the same association.
+    let mut association = dicom_ul::ClientAssociationOptions::new()
+        .with_abstract_syntax(PATIENT_ROOT_GET)
+        .with_abstract_syntax(SECONDARY_CAPTURE)
+        .establish(addr.as_str())
+        .expect("associate");
+    let identifier = patient_identifier();
+    let request = retrieve_request(dimse::command::C_GET_RQ, PATIENT_ROOT_GET, None);
+    dimse::send_message(&mut association, 1, request, Some(&identifier)).expect("send get");
+    let (received, last) = collect_retrieve(&mut association);
+    assert_eq!(received, vec!["1.2.826.0.1.3680043.2.1125.1".to_string()]);
+    assert_eq!(last.status(), Some(dimse::status::SUCCESS));
+    let _ = association.release();
+
+    // C-MOVE: unknown destinations are refused, known ones receive the instance.
+    let mut association = dicom_ul::ClientAssociationOptions::new()
+        .with_abstract_syntax(PATIENT_ROOT_MOVE)
+        .establish(addr.as_str())
+        .expect("associate");
+    let request = retrieve_request(
+        dimse::command::C_MOVE_RQ,
+        PATIENT_ROOT_MOVE,
+        Some("NOWHERE"),
+    );
+    dimse::send_message(&mut association, 1, request, Some(&identifier)).expect("send move");
+    let (_, last) = collect_retrieve(&mut association);
+    assert_eq!(last.status(), Some(dimse::status::MOVE_DESTINATION_UNKNOWN));
+
+    let request = retrieve_request(dimse::command::C_MOVE_RQ, PATIENT_ROOT_MOVE, Some("DEST"));
+    dimse::send_message(&mut association, 1, request, Some(&identifier)).expect("send move");
+    let (received, last) = collect_retrieve(&mut association);
+    assert!(received.is_empty());
+    assert_eq!(last.status(), Some(dimse::status::SUCCESS));
+    assert_eq!(
+        last.command
+            .element(dimse::COMPLETED_SUBOPERATIONS)
+            .unwrap()
+            .to_int::<u16>()
+            .unwrap(),
+        1
+    );
+    let _ = association.release();
+    assert_eq!(store_thread.join().expect("destination"), vec![true]);
+}
+
+#[test]
+fn dimse_trace_logs_both_ends_of_an_echo() {
+    let (dir, _path) = build_test_dicom();
+    let logs = tempdir().expect("log dir");
+    let server_log = logs.path().join("scp.log");
+    let client_log = logs.path().join("echo.log");
+
+    let server = scp::RetrieveScp::bind(
+        "127.0.0.1:0",
+        scp::ScpConfig {
+            ae_title: "DICOM-TOOLS".into(),
+            root: dir.path().to_path_buf(),
+            destinations: scp::AeMap::default(),
+            trace: Some(dimse_trace::DimseTracer::create(&server_log, true).expect("tracer")),
+            store: None,
+            router: None,
+            worklist: None,
+            mpps: None,
+            audit: None,
+        },
+    )
+    .expect("bind scp");
+    let addr = server.local_addr().unwrap().to_string();
+    std::thread::spawn(move || server.serve());
+
+    let tracer = dimse_trace::DimseTracer::create(&client_log, false).expect("tracer");
+    scu::echo_traced(&addr, &dimse::AssociationSettings::default(), Some(tracer)).expect("echo");
+
+    let client = std::fs::read_to_string(&client_log).expect("client log");
+    assert!(client.contains(">> A-ASSOCIATE-RQ to"));
+    assert!(client.contains("pc 1 1.2.840.10008.1.1"));
+    assert!(client.contains("command (0000,0100) US CommandField = 48"));
+    assert!(client.contains("<< DIMSE message on pc 1"));
+
+    // The server finishes tracing the release after the client returns.
+    let mut server = String::new();
+    for _ in 0..50 {
+        server = std::fs::read_to_string(&server_log).unwrap_or_default();
+        if server.contains("association closing") {
+            break;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(20));
+    }
+    assert!(server.contains("<< A-ASSOCIATE-RQ"));
+    assert!(server.contains("== presentation contexts"));
+    assert!(server.contains(">> DIMSE message on pc 1"));
+}
+
+#[test]
+fn echo_scan_reports_the_endpoints_that_answered() {
+    let (dir, _path) = build_test_dicom();
+    let server = scp::RetrieveScp::bind(
+        "127.0.0.1:0",
+        scp::ScpConfig {
+            ae_title: "DICOM-TOOLS".into(),
+            root: dir.path().to_path_buf(),
+            destinations: scp::AeMap::default(),
+            trace: None,
+            store: None,
+            router: None,
+            worklist: None,
+            mpps: None,
+            audit: None,
+        },
+    )
+    .expect("bind scp");
+    let open_port = server.local_addr().unwrap().port();
+    std::thread::spawn(move || server.serve());
+    // A port nothing listens on any more.
+    let closed_port = std::net::TcpListener::bind("127.0.0.1:0")
+        .expect("bind")
+        .local_addr()
+        .unwrap()
+        .port();
+
+    let hosts = echo_scan::parse_targets("127.0.0.1/32").expect("targets");
+    let ports = echo_scan::parse_ports(&format!("{},{}", open_port, closed_port)).expect("ports");
+    let results = echo_scan::scan(&hosts, &ports, &dimse::AssociationSettings::default(), 4);
+    assert_eq!(results.len(), 2);
+
+    let answered = results
+        .iter()
+        .find(|r| r.addr == format!("127.0.0.1:{}", open_port))
+        .expect("open port");
+    assert_eq!(answered.outcome, echo_scan::EchoOutcome::Answered);
+    assert_eq!(answered.status, Some(0));
+    assert!(answered
+        .implementation_class_uid
+        .as_deref()
+        .is_some_and(|uid| !uid.is_empty()));
+
+    let closed = results
+        .iter()
+        .find(|r| r.addr == format!("127.0.0.1:{}", closed_port))
+        .expect("closed port");
+    assert_eq!(closed.outcome, echo_scan::EchoOutcome::Unreachable);
+    assert!(closed.error.is_some());
+}
+
+#[test]
+fn scu_association_settings_are_checked_and_time_out() {
+    // A peer that accepts the connection but never answers the A-ASSOCIATE-RQ.
+    let silent = std::net::TcpListener::bind("127.0.0.1:0").expect("bind");
+    let addr = silent.local_addr().unwrap().to_string();
+    std::thread::spawn(move || {
+        let _held: Vec<_> = silent.incoming().collect();
+    });
+
+    let settings = dimse::AssociationSettings {
+        calling_ae_title: "CUSTOM-SCU".into(),
+        connect_timeout: Some(std::time::Duration::from_millis(300)),
+        ..Default::default()
+    };
+    let started = std::time::Instant::now();
+    let err = scu::echo_traced(&addr, &settings, None).expect_err("silent peer");
+    assert!(err.to_string().contains("Timed out"), "{:#}", err);
+    assert!(started.elapsed() < std::time::Duration::from_secs(5));
+
+    let long_title = dimse::AssociationSettings {
+        calling_ae_title: "A-TITLE-OVER-16-CHARS".into(),
+        ..settings.clone()
+    };
+    assert!(scu::echo_traced(&addr, &long_title, None).is_err());
+    let tiny_pdu = dimse::AssociationSettings {
+        max_pdu_length: 1024,
+        ..settings
+    };
+    assert!(tiny_pdu.validate().is_err());
+}
+
+#[test]
+fn retrieve_moves_a_study_into_a_directory() {
+    let dir = tempdir().expect("tempdir");
+    let spec = synth::SynthSpec {
+        instances: 3,
+        ..synth::SynthSpec::default()
+    };
+    let files = synth::write_series(&spec, dir.path()).expect("series");
+    let first = dicom::object::open_file(&files[0]).expect("open");
+    let uid = |tag| {
+        first
+            .element(tag)
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .trim_end_matches('\0')
+            .to_string()
+    };
+    let (study, series) = (uid(Tag(0x0020, 0x000D)), uid(Tag(0x0020, 0x000E)));
+
+    let out = tempdir().expect("out dir");
+    let retriever = scu::MoveRetriever::bind(
+        "127.0.0.1:0",
+        scu::RetrieveOptions {
+            association: dimse::AssociationSettings {
+                calling_ae_title: "RETRIEVER".into(),
+                called_ae_title: "DICOM-TOOLS".into(),
+                ..Default::default()
+            },
+            output: out.path().join("study"),
+        },
+    )
+    .expect("bind retriever");
+    let mut destinations = scp::AeMap::default();
+    destinations
+        .insert_entry(&format!("RETRIEVER={}", retriever.local_addr().unwrap()))
+        .unwrap();
+    let server = scp::RetrieveScp::bind(
+        "127.0.0.1:0",
+        scp::ScpConfig {
+            ae_title: "DICOM-TOOLS".into(),
+            root: dir.path().to_path_buf(),
+            destinations,
+            trace: None,
+            store: None,
+            router: None,
+            worklist: None,
+            mpps: None,
+            audit: None,
+        },
+    )
+    .expect("bind scp");
+    let addr = server.local_addr().unwrap().to_string();
+    std::thread::spawn(move || server.serve());
+
+    let summary = retriever
+        .retrieve(
+            &addr,
+            &scu::RetrieveTarget::Series { study, series },
+            &progress::NoProgress,
+            None,
+        )
+        .expect("retrieve");
+    assert_eq!(summary.status, dimse::status::SUCCESS);
+    assert_eq!(summary.completed, 3);
+    assert_eq!(summary.stored.len(), 3);
+    for (path, source) in summary.stored.iter().zip(&files) {
+        let received = dicom::object::open_file(path).expect("open received");
+        let original = dicom::object::open_file(source).expect("open source");
+        assert_eq!(
+            received
+                .element(Tag(0x7FE0, 0x0010))
+                .unwrap()
+                .to_bytes()
+                .unwrap(),
+            original
+                .element(Tag(0x7FE0, 0x0010))
+                .unwrap()
+                .to_bytes()
+                .unwrap()
+        );
+        assert_eq!(
+            received
+                .meta()
+                .source_application_entity_title
+                .as_deref()
+                .map(str::trim_end),
+            Some("DICOM-TOOLS")
+        );
+    }
+}
+
+#[test]
+fn get_receives_a_series_on_the_same_association() {
+    let dir = tempdir().expect("tempdir");
+    let spec = synth::SynthSpec {
+        instances: 2,
+        ..synth::SynthSpec::default()
+    };
+    let files = synth::write_series(&spec, dir.path()).expect("series");
+    let first = dicom::object::open_file(&files[0]).expect("open");
+    let study = first
+        .element(Tag(0x0020, 0x000D))
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .trim_end_matches('\0')
+        .to_string();
+
+    let server = scp::RetrieveScp::bind(
+        "127.0.0.1:0",
+        scp::ScpConfig {
+            ae_title: "DICOM-TOOLS".into(),
+            root: dir.path().to_path_buf(),
+            destinations: scp::AeMap::default(),
+            trace: None,
+            store: None,
+            router: None,
+            worklist: None,
+            mpps: None,
+            audit: None,
+        },
+    )
+    .expect("bind scp");
+    let addr = server.local_addr().unwrap().to_string();
+    std::thread::spawn(move || server.serve());
+
+    let out = tempdir().expect("out dir");
+    let summary = scu::get(
+        &addr,
+        &scu::RetrieveOptions {
+            association: dimse::AssociationSettings {
+                calling_ae_title: "GETSCU".into(),
+                called_ae_title: "DICOM-TOOLS".into(),
+                ..Default::default()
+            },
+            output: out.path().to_path_buf(),
+        },
+        &scu::RetrieveTarget::Study(study),
+        &progress::NoProgress,
+        None,
+    )
+    .expect("get");
+    assert_eq!(summary.status, dimse::status::SUCCESS);
+    assert_eq!(summary.completed, 2);
+    assert_eq!(summary.stored.len(), 2);
+    for path in &summary.stored {
+        dicom::object::open_file(path).expect("open received");
+    }
+}
+
+#[test]
+fn scp_routes_incoming_stores_through_matching_rules() {
+    let (dir, path) = build_test_dicom();
+    let out = tempdir().expect("out dir");
+    let archive = out.path().join("archive");
+    let mr_only = out.path().join("mr");
+    let rules = format!(
+        r#"
+        [[rules]]
+        name = "anonymized-archive"
+        match = {{ modality = ["OT"], station_ae = ["THIS-SCU"] }}
+        actions = [{{ do = "anonymize" }}, {{ do = "store", dir = {:?} }}]
+
+        [[rules]]
+        name = "mr-only"
+        match = {{ modality = ["MR"] }}
+        actions = [{{ do = "store", dir = {:?} }}]
+        "#,
+        archive, mr_only
+    );
+    let router = router::Router::new(toml::from_str(&rules).expect("rules")).expect("router");
+
+    let server = scp::RetrieveScp::bind(
+        "127.0.0.1:0",
+        scp::ScpConfig {
+            ae_title: "DICOM-TOOLS".into(),
+            root: dir.path().to_path_buf(),
+            destinations: scp::AeMap::default(),
+            trace: None,
+            store: None,
+            router: Some(std::sync::Arc::new(router)),
+            worklist: None,
+            mpps: None,
+            audit: None,
+        },
+    )
+    .expect("bind scp");
+    let addr = server.local_addr().unwrap().to_string();
+    std::thread::spawn(move || server.serve());
+
+    scu::push(&addr, &path).expect("push");
+
+    let stored = archive.join("1.2.826.0.1.3680043.2.1125.1.dcm");
+    let meta = metadata::read_basic_metadata(&stored).expect("routed copy");
+    assert_ne!(meta.patient_name.as_deref(), Some("Test^Patient"));
+    assert!(!mr_only.exists());
+}
+
+#[test]
+fn worklist_queries_read_scheduled_steps_back() {
+    let dir = tempdir().expect("tempdir");
+    let worklist_path = dir.path().join("worklist.csv");
+    std::fs::write(
+        &worklist_path,
+        "patient_name,patient_id,accession_number,modality,scheduled_station_ae_title,scheduled_date,scheduled_time,scheduled_procedure_step_description\n\
+         Doe^Jane,P1,A1,CT,CT01,20250102,093000,Chest CT\n\
+         Roe^Rich,P2,A2,CT,CT02,20250102,101500,Head CT\n\
+         Poe^Ann,P3,A3,MR,MR01,20250102,110000,Knee MR\n\
+         Moe^Max,P4,A4,CT,CT01,20250103,080000,Abdomen CT\n",
+    )
+    .unwrap();
+    let server = scp::RetrieveScp::bind(
+        "127.0.0.1:0",
+        scp::ScpConfig {
+            ae_title: "DICOM-TOOLS".into(),
+            root: dir.path().to_path_buf(),
+            destinations: scp::AeMap::default(),
+            trace: None,
+            store: None,
+            router: None,
+            worklist: Some(worklist::WorklistSource::File(worklist_path)),
+            mpps: None,
+            audit: None,
+        },
+    )
+    .expect("bind scp");
+    let addr = server.local_addr().unwrap().to_string();
+    std::thread::spawn(move || server.serve());
+
+    let settings = dimse::AssociationSettings {
+        called_ae_title: "DICOM-TOOLS".into(),
+        ..Default::default()
+    };
+    let query = worklist::WorklistQuery {
+        scheduled_date: Some("20250102".into()),
+        modality: Some("CT".into()),
+        station_ae_title: None,
+    };
+    let answers = scu::find(
+        &addr,
+        &settings,
+        worklist::MODALITY_WORKLIST_FIND,
+        &query.identifier(),
+        None,
+    )
+    .expect("worklist query");
+    let items: Vec<_> = answers
+        .iter()
+        .map(worklist::WorklistItem::from_identifier)
+        .collect();
+    assert_eq!(items.len(), 2);
+    assert_eq!(items[0].patient_name.as_deref(), Some("Doe^Jane"));
+    assert_eq!(items[0].accession_number.as_deref(), Some("A1"));
+    assert_eq!(items[0].scheduled_station_ae_title.as_deref(), Some("CT01"));
+    assert_eq!(items[0].scheduled_time.as_deref(), Some("093000"));
+    assert_eq!(
+        items[1].scheduled_procedure_step_description.as_deref(),
+        Some("Head CT")
+    );
+
+    let by_station = worklist::WorklistQuery {
+        station_ae_title: Some("CT01".into()),
+        ..Default::default()
+    };
+    let answers = scu::find(
+        &addr,
+        &settings,
+        worklist::MODALITY_WORKLIST_FIND,
+        &by_station.identifier(),
+        None,
+    )
+    .expect("station query");
+    let patients: Vec<_> = answers
+        .iter()
+        .map(|answer| worklist::WorklistItem::from_identifier(answer).patient_id)
+        .collect();
+    assert_eq!(patients, [Some("P1".to_string()), Some("P4".to_string())]);
+}
+
+#[test]
+fn worklist_scp_answers_modality_worklist_queries() {
+    let dir = tempdir().expect("tempdir");
+    let spec = synth::SynthSpec {
+        instances: 2,
+        ..synth::SynthSpec::default()
+    };
+    synth::write_series(&spec, &dir.path().join("series")).expect("synth");
+    let from_index = worklist::Worklist::from_directory(dir.path()).expect("index worklist");
+    assert_eq!(from_index.len(), 1);
+
+    let worklist_path = dir.path().join("worklist.json");
+    std::fs::write(
+        &worklist_path,
+        r#"[
+            {"patient_name": "Doe^Jane", "patient_id": "P1", "accession_number": "A1",
+             "modality": "CT", "scheduled_station_ae_title": "CT01",
+             "scheduled_date": "20250102", "scheduled_time": "093000",
+             "scheduled_procedure_step_id": "SPS1"},
+            {"patient_name": "Roe^Rich", "patient_id": "P2", "modality": "MR",
+             "scheduled_date": "20250102"}
+        ]"#,
+    )
+    .unwrap();
+    let server = scp::RetrieveScp::bind(
+        "127.0.0.1:0",
+        scp::ScpConfig {
+            ae_title: "DICOM-TOOLS".into(),
+            root: dir.path().to_path_buf(),
+            destinations: scp::AeMap::default(),
+            trace: None,
+            store: None,
+            router: None,
+            worklist: Some(worklist::WorklistSource::File(worklist_path)),
+            mpps: None,
+            audit: None,
+        },
+    )
+    .expect("bind scp");
+    let addr = server.local_addr().unwrap().to_string();
+    std::thread::spawn(move || server.serve());
+
+    let mut association = dicom_ul::ClientAssociationOptions::new()
+        .with_abstract_syntax(worklist::MODALITY_WORKLIST_FIND)
+        .establish(addr.as_str())
+        .expect("associate");
+    let mut step = InMemDicomObject::new_empty();
+    step.put(DataElement::new(
+        Tag(0x0008, 0x0060),
+        VR::CS,
+        PrimitiveValue::from("CT"),
+    ));
+    step.put(DataElement::new(
+        Tag(0x0040, 0x0002),
+        VR::DA,
+        PrimitiveValue::from("20250101-20250131"),
+    ));
+    step.put(DataElement::new(
+        Tag(0x0040, 0x0009),
+        VR::SH,
+        PrimitiveValue::Empty,
+    ));
+    let mut query = InMemDicomObject::new_empty();
+    query.put(DataElement::new(
+        Tag(0x0010, 0x0010),
+        VR::PN,
+        PrimitiveValue::Empty,
+    ));
+    query.put(DataElement::new(
+        Tag(0x0040, 0x0100),
+        VR::SQ,
+        dicom::core::value::DataSetSequence::from(vec![step]),
+    ));
+    let ts = dimse::negotiated_ts(&association, 1).expect("ts");
+    let mut identifier = Vec::new();
+    query
+        .write_dataset_with_ts(&mut identifier, ts)
+        .expect("encode query");
+    let request = retrieve_request(
+        dimse::command::C_FIND_RQ,
+        worklist::MODALITY_WORKLIST_FIND,
+        None,
+    );
+    dimse::send_message(&mut association, 1, request, Some(&identifier)).expect("send find");
+
+    let mut answers = Vec::new();
+    loop {
+        let message = dimse::read_message(&mut association)
+            .expect("read")
+            .expect("message");
+        if message.status() != Some(dimse::status::PENDING) {
+            assert_eq!(message.status(), Some(dimse::status::SUCCESS));
+            break;
+        }
+        answers.push(message.dataset(&association).unwrap().expect("identifier"));
+    }
+    let _ = association.release();
+
+    assert_eq!(answers.len(), 1);
+    let answer = &answers[0];
+    assert_eq!(
+        answer
+            .element(Tag(0x0010, 0x0010))
+            .unwrap()
+            .to_str()
+            .unwrap(),
+        "Doe^Jane"
+    );
+    assert!(answer.element(Tag(0x0010, 0x0020)).is_err());
+    let step = &answer
+        .element(Tag(0x0040, 0x0100))
+        .unwrap()
+        .items()
+        .unwrap()[0];
+    assert_eq!(
+        step.element(Tag(0x0040, 0x0009))
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .trim_end(),
+        "SPS1"
+    );
+}
+
+#[test]
+fn mpps_steps_are_created_in_progress_and_finished_once() {
+    let acquired = tempdir().expect("tempdir");
+    let spec = synth::SynthSpec {
+        instances: 3,
+        ..synth::SynthSpec::default()
+    };
+    let files = synth::write_series(&spec, acquired.path()).expect("series");
+    let steps = tempdir().expect("tempdir");
+    let server = scp::RetrieveScp::bind(
+        "127.0.0.1:0",
+        scp::ScpConfig {
+            ae_title: "DICOM-TOOLS".into(),
+            root: steps.path().to_path_buf(),
+            destinations: scp::AeMap::default(),
+            trace: None,
+            store: None,
+            router: None,
+            worklist: None,
+            mpps: Some(std::sync::Arc::new(
+                mpps::MppsStore::new(steps.path()).expect("mpps store"),
+            )),
+            audit: None,
+        },
+    )
+    .expect("bind scp");
+    let addr = server.local_addr().unwrap().to_string();
+    std::thread::spawn(move || server.serve());
+
+    let settings = dimse::AssociationSettings {
+        calling_ae_title: "CT01".into(),
+        called_ae_title: "DICOM-TOOLS".into(),
+        ..Default::default()
+    };
+    let procedure = mpps::Procedure::scan(acquired.path()).expect("scan procedure");
+    let now = chrono::Local::now().naive_local();
+    let uid = "1.2.826.0.1.3680043.2.1125.9.1";
+    let in_progress = procedure.in_progress("PPS1", "CT01", now);
+    scu::n_create(
+        &addr,
+        &settings,
+        mpps::MODALITY_PERFORMED_PROCEDURE_STEP,
+        uid,
+        &in_progress,
+        None,
+    )
+    .expect("n-create");
+    let stored = mpps::MppsStore::new(steps.path()).unwrap();
+    let step = stored.load(uid).unwrap().expect("step stored");
+    assert_eq!(
+        mpps::StepStatus::of(&step),
+        Some(mpps::StepStatus::InProgress)
+    );
+
+    let completed = procedure.completion(mpps::StepStatus::Completed, now, Some("PACS"));
+    scu::n_set(
+        &addr,
+        &settings,
+        mpps::MODALITY_PERFORMED_PROCEDURE_STEP,
+        uid,
+        &completed,
+        None,
+    )
+    .expect("n-set");
+    let step = stored.load(uid).unwrap().unwrap();
+    assert_eq!(
+        mpps::StepStatus::of(&step),
+        Some(mpps::StepStatus::Completed)
+    );
+    let first = dicom::object::open_file(&files[0]).unwrap();
+    let first_uid = first
+        .element(Tag(0x0008, 0x0018))
+        .unwrap()
+        .to_str()
+        .unwrap();
+    let referenced = mpps::referenced_instances(&step);
+    assert_eq!(referenced.len(), 3);
+    assert!(referenced
+        .iter()
+        .any(|(_, sop_instance)| sop_instance == first_uid.trim_end_matches('\0')));
+
+    // A finished step is final, and unknown steps cannot be set.
+    let discontinued = procedure.completion(mpps::StepStatus::Discontinued, now, None);
+    let err = scu::n_set(
+        &addr,
+        &settings,
+        mpps::MODALITY_PERFORMED_PROCEDURE_STEP,
+        uid,
+        &discontinued,
+        None,
+    )
+    .unwrap_err();
+    assert!(err.to_string().contains("0x0110"), "{err}");
+    let err = scu::n_set(
+        &addr,
+        &settings,
+        mpps::MODALITY_PERFORMED_PROCEDURE_STEP,
+        "1.2.3.999",
+        &discontinued,
+        None,
+    )
+    .unwrap_err();
+    assert!(err.to_string().contains("0x0112"), "{err}");
+}
+
+#[test]
+fn store_scp_saves_received_instances_to_the_file_store() {
+    let (_dir, path) = build_test_dicom();
+    let received = tempdir().expect("store dir");
+    let store = storage::FileStore::new(received.path()).expect("store");
+    let server = scp::RetrieveScp::bind(
+        "127.0.0.1:0",
+        scp::ScpConfig {
+            ae_title: "DICOM-TOOLS".into(),
+            root: received.path().to_path_buf(),
+            destinations: scp::AeMap::default(),
+            trace: None,
+            store: Some(store.clone()),
+            router: None,
+            worklist: None,
+            mpps: None,
+            audit: None,
+        },
+    )
+    .expect("bind scp");
+    let addr = server.local_addr().unwrap().to_string();
+    std::thread::spawn(move || server.serve());
+
+    // Identical content is stored once.
+    scu::push(&addr, &path).expect("push");
+    scu::push(&addr, &path).expect("push again");
+    let uploads = store.uploads().expect("uploads");
+    assert_eq!(uploads.len(), 1);
+    assert!(uploads[0].starts_with("1_2_826_0_1_3680043_2_1125_1-"));
+    let saved = dicom::object::open_file(store.resolve(&uploads[0]).unwrap()).expect("saved");
+    assert_eq!(
+        saved
+            .meta()
+            .source_application_entity_title
+            .as_deref()
+            .map(str::trim_end),
+        Some("THIS-SCU")
+    );
+    assert_eq!(scp::InstanceIndex::scan(received.path()).unwrap().len(), 1);
+
+    // Over-quota instances are refused and leave nothing behind.
+    let full = tempdir().expect("full store dir");
+    let store = storage::FileStore::new(full.path())
+        .expect("store")
+        .with_limits(storage::StoreLimits {
+            max_upload_bytes: Some(16),
+            ..storage::StoreLimits::default()
+        });
+    let server = scp::RetrieveScp::bind(
+        "127.0.0.1:0",
+        scp::ScpConfig {
+            ae_title: "DICOM-TOOLS".into(),
+            root: full.path().to_path_buf(),
+            destinations: scp::AeMap::default(),
+            trace: None,
+            store: Some(store.clone()),
+            router: None,
+            worklist: None,
+            mpps: None,
+            audit: None,
+        },
+    )
+    .expect("bind scp");
+    let addr = server.local_addr().unwrap().to_string();
+    std::thread::spawn(move || server.serve());
+    scu::push(&addr, &path).expect("push");
+    assert!(store.uploads().expect("uploads").is_empty());
+}
+
+#[test]
+fn scp_rules_can_reject_received_instances() {
+    let source = tempdir().expect("tempdir");
+    let valid = synth::write_series(&synth::SynthSpec::default(), source.path()).expect("series")
+        [0]
+    .clone();
+    let mut obj = dicom::object::open_file(&valid).expect("open");
+    obj.remove_element(Tag(0x0010, 0x0020));
+    let invalid = source.path().join("no-patient-id.dcm");
+    obj.write_to_file(&invalid).expect("write");
+
+    // A mirror that is never reachable.
+    let closed = std::net::TcpListener::bind("127.0.0.1:0").expect("bind");
+    let mirror = closed.local_addr().unwrap().to_string();
+    drop(closed);
+    let logs = tempdir().expect("logs");
+    let index = logs.path().join("received.jsonl");
+
+    let serve = |policy: &str| {
+        let rules = format!(
+            r#"
+            [nodes]
+            MIRROR = "{}"
+
+            [[rules]]
+            name = "gate"
+            actions = [{{ do = "validate" }}, {{ do = "index", file = '{}' }}]
+
+            [[rules]]
+            name = "mirror"
+            on_failure = "{}"
+            actions = [{{ do = "push", node = "MIRROR" }}]
+            "#,
+            mirror,
+            index.display(),
+            policy
+        );
+        let router = router::Router::new(toml::from_str(&rules).expect("rules")).expect("router");
+        let received = tempdir().expect("store dir");
+        let store = storage::FileStore::new(received.path()).expect("store");
+        let server = scp::RetrieveScp::bind(
+            "127.0.0.1:0",
+            scp::ScpConfig {
+                ae_title: "DICOM-TOOLS".into(),
+                root: received.path().to_path_buf(),
+                destinations: scp::AeMap::default(),
+                trace: None,
+                store: Some(store.clone()),
+                router: Some(std::sync::Arc::new(router)),
+                worklist: None,
+                mpps: None,
+                audit: None,
+            },
+        )
+        .expect("bind scp");
+        let addr = server.local_addr().unwrap().to_string();
+        std::thread::spawn(move || server.serve());
+        (addr, store, received)
+    };
+    let push = |addr: &str, path: &std::path::Path, log: &str| {
+        let log = logs.path().join(log);
+        let tracer = dimse_trace::DimseTracer::create(&log, false).expect("tracer");
+        scu::push_traced(
+            addr,
+            path,
+            &dimse::AssociationSettings::default(),
+            &progress::NoProgress,
+            Some(tracer),
+        )
+        .expect("push");
+        std::fs::read_to_string(&log).expect("log")
+    };
+
+    // A failing mirror is ignored; the instance is indexed and stored.
+    let (addr, store, _received) = serve("ignore");
+    assert!(push(&addr, &valid, "ignored.log").contains("US Status = 0\n"));
+    assert_eq!(store.uploads().unwrap().len(), 1);
+    let indexed = std::fs::read_to_string(&index).expect("index");
+    assert_eq!(indexed.lines().count(), 1);
+    assert!(indexed.contains("\"station_ae\":\"THIS-SCU\""));
+
+    // Invalid instances fail the gate, whose default policy is to reject.
+    assert!(push(&addr, &invalid, "invalid.log").contains("US Status = 49152"));
+    assert_eq!(store.uploads().unwrap().len(), 1);
+    assert_eq!(std::fs::read_to_string(&index).unwrap().lines().count(), 1);
+
+    // A mirror failure under out-of-resources refuses the instance with 0xA700.
+    let (addr, store, _received) = serve("out-of-resources");
+    assert!(push(&addr, &valid, "busy.log").contains("US Status = 42752"));
+    assert!(store.uploads().unwrap().is_empty());
+}
+
+#[test]
+fn push_transcodes_to_the_first_accepted_preferred_syntax() {
+    let source = tempdir().expect("tempdir");
+    let spec = synth::SynthSpec {
+        bits_stored: 8,
+        ..synth::SynthSpec::default()
+    };
+    let path = synth::write_series(&spec, source.path()).expect("series")[0].clone();
+    let received = tempdir().expect("store dir");
+    let store = storage::FileStore::new(received.path()).expect("store");
+    let server = scp::RetrieveScp::bind(
+        "127.0.0.1:0",
+        scp::ScpConfig {
+            ae_title: "DICOM-TOOLS".into(),
+            root: received.path().to_path_buf(),
+            destinations: scp::AeMap::default(),
+            trace: None,
+            store: Some(store.clone()),
+            router: None,
+            worklist: None,
+            mpps: None,
+            audit: None,
+        },
+    )
+    .expect("bind scp");
+    let addr = server.local_addr().unwrap().to_string();
+    std::thread::spawn(move || server.serve());
+
+    let push_as = |preference: &str| {
+        let settings = dimse::AssociationSettings {
+            transfer_syntaxes: preference.parse().expect("preference"),
+            ..Default::default()
+        };
+        scu::push_traced(&addr, &path, &settings, &progress::NoProgress, None).expect("push");
+        let uploads = store.uploads().expect("uploads");
+        assert_eq!(uploads.len(), 1);
+        let saved = dicom::object::open_file(store.resolve(&uploads[0]).unwrap()).expect("saved");
+        store.release(&uploads[0]).expect("release");
+        saved
+    };
+
+    // No JPEG-LS encoder is built in, so JPEG Baseline is the first one offered.
+    let saved = push_as("jpeg-ls,jpeg-baseline,explicit");
+    assert_eq!(
+        saved.meta().transfer_syntax().trim_end_matches('\0'),
+        "1.2.840.10008.1.2.4.50"
+    );
+    let lossy = |obj: &dicom::object::DefaultDicomObject| {
+        obj.element(Tag(0x0028, 0x2110))
+            .ok()
+            .map(|e| e.to_str().unwrap().trim_end().to_string())
+    };
+    assert_eq!(lossy(&saved).as_deref(), Some("01"));
+
+    let saved = push_as("implicit");
+    assert_eq!(
+        saved.meta().transfer_syntax().trim_end_matches('\0'),
+        "1.2.840.10008.1.2"
+    );
+    assert_eq!(lossy(&saved), None);
+}
+
+#[test]
+fn push_decompresses_for_a_peer_accepting_only_native_syntaxes() {
+    let dir = tempdir().expect("tempdir");
+    let spec = synth::SynthSpec {
+        bits_stored: 8,
+        ..synth::SynthSpec::default()
+    };
+    let native = synth::write_series(&spec, &dir.path().join("native")).expect("series")[0].clone();
+    let jpeg = dir.path().join("jpeg.dcm");
+    transcode::transcode_lossy(
+        &native,
+        &jpeg,
+        transcode::LossyTransferSyntax::JpegBaseline,
+        &transcode::LossyOptions::default(),
+    )
+    .expect("jpeg");
+
+    // A storage SCP that only knows Explicit VR Little Endian.
+    let peer = std::net::TcpListener::bind("127.0.0.1:0").expect("bind peer");
+    let addr = peer.local_addr().unwrap().to_string();
+    let sop_class = scp::IndexedInstance::read(&jpeg)
+        .expect("instance")
+        .sop_class_uid;
+    let peer_thread = std::thread::spawn(move || {
+        let (stream, _) = peer.accept().expect("accept");
+        let mut association = dicom_ul::ServerAssociationOptions::new()
+            .with_abstract_syntax(sop_class)
+            .with_transfer_syntax(EXPLICIT_VR_LITTLE_ENDIAN.uid())
+            .establish(stream)
+            .expect("association");
+        let mut received = Vec::new();
+        while let Some(request) = dimse::read_message(&mut association).expect("read") {
+            let ts = dimse::negotiated_ts(&association, request.pc_id).unwrap();
+            received.push((
+                ts.uid().to_string(),
+                request.dataset(&association).unwrap().expect("data set"),
+            ));
+            let response = dimse::response_to(&request, dimse::status::SUCCESS, false);
+            dimse::send_message(&mut association, request.pc_id, response, None)
+                .expect("store rsp");
+        }
+        received
+    });
+
+    scu::push(&addr, &jpeg).expect("push");
+    let received = peer_thread.join().expect("peer");
+    assert_eq!(received.len(), 1);
+    let (ts, data) = &received[0];
+    assert_eq!(ts, EXPLICIT_VR_LITTLE_ENDIAN.uid());
+    let pixels = data.element(Tag(0x7FE0, 0x0010)).expect("pixel data");
+    assert!(pixels.fragments().is_none());
+    assert_eq!(pixels.to_bytes().unwrap().len(), 64 * 64);
+    // Decompressing does not undo the loss.
+    assert_eq!(
+        data.element(Tag(0x0028, 0x2110))
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .trim_end(),
+        "01"
+    );
+}
+
+#[test]
+fn push_dir_sends_a_directory_within_its_rate_limit() {
+    let dir = tempdir().expect("tempdir");
+    let spec = synth::SynthSpec {
+        instances: 3,
+        ..synth::SynthSpec::default()
+    };
+    synth::write_series(&spec, dir.path()).expect("series");
+    std::fs::write(dir.path().join("notes.txt"), "not dicom").unwrap();
+
+    let received = tempdir().expect("store dir");
+    let store = storage::FileStore::new(received.path()).expect("store");
+    let server = scp::RetrieveScp::bind(
+        "127.0.0.1:0",
+        scp::ScpConfig {
+            ae_title: "DICOM-TOOLS".into(),
+            root: received.path().to_path_buf(),
+            destinations: scp::AeMap::default(),
+            trace: None,
+            store: Some(store.clone()),
+            router: None,
+            worklist: None,
+            mpps: None,
+            audit: None,
+        },
+    )
+    .expect("bind scp");
+    let addr = server.local_addr().unwrap().to_string();
+    std::thread::spawn(move || server.serve());
+
+    let options = scu::PushDirOptions {
+        association: dimse::AssociationSettings {
+            calling_ae_title: "MIGRATOR".into(),
+            called_ae_title: "DICOM-TOOLS".into(),
+            ..Default::default()
+        },
+        rate_limit: Some(0.05),
+        window: Some("00:00-00:00".parse().unwrap()),
+        audit: None,
+        queue: None,
+    };
+    let started = std::time::Instant::now();
+    let events = std::sync::Mutex::new(Vec::new());
+    let sink = |event: progress::ProgressEvent| events.lock().unwrap().push(event);
+    let summary = scu::push_dir(&addr, dir.path(), &options, &sink, None).expect("push dir");
+    assert_eq!(summary.sent, 3);
+    assert!(summary.failed.is_empty());
+    assert_eq!(summary.skipped, vec![dir.path().join("notes.txt")]);
+    assert_eq!(store.uploads().unwrap().len(), 3);
+    // One timed transfer per instance, adding up to the run's bytes and time.
+    assert_eq!(summary.transfers.len(), 3);
+    assert!(summary.transfers.iter().all(|t| t.status == Some(0)));
+    assert!(summary
+        .transfers
+        .iter()
+        .all(|t| t.path.extension().is_some_and(|ext| ext == "dcm") && t.bytes > 0));
+    let sent: u64 = summary.transfers.iter().map(|t| t.bytes).sum();
+    assert_eq!(sent, summary.bytes);
+    // The rate limit paces every instance, so each one takes measurable time.
+    assert!(summary.transfers.iter().all(|t| !t.elapsed.is_zero()));
+    let timed: std::time::Duration = summary.transfers.iter().map(|t| t.elapsed).sum();
+    assert!(timed <= summary.elapsed);
+
+    // Progress counts file bytes: before each instance, the sizes of those already sent.
+    let events = events.into_inner().unwrap();
+    let sizes: Vec<u64> = summary
+        .transfers
+        .iter()
+        .map(|t| std::fs::metadata(&t.path).unwrap().len())
+        .collect();
+    let file_bytes: u64 = sizes.iter().sum();
+    assert!(events.iter().all(|e| e.total == file_bytes));
+    let pushes: Vec<&progress::ProgressEvent> =
+        events.iter().filter(|e| e.phase == "push").collect();
+    assert_eq!(pushes.len(), 3);
+    let mut before = 0;
+    for ((event, transfer), size) in pushes.iter().zip(&summary.transfers).zip(&sizes) {
+        assert_eq!(event.current, before);
+        assert!(event
+            .item
+            .as_deref()
+            .is_some_and(|item| transfer.path.ends_with(item)));
+        before += size;
+    }
+    let last = events.last().unwrap();
+    assert_eq!((last.phase.as_str(), last.current), ("done", file_bytes));
+    assert_eq!(last.percent, 100.0);
+    // The average rate stays under the cap.
+    let floor = summary.bytes as f64 / (0.05 * 1024.0 * 1024.0);
+    assert!(started.elapsed().as_secs_f64() >= floor * 0.9);
+}
+
+#[test]
+fn push_dir_queues_instances_while_the_peer_is_down_and_retries_them() {
+    let dir = tempdir().expect("tempdir");
+    let spec = synth::SynthSpec {
+        instances: 3,
+        ..synth::SynthSpec::default()
+    };
+    synth::write_series(&spec, dir.path()).expect("series");
+    let spool = tempdir().expect("queue dir");
+    let queue = send_queue::SendQueue::open(spool.path())
+        .expect("queue")
+        .with_policy(send_queue::RetryPolicy {
+            initial_delay: std::time::Duration::ZERO,
+            max_delay: std::time::Duration::ZERO,
+            max_attempts: 5,
+        });
+
+    // The PACS is down during the push.
+    let closed = std::net::TcpListener::bind("127.0.0.1:0").expect("bind");
+    let addr = closed.local_addr().unwrap().to_string();
+    drop(closed);
+    let options = scu::PushDirOptions {
+        association: dimse::AssociationSettings {
+            called_ae_title: "DICOM-TOOLS".into(),
+            ..Default::default()
+        },
+        rate_limit: None,
+        window: None,
+        audit: None,
+        queue: Some(queue.clone()),
+    };
+    let summary =
+        scu::push_dir(&addr, dir.path(), &options, &progress::NoProgress, None).expect("push dir");
+    assert_eq!(summary.sent, 0);
+    assert_eq!((summary.failed.len(), summary.queued), (3, 3));
+    assert_eq!(queue.pending().unwrap().len(), 3);
+
+    // Back up on the same address.
+    let received = tempdir().expect("store dir");
+    let store = storage::FileStore::new(received.path()).expect("store");
+    let server = scp::RetrieveScp::bind(
+        &addr,
+        scp::ScpConfig {
+            ae_title: "DICOM-TOOLS".into(),
+            root: received.path().to_path_buf(),
+            destinations: scp::AeMap::default(),
+            trace: None,
+            store: Some(store.clone()),
+            router: None,
+            worklist: None,
+            mpps: None,
+            audit: None,
+        },
+    )
+    .expect("bind scp");
+    std::thread::spawn(move || server.serve());
+
+    let delivery = send_queue::DeliveryOptions::default();
+    let drained = queue
+        .drain(
+            &mut |entry, file| send_queue::deliver(entry, file, &delivery),
+            true,
+        )
+        .expect("drain");
+    assert_eq!(drained.sent, 3);
+    assert_eq!((drained.retrying, drained.failed), (0, 0));
+    assert!(queue.pending().unwrap().is_empty());
+    assert_eq!(store.uploads().unwrap().len(), 3);
+}
+
+#[test]
+fn push_dir_streams_the_entries_of_a_zip_export() {
+    use std::io::Write;
+
+    let dir = tempdir().expect("tempdir");
+    let spec = synth::SynthSpec {
+        instances: 2,
+        ..synth::SynthSpec::default()
+    };
+    let files = synth::write_series(&spec, &dir.path().join("series")).expect("series");
+    let archive = dir.path().join("export.zip");
+    let mut zip = zip::ZipWriter::new(std::fs::File::create(&archive).expect("zip"));
+    let entry = zip::write::SimpleFileOptions::default();
+    for (n, file) in files.iter().enumerate() {
+        zip.start_file(format!("DICOM/SER1/IM{}", n), entry)
+            .expect("entry");
+        zip.write_all(&std::fs::read(file).expect("read"))
+            .expect("write");
+    }
+    zip.start_file("README.txt", entry).expect("entry");
+    zip.write_all(b"exported by PACS").expect("write");
+    zip.finish().expect("finish");
+
+    let received = tempdir().expect("store dir");
+    let store = storage::FileStore::new(received.path()).expect("store");
+    let server = scp::RetrieveScp::bind(
+        "127.0.0.1:0",
+        scp::ScpConfig {
+            ae_title: "DICOM-TOOLS".into(),
+            root: received.path().to_path_buf(),
+            destinations: scp::AeMap::default(),
+            trace: None,
+            store: Some(store.clone()),
+            router: None,
+            worklist: None,
+            mpps: None,
+            audit: None,
+        },
+    )
+    .expect("bind scp");
+    let addr = server.local_addr().unwrap().to_string();
+    std::thread::spawn(move || server.serve());
+
+    let options = scu::PushDirOptions {
+        association: dimse::AssociationSettings {
+            called_ae_title: "DICOM-TOOLS".into(),
+            ..Default::default()
+        },
+        rate_limit: None,
+        window: None,
+        audit: None,
+        queue: None,
+    };
+    let summary =
+        scu::push_dir(&addr, &archive, &options, &progress::NoProgress, None).expect("push zip");
+    assert_eq!(summary.sent, 2);
+    assert!(summary.failed.is_empty());
+    assert!(summary.bytes > 0);
+    assert_eq!(store.uploads().unwrap().len(), 2);
+}
+
+#[test]
+fn scp_answers_study_root_queries_from_the_served_directory() {
+    let dir = tempdir().expect("tempdir");
+    for (seed, modality, instances) in [(1, "CT", 2), (2, "MR", 3)] {
+        let spec = synth::SynthSpec {
+            modality: modality.into(),
+            instances,
+            seed,
+            ..synth::SynthSpec::default()
+        };
+        synth::write_series(&spec, &dir.path().join(modality)).expect("series");
+    }
+    let ct = scp::InstanceIndex::scan(&dir.path().join("CT")).expect("index");
+    let (study, series) = (
+        ct.instances()[0].study_instance_uid.clone(),
+        ct.instances()[0].series_instance_uid.clone(),
+    );
+    let server = scp::RetrieveScp::bind(
+        "127.0.0.1:0",
+        scp::ScpConfig {
+            ae_title: "DICOM-TOOLS".into(),
+            root: dir.path().to_path_buf(),
+            destinations: scp::AeMap::default(),
+            trace: None,
+            store: None,
+            router: None,
+            worklist: None,
+            mpps: None,
+            audit: None,
+        },
+    )
+    .expect("bind scp");
+    let addr = server.local_addr().unwrap().to_string();
+    std::thread::spawn(move || server.serve());
+
+    let settings = dimse::AssociationSettings {
+        called_ae_title: "DICOM-TOOLS".into(),
+        ..Default::default()
+    };
+    let query = |keys: &[(Tag, VR, &str)]| {
+        let mut identifier = InMemDicomObject::new_empty();
+        for &(tag, vr, value) in keys {
+            let value = if value.is_empty() {
+                PrimitiveValue::Empty
+            } else {
+                PrimitiveValue::from(value)
+            };
+            identifier.put(DataElement::new(tag, vr, value));
+        }
+        scu::find(&addr, &settings, scu::STUDY_ROOT_FIND, &identifier, None)
+    };
+    let text = |obj: &InMemDicomObject, tag: Tag| {
+        obj.element(tag)
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .trim_end_matches(['\0', ' '])
+            .to_string()
+    };
+    let level = Tag(0x0008, 0x0052);
+
+    let studies = query(&[
+        (level, VR::CS, "STUDY"),
+        (Tag(0x0008, 0x0061), VR::CS, "CT"),
+        (Tag(0x0020, 0x000D), VR::UI, ""),
+        (Tag(0x0020, 0x1208), VR::IS, ""),
+    ])
+    .expect("study query");
+    assert_eq!(studies.len(), 1);
+    assert_eq!(text(&studies[0], Tag(0x0020, 0x000D)), study);
+    assert_eq!(text(&studies[0], Tag(0x0020, 0x1208)), "2");
+    assert_eq!(text(&studies[0], Tag(0x0008, 0x0054)), "DICOM-TOOLS");
+    let by_name = query(&[
+        (level, VR::CS, "STUDY"),
+        (Tag(0x0010, 0x0010), VR::PN, "synth*"),
+    ])
+    .expect("name query");
+    assert_eq!(by_name.len(), 2);
+
+    let series_answers = query(&[
+        (level, VR::CS, "SERIES"),
+        (Tag(0x0020, 0x000D), VR::UI, &study),
+        (Tag(0x0008, 0x0060), VR::CS, ""),
+        (Tag(0x0020, 0x1209), VR::IS, ""),
+    ])
+    .expect("series query");
+    assert_eq!(series_answers.len(), 1);
+    assert_eq!(text(&series_answers[0], Tag(0x0008, 0x0060)), "CT");
+    assert_eq!(text(&series_answers[0], Tag(0x0020, 0x1209)), "2");
+
+    let images = query(&[
+        (level, VR::CS, "IMAGE"),
+        (Tag(0x0020, 0x000D), VR::UI, &study),
+        (Tag(0x0020, 0x000E), VR::UI, &series),
+        (Tag(0x0008, 0x0018), VR::UI, ""),
+        (Tag(0x0020, 0x0013), VR::IS, "2"),
+    ])
+    .expect("image query");
+    assert_eq!(images.len(), 1);
+
+    // Queries below the study level must name the study.
+    let err = query(&[(level, VR::CS, "SERIES")]).unwrap_err();
+    assert!(err.to_string().contains("0xA900"), "{err}");
+
+    // verify-remote against our own SCP finds every instance.
+    let remote = availability::RemoteAe {
+        addr: addr.clone(),
+        association: settings.clone(),
+    };
+    let report = availability::verify(dir.path(), &remote, None).expect("verify");
+    assert_eq!(report.studies.len(), 2);
+    assert!(report.studies.iter().all(|study| study.is_complete()));
+}
+
+#[test]
+fn verify_remote_reports_instances_missing_on_either_side() {
+    let dir = tempdir().expect("tempdir");
+    let spec = synth::SynthSpec {
+        instances: 2,
+        ..synth::SynthSpec::default()
+    };
+    synth::write_series(&spec, dir.path()).expect("series");
+    let index = scp::InstanceIndex::scan(dir.path()).expect("index");
+    let mut local: Vec<_> = index.instances().to_vec();
+    local.sort_by(|a, b| a.sop_instance_uid.cmp(&b.sop_instance_uid));
+    let (study, series) = (
+        local[0].study_instance_uid.clone(),
+        local[0].series_instance_uid.clone(),
+    );
+
+    // The PACS holds the first local instance and one the directory lacks; it answers one
+    // SERIES-level and one IMAGE-level query, each on its own association.
+    let pacs = std::net::TcpListener::bind("127.0.0.1:0").expect("bind pacs");
+    let addr = pacs.local_addr().unwrap().to_string();
+    let held = [local[0].sop_instance_uid.clone(), "1.2.3.999".to_string()];
+    let pacs_series = series.clone();
+    let pacs_thread = std::thread::spawn(move || {
+        let mut levels = Vec::new();
+        for _ in 0..2 {
+            let (stream, _) = pacs.accept().expect("accept");
+            let mut association = dicom_ul::ServerAssociationOptions::new()
+                .with_abstract_syntax(scu::STUDY_ROOT_FIND)
+                .establish(stream)
+                .expect("association");
+            while let Some(request) = dimse::read_message(&mut association).expect("read") {
+                let query = request.dataset(&association).unwrap().expect("identifier");
+                let text = |tag| {
+                    query
+                        .element(tag)
+                        .unwrap()
+                        .to_str()
+                        .unwrap()
+                        .trim_end_matches(['\0', ' '])
+                        .to_string()
+                };
+                let level = text(Tag(0x0008, 0x0052));
+                let answers: Vec<(Tag, String)> = if level == "SERIES" {
+                    vec![(Tag(0x0020, 0x000E), pacs_series.clone())]
+                } else {
+                    assert_eq!(text(Tag(0x0020, 0x000E)), pacs_series);
+                    held.iter()
+                        .map(|uid| (Tag(0x0008, 0x0018), uid.clone()))
+                        .collect()
+                };
+                levels.push(level);
+                let ts = dimse::negotiated_ts(&association, request.pc_id).unwrap();
+                for (tag, value) in answers {
+                    let mut found = InMemDicomObject::new_empty();
+                    found.put(DataElement::new(tag, VR::UI, PrimitiveValue::from(value)));
+                    let mut bytes = Vec::new();
+                    found.write_dataset_with_ts(&mut bytes, ts).unwrap();
+                    let pending = dimse::response_to(&request, dimse::status::PENDING, true);
+                    dimse::send_message(&mut association, request.pc_id, pending, Some(&bytes))
+                        .expect("pending");
+                }
+                let done = dimse::response_to(&request, dimse::status::SUCCESS, false);
+                dimse::send_message(&mut association, request.pc_id, done, None).expect("done");
+            }
+        }
+        levels
+    });
+
+    let remote = availability::RemoteAe {
+        addr,
+        association: dimse::AssociationSettings {
+            calling_ae_title: "DICOM-TOOLS".into(),
+            called_ae_title: "PACS".into(),
+            ..Default::default()
+        },
+    };
+    let json = dir.path().join("availability.json");
+    let report =
+        availability::print_report(dir.path(), &remote, Some(&json), None).expect("verify");
+    assert_eq!(pacs_thread.join().unwrap(), vec!["SERIES", "IMAGE"]);
+
+    assert_eq!(report.studies.len(), 1);
+    let outcome = &report.studies[0];
+    assert_eq!(outcome.study_instance_uid, study);
+    assert_eq!((outcome.local_instances, outcome.remote_instances), (2, 2));
+    assert_eq!(outcome.missing_remotely.len(), 1);
+    assert_eq!(
+        outcome.missing_remotely[0].sop_instance_uid,
+        local[1].sop_instance_uid
+    );
+    assert_eq!(
+        outcome.missing_remotely[0].path.as_ref(),
+        Some(&local[1].path)
+    );
+    assert_eq!(outcome.missing_locally[0].sop_instance_uid, "1.2.3.999");
+    let saved: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&json).unwrap()).unwrap();
+    assert_eq!(
+        saved["studies"][0]["missing_locally"][0]["series_instance_uid"],
+        *series
+    );
+}
+
+#[test]
+fn size_report_estimates_lossless_sizes_per_series() {
+    let (dir, path) = build_test_dicom();
+    std::fs::write(dir.path().join("notes.txt"), "not dicom").unwrap();
+
+    let report = size_report::scan_directory(dir.path()).expect("size report");
+    assert_eq!((report.files, report.skipped), (1, 1));
+    assert_eq!(report.series.len(), 1);
+
+    let series = &report.series[0];
+    assert_eq!(series.modality, "OT");
+    assert_eq!(series.not_estimated, 0);
+    assert_eq!(series.uncompressed_pixel_bytes, 4);
+    assert_eq!(
+        series.current_bytes,
+        std::fs::metadata(&path).unwrap().len()
+    );
+    assert!(series.jpeg_ls_bytes <= series.current_bytes);
+    assert!(series.j2k_bytes <= series.current_bytes);
+    assert!(series.entropy_bits_per_sample > 0.0);
+
+    let csv = size_report::to_csv(&report);
+    assert_eq!(csv.lines().count(), 3);
+    assert!(csv.lines().last().unwrap().starts_with("TOTAL,"));
+}
+
+#[test]
+fn parametric_map_float_pixels_are_previewed_and_measured() {
+    let dir = tempdir().expect("tempdir");
+    let path = dir.path().join("pmap.dcm");
+    let mut obj = InMemDicomObject::new_empty_with_dict(StandardDataDictionary);
+    obj.put(DataElement::new(
+        Tag(0x0008, 0x0016),
+        VR::UI,
+        PrimitiveValue::from("1.2.840.10008.5.1.4.1.1.30"),
+    ));
+    obj.put(DataElement::new(
+        Tag(0x0008, 0x0018),
+        VR::UI,
+        PrimitiveValue::from("1.2.826.0.1.3680043.2.1125.3"),
+    ));
+    obj.put(DataElement::new(
+        Tag(0x0028, 0x0008),
+        VR::IS,
+        PrimitiveValue::from("2"),
+    ));
+    for tag in [Tag(0x0028, 0x0010), Tag(0x0028, 0x0011)] {
+        obj.put(DataElement::new(tag, VR::US, PrimitiveValue::from(4_u16)));
+    }
+    // Two 4x4 frames of ADC-like values, with a NaN background voxel and one fit failure.
+    let mut values: Vec<f32> = (0..32).map(|v| v as f32 * 1.0e-4).collect();
+    values[0] = f32::NAN;
+    values[31] = 1.0e9;
+    obj.put(DataElement::new(
+        Tag(0x7FE0, 0x0008),
+        VR::OF,
+        PrimitiveValue::F32(values.into()),
+    ));
+    let meta = FileMetaTableBuilder::new()
+        .transfer_syntax(EXPLICIT_VR_LITTLE_ENDIAN.uid())
+        .media_storage_sop_class_uid("1.2.840.10008.5.1.4.1.1.30")
+        .media_storage_sop_instance_uid("1.2.826.0.1.3680043.2.1125.3");
+    obj.with_meta(meta)
+        .expect("pmap file")
+        .write_to_file(&path)
+        .expect("write pmap");
+
+    let stats = stats::pixel_statistics_for_file(&path).expect("float stats");
+    assert_eq!(stats.shape, vec![2, 4, 4, 1]);
+    assert_eq!(stats.total_pixels, 31);
+    assert_eq!(stats.max, 1.0e9);
+    let histogram = stats::histogram_for_file(&path, 8).expect("float histogram");
+    assert_eq!(histogram.bins.iter().sum::<u64>(), 31);
+
+    let png = image::preview_png_bytes(&path, 1, None, None).expect("float preview");
+    let preview = ::image::load_from_memory(&png).expect("png").to_luma8();
+    assert_eq!((preview.width(), preview.height()), (4, 4));
+    // The outlier saturates without flattening the rest of the frame.
+    assert_eq!(preview.get_pixel(3, 3)[0], 255);
+    assert!(preview.get_pixel(1, 1)[0] > 0 && preview.get_pixel(1, 1)[0] < 255);
+
+    let out = dir.path().join("pmap.png");
+    image::convert(
+        &path,
+        Some(out),
+        "png",
+        &image::ImageExportOptions::default(),
+    )
+    .expect("float export");
+    assert!(dir.path().join("pmap_frame001.png").exists());
+}
+
+#[test]
+fn batch_anonymizes_the_entries_of_a_zip_export() {
+    use std::io::Write;
+
+    let (dir, path) = build_test_dicom();
+    let archive = dir.path().join("export.zip");
+    let mut zip = zip::ZipWriter::new(std::fs::File::create(&archive).expect("zip"));
+    let options = zip::write::SimpleFileOptions::default();
+    zip.start_file("DICOM/PAT1/IM0", options).expect("entry");
+    zip.write_all(&std::fs::read(&path).expect("read"))
+        .expect("write");
+    zip.start_file("DICOMDIR", options).expect("entry");
+    zip.write_all(b"index").expect("write");
+    zip.start_file("../escape", options).expect("entry");
+    zip.write_all(b"outside").expect("write");
+    zip.finish().expect("finish");
+
+    batch::process_directory(&archive, dicom_tools::cli::BatchOperation::Anonymize)
+        .expect("batch over archive");
+    let anonymized = dir.path().join("export_anon/DICOM/PAT1/IM0");
+    let obj = dicom::object::open_file(&anonymized).expect("anonymized entry");
+    assert_ne!(
+        obj.element(Tag(0x0010, 0x0010)).unwrap().to_str().unwrap(),
+        "Test^Patient"
+    );
+    assert!(!dir.path().join("escape").exists());
+    assert_eq!(
+        batch::anonymized_archive_dir(std::path::Path::new("/x/Study.TAR.GZ")),
+        std::path::Path::new("/x/Study_anon")
+    );
+}
+
+/// Peer channel noting each P-DATA value received: command or data, last flag and length.
+struct PdvRecorder {
+    association: dicom_ul::ServerAssociation,
+    received: Vec<(dicom_ul::pdu::PDataValueType, bool, usize)>,
+}
+
+impl dimse::DimseChannel for PdvRecorder {
+    fn send_pdu(&mut self, pdu: &dicom_ul::Pdu) -> anyhow::Result<()> {
+        self.association.send_pdu(pdu)
+    }
+
+    fn receive_pdu(&mut self) -> anyhow::Result<dicom_ul::Pdu> {
+        let pdu = self.association.receive_pdu()?;
+        if let dicom_ul::Pdu::PData { data } = &pdu {
+            self.received.extend(
+                data.iter()
+                    .map(|value| (value.value_type.clone(), value.is_last, value.data.len())),
+            );
+        }
+        Ok(pdu)
+    }
+
+    fn send_data(&mut self, pc_id: u8, data: &[u8]) -> anyhow::Result<()> {
+        self.association.send_data(pc_id, data)
+    }
+
+    fn contexts(&self) -> &[dicom_ul::pdu::PresentationContextResult] {
+        dimse::DimseChannel::contexts(&self.association)
+    }
+}
+
+#[test]
+fn push_splits_large_data_sets_to_the_peer_max_pdu() {
+    let dir = tempdir().expect("tempdir");
+    let spec = synth::SynthSpec {
+        rows: 256,
+        columns: 256,
+        ..synth::SynthSpec::default()
+    };
+    let file = synth::write_series(&spec, dir.path()).expect("series")[0].clone();
+    let instance = scp::IndexedInstance::read(&file).expect("instance");
+
+    // A peer accepting PDUs of at most 4 KiB; dicom-ul refuses to read longer ones.
+    const PEER_MAX_PDU: u32 = 4096;
+    let peer = std::net::TcpListener::bind("127.0.0.1:0").expect("bind peer");
+    let addr = peer.local_addr().unwrap().to_string();
+    let peer_thread = std::thread::spawn(move || {
+        let (stream, _) = peer.accept().expect("accept");
+        let association = dicom_ul::ServerAssociationOptions::new()
+            .with_abstract_syntax(instance.sop_class_uid)
+            .with_transfer_syntax(EXPLICIT_VR_LITTLE_ENDIAN.uid())
+            .max_pdu_length(PEER_MAX_PDU)
+            .establish(stream)
+            .expect("association");
+        let mut channel = PdvRecorder {
+            association,
+            received: Vec::new(),
+        };
+        let request = dimse::read_message(&mut channel)
+            .expect("read")
+            .expect("store rq");
+        let data = request.dataset(&channel).unwrap().expect("data set");
+        let response = dimse::response_to(&request, dimse::status::SUCCESS, false);
+        dimse::send_message(&mut channel, request.pc_id, response, None).expect("store rsp");
+        assert!(dimse::read_message(&mut channel)
+            .expect("release")
+            .is_none());
+        (channel.received, data)
+    });
+
+    scu::push(&addr, &file).expect("push");
+    let (received, data) = peer_thread.join().expect("peer");
+    let fragments: Vec<_> = received
+        .iter()
+        .filter(|(kind, _, _)| *kind == dicom_ul::pdu::PDataValueType::Data)
+        .collect();
+    assert!(fragments.len() > 1, "{} fragment(s)", fragments.len());
+    // Each PDU carries the PDV length, context id and header besides the data.
+    assert!(fragments
+        .iter()
+        .all(|(_, _, len)| *len as u32 <= PEER_MAX_PDU - 6));
+    let last = fragments.len() - 1;
+    assert!(fragments
+        .iter()
+        .enumerate()
+        .all(|(index, (_, is_last, _))| *is_last == (index == last)));
+    assert_eq!(
+        data.element(Tag(0x7FE0, 0x0010))
+            .unwrap()
+            .to_bytes()
+            .unwrap()
+            .len(),
+        256 * 256 * 2
+    );
+}
+
+#[test]
+fn a_tiny_peer_max_pdu_does_not_underflow_the_fragment_size() {
+    use dicom_ul::pdu::{
+        AssociationAC, PresentationContextResult, PresentationContextResultReason, UserVariableItem,
+    };
+
+    // An acceptor announcing a 3-byte maximum PDU, less than the PDV header alone.
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("bind");
+    let addr = listener.local_addr().unwrap().to_string();
+    let peer = std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().expect("accept");
+        let dicom_ul::Pdu::AssociationRQ(rq) =
+            dicom_ul::read_pdu(&mut stream, 16384, false).expect("A-ASSOCIATE-RQ")
+        else {
+            panic!("expected A-ASSOCIATE-RQ");
+        };
+        let ac = AssociationAC {
+            protocol_version: 1,
+            calling_ae_title: rq.calling_ae_title,
+            called_ae_title: rq.called_ae_title,
+            application_context_name: rq.application_context_name,
+            presentation_contexts: vec![PresentationContextResult {
+                id: 1,
+                reason: PresentationContextResultReason::Acceptance,
+                transfer_syntax: EXPLICIT_VR_LITTLE_ENDIAN.uid().to_string(),
+            }],
+            user_variables: vec![UserVariableItem::MaxLength(3)],
+        };
+        dicom_ul::write_pdu(&mut stream, &dicom_ul::Pdu::AssociationAC(ac)).expect("AC");
+        let mut fragments = Vec::new();
+        loop {
+            match dicom_ul::read_pdu(&mut stream, 16384, false).expect("P-DATA") {
+                dicom_ul::Pdu::PData { data } => {
+                    fragments.extend(data.into_iter().map(|v| (v.data.len(), v.is_last)));
+                    if fragments.last().is_some_and(|(_, last)| *last) {
+                        return fragments;
+                    }
+                }
+                other => panic!("unexpected {:?}", other),
+            }
+        }
+    });
+
+    let contexts = vec![(
+        "1.2.840.10008.1.1".to_string(),
+        vec![EXPLICIT_VR_LITTLE_ENDIAN.uid().to_string()],
+    )];
+    let mut association = dimse::RoleSelectingAssociation::establish(
+        &addr,
+        &dimse::AssociationSettings::default(),
+        &contexts,
+        &[],
+    )
+    .expect("associate");
+    dimse::DimseChannel::send_data(&mut association, 1, b"abc").expect("send");
+
+    assert_eq!(
+        peer.join().expect("peer"),
+        vec![(1, false), (1, false), (1, true)]
+    );
+}
+
+#[test]
+fn scp_rules_forward_complete_studies_once_they_go_quiet() {
+    let source = tempdir().expect("tempdir");
+    let spec = synth::SynthSpec {
+        instances: 3,
+        ..synth::SynthSpec::default()
+    };
+    synth::write_series(&spec, source.path()).expect("series");
+    let logs = tempdir().expect("logs");
+    let log = logs.path().join("archive.log");
+
+    let serve = |store: Option<storage::FileStore>,
+                 router: Option<router::Router>,
+                 trace: Option<std::sync::Arc<dimse_trace::DimseTracer>>| {
+        let root = tempdir().expect("root");
+        let server = scp::RetrieveScp::bind(
+            "127.0.0.1:0",
+            scp::ScpConfig {
+                ae_title: "DICOM-TOOLS".into(),
+                root: root.path().to_path_buf(),
+                destinations: scp::AeMap::default(),
+                trace,
+                store,
+                router: router.map(std::sync::Arc::new),
+                worklist: None,
+                mpps: None,
+                audit: None,
+            },
+        )
+        .expect("bind scp");
+        let addr = server.local_addr().unwrap().to_string();
+        std::thread::spawn(move || server.serve());
+        (addr, root)
+    };
+    let received = tempdir().expect("archive store");
+    let archive_store = storage::FileStore::new(received.path()).expect("store");
+    let tracer = dimse_trace::DimseTracer::create(&log, false).expect("tracer");
+    let (archive, _archive_root) = serve(Some(archive_store.clone()), None, Some(tracer));
+    let rules = format!(
+        r#"
+        [nodes]
+        ARCHIVE = "{}"
+
+        [[rules]]
+        name = "whole-studies"
+        coalesce_secs = 2
+        actions = [{{ do = "push", node = "ARCHIVE" }}]
+        "#,
+        archive
+    );
+    let router = router::Router::new(toml::from_str(&rules).expect("rules")).expect("router");
+    let (gateway, _gateway_root) = serve(None, Some(router), None);
+
+    let summary = scu::push_dir(
+        &gateway,
+        source.path(),
+        &scu::PushDirOptions {
+            association: dimse::AssociationSettings::default(),
+            rate_limit: None,
+            window: None,
+            audit: None,
+            queue: None,
+        },
+        &progress::NoProgress,
+        None,
+    )
+    .expect("push dir");
+    assert_eq!(summary.sent, 3);
+    // The study is held while instances may still arrive.
+    assert!(archive_store.uploads().unwrap().is_empty());
+
+    let deadline = std::time::Instant::now() + std::time::Duration::from_secs(20);
+    while archive_store.uploads().unwrap().len() < 3 {
+        assert!(std::time::Instant::now() < deadline, "study not forwarded");
+        std::thread::sleep(std::time::Duration::from_millis(100));
+    }
+    // Forwarded as a whole, over one association.
+    let trace = std::fs::read_to_string(&log).expect("log");
+    assert_eq!(trace.matches("A-ASSOCIATE-RQ").count(), 1);
+}
+
+#[test]
+fn pushes_and_receptions_are_audited() {
+    let dir = tempdir().expect("tempdir");
+    let spec = synth::SynthSpec {
+        instances: 2,
+        ..synth::SynthSpec::default()
+    };
+    let files = synth::write_series(&spec, &dir.path().join("study")).expect("series");
+    let study = scp::IndexedInstance::read(&files[0])
+        .expect("instance")
+        .study_instance_uid;
+    let open = |name: &str| {
+        let path = dir.path().join(name);
+        let target = path.display().to_string().parse().expect("target");
+        (path, audit::AuditLog::open(&target).expect("audit log"))
+    };
+    let (scp_log, scp_audit) = open("scp-audit.log");
+    let (scu_log, scu_audit) = open("scu-audit.log");
+
+    let received = tempdir().expect("store dir");
+    let server = scp::RetrieveScp::bind(
+        "127.0.0.1:0",
+        scp::ScpConfig {
+            ae_title: "ARCHIVE".into(),
+            root: received.path().to_path_buf(),
+            destinations: scp::AeMap::default(),
+            trace: None,
+            store: Some(storage::FileStore::new(received.path()).expect("store")),
+            router: None,
+            worklist: None,
+            mpps: None,
+            audit: Some(scp_audit),
+        },
+    )
+    .expect("bind scp");
+    let addr = server.local_addr().unwrap().to_string();
+    std::thread::spawn(move || server.serve());
+
+    let options = scu::PushDirOptions {
+        association: dimse::AssociationSettings {
+            calling_ae_title: "MIGRATOR".into(),
+            called_ae_title: "ARCHIVE".into(),
+            ..Default::default()
+        },
+        rate_limit: None,
+        window: None,
+        audit: Some(scu_audit),
+        queue: None,
+    };
+    let summary = scu::push_dir(
+        &addr,
+        &dir.path().join("study"),
+        &options,
+        &progress::NoProgress,
+        None,
+    )
+    .expect("push dir");
+    assert_eq!(summary.sent, 2);
+
+    // One export for the run, naming both instances of the study.
+    let exported = std::fs::read_to_string(&scu_log).expect("scu audit");
+    assert_eq!(exported.lines().count(), 1);
+    assert!(exported.contains("csd-code=\"110106\""));
+    assert!(exported.contains("EventOutcomeIndicator=\"0\""));
+    assert!(exported.contains("AlternativeUserID=\"AETITLES=ARCHIVE\""));
+    assert!(exported.contains(&format!("ParticipantObjectID=\"{}\"", study)));
+    assert!(exported.contains("NumberOfInstances=\"2\""));
+    // One import per received instance, with the sender as requestor.
+    let imported = std::fs::read_to_string(&scp_log).expect("scp audit");
+    assert_eq!(imported.lines().count(), 2);
+    assert!(imported.lines().all(|line| line.contains("csd-code=\"110107\"")
+        && line.contains(
+            "UserID=\"MIGRATOR\" AlternativeUserID=\"AETITLES=MIGRATOR\" UserIsRequestor=\"true\" NetworkAccessPointID=\"127.0.0.1\""
+        )));
+}
+
+#[tokio::test]
+async fn async_scu_pushes_run_concurrently_on_the_runtime() {
+    let dir = tempdir().expect("tempdir");
+    let spec = synth::SynthSpec {
+        instances: 3,
+        ..synth::SynthSpec::default()
+    };
+    let mut files = synth::write_series(&spec, dir.path()).expect("series");
+    let notes = dir.path().join("notes.txt");
+    std::fs::write(&notes, "not dicom").unwrap();
+    files.insert(1, notes.clone());
+
+    let received = tempdir().expect("store dir");
+    let store = storage::FileStore::new(received.path()).expect("store");
+    let server = scp::RetrieveScp::bind(
+        "127.0.0.1:0",
+        scp::ScpConfig {
+            ae_title: "DICOM-TOOLS".into(),
+            root: received.path().to_path_buf(),
+            destinations: scp::AeMap::default(),
+            trace: None,
+            store: Some(store.clone()),
+            router: None,
+            worklist: None,
+            mpps: None,
+            audit: None,
+        },
+    )
+    .expect("bind scp");
+    let addr = server.local_addr().unwrap().to_string();
+    std::thread::spawn(move || server.serve());
+
+    let settings = dimse::AssociationSettings::default();
+    let (echo, outcomes) = tokio::join!(
+        scu_async::echo(addr.clone(), settings.clone(), None),
+        scu_async::push_files(&addr, files.clone(), &settings, 2),
+    );
+    echo.expect("echo");
+
+    assert_eq!(
+        outcomes.iter().map(|(file, _)| file).collect::<Vec<_>>(),
+        files.iter().collect::<Vec<_>>()
+    );
+    for (file, outcome) in &outcomes {
+        if *file == notes {
+            assert!(outcome.is_err());
+        } else {
+            assert_eq!(*outcome.as_ref().expect("push"), dimse::status::SUCCESS);
+        }
+    }
+    assert_eq!(store.uploads().unwrap().len(), 3);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn web_push_only_reaches_configured_destinations() {
+    let (_dir, path) = build_test_dicom();
+    let uploads = tempdir().expect("upload dir");
+    let store = storage::FileStore::new(uploads.path()).expect("store");
+    let name = store
+        .save(Some("scan.dcm"), &std::fs::read(&path).unwrap())
+        .expect("save");
+
+    let received = tempdir().expect("received dir");
+    let pacs_store = storage::FileStore::new(received.path()).expect("pacs store");
+    let pacs = scp::RetrieveScp::bind(
+        "127.0.0.1:0",
+        scp::ScpConfig {
+            ae_title: "PACS".into(),
+            root: received.path().to_path_buf(),
+            destinations: scp::AeMap::default(),
+            trace: None,
+            store: Some(pacs_store.clone()),
+            router: None,
+            worklist: None,
+            mpps: None,
+            audit: None,
+        },
+    )
+    .expect("bind scp");
+    let pacs_addr = pacs.local_addr().unwrap();
+    std::thread::spawn(move || pacs.serve());
+
+    let mut push = scp::AeMap::default();
+    push.insert_entry(&format!("PACS={}", pacs_addr)).unwrap();
+    let port = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    tokio::spawn(web::start_server(
+        "127.0.0.1",
+        port,
+        store,
+        std::sync::Arc::new(dicom_tools::screening::ScreeningPipeline::new()),
+        web::WorkerLimits::default(),
+        dicom_tools::preview_cache::PreviewCache::new(0),
+        dicom_tools::sharing::ShareSigner::random(),
+        dicom_tools::upload_pipeline::UploadPipeline::default(),
+        push,
+    ));
+
+    let url = format!("http://127.0.0.1:{}/api/push/{}", port, name);
+    let post = move |body: &'static str| {
+        let url = url.clone();
+        tokio::task::spawn_blocking(move || {
+            for _ in 0..50 {
+                let sent = attohttpc::post(&url)
+                    .header("Content-Type", "application/json")
+                    .bytes(body.as_bytes().to_vec())
+                    .send();
+                match sent {
+                    Ok(response) => {
+                        let status = response.status().as_u16();
+                        return (status, response.text().unwrap());
+                    }
+                    // The server may still be starting.
+                    Err(_) => std::thread::sleep(std::time::Duration::from_millis(100)),
+                }
+            }
+            panic!("web server did not start");
+        })
+    };
+
+    // Neither an unknown AE title nor a raw address reaches the network.
+    let (status, text) = post(r#"{"destination":"ELSEWHERE"}"#).await.unwrap();
+    assert_eq!(status, 403);
+    assert!(text.contains("ELSEWHERE is not a push destination"));
+    let (status, _) = post(r#"{"addr":"10.0.0.5:104","destination":"10.0.0.5:104"}"#)
+        .await
+        .unwrap();
+    assert_eq!(status, 403);
+    assert!(pacs_store.uploads().unwrap().is_empty());
+
+    let (status, text) = post(r#"{"destination":"PACS"}"#).await.unwrap();
+    assert_eq!(status, 200, "{}", text);
+    assert!(text.contains("\"success\":true"));
+    assert_eq!(pacs_store.uploads().unwrap().len(), 1);
+}
+
+#[test]
+fn fhir_export_builds_imaging_studies_and_patient_stubs() {
+    let dir = tempdir().expect("tempdir");
+    let ct = synth::SynthSpec {
+        modality: "CT".into(),
+        instances: 3,
+        seed: 1,
+        ..synth::SynthSpec::default()
+    };
+    let mr = synth::SynthSpec {
+        modality: "MR".into(),
+        seed: 2,
+        ..synth::SynthSpec::default()
+    };
+    synth::write_series(&ct, &dir.path().join("ct")).expect("ct series");
+    synth::write_series(&mr, &dir.path().join("mr")).expect("mr series");
+    std::fs::write(dir.path().join("notes.txt"), "not dicom").unwrap();
+
+    let export = fhir::export_directory(dir.path()).expect("export");
+    assert_eq!(export.imaging_studies.len(), 2);
+    assert_eq!(export.patients.len(), 2);
+    assert_eq!(export.skipped.len(), 1);
+
+    let ct_study = export
+        .imaging_studies
+        .iter()
+        .find(|study| study["modality"][0]["code"] == "CT")
+        .expect("CT study");
+    assert_eq!(ct_study["status"], "available");
+    assert_eq!(ct_study["subject"]["reference"], "Patient/SYNTH0001");
+    assert_eq!(ct_study["started"], "2000-01-01");
+    assert_eq!(ct_study["numberOfSeries"], 1);
+    assert_eq!(ct_study["numberOfInstances"], 3);
+    let series = &ct_study["series"][0];
+    assert_eq!(series["modality"]["code"], "CT");
+    let numbers: Vec<_> = series["instance"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|instance| instance["number"].as_u64().unwrap())
+        .collect();
+    assert_eq!(numbers, [1, 2, 3]);
+    assert!(series["instance"][0]["sopClass"]["code"]
+        .as_str()
+        .unwrap()
+        .starts_with("urn:oid:1.2.840.10008."));
+
+    let patient = &export.patients[0];
+    assert_eq!(patient["resourceType"], "Patient");
+    assert_eq!(patient["name"][0]["family"], "SYNTHETIC");
+    assert_eq!(patient["name"][0]["given"][0], "PATIENT");
+
+    let bundle = export.bundle();
+    assert_eq!(bundle["type"], "transaction");
+    let entries = bundle["entry"].as_array().unwrap();
+    assert_eq!(entries.len(), 4);
+    assert_eq!(entries[0]["request"]["method"], "PUT");
+    assert_eq!(entries[0]["request"]["url"], "Patient/SYNTH0001");
+}
+
+#[test]
+fn csv_and_osirix_annotations_import_as_seg_and_sr() {
+    let dir = tempdir().expect("tempdir");
+    let spec = synth::SynthSpec {
+        instances: 3,
+        ..synth::SynthSpec::default()
+    };
+    let files = synth::write_series(&spec, &dir.path().join("series")).expect("series");
+    let second = dicom::object::open_file(&files[1]).expect("second instance");
+    let second_uid = second
+        .element(Tag(0x0008, 0x0018))
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .trim_end_matches('\0')
+        .to_string();
+
+    // A 10x10 pixel square on the second instance, and a seed point on the first.
+    let csv = dir.path().join("points.csv");
+    std::fs::write(
+        &csv,
+        format!(
+            "label,sop_instance_uid,image_index,x,y,shape\n\
+             Lesion,{uid},,10,10,polygon\n\
+             Lesion,{uid},,20,10,polygon\n\
+             Lesion,{uid},,20,20,polygon\n\
+             Lesion,{uid},,10,20,polygon\n\
+             Seed,,0,5,5,\n",
+            uid = second_uid
+        ),
+    )
+    .unwrap();
+    let seg_path = dir.path().join("seg.dcm");
+    let summary = annotations::import(
+        &csv,
+        &dir.path().join("series"),
+        annotations::ImportTarget::Seg,
+        &seg_path,
+    )
+    .expect("import seg");
+    assert_eq!(summary.annotations, 2);
+    assert_eq!(summary.referenced_instances, 2);
+    let mask = roi_mask::load(&seg_path, 1, &second, 1).expect("segment 1");
+    assert_eq!(mask.label.as_deref(), Some("Lesion"));
+    assert_eq!(mask.count(), 100);
+    let first = dicom::object::open_file(&files[0]).expect("first instance");
+    assert_eq!(roi_mask::load(&seg_path, 2, &first, 1).unwrap().count(), 1);
+    assert_eq!(roi_mask::load(&seg_path, 1, &first, 1).unwrap().count(), 0);
+
+    let xml = dir.path().join("rois.xml");
+    std::fs::write(
+        &xml,
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<plist version="1.0"><dict><key>Images</key><array><dict>
+<key>ImageIndex</key><integer>1</integer>
+<key>ROIs</key><array><dict>
+<key>Name</key><string>Ruler</string><key>Type</key><integer>5</integer>
+<key>Point_px</key><array><string>(0, 0)</string><string>(3, 4)</string></array>
+</dict></array></dict></array></dict></plist>"#,
+    )
+    .unwrap();
+    let sr_path = dir.path().join("sr.dcm");
+    annotations::import(
+        &xml,
+        &dir.path().join("series"),
+        annotations::ImportTarget::Sr,
+        &sr_path,
+    )
+    .expect("import sr");
+    let sr = dicom::object::open_file(&sr_path).expect("sr");
+    let text = |obj: &dicom::object::InMemDicomObject, tag| {
+        obj.element(tag)
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .trim_end_matches(['\0', ' '])
+            .to_string()
+    };
+    assert_eq!(text(&sr, Tag(0x0008, 0x0060)), "SR");
+    let content = Tag(0x0040, 0xA730);
+    let measurements = &sr.element(content).unwrap().items().unwrap()[0];
+    let group = &measurements.element(content).unwrap().items().unwrap()[0];
+    let items = group.element(content).unwrap().items().unwrap();
+    assert_eq!(text(&items[0], Tag(0x0040, 0xA160)), "Ruler");
+    assert_eq!(text(&items[2], Tag(0x0070, 0x0023)), "POLYLINE");
+    let image = &items[2].element(content).unwrap().items().unwrap()[0];
+    let reference = &image.element(Tag(0x0008, 0x1199)).unwrap().items().unwrap()[0];
+    assert_eq!(text(reference, Tag(0x0008, 0x1155)), second_uid);
+    let length = &items[3]
+        .element(Tag(0x0040, 0xA300))
+        .unwrap()
+        .items()
+        .unwrap()[0];
+    let value: f64 = text(length, Tag(0x0040, 0xA30A)).parse().unwrap();
+    assert!((value - 5.0).abs() < 1e-6);
+}
+
+#[test]
+fn parametric_maps_store_float_arrays_on_the_reference_geometry() {
+    let dir = tempdir().expect("tempdir");
+    let spec = synth::SynthSpec {
+        modality: "MR".into(),
+        rows: 4,
+        columns: 6,
+        instances: 3,
+        ..synth::SynthSpec::default()
+    };
+    let files = synth::write_series(&spec, &dir.path().join("series")).expect("series");
+
+    // What numpy's tofile writes for a float32 array of shape (3, 4, 6).
+    let values: Vec<f32> = (0..72).map(|i| i as f32 * 0.5 - 3.0).collect();
+    let raw = dir.path().join("adc.f32");
+    std::fs::write(
+        &raw,
+        values
+            .iter()
+            .flat_map(|v| v.to_le_bytes())
+            .collect::<Vec<u8>>(),
+    )
+    .unwrap();
+    let array = parametric_map::read_raw_f32(&raw, (3, 4, 6)).expect("raw array");
+    let options = parametric_map::ParametricMapOptions {
+        label: "ADC".into(),
+        description: "Apparent diffusion coefficient".into(),
+        units: parametric_map::ucum("um2/s", "um2/s"),
+        ..Default::default()
+    };
+    let output = dir.path().join("adc.dcm");
+    let summary =
+        parametric_map::write(array.view(), &dir.path().join("series"), &options, &output)
+            .expect("write map");
+    assert_eq!(summary.frames, 3);
+    assert_eq!(summary.range, (-3.0, 32.5));
+
+    let map = dicom::object::open_file(&output).expect("map");
+    let pixels = float_pixels::FloatPixels::from_object(&map)
+        .unwrap()
+        .expect("float pixels");
+    assert_eq!((pixels.frames, pixels.rows, pixels.columns), (3, 4, 6));
+    assert_eq!(pixels.values, values);
+    let text = |obj: &InMemDicomObject, tag: Tag| {
+        obj.element(tag)
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .trim_end_matches(['\0', ' '])
+            .to_string()
+    };
+    assert_eq!(
+        text(&map, Tag(0x0008, 0x0016)),
+        parametric_map::PARAMETRIC_MAP_STORAGE
+    );
+    assert_eq!(text(&map, Tag(0x0008, 0x0060)), "MR");
+
+    let source = dicom::object::open_file(&files[1]).expect("second instance");
+    for tag in [Tag(0x0020, 0x000D), Tag(0x0020, 0x0052)] {
+        assert_eq!(text(&map, tag), text(&source, tag));
+    }
+    let shared = &map.element(Tag(0x5200, 0x9229)).unwrap().items().unwrap()[0];
+    let mapping = &shared
+        .element(Tag(0x0040, 0x9096))
+        .unwrap()
+        .items()
+        .unwrap()[0];
+    assert_eq!(text(mapping, Tag(0x0040, 0x9210)), "ADC");
+    let units = &mapping
+        .element(Tag(0x0040, 0x08EA))
+        .unwrap()
+        .items()
+        .unwrap()[0];
+    assert_eq!(text(units, Tag(0x0008, 0x0100)), "um2/s");
+    assert_eq!(text(units, Tag(0x0008, 0x0102)), "UCUM");
+
+    // The second map frame sits where the second reference image does and points back at it.
+    let second = &map.element(Tag(0x5200, 0x9230)).unwrap().items().unwrap()[1];
+    let position = &second
+        .element(Tag(0x0020, 0x9113))
+        .unwrap()
+        .items()
+        .unwrap()[0];
+    assert_eq!(
+        position
+            .element(Tag(0x0020, 0x0032))
+            .unwrap()
+            .to_multi_float64()
+            .unwrap(),
+        source
+            .element(Tag(0x0020, 0x0032))
+            .unwrap()
+            .to_multi_float64()
+            .unwrap()
+    );
+    let reference = &second
+        .element(Tag(0x0008, 0x1140))
+        .unwrap()
+        .items()
+        .unwrap()[0];
+    assert_eq!(
+        text(reference, Tag(0x0008, 0x1155)),
+        text(&source, Tag(0x0008, 0x0018))
+    );
+
+    let short = parametric_map::read_raw_f32(&raw, (2, 4, 6));
+    assert!(short.is_err());
+    let two_frames = array.slice(ndarray::s![..2, .., ..]);
+    let err = parametric_map::write(
+        two_frames,
+        &dir.path().join("series"),
+        &options,
+        &dir.path().join("bad.dcm"),
+    )
+    .unwrap_err();
+    assert!(err.to_string().contains("reference series has 3"));
+}
+
+#[test]
+fn measurement_reports_carry_model_findings_back_to_the_source_images() {
+    let dir = tempdir().expect("tempdir");
+    let spec = synth::SynthSpec {
+        instances: 2,
+        ..synth::SynthSpec::default()
+    };
+    let files = synth::write_series(&spec, &dir.path().join("series")).expect("series");
+    let second = dicom::object::open_file(&files[1]).expect("second instance");
+    let text = |obj: &InMemDicomObject, tag: Tag| {
+        obj.element(tag)
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .trim_end_matches(['\0', ' '])
+            .to_string()
+    };
+    let second_uid = text(&second, Tag(0x0008, 0x0018));
+
+    let findings = dir.path().join("findings.json");
+    std::fs::write(
+        &findings,
+        format!(
+            r#"{{
+                "observer": {{"name": "LungNet", "model": "2.1"}},
+                "findings": [
+                    {{
+                        "tracking_id": "Nodule 1",
+                        "tracking_uid": "2.25.1234",
+                        "finding": {{"value": "27925004", "scheme": "SCT", "meaning": "Nodule"}},
+                        "image": {{"sop_instance_uid": "{uid}"}},
+                        "region": {{"shape": "polygon", "points": [[10, 10], [20, 10], [20, 20]]}},
+                        "measurements": [{{
+                            "name": {{"value": "81827009", "scheme": "SCT", "meaning": "Diameter"}},
+                            "value": 7.25,
+                            "units": {{"value": "mm", "scheme": "UCUM", "meaning": "millimeter"}}
+                        }}],
+                        "evaluations": [{{
+                            "name": {{"value": "RID15", "scheme": "RADLEX", "meaning": "Malignancy"}},
+                            "value": {{"value": "RID5", "scheme": "RADLEX", "meaning": "Likely"}}
+                        }}]
+                    }},
+                    {{
+                        "tracking_id": "Study score",
+                        "measurements": [{{
+                            "name": {{"value": "S1", "scheme": "99AI", "meaning": "Score"}},
+                            "value": 0.87,
+                            "units": {{"value": "1", "scheme": "UCUM", "meaning": "no units"}}
+                        }}]
+                    }}
+                ]
+            }}"#,
+            uid = second_uid
+        ),
+    )
+    .unwrap();
+    let output = dir.path().join("report.dcm");
+    let summary = measurement_report::write(&findings, &dir.path().join("series"), &output)
+        .expect("write report");
+    assert_eq!((summary.findings, summary.measurements), (2, 2));
+
+    let sr = dicom::object::open_file(&output).expect("report");
+    assert_eq!(text(&sr, Tag(0x0008, 0x0060)), "SR");
+    assert_eq!(
+        text(&sr, Tag(0x0020, 0x000D)),
+        text(&second, Tag(0x0020, 0x000D))
+    );
+    let content = Tag(0x0040, 0xA730);
+    let concept = |item: &InMemDicomObject| {
+        let code = &item.element(Tag(0x0040, 0xA043)).unwrap().items().unwrap()[0];
+        text(code, Tag(0x0008, 0x0104))
+    };
+    let root = sr.element(content).unwrap().items().unwrap();
+    let concepts: Vec<String> = root.iter().map(concept).collect();
+    assert_eq!(
+        concepts,
+        [
+            "Language of Content Item and Descendants",
+            "Observer Type",
+            "Device Observer UID",
+            "Device Observer Name",
+            "Device Observer Model Name",
+            "Procedure reported",
+            "Imaging Measurements",
+        ]
+    );
+    let groups = root[6].element(content).unwrap().items().unwrap();
+    assert_eq!(groups.len(), 2);
+    let nodule = groups[0].element(content).unwrap().items().unwrap();
+    assert_eq!(text(&nodule[0], Tag(0x0040, 0xA160)), "Nodule 1");
+    assert_eq!(text(&nodule[1], Tag(0x0040, 0xA124)), "2.25.1234");
+    assert_eq!(concept(&nodule[2]), "Finding");
+    assert_eq!(text(&nodule[3], Tag(0x0070, 0x0023)), "POLYGON");
+    let image = &nodule[3].element(content).unwrap().items().unwrap()[0];
+    let reference = &image.element(Tag(0x0008, 0x1199)).unwrap().items().unwrap()[0];
+    assert_eq!(text(reference, Tag(0x0008, 0x1155)), second_uid);
+    let diameter = &nodule[4]
+        .element(Tag(0x0040, 0xA300))
+        .unwrap()
+        .items()
+        .unwrap()[0];
+    assert_eq!(text(diameter, Tag(0x0040, 0xA30A)), "7.25");
+    assert_eq!(concept(&nodule[5]), "Malignancy");
+    // A study-level score has no image to point at.
+    let score = groups[1].element(content).unwrap().items().unwrap();
+    assert_eq!(score.len(), 3);
+
+    // Only the referenced image is listed as evidence.
+    let evidence = &sr.element(Tag(0x0040, 0xA375)).unwrap().items().unwrap()[0];
+    let series = &evidence
+        .element(Tag(0x0008, 0x1115))
+        .unwrap()
+        .items()
+        .unwrap()[0];
+    let instances = series
+        .element(Tag(0x0008, 0x1199))
+        .unwrap()
+        .items()
+        .unwrap();
+    assert_eq!(instances.len(), 1);
+
+    let missing = dir.path().join("missing.json");
+    std::fs::write(
+        &missing,
+        r#"{"findings": [{"tracking_id": "X", "image": {"sop_instance_uid": "1.2.3"}}]}"#,
+    )
+    .unwrap();
+    let err = measurement_report::write(&missing, &dir.path().join("series"), &output).unwrap_err();
+    assert!(err.to_string().contains("is not in the series"));
+}
+
+#[test]
+fn harmonize_rewrites_synonyms_into_corrected_copies_with_a_report() {
+    let dir = tempdir().expect("tempdir");
+    let cohort = dir.path().join("cohort");
+    let spec = synth::SynthSpec {
+        instances: 3,
+        ..synth::SynthSpec::default()
+    };
+    let files = synth::write_series(&spec, &cohort).expect("series");
+    let sites = [
+        ("THORAX", "GEN HOSP", "t1  axial "),
+        ("chest", "General Hospital", "T1 AXIAL"),
+        ("HEAD", "St. Mary", "T2 SAG"),
+    ];
+    for (path, (body, institution, description)) in files.iter().zip(sites) {
+        let mut obj = dicom::object::open_file(path).unwrap();
+        for (tag, vr, value) in [
+            (Tag(0x0018, 0x0015), VR::CS, body),
+            (Tag(0x0008, 0x0080), VR::LO, institution),
+            (Tag(0x0008, 0x103E), VR::LO, description),
+        ] {
+            obj.put(DataElement::new(tag, vr, PrimitiveValue::from(value)));
+        }
+        obj.write_to_file(path).unwrap();
+    }
+    std::fs::write(cohort.join("notes.txt"), "not DICOM").unwrap();
+    let mapping = dir.path().join("mapping.toml");
+    std::fs::write(
+        &mapping,
+        r#"
+[[rules]]
+tag = "BodyPartExamined"
+[rules.values]
+CHEST = ["THORAX", "TORAX"]
+
+[[rules]]
+tag = "InstitutionName"
+[rules.values]
+"General Hospital" = ["GEN HOSP", "General Hosp."]
+
+[[rules]]
+tag = "SeriesDescription"
+normalize = ["trim", "collapse-spaces", "upper"]
+"#,
+    )
+    .unwrap();
+    let harmonizer = harmonize::Harmonizer::load(&mapping).expect("mapping");
+
+    let dry = harmonize::harmonize_directory(&cohort, &harmonizer, None).expect("dry run");
+    assert_eq!((dry.files, dry.changed_files, dry.skipped), (3, 2, 1));
+
+    let output = dir.path().join("harmonized");
+    let summary =
+        harmonize::harmonize_directory(&cohort, &harmonizer, Some(&output)).expect("harmonize");
+    assert_eq!(summary.changes.len(), 4);
+    assert!(summary.failed.is_empty());
+    let text = |path: &std::path::Path, tag: Tag| {
+        dicom::object::open_file(path)
+            .unwrap()
+            .element(tag)
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .trim_end_matches(['\0', ' '])
+            .to_string()
+    };
+    for path in &files {
+        let copy = output.join(path.strip_prefix(&cohort).unwrap());
+        assert_eq!(
+            text(&copy, Tag(0x0008, 0x0016)),
+            text(path, Tag(0x0008, 0x0016))
+        );
+        assert_eq!(
+            text(&copy, Tag(0x0008, 0x0018)),
+            text(path, Tag(0x0008, 0x0018))
+        );
+    }
+    let first = output.join(files[0].strip_prefix(&cohort).unwrap());
+    assert_eq!(text(&first, Tag(0x0018, 0x0015)), "CHEST");
+    assert_eq!(text(&first, Tag(0x0008, 0x0080)), "General Hospital");
+    assert_eq!(text(&first, Tag(0x0008, 0x103E)), "T1 AXIAL");
+    let second = output.join(files[1].strip_prefix(&cohort).unwrap());
+    assert_eq!(text(&second, Tag(0x0018, 0x0015)), "CHEST");
+    let third = output.join(files[2].strip_prefix(&cohort).unwrap());
+    assert_eq!(text(&third, Tag(0x0018, 0x0015)), "HEAD");
+    assert_eq!(
+        std::fs::read(files[2].as_path()).unwrap(),
+        std::fs::read(third).unwrap()
+    );
+    assert!(output.join("notes.txt").is_file());
+    // The input is left alone.
+    assert_eq!(text(&files[0], Tag(0x0018, 0x0015)), "THORAX");
+
+    let report = std::fs::read_to_string(output.join(harmonize::REPORT_FILE)).unwrap();
+    assert_eq!(report.lines().count(), 5);
+    assert!(report.contains("IMG0001.dcm,\"(0018,0015)\",BodyPartExamined,THORAX,CHEST"));
+    assert!(report.contains("\"(0008,103E)\",SeriesDescription,t1  axial,T1 AXIAL"));
+    let tallies = summary.tallies();
+    assert!(tallies.contains(&("BodyPartExamined", "chest", "CHEST", 1)));
+
+    assert!(
+        harmonize::harmonize_directory(&cohort, &harmonizer, Some(&cohort.join("out"))).is_err()
+    );
+}
+
+#[test]
+fn stow_uploads_multipart_and_sorts_the_response() {
+    use std::io::{BufRead, BufReader, Read, Write};
+
+    let dir = tempdir().expect("tempdir");
+    let spec = synth::SynthSpec {
+        instances: 2,
+        ..synth::SynthSpec::default()
+    };
+    let files = synth::write_series(&spec, dir.path()).expect("series");
+    std::fs::write(dir.path().join("notes.txt"), "not DICOM").unwrap();
+    let uids: Vec<String> = files
+        .iter()
+        .map(|path| {
+            let obj = dicom::object::open_file(path).unwrap();
+            obj.element(Tag(0x0008, 0x0018))
+                .unwrap()
+                .to_str()
+                .unwrap()
+                .trim_end_matches('\0')
+                .to_string()
+        })
+        .collect();
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let response = format!(
+        r#"{{"00081199": {{"vr": "SQ", "Value": [{{"00081155": {{"vr": "UI", "Value": ["{}"]}}}}]}},
+            "00081198": {{"vr": "SQ", "Value": [{{"00081155": {{"vr": "UI", "Value": ["{}"]}},
+                                                "00081197": {{"vr": "US", "Value": [42752]}}}}]}}}}"#,
+        uids[0], uids[1]
+    );
+    let server = std::thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream);
+        let mut headers = Vec::new();
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            if line.trim_end().is_empty() {
+                break;
+            }
+            headers.push(line.trim_end().to_ascii_lowercase());
+        }
+        let length: usize = headers
+            .iter()
+            .find_map(|h| h.strip_prefix("content-length: "))
+            .and_then(|v| v.parse().ok())
+            .expect("content length");
+        let mut body = vec![0; length];
+        reader.read_exact(&mut body).unwrap();
+        let mut stream = reader.into_inner();
+        write!(
+            stream,
+            "HTTP/1.1 202 Accepted\r\nContent-Type: application/dicom+json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            response.len(),
+            response
+        )
+        .unwrap();
+        (headers, body)
+    });
+
+    let options = stow::StowOptions {
+        url: format!("http://127.0.0.1:{}/dicomweb", port),
+        token: Some("secret".to_string()),
+        batch_size: 10,
+        timeout: Some(std::time::Duration::from_secs(10)),
+        rate_limit: Some(0.05),
+        window: Some("00:00-00:00".parse().unwrap()),
+    };
+    let started = std::time::Instant::now();
+    let summary = stow::store(&[dir.path().to_path_buf()], &options).expect("stow");
+    let elapsed = started.elapsed();
+    let (headers, body) = server.join().unwrap();
+    // The request is paced to the rate limit (0.05 MB/s); the all-day window never waits.
+    assert!(elapsed.as_secs_f64() >= body.len() as f64 / (0.05 * 1024.0 * 1024.0));
+
+    assert!(headers[0].starts_with("post /dicomweb/studies "));
+    assert!(headers.contains(&"authorization: bearer secret".to_string()));
+    assert!(headers
+        .iter()
+        .any(|h| h.starts_with("content-type: multipart/related; type=\"application/dicom\"")));
+    let body = String::from_utf8_lossy(&body);
+    assert_eq!(
+        body.matches("Content-Type: application/dicom\r\n").count(),
+        2
+    );
+    assert!(body.trim_end().ends_with("--"));
+
+    assert_eq!(summary.requests, 1);
+    assert_eq!(summary.skipped.len(), 1);
+    assert_eq!(summary.accepted.len(), 1);
+    assert_eq!(summary.accepted[0].sop_instance_uid, uids[0]);
+    assert!(summary.accepted[0].file.is_some());
+    assert_eq!(summary.rejected.len(), 1);
+    assert_eq!(summary.rejected[0].failure_reason, Some(0xA700));
+}
+
+#[test]
+fn wado_retrieves_a_series_as_multipart_dicom() {
+    use std::io::{BufRead, BufReader, Write};
+
+    let dir = tempdir().expect("tempdir");
+    let spec = synth::SynthSpec {
+        instances: 2,
+        ..synth::SynthSpec::default()
+    };
+    let files = synth::write_series(&spec, dir.path()).expect("series");
+    let parts: Vec<(String, Vec<u8>)> = files
+        .iter()
+        .map(|path| {
+            let obj = dicom::object::open_file(path).unwrap();
+            let uid = obj
+                .element(Tag(0x0008, 0x0018))
+                .unwrap()
+                .to_str()
+                .unwrap()
+                .trim_end_matches('\0')
+                .to_string();
+            (uid, std::fs::read(path).unwrap())
+        })
+        .collect();
+    let mut body = Vec::new();
+    for (_, bytes) in &parts {
+        body.extend_from_slice(b"--wado-boundary\r\nContent-Type: application/dicom\r\n\r\n");
+        body.extend_from_slice(bytes);
+        body.extend_from_slice(b"\r\n");
+    }
+    body.extend_from_slice(b"--wado-boundary--\r\n");
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let server = std::thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream);
+        let mut headers = Vec::new();
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            if line.trim_end().is_empty() {
+                break;
+            }
+            headers.push(line.trim_end().to_ascii_lowercase());
+        }
+        let mut stream = reader.into_inner();
+        write!(
+            stream,
+            "HTTP/1.1 200 OK\r\nContent-Type: multipart/related; type=\"application/dicom\"; boundary=\"wado-boundary\"\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            body.len()
+        )
+        .unwrap();
+        stream.write_all(&body).unwrap();
+        headers
+    });
+
+    let out = tempdir().expect("output dir");
+    let options = wado::WadoOptions {
+        url: format!("http://127.0.0.1:{}/dicomweb/studies", port),
+        token: None,
+        timeout: Some(std::time::Duration::from_secs(10)),
+        transfer_syntax: Some("*".to_string()),
+    };
+    let target = wado::WadoTarget {
+        study: "1.2.3".to_string(),
+        series: Some("1.2.3.4".to_string()),
+        instance: None,
+    };
+    let summary = wado::retrieve(
+        &options,
+        &target,
+        &wado::WadoResource::Instances,
+        out.path(),
+    )
+    .expect("wado");
+    let headers = server.join().unwrap();
+
+    assert!(headers[0].starts_with("get /dicomweb/studies/1.2.3/series/1.2.3.4 "));
+    assert!(headers.contains(
+        &"accept: multipart/related; type=\"application/dicom\"; transfer-syntax=*".to_string()
+    ));
+    assert_eq!(summary.files.len(), 2);
+    for (uid, bytes) in &parts {
+        let written = out.path().join(format!("{}.dcm", uid));
+        assert_eq!(&std::fs::read(&written).unwrap(), bytes);
+    }
+}
+
+#[test]
+fn nifti_export_refuses_gaps_unless_resampled() {
+    let dir = tempdir().expect("tempdir");
+    let spec = synth::SynthSpec {
+        modality: "CT".to_string(),
+        rows: 8,
+        columns: 6,
+        instances: 5,
+        ..synth::SynthSpec::default()
+    };
+    let files = synth::write_series(&spec, dir.path()).expect("series");
+    let out = tempdir().expect("output dir");
+
+    let complete = volume::load_series(dir.path(), &volume::VolumeOptions::default()).unwrap();
+    assert_eq!(
+        (complete.columns, complete.rows, complete.slices),
+        (6, 8, 5)
+    );
+    assert!(complete.warnings.is_empty());
+
+    std::fs::remove_file(&files[2]).unwrap();
+    let nifti = out.path().join("volume.nii");
+    let error = volume::export_nifti(dir.path(), &nifti, &volume::VolumeOptions::default())
+        .unwrap_err()
+        .to_string();
+    assert!(error.contains("1 missing slice(s)"), "{}", error);
+    assert!(!nifti.exists());
+
+    let options = volume::VolumeOptions {
+        series: None,
+        resampling: volume::Resampling::Linear,
+    };
+    let filled = volume::export_nifti(dir.path(), &nifti, &options).unwrap();
+    assert_eq!(filled.slices, 5);
+    assert_eq!(filled.interpolated_slices, 1);
+    assert!(!filled.warnings.is_empty());
+    let plane = 6 * 8;
+    for i in 0..plane {
+        let expected = (filled.values[plane + i] + filled.values[3 * plane + i]) / 2.0;
+        assert!((filled.values[2 * plane + i] - expected).abs() < 1e-3);
+    }
+    assert_eq!(
+        std::fs::metadata(&nifti).unwrap().len(),
+        352 + 5 * plane as u64 * 4
+    );
+
+    let gz = out.path().join("volume.nii.gz");
+    volume::write_nifti(&filled, &gz).unwrap();
+    let mut header = [0u8; 4];
+    std::io::Read::read_exact(
+        &mut flate2::read::GzDecoder::new(std::fs::File::open(&gz).unwrap()),
+        &mut header,
+    )
+    .unwrap();
+    assert_eq!(i32::from_le_bytes(header), 348);
+}
+
+#[test]
+fn mixed_echo_series_split_into_one_series_per_echo_time() {
+    let dir = tempdir().expect("tempdir");
+    let spec = synth::SynthSpec {
+        modality: "MR".to_string(),
+        rows: 4,
+        columns: 4,
+        instances: 4,
+        ..synth::SynthSpec::default()
+    };
+    let files = synth::write_series(&spec, dir.path()).expect("series");
+    for (i, path) in files.iter().enumerate() {
+        let mut obj = dicom::object::open_file(path).unwrap();
+        let echo = if i % 2 == 0 { "12" } else { "90.0" };
+        obj.put(DataElement::new(
+            Tag(0x0018, 0x0081),
+            VR::DS,
+            PrimitiveValue::from(echo),
+        ));
+        obj.write_to_file(path).unwrap();
+    }
+    let other = dir.path().join("other");
+    synth::write_series(
+        &synth::SynthSpec {
+            seed: 1,
+            ..spec.clone()
+        },
+        &other,
+    )
+    .expect("second series");
+    std::fs::write(dir.path().join("notes.txt"), "not DICOM").unwrap();
+
+    let dry = series_split::split_directory(dir.path(), None, None).expect("dry run");
+    assert_eq!(dry.split.len(), 1);
+    assert_eq!(dry.split[0].key, series_split::SplitKey::EchoTime);
+    assert_eq!((dry.unchanged_files, dry.skipped), (4, 1));
+
+    let out = tempdir().expect("output");
+    let summary = series_split::split_directory(dir.path(), None, Some(out.path())).expect("split");
+    let parts = &summary.split[0].parts;
+    assert_eq!(parts.len(), 2);
+    assert_eq!((parts[0].series_number, parts[1].series_number), (101, 102));
+    for part in parts {
+        assert_eq!(part.files.len(), 2);
+        for file in &part.files {
+            let obj = dicom::object::open_file(out.path().join(file)).unwrap();
+            let text = |tag| {
+                obj.element(tag)
+                    .unwrap()
+                    .to_str()
+                    .unwrap()
+                    .trim_end_matches(['\0', ' '])
+                    .to_string()
+            };
+            assert_eq!(text(Tag(0x0020, 0x000E)), part.series_instance_uid);
+            assert_eq!(text(Tag(0x0020, 0x0011)), part.series_number.to_string());
+            assert_eq!(text(Tag(0x0008, 0x103E)), part.series_description);
+        }
+    }
+    assert!(parts[1].series_description.ends_with("(TE 90)"));
+    assert!(out.path().join("other/IMG0001.dcm").is_file());
+
+    assert!(
+        series_split::split_directory(dir.path(), Some(series_split::SplitKey::BValue), None)
+            .unwrap()
+            .split
+            .is_empty()
+    );
+}
+
+#[test]
+fn time_curves_follow_a_contoured_region_across_timepoints() {
+    let dir = tempdir().expect("tempdir");
+    let spec = synth::SynthSpec {
+        modality: "CT".to_string(),
+        rows: 8,
+        columns: 8,
+        instances: 6,
+        ..synth::SynthSpec::default()
+    };
+    // Instances 1-3 and 4-6 are two passes over the same three slices, 30 s apart, the
+    // second enhanced by 100.
+    let files = synth::write_series(&spec, dir.path()).expect("series");
+    for (i, path) in files.iter().enumerate() {
+        let (pass, slice) = (i / 3, i % 3);
+        let mut obj = dicom::object::open_file(path).unwrap();
+        for (tag, value) in [
+            (Tag(0x0020, 0x0032), format!("0\\0\\{}", slice)),
+            (Tag(0x0028, 0x1052), (100 * pass).to_string()),
+        ] {
+            obj.put(DataElement::new(tag, VR::DS, PrimitiveValue::from(value)));
+        }
+        obj.put(DataElement::new(
+            Tag(0x0008, 0x0032),
+            VR::TM,
+            PrimitiveValue::from(if pass == 0 { "120000" } else { "120030" }),
+        ));
+        obj.write_to_file(path).unwrap();
+    }
+
+    let masks = tempdir().expect("mask dir");
+    let mut rtstruct = InMemDicomObject::new_empty_with_dict(StandardDataDictionary);
+    for (tag, value) in [
+        (Tag(0x0008, 0x0016), "1.2.840.10008.5.1.4.1.1.481.3"),
+        (Tag(0x0008, 0x0018), "1.2.826.0.1.3680043.2.1125.9"),
+    ] {
+        rtstruct.put(DataElement::new(tag, VR::UI, PrimitiveValue::from(value)));
+    }
+    let item = |elements: Vec<DataElement<InMemDicomObject>>| {
+        let mut item = InMemDicomObject::new_empty_with_dict(StandardDataDictionary);
+        for element in elements {
+            item.put(element);
+        }
+        item
+    };
+    let roi = item(vec![
+        DataElement::new(Tag(0x3006, 0x0022), VR::IS, PrimitiveValue::from("1")),
+        DataElement::new(Tag(0x3006, 0x0026), VR::LO, PrimitiveValue::from("LESION")),
+    ]);
+    let contour = item(vec![
+        DataElement::new(
+            Tag(0x3006, 0x0042),
+            VR::CS,
+            PrimitiveValue::from("CLOSED_PLANAR"),
+        ),
+        DataElement::new(
+            Tag(0x3006, 0x0050),
+            VR::DS,
+            PrimitiveValue::from("1\\1\\1\\5\\1\\1\\5\\5\\1\\1\\5\\1"),
+        ),
+    ]);
+    let roi_contour = item(vec![
+        DataElement::new(Tag(0x3006, 0x0084), VR::IS, PrimitiveValue::from("1")),
+        DataElement::new(
+            Tag(0x3006, 0x0040),
+            VR::SQ,
+            dicom::core::value::DataSetSequence::from(vec![contour]),
+        ),
+    ]);
+    rtstruct.put(DataElement::new(
+        Tag(0x3006, 0x0020),
+        VR::SQ,
+        dicom::core::value::DataSetSequence::from(vec![roi]),
+    ));
+    rtstruct.put(DataElement::new(
+        Tag(0x3006, 0x0039),
+        VR::SQ,
+        dicom::core::value::DataSetSequence::from(vec![roi_contour]),
+    ));
+    let meta = FileMetaTableBuilder::new()
+        .transfer_syntax(EXPLICIT_VR_LITTLE_ENDIAN.uid())
+        .media_storage_sop_class_uid("1.2.840.10008.5.1.4.1.1.481.3")
+        .media_storage_sop_instance_uid("1.2.826.0.1.3680043.2.1125.9");
+    let mask = masks.path().join("rtstruct.dcm");
+    rtstruct
+        .with_meta(meta)
+        .expect("rtstruct file")
+        .write_to_file(&mask)
+        .expect("write rtstruct");
+
+    let request = time_curves::CurveRequest {
+        series: None,
+        mask: Some((mask, 1)),
+        voxels: vec!["2,3,0".parse().unwrap()],
+    };
+    let curves = time_curves::compute(dir.path(), &request).expect("curves");
+    assert_eq!(curves.timepoints.len(), 2);
+    assert_eq!(curves.timepoints[1].time_s, 30.0);
+    assert_eq!((curves.columns, curves.rows, curves.slices), (8, 8, 3));
+    assert_eq!(curves.curves.len(), 2);
+    let lesion = &curves.curves[0];
+    assert_eq!(lesion.label, "LESION");
+    assert!(lesion.voxels > 0 && lesion.voxels <= 25);
+    for curve in &curves.curves {
+        assert!((curve.summary.peak_enhancement - 100.0).abs() < 1e-6);
+        assert_eq!(curve.summary.time_to_peak_s, 30.0);
+    }
+    assert_eq!(curves.curves[1].voxels, 1);
+    assert_eq!(curves.to_csv().lines().count(), 1 + 2 * 2);
+
+    let whole = time_curves::compute(dir.path(), &time_curves::CurveRequest::default()).unwrap();
+    assert_eq!(whole.curves[0].voxels, 8 * 8 * 3);
+    let plot = whole.plot();
+    assert_ne!(plot.get_pixel(40, 440).0, [255, 255, 255]);
+}